@@ -0,0 +1,114 @@
+//! Packets shared by the benchmarks.  Each benchmark uses some of them.
+#![allow(dead_code)]
+
+/// Cisco's private enterprise number
+pub const CISCO: u32 = 9;
+
+/// A V5 packet of `records` flows.
+pub fn v5_bulk(records: u16) -> Vec<u8> {
+    let mut packet = vec![0, 5];
+    packet.extend(records.to_be_bytes());
+    packet.extend([0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 0]);
+    for record in 0..records {
+        let [high, low] = record.to_be_bytes();
+        packet.extend([10, 0, high, low, 192, 0, 2, low, 0, 0, 0, 0, 0, 1, 0, 2]);
+        packet.extend([0, 0, 0, 10, 0, 0, 5, 220, 0, 0, 0, 1, 0, 0, 0, 2]);
+        packet.extend([high, low, 1, 187, 0, 0x18, 6, 0, 0, 1, 0, 2, 24, 24, 0, 0]);
+    }
+    packet
+}
+
+fn v9_header(count: u16, sequence: u32) -> Vec<u8> {
+    let mut packet = vec![0, 9];
+    packet.extend(count.to_be_bytes());
+    packet.extend([0, 0, 9, 9, 0, 1, 2, 3]);
+    packet.extend(sequence.to_be_bytes());
+    packet.extend([0, 0, 0, 1]);
+    packet
+}
+
+/// A V9 template 300 of `fields` 4 byte fields, and a packet of `records` records for it.
+pub fn v9_template_and_data(fields: u16, records: u16) -> (Vec<u8>, Vec<u8>) {
+    let mut template = v9_header(1, 1);
+    template.extend([0, 0]);
+    template.extend((8 + fields * 4).to_be_bytes());
+    template.extend([1, 44]);
+    template.extend(fields.to_be_bytes());
+    for field_type in 1..=fields {
+        template.extend(field_type.to_be_bytes());
+        template.extend([0, 4]);
+    }
+    let mut data = v9_header(1, 2);
+    data.extend([1, 44]);
+    data.extend((4 + fields * 4 * records).to_be_bytes());
+    data.extend((0..u32::from(fields * records)).flat_map(u32::to_be_bytes));
+    (template, data)
+}
+
+/// An IPFix message of a template set for `template_fields`, each a
+/// `(field id, length, enterprise number)`, followed by a data set of `records` records.
+fn ipfix_message(template_fields: &[(u16, u16, Option<u32>)], records: u16) -> Vec<u8> {
+    let mut template = vec![1, 0];
+    template.extend((template_fields.len() as u16).to_be_bytes());
+    for (field_id, length, enterprise_number) in template_fields {
+        match enterprise_number {
+            Some(enterprise_number) => {
+                template.extend((field_id | 0x8000).to_be_bytes());
+                template.extend(length.to_be_bytes());
+                template.extend(enterprise_number.to_be_bytes());
+            }
+            None => {
+                template.extend(field_id.to_be_bytes());
+                template.extend(length.to_be_bytes());
+            }
+        }
+    }
+    let record_length: u16 = template_fields.iter().map(|(_, length, _)| length).sum();
+    let mut data = vec![];
+    for record in 0..records {
+        for (field_id, length, _) in template_fields {
+            data.extend(match field_id {
+                // interfaceName and applicationName
+                82 | 96 => {
+                    let mut name = format!("name-{record}").into_bytes();
+                    name.resize(usize::from(*length), 0);
+                    name
+                }
+                _ => (0..*length).map(|i| (i ^ record) as u8).collect(),
+            });
+        }
+    }
+    let mut message = vec![0, 10];
+    message
+        .extend((16 + 4 + template.len() as u16 + 4 + record_length * records).to_be_bytes());
+    message.extend([0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1]);
+    message.extend([0, 2]);
+    message.extend((4 + template.len() as u16).to_be_bytes());
+    message.extend(template);
+    message.extend([1, 0]);
+    message.extend((4 + record_length * records).to_be_bytes());
+    message.extend(data);
+    message
+}
+
+/// An IPFix message of `records` records carrying two Cisco enterprise fields.
+pub fn ipfix_enterprise(records: u16) -> Vec<u8> {
+    ipfix_message(
+        &[
+            (8, 4, None),
+            (12, 4, None),
+            (1, 8, None),
+            (12235, 8, Some(CISCO)),
+            (12236, 4, Some(CISCO)),
+        ],
+        records,
+    )
+}
+
+/// An IPFix message of `records` records carrying interface and application names.
+pub fn ipfix_strings(records: u16) -> Vec<u8> {
+    ipfix_message(
+        &[(8, 4, None), (82, 16, None), (96, 32, None), (1, 8, None)],
+        records,
+    )
+}
@@ -2,6 +2,8 @@ use criterion::{criterion_group, criterion_main, Criterion};
 use netflow_parser::NetflowParser;
 use std::hint::black_box;
 
+mod fixtures;
+
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("netflow_common bench", |b| {
         b.iter(|| {
@@ -34,6 +36,17 @@ fn criterion_benchmark(c: &mut Criterion) {
             NetflowParser::default().parse_bytes_as_netflow_common_flowsets(black_box(&all));
         })
     });
+
+    let v5_packet = fixtures::v5_bulk(30);
+    let (template, v9_packet) = fixtures::v9_template_and_data(40, 30);
+    let mut parser = NetflowParser::default();
+    parser.parse_bytes(&template);
+    c.bench_function("netflow_common bulk conversion bench", |b| {
+        b.iter(|| {
+            parser.parse_bytes_as_netflow_common_flowsets(black_box(&v5_packet));
+            parser.parse_bytes_as_netflow_common_flowsets(black_box(&v9_packet))
+        })
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
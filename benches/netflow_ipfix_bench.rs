@@ -1,7 +1,11 @@
 use criterion::{criterion_group, criterion_main, Criterion};
+use netflow_parser::variable_versions::data_number::{CustomValue, FieldValue};
+use netflow_parser::variable_versions::enterprise::EnterpriseFieldRegistry;
 use netflow_parser::NetflowParser;
 use std::hint::black_box;
 
+mod fixtures;
+
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("netflow_ipfix bench", |b| {
         b.iter(|| {
@@ -13,6 +17,30 @@ fn criterion_benchmark(c: &mut Criterion) {
             NetflowParser::default().parse_bytes(black_box(&ipfix_packet));
         })
     });
+
+    let ipfix_packet = fixtures::ipfix_enterprise(30);
+    let registry = EnterpriseFieldRegistry::new().with_decoder(
+        fixtures::CISCO,
+        12235,
+        |bytes: &[u8]| {
+            Some(FieldValue::Custom(
+                CustomValue::Bytes(bytes.to_vec()).into(),
+            ))
+        },
+    );
+    let mut parser = NetflowParser::builder()
+        .with_enterprise_fields(registry)
+        .build()
+        .expect("valid configuration");
+    c.bench_function("netflow_ipfix enterprise fields bench", |b| {
+        b.iter(|| parser.parse_bytes(black_box(&ipfix_packet)))
+    });
+
+    let ipfix_packet = fixtures::ipfix_strings(30);
+    let mut parser = NetflowParser::default();
+    c.bench_function("netflow_ipfix strings bench", |b| {
+        b.iter(|| parser.parse_bytes(black_box(&ipfix_packet)))
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -2,6 +2,8 @@ use criterion::{criterion_group, criterion_main, Criterion};
 use netflow_parser::NetflowParser;
 use std::hint::black_box;
 
+mod fixtures;
+
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("netflow_v5 bench", |b| {
         b.iter(|| {
@@ -13,6 +15,11 @@ fn criterion_benchmark(c: &mut Criterion) {
             NetflowParser::default().parse_bytes(black_box(&v5_packet));
         })
     });
+
+    let v5_packet = fixtures::v5_bulk(30);
+    c.bench_function("netflow_v5 bulk bench", |b| {
+        b.iter(|| NetflowParser::default().parse_bytes(black_box(&v5_packet)))
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
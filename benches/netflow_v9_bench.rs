@@ -2,6 +2,8 @@ use criterion::{criterion_group, criterion_main, Criterion};
 use netflow_parser::NetflowParser;
 use std::hint::black_box;
 
+mod fixtures;
+
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("netflow_v9 bench", |b| {
         b.iter(|| {
@@ -12,6 +14,33 @@ fn criterion_benchmark(c: &mut Criterion) {
             NetflowParser::default().parse_bytes(black_box(&v9_packet));
         })
     });
+
+    let (template, data) = fixtures::v9_template_and_data(8, 30);
+    c.bench_function("netflow_v9 template and data bench", |b| {
+        b.iter(|| {
+            let mut parser = NetflowParser::default();
+            parser.parse_bytes(black_box(&template));
+            parser.parse_bytes(black_box(&data))
+        })
+    });
+
+    let (template, data) = fixtures::v9_template_and_data(40, 30);
+    let mut parser = NetflowParser::default();
+    parser.parse_bytes(&template);
+    c.bench_function("netflow_v9 wide template bench", |b| {
+        b.iter(|| parser.parse_bytes(black_box(&data)))
+    });
+
+    #[cfg(feature = "perf")]
+    {
+        let mut arena = netflow_parser::arena::RecordArena::new();
+        c.bench_function("netflow_v9 wide template arena bench", |b| {
+            b.iter(|| {
+                arena.clear();
+                parser.parse_records(black_box(&data), &mut arena)
+            })
+        });
+    }
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,41 @@
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use netflow_parser::batcher::Batcher;
+use netflow_parser::pipeline::{Pipeline, PipelineRecord};
+use netflow_parser::NetflowParser;
+
+fn main() {
+    let mut socket = UdpSocket::bind("127.0.0.1:9995").expect("couldn't bind to address");
+    socket
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .expect("couldn't set read timeout");
+
+    // Print batches of up to 100 records at least every 5 seconds
+    let batcher = Batcher::new(
+        100,
+        Some(Duration::from_secs(5)),
+        |batch: Vec<PipelineRecord>| {
+            for record in batch {
+                println!("{} {:?} {:?}", record.exporter, record.tags, record.record);
+            }
+        },
+    );
+
+    let mut pipeline = Pipeline::builder()
+        .with_parser(NetflowParser::builder().with_scoped_templates(true))
+        .with_filter(|record: &PipelineRecord| record.record.version() != 7)
+        .with_enricher(|record: &mut PipelineRecord| {
+            record
+                .tags
+                .insert("collector".to_string(), "example".to_string());
+        })
+        .with_sink(batcher)
+        .build()
+        .expect("valid configuration");
+
+    if let Err(e) = pipeline.run(&mut socket) {
+        eprintln!("pipeline stopped: {e}");
+    }
+    println!("{:?}", pipeline.stats());
+}
@@ -0,0 +1,37 @@
+#![no_main]
+
+//! Parses an IPFix message whose template has a variable length field, of the type in the
+//! first two bytes of the input, and whose data set is the rest of the input.
+
+use libfuzzer_sys::fuzz_target;
+use netflow_parser::NetflowParser;
+
+/// Field length of variable length information elements
+const VARIABLE_LENGTH: u16 = 65_535;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((field_type, records)) = data.split_first_chunk::<2>() else {
+        return;
+    };
+    let field_type = u16::from_be_bytes(*field_type) & 0x7fff;
+    let template = [
+        [0, 2, 0, 16, 1, 0, 0, 2, 0, 8, 0, 4].as_slice(),
+        &field_type.to_be_bytes(),
+        &VARIABLE_LENGTH.to_be_bytes(),
+    ]
+    .concat();
+    let Ok(set_length) = u16::try_from(records.len() + 4) else {
+        return;
+    };
+    let Ok(length) = u16::try_from(16 + template.len() + usize::from(set_length)) else {
+        return;
+    };
+    let mut message = vec![0, 10];
+    message.extend(length.to_be_bytes());
+    message.extend([0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1]);
+    message.extend(template);
+    message.extend([1, 0]);
+    message.extend(set_length.to_be_bytes());
+    message.extend(records);
+    NetflowParser::default().parse_bytes(&message);
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+//! Parses the input as the body of a V9 options template flowset, then as options data for
+//! every options template that defined.
+
+use libfuzzer_sys::fuzz_target;
+use netflow_parser::NetflowParser;
+
+fn v9_packet(flowset_id: u16, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0, 9, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1];
+    packet.extend(flowset_id.to_be_bytes());
+    packet.extend(
+        u16::try_from(body.len() + 4)
+            .unwrap_or(u16::MAX)
+            .to_be_bytes(),
+    );
+    packet.extend(body);
+    packet
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut parser = NetflowParser::default();
+    parser.parse_bytes(&v9_packet(1, data));
+    let template_ids: Vec<u16> = parser.v9_parser.engine.options_templates.keys().copied().collect();
+    for template_id in template_ids {
+        parser.parse_bytes(&v9_packet(template_id, data));
+    }
+});
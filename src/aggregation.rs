@@ -0,0 +1,461 @@
+//! # Aggregation
+//!
+//! Aggregates `NetflowCommonFlowSet`s into rolling byte, packet and flow counts per key, such as
+//! the 5-tuple, source and destination prefixes or AS pair.  An aggregate is emitted once no
+//! flow was added to it for `idle_timeout`, or once it has been open for `active_timeout` so
+//! long lived conversations are still reported periodically.  At most `with_max_aggregates`
+//! are kept open, 65536 unless set, emitting the least recently added to early to make room.
+//!
+//! ```rust
+//! use netflow_parser::aggregation::{AggregateBy, Aggregator};
+//! use netflow_parser::NetflowParser;
+//! use std::time::Duration;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,];
+//! let mut parser = NetflowParser::default();
+//! let mut aggregates = vec![];
+//! let mut aggregator = Aggregator::new(
+//!     AggregateBy::FiveTuple,
+//!     Duration::from_secs(60),
+//!     Duration::from_secs(15),
+//!     |aggregate| aggregates.push(aggregate),
+//! );
+//! for _ in 0..3 {
+//!     aggregator.extend(parser.parse_bytes_as_netflow_common_flowsets(&v5_packet));
+//! }
+//! drop(aggregator);
+//! assert_eq!(aggregates.len(), 1);
+//! assert_eq!(aggregates[0].flows, 3);
+//! ```
+
+use crate::clock::Clock;
+#[cfg(feature = "std")]
+use crate::clock::SystemClock;
+use crate::netflow_common::NetflowCommonFlowSet;
+use crate::types::{Asn, Port};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use core::time::Duration;
+
+use serde::Serialize;
+
+/// What flows are aggregated by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateBy {
+    /// Source and destination address and port, and protocol
+    #[default]
+    FiveTuple,
+    /// Source and destination address masked to a prefix of `ipv4` or `ipv6` bits, and
+    /// protocol, such as `Prefix { ipv4: 24, ipv6: 64 }`
+    Prefix { ipv4: u8, ipv6: u8 },
+    /// Source and destination AS number
+    AsPair,
+}
+
+/// Key of an aggregate.  Fields `AggregateBy` doesn't group by are `None`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct AggregateKey {
+    pub src_addr: Option<IpAddr>,
+    pub dst_addr: Option<IpAddr>,
+    pub src_port: Option<Port>,
+    pub dst_port: Option<Port>,
+    pub protocol_number: Option<u8>,
+    pub src_as: Option<Asn>,
+    pub dst_as: Option<Asn>,
+}
+
+/// `addr` with the bits after the first `ipv4` or `ipv6` cleared.
+fn mask(addr: IpAddr, ipv4: u8, ipv6: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => {
+            let bits = u32::MAX
+                .checked_shl(32 - u32::from(ipv4.min(32)))
+                .unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(addr) & bits))
+        }
+        IpAddr::V6(addr) => {
+            let bits = u128::MAX
+                .checked_shl(128 - u32::from(ipv6.min(128)))
+                .unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(addr) & bits))
+        }
+    }
+}
+
+impl AggregateBy {
+    /// Key of the aggregate `flowset` is added to.
+    pub fn key(&self, flowset: &NetflowCommonFlowSet) -> AggregateKey {
+        match *self {
+            AggregateBy::FiveTuple => AggregateKey {
+                src_addr: flowset.src_addr,
+                dst_addr: flowset.dst_addr,
+                src_port: flowset.src_port,
+                dst_port: flowset.dst_port,
+                protocol_number: flowset.protocol_number,
+                ..Default::default()
+            },
+            AggregateBy::Prefix { ipv4, ipv6 } => AggregateKey {
+                src_addr: flowset.src_addr.map(|addr| mask(addr, ipv4, ipv6)),
+                dst_addr: flowset.dst_addr.map(|addr| mask(addr, ipv4, ipv6)),
+                protocol_number: flowset.protocol_number,
+                ..Default::default()
+            },
+            AggregateBy::AsPair => AggregateKey {
+                src_as: flowset.src_as,
+                dst_as: flowset.dst_as,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Sums of the flows added under one key.  Flows without counters add to `flows` only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AggregatedFlow {
+    pub key: AggregateKey,
+    pub bytes: u64,
+    pub packets: u64,
+    /// Number of flows added
+    pub flows: u64,
+    /// Clock time the first flow was added
+    pub first: Duration,
+    /// Clock time the last flow was added
+    pub last: Duration,
+}
+
+/// Aggregates kept open unless `with_max_aggregates` is set.
+const DEFAULT_MAX_AGGREGATES: usize = 65_536;
+
+/// Aggregates flows by an `AggregateBy` key and passes aggregates to `on_emit` once they time
+/// out.  Aggregates still open when the aggregator is dropped are emitted.
+pub struct Aggregator<F: FnMut(AggregatedFlow)> {
+    aggregate_by: AggregateBy,
+    active_timeout: Duration,
+    idle_timeout: Duration,
+    clock: Arc<dyn Clock>,
+    aggregates: BTreeMap<AggregateKey, AggregatedFlow>,
+    max_aggregates: usize,
+    on_emit: F,
+}
+
+impl<F: FnMut(AggregatedFlow)> Aggregator<F> {
+    /// Emits aggregates open for `active_timeout`, or that no flow was added to for
+    /// `idle_timeout`.
+    #[cfg(feature = "std")]
+    pub fn new(
+        aggregate_by: AggregateBy,
+        active_timeout: Duration,
+        idle_timeout: Duration,
+        on_emit: F,
+    ) -> Self {
+        Self::with_clock(
+            aggregate_by,
+            active_timeout,
+            idle_timeout,
+            Arc::new(SystemClock::default()),
+            on_emit,
+        )
+    }
+
+    /// Measures the timeouts using time from `clock`.
+    pub fn with_clock(
+        aggregate_by: AggregateBy,
+        active_timeout: Duration,
+        idle_timeout: Duration,
+        clock: Arc<dyn Clock>,
+        on_emit: F,
+    ) -> Self {
+        Self {
+            aggregate_by,
+            active_timeout,
+            idle_timeout,
+            clock,
+            aggregates: BTreeMap::new(),
+            max_aggregates: DEFAULT_MAX_AGGREGATES,
+            on_emit,
+        }
+    }
+
+    /// Keeps at most `max_aggregates` open, emitting the one least recently added to when a
+    /// flow opens a new one.
+    pub fn with_max_aggregates(mut self, max_aggregates: usize) -> Self {
+        self.max_aggregates = max_aggregates;
+        self
+    }
+
+    /// Adds a flow to its aggregate.  An aggregate that timed out is emitted first, and the
+    /// flow starts a new one.
+    pub fn push(&mut self, flowset: &NetflowCommonFlowSet) {
+        let now = self.clock.now();
+        let key = self.aggregate_by.key(flowset);
+        if let Some(aggregate) = self.aggregates.get(&key) {
+            if self.is_expired(aggregate, now) {
+                let aggregate = *aggregate;
+                self.aggregates.remove(&key);
+                (self.on_emit)(aggregate);
+            }
+        }
+        let aggregate = self.aggregates.entry(key).or_insert(AggregatedFlow {
+            key,
+            bytes: 0,
+            packets: 0,
+            flows: 0,
+            first: now,
+            last: now,
+        });
+        aggregate.bytes = aggregate.bytes.saturating_add(flowset.bytes.unwrap_or(0));
+        aggregate.packets = aggregate
+            .packets
+            .saturating_add(flowset.packets.unwrap_or(0));
+        aggregate.flows += 1;
+        aggregate.last = now;
+
+        while self.aggregates.len() > self.max_aggregates {
+            let Some(oldest) = self
+                .aggregates
+                .values()
+                .filter(|aggregate| aggregate.key != key)
+                .min_by_key(|aggregate| aggregate.last)
+                .map(|aggregate| aggregate.key)
+            else {
+                break;
+            };
+            if let Some(aggregate) = self.aggregates.remove(&oldest) {
+                (self.on_emit)(aggregate);
+            }
+        }
+    }
+
+    /// Emits the aggregates that timed out, returning how many.  Call it periodically so
+    /// aggregates no flow is added to anymore are still emitted.
+    pub fn poll(&mut self) -> usize {
+        let now = self.clock.now();
+        let expired: Vec<AggregateKey> = self
+            .aggregates
+            .values()
+            .filter(|aggregate| self.is_expired(aggregate, now))
+            .map(|aggregate| aggregate.key)
+            .collect();
+        for key in &expired {
+            if let Some(aggregate) = self.aggregates.remove(key) {
+                (self.on_emit)(aggregate);
+            }
+        }
+        expired.len()
+    }
+
+    /// Emits every open aggregate.
+    pub fn flush(&mut self) {
+        for (_, aggregate) in core::mem::take(&mut self.aggregates) {
+            (self.on_emit)(aggregate);
+        }
+    }
+
+    /// Open aggregates, by key.
+    pub fn aggregates(&self) -> impl Iterator<Item = &AggregatedFlow> {
+        self.aggregates.values()
+    }
+
+    /// Number of open aggregates.
+    pub fn len(&self) -> usize {
+        self.aggregates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aggregates.is_empty()
+    }
+
+    fn is_expired(&self, aggregate: &AggregatedFlow, now: Duration) -> bool {
+        now.saturating_sub(aggregate.first) >= self.active_timeout
+            || now.saturating_sub(aggregate.last) >= self.idle_timeout
+    }
+}
+
+impl<'a, F: FnMut(AggregatedFlow)> Extend<&'a NetflowCommonFlowSet> for Aggregator<F> {
+    fn extend<I: IntoIterator<Item = &'a NetflowCommonFlowSet>>(&mut self, flowsets: I) {
+        for flowset in flowsets {
+            self.push(flowset);
+        }
+    }
+}
+
+impl<F: FnMut(AggregatedFlow)> Extend<NetflowCommonFlowSet> for Aggregator<F> {
+    fn extend<I: IntoIterator<Item = NetflowCommonFlowSet>>(&mut self, flowsets: I) {
+        for flowset in flowsets {
+            self.push(&flowset);
+        }
+    }
+}
+
+impl<F: FnMut(AggregatedFlow)> Drop for Aggregator<F> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl<F: FnMut(AggregatedFlow)> fmt::Debug for Aggregator<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Aggregator")
+            .field("aggregate_by", &self.aggregate_by)
+            .field("active_timeout", &self.active_timeout)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("clock", &self.clock)
+            .field("open", &self.aggregates.len())
+            .field("max_aggregates", &self.max_aggregates)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod aggregation_tests {
+    use super::{AggregateBy, AggregateKey, AggregatedFlow, Aggregator};
+    use crate::clock::ManualClock;
+    use crate::netflow_common::NetflowCommonFlowSet;
+    use crate::types::{Asn, Port};
+
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn flow(src: [u8; 4], src_port: u16, bytes: u64) -> NetflowCommonFlowSet {
+        NetflowCommonFlowSet {
+            src_addr: Some(IpAddr::V4(Ipv4Addr::from(src))),
+            dst_addr: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+            src_port: Some(Port(src_port)),
+            dst_port: Some(Port(443)),
+            src_as: Some(Asn(64512)),
+            dst_as: Some(Asn(64513)),
+            protocol_number: Some(6),
+            bytes: Some(bytes),
+            packets: Some(1),
+            ..Default::default()
+        }
+    }
+
+    fn aggregator(
+        aggregate_by: AggregateBy,
+        clock: &Arc<ManualClock>,
+        emitted: &Arc<Mutex<Vec<AggregatedFlow>>>,
+    ) -> Aggregator<impl FnMut(AggregatedFlow)> {
+        let emitted = emitted.clone();
+        Aggregator::with_clock(
+            aggregate_by,
+            Duration::from_secs(60),
+            Duration::from_secs(15),
+            clock.clone(),
+            move |aggregate| emitted.lock().unwrap().push(aggregate),
+        )
+    }
+
+    #[test]
+    fn it_sums_flows_by_five_tuple() {
+        let clock = Arc::new(ManualClock::default());
+        let emitted = Arc::new(Mutex::new(vec![]));
+        let mut aggregator = aggregator(AggregateBy::FiveTuple, &clock, &emitted);
+        aggregator.extend([
+            flow([10, 0, 0, 1], 1000, 100),
+            flow([10, 0, 0, 1], 1000, 200),
+            flow([10, 0, 0, 1], 1001, 50),
+        ]);
+        assert_eq!(aggregator.len(), 2);
+        let aggregate = aggregator.aggregates().next().unwrap();
+        assert_eq!(
+            (aggregate.bytes, aggregate.packets, aggregate.flows),
+            (300, 2, 2)
+        );
+        assert_eq!(aggregate.key.src_port, Some(Port(1000)));
+        assert_eq!(aggregate.key.src_as, None);
+        drop(aggregator);
+        assert_eq!(emitted.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn it_aggregates_by_prefix_and_as_pair() {
+        let clock = Arc::new(ManualClock::default());
+        let emitted = Arc::new(Mutex::new(vec![]));
+        let by_prefix = AggregateBy::Prefix { ipv4: 24, ipv6: 64 };
+        let mut aggregator = aggregator(by_prefix, &clock, &emitted);
+        aggregator.extend([
+            flow([10, 0, 0, 1], 1000, 100),
+            flow([10, 0, 0, 2], 1001, 50),
+        ]);
+        let aggregate = aggregator.aggregates().next().unwrap();
+        assert_eq!(aggregator.len(), 1);
+        assert_eq!(
+            aggregate.key.src_addr,
+            Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)))
+        );
+        assert_eq!(aggregate.bytes, 150);
+
+        let key = AggregateBy::AsPair.key(&flow([10, 0, 0, 1], 1000, 100));
+        assert_eq!(
+            key,
+            AggregateKey {
+                src_as: Some(Asn(64512)),
+                dst_as: Some(Asn(64513)),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn it_emits_idle_aggregates() {
+        let clock = Arc::new(ManualClock::default());
+        let emitted = Arc::new(Mutex::new(vec![]));
+        let mut aggregator = aggregator(AggregateBy::FiveTuple, &clock, &emitted);
+        aggregator.push(&flow([10, 0, 0, 1], 1000, 100));
+        clock.set(Duration::from_secs(10));
+        aggregator.push(&flow([10, 0, 0, 2], 1000, 100));
+        assert_eq!(aggregator.poll(), 0);
+        clock.set(Duration::from_secs(15));
+        assert_eq!(aggregator.poll(), 1);
+        assert_eq!(aggregator.len(), 1);
+        let emitted = emitted.lock().unwrap();
+        assert_eq!(
+            emitted[0].key.src_addr,
+            Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+        );
+    }
+
+    #[test]
+    fn it_emits_active_aggregates_and_starts_new_ones() {
+        let clock = Arc::new(ManualClock::default());
+        let emitted = Arc::new(Mutex::new(vec![]));
+        let mut aggregator = aggregator(AggregateBy::FiveTuple, &clock, &emitted);
+        for seconds in (0..=60).step_by(10) {
+            clock.set(Duration::from_secs(seconds));
+            aggregator.push(&flow([10, 0, 0, 1], 1000, 100));
+        }
+        // The flow added at 60 seconds starts a new aggregate
+        let emitted = emitted.lock().unwrap().clone();
+        assert_eq!(emitted.len(), 1);
+        assert_eq!((emitted[0].flows, emitted[0].bytes), (6, 600));
+        assert_eq!(emitted[0].last, Duration::from_secs(50));
+        assert_eq!(aggregator.aggregates().next().unwrap().flows, 1);
+    }
+
+    #[test]
+    fn it_emits_the_least_recently_added_to_aggregate_to_make_room() {
+        let clock = Arc::new(ManualClock::default());
+        let emitted = Arc::new(Mutex::new(vec![]));
+        let mut aggregator =
+            aggregator(AggregateBy::FiveTuple, &clock, &emitted).with_max_aggregates(2);
+        for (seconds, src) in
+            (0..).zip([[10, 0, 0, 1], [10, 0, 0, 2], [10, 0, 0, 1], [10, 0, 0, 3]])
+        {
+            clock.set(Duration::from_secs(seconds));
+            aggregator.push(&flow(src, 1000, 100));
+        }
+        assert_eq!(aggregator.len(), 2);
+        let emitted = emitted.lock().unwrap().clone();
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(
+            emitted[0].key.src_addr,
+            Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)))
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! # Analyze
+//!
+//! One call summaries of a buffer of netflow packets or a pcap capture, for triaging what a
+//! capture contains before processing it.
+
+use crate::batch::BatchProcessor;
+use crate::protocol::ProtocolTypes;
+use crate::types::Port;
+use crate::{NetflowPacket, NetflowParser};
+
+use serde::Serialize;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read};
+use std::net::IpAddr;
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+/// Entries kept in the top protocol and port lists.
+const TOP_ENTRIES: usize = 10;
+
+/// A template id announced in the analyzed packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct TemplateSeen {
+    /// 9 or 10 (IPFix)
+    pub version: u16,
+    /// V9 `source_id` or IPFix `observation_domain_id` the template was announced in
+    pub scope: u32,
+    pub template_id: u16,
+    /// Options template rather than a data template
+    pub options: bool,
+}
+
+/// Summary of the analyzed packets.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct CaptureSummary {
+    /// Parsed packets by version
+    pub packets_per_version: BTreeMap<u16, u64>,
+    /// Flow records in the parsed packets
+    pub flows: u64,
+    /// Packets that failed to parse
+    pub errors: u64,
+    /// `errors` over every packet, parsed or not
+    pub error_rate: f64,
+    /// Addresses packets were sent from, only known for captures
+    pub exporters: BTreeSet<IpAddr>,
+    /// V9 `source_id`s and IPFix `observation_domain_id`s
+    pub observation_domains: BTreeSet<u32>,
+    pub templates_seen: BTreeSet<TemplateSeen>,
+    /// Most common protocols with their flow counts, most common first
+    pub top_protocols: Vec<(ProtocolTypes, u64)>,
+    /// Most common destination ports with their flow counts, most common first
+    pub top_dst_ports: Vec<(Port, u64)>,
+    /// Earliest and latest export time in the packet headers, seconds since the unix epoch
+    pub time_range: Option<(u32, u32)>,
+}
+
+#[derive(Debug, Default)]
+struct Analyzer {
+    summary: CaptureSummary,
+    protocols: BTreeMap<ProtocolTypes, u64>,
+    dst_ports: BTreeMap<Port, u64>,
+}
+
+impl Analyzer {
+    fn add(&mut self, packet: &NetflowPacket) {
+        let summary = &mut self.summary;
+        let (version, export_time) = match packet {
+            NetflowPacket::V5(v5) => (5, v5.header.unix_secs),
+            NetflowPacket::V7(v7) => (7, v7.header.unix_secs),
+            NetflowPacket::V9(v9) => {
+                let scope = v9.header.source_id;
+                summary.observation_domains.insert(scope);
+                for body in v9.flowsets.iter().map(|flowset| &flowset.body) {
+                    let templates = body.templates.iter().flatten();
+                    let options = body.options_templates.iter().flatten();
+                    summary.templates_seen.extend(
+                        templates
+                            .map(|t| (t.template_id, false))
+                            .chain(options.map(|t| (t.template_id, true)))
+                            .map(|(template_id, options)| TemplateSeen {
+                                version: 9,
+                                scope,
+                                template_id,
+                                options,
+                            }),
+                    );
+                }
+                (9, v9.header.unix_secs)
+            }
+            NetflowPacket::IPFix(ipfix) => {
+                let scope = ipfix.header.observation_domain_id;
+                summary.observation_domains.insert(scope);
+                for body in ipfix.flowsets.iter().map(|flowset| &flowset.body) {
+                    let templates = body.templates.iter().map(|t| (t.template_id, false));
+                    let options = body.options_templates.iter().map(|t| (t.template_id, true));
+                    summary.templates_seen.extend(templates.chain(options).map(
+                        |(template_id, options)| TemplateSeen {
+                            version: 10,
+                            scope,
+                            template_id,
+                            options,
+                        },
+                    ));
+                }
+                (10, ipfix.header.export_time)
+            }
+            NetflowPacket::Error(_) => {
+                summary.errors += 1;
+                return;
+            }
+        };
+
+        *summary.packets_per_version.entry(version).or_default() += 1;
+        summary.time_range = Some(match summary.time_range {
+            Some((first, last)) => (first.min(export_time), last.max(export_time)),
+            None => (export_time, export_time),
+        });
+        for flowset in packet.as_netflow_common().unwrap_or_default().flowsets {
+            summary.flows += 1;
+            if let Some(protocol) = flowset.protocol_type {
+                *self.protocols.entry(protocol).or_default() += 1;
+            }
+            if let Some(port) = flowset.dst_port {
+                *self.dst_ports.entry(port).or_default() += 1;
+            }
+        }
+    }
+
+    fn finish(self) -> CaptureSummary {
+        let mut summary = self.summary;
+        let parsed: u64 = summary.packets_per_version.values().sum();
+        if parsed + summary.errors > 0 {
+            summary.error_rate = summary.errors as f64 / (parsed + summary.errors) as f64;
+        }
+        summary.top_protocols = top(self.protocols);
+        summary.top_dst_ports = top(self.dst_ports);
+        summary
+    }
+}
+
+/// Highest counts first, ties broken by key so the output is stable.
+fn top<K: Ord + Copy>(counts: BTreeMap<K, u64>) -> Vec<(K, u64)> {
+    let mut counts: Vec<(K, u64)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts.truncate(TOP_ENTRIES);
+    counts
+}
+
+/// Summarizes every packet in `packets`, such as a dump of UDP payloads from one exporter.
+///
+/// ```rust
+/// use netflow_parser::analyze::analyze;
+/// use netflow_parser::types::Port;
+///
+/// let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,];
+/// let summary = analyze(&v5_packet);
+/// assert_eq!(summary.packets_per_version[&5], 1);
+/// assert_eq!(summary.top_dst_ports, vec![(Port(1029), 1)]);
+/// ```
+pub fn analyze(packets: &[u8]) -> CaptureSummary {
+    let mut analyzer = Analyzer::default();
+    for packet in NetflowParser::default().parse_bytes(packets) {
+        analyzer.add(&packet);
+    }
+    analyzer.finish()
+}
+
+/// Summarizes every netflow packet in a pcap capture, parsing each exporter's packets with
+/// its own parser.
+pub fn analyze_pcap<R: Read>(capture: R) -> io::Result<CaptureSummary> {
+    let mut analyzer = Analyzer::default();
+    let mut exporters = BTreeSet::new();
+    // The interval only matters for progress reporting, which isn't used here.
+    let mut processor = BatchProcessor::new(NetflowParser::builder(), Duration::MAX)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    processor.process_pcap(
+        capture,
+        |exporter, packet| {
+            exporters.insert(exporter.ip());
+            analyzer.add(&packet);
+        },
+        |_| ControlFlow::Continue(()),
+    )?;
+    let mut summary = analyzer.finish();
+    summary.exporters = exporters;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod analyze_tests {
+    use super::{analyze, analyze_pcap, TemplateSeen};
+    use crate::pcap::pcap_tests::capture;
+    use crate::protocol::ProtocolTypes;
+    use crate::testing::V9_PACKET;
+
+    use std::time::Duration;
+
+    #[test]
+    fn it_summarizes_packets() {
+        let mut packets = V9_PACKET.to_vec();
+        packets.extend_from_slice(&[0, 5, 0, 1]);
+
+        let summary = analyze(&packets);
+        assert_eq!(summary.packets_per_version[&9], 1);
+        assert_eq!(summary.flows, 1);
+        assert_eq!(summary.errors, 1);
+        assert_eq!(summary.error_rate, 0.5);
+        assert_eq!(summary.time_range, Some((66051, 66051)));
+        assert_eq!(summary.observation_domains.len(), 1);
+        assert_eq!(
+            summary.templates_seen.into_iter().collect::<Vec<_>>(),
+            vec![TemplateSeen {
+                version: 9,
+                scope: 1,
+                template_id: 258,
+                options: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn it_summarizes_pcap_captures() {
+        let v5_packet = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let pcap = capture(&[
+            (Duration::from_secs(1), &v5_packet),
+            (Duration::from_secs(2), &v5_packet),
+        ]);
+
+        let summary = analyze_pcap(pcap.as_slice()).unwrap();
+        assert_eq!(summary.packets_per_version[&5], 2);
+        assert_eq!(summary.exporters.len(), 1);
+        assert_eq!(summary.top_protocols, vec![(ProtocolTypes::Egp, 2)]);
+        assert_eq!(summary.error_rate, 0.0);
+    }
+}
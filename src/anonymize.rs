@@ -0,0 +1,436 @@
+//! # Anonymize
+//!
+//! Rewrites the IP addresses of parsed flows so they can be kept without identifying anyone.
+//! Give an [`Anonymizer`] to `NetflowParserBuilder::with_anonymizer` and every address of
+//! every packet parsed is anonymized before the packet is returned: V5/V7 addresses, and
+//! every IPv4 and IPv6 field of V9/IPFix data and options records.  Flows converted
+//! to `NetflowCommon` then only carry anonymized addresses.  Raw bytes kept with
+//! `with_keep_raw` and the `Unknown` bytes of undecoded fields are left as is.
+//!
+//! Two schemes are offered:
+//!
+//! * [`Anonymizer::truncate`] keeps a network prefix and zeroes the host bits, so flows
+//!   stay attributable to a network.
+//! * [`Anonymizer::crypto_pan`] maps addresses one to one with Crypto-PAn, keyed by a
+//!   32 byte secret.  It is prefix preserving: addresses sharing a prefix share an
+//!   anonymized prefix of the same length, so subnets can still be told apart.  The same key
+//!   gives the same mapping, across runs and collectors.
+//!
+//! ```rust
+//! use netflow_parser::anonymize::Anonymizer;
+//! use netflow_parser::NetflowParser;
+//! use std::net::{IpAddr, Ipv4Addr};
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let mut parser = NetflowParser::builder()
+//!     .with_anonymizer(Anonymizer::truncate(24, 48))
+//!     .build()
+//!     .expect("valid configuration");
+//! let flowsets = parser.parse_bytes_as_netflow_common_flowsets(&v5_packet);
+//! assert_eq!(flowsets[0].src_addr, Some(IpAddr::V4(Ipv4Addr::new(0, 1, 2, 0))));
+//! ```
+
+use crate::netflow_common::NetflowCommonFlowSet;
+use crate::variable_versions::data_number::{FieldDataType, FieldValue};
+use crate::NetflowPacket;
+
+use alloc::collections::BTreeMap;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// How addresses are anonymized.
+#[derive(Debug, Clone)]
+pub enum Anonymizer {
+    /// Keeps the first `ipv4_prefix` bits of IPv4 addresses and `ipv6_prefix` bits of IPv6
+    /// addresses, zeroing the rest.
+    Truncate { ipv4_prefix: u8, ipv6_prefix: u8 },
+    /// Prefix preserving Crypto-PAn.
+    CryptoPan(CryptoPan),
+}
+
+impl Anonymizer {
+    /// Keeps `ipv4_prefix` bits of IPv4 and `ipv6_prefix` bits of IPv6 addresses.  Prefixes
+    /// longer than the address keep all of it.
+    pub fn truncate(ipv4_prefix: u8, ipv6_prefix: u8) -> Self {
+        Self::Truncate {
+            ipv4_prefix,
+            ipv6_prefix,
+        }
+    }
+
+    /// Crypto-PAn keyed by `key`: the first 16 bytes are the AES key, the last 16 the pad.
+    pub fn crypto_pan(key: [u8; 32]) -> Self {
+        Self::CryptoPan(CryptoPan::new(key))
+    }
+
+    pub fn anonymize(&self, addr: IpAddr) -> IpAddr {
+        match addr {
+            IpAddr::V4(addr) => IpAddr::V4(self.anonymize_v4(addr)),
+            IpAddr::V6(addr) => IpAddr::V6(self.anonymize_v6(addr)),
+        }
+    }
+
+    pub fn anonymize_v4(&self, addr: Ipv4Addr) -> Ipv4Addr {
+        match self {
+            Self::Truncate { ipv4_prefix, .. } => {
+                Ipv4Addr::from(u32::from(addr) & prefix_mask::<u32>(*ipv4_prefix))
+            }
+            Self::CryptoPan(crypto_pan) => crypto_pan.anonymize_v4(addr),
+        }
+    }
+
+    pub fn anonymize_v6(&self, addr: Ipv6Addr) -> Ipv6Addr {
+        match self {
+            Self::Truncate { ipv6_prefix, .. } => {
+                Ipv6Addr::from(u128::from(addr) & prefix_mask::<u128>(*ipv6_prefix))
+            }
+            Self::CryptoPan(crypto_pan) => crypto_pan.anonymize_v6(addr),
+        }
+    }
+
+    /// Anonymizes every address of `packet`.
+    pub fn anonymize_packet(&self, packet: &mut NetflowPacket) {
+        match packet {
+            NetflowPacket::V5(v5) => {
+                for flowset in v5.flowsets.iter_mut() {
+                    flowset.src_addr = self.anonymize_v4(flowset.src_addr);
+                    flowset.dst_addr = self.anonymize_v4(flowset.dst_addr);
+                    flowset.next_hop = self.anonymize_v4(flowset.next_hop);
+                }
+            }
+            NetflowPacket::V7(v7) => {
+                for flowset in v7.flowsets.iter_mut() {
+                    flowset.src_addr = self.anonymize_v4(flowset.src_addr);
+                    flowset.dst_addr = self.anonymize_v4(flowset.dst_addr);
+                    flowset.next_hop = self.anonymize_v4(flowset.next_hop);
+                    flowset.router_src = self.anonymize_v4(flowset.router_src);
+                }
+            }
+            NetflowPacket::V9(v9) => {
+                for flowset in v9.flowsets.iter_mut() {
+                    if let Some(data) = flowset.body.data.as_mut() {
+                        data.data_fields
+                            .iter_mut()
+                            .for_each(|record| self.anonymize_record(record));
+                    }
+                    if let Some(options_data) = flowset.body.options_data.as_mut() {
+                        for field in options_data.options_fields.iter_mut() {
+                            self.anonymize_bytes(
+                                field.field_type.into(),
+                                &mut field.field_value,
+                            );
+                        }
+                    }
+                }
+            }
+            NetflowPacket::IPFix(ipfix) => {
+                for flowset in ipfix.flowsets.iter_mut() {
+                    let records = flowset
+                        .body
+                        .data
+                        .iter_mut()
+                        .flat_map(|data| data.data_fields.iter_mut())
+                        .chain(
+                            flowset
+                                .body
+                                .options_data
+                                .iter_mut()
+                                .flat_map(|data| data.data_fields.iter_mut()),
+                        );
+                    records.for_each(|record| self.anonymize_record(record));
+                }
+            }
+            NetflowPacket::Error(_) => {}
+        }
+    }
+
+    /// Anonymizes the addresses of a flow converted from a packet that wasn't.
+    pub fn anonymize_flow(&self, flow: &mut NetflowCommonFlowSet) {
+        for addr in [&mut flow.src_addr, &mut flow.dst_addr, &mut flow.next_hop] {
+            *addr = addr.map(|addr| self.anonymize(addr));
+        }
+    }
+
+    fn anonymize_record<F>(&self, record: &mut BTreeMap<usize, (F, FieldValue)>) {
+        for (_, value) in record.values_mut() {
+            self.anonymize_value(value);
+        }
+    }
+
+    /// Anonymizes the raw bytes of a field of `data_type` if it is an address.
+    fn anonymize_bytes(&self, data_type: FieldDataType, bytes: &mut [u8]) {
+        match (
+            data_type,
+            <[u8; 4]>::try_from(&*bytes),
+            <[u8; 16]>::try_from(&*bytes),
+        ) {
+            (FieldDataType::Ip4Addr, Ok(addr), _) => {
+                bytes.copy_from_slice(&self.anonymize_v4(addr.into()).octets())
+            }
+            (FieldDataType::Ip6Addr, _, Ok(addr)) => {
+                bytes.copy_from_slice(&self.anonymize_v6(addr.into()).octets())
+            }
+            _ => {}
+        }
+    }
+
+    /// Anonymizes `value` if it is an address.
+    pub(crate) fn anonymize_value(&self, value: &mut FieldValue) {
+        match value {
+            FieldValue::Ip4Addr(addr) => *addr = self.anonymize_v4(*addr),
+            FieldValue::Ip6Addr(addr) => *addr = self.anonymize_v6(*addr),
+            _ => {}
+        }
+    }
+}
+
+/// Mask of the first `prefix` bits of an address.
+pub(crate) fn prefix_mask<T>(prefix: u8) -> T
+where
+    T: From<u8> + core::ops::Not<Output = T> + core::ops::Shr<u32, Output = T>,
+    T: core::ops::Shl<u32, Output = T> + Copy,
+{
+    let bits = (core::mem::size_of::<T>() * 8) as u32;
+    let prefix = u32::from(prefix).min(bits);
+    if prefix == 0 {
+        T::from(0)
+    } else {
+        !T::from(0) << (bits - prefix)
+    }
+}
+
+/// Crypto-PAn (Xu, Fan, Ammar and Moon, 2002), anonymizing each bit of an address with the
+/// AES encryption of the bits before it, so that the mapping is one to one and preserves
+/// prefixes.  IPv6 addresses are anonymized the same way over 128 bits.
+#[derive(Clone)]
+pub struct CryptoPan {
+    cipher: Aes128,
+    pad: [u8; 16],
+}
+
+/// Leaves the key out.
+impl fmt::Debug for CryptoPan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CryptoPan").finish_non_exhaustive()
+    }
+}
+
+impl CryptoPan {
+    pub fn new(key: [u8; 32]) -> Self {
+        let mut cipher_key = [0; 16];
+        cipher_key.copy_from_slice(&key[..16]);
+        let mut pad = [0; 16];
+        pad.copy_from_slice(&key[16..]);
+        let cipher = Aes128::new(&cipher_key);
+        cipher.encrypt(&mut pad);
+        Self { cipher, pad }
+    }
+
+    pub fn anonymize_v4(&self, addr: Ipv4Addr) -> Ipv4Addr {
+        let addr = u32::from(addr);
+        let mut pad = [0; 4];
+        pad.copy_from_slice(&self.pad[..4]);
+        let pad = u32::from_be_bytes(pad);
+        let mut flips = 0;
+        for position in 0..32 {
+            let prefix = prefix_mask::<u32>(position as u8);
+            let mut block = self.pad;
+            block[..4].copy_from_slice(&((addr & prefix) | (pad & !prefix)).to_be_bytes());
+            self.cipher.encrypt(&mut block);
+            flips |= u32::from(block[0] >> 7) << (31 - position);
+        }
+        Ipv4Addr::from(addr ^ flips)
+    }
+
+    pub fn anonymize_v6(&self, addr: Ipv6Addr) -> Ipv6Addr {
+        let addr = u128::from(addr);
+        let pad = u128::from_be_bytes(self.pad);
+        let mut flips = 0;
+        for position in 0..128 {
+            let prefix = prefix_mask::<u128>(position as u8);
+            let mut block = ((addr & prefix) | (pad & !prefix)).to_be_bytes();
+            self.cipher.encrypt(&mut block);
+            flips |= u128::from(block[0] >> 7) << (127 - position);
+        }
+        Ipv6Addr::from(addr ^ flips)
+    }
+}
+
+/// AES-128 encryption of single blocks (FIPS 197), all Crypto-PAn needs.  Not constant
+/// time, which Crypto-PAn doesn't rely on.
+#[derive(Clone)]
+struct Aes128 {
+    round_keys: [[u8; 16]; 11],
+}
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab,
+    0x76, 0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4,
+    0x72, 0xc0, 0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71,
+    0xd8, 0x31, 0x15, 0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2,
+    0xeb, 0x27, 0xb2, 0x75, 0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6,
+    0xb3, 0x29, 0xe3, 0x2f, 0x84, 0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb,
+    0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf, 0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45,
+    0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8, 0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5,
+    0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2, 0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44,
+    0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73, 0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a,
+    0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb, 0xe0, 0x32, 0x3a, 0x0a, 0x49,
+    0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79, 0xe7, 0xc8, 0x37, 0x6d,
+    0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08, 0xba, 0x78, 0x25,
+    0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a, 0x70, 0x3e,
+    0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e, 0xe1,
+    0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb,
+    0x16,
+];
+
+/// Doubles `byte` in GF(2^8).
+fn xtime(byte: u8) -> u8 {
+    (byte << 1) ^ if byte & 0x80 == 0 { 0 } else { 0x1b }
+}
+
+impl Aes128 {
+    fn new(key: &[u8; 16]) -> Self {
+        let mut round_keys = [[0; 16]; 11];
+        round_keys[0] = *key;
+        let mut rcon = 1;
+        for round in 1..11 {
+            let previous = round_keys[round - 1];
+            let mut word = [previous[13], previous[14], previous[15], previous[12]];
+            word.iter_mut()
+                .for_each(|byte| *byte = SBOX[usize::from(*byte)]);
+            word[0] ^= rcon;
+            rcon = xtime(rcon);
+            let key = &mut round_keys[round];
+            for i in 0..16 {
+                let byte = if i < 4 { word[i] } else { key[i - 4] };
+                key[i] = previous[i] ^ byte;
+            }
+        }
+        Self { round_keys }
+    }
+
+    fn encrypt(&self, block: &mut [u8; 16]) {
+        let add_round_key = |block: &mut [u8; 16], key: &[u8; 16]| {
+            block
+                .iter_mut()
+                .zip(key)
+                .for_each(|(byte, key)| *byte ^= key);
+        };
+        add_round_key(block, &self.round_keys[0]);
+        for round in 1..11 {
+            // SubBytes and ShiftRows, the block being column major
+            let state = *block;
+            for column in 0..4 {
+                for row in 0..4 {
+                    let byte = state[((column + row) % 4) * 4 + row];
+                    block[column * 4 + row] = SBOX[usize::from(byte)];
+                }
+            }
+            if round < 10 {
+                for column in block.chunks_exact_mut(4) {
+                    let [a, b, c, d] = [column[0], column[1], column[2], column[3]];
+                    let all = a ^ b ^ c ^ d;
+                    column[0] ^= all ^ xtime(a ^ b);
+                    column[1] ^= all ^ xtime(b ^ c);
+                    column[2] ^= all ^ xtime(c ^ d);
+                    column[3] ^= all ^ xtime(d ^ a);
+                }
+            }
+            add_round_key(block, &self.round_keys[round]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod anonymize_tests {
+    use super::{Aes128, Anonymizer};
+    use crate::{NetflowPacket, NetflowParser};
+
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn it_encrypts_the_fips_197_example() {
+        let key: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let mut block: [u8; 16] = core::array::from_fn(|i| (i as u8) * 0x11);
+        Aes128::new(&key).encrypt(&mut block);
+        assert_eq!(
+            block,
+            [
+                0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70,
+                0xb4, 0xc5, 0x5a
+            ]
+        );
+    }
+
+    #[test]
+    fn it_anonymizes_with_crypto_pan() {
+        // Key and addresses of the sample trace of the Crypto-PAn reference implementation
+        let anonymizer = Anonymizer::crypto_pan([
+            21, 34, 23, 141, 51, 164, 207, 128, 19, 10, 91, 22, 73, 144, 125, 16, 216, 152,
+            143, 131, 121, 121, 101, 39, 98, 87, 76, 45, 42, 132, 34, 2,
+        ]);
+        for (addr, anonymized) in [
+            ("128.11.68.132", "135.242.180.132"),
+            ("129.118.74.4", "134.136.186.123"),
+            ("130.132.252.244", "133.68.164.234"),
+        ] {
+            let addr: Ipv4Addr = addr.parse().unwrap();
+            assert_eq!(
+                anonymizer.anonymize_v4(addr),
+                anonymized.parse::<Ipv4Addr>().unwrap()
+            );
+        }
+
+        // Prefixes are preserved over IPv6 too.
+        let anonymize = |addr: &str| anonymizer.anonymize_v6(addr.parse().unwrap());
+        let (a, b) = (anonymize("2001:db8::1"), anonymize("2001:db8::2"));
+        assert_ne!(a, b);
+        assert_eq!(u128::from(a) >> 2, u128::from(b) >> 2);
+        assert_ne!(a, "2001:db8::1".parse::<Ipv6Addr>().unwrap());
+    }
+
+    #[test]
+    fn it_truncates_addresses_while_parsing() {
+        // Template 256 of sourceIPv6Address, and a record of it
+        let ipfix = [
+            0, 10, 0, 48, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 12, 1, 0, 0, 1, 0, 27,
+            0, 16, 1, 0, 0, 20, 0x20, 1, 0x0d, 0xb8, 0, 1, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1,
+        ];
+        let mut parser = NetflowParser::builder()
+            .with_anonymizer(Anonymizer::truncate(24, 32))
+            .build()
+            .unwrap();
+        let flowsets = parser.parse_bytes_as_netflow_common_flowsets(&ipfix);
+        assert_eq!(
+            flowsets[0].src_addr,
+            Some(IpAddr::V6("2001:db8::".parse().unwrap()))
+        );
+        assert_eq!(
+            Anonymizer::truncate(40, 0).anonymize_v4(Ipv4Addr::new(192, 0, 2, 1)),
+            Ipv4Addr::new(192, 0, 2, 1)
+        );
+    }
+
+    #[test]
+    fn it_truncates_v9_options_addresses() {
+        // Options template 256 of a system scope and ipv4SrcAddr, and a record of it
+        let v9 = [
+            0, 9, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 1, 0, 18, 1, 0, 0,
+            4, 0, 4, 0, 1, 0, 4, 0, 8, 0, 4, 1, 0, 0, 12, 0, 0, 0, 1, 192, 0, 2, 1,
+        ];
+        let mut parser = NetflowParser::builder()
+            .with_anonymizer(Anonymizer::truncate(24, 32))
+            .build()
+            .unwrap();
+        let Some(NetflowPacket::V9(v9)) = parser.parse_bytes(&v9).pop() else {
+            panic!("expected a V9 packet");
+        };
+        let options_data = v9.flowsets[1].body.options_data.as_ref().unwrap();
+        assert_eq!(options_data.options_fields[0].field_value, [192, 0, 2, 0]);
+        // Scope values aren't addresses
+        assert_eq!(options_data.scope_fields[0].system, Some(vec![0, 0, 0, 1]));
+    }
+}
@@ -0,0 +1,397 @@
+//! # Archive
+//!
+//! A container for recording raw netflow feeds and replaying them later, such as for
+//! regression tests of a collector.  [`ArchiveWriter`] records each message with the time it
+//! was received and the address it came from, [`ArchiveReader`] reads them back, and
+//! [`ArchiveReplay`] parses them again with a parser per exporter, at the pace they were
+//! recorded, faster, or as fast as possible.
+//!
+//! An archive starts with the magic `NFAR` and a big endian `u16` format version, currently
+//! 1, followed by two reserved bytes.  Every record is, in big endian:
+//!
+//! | Field | Size | |
+//! |-|-|-|
+//! | timestamp | 8 | Nanoseconds since the unix epoch |
+//! | family | 1 | 0 without a source, 4 for IPv4, 6 for IPv6 |
+//! | address | 0, 4 or 16 | Source address |
+//! | port | 0 or 2 | Source port, unless the family is 0 |
+//! | length | 4 | Length of the message, at most 65535 |
+//! | message | length | Message as received |
+//!
+//! ```rust
+//! use netflow_parser::archive::{ArchiveReader, ArchiveReplay, ArchiveWriter, ReplaySpeed};
+//! use netflow_parser::NetflowParser;
+//! use std::time::Duration;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let mut writer = ArchiveWriter::new(Vec::new())?;
+//! writer.write_message(Duration::from_secs(1_700_000_000), None, &v5_packet)?;
+//! let archive = writer.into_inner();
+//!
+//! let reader = ArchiveReader::new(archive.as_slice())?;
+//! let mut replay = ArchiveReplay::new(reader, NetflowParser::builder())?
+//!     .with_speed(ReplaySpeed::Unpaced);
+//! while let Some((record, packets)) = replay.next_packets()? {
+//!     assert_eq!(record.message, v5_packet);
+//!     assert!(packets[0].is_v5());
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::{NetflowPacket, NetflowParser, NetflowParserBuilder};
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MAGIC: [u8; 4] = *b"NFAR";
+const FORMAT_VERSION: u16 = 1;
+const FAMILY_NONE: u8 = 0;
+const FAMILY_IPV4: u8 = 4;
+const FAMILY_IPV6: u8 = 6;
+/// Longest message an archive holds, the longest netflow message.  Longer lengths are read
+/// as a corrupt archive rather than allocated.
+const MAX_MESSAGE_LENGTH: usize = 65_535;
+
+/// A recorded message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveRecord {
+    /// Time the message was received, since the unix epoch
+    pub timestamp: Duration,
+    /// Address the message came from, if known
+    pub source: Option<SocketAddr>,
+    /// The message as received
+    pub message: Vec<u8>,
+}
+
+/// Records messages to an archive.
+#[derive(Debug)]
+pub struct ArchiveWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Writes the archive header to `writer`.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_be_bytes())?;
+        writer.write_all(&[0, 0])?;
+        Ok(Self { writer })
+    }
+
+    /// Records `message`, received at `timestamp` since the unix epoch from `source`.
+    pub fn write_message(
+        &mut self,
+        timestamp: Duration,
+        source: Option<SocketAddr>,
+        message: &[u8],
+    ) -> io::Result<()> {
+        let invalid = |reason| io::Error::new(io::ErrorKind::InvalidInput, reason);
+        let nanos = u64::try_from(timestamp.as_nanos())
+            .map_err(|_| invalid("timestamp past the year 2554"))?;
+        if message.len() > MAX_MESSAGE_LENGTH {
+            return Err(invalid("message longer than 65535 bytes"));
+        }
+        let length = message.len() as u32;
+        let mut header = Vec::with_capacity(31);
+        header.extend(nanos.to_be_bytes());
+        match source {
+            None => header.push(FAMILY_NONE),
+            Some(SocketAddr::V4(source)) => {
+                header.push(FAMILY_IPV4);
+                header.extend(source.ip().octets());
+                header.extend(source.port().to_be_bytes());
+            }
+            Some(SocketAddr::V6(source)) => {
+                header.push(FAMILY_IPV6);
+                header.extend(source.ip().octets());
+                header.extend(source.port().to_be_bytes());
+            }
+        }
+        header.extend(length.to_be_bytes());
+        self.writer.write_all(&header)?;
+        self.writer.write_all(message)
+    }
+
+    /// Records `record`.
+    pub fn write_record(&mut self, record: &ArchiveRecord) -> io::Result<()> {
+        self.write_message(record.timestamp, record.source, &record.message)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// The underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads the records of an archive.
+#[derive(Debug)]
+pub struct ArchiveReader<R> {
+    reader: R,
+}
+
+fn invalid_archive(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+impl<R: Read> ArchiveReader<R> {
+    /// Reads and checks the archive header.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(invalid_archive(format!(
+                "not a netflow archive, magic {:02x?}",
+                &header[..4]
+            )));
+        }
+        let version = u16::from_be_bytes([header[4], header[5]]);
+        if version != FORMAT_VERSION {
+            return Err(invalid_archive(format!(
+                "unsupported archive format version {version}"
+            )));
+        }
+        Ok(Self { reader })
+    }
+
+    /// Reads the next record, `None` at the end of the archive.
+    pub fn next_record(&mut self) -> io::Result<Option<ArchiveRecord>> {
+        let mut prefix = [0; 9];
+        // The archive may only end between records
+        match self.reader.read(&mut prefix[..1])? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut prefix[1..])?,
+        }
+        let timestamp =
+            Duration::from_nanos(u64::from_be_bytes(prefix[..8].try_into().expect("8 bytes")));
+        let source = match prefix[8] {
+            FAMILY_NONE => None,
+            FAMILY_IPV4 => {
+                let mut address = [0; 6];
+                self.reader.read_exact(&mut address)?;
+                let ip = Ipv4Addr::new(address[0], address[1], address[2], address[3]);
+                Some(SocketAddr::new(
+                    IpAddr::V4(ip),
+                    u16::from_be_bytes([address[4], address[5]]),
+                ))
+            }
+            FAMILY_IPV6 => {
+                let mut address = [0; 18];
+                self.reader.read_exact(&mut address)?;
+                let ip: [u8; 16] = address[..16].try_into().expect("16 bytes");
+                Some(SocketAddr::new(
+                    IpAddr::V6(Ipv6Addr::from(ip)),
+                    u16::from_be_bytes([address[16], address[17]]),
+                ))
+            }
+            family => return Err(invalid_archive(format!("unknown address family {family}"))),
+        };
+        let mut length = [0; 4];
+        self.reader.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_MESSAGE_LENGTH {
+            return Err(invalid_archive(format!(
+                "message length {length} is over 65535"
+            )));
+        }
+        let mut message = vec![0; length];
+        self.reader.read_exact(&mut message)?;
+        Ok(Some(ArchiveRecord {
+            timestamp,
+            source,
+            message,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for ArchiveReader<R> {
+    type Item = io::Result<ArchiveRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Pace of an [`ArchiveReplay`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Records are returned as far apart as they were recorded
+    Original,
+    /// Records are returned this many times faster than they were recorded
+    Accelerated(f64),
+    /// Records are returned as soon as they are read
+    Unpaced,
+}
+
+/// Replays the records of an archive through a parser per exporter address.
+#[derive(Debug)]
+pub struct ArchiveReplay<R> {
+    reader: ArchiveReader<R>,
+    builder: NetflowParserBuilder,
+    speed: ReplaySpeed,
+    parsers: HashMap<Option<IpAddr>, NetflowParser>,
+    /// When the first record was replayed, and its timestamp
+    origin: Option<(Instant, Duration)>,
+}
+
+impl<R: Read> ArchiveReplay<R> {
+    /// Parsers for new exporters are built from `builder`.  Records are replayed at their
+    /// original pace.
+    pub fn new(reader: ArchiveReader<R>, builder: NetflowParserBuilder) -> io::Result<Self> {
+        // Surface configuration errors now rather than on the first exporter.
+        builder
+            .clone()
+            .build()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(Self {
+            reader,
+            builder,
+            speed: ReplaySpeed::Original,
+            parsers: HashMap::new(),
+            origin: None,
+        })
+    }
+
+    pub fn with_speed(mut self, speed: ReplaySpeed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Parsers by exporter address, holding the templates learned so far.  Records without a
+    /// source share the parser under `None`.
+    pub fn parsers(&self) -> &HashMap<Option<IpAddr>, NetflowParser> {
+        &self.parsers
+    }
+
+    /// Reads the next record, once it is due.  `None` at the end of the archive.
+    pub fn next_record(&mut self) -> io::Result<Option<ArchiveRecord>> {
+        let Some(record) = self.reader.next_record()? else {
+            return Ok(None);
+        };
+        let factor = match self.speed {
+            ReplaySpeed::Original => 1.0,
+            ReplaySpeed::Accelerated(factor) => factor,
+            ReplaySpeed::Unpaced => return Ok(Some(record)),
+        };
+        let (started, first) = *self
+            .origin
+            .get_or_insert((Instant::now(), record.timestamp));
+        let offset = record.timestamp.saturating_sub(first);
+        if factor > 0.0 {
+            let due = started + offset.div_f64(factor);
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+        Ok(Some(record))
+    }
+
+    /// Reads the next record once it is due and parses it with the parser of its exporter.
+    /// Packets get the record timestamp as `received_at`.
+    pub fn next_packets(&mut self) -> io::Result<Option<(ArchiveRecord, Vec<NetflowPacket>)>> {
+        let Some(record) = self.next_record()? else {
+            return Ok(None);
+        };
+        let builder = &self.builder;
+        let parser = self
+            .parsers
+            .entry(record.source.map(|source| source.ip()))
+            .or_insert_with(|| builder.clone().build().expect("validated in new"));
+        let packets = parser.parse_bytes_received_at(&record.message, record.timestamp);
+        Ok(Some((record, packets)))
+    }
+}
+
+#[cfg(test)]
+mod archive_tests {
+    use super::{ArchiveReader, ArchiveRecord, ArchiveReplay, ArchiveWriter, ReplaySpeed};
+    use crate::testing::V9_PACKET;
+    use crate::NetflowParser;
+
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    fn records() -> Vec<ArchiveRecord> {
+        let sources: [Option<SocketAddr>; 3] = [
+            Some("192.0.2.1:2055".parse().unwrap()),
+            Some("[2001:db8::1]:4739".parse().unwrap()),
+            None,
+        ];
+        sources
+            .into_iter()
+            .enumerate()
+            .map(|(i, source)| ArchiveRecord {
+                timestamp: Duration::new(1_700_000_000, 0)
+                    + Duration::from_millis(20) * i as u32,
+                source,
+                message: V9_PACKET.to_vec(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_reads_back_recorded_messages() {
+        let mut writer = ArchiveWriter::new(vec![]).unwrap();
+        for record in records() {
+            writer.write_record(&record).unwrap();
+        }
+        let archive = writer.into_inner();
+        let read: Vec<_> = ArchiveReader::new(archive.as_slice())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, records());
+
+        // Cut short in the middle of a record
+        let mut reader = ArchiveReader::new(&archive[..archive.len() - 1]).unwrap();
+        assert!(reader.next_record().unwrap().is_some());
+        assert!(reader.next_record().unwrap().is_some());
+        assert!(reader.next_record().is_err());
+
+        assert!(ArchiveReader::new(&V9_PACKET[..]).is_err());
+
+        // A corrupt length is rejected instead of allocated
+        let mut corrupt = ArchiveWriter::new(vec![]).unwrap();
+        corrupt.write_message(Duration::ZERO, None, &[]).unwrap();
+        let mut corrupt = corrupt.into_inner();
+        let length = corrupt.len() - 4;
+        corrupt[length..].copy_from_slice(&u32::MAX.to_be_bytes());
+        let mut reader = ArchiveReader::new(corrupt.as_slice()).unwrap();
+        assert!(reader.next_record().is_err());
+        let mut writer = ArchiveWriter::new(vec![]).unwrap();
+        assert!(writer
+            .write_message(Duration::ZERO, None, &[0; 65_536])
+            .is_err());
+    }
+
+    #[test]
+    fn it_replays_at_an_accelerated_pace() {
+        let mut writer = ArchiveWriter::new(vec![]).unwrap();
+        for record in records() {
+            writer.write_record(&record).unwrap();
+        }
+        let archive = writer.into_inner();
+        let reader = ArchiveReader::new(archive.as_slice()).unwrap();
+        let mut replay = ArchiveReplay::new(reader, NetflowParser::builder())
+            .unwrap()
+            .with_speed(ReplaySpeed::Accelerated(2.0));
+        let started = Instant::now();
+        let mut replayed = 0;
+        while let Some((record, packets)) = replay.next_packets().unwrap() {
+            assert!(packets[0].is_v9());
+            assert_eq!(packets[0].received_at(), Some(record.timestamp));
+            replayed += 1;
+        }
+        assert_eq!(replayed, 3);
+        // The last record was recorded 40ms after the first
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        // Each exporter got its own parser
+        assert_eq!(replay.parsers().len(), 3);
+    }
+}
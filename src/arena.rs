@@ -0,0 +1,291 @@
+//! # Arena
+//!
+//! Allocation light parsing for collectors handling hundreds of thousands of records a
+//! second.  [`NetflowParser::parse_records`] decodes the V9 and IPFix data records of a buffer
+//! straight into a [`RecordArena`], whose fields live in one `Vec` per version, instead of
+//! allocating a map per record.  Clearing the arena keeps its capacity, so once it has grown to
+//! the size of a buffer, records are parsed without allocating beyond the strings and byte
+//! fields they hold.  Templates, options records and everything else the parser keeps are
+//! handled as usual.  Requires the `perf` feature.
+//!
+//! ```rust
+//! use netflow_parser::arena::{ArenaRecord, RecordArena};
+//! use netflow_parser::NetflowParser;
+//!
+//! let v9_packet = [
+//!     0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0, 2, 0,
+//!     1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
+//! ];
+//! let mut parser = NetflowParser::default();
+//! let mut arena = RecordArena::new();
+//! for _ in 0..3 {
+//!     arena.clear();
+//!     parser.parse_records(&v9_packet, &mut arena).expect("valid packet");
+//!     for record in arena.iter() {
+//!         if let ArenaRecord::V9(fields) = record {
+//!             assert_eq!(fields.len(), 2);
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::anonymize::Anonymizer;
+use crate::flow_records::FlowRecord;
+use crate::static_versions::{v5, v7};
+use crate::variable_versions::ipfix::IPFixFieldPair;
+use crate::variable_versions::v9::V9FieldPair;
+use crate::NetflowPacket;
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// Where the fields of a record are kept in a [`RecordArena`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    V5(usize),
+    V7(usize),
+    V9(Range<usize>),
+    IPFix(Range<usize>),
+}
+
+/// Flow records of parsed packets, with the fields of every V9 and IPFix record kept in one
+/// `Vec` per version.  Records are in the order they were parsed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RecordArena {
+    v5: Vec<v5::FlowSet>,
+    v7: Vec<v7::FlowSet>,
+    v9_fields: Vec<V9FieldPair>,
+    ipfix_fields: Vec<IPFixFieldPair>,
+    entries: Vec<Entry>,
+}
+
+/// A flow record borrowed from a [`RecordArena`].  V9/IPFix records hold their fields in
+/// template order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArenaRecord<'a> {
+    V5(&'a v5::FlowSet),
+    V7(&'a v7::FlowSet),
+    V9(&'a [V9FieldPair]),
+    IPFix(&'a [IPFixFieldPair]),
+}
+
+impl ArenaRecord<'_> {
+    /// Netflow version of the packet the record came from.
+    pub fn version(&self) -> u16 {
+        match self {
+            ArenaRecord::V5(_) => 5,
+            ArenaRecord::V7(_) => 7,
+            ArenaRecord::V9(_) => 9,
+            ArenaRecord::IPFix(_) => 10,
+        }
+    }
+
+    /// Copies the record out of the arena.
+    pub fn to_flow_record(&self) -> FlowRecord {
+        match self {
+            ArenaRecord::V5(record) => FlowRecord::V5((*record).clone()),
+            ArenaRecord::V7(record) => FlowRecord::V7((*record).clone()),
+            ArenaRecord::V9(fields) => FlowRecord::V9(fields.to_vec()),
+            ArenaRecord::IPFix(fields) => FlowRecord::IPFix(fields.to_vec()),
+        }
+    }
+}
+
+impl RecordArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of records held.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drops every record, keeping the memory allocated for the next ones.
+    pub fn clear(&mut self) {
+        self.v5.clear();
+        self.v7.clear();
+        self.v9_fields.clear();
+        self.ipfix_fields.clear();
+        self.entries.clear();
+    }
+
+    /// Record `index`, in the order records were parsed.
+    pub fn get(&self, index: usize) -> Option<ArenaRecord<'_>> {
+        self.entries.get(index).map(|entry| self.record(entry))
+    }
+
+    /// Every record, in the order they were parsed.
+    pub fn iter(&self) -> impl Iterator<Item = ArenaRecord<'_>> + '_ {
+        self.entries.iter().map(|entry| self.record(entry))
+    }
+
+    fn record(&self, entry: &Entry) -> ArenaRecord<'_> {
+        match entry {
+            Entry::V5(index) => ArenaRecord::V5(&self.v5[*index]),
+            Entry::V7(index) => ArenaRecord::V7(&self.v7[*index]),
+            Entry::V9(range) => ArenaRecord::V9(&self.v9_fields[range.clone()]),
+            Entry::IPFix(range) => ArenaRecord::IPFix(&self.ipfix_fields[range.clone()]),
+        }
+    }
+
+    /// Records and V9 fields held, to roll back to if a flowset fails.
+    pub(crate) fn v9_mark(&self) -> (usize, usize) {
+        (self.entries.len(), self.v9_fields.len())
+    }
+
+    /// Drops the records and V9 fields added since `mark`.
+    pub(crate) fn v9_rollback(&mut self, (entries, fields): (usize, usize)) {
+        self.entries.truncate(entries);
+        self.v9_fields.truncate(fields);
+    }
+
+    /// Fields of the V9 records, to push the fields of the next one onto.
+    pub(crate) fn v9_fields(&mut self) -> &mut Vec<V9FieldPair> {
+        &mut self.v9_fields
+    }
+
+    /// Ends the V9 record whose fields start at `start`, dropping it unless `keep`.
+    pub(crate) fn end_v9_record(&mut self, start: usize, keep: bool) {
+        if keep {
+            self.entries.push(Entry::V9(start..self.v9_fields.len()));
+        } else {
+            self.v9_fields.truncate(start);
+        }
+    }
+
+    /// Records and IPFix fields held, to roll back to if a set fails.
+    pub(crate) fn ipfix_mark(&self) -> (usize, usize) {
+        (self.entries.len(), self.ipfix_fields.len())
+    }
+
+    /// Drops the records and IPFix fields added since `mark`.
+    pub(crate) fn ipfix_rollback(&mut self, (entries, fields): (usize, usize)) {
+        self.entries.truncate(entries);
+        self.ipfix_fields.truncate(fields);
+    }
+
+    /// Fields of the IPFix records, to push the fields of the next one onto.
+    pub(crate) fn ipfix_fields(&mut self) -> &mut Vec<IPFixFieldPair> {
+        &mut self.ipfix_fields
+    }
+
+    /// Ends the IPFix record whose fields start at `start`, dropping it unless `keep`.
+    pub(crate) fn end_ipfix_record(&mut self, start: usize, keep: bool) {
+        if keep {
+            self.entries
+                .push(Entry::IPFix(start..self.ipfix_fields.len()));
+        } else {
+            self.ipfix_fields.truncate(start);
+        }
+    }
+
+    /// Moves the V5 and V7 records of `packet` into the arena.  V9 and IPFix records were
+    /// decoded into it already.
+    pub(crate) fn push_packet(&mut self, packet: NetflowPacket) {
+        match packet {
+            NetflowPacket::V5(v5) => {
+                for record in v5.flowsets {
+                    self.entries.push(Entry::V5(self.v5.len()));
+                    self.v5.push(record);
+                }
+            }
+            NetflowPacket::V7(v7) => {
+                for record in v7.flowsets {
+                    self.entries.push(Entry::V7(self.v7.len()));
+                    self.v7.push(record);
+                }
+            }
+            NetflowPacket::V9(_) | NetflowPacket::IPFix(_) | NetflowPacket::Error(_) => {}
+        }
+    }
+
+    /// Anonymizes the addresses of the V9 and IPFix fields from `v9_start` and
+    /// `ipfix_start` on.  V5 and V7 records were anonymized with their packet.
+    pub(crate) fn anonymize_from(
+        &mut self,
+        anonymizer: &Anonymizer,
+        v9_start: usize,
+        ipfix_start: usize,
+    ) {
+        let v9 = self.v9_fields[v9_start..]
+            .iter_mut()
+            .map(|(_, value)| value);
+        let ipfix = self.ipfix_fields[ipfix_start..]
+            .iter_mut()
+            .map(|(_, value)| value);
+        v9.chain(ipfix)
+            .for_each(|value| anonymizer.anonymize_value(value));
+    }
+
+    /// Number of V9 and IPFix fields held, to anonymize the ones parsed next.
+    pub(crate) fn field_counts(&self) -> (usize, usize) {
+        (self.v9_fields.len(), self.ipfix_fields.len())
+    }
+}
+
+#[cfg(test)]
+mod arena_tests {
+    use super::{ArenaRecord, RecordArena};
+    use crate::filter::Filter;
+    use crate::flow_records::FlowRecord;
+    use crate::variable_versions::v9::CountSemantics;
+    use crate::{NetflowError, NetflowParser};
+
+    #[test]
+    fn it_parses_records_into_the_arena() {
+        // A template of bytes and source address, then a data flowset of two records
+        // counted as RFC 3954 defines it, followed by a V5 packet and an IPFix packet of a
+        // template and two records
+        let v9_packet = [
+            0, 9, 0, 3, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 20, 9, 2, 3, 4, 9, 9, 9, 8, 1, 1, 1, 1, 2, 2,
+            2, 2,
+        ];
+        let v5_packet = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let ipfix_packet = [
+            0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2,
+            0, 2, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let buffer = [&v9_packet[..], &v5_packet, &ipfix_packet].concat();
+        let mut parser = NetflowParser::builder()
+            .with_v9_count_semantics(CountSemantics::Records)
+            .build()
+            .unwrap();
+        let expected: Vec<FlowRecord> = parser.iter_flow_records(&buffer).collect();
+
+        let mut arena = RecordArena::new();
+        parser.parse_records(&buffer, &mut arena).unwrap();
+        let records: Vec<FlowRecord> = arena.iter().map(|r| r.to_flow_record()).collect();
+        assert_eq!(records, expected);
+        assert_eq!(arena.len(), 5);
+        assert_eq!(arena.get(2).map(|record| record.version()), Some(5));
+        assert!(matches!(arena.get(3), Some(ArenaRecord::IPFix(fields)) if fields.len() == 3));
+
+        // Filtered records are dropped from the arena as they are decoded
+        let mut parser = NetflowParser::builder()
+            .with_filter(Filter::parse("bytes<20000000").unwrap())
+            .build()
+            .unwrap();
+        arena.clear();
+        parser.parse_records(&v9_packet, &mut arena).unwrap();
+        assert_eq!(arena.len(), 1);
+        assert!(matches!(arena.get(0), Some(ArenaRecord::V9(fields)) if fields.len() == 2));
+
+        // Parsing stops at a packet it can't skip
+        arena.clear();
+        assert!(matches!(
+            parser.parse_records(&[0, 11, 0, 0], &mut arena),
+            Err(NetflowError::UnallowedVersion(11))
+        ));
+    }
+}
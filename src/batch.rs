@@ -0,0 +1,289 @@
+//! # Batch
+//!
+//! Offline processing of pcap captures.  Every exporter address gets its own
+//! [`NetflowParser`], so templates from different exporters never collide, and progress is
+//! reported once per interval of capture time.
+
+use crate::metrics::{MetricLabels, MetricsSnapshot};
+use crate::pcap::{udp_datagram, PcapReader};
+use crate::{NetflowError, NetflowPacket, NetflowParser, NetflowParserBuilder};
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+/// Counts for a slice of capture time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchProgress {
+    /// Capture time the slice starts at
+    pub start: Duration,
+    /// Capture time the slice ends at, exclusive
+    pub end: Duration,
+    /// Netflow packets parsed
+    pub packets: u64,
+    /// Flow records in the parsed packets
+    pub flows: u64,
+    /// Packets that failed to parse
+    pub errors: u64,
+    /// Captured frames that weren't UDP
+    pub skipped_frames: u64,
+}
+
+impl BatchProgress {
+    fn add(&mut self, other: &BatchProgress) {
+        if self.end.is_zero() {
+            self.start = other.start;
+        }
+        self.end = other.end;
+        self.packets += other.packets;
+        self.flows += other.flows;
+        self.errors += other.errors;
+        self.skipped_frames += other.skipped_frames;
+    }
+}
+
+/// Result of processing a whole capture.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchSummary {
+    /// Counts over every processed slice
+    pub progress: BatchProgress,
+    /// Processing was stopped by the progress callback
+    pub cancelled: bool,
+}
+
+/// Parses pcap captures with a parser per exporter address.
+#[derive(Debug)]
+pub struct BatchProcessor {
+    builder: NetflowParserBuilder,
+    interval: Duration,
+    parsers: HashMap<IpAddr, NetflowParser>,
+}
+
+impl BatchProcessor {
+    /// Parsers for new exporters are built from `builder`.  Progress is reported every
+    /// `interval` of capture time.
+    pub fn new(
+        builder: NetflowParserBuilder,
+        interval: Duration,
+    ) -> Result<Self, NetflowError> {
+        if interval.is_zero() {
+            return Err(NetflowError::ConfigError(
+                "batch progress interval must be greater than zero".to_string(),
+            ));
+        }
+        // Surface configuration errors now rather than on the first exporter.
+        builder.clone().build()?;
+        Ok(Self {
+            builder,
+            interval,
+            parsers: HashMap::new(),
+        })
+    }
+
+    /// Parsers by exporter address, holding the templates learned so far.
+    pub fn parsers(&self) -> &HashMap<IpAddr, NetflowParser> {
+        &self.parsers
+    }
+
+    /// Metrics of every exporter's parser, labelled with `exporter="<address>"`.  Render them
+    /// with [`render_prometheus`](crate::metrics::render_prometheus).
+    pub fn metrics_snapshots(&self) -> Vec<(MetricLabels, MetricsSnapshot)> {
+        let mut snapshots: Vec<_> = self
+            .parsers
+            .iter()
+            .map(|(exporter, parser)| {
+                (
+                    vec![("exporter".to_string(), exporter.to_string())],
+                    parser.metrics_snapshot(),
+                )
+            })
+            .collect();
+        snapshots.sort_by(|a, b| a.0.cmp(&b.0));
+        snapshots
+    }
+
+    /// Parses every UDP payload in the capture, passing each netflow packet and the address
+    /// it was sent from to `on_packet`.  `on_progress` is called with the counts of each
+    /// interval of capture time, including the last partial one; returning
+    /// `ControlFlow::Break` stops processing.
+    pub fn process_pcap<R: Read>(
+        &mut self,
+        capture: R,
+        mut on_packet: impl FnMut(SocketAddr, NetflowPacket),
+        mut on_progress: impl FnMut(&BatchProgress) -> ControlFlow<()>,
+    ) -> io::Result<BatchSummary> {
+        let mut reader = PcapReader::new(capture)?;
+        let mut summary = BatchSummary::default();
+        let mut slice: Option<BatchProgress> = None;
+
+        while let Some(frame) = reader.next_packet()? {
+            let current = slice.get_or_insert_with(|| BatchProgress {
+                start: frame.timestamp,
+                end: frame.timestamp.saturating_add(self.interval),
+                ..Default::default()
+            });
+            if frame.timestamp >= current.end {
+                let finished = *current;
+                summary.progress.add(&finished);
+                if on_progress(&finished).is_break() {
+                    summary.cancelled = true;
+                    break;
+                }
+                // Skip over intervals without any traffic.
+                let elapsed = (frame.timestamp - finished.start).as_nanos();
+                let skipped = (elapsed / self.interval.as_nanos()) as u32;
+                let start = finished.start + self.interval * skipped;
+                *current = BatchProgress {
+                    start,
+                    end: start.saturating_add(self.interval),
+                    ..Default::default()
+                };
+            }
+
+            let Some(datagram) = udp_datagram(frame.link_type, &frame.data) else {
+                current.skipped_frames += 1;
+                continue;
+            };
+            let parser = match self.parsers.get_mut(&datagram.source.ip()) {
+                Some(parser) => parser,
+                None => {
+                    let parser = self
+                        .builder
+                        .clone()
+                        .build()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                    self.parsers.entry(datagram.source.ip()).or_insert(parser)
+                }
+            };
+            for packet in parser.parse_bytes_received_at(datagram.payload, frame.timestamp) {
+                match &packet {
+                    NetflowPacket::Error(_) => current.errors += 1,
+                    packet => {
+                        current.packets += 1;
+                        current.flows += flow_count(packet);
+                    }
+                }
+                on_packet(datagram.source, packet);
+            }
+        }
+
+        if let (false, Some(last)) = (summary.cancelled, slice) {
+            summary.progress.add(&last);
+            summary.cancelled = on_progress(&last).is_break();
+        }
+        Ok(summary)
+    }
+}
+
+fn flow_count(packet: &NetflowPacket) -> u64 {
+    let count = match packet {
+        NetflowPacket::V5(v5) => v5.flowsets.len(),
+        NetflowPacket::V7(v7) => v7.flowsets.len(),
+        NetflowPacket::V9(v9) => v9
+            .flowsets
+            .iter()
+            .filter_map(|flowset| flowset.body.data.as_ref())
+            .map(|data| data.data_fields.len())
+            .sum(),
+        NetflowPacket::IPFix(ipfix) => ipfix
+            .flowsets
+            .iter()
+            .filter_map(|flowset| flowset.body.data.as_ref())
+            .map(|data| data.data_fields.len())
+            .sum(),
+        NetflowPacket::Error(_) => 0,
+    };
+    count as u64
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use super::{BatchProcessor, BatchProgress};
+    use crate::pcap::pcap_tests::capture;
+    use crate::NetflowParser;
+
+    use std::ops::ControlFlow;
+    use std::time::Duration;
+
+    const V5_PACKET: [u8; 72] = [
+        0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4,
+        5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+        4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+    ];
+
+    #[test]
+    fn it_reports_progress_per_interval() {
+        let pcap = capture(&[
+            (Duration::from_secs(100), &V5_PACKET),
+            (Duration::from_secs(105), &[0, 5, 0, 1]),
+            (Duration::from_secs(135), &V5_PACKET),
+        ]);
+        let mut processor =
+            BatchProcessor::new(NetflowParser::builder(), Duration::from_secs(10)).unwrap();
+        let mut packets = 0;
+        let mut slices = vec![];
+        let summary = processor
+            .process_pcap(
+                pcap.as_slice(),
+                |_, _| packets += 1,
+                |progress| {
+                    slices.push(*progress);
+                    ControlFlow::Continue(())
+                },
+            )
+            .unwrap();
+
+        assert_eq!(packets, 3);
+        assert_eq!(
+            slices,
+            vec![
+                BatchProgress {
+                    start: Duration::from_secs(100),
+                    end: Duration::from_secs(110),
+                    packets: 1,
+                    flows: 1,
+                    errors: 1,
+                    skipped_frames: 0,
+                },
+                BatchProgress {
+                    start: Duration::from_secs(130),
+                    end: Duration::from_secs(140),
+                    packets: 1,
+                    flows: 1,
+                    errors: 0,
+                    skipped_frames: 0,
+                },
+            ]
+        );
+        assert_eq!(summary.progress.start, Duration::from_secs(100));
+        assert_eq!(summary.progress.end, Duration::from_secs(140));
+        assert_eq!(summary.progress.packets, 2);
+        assert_eq!(summary.progress.errors, 1);
+        assert!(!summary.cancelled);
+        assert_eq!(processor.parsers().len(), 1);
+
+        let metrics = processor.metrics_snapshots();
+        assert_eq!(metrics[0].0, vec![("exporter".into(), "10.0.0.1".into())]);
+        assert_eq!(metrics[0].1.parser.packets_v5, 2);
+        assert_eq!(metrics[0].1.parser.parse_errors, 1);
+    }
+
+    #[test]
+    fn it_stops_when_cancelled() {
+        let pcap = capture(&[
+            (Duration::from_secs(0), &V5_PACKET),
+            (Duration::from_secs(20), &V5_PACKET),
+            (Duration::from_secs(40), &V5_PACKET),
+        ]);
+        let mut processor =
+            BatchProcessor::new(NetflowParser::builder(), Duration::from_secs(10)).unwrap();
+        let summary = processor
+            .process_pcap(pcap.as_slice(), |_, _| (), |_| ControlFlow::Break(()))
+            .unwrap();
+
+        assert!(summary.cancelled);
+        assert_eq!(summary.progress.packets, 1);
+    }
+}
@@ -0,0 +1,191 @@
+//! # Batcher
+//!
+//! Groups parsed records into batches for sinks that write in bulk, such as files, message
+//! queues or databases.  A batch is handed to the flush callback once it holds `max_records`
+//! records or, when a `max_age` is set, once its first record has waited that long.
+//!
+//! ```rust
+//! use netflow_parser::batcher::Batcher;
+//! use netflow_parser::NetflowParser;
+//! use std::time::Duration;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,];
+//! let mut parser = NetflowParser::default();
+//! let mut batches = vec![];
+//! let mut batcher = Batcher::new(2, Some(Duration::from_secs(5)), |batch| batches.push(batch));
+//! for _ in 0..3 {
+//!     batcher.extend(parser.iter_flow_records(&v5_packet));
+//! }
+//! drop(batcher);
+//! assert_eq!(batches.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 1]);
+//! ```
+
+use crate::clock::Clock;
+#[cfg(feature = "std")]
+use crate::clock::SystemClock;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem;
+use core::time::Duration;
+
+/// Collects records and passes them to `on_flush` in size or time bounded batches.  Records
+/// still waiting when the batcher is dropped are flushed.
+pub struct Batcher<T, F: FnMut(Vec<T>)> {
+    max_records: usize,
+    max_age: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    records: Vec<T>,
+    /// Clock time the first record of the current batch was added
+    started: Duration,
+    on_flush: F,
+}
+
+impl<T, F: FnMut(Vec<T>)> Batcher<T, F> {
+    /// Flushes batches of `max_records` records, or earlier once the oldest record is
+    /// `max_age` old.  A `max_records` of 0 is treated as 1.
+    #[cfg(feature = "std")]
+    pub fn new(max_records: usize, max_age: Option<Duration>, on_flush: F) -> Self {
+        Self::with_clock(
+            max_records,
+            max_age,
+            Arc::new(SystemClock::default()),
+            on_flush,
+        )
+    }
+
+    /// Measures the age of batches using time from `clock`.
+    pub fn with_clock(
+        max_records: usize,
+        max_age: Option<Duration>,
+        clock: Arc<dyn Clock>,
+        on_flush: F,
+    ) -> Self {
+        let max_records = max_records.max(1);
+        Self {
+            max_records,
+            max_age,
+            clock,
+            records: Vec::with_capacity(max_records),
+            started: Duration::ZERO,
+            on_flush,
+        }
+    }
+
+    /// Adds a record, flushing the batch if it is full or too old.
+    pub fn push(&mut self, record: T) {
+        if self.records.is_empty() {
+            self.started = self.clock.now();
+        }
+        self.records.push(record);
+        if self.records.len() >= self.max_records || self.is_expired() {
+            self.flush();
+        }
+    }
+
+    /// Flushes the batch if it is older than `max_age`, returning whether it did.  Call it
+    /// periodically so a quiet stream doesn't hold records back indefinitely.
+    pub fn poll(&mut self) -> bool {
+        if !self.records.is_empty() && self.is_expired() {
+            self.flush();
+            return true;
+        }
+        false
+    }
+
+    /// Passes the records collected so far to the flush callback, if there are any.
+    pub fn flush(&mut self) {
+        if self.records.is_empty() {
+            return;
+        }
+        let batch = mem::replace(&mut self.records, Vec::with_capacity(self.max_records));
+        (self.on_flush)(batch);
+    }
+
+    /// Records waiting to be flushed.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    fn is_expired(&self) -> bool {
+        self.max_age
+            .is_some_and(|max_age| self.clock.now().saturating_sub(self.started) >= max_age)
+    }
+}
+
+impl<T, F: FnMut(Vec<T>)> Extend<T> for Batcher<T, F> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, records: I) {
+        for record in records {
+            self.push(record);
+        }
+    }
+}
+
+impl<T, F: FnMut(Vec<T>)> Drop for Batcher<T, F> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl<T, F: FnMut(Vec<T>)> fmt::Debug for Batcher<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Batcher")
+            .field("max_records", &self.max_records)
+            .field("max_age", &self.max_age)
+            .field("clock", &self.clock)
+            .field("pending", &self.records.len())
+            .field("started", &self.started)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod batcher_tests {
+    use super::Batcher;
+    use crate::clock::ManualClock;
+
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn it_flushes_full_batches() {
+        let mut batches = vec![];
+        let mut batcher = Batcher::new(3, None, |batch| batches.push(batch));
+        batcher.extend(1..=7);
+        assert_eq!(batcher.len(), 1);
+        drop(batcher);
+        assert_eq!(batches, vec![vec![1, 2, 3], vec![4, 5, 6], vec![7]]);
+    }
+
+    #[test]
+    fn it_flushes_batches_by_age() {
+        let clock = Arc::new(ManualClock::default());
+        let mut batches = vec![];
+        let mut batcher =
+            Batcher::with_clock(10, Some(Duration::from_secs(5)), clock.clone(), |batch| {
+                batches.push(batch)
+            });
+        batcher.push(1);
+        clock.set(Duration::from_secs(4));
+        assert!(!batcher.poll());
+        batcher.push(2);
+
+        clock.set(Duration::from_secs(5));
+        assert!(batcher.poll());
+        assert!(batcher.is_empty());
+
+        // The age of a batch counts from its first record
+        batcher.push(3);
+        clock.set(Duration::from_secs(9));
+        batcher.push(4);
+        clock.set(Duration::from_secs(10));
+        batcher.push(5);
+        drop(batcher);
+        assert_eq!(batches, vec![vec![1, 2], vec![3, 4, 5]]);
+    }
+}
@@ -0,0 +1,272 @@
+//! Command line tool for troubleshooting exporters.
+//!
+//! ```text
+//! netflow_parser-cli decode <pcap> [--format json|csv]   Decodes the netflow packets of a capture
+//! netflow_parser-cli listen <port>                       Prints flow records received on a UDP port
+//! netflow_parser-cli templates show <pcap>               Prints the templates exporters announced
+//! ```
+//!
+//! Output is written to stdout, one JSON object per line unless CSV is asked for.  Requires
+//! the `cli` feature.
+
+use netflow_parser::csv::Column;
+use netflow_parser::netflow_common::NetflowCommonFlowSet;
+use netflow_parser::pcap::PcapNetflowReader;
+use netflow_parser::pipeline::{Pipeline, PipelineRecord};
+use netflow_parser::{NetflowPacket, NetflowParser};
+
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage:
+    netflow_parser-cli decode <pcap> [--format json|csv]
+    netflow_parser-cli listen <port>
+    netflow_parser-cli templates show <pcap>";
+
+const CSV_HEADER: &str = "exporter,version,src_addr,dst_addr,src_port,dst_port,protocol,\
+src_as,dst_as,first_seen,last_seen,input_interface,output_interface,src_mac,dst_mac";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Csv,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Decode { pcap: String, format: Format },
+    Listen { port: u16 },
+    TemplatesShow { pcap: String },
+}
+
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["decode", pcap] => Ok(Command::Decode {
+            pcap: pcap.to_string(),
+            format: Format::Json,
+        }),
+        ["decode", pcap, "--format", format] => {
+            let format = match *format {
+                "json" => Format::Json,
+                "csv" => Format::Csv,
+                _ => return Err(format!("unknown format {format}")),
+            };
+            Ok(Command::Decode {
+                pcap: pcap.to_string(),
+                format,
+            })
+        }
+        ["listen", port] => port
+            .parse()
+            .map(|port| Command::Listen { port })
+            .map_err(|_| format!("invalid port {port}")),
+        ["templates", "show", pcap] => Ok(Command::TemplatesShow {
+            pcap: pcap.to_string(),
+        }),
+        _ => Err("invalid arguments".to_string()),
+    }
+}
+
+/// Parses every netflow packet in the capture at `path`, each exporter with its own parser.
+/// Stops at the first error `on_packet` returns.
+fn for_each_packet(
+    path: &str,
+    mut on_packet: impl FnMut(SocketAddr, NetflowPacket) -> io::Result<()>,
+) -> io::Result<()> {
+    for packet in PcapNetflowReader::open(path)? {
+        let packet = packet?;
+        on_packet(packet.exporter, packet.packet)?;
+    }
+    Ok(())
+}
+
+/// Columns after the exporter and version, matching `CSV_HEADER`.
+const CSV_COLUMNS: [Column; 13] = [
+    Column::SrcAddr,
+    Column::DstAddr,
+    Column::SrcPort,
+    Column::DstPort,
+    Column::Protocol,
+    Column::SrcAs,
+    Column::DstAs,
+    Column::FirstSeen,
+    Column::LastSeen,
+    Column::InputInterface,
+    Column::OutputInterface,
+    Column::SrcMac,
+    Column::DstMac,
+];
+
+fn csv_row(exporter: SocketAddr, version: u16, flowset: &NetflowCommonFlowSet) -> String {
+    [exporter.to_string(), version.to_string()]
+        .into_iter()
+        .chain(CSV_COLUMNS.iter().map(|column| column.value(flowset)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn decode(pcap: &str, format: Format) -> io::Result<()> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    if format == Format::Csv {
+        writeln!(out, "{CSV_HEADER}")?;
+    }
+    for_each_packet(pcap, |exporter, packet| match format {
+        Format::Json => writeln!(out, "{}", json!({ "exporter": exporter, "packet": packet })),
+        Format::Csv => match packet.as_netflow_common() {
+            Ok(common) => common.flowsets.iter().try_for_each(|flowset| {
+                writeln!(out, "{}", csv_row(exporter, common.version, flowset))
+            }),
+            Err(_) => {
+                eprintln!("{exporter}: {packet:?}");
+                Ok(())
+            }
+        },
+    })?;
+    out.flush()
+}
+
+fn listen(port: u16) -> io::Result<()> {
+    let mut socket = UdpSocket::bind(("0.0.0.0", port))?;
+    let mut pipeline = Pipeline::builder()
+        .with_parser(NetflowParser::builder().with_scoped_templates(true))
+        .with_sink(|record: &PipelineRecord| {
+            writeln!(
+                io::stdout().lock(),
+                "{}",
+                json!({ "exporter": record.exporter, "record": record.record })
+            )
+        })
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let result = pipeline.run(&mut socket);
+    eprintln!("{:?}", pipeline.stats());
+    result
+}
+
+fn templates_show(pcap: &str) -> io::Result<()> {
+    // Latest definition of each template, by exporter, version, scope and template id
+    let mut templates: BTreeMap<(IpAddr, u16, u32, u16), Value> = BTreeMap::new();
+    for_each_packet(pcap, |exporter, packet| {
+        let scope = packet.source_id().unwrap_or_default();
+        let mut add = |version: u16, template_id: u16, options: bool, template: Value| {
+            templates.insert(
+                (exporter.ip(), version, scope, template_id),
+                json!({
+                    "exporter": exporter.ip(),
+                    "version": version,
+                    "scope": scope,
+                    "template_id": template_id,
+                    "options": options,
+                    "template": template,
+                }),
+            );
+        };
+        match &packet {
+            NetflowPacket::V9(v9) => {
+                for body in v9.flowsets.iter().map(|flowset| &flowset.body) {
+                    for template in body.templates.iter().flatten() {
+                        add(9, template.template_id, false, json!(template));
+                    }
+                    for template in body.options_templates.iter().flatten() {
+                        add(9, template.template_id, true, json!(template));
+                    }
+                }
+            }
+            NetflowPacket::IPFix(ipfix) => {
+                for body in ipfix.flowsets.iter().map(|flowset| &flowset.body) {
+                    if let Some(template) = &body.templates {
+                        add(10, template.template_id, false, json!(template));
+                    }
+                    if let Some(template) = &body.options_templates {
+                        add(10, template.template_id, true, json!(template));
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    })?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    for template in templates.values() {
+        writeln!(out, "{template}")?;
+    }
+    out.flush()
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match parse_args(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    let result = match command {
+        Command::Decode { pcap, format } => decode(&pcap, format),
+        Command::Listen { port } => listen(port),
+        Command::TemplatesShow { pcap } => templates_show(&pcap),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::{csv_row, parse_args, Command, Format, CSV_HEADER};
+    use netflow_parser::NetflowParser;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn it_parses_commands() {
+        assert_eq!(
+            parse_args(&args(&["decode", "capture.pcap", "--format", "csv"])),
+            Ok(Command::Decode {
+                pcap: "capture.pcap".to_string(),
+                format: Format::Csv,
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["listen", "2055"])),
+            Ok(Command::Listen { port: 2055 })
+        );
+        assert_eq!(
+            parse_args(&args(&["templates", "show", "capture.pcap"])),
+            Ok(Command::TemplatesShow {
+                pcap: "capture.pcap".to_string(),
+            })
+        );
+        assert!(parse_args(&args(&["decode", "capture.pcap", "--format", "xml"])).is_err());
+        assert!(parse_args(&args(&["listen", "http"])).is_err());
+        assert!(parse_args(&args(&[])).is_err());
+    }
+
+    #[test]
+    fn it_writes_csv_rows() {
+        let v5_packet = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let flowsets =
+            NetflowParser::default().parse_bytes_as_netflow_common_flowsets(&v5_packet);
+        let row = csv_row("192.0.2.1:2055".parse().unwrap(), 5, &flowsets[0]);
+        assert_eq!(row.split(',').count(), CSV_HEADER.split(',').count());
+        assert!(row.starts_with("192.0.2.1:2055,5,0.1.2.3,4.5.6.7,"));
+        assert!(row.ends_with(",,"));
+    }
+}
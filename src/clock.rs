@@ -0,0 +1,167 @@
+//! # Clock
+//!
+//! Monotonic time source for the template rate limit and missing template tracking.  With the
+//! `std` feature [`SystemClock`] is used by default; without it a [`Clock`] has to be given to
+//! `NetflowParserBuilder::with_clock`.  [`ExportTimeClock`] follows the export time of the
+//! packets parsed instead, see `NetflowParserBuilder::with_export_time_clock`.
+
+use crate::HashMap;
+
+use alloc::sync::Arc;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+/// A monotonic clock.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Time elapsed since an arbitrary fixed point.  Must never decrease.
+    fn now(&self) -> Duration;
+}
+
+/// [`Clock`] backed by `std::time::Instant`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// [`Clock`] following the export time in packet headers rather than local time, so source
+/// ttls and missing template alerts behave the same when a capture is replayed faster than it
+/// was recorded, or when the collector pauses.  Only moves forward, packets exported before
+/// the latest one seen leave it unchanged.  Export times are aligned per source by
+/// [`ExportTimeSources`], so an exporter with a skewed clock doesn't move it.
+#[derive(Debug, Default)]
+pub struct ExportTimeClock {
+    /// Latest export time seen, in milliseconds since the unix epoch
+    millis: AtomicU64,
+}
+
+impl ExportTimeClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the clock to `export_time`, since the unix epoch, if it is later.
+    pub fn advance(&self, export_time: Duration) {
+        let millis = u64::try_from(export_time.as_millis()).unwrap_or(u64::MAX);
+        self.millis.fetch_max(millis, Ordering::Relaxed);
+    }
+
+    /// Moves the clock back to `now`, undoing the advance for a packet that failed to parse.
+    pub(crate) fn reset(&self, now: Duration) {
+        let millis = u64::try_from(now.as_millis()).unwrap_or(u64::MAX);
+        self.millis.store(millis, Ordering::Relaxed);
+    }
+}
+
+/// Aligns the export times of each source to an [`ExportTimeClock`].  The first packet
+/// parsed starts the clock at its export time, the first one from any other source stands
+/// for the clock's current time, and later ones move the clock by as much as the source's
+/// export time moved since, so how far the clocks of exporters are apart doesn't matter.
+/// Sources are keyed by version and V9 `source_id` or IPFix
+/// `observation_domain_id`, and only packets that parsed are counted.
+#[derive(Debug, Default, Clone)]
+pub struct ExportTimeSources {
+    /// Clock time minus export time, and the clock time last reached, in milliseconds
+    offsets: HashMap<(u16, u32), (i128, u64)>,
+}
+
+impl ExportTimeSources {
+    /// Number of sources aligned before the least recently seen one is dropped.
+    pub const MAX_TRACKED: usize = 4096;
+
+    /// Number of sources aligned.
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// The clock time a packet of `source` exported at `export_time` stands for, `now` if
+    /// the source is new, or `export_time` itself if the clock hasn't started yet.
+    pub(crate) fn align(
+        &self,
+        source: (u16, u32),
+        export_time: Duration,
+        now: Duration,
+    ) -> Duration {
+        let Some((offset, _)) = self.offsets.get(&source) else {
+            return if now.is_zero() { export_time } else { now };
+        };
+        let millis = (export_time.as_millis() as i128 + offset).clamp(0, u64::MAX.into());
+        Duration::from_millis(millis as u64)
+    }
+
+    /// Keeps the alignment of `source` after a packet exported at `export_time` parsed,
+    /// standing for clock time `at`.
+    pub(crate) fn record(&mut self, source: (u16, u32), export_time: Duration, at: Duration) {
+        let at = u64::try_from(at.as_millis()).unwrap_or(u64::MAX);
+        if let Some((_, last)) = self.offsets.get_mut(&source) {
+            *last = at;
+            return;
+        }
+        if self.offsets.len() >= Self::MAX_TRACKED {
+            let oldest = self
+                .offsets
+                .iter()
+                .min_by_key(|(_, (_, last))| *last)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.offsets.remove(&oldest);
+            }
+        }
+        let offset = i128::from(at) - export_time.as_millis() as i128;
+        self.offsets.insert(source, (offset, at));
+    }
+}
+
+impl Clock for ExportTimeClock {
+    fn now(&self) -> Duration {
+        Duration::from_millis(self.millis.load(Ordering::Relaxed))
+    }
+}
+
+/// The clock used when none is configured, if there is one.
+pub(crate) fn default_clock() -> Option<Arc<dyn Clock>> {
+    #[cfg(feature = "std")]
+    return Some(Arc::new(SystemClock::default()));
+    #[cfg(not(feature = "std"))]
+    None
+}
+
+/// [`Clock`] moved by hand, for tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct ManualClock(std::sync::Mutex<Duration>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub(crate) fn set(&self, now: Duration) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.0.lock().unwrap()
+    }
+}
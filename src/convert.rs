@@ -0,0 +1,831 @@
+//! # Convert
+//!
+//! Conversion of V5, V7 and V9 packets to IPFix messages, so a collector can normalize legacy
+//! exporters into a single IPFix processing and re-export pipeline.
+//!
+//! V9 field types below 32768 are the IANA information elements of the same number, which
+//! IPFix inherited from V9.  Cisco's field types from 32768 on become Cisco (PEN 9) enterprise
+//! fields of the same number without the enterprise bit.  Options template scopes map to
+//! `exportingProcessId` (system), `ingressInterface`, `lineCardId`, `meteringProcessId`
+//! (NetFlow cache) and `templateId`.
+//!
+//! Values are re-decoded as the IPFix parser would decode them, so a converted record looks
+//! like one parsed from an IPFix exporter.  The message keeps the V9 export time, source id as
+//! observation domain id and sequence number.  V9 sequence numbers count packets while IPFix
+//! ones count data records, so a collector checking IPFix sequence numbers for loss should
+//! convert a stream with a [`V9Converter`], which numbers messages by the records it converted
+//! for each observation domain.
+//!
+//! V5 and V7 records are written with a fixed template, [`legacy_template`], of the standard
+//! information elements for their addresses, interfaces, ports, counters and timestamps.  Each
+//! converted message announces the template before its data set, so it can be parsed on its
+//! own.  Flow start and end times are absolute, converted from the packet's uptime.
+//!
+//! ```rust
+//! use netflow_parser::variable_versions::ipfix::IPFix;
+//! use netflow_parser::{NetflowPacket, NetflowParser};
+//!
+//! let v9_packet = [
+//!     0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0, 2, 0,
+//!     1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
+//! ];
+//! for packet in NetflowParser::default().parse_bytes(&v9_packet) {
+//!     if let NetflowPacket::V9(v9) = packet {
+//!         let ipfix = IPFix::try_from(&v9).expect("convertible");
+//!         let bytes = ipfix.to_be_bytes().expect("exportable");
+//!     }
+//! }
+//! ```
+
+use crate::mediation::set_body_length;
+use crate::static_versions::v5::{self, V5};
+use crate::static_versions::v7::{self, V7};
+use crate::variable_versions::data_number::{field_warnings, FieldDataType, FieldValue};
+use crate::variable_versions::enterprise::EnterpriseFieldRegistry;
+use crate::variable_versions::ipfix::{self, IPFix};
+use crate::variable_versions::ipfix_lookup::IPFixField;
+use crate::variable_versions::v9::{self, V9};
+use crate::variable_versions::v9_lookup::ScopeFieldType;
+use crate::{HashMap, NetflowError};
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// Private enterprise number of the V9 field types from 32768 on
+pub const CISCO_ENTERPRISE_NUMBER: u32 = 9;
+
+/// Id of the fixed template of converted V5 and V7 records
+pub const LEGACY_TEMPLATE_ID: u16 = 256;
+
+const ENTERPRISE_BIT: u16 = 0x8000;
+const TEMPLATE_SET_ID: u16 = 2;
+const OPTIONS_TEMPLATE_SET_ID: u16 = 3;
+const HEADER_LENGTH: usize = 16;
+const SET_HEADER_LENGTH: usize = 4;
+
+type Record = BTreeMap<usize, (IPFixField, FieldValue)>;
+
+/// The IPFix field of V9 field type `field_type_number`.
+fn ipfix_field(field_type_number: u16, field_length: u16) -> ipfix::TemplateField {
+    if field_type_number & ENTERPRISE_BIT == 0 {
+        ipfix::TemplateField {
+            field_type_number,
+            field_type: IPFixField::from(field_type_number),
+            field_length,
+            enterprise_number: None,
+        }
+    } else {
+        ipfix::TemplateField {
+            field_type_number: field_type_number & !ENTERPRISE_BIT,
+            field_type: IPFixField::Enterprise,
+            field_length,
+            enterprise_number: Some(CISCO_ENTERPRISE_NUMBER),
+        }
+    }
+}
+
+/// The IPFix field of a V9 options template scope.
+fn scope_field(
+    field: &v9::OptionsTemplateScopeField,
+) -> Result<ipfix::TemplateField, NetflowError> {
+    let information_element = match field.field_type {
+        ScopeFieldType::System => IPFixField::ExportingProcessId,
+        ScopeFieldType::Interface => IPFixField::IngressInterface,
+        ScopeFieldType::LineCard => IPFixField::LineCardId,
+        ScopeFieldType::NetflowCache => IPFixField::MeteringProcessId,
+        ScopeFieldType::Template => IPFixField::TemplateId,
+        ScopeFieldType::Unknown => {
+            return Err(NetflowError::InvalidFieldValue(format!(
+                "V9 scope field type {} has no IPFix equivalent",
+                field.field_type_number
+            )))
+        }
+    };
+    Ok(ipfix_field(information_element as u16, field.field_length))
+}
+
+fn convert_template(template: &v9::Template) -> ipfix::Template {
+    let fields: Vec<ipfix::TemplateField> = template
+        .fields
+        .iter()
+        .map(|field| ipfix_field(field.field_type_number, field.field_length))
+        .collect();
+    ipfix::Template {
+        template_id: template.template_id,
+        field_count: fields.len() as u16,
+        fields,
+    }
+}
+
+fn convert_options_template(
+    template: &v9::OptionsTemplate,
+) -> Result<ipfix::OptionsTemplate, NetflowError> {
+    let mut fields = template
+        .scope_fields
+        .iter()
+        .map(scope_field)
+        .collect::<Result<Vec<_>, _>>()?;
+    fields.extend(
+        template
+            .option_fields
+            .iter()
+            .map(|field| ipfix_field(field.field_type_number, field.field_length)),
+    );
+    Ok(ipfix::OptionsTemplate::new(
+        template.template_id,
+        template.scope_fields.len() as u16,
+        fields,
+    ))
+}
+
+/// Wire bytes of a parsed V9 value, `field.field_length` long.  Durations are written in the
+/// unit of their field, which `FieldValue::to_be_bytes` can't know.
+fn v9_value_bytes(
+    field: &v9::TemplateField,
+    value: &FieldValue,
+) -> Result<Vec<u8>, NetflowError> {
+    let length = usize::from(field.field_length);
+    let mut bytes = match (value, FieldDataType::from(field.field_type)) {
+        (FieldValue::Duration(duration), data_type) => {
+            let count = match data_type {
+                FieldDataType::DurationMillis => duration.as_millis(),
+                FieldDataType::DurationMicros => duration.as_micros(),
+                FieldDataType::DurationNanos => duration.as_nanos(),
+                _ => u128::from(duration.as_secs()),
+            };
+            let count = count.to_be_bytes();
+            count[count.len().saturating_sub(length)..].to_vec()
+        }
+        (value, _) => value.to_be_bytes()?,
+    };
+    if bytes.len() > length {
+        return Err(NetflowError::InvalidFieldValue(format!(
+            "{value:?} does not fit in {length} bytes of {:?}",
+            field.field_type
+        )));
+    }
+    // Strings may have had their NUL padding trimmed
+    bytes.resize(length, 0);
+    Ok(bytes)
+}
+
+/// Decodes `bytes` as the IPFix parser decodes `field`.
+fn decode(field: &ipfix::TemplateField, bytes: &[u8]) -> Result<FieldValue, NetflowError> {
+    let registry = EnterpriseFieldRegistry::default();
+    ipfix::parse_field(bytes, field, &registry, Default::default())
+        .map(|(_, value)| value)
+        .map_err(|_| {
+            NetflowError::InvalidFieldValue(format!("{bytes:?} is not a valid {field:?}"))
+        })
+}
+
+fn convert_records(
+    v9_template: &v9::Template,
+    template: &ipfix::Template,
+    records: &[BTreeMap<usize, v9::V9FieldPair>],
+) -> Result<Vec<Record>, NetflowError> {
+    records
+        .iter()
+        .map(|record| {
+            let fields = v9_template.fields.iter().zip(template.fields.iter());
+            fields
+                .enumerate()
+                .map(|(index, (v9_field, field))| {
+                    let (_, value) = record.get(&index).ok_or_else(|| {
+                        NetflowError::InvalidFieldValue(format!(
+                            "field {index} of template {} wasn't parsed",
+                            v9_template.template_id
+                        ))
+                    })?;
+                    let value = decode(field, &v9_value_bytes(v9_field, value)?)?;
+                    Ok((index, (field.field_type, value)))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn convert_options_record(
+    template: &ipfix::OptionsTemplate,
+    options_data: &v9::OptionsData,
+) -> Result<Record, NetflowError> {
+    let scope_values = options_data.scope_fields.iter().map(|scope| {
+        [
+            &scope.system,
+            &scope.interface,
+            &scope.line_card,
+            &scope.net_flow_cache,
+            &scope.template,
+        ]
+        .into_iter()
+        .flatten()
+        .next()
+    });
+    let option_values = options_data
+        .options_fields
+        .iter()
+        .map(|field| Some(&field.field_value));
+    template
+        .fields
+        .iter()
+        .zip(scope_values.chain(option_values))
+        .enumerate()
+        .map(|(index, (field, bytes))| {
+            let bytes = bytes.ok_or_else(|| {
+                NetflowError::InvalidFieldValue(format!(
+                    "scope field {index} of options template {} wasn't parsed",
+                    options_data.template_id
+                ))
+            })?;
+            Ok((index, (field.field_type, decode(field, bytes)?)))
+        })
+        .collect()
+}
+
+/// Converts every template and data flowset.  Data flowsets are converted with the template
+/// that decoded them, or a template of the same packet.  Flowsets that weren't parsed are left
+/// out.
+impl TryFrom<&V9> for IPFix {
+    type Error = NetflowError;
+
+    fn try_from(v9: &V9) -> Result<Self, Self::Error> {
+        let missing = |template_id| NetflowError::MissingTemplate {
+            version: 9,
+            template_id,
+        };
+        let mut v9_templates = BTreeMap::new();
+        let mut v9_options_templates = BTreeMap::new();
+        for body in v9.flowsets.iter().map(|flowset| &flowset.body) {
+            for template in body.templates.iter().flatten() {
+                v9_templates.insert(template.template_id, template.clone());
+            }
+            for template in body.options_templates.iter().flatten() {
+                v9_options_templates.insert(template.template_id, template.clone());
+            }
+        }
+
+        let mut bodies = Vec::new();
+        for flowset in v9.flowsets.iter() {
+            let body = &flowset.body;
+            for template in body.templates.iter().flatten() {
+                let body = ipfix::FlowSetBody {
+                    templates: Some(Arc::new(convert_template(template))),
+                    ..Default::default()
+                };
+                bodies.push((TEMPLATE_SET_ID, body));
+            }
+            for template in body.options_templates.iter().flatten() {
+                let body = ipfix::FlowSetBody {
+                    options_templates: Some(Arc::new(convert_options_template(template)?)),
+                    ..Default::default()
+                };
+                bodies.push((OPTIONS_TEMPLATE_SET_ID, body));
+            }
+            if let Some(data) = &body.data {
+                let v9_template = data
+                    .template
+                    .as_ref()
+                    .or_else(|| v9_templates.get(&data.template_id))
+                    .ok_or_else(|| missing(data.template_id))?;
+                let template = Arc::new(convert_template(v9_template));
+                let data_fields = convert_records(v9_template, &template, &data.data_fields)?;
+                let body = ipfix::FlowSetBody {
+                    data: Some(ipfix::Data {
+                        template_id: data.template_id,
+                        warnings: field_warnings(&data_fields),
+                        data_fields,
+                        template: Some(template),
+                    }),
+                    ..Default::default()
+                };
+                bodies.push((data.template_id, body));
+            }
+            if let Some(options_data) = &body.options_data {
+                let v9_template = options_data
+                    .template
+                    .as_ref()
+                    .or_else(|| v9_options_templates.get(&options_data.template_id))
+                    .ok_or_else(|| missing(options_data.template_id))?;
+                let template = Arc::new(convert_options_template(v9_template)?);
+                let data_fields = vec![convert_options_record(&template, options_data)?];
+                let body = ipfix::FlowSetBody {
+                    options_data: Some(ipfix::OptionsData {
+                        template_id: options_data.template_id,
+                        scope_field_count: template.scope_field_count,
+                        warnings: field_warnings(&data_fields),
+                        data_fields,
+                        template: Some(template),
+                    }),
+                    ..Default::default()
+                };
+                bodies.push((options_data.template_id, body));
+            }
+        }
+
+        message(
+            ipfix::Header {
+                version: 10,
+                length: 0,
+                export_time: v9.header.unix_secs,
+                sequence_number: v9.header.sequence_number,
+                observation_domain_id: v9.header.source_id,
+            },
+            bodies,
+            v9.received_at,
+        )
+    }
+}
+
+/// Converts the V9 packets of a stream to IPFix with sequence numbers as RFC 7011 defines
+/// them, the number of data records converted before the message from its observation domain,
+/// modulo 2^32.  Options data records are counted as data records.
+#[derive(Debug, Default, Clone)]
+pub struct V9Converter {
+    records: HashMap<u32, u32>,
+}
+
+impl V9Converter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Converts `v9` as `IPFix::try_from` does, numbered by the records converted before it
+    /// with the same source id.  Packets that fail to convert aren't counted.
+    pub fn convert(&mut self, v9: &V9) -> Result<IPFix, NetflowError> {
+        let mut ipfix = IPFix::try_from(v9)?;
+        let records: usize = ipfix
+            .flowsets
+            .iter()
+            .map(|flowset| {
+                let body = &flowset.body;
+                body.data.as_ref().map_or(0, |data| data.data_fields.len())
+                    + body
+                        .options_data
+                        .as_ref()
+                        .map_or(0, |options_data| options_data.data_fields.len())
+            })
+            .sum();
+        let sequence_number = self.records.entry(v9.header.source_id).or_default();
+        ipfix.header.sequence_number = *sequence_number;
+        *sequence_number = sequence_number.wrapping_add(records as u32);
+        Ok(ipfix)
+    }
+
+    /// Forgets the records converted for every observation domain.
+    pub fn reset(&mut self) {
+        self.records.clear();
+    }
+}
+
+/// Fields of the template of converted V5 and V7 records, in the order of their wire bytes.
+const LEGACY_FIELDS: [(IPFixField, u16); 18] = [
+    (IPFixField::SourceIpv4address, 4),
+    (IPFixField::DestinationIpv4address, 4),
+    (IPFixField::IpNextHopIpv4address, 4),
+    (IPFixField::IngressInterface, 2),
+    (IPFixField::EgressInterface, 2),
+    (IPFixField::PacketDeltaCount, 4),
+    (IPFixField::OctetDeltaCount, 4),
+    (IPFixField::FlowStartMilliseconds, 8),
+    (IPFixField::FlowEndMilliseconds, 8),
+    (IPFixField::SourceTransportPort, 2),
+    (IPFixField::DestinationTransportPort, 2),
+    (IPFixField::TcpControlBits, 1),
+    (IPFixField::ProtocolIdentifier, 1),
+    (IPFixField::IpClassOfService, 1),
+    (IPFixField::BgpSourceAsNumber, 2),
+    (IPFixField::BgpDestinationAsNumber, 2),
+    (IPFixField::SourceIpv4prefixLength, 1),
+    (IPFixField::DestinationIpv4prefixLength, 1),
+];
+
+/// The fixed template of converted V5 and V7 records.
+pub fn legacy_template() -> ipfix::Template {
+    let fields: Vec<ipfix::TemplateField> = LEGACY_FIELDS
+        .iter()
+        .map(|(field, length)| ipfix_field(*field as u16, *length))
+        .collect();
+    ipfix::Template {
+        template_id: LEGACY_TEMPLATE_ID,
+        field_count: fields.len() as u16,
+        fields,
+    }
+}
+
+/// A V5 or V7 record, written as the fields of the legacy template.
+trait LegacyRecord {
+    /// Wire bytes of the record, with `unix_time` converting the `first` and `last` uptimes.
+    fn legacy_bytes(&self, unix_time: impl Fn(u32) -> Duration) -> Vec<u8>;
+}
+
+macro_rules! impl_legacy_record {
+    ($($t:ty),*) => {
+        $(
+            impl LegacyRecord for $t {
+                fn legacy_bytes(&self, unix_time: impl Fn(u32) -> Duration) -> Vec<u8> {
+                    let millis = |uptime| (unix_time(uptime).as_millis() as u64).to_be_bytes();
+                    let mut bytes = Vec::with_capacity(53);
+                    bytes.extend(self.src_addr.octets());
+                    bytes.extend(self.dst_addr.octets());
+                    bytes.extend(self.next_hop.octets());
+                    bytes.extend(self.input.to_be_bytes());
+                    bytes.extend(self.output.to_be_bytes());
+                    bytes.extend(self.d_pkts.to_be_bytes());
+                    bytes.extend(self.d_octets.to_be_bytes());
+                    bytes.extend(millis(self.first));
+                    bytes.extend(millis(self.last));
+                    bytes.extend(self.src_port.to_be_bytes());
+                    bytes.extend(self.dst_port.to_be_bytes());
+                    bytes.extend([self.tcp_flags, self.protocol_number, self.tos]);
+                    bytes.extend(self.src_as.to_be_bytes());
+                    bytes.extend(self.dst_as.to_be_bytes());
+                    bytes.extend([self.src_mask, self.dst_mask]);
+                    bytes
+                }
+            }
+        )*
+    };
+}
+
+impl_legacy_record!(v5::FlowSet, v7::FlowSet);
+
+/// A template set for the legacy template and a data set of `flows`.
+fn legacy_bodies<F: LegacyRecord>(
+    flows: &[F],
+    unix_time: impl Fn(u32) -> Duration + Copy,
+) -> Result<Vec<(u16, ipfix::FlowSetBody)>, NetflowError> {
+    let template = Arc::new(legacy_template());
+    let data_fields = flows
+        .iter()
+        .map(|flow| {
+            let bytes = flow.legacy_bytes(unix_time);
+            let mut offset = 0;
+            template
+                .fields
+                .iter()
+                .enumerate()
+                .map(|(index, field)| {
+                    let end = offset + usize::from(field.field_length);
+                    let value = decode(field, &bytes[offset..end])?;
+                    offset = end;
+                    Ok((index, (field.field_type, value)))
+                })
+                .collect()
+        })
+        .collect::<Result<Vec<Record>, NetflowError>>()?;
+    let template_body = ipfix::FlowSetBody {
+        templates: Some(template.clone()),
+        ..Default::default()
+    };
+    let data_body = ipfix::FlowSetBody {
+        data: Some(ipfix::Data {
+            template_id: LEGACY_TEMPLATE_ID,
+            warnings: field_warnings(&data_fields),
+            data_fields,
+            template: Some(template),
+        }),
+        ..Default::default()
+    };
+    Ok(vec![
+        (TEMPLATE_SET_ID, template_body),
+        (LEGACY_TEMPLATE_ID, data_body),
+    ])
+}
+
+/// Converts the records to a message announcing the legacy template followed by a data set of
+/// every record.  The observation domain id is the engine type and id, as `engine_type << 8 |
+/// engine_id`.
+impl TryFrom<&V5> for IPFix {
+    type Error = NetflowError;
+
+    fn try_from(v5: &V5) -> Result<Self, Self::Error> {
+        let header = &v5.header;
+        message(
+            ipfix::Header {
+                version: 10,
+                length: 0,
+                export_time: header.unix_secs,
+                sequence_number: header.flow_sequence,
+                observation_domain_id: u32::from(header.engine_type) << 8
+                    | u32::from(header.engine_id),
+            },
+            legacy_bodies(&v5.flowsets, |uptime| header.uptime_to_unix_time(uptime))?,
+            v5.received_at,
+        )
+    }
+}
+
+/// Converts the records to a message announcing the legacy template followed by a data set of
+/// every record.  `router_src` has no information element and is left out.
+impl TryFrom<&V7> for IPFix {
+    type Error = NetflowError;
+
+    fn try_from(v7: &V7) -> Result<Self, Self::Error> {
+        let header = &v7.header;
+        message(
+            ipfix::Header {
+                version: 10,
+                length: 0,
+                export_time: header.unix_secs,
+                sequence_number: header.flow_sequence,
+                observation_domain_id: 0,
+            },
+            legacy_bodies(&v7.flowsets, |uptime| header.uptime_to_unix_time(uptime))?,
+            v7.received_at,
+        )
+    }
+}
+
+/// A message of `bodies`, each with the id of its set header, with the lengths of `header` and
+/// the sets computed.
+fn message(
+    mut header: ipfix::Header,
+    bodies: Vec<(u16, ipfix::FlowSetBody)>,
+    received_at: Option<Duration>,
+) -> Result<IPFix, NetflowError> {
+    let mut length = HEADER_LENGTH;
+    let mut flowsets = Vec::with_capacity(bodies.len());
+    for (header_id, body) in bodies {
+        let set_length = SET_HEADER_LENGTH + set_body_length(&body)?;
+        length += set_length;
+        flowsets.push(ipfix::FlowSet {
+            header: ipfix::FlowSetHeader {
+                header_id,
+                length: u16::try_from(set_length).unwrap_or(u16::MAX),
+            },
+            body,
+        });
+    }
+    header.length = u16::try_from(length).map_err(|_| NetflowError::InvalidLength {
+        version: 10,
+        declared: length,
+        available: usize::from(u16::MAX),
+    })?;
+    Ok(IPFix {
+        header,
+        flowsets,
+        received_at,
+        digest: None,
+    })
+}
+
+#[cfg(test)]
+mod convert_tests {
+    use super::{ipfix_field, CISCO_ENTERPRISE_NUMBER};
+    use super::{legacy_template, V9Converter, LEGACY_TEMPLATE_ID};
+    use crate::protocol::ProtocolTypes;
+    use crate::static_versions::v7::{self, V7};
+    use crate::testing::V9_PACKET;
+    use crate::variable_versions::data_number::{DataNumber, FieldValue};
+    use crate::variable_versions::ipfix::IPFix;
+    use crate::variable_versions::ipfix_lookup::IPFixField;
+    use crate::variable_versions::v9::V9;
+    use crate::{NetflowError, NetflowPacket, NetflowParser};
+
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    // One flow, first seen 500ms and last seen 100ms before its export at 1700000000s
+    const V5_PACKET: [u8; 72] = [
+        0, 5, 0, 1, 0, 0, 3, 232, 101, 83, 241, 0, 0, 0, 0, 0, 0, 0, 0, 7, 1, 2, 0, 0, 10, 0,
+        0, 1, 192, 0, 2, 1, 0, 0, 0, 0, 0, 1, 0, 2, 0, 0, 0, 10, 0, 0, 5, 220, 0, 0, 1, 244, 0,
+        0, 3, 132, 4, 210, 1, 187, 0, 0x18, 6, 0, 0, 1, 0, 2, 24, 24, 0, 0,
+    ];
+
+    // An options template for 275 scoped to an interface, and options data using it
+    const V9_OPTIONS_PACKET: [u8; 51] = [
+        0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 1, 0, 22, 1, 19, 0, 4,
+        0, 8, 0, 2, 0, 2, 0, 34, 0, 2, 0, 36, 0, 1, 1, 19, 0, 9, 0, 2, 0, 100, 1,
+    ];
+
+    fn parse_v9(packet: &[u8]) -> V9 {
+        match NetflowParser::default().parse_bytes(packet).pop() {
+            Some(NetflowPacket::V9(v9)) => v9,
+            packet => panic!("expected a V9 packet, got {packet:?}"),
+        }
+    }
+
+    fn reparse(ipfix: &IPFix) -> IPFix {
+        let bytes = ipfix.to_be_bytes().unwrap();
+        match NetflowParser::default().parse_bytes(&bytes).pop() {
+            Some(NetflowPacket::IPFix(ipfix)) => ipfix,
+            packet => panic!("expected an IPFix packet, got {packet:?}"),
+        }
+    }
+
+    #[test]
+    fn it_converts_v9_templates_and_data() {
+        let ipfix = IPFix::try_from(&parse_v9(&V9_PACKET)).unwrap();
+        assert_eq!(ipfix.header.version, 10);
+        assert_eq!(ipfix.header.export_time, 0x00010203);
+        assert_eq!(ipfix.header.sequence_number, 1);
+        assert_eq!(ipfix.header.observation_domain_id, 1);
+
+        let template = ipfix.flowsets[0].body.templates.as_ref().unwrap();
+        assert_eq!(ipfix.flowsets[0].header.header_id, 2);
+        assert_eq!(template.template_id, 258);
+        let field_types: Vec<_> = template.fields.iter().map(|f| f.field_type).collect();
+        assert_eq!(
+            field_types,
+            [IPFixField::OctetDeltaCount, IPFixField::SourceIpv4address]
+        );
+
+        let data = ipfix.flowsets[1].body.data.as_ref().unwrap();
+        assert_eq!(ipfix.flowsets[1].header.header_id, 258);
+        assert_eq!(
+            data.data_fields[0][&0].1,
+            FieldValue::DataNumber(DataNumber::U32(0x09020304))
+        );
+        assert_eq!(
+            data.data_fields[0][&1].1,
+            FieldValue::Ip4Addr(Ipv4Addr::new(9, 9, 9, 8))
+        );
+
+        let reparsed = reparse(&ipfix);
+        assert_eq!(reparsed.header.length, ipfix.header.length);
+        assert_eq!(
+            reparsed.flowsets[1].body.data.as_ref().unwrap().data_fields,
+            data.data_fields
+        );
+    }
+
+    #[test]
+    fn it_converts_v9_options_templates_and_data() {
+        let ipfix = IPFix::try_from(&parse_v9(&V9_OPTIONS_PACKET)).unwrap();
+        let template = ipfix.flowsets[0].body.options_templates.as_ref().unwrap();
+        assert_eq!(ipfix.flowsets[0].header.header_id, 3);
+        assert_eq!(template.scope_field_count, 1);
+        assert_eq!(template.fields[0].field_type, IPFixField::IngressInterface);
+
+        let options_data = ipfix.flowsets[1].body.options_data.as_ref().unwrap();
+        assert_eq!(options_data.data_fields.len(), 1);
+        assert_eq!(
+            options_data.data_fields[0][&0].1,
+            FieldValue::DataNumber(DataNumber::U16(2))
+        );
+
+        let reparsed = reparse(&ipfix);
+        assert_eq!(
+            reparsed.flowsets[1]
+                .body
+                .options_data
+                .as_ref()
+                .unwrap()
+                .data_fields,
+            options_data.data_fields
+        );
+    }
+
+    #[test]
+    fn it_numbers_converted_v9_messages_by_records() {
+        let mut converter = V9Converter::new();
+        let v9 = parse_v9(&V9_PACKET);
+        let sequence_numbers: Vec<u32> = [&v9, &v9, &parse_v9(&V9_OPTIONS_PACKET), &v9]
+            .into_iter()
+            .map(|v9| converter.convert(v9).unwrap().header.sequence_number)
+            .collect();
+        assert_eq!(sequence_numbers, [0, 1, 2, 3]);
+
+        // Each source id is numbered on its own
+        let mut other_source = V9_PACKET;
+        other_source[19] = 2;
+        let ipfix = converter.convert(&parse_v9(&other_source)).unwrap();
+        assert_eq!(ipfix.header.observation_domain_id, 2);
+        assert_eq!(ipfix.header.sequence_number, 0);
+        assert_eq!(converter.convert(&v9).unwrap().header.sequence_number, 4);
+
+        converter.reset();
+        assert_eq!(converter.convert(&v9).unwrap().header.sequence_number, 0);
+    }
+
+    #[test]
+    fn it_maps_v9_vendor_fields_to_cisco_enterprise_fields() {
+        let field = ipfix_field(0x8000 | 12235, 8);
+        assert_eq!(field.field_type, IPFixField::Enterprise);
+        assert_eq!(field.field_type_number, 12235);
+        assert_eq!(field.enterprise_number, Some(CISCO_ENTERPRISE_NUMBER));
+    }
+
+    #[test]
+    fn it_converts_v5_records_with_the_legacy_template() {
+        let v5 = match NetflowParser::default().parse_bytes(&V5_PACKET).pop() {
+            Some(NetflowPacket::V5(v5)) => v5,
+            packet => panic!("expected a V5 packet, got {packet:?}"),
+        };
+        let ipfix = IPFix::try_from(&v5).unwrap();
+        assert_eq!(ipfix.header.export_time, 1_700_000_000);
+        assert_eq!(ipfix.header.sequence_number, 7);
+        assert_eq!(ipfix.header.observation_domain_id, 0x0102);
+        assert_eq!(
+            ipfix.flowsets[0].body.templates.as_deref(),
+            Some(&legacy_template())
+        );
+
+        let data = ipfix.flowsets[1].body.data.as_ref().unwrap();
+        assert_eq!(ipfix.flowsets[1].header.header_id, LEGACY_TEMPLATE_ID);
+        let fields: Vec<_> = data.data_fields[0].values().cloned().collect();
+        let value = |field| fields.iter().find(|(f, _)| *f == field).unwrap().1.clone();
+        assert_eq!(
+            value(IPFixField::SourceIpv4address),
+            FieldValue::Ip4Addr(Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert_eq!(
+            value(IPFixField::OctetDeltaCount),
+            FieldValue::DataNumber(DataNumber::U32(1500))
+        );
+        assert_eq!(
+            value(IPFixField::DestinationTransportPort),
+            FieldValue::DataNumber(DataNumber::U16(443))
+        );
+        assert_eq!(
+            value(IPFixField::FlowStartMilliseconds),
+            FieldValue::DateTimeMillis(Duration::from_millis(1_699_999_999_500))
+        );
+        assert_eq!(
+            value(IPFixField::FlowEndMilliseconds),
+            FieldValue::DateTimeMillis(Duration::from_millis(1_699_999_999_900))
+        );
+
+        let reparsed = reparse(&ipfix);
+        assert_eq!(
+            reparsed.flowsets[1].body.data.as_ref().unwrap().data_fields,
+            data.data_fields
+        );
+    }
+
+    #[test]
+    fn it_converts_v7_records_with_the_legacy_template() {
+        let flow = v7::FlowSet {
+            src_addr: Ipv4Addr::new(192, 168, 1, 1),
+            dst_addr: Ipv4Addr::new(192, 168, 1, 2),
+            next_hop: Ipv4Addr::new(192, 168, 1, 254),
+            input: 1,
+            output: 2,
+            d_pkts: 10,
+            d_octets: 1000,
+            first: 100,
+            last: 200,
+            src_port: 1234,
+            dst_port: 80,
+            flags_fields_valid: 0,
+            tcp_flags: 0,
+            protocol_number: 6,
+            protocol_type: ProtocolTypes::Tcp,
+            tos: 0,
+            src_as: 0,
+            dst_as: 0,
+            src_mask: 24,
+            dst_mask: 24,
+            flags_fields_invalid: 0,
+            router_src: Ipv4Addr::new(192, 168, 1, 253),
+        };
+        let v7 = V7 {
+            header: v7::Header {
+                version: 7,
+                count: 2,
+                sys_up_time: 200,
+                unix_secs: 1_700_000_000,
+                unix_nsecs: 0,
+                flow_sequence: 3,
+                reserved: 0,
+            },
+            flowsets: vec![flow.clone(), flow],
+            received_at: None,
+            digest: None,
+        };
+        let ipfix = IPFix::try_from(&v7).unwrap();
+        assert_eq!(ipfix.header.sequence_number, 3);
+        assert_eq!(ipfix.header.observation_domain_id, 0);
+        let data = ipfix.flowsets[1].body.data.as_ref().unwrap();
+        assert_eq!(data.data_fields.len(), 2);
+        assert_eq!(
+            data.data_fields[1][&1].1,
+            FieldValue::Ip4Addr(Ipv4Addr::new(192, 168, 1, 2))
+        );
+
+        let reparsed = reparse(&ipfix);
+        assert_eq!(reparsed.header.length, ipfix.header.length);
+        assert_eq!(
+            reparsed.flowsets[1].body.data.as_ref().unwrap().data_fields,
+            data.data_fields
+        );
+    }
+
+    #[test]
+    fn it_fails_to_convert_data_without_its_template() {
+        let mut v9 = parse_v9(&V9_PACKET);
+        v9.flowsets.remove(0);
+        v9.flowsets[0].body.data.as_mut().unwrap().template = None;
+        assert_eq!(
+            IPFix::try_from(&v9),
+            Err(NetflowError::MissingTemplate {
+                version: 9,
+                template_id: 258
+            })
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! # CSV
+//!
+//! Writes `NetflowCommonFlowSet`s as CSV, one row per flow with the columns of your choice,
+//! for quick offline analysis of captures in a spreadsheet or with command line tools.
+//! Fields a flow doesn't have are left empty, and values holding a comma, quote or line break
+//! are quoted.  Requires the `csv` feature.
+//!
+//! ```rust
+//! use netflow_parser::csv::{write_csv, Column};
+//! use netflow_parser::NetflowParser;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let flowsets = NetflowParser::default().parse_bytes_as_netflow_common_flowsets(&v5_packet);
+//! let mut out = Vec::new();
+//! write_csv(&flowsets, &mut out, &[Column::SrcAddr, Column::DstAddr, Column::Protocol])
+//!     .expect("written");
+//! assert_eq!(String::from_utf8(out).unwrap(), "src_addr,dst_addr,protocol\n0.1.2.3,4.5.6.7,Egp\n");
+//! ```
+
+use crate::netflow_common::NetflowCommonFlowSet;
+
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::io::{self, Write};
+
+/// A column of the CSV, named after the `NetflowCommonFlowSet` field it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Column {
+    SrcAddr,
+    DstAddr,
+    SrcPort,
+    DstPort,
+    SrcAs,
+    DstAs,
+    ProtocolNumber,
+    /// Protocol name, such as `Tcp`
+    Protocol,
+    TcpFlags,
+    Tos,
+    NextHop,
+    FirstSeen,
+    LastSeen,
+    SrcMac,
+    DstMac,
+    /// `export_delay` in milliseconds
+    ExportDelay,
+    Late,
+    InputInterface,
+    OutputInterface,
+    InputInterfaceName,
+    OutputInterfaceName,
+}
+
+impl Column {
+    /// Every column, in the order of the fields of `NetflowCommonFlowSet`.
+    pub const ALL: [Column; 21] = [
+        Column::SrcAddr,
+        Column::DstAddr,
+        Column::SrcPort,
+        Column::DstPort,
+        Column::SrcAs,
+        Column::DstAs,
+        Column::ProtocolNumber,
+        Column::Protocol,
+        Column::TcpFlags,
+        Column::Tos,
+        Column::NextHop,
+        Column::FirstSeen,
+        Column::LastSeen,
+        Column::SrcMac,
+        Column::DstMac,
+        Column::ExportDelay,
+        Column::Late,
+        Column::InputInterface,
+        Column::OutputInterface,
+        Column::InputInterfaceName,
+        Column::OutputInterfaceName,
+    ];
+
+    /// Name of the column in the header row.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::SrcAddr => "src_addr",
+            Column::DstAddr => "dst_addr",
+            Column::SrcPort => "src_port",
+            Column::DstPort => "dst_port",
+            Column::SrcAs => "src_as",
+            Column::DstAs => "dst_as",
+            Column::ProtocolNumber => "protocol_number",
+            Column::Protocol => "protocol",
+            Column::TcpFlags => "tcp_flags",
+            Column::Tos => "tos",
+            Column::NextHop => "next_hop",
+            Column::FirstSeen => "first_seen",
+            Column::LastSeen => "last_seen",
+            Column::SrcMac => "src_mac",
+            Column::DstMac => "dst_mac",
+            Column::ExportDelay => "export_delay",
+            Column::Late => "late",
+            Column::InputInterface => "input_interface",
+            Column::OutputInterface => "output_interface",
+            Column::InputInterfaceName => "input_interface_name",
+            Column::OutputInterfaceName => "output_interface_name",
+        }
+    }
+
+    /// Value of the column for `flowset`, empty if the flow doesn't have it.
+    pub fn value(&self, flowset: &NetflowCommonFlowSet) -> String {
+        fn field(value: Option<impl Display>) -> String {
+            value.map_or_else(String::new, |value| value.to_string())
+        }
+        match self {
+            Column::SrcAddr => field(flowset.src_addr),
+            Column::DstAddr => field(flowset.dst_addr),
+            Column::SrcPort => field(flowset.src_port),
+            Column::DstPort => field(flowset.dst_port),
+            Column::SrcAs => field(flowset.src_as),
+            Column::DstAs => field(flowset.dst_as),
+            Column::ProtocolNumber => field(flowset.protocol_number),
+            Column::Protocol => field(
+                flowset
+                    .protocol_type
+                    .map(|protocol| format!("{protocol:?}")),
+            ),
+            Column::TcpFlags => field(flowset.tcp_flags),
+            Column::Tos => field(flowset.tos),
+            Column::NextHop => field(flowset.next_hop),
+            Column::FirstSeen => field(flowset.first_seen),
+            Column::LastSeen => field(flowset.last_seen),
+            Column::SrcMac => field(flowset.src_mac.as_ref()),
+            Column::DstMac => field(flowset.dst_mac.as_ref()),
+            Column::ExportDelay => field(flowset.export_delay.map(|delay| delay.as_millis())),
+            Column::Late => field(flowset.late),
+            Column::InputInterface => field(flowset.input_interface),
+            Column::OutputInterface => field(flowset.output_interface),
+            Column::InputInterfaceName => field(flowset.input_interface_name.as_ref()),
+            Column::OutputInterfaceName => field(flowset.output_interface_name.as_ref()),
+        }
+    }
+}
+
+/// Quotes `value` if it holds a comma, quote or line break.
+fn escape(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+fn write_row<W: Write>(writer: &mut W, values: impl Iterator<Item = String>) -> io::Result<()> {
+    let row: Vec<String> = values.map(|value| escape(&value).into_owned()).collect();
+    writeln!(writer, "{}", row.join(","))
+}
+
+/// Writes a header row naming `columns`, then a row per flowset.
+pub fn write_csv<W: Write>(
+    flowsets: &[NetflowCommonFlowSet],
+    mut writer: W,
+    columns: &[Column],
+) -> io::Result<()> {
+    write_row(
+        &mut writer,
+        columns.iter().map(|column| column.name().to_string()),
+    )?;
+    write_csv_rows(flowsets, writer, columns)
+}
+
+/// Like [`write_csv`] without the header row, for appending flows to a CSV already started.
+pub fn write_csv_rows<W: Write>(
+    flowsets: &[NetflowCommonFlowSet],
+    mut writer: W,
+    columns: &[Column],
+) -> io::Result<()> {
+    for flowset in flowsets {
+        write_row(
+            &mut writer,
+            columns.iter().map(|column| column.value(flowset)),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod csv_tests {
+    use super::{write_csv, write_csv_rows, Column};
+    use crate::NetflowParser;
+
+    const V5_PACKET: [u8; 72] = [
+        0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4,
+        5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+        4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+    ];
+
+    #[test]
+    fn it_writes_every_column() {
+        let flowsets =
+            NetflowParser::default().parse_bytes_as_netflow_common_flowsets(&V5_PACKET);
+        let mut out = Vec::new();
+        write_csv(&flowsets, &mut out, &Column::ALL).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "src_addr,dst_addr,src_port,dst_port,src_as,dst_as,protocol_number,protocol,\
+tcp_flags,tos,next_hop,first_seen,last_seen,src_mac,dst_mac,export_delay,late,input_interface,output_interface,\
+input_interface_name,output_interface_name"
+        );
+        assert_eq!(lines[1].split(',').count(), lines[0].split(',').count());
+        assert!(lines[1].starts_with("0.1.2.3,4.5.6.7,515,1029,1,515,8,Egp,"));
+    }
+
+    #[test]
+    fn it_quotes_values_and_skips_the_header() {
+        let mut flowsets =
+            NetflowParser::default().parse_bytes_as_netflow_common_flowsets(&V5_PACKET);
+        flowsets[0].input_interface_name = Some("uplink, \"core\"".to_string());
+        let mut out = Vec::new();
+        write_csv_rows(
+            &flowsets,
+            &mut out,
+            &[Column::InputInterfaceName, Column::SrcMac],
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\"uplink, \"\"core\"\"\",\n"
+        );
+    }
+}
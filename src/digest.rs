@@ -0,0 +1,73 @@
+//! # Digest
+//!
+//! Identity of parsed messages for exactly-once pipelines.  With
+//! `NetflowParserBuilder::with_packet_digests` every packet carries a [`PacketDigest`]: the
+//! source id and sequence number from its header, and a hash of its wire bytes.  A datagram
+//! retransmitted by the network or replayed by a relay gets the same digest, so it can be
+//! dropped downstream.  The hash is 64 bit FNV-1a, which is stable across platforms and
+//! releases, so digests can be stored and compared later.  It is not a cryptographic hash.
+//!
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let mut parser = NetflowParser::builder()
+//!     .with_packet_digests(true)
+//!     .build()
+//!     .expect("valid configuration");
+//! let first = parser.parse_bytes(&v5_packet)[0].digest();
+//! let retransmitted = parser.parse_bytes(&v5_packet)[0].digest();
+//! assert!(first.is_some());
+//! assert_eq!(first, retransmitted);
+//! ```
+
+use crate::NetflowPacket;
+
+use serde::Serialize;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Identity of a parsed message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct PacketDigest {
+    /// V9 source id or IPFix observation domain id, `None` for V5 and V7 whose exporters are
+    /// told apart by address.
+    pub source_id: Option<u32>,
+    /// Flow sequence of V5 and V7 headers, sequence number of V9 and IPFix headers.
+    pub sequence: u32,
+    /// FNV-1a hash of the message's wire bytes
+    pub hash: u64,
+}
+
+impl PacketDigest {
+    /// Digest of `packet`, parsed from `message`.  `None` for error packets.
+    pub fn new(packet: &NetflowPacket, message: &[u8]) -> Option<Self> {
+        Some(Self {
+            source_id: packet.source_id(),
+            sequence: packet.sequence_number()?,
+            hash: fnv1a(message),
+        })
+    }
+}
+
+/// 64 bit FNV-1a hash of `bytes`.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod digest_tests {
+    use super::fnv1a;
+
+    #[test]
+    fn it_hashes_with_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -0,0 +1,455 @@
+//! # DNS
+//!
+//! Names flow addresses from their PTR records.  [`DnsEnricher`] caches the names it resolves
+//! for a TTL, evicting the least recently used once full, and caps how many lookups it starts
+//! per window, [`DEFAULT_MAX_LOOKUPS`] a second by default, so a scan or a DDoS can't flood
+//! the resolver.  Await [`DnsEnricher::enrich`] on the flows of a packet to name them before
+//! they are passed on, or give the enricher to `NetflowParserBuilder::with_enrichment`, which
+//! names flows from the cache and resolves the addresses it misses in the background, so
+//! parsing never waits on DNS.  Lookups go to the system resolver by default, any other can be
+//! plugged in with [`PtrResolver`].  Requires the `dns` feature.
+//!
+//! ```rust,no_run
+//! use netflow_parser::dns::DnsEnricher;
+//! use netflow_parser::NetflowParser;
+//!
+//! # async fn run(v5_packet: &[u8]) {
+//! let dns = DnsEnricher::default();
+//! let mut parser = NetflowParser::default();
+//! let mut flowsets = parser.parse_bytes_as_netflow_common_flowsets(v5_packet);
+//! dns.enrich(&mut flowsets).await;
+//! for flowset in flowsets {
+//!     println!("{:?} -> {:?}", flowset.src_hostname, flowset.dst_hostname);
+//! }
+//! # }
+//! ```
+
+use crate::enrichment::Enrichment;
+use crate::netflow_common::NetflowCommonFlowSet;
+
+use tokio::task::JoinSet;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Lookups a [`DnsEnricher`] starts per [`DEFAULT_LOOKUP_WINDOW`] unless set.
+pub const DEFAULT_MAX_LOOKUPS: u32 = 100;
+
+/// Window of the default lookup rate limit.
+pub const DEFAULT_LOOKUP_WINDOW: Duration = Duration::from_secs(1);
+
+/// Future of a [`PtrResolver`] lookup.
+pub type ResolveFuture = Pin<Box<dyn Future<Output = Option<String>> + Send>>;
+
+/// Looks up the PTR record of an address.
+pub trait PtrResolver: Send + Sync + 'static {
+    /// Name of `addr`, `None` if it has none or the lookup failed.
+    fn resolve(&self, addr: IpAddr) -> ResolveFuture;
+}
+
+/// [`PtrResolver`] calling the system resolver on tokio's blocking pool.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemResolver;
+
+impl PtrResolver for SystemResolver {
+    fn resolve(&self, addr: IpAddr) -> ResolveFuture {
+        Box::pin(async move {
+            let name = tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&addr))
+                .await
+                .ok()?
+                .ok()?;
+            // Addresses without a PTR record come back in numeric form.
+            name.parse::<IpAddr>().is_err().then_some(name)
+        })
+    }
+}
+
+struct Cached {
+    name: Option<String>,
+    expires: Instant,
+    /// Value of `State::uses` when the name was cached
+    inserted: u64,
+    /// Value of `State::uses` when the name was last used
+    last_used: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Config {
+    ttl: Duration,
+    max_entries: usize,
+    /// `(max_lookups, window)`
+    rate_limit: Option<(u32, Duration)>,
+}
+
+/// Cache and rate limit shared by the clones of an enricher
+struct State {
+    cache: HashMap<IpAddr, Cached>,
+    /// Cached addresses by their `last_used`, least recently used first
+    lru: BTreeMap<u64, IpAddr>,
+    /// Cached addresses by their `expires` and `inserted`, first to expire first
+    expiries: BTreeMap<(Instant, u64), IpAddr>,
+    /// Cache reads and writes so far, ordering entries by when they were last used
+    uses: u64,
+    /// Addresses being resolved in the background
+    pending: HashSet<IpAddr>,
+    /// Start of the rate limit window and lookups started in it
+    window: (Instant, u32),
+    rate_limited: u64,
+}
+
+impl State {
+    /// Drops `addr` from the cache and its indexes.
+    fn remove(&mut self, addr: IpAddr) {
+        if let Some(cached) = self.cache.remove(&addr) {
+            self.lru.remove(&cached.last_used);
+            self.expiries.remove(&(cached.expires, cached.inserted));
+        }
+    }
+}
+
+/// Caching, rate limited reverse DNS [`Enrichment`] setting `src_hostname` and
+/// `dst_hostname`.  Clones share their cache and rate limit.
+pub struct DnsEnricher<R = SystemResolver> {
+    resolver: Arc<R>,
+    config: Config,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for DnsEnricher {
+    fn default() -> Self {
+        Self::new(SystemResolver)
+    }
+}
+
+impl<R> Clone for DnsEnricher<R> {
+    fn clone(&self) -> Self {
+        Self {
+            resolver: Arc::clone(&self.resolver),
+            config: self.config,
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<R: PtrResolver> DnsEnricher<R> {
+    /// Caches names for an hour, up to 65,536 addresses, starting at most
+    /// [`DEFAULT_MAX_LOOKUPS`] lookups every [`DEFAULT_LOOKUP_WINDOW`].
+    pub fn new(resolver: R) -> Self {
+        Self {
+            resolver: Arc::new(resolver),
+            config: Config {
+                ttl: Duration::from_secs(3600),
+                max_entries: 65_536,
+                rate_limit: Some((DEFAULT_MAX_LOOKUPS, DEFAULT_LOOKUP_WINDOW)),
+            },
+            state: Arc::new(Mutex::new(State {
+                cache: HashMap::new(),
+                lru: BTreeMap::new(),
+                expiries: BTreeMap::new(),
+                uses: 0,
+                pending: HashSet::new(),
+                window: (Instant::now(), 0),
+                rate_limited: 0,
+            })),
+        }
+    }
+
+    /// Sets how long names, and addresses without one, are cached.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.config.ttl = ttl;
+        self
+    }
+
+    /// Sets how many addresses are cached at most.  Once full, expired addresses are evicted
+    /// and then the least recently used.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.config.max_entries = max_entries;
+        self
+    }
+
+    /// Starts at most `max_lookups` lookups every `window`.  Addresses over the limit are left
+    /// unnamed and counted in `rate_limited`.
+    pub fn with_rate_limit(mut self, max_lookups: u32, window: Duration) -> Self {
+        self.config.rate_limit = Some((max_lookups, window));
+        self
+    }
+
+    /// Starts a lookup for every address missing from the cache, for resolvers that can't be
+    /// flooded, such as a local table.
+    pub fn without_rate_limit(mut self) -> Self {
+        self.config.rate_limit = None;
+        self
+    }
+
+    /// Lookups skipped by the rate limit.
+    pub fn rate_limited(&self) -> u64 {
+        self.lock().rate_limited
+    }
+
+    /// Addresses cached, expired ones included until they are evicted.
+    pub fn cached(&self) -> usize {
+        self.lock().cache.len()
+    }
+
+    /// Name of `addr`, from the cache or looked up.  `None` if it has none or the lookup was
+    /// rate limited.
+    pub async fn resolve(&self, addr: IpAddr) -> Option<String> {
+        if let Some(cached) = self.cached_name(addr) {
+            return cached;
+        }
+        if !self.start_lookup() {
+            return None;
+        }
+        let name = self.resolver.resolve(addr).await;
+        self.insert(addr, name.clone());
+        name
+    }
+
+    /// Sets `src_hostname` and `dst_hostname` of `flows`, looking up the addresses that
+    /// aren't cached concurrently.
+    pub async fn enrich(&self, flows: &mut [NetflowCommonFlowSet]) {
+        let addrs: HashSet<IpAddr> = flows
+            .iter()
+            .flat_map(|flow| [flow.src_addr, flow.dst_addr])
+            .flatten()
+            .collect();
+        let mut lookups = JoinSet::new();
+        for addr in addrs {
+            let enricher = self.clone();
+            lookups.spawn(async move { (addr, enricher.resolve(addr).await) });
+        }
+        let mut names = HashMap::new();
+        while let Some(lookup) = lookups.join_next().await {
+            if let Ok((addr, name)) = lookup {
+                names.insert(addr, name);
+            }
+        }
+        for flow in flows {
+            let name = |addr: Option<IpAddr>| names.get(&addr?).cloned().flatten();
+            flow.src_hostname = name(flow.src_addr);
+            flow.dst_hostname = name(flow.dst_addr);
+        }
+    }
+
+    /// Name of `addr` from the cache.  A miss is resolved in the background when called from
+    /// a tokio runtime.
+    fn cached_or_spawn(&self, addr: IpAddr) -> Option<String> {
+        if let Some(cached) = self.cached_name(addr) {
+            return cached;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return None;
+        };
+        if !self.lock().pending.insert(addr) {
+            return None;
+        }
+        if !self.start_lookup() {
+            self.lock().pending.remove(&addr);
+            return None;
+        }
+        let enricher = self.clone();
+        runtime.spawn(async move {
+            let name = enricher.resolver.resolve(addr).await;
+            enricher.lock().pending.remove(&addr);
+            enricher.insert(addr, name);
+        });
+        None
+    }
+}
+
+impl<R> DnsEnricher<R> {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn cached_name(&self, addr: IpAddr) -> Option<Option<String>> {
+        let mut state = self.lock();
+        state.uses += 1;
+        let uses = state.uses;
+        let state = &mut *state;
+        let cached = state.cache.get_mut(&addr)?;
+        if cached.expires <= Instant::now() {
+            return None;
+        }
+        state.lru.remove(&cached.last_used);
+        state.lru.insert(uses, addr);
+        cached.last_used = uses;
+        Some(cached.name.clone())
+    }
+
+    /// Whether a lookup can start within the rate limit, counting it if so.
+    fn start_lookup(&self) -> bool {
+        let Some((max_lookups, window)) = self.config.rate_limit else {
+            return true;
+        };
+        let mut state = self.lock();
+        let now = Instant::now();
+        if now.duration_since(state.window.0) >= window {
+            state.window = (now, 0);
+        }
+        if state.window.1 >= max_lookups {
+            state.rate_limited += 1;
+            return false;
+        }
+        state.window.1 += 1;
+        true
+    }
+
+    fn insert(&self, addr: IpAddr, name: Option<String>) {
+        let mut state = self.lock();
+        let now = Instant::now();
+        state.uses += 1;
+        let last_used = state.uses;
+        state.remove(addr);
+        while state.cache.len() >= self.config.max_entries {
+            let expired = state
+                .expiries
+                .first_key_value()
+                .filter(|((expires, _), _)| *expires <= now)
+                .map(|(_, addr)| *addr);
+            let Some(evicted) = expired.or_else(|| state.lru.values().next().copied()) else {
+                break;
+            };
+            state.remove(evicted);
+        }
+        if self.config.max_entries > 0 {
+            let expires = now + self.config.ttl;
+            state.lru.insert(last_used, addr);
+            state.expiries.insert((expires, last_used), addr);
+            state.cache.insert(
+                addr,
+                Cached {
+                    name,
+                    expires,
+                    inserted: last_used,
+                    last_used,
+                },
+            );
+        }
+    }
+}
+
+impl<R: PtrResolver> Enrichment for DnsEnricher<R> {
+    /// Names flows from the cache, resolving misses in the background for the flows that
+    /// follow.
+    fn enrich(&self, flow: &mut NetflowCommonFlowSet) {
+        flow.src_hostname = flow.src_addr.and_then(|addr| self.cached_or_spawn(addr));
+        flow.dst_hostname = flow.dst_addr.and_then(|addr| self.cached_or_spawn(addr));
+    }
+}
+
+impl<R> fmt::Debug for DnsEnricher<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DnsEnricher")
+            .field("config", &self.config)
+            .field("cached", &self.lock().cache.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod dns_tests {
+    use super::{DnsEnricher, PtrResolver, ResolveFuture};
+    use crate::enrichment::Enrichment;
+    use crate::netflow_common::NetflowCommonFlowSet;
+
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Names 10.0.0.x `host-x`, counting lookups.
+    #[derive(Default)]
+    struct Hosts(Arc<AtomicUsize>);
+
+    impl PtrResolver for Hosts {
+        fn resolve(&self, addr: IpAddr) -> ResolveFuture {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Box::pin(async move {
+                match addr {
+                    IpAddr::V4(v4) if v4.octets()[..3] == [10, 0, 0] => {
+                        Some(format!("host-{}", v4.octets()[3]))
+                    }
+                    _ => None,
+                }
+            })
+        }
+    }
+
+    fn flow(src: [u8; 4], dst: [u8; 4]) -> NetflowCommonFlowSet {
+        NetflowCommonFlowSet {
+            src_addr: Some(IpAddr::V4(Ipv4Addr::from(src))),
+            dst_addr: Some(IpAddr::V4(Ipv4Addr::from(dst))),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn it_names_flows_and_caches_lookups() {
+        let hosts = Hosts::default();
+        let lookups = Arc::clone(&hosts.0);
+        let dns = DnsEnricher::new(hosts);
+        let mut flows = vec![
+            flow([10, 0, 0, 1], [192, 0, 2, 1]),
+            flow([10, 0, 0, 1], [10, 0, 0, 2]),
+        ];
+        dns.enrich(&mut flows).await;
+        assert_eq!(flows[0].src_hostname.as_deref(), Some("host-1"));
+        assert_eq!(flows[0].dst_hostname, None);
+        assert_eq!(flows[1].dst_hostname.as_deref(), Some("host-2"));
+        assert_eq!(lookups.load(Ordering::Relaxed), 3);
+
+        dns.enrich(&mut flows).await;
+        assert_eq!(lookups.load(Ordering::Relaxed), 3);
+        assert_eq!(dns.cached(), 3);
+    }
+
+    #[tokio::test]
+    async fn it_evicts_the_least_recently_used_names() {
+        let hosts = Hosts::default();
+        let lookups = Arc::clone(&hosts.0);
+        let dns = DnsEnricher::new(hosts).with_max_entries(2);
+        let addr = |host| IpAddr::V4(Ipv4Addr::new(10, 0, 0, host));
+        dns.resolve(addr(1)).await;
+        dns.resolve(addr(2)).await;
+        dns.resolve(addr(1)).await;
+        // 2 was used least recently, so it makes room for 3
+        dns.resolve(addr(3)).await;
+        assert_eq!(dns.cached(), 2);
+        assert_eq!(lookups.load(Ordering::Relaxed), 3);
+        dns.resolve(addr(1)).await;
+        dns.resolve(addr(3)).await;
+        assert_eq!(lookups.load(Ordering::Relaxed), 3);
+        dns.resolve(addr(2)).await;
+        assert_eq!(lookups.load(Ordering::Relaxed), 4);
+    }
+
+    #[tokio::test]
+    async fn it_rate_limits_and_resolves_in_the_background() {
+        let dns =
+            DnsEnricher::new(Hosts::default()).with_rate_limit(1, Duration::from_secs(60));
+        let mut first = flow([10, 0, 0, 1], [10, 0, 0, 2]);
+        Enrichment::enrich(&dns, &mut first);
+        assert_eq!((first.src_hostname, first.dst_hostname), (None, None));
+        assert_eq!(dns.rate_limited(), 1);
+
+        tokio::task::yield_now().await;
+        let mut second = flow([10, 0, 0, 1], [10, 0, 0, 2]);
+        Enrichment::enrich(&dns, &mut second);
+        assert_eq!(second.src_hostname.as_deref(), Some("host-1"));
+        assert_eq!(second.dst_hostname, None);
+
+        // Lookups are rate limited by default
+        let dns = DnsEnricher::new(Hosts::default());
+        for host in 0..=super::DEFAULT_MAX_LOOKUPS {
+            let mut flow = flow([10, 0, 0, 1], [10, 1, (host >> 8) as u8, host as u8]);
+            Enrichment::enrich(&dns, &mut flow);
+        }
+        assert_eq!(dns.rate_limited(), 2);
+    }
+}
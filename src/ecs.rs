@@ -0,0 +1,412 @@
+//! # Elastic Common Schema
+//!
+//! Converts flows to documents with [ECS](https://www.elastic.co/guide/en/ecs/current/index.html)
+//! field names, so they can be indexed into Elasticsearch without a Logstash netflow codec in
+//! between.  Addresses, ports, AS numbers and MAC addresses go under `source` and
+//! `destination`, the protocol and counters under `network`, and interfaces under
+//! `observer.ingress` and `observer.egress`.  Raw records also keep every field under
+//! `netflow`, by its snake case name.  Dotted names are written as nested objects.  Requires
+//! the `json` feature.
+//!
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let flowsets = NetflowParser::default().parse_bytes_as_netflow_common_flowsets(&v5_packet);
+//! let document = flowsets[0].to_ecs();
+//! assert_eq!(document["source"]["ip"], "0.1.2.3");
+//! assert_eq!(document["network"]["transport"], "egp");
+//! ```
+
+use crate::flow_records::FlowRecord;
+use crate::json_lines::untagged;
+use crate::netflow_common::{
+    asn, interface_index, protocol_number, FlowDirection, NetflowCommonFlowSet,
+};
+use crate::protocol::ProtocolTypes;
+use crate::variable_versions::data_number::FieldValue;
+use crate::variable_versions::ipfix_lookup::IPFixField;
+use crate::variable_versions::v9_lookup::V9Field;
+
+use serde_json::{Map, Value};
+
+use std::fmt::Debug;
+use std::net::IpAddr;
+
+/// ECS field a V9/IPFix field is written to, besides `netflow`.
+#[derive(Debug, Clone, Copy)]
+enum EcsField {
+    /// `<side>.ip`
+    Ip(&'static str),
+    /// `<side>.port`
+    Port(&'static str),
+    /// `<side>.as.number`
+    As(&'static str),
+    /// `<side>.mac`
+    Mac(&'static str),
+    /// `observer.<direction>.interface.id`
+    Interface(&'static str),
+    /// `network.iana_number` and `network.transport`
+    Protocol,
+    /// `network.bytes`
+    Bytes,
+    /// `network.packets`
+    Packets,
+}
+
+fn v9_ecs_field(field: V9Field) -> Option<EcsField> {
+    Some(match field {
+        V9Field::Ipv4SrcAddr | V9Field::Ipv6SrcAddr => EcsField::Ip("source"),
+        V9Field::Ipv4DstAddr | V9Field::Ipv6DstAddr => EcsField::Ip("destination"),
+        V9Field::L4SrcPort => EcsField::Port("source"),
+        V9Field::L4DstPort => EcsField::Port("destination"),
+        V9Field::SrcAs => EcsField::As("source"),
+        V9Field::DstAs => EcsField::As("destination"),
+        V9Field::InSrcMac => EcsField::Mac("source"),
+        V9Field::InDstMac => EcsField::Mac("destination"),
+        V9Field::InputSnmp => EcsField::Interface("ingress"),
+        V9Field::OutputSnmp => EcsField::Interface("egress"),
+        V9Field::Protocol => EcsField::Protocol,
+        V9Field::InBytes => EcsField::Bytes,
+        V9Field::InPkts => EcsField::Packets,
+        _ => return None,
+    })
+}
+
+fn ipfix_ecs_field(field: IPFixField) -> Option<EcsField> {
+    Some(match field {
+        IPFixField::SourceIpv4address | IPFixField::SourceIpv6address => EcsField::Ip("source"),
+        IPFixField::DestinationIpv4address | IPFixField::DestinationIpv6address => {
+            EcsField::Ip("destination")
+        }
+        IPFixField::SourceTransportPort => EcsField::Port("source"),
+        IPFixField::DestinationTransportPort => EcsField::Port("destination"),
+        IPFixField::BgpSourceAsNumber => EcsField::As("source"),
+        IPFixField::BgpDestinationAsNumber => EcsField::As("destination"),
+        IPFixField::SourceMacaddress => EcsField::Mac("source"),
+        IPFixField::DestinationMacaddress => EcsField::Mac("destination"),
+        IPFixField::IngressInterface => EcsField::Interface("ingress"),
+        IPFixField::EgressInterface => EcsField::Interface("egress"),
+        IPFixField::ProtocolIdentifier => EcsField::Protocol,
+        IPFixField::OctetDeltaCount => EcsField::Bytes,
+        IPFixField::PacketDeltaCount => EcsField::Packets,
+        _ => return None,
+    })
+}
+
+/// ECS document being built, dotted names nested as objects.
+#[derive(Debug, Default)]
+struct Document(Map<String, Value>);
+
+impl Document {
+    fn set(&mut self, name: &str, value: impl Into<Value>) {
+        let mut object = &mut self.0;
+        let mut keys = name.split('.').peekable();
+        while let Some(key) = keys.next() {
+            if keys.peek().is_none() {
+                object.insert(key.to_string(), value.into());
+                return;
+            }
+            let child = object
+                .entry(key)
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !child.is_object() {
+                *child = Value::Object(Map::new());
+            }
+            object = child.as_object_mut().expect("just made an object");
+        }
+    }
+
+    fn ip(&mut self, side: &str, ip: IpAddr) {
+        self.set(&format!("{side}.ip"), ip.to_string());
+        let network_type = if ip.is_ipv4() { "ipv4" } else { "ipv6" };
+        self.set("network.type", network_type);
+    }
+
+    /// ECS MAC addresses are upper case and separated by hyphens.
+    fn mac(&mut self, side: &str, mac: &str) {
+        self.set(&format!("{side}.mac"), mac.to_uppercase().replace(':', "-"));
+    }
+
+    fn protocol(&mut self, number: u8) {
+        self.set("network.iana_number", number.to_string());
+        if let Some(keyword) = ProtocolTypes::from(number).keyword() {
+            self.set("network.transport", keyword);
+        }
+    }
+
+    fn interface(&mut self, direction: &str, id: u32, name: Option<&str>) {
+        self.set(
+            &format!("observer.{direction}.interface.id"),
+            id.to_string(),
+        );
+        if let Some(name) = name {
+            self.set(&format!("observer.{direction}.interface.name"), name);
+        }
+    }
+
+    fn field(&mut self, ecs_field: EcsField, value: &FieldValue) {
+        match ecs_field {
+            EcsField::Ip(side) => {
+                if let Ok(ip) = IpAddr::try_from(value) {
+                    self.ip(side, ip);
+                }
+            }
+            EcsField::Port(side) => {
+                if let Ok(port) = u16::try_from(value) {
+                    self.set(&format!("{side}.port"), port);
+                }
+            }
+            EcsField::As(side) => {
+                if let Some(asn) = asn(value) {
+                    self.set(&format!("{side}.as.number"), asn.0);
+                }
+            }
+            EcsField::Mac(side) => {
+                if let Ok(mac) = String::try_from(value) {
+                    self.mac(side, &mac);
+                }
+            }
+            EcsField::Interface(direction) => {
+                if let Some(id) = interface_index(value) {
+                    self.interface(direction, id, None);
+                }
+            }
+            EcsField::Protocol => {
+                if let Some(number) = protocol_number(value) {
+                    self.protocol(number);
+                }
+            }
+            EcsField::Bytes => self.set("network.bytes", untagged(value)),
+            EcsField::Packets => self.set("network.packets", untagged(value)),
+        }
+    }
+
+    /// Raw V9/IPFix record, every field under `netflow` and those ECS has in their place.
+    fn record<F: Copy + Debug>(
+        &mut self,
+        record: &[(F, FieldValue)],
+        ecs_field: fn(F) -> Option<EcsField>,
+    ) {
+        for (field, value) in record {
+            self.set(
+                &format!("netflow.{}", snake_case(&format!("{field:?}"))),
+                untagged(value),
+            );
+            if let Some(ecs_field) = ecs_field(*field) {
+                self.field(ecs_field, value);
+            }
+        }
+    }
+}
+
+/// `InBytes` as `in_bytes`.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len() + 4);
+    let mut previous_lower = false;
+    for c in name.chars() {
+        if c.is_ascii_uppercase() && previous_lower {
+            snake.push('_');
+        }
+        previous_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+impl NetflowCommonFlowSet {
+    /// The flow as an ECS document.  Fields that aren't set are left out.
+    pub fn to_ecs(&self) -> Value {
+        let mut document = Document::default();
+        if let Some(ip) = self.src_addr {
+            document.ip("source", ip);
+        }
+        if let Some(ip) = self.dst_addr {
+            document.ip("destination", ip);
+        }
+        if let Some(port) = self.src_port {
+            document.set("source.port", port.0);
+        }
+        if let Some(port) = self.dst_port {
+            document.set("destination.port", port.0);
+        }
+        if let Some(asn) = self.src_as {
+            document.set("source.as.number", asn.0);
+        }
+        if let Some(asn) = self.dst_as {
+            document.set("destination.as.number", asn.0);
+        }
+        if let Some(name) = &self.src_hostname {
+            document.set("source.domain", name.as_str());
+        }
+        if let Some(name) = &self.dst_hostname {
+            document.set("destination.domain", name.as_str());
+        }
+        if let Some(country) = &self.src_country {
+            document.set("source.geo.country_iso_code", country.as_str());
+        }
+        if let Some(country) = &self.dst_country {
+            document.set("destination.geo.country_iso_code", country.as_str());
+        }
+        if let Some(mac) = &self.src_mac {
+            document.mac("source", mac);
+        }
+        if let Some(mac) = &self.dst_mac {
+            document.mac("destination", mac);
+        }
+        if let Some(number) = self.protocol_number {
+            document.protocol(number);
+        }
+        if let Some(id) = self.input_interface {
+            document.interface("ingress", id, self.input_interface_name.as_deref());
+        }
+        if let Some(id) = self.output_interface {
+            document.interface("egress", id, self.output_interface_name.as_deref());
+        }
+        if let Some(direction) = self.direction {
+            let direction = match direction {
+                FlowDirection::Ingress => "ingress",
+                FlowDirection::Egress => "egress",
+            };
+            document.set("network.direction", direction);
+        }
+        if let Some(bytes) = self.bytes {
+            document.set("network.bytes", bytes);
+        }
+        if let Some(packets) = self.packets {
+            document.set("network.packets", packets);
+        }
+        if let Some(first) = self.first_seen {
+            document.set("netflow.first_switched", first);
+        }
+        if let Some(last) = self.last_seen {
+            document.set("netflow.last_switched", last);
+        }
+        Value::Object(document.0)
+    }
+}
+
+impl FlowRecord {
+    /// The record as an ECS document, with every field of the record under `netflow`.
+    pub fn to_ecs(&self) -> Value {
+        let mut document = Document::default();
+        match self {
+            FlowRecord::V5(flowset) => {
+                document.set("netflow", serde_json::to_value(flowset).unwrap_or_default());
+                document.ip("source", flowset.src_addr.into());
+                document.ip("destination", flowset.dst_addr.into());
+                document.set("source.port", flowset.src_port);
+                document.set("destination.port", flowset.dst_port);
+                document.set("source.as.number", flowset.src_as);
+                document.set("destination.as.number", flowset.dst_as);
+                document.protocol(flowset.protocol_number);
+                document.set("network.bytes", flowset.d_octets);
+                document.set("network.packets", flowset.d_pkts);
+                document.interface("ingress", flowset.input.into(), None);
+                document.interface("egress", flowset.output.into(), None);
+            }
+            FlowRecord::V7(flowset) => {
+                document.set("netflow", serde_json::to_value(flowset).unwrap_or_default());
+                document.ip("source", flowset.src_addr.into());
+                document.ip("destination", flowset.dst_addr.into());
+                document.set("source.port", flowset.src_port);
+                document.set("destination.port", flowset.dst_port);
+                document.set("source.as.number", flowset.src_as);
+                document.set("destination.as.number", flowset.dst_as);
+                document.protocol(flowset.protocol_number);
+                document.set("network.bytes", flowset.d_octets);
+                document.set("network.packets", flowset.d_pkts);
+                document.interface("ingress", flowset.input.into(), None);
+                document.interface("egress", flowset.output.into(), None);
+            }
+            FlowRecord::V9(record) => document.record(record, v9_ecs_field),
+            FlowRecord::IPFix(record) => document.record(record, ipfix_ecs_field),
+        }
+        Value::Object(document.0)
+    }
+}
+
+#[cfg(test)]
+mod ecs_tests {
+    use super::snake_case;
+    use crate::flow_records::FlowRecord;
+    use crate::NetflowParser;
+
+    use serde_json::json;
+
+    #[test]
+    fn it_maps_raw_records_to_ecs() {
+        let packet = [
+            0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2,
+            0, 2, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let records: Vec<FlowRecord> = NetflowParser::default()
+            .iter_flow_records(&packet)
+            .collect();
+        let document = records[0].to_ecs();
+        assert_eq!(document["source"]["ip"], json!("1.2.3.4"));
+        assert_eq!(document["destination"]["ip"], json!("1.2.3.3"));
+        assert_eq!(document["network"]["type"], json!("ipv4"));
+        assert_eq!(document["network"]["packets"], json!(16909058));
+        assert_eq!(document["netflow"]["source_ipv4address"], json!("1.2.3.4"));
+    }
+
+    #[test]
+    fn it_maps_common_flowsets_to_ecs() {
+        let v5_packet = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let mut parser = NetflowParser::default();
+        let mut flowsets = parser.parse_bytes_as_netflow_common_flowsets(&v5_packet);
+        let flowset = &mut flowsets[0];
+        flowset.src_mac = Some("aa:bb:cc:00:11:22".to_string());
+        flowset.input_interface_name = Some("ge-0/0/1".to_string());
+        flowset.dst_country = Some("NZ".to_string());
+        assert_eq!(
+            flowset.to_ecs(),
+            json!({
+                "source": {
+                    "ip": "0.1.2.3",
+                    "port": 515,
+                    "as": { "number": 1 },
+                    "mac": "AA-BB-CC-00-11-22",
+                },
+                "destination": {
+                    "ip": "4.5.6.7",
+                    "port": 1029,
+                    "as": { "number": 515 },
+                    "geo": { "country_iso_code": "NZ" },
+                },
+                "network": {
+                    "type": "ipv4",
+                    "iana_number": "8",
+                    "transport": "egp",
+                    "bytes": 66051,
+                    "packets": 101124105,
+                },
+                "observer": {
+                    "ingress": { "interface": { "id": "515", "name": "ge-0/0/1" } },
+                    "egress": { "interface": { "id": "1029" } },
+                },
+                "netflow": { "first_switched": 67438087, "last_switched": 134807553 },
+            })
+        );
+
+        let v5_record = parser.iter_flow_records(&v5_packet).next().unwrap();
+        let document = v5_record.to_ecs();
+        assert_eq!(document["network"]["bytes"], json!(66051));
+        assert_eq!(document["netflow"]["tcp_flags"], json!(7));
+    }
+
+    #[test]
+    fn it_converts_field_names_to_snake_case() {
+        assert_eq!(snake_case("InBytes"), "in_bytes");
+        assert_eq!(snake_case("Ipv4SrcAddr"), "ipv4_src_addr");
+        assert_eq!(snake_case("BgpSourceAsNumber"), "bgp_source_as_number");
+    }
+}
@@ -0,0 +1,123 @@
+//! # Enrichment
+//!
+//! Attaches data the exporter doesn't send, such as the country or AS of an address, to flows
+//! as they are parsed.  Give an [`Enrichment`] to `NetflowParserBuilder::with_enrichment` and
+//! `parse_bytes_as_netflow_common_flowsets` runs every flow through it, after late flows are
+//! flagged and interfaces named.  With the `geoip` feature `MaxMindEnrichment` looks
+//! countries and AS numbers up in MaxMind GeoIP2/GeoLite2 databases.
+//!
+//! ```rust
+//! use netflow_parser::enrichment::Enrichment;
+//! use netflow_parser::netflow_common::NetflowCommonFlowSet;
+//! use netflow_parser::NetflowParser;
+//! use std::sync::Arc;
+//!
+//! #[derive(Debug)]
+//! struct Lab;
+//!
+//! impl Enrichment for Lab {
+//!     fn enrich(&self, flow: &mut NetflowCommonFlowSet) {
+//!         flow.src_country = Some("AQ".to_string());
+//!     }
+//! }
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let mut parser = NetflowParser::builder()
+//!     .with_enrichment(Arc::new(Lab))
+//!     .build()
+//!     .expect("valid configuration");
+//! let flowsets = parser.parse_bytes_as_netflow_common_flowsets(&v5_packet);
+//! assert_eq!(flowsets[0].src_country.as_deref(), Some("AQ"));
+//! ```
+
+use crate::netflow_common::NetflowCommonFlowSet;
+
+use core::fmt;
+
+/// Adds data to parsed flows.
+pub trait Enrichment: fmt::Debug + Send + Sync {
+    fn enrich(&self, flow: &mut NetflowCommonFlowSet);
+}
+
+/// [`Enrichment`] setting the country of flow addresses, and their AS number when the exporter
+/// didn't send one, from MaxMind databases.  Requires the `geoip` feature.
+#[cfg(feature = "geoip")]
+#[derive(Default)]
+pub struct MaxMindEnrichment {
+    country: Option<maxminddb::Reader<Vec<u8>>>,
+    asn: Option<maxminddb::Reader<Vec<u8>>>,
+}
+
+#[cfg(feature = "geoip")]
+impl MaxMindEnrichment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Looks countries up in the GeoIP2/GeoLite2 Country or City database at `path`.
+    pub fn with_country_database(
+        mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Self, maxminddb::MaxMindDBError> {
+        self.country = Some(maxminddb::Reader::open_readfile(path)?);
+        Ok(self)
+    }
+
+    /// Looks AS numbers up in the GeoLite2 ASN database at `path`.
+    pub fn with_asn_database(
+        mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Self, maxminddb::MaxMindDBError> {
+        self.asn = Some(maxminddb::Reader::open_readfile(path)?);
+        Ok(self)
+    }
+
+    /// ISO 3166-1 code of the country of `addr`.
+    pub fn country(&self, addr: std::net::IpAddr) -> Option<String> {
+        let country: maxminddb::geoip2::Country = self.country.as_ref()?.lookup(addr).ok()?;
+        country.country?.iso_code.map(str::to_string)
+    }
+
+    /// AS number `addr` is announced by.
+    pub fn asn(&self, addr: std::net::IpAddr) -> Option<crate::types::Asn> {
+        let asn: maxminddb::geoip2::Asn = self.asn.as_ref()?.lookup(addr).ok()?;
+        asn.autonomous_system_number.map(crate::types::Asn)
+    }
+}
+
+#[cfg(feature = "geoip")]
+impl Enrichment for MaxMindEnrichment {
+    fn enrich(&self, flow: &mut NetflowCommonFlowSet) {
+        // Exporters without BGP send 0 for AS numbers they don't know.
+        let unknown = |asn: Option<crate::types::Asn>| asn.is_none_or(|asn| asn.0 == 0);
+        if let Some(addr) = flow.src_addr {
+            flow.src_country = self.country(addr);
+            if unknown(flow.src_as) {
+                flow.src_as = self.asn(addr).or(flow.src_as);
+            }
+        }
+        if let Some(addr) = flow.dst_addr {
+            flow.dst_country = self.country(addr);
+            if unknown(flow.dst_as) {
+                flow.dst_as = self.asn(addr).or(flow.dst_as);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "geoip")]
+impl fmt::Debug for MaxMindEnrichment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let database = |reader: &Option<maxminddb::Reader<Vec<u8>>>| {
+            reader
+                .as_ref()
+                .map(|reader| reader.metadata.database_type.clone())
+        };
+        f.debug_struct("MaxMindEnrichment")
+            .field("country", &database(&self.country))
+            .field("asn", &database(&self.asn))
+            .finish()
+    }
+}
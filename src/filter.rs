@@ -0,0 +1,658 @@
+//! # Filter
+//!
+//! Filter expressions selecting flow records, such as `proto==6 && dst_port==443 &&
+//! bytes>1000`.  Set one with `NetflowParserBuilder::with_filter` and records that don't match
+//! are dropped from every packet parsed, V9 and IPFix data records as they are decoded, or
+//! call [`Filter::matches`] yourself.
+//!
+//! Comparisons are `field op value` with `==`, `!=`, `<`, `<=`, `>` and `>=`, combined with
+//! `&&`, `||`, `!` and parentheses.  Values are numbers or IP addresses, and addresses can be
+//! compared to a network such as `src_addr==10.0.0.0/8`.  A comparison on a field the record
+//! doesn't have never matches.  The fields are:
+//!
+//! * `proto` (or `protocol`), `src_port`, `dst_port`, `src_addr`, `dst_addr`, `src_as`, `dst_as`
+//! * `bytes`, `packets`, which `NetflowCommonFlowSet`s don't have
+//! * `input_interface`, `output_interface`
+//!
+//! ```rust
+//! use netflow_parser::filter::Filter;
+//! use netflow_parser::NetflowParser;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let filter = Filter::parse("proto==8 && dst_addr==4.5.0.0/16").expect("valid filter");
+//! let mut parser = NetflowParser::builder()
+//!     .with_filter(filter)
+//!     .build()
+//!     .expect("valid configuration");
+//! assert_eq!(parser.iter_flow_records(&v5_packet).count(), 1);
+//!
+//! let filter = Filter::parse("proto==6 && (dst_port==443 || bytes>1000)").expect("valid filter");
+//! let mut parser = NetflowParser::builder()
+//!     .with_filter(filter)
+//!     .build()
+//!     .expect("valid configuration");
+//! assert_eq!(parser.iter_flow_records(&v5_packet).count(), 0);
+//! ```
+
+use crate::flow_records::FlowRecord;
+use crate::netflow_common::NetflowCommonFlowSet;
+use crate::static_versions::{v5, v7};
+use crate::variable_versions::data_number::{DataNumber, FieldValue};
+use crate::variable_versions::ipfix_lookup::IPFixField;
+use crate::variable_versions::v9_lookup::V9Field;
+use crate::NetflowPacket;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use core::fmt;
+use core::net::IpAddr;
+
+/// A field filters compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FilterField {
+    Protocol,
+    SrcPort,
+    DstPort,
+    SrcAddr,
+    DstAddr,
+    SrcAs,
+    DstAs,
+    Bytes,
+    Packets,
+    InputInterface,
+    OutputInterface,
+}
+
+impl FilterField {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "proto" | "protocol" => FilterField::Protocol,
+            "src_port" => FilterField::SrcPort,
+            "dst_port" => FilterField::DstPort,
+            "src_addr" => FilterField::SrcAddr,
+            "dst_addr" => FilterField::DstAddr,
+            "src_as" => FilterField::SrcAs,
+            "dst_as" => FilterField::DstAs,
+            "bytes" => FilterField::Bytes,
+            "packets" => FilterField::Packets,
+            "input_interface" => FilterField::InputInterface,
+            "output_interface" => FilterField::OutputInterface,
+            _ => return None,
+        })
+    }
+}
+
+/// Value of a [`FilterField`] in a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterValue {
+    Number(u64),
+    Addr(IpAddr),
+}
+
+/// Records filters can be applied to.
+pub trait FilterFields {
+    /// Value of `field`, `None` if the record doesn't have it.
+    fn filter_value(&self, field: FilterField) -> Option<FilterValue>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Number(u64),
+    Addr(IpAddr),
+    Network(IpAddr, u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Compare(FilterField, Op, Operand),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// Why a filter expression couldn't be parsed, with the byte offset it failed at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterError {
+    UnknownField {
+        position: usize,
+        name: String,
+    },
+    InvalidValue {
+        position: usize,
+        value: String,
+    },
+    /// Something else was found where `expected` should be
+    Expected {
+        position: usize,
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::UnknownField { position, name } => {
+                write!(f, "unknown field `{name}` at {position}")
+            }
+            FilterError::InvalidValue { position, value } => {
+                write!(f, "invalid value `{value}` at {position}")
+            }
+            FilterError::Expected { position, expected } => {
+                write!(f, "expected {expected} at {position}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for FilterError {}
+
+/// A parsed filter expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(expression: &str) -> Result<Self, FilterError> {
+        let mut parser = ExprParser {
+            input: expression,
+            position: 0,
+        };
+        let expr = parser.or()?;
+        parser.skip_whitespace();
+        if parser.position < expression.len() {
+            return Err(parser.expected("`&&`, `||` or the end"));
+        }
+        Ok(Self { expr })
+    }
+
+    /// Whether `record` matches the filter.
+    pub fn matches(&self, record: &impl FilterFields) -> bool {
+        self.expr.matches(record)
+    }
+
+    /// Drops the flow records of a V5 or V7 `packet` that don't match.  V9 and IPFix data
+    /// records are filtered as they are decoded, and templates and options records are kept.
+    pub(crate) fn retain(&self, packet: &mut NetflowPacket) {
+        match packet {
+            NetflowPacket::V5(v5) => v5.flowsets.retain(|record| self.matches(record)),
+            NetflowPacket::V7(v7) => v7.flowsets.retain(|record| self.matches(record)),
+            NetflowPacket::V9(_) | NetflowPacket::IPFix(_) | NetflowPacket::Error(_) => {}
+        }
+    }
+}
+
+impl core::str::FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(expression: &str) -> Result<Self, FilterError> {
+        Filter::parse(expression)
+    }
+}
+
+impl Expr {
+    fn matches(&self, record: &impl FilterFields) -> bool {
+        match self {
+            Expr::Compare(field, op, operand) => record
+                .filter_value(*field)
+                .is_some_and(|value| compare(value, *op, *operand)),
+            Expr::Not(expr) => !expr.matches(record),
+            Expr::And(left, right) => left.matches(record) && right.matches(record),
+            Expr::Or(left, right) => left.matches(record) || right.matches(record),
+        }
+    }
+}
+
+fn compare(value: FilterValue, op: Op, operand: Operand) -> bool {
+    let ordering = match (value, operand) {
+        (FilterValue::Number(value), Operand::Number(operand)) => value.cmp(&operand),
+        (FilterValue::Addr(value), Operand::Addr(operand)) => value.cmp(&operand),
+        (FilterValue::Addr(value), Operand::Network(network, prefix)) => {
+            let contained = in_network(value, network, prefix);
+            return match op {
+                Op::Eq => contained,
+                Op::Ne => !contained,
+                _ => false,
+            };
+        }
+        _ => return false,
+    };
+    match op {
+        Op::Eq => ordering.is_eq(),
+        Op::Ne => ordering.is_ne(),
+        Op::Lt => ordering.is_lt(),
+        Op::Le => ordering.is_le(),
+        Op::Gt => ordering.is_gt(),
+        Op::Ge => ordering.is_ge(),
+    }
+}
+
+fn in_network(addr: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    let (addr, network, bits) = match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            (u32::from(addr).into(), u32::from(network).into(), 32)
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => (u128::from(addr), u128::from(network), 128),
+        _ => return false,
+    };
+    let shift = bits - u32::from(prefix);
+    shift >= bits || addr >> shift == network >> shift
+}
+
+struct ExprParser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl ExprParser<'_> {
+    fn rest(&self) -> &str {
+        &self.input[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consumes `token` if it is next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.rest().starts_with(token);
+        if found {
+            self.position += token.len();
+        }
+        found
+    }
+
+    fn expected(&self, expected: &'static str) -> FilterError {
+        FilterError::Expected {
+            position: self.position,
+            expected,
+        }
+    }
+
+    /// Takes a field name or value.
+    fn word(&mut self) -> (usize, &str) {
+        self.skip_whitespace();
+        let start = self.position;
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "_.:/".contains(c)))
+            .unwrap_or(rest.len());
+        self.position += length;
+        (start, &self.input[start..start + length])
+    }
+
+    fn or(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.unary()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, FilterError> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                return Err(self.expected("`)`"));
+            }
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, FilterError> {
+        let (position, name) = self.word();
+        if name.is_empty() {
+            return Err(self.expected("a field"));
+        }
+        let field = FilterField::from_name(name).ok_or_else(|| FilterError::UnknownField {
+            position,
+            name: name.to_string(),
+        })?;
+        // Two character operators first, so `<=` isn't taken as `<`.
+        let op = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ]
+        .into_iter()
+        .find(|(token, _)| self.eat(token))
+        .map(|(_, op)| op)
+        .ok_or_else(|| self.expected("a comparison"))?;
+        let (position, value) = self.word();
+        if value.is_empty() {
+            return Err(self.expected("a value"));
+        }
+        let operand = parse_operand(value).ok_or_else(|| FilterError::InvalidValue {
+            position,
+            value: value.to_string(),
+        })?;
+        Ok(Expr::Compare(field, op, operand))
+    }
+}
+
+fn parse_operand(value: &str) -> Option<Operand> {
+    if let Some((network, prefix)) = value.split_once('/') {
+        let network: IpAddr = network.parse().ok()?;
+        let prefix: u8 = prefix.parse().ok()?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        return (prefix <= bits).then_some(Operand::Network(network, prefix));
+    }
+    if let Ok(number) = value.parse() {
+        return Some(Operand::Number(number));
+    }
+    value.parse().ok().map(Operand::Addr)
+}
+
+fn number(value: &FieldValue) -> Option<FilterValue> {
+    let FieldValue::DataNumber(number) = value else {
+        return None;
+    };
+    let number = match *number {
+        DataNumber::U8(n) => n.into(),
+        DataNumber::U16(n) => n.into(),
+        DataNumber::U24(n) | DataNumber::U32(n) => n.into(),
+        DataNumber::U64(n) => n,
+        DataNumber::U128(n) => u64::try_from(n).ok()?,
+        DataNumber::I24(n) | DataNumber::I32(n) => u64::try_from(n).ok()?,
+    };
+    Some(FilterValue::Number(number))
+}
+
+fn addr(value: &FieldValue) -> Option<FilterValue> {
+    IpAddr::try_from(value).ok().map(FilterValue::Addr)
+}
+
+macro_rules! impl_static_filter_fields {
+    ($($t:ty),*) => {
+        $(
+            impl FilterFields for $t {
+                fn filter_value(&self, field: FilterField) -> Option<FilterValue> {
+                    let number = |n: u64| Some(FilterValue::Number(n));
+                    match field {
+                        FilterField::Protocol => number(self.protocol_number.into()),
+                        FilterField::SrcPort => number(self.src_port.into()),
+                        FilterField::DstPort => number(self.dst_port.into()),
+                        FilterField::SrcAddr => Some(FilterValue::Addr(self.src_addr.into())),
+                        FilterField::DstAddr => Some(FilterValue::Addr(self.dst_addr.into())),
+                        FilterField::SrcAs => number(self.src_as.into()),
+                        FilterField::DstAs => number(self.dst_as.into()),
+                        FilterField::Bytes => number(self.d_octets.into()),
+                        FilterField::Packets => number(self.d_pkts.into()),
+                        FilterField::InputInterface => number(self.input.into()),
+                        FilterField::OutputInterface => number(self.output.into()),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_static_filter_fields!(v5::FlowSet, v7::FlowSet);
+
+/// Value of `field` in the V9 fields of a record.
+fn v9_value<'a>(
+    mut pairs: impl Iterator<Item = &'a (V9Field, FieldValue)>,
+    field: FilterField,
+) -> Option<FilterValue> {
+    let fields: &[V9Field] = match field {
+        FilterField::Protocol => &[V9Field::Protocol],
+        FilterField::SrcPort => &[V9Field::L4SrcPort],
+        FilterField::DstPort => &[V9Field::L4DstPort],
+        FilterField::SrcAddr => &[V9Field::Ipv4SrcAddr, V9Field::Ipv6SrcAddr],
+        FilterField::DstAddr => &[V9Field::Ipv4DstAddr, V9Field::Ipv6DstAddr],
+        FilterField::SrcAs => &[V9Field::SrcAs],
+        FilterField::DstAs => &[V9Field::DstAs],
+        FilterField::Bytes => &[V9Field::InBytes],
+        FilterField::Packets => &[V9Field::InPkts],
+        FilterField::InputInterface => &[V9Field::InputSnmp],
+        FilterField::OutputInterface => &[V9Field::OutputSnmp],
+    };
+    let (_, value) = pairs.find(|(f, _)| fields.contains(f))?;
+    match field {
+        FilterField::SrcAddr | FilterField::DstAddr => addr(value),
+        FilterField::Protocol => match value {
+            FieldValue::ProtocolType(protocol) => {
+                Some(FilterValue::Number(u8::from(*protocol).into()))
+            }
+            _ => number(value),
+        },
+        _ => number(value),
+    }
+}
+
+/// Value of `field` in the IPFix fields of a record.
+fn ipfix_value<'a>(
+    mut pairs: impl Iterator<Item = &'a (IPFixField, FieldValue)>,
+    field: FilterField,
+) -> Option<FilterValue> {
+    let fields: &[IPFixField] = match field {
+        FilterField::Protocol => &[IPFixField::ProtocolIdentifier],
+        FilterField::SrcPort => &[IPFixField::SourceTransportPort],
+        FilterField::DstPort => &[IPFixField::DestinationTransportPort],
+        FilterField::SrcAddr => &[IPFixField::SourceIpv4address, IPFixField::SourceIpv6address],
+        FilterField::DstAddr => &[
+            IPFixField::DestinationIpv4address,
+            IPFixField::DestinationIpv6address,
+        ],
+        FilterField::SrcAs => &[IPFixField::BgpSourceAsNumber],
+        FilterField::DstAs => &[IPFixField::BgpDestinationAsNumber],
+        FilterField::Bytes => &[IPFixField::OctetDeltaCount],
+        FilterField::Packets => &[IPFixField::PacketDeltaCount],
+        FilterField::InputInterface => &[IPFixField::IngressInterface],
+        FilterField::OutputInterface => &[IPFixField::EgressInterface],
+    };
+    let (_, value) = pairs.find(|(f, _)| fields.contains(f))?;
+    match field {
+        FilterField::SrcAddr | FilterField::DstAddr => addr(value),
+        _ => number(value),
+    }
+}
+
+impl FilterFields for BTreeMap<usize, (V9Field, FieldValue)> {
+    fn filter_value(&self, field: FilterField) -> Option<FilterValue> {
+        v9_value(self.values(), field)
+    }
+}
+
+impl FilterFields for BTreeMap<usize, (IPFixField, FieldValue)> {
+    fn filter_value(&self, field: FilterField) -> Option<FilterValue> {
+        ipfix_value(self.values(), field)
+    }
+}
+
+impl FilterFields for &[(V9Field, FieldValue)] {
+    fn filter_value(&self, field: FilterField) -> Option<FilterValue> {
+        v9_value(self.iter(), field)
+    }
+}
+
+impl FilterFields for &[(IPFixField, FieldValue)] {
+    fn filter_value(&self, field: FilterField) -> Option<FilterValue> {
+        ipfix_value(self.iter(), field)
+    }
+}
+
+impl FilterFields for FlowRecord {
+    fn filter_value(&self, field: FilterField) -> Option<FilterValue> {
+        match self {
+            FlowRecord::V5(record) => record.filter_value(field),
+            FlowRecord::V7(record) => record.filter_value(field),
+            FlowRecord::V9(record) => v9_value(record.iter(), field),
+            FlowRecord::IPFix(record) => ipfix_value(record.iter(), field),
+        }
+    }
+}
+
+impl FilterFields for NetflowCommonFlowSet {
+    fn filter_value(&self, field: FilterField) -> Option<FilterValue> {
+        let number = |n: Option<u64>| n.map(FilterValue::Number);
+        match field {
+            FilterField::Protocol => number(self.protocol_number.map(u64::from)),
+            FilterField::SrcPort => number(self.src_port.map(|port| port.0.into())),
+            FilterField::DstPort => number(self.dst_port.map(|port| port.0.into())),
+            FilterField::SrcAddr => self.src_addr.map(FilterValue::Addr),
+            FilterField::DstAddr => self.dst_addr.map(FilterValue::Addr),
+            FilterField::SrcAs => number(self.src_as.map(|asn| asn.0.into())),
+            FilterField::DstAs => number(self.dst_as.map(|asn| asn.0.into())),
+            FilterField::Bytes => number(self.bytes),
+            FilterField::Packets => number(self.packets),
+            FilterField::InputInterface => number(self.input_interface.map(u64::from)),
+            FilterField::OutputInterface => number(self.output_interface.map(u64::from)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::{Filter, FilterError};
+    use crate::netflow_common::NetflowCommonFlowSet;
+    use crate::types::Port;
+    use crate::variable_versions::v9::CountSemantics;
+    use crate::{NetflowPacket, NetflowParser};
+
+    use std::net::{IpAddr, Ipv6Addr};
+
+    #[test]
+    fn it_evaluates_expressions() {
+        let flow = NetflowCommonFlowSet {
+            src_addr: Some(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))),
+            dst_port: Some(Port(443)),
+            protocol_number: Some(6),
+            ..Default::default()
+        };
+        let matches = |expression: &str| Filter::parse(expression).unwrap().matches(&flow);
+        assert!(matches("proto==6 && dst_port==443"));
+        assert!(matches("proto == 17 || dst_port >= 400"));
+        assert!(matches("!(proto==17) && src_addr==2001:db8::/32"));
+        assert!(matches("src_addr != 10.0.0.0/8 && src_addr > ::1"));
+        assert!(!matches("proto==6 && (dst_port<443 || src_port==1)"));
+        // Fields the flow doesn't have never match.
+        assert!(!matches("bytes>1000"));
+        assert!(!matches("bytes<=1000"));
+        let flow = NetflowCommonFlowSet {
+            bytes: Some(1500),
+            packets: Some(1),
+            ..Default::default()
+        };
+        assert!(Filter::parse("bytes>1000 && packets==1")
+            .unwrap()
+            .matches(&flow));
+    }
+
+    #[test]
+    fn it_filters_v9_common_flowsets_by_protocol() {
+        // Template 256 of protocol and destination port, and a TCP record of it to port 443
+        let packet = [
+            0, 9, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 0, 0,
+            2, 0, 4, 0, 1, 0, 11, 0, 2, 1, 0, 0, 8, 6, 1, 187, 0,
+        ];
+        let filter = Filter::parse("proto==6 && dst_port==443").unwrap();
+        let mut parser = NetflowParser::builder()
+            .with_filter(filter)
+            .build()
+            .unwrap();
+        let flowsets = parser.parse_bytes_as_netflow_common_flowsets(&packet);
+        assert_eq!(flowsets.len(), 1);
+        assert_eq!(flowsets[0].protocol_number, Some(6));
+    }
+
+    #[test]
+    fn it_drops_records_as_they_are_decoded() {
+        // A template of bytes and source address, then a data flowset of two records
+        // counted as RFC 3954 defines it, followed by a V5 packet
+        let v9_packet = [
+            0, 9, 0, 3, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 20, 9, 2, 3, 4, 9, 9, 9, 8, 1, 1, 1, 1, 2, 2,
+            2, 2,
+        ];
+        let v5_packet = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let mut parser = NetflowParser::builder()
+            .with_v9_count_semantics(CountSemantics::Records)
+            .with_filter(Filter::parse("bytes<20000000 || proto==8").unwrap())
+            .build()
+            .unwrap();
+        let packets = parser.parse_bytes(&[&v9_packet[..], &v5_packet].concat());
+        assert_eq!(packets.len(), 2);
+        let NetflowPacket::V9(v9) = &packets[0] else {
+            panic!("expected a V9 packet");
+        };
+        // Dropped records still count towards the header count
+        let data = v9.flowsets[1].body.data.as_ref().unwrap();
+        assert_eq!(data.data_fields.len(), 1);
+        assert!(Filter::parse("src_addr==2.2.2.2")
+            .unwrap()
+            .matches(&data.data_fields[0]));
+        assert!(packets[1].is_v5());
+    }
+
+    #[test]
+    fn it_reports_invalid_expressions() {
+        let error = |expression: &str| Filter::parse(expression).unwrap_err();
+        assert_eq!(
+            error("proto==6 && port==80"),
+            FilterError::UnknownField {
+                position: 12,
+                name: "port".to_string()
+            }
+        );
+        assert_eq!(
+            error("src_addr==10.0.0.0/33"),
+            FilterError::InvalidValue {
+                position: 10,
+                value: "10.0.0.0/33".to_string()
+            }
+        );
+        assert_eq!(
+            error("(proto==6"),
+            FilterError::Expected {
+                position: 9,
+                expected: "`)`"
+            }
+        );
+        assert_eq!(
+            error("proto 6"),
+            FilterError::Expected {
+                position: 6,
+                expected: "a comparison"
+            }
+        );
+        assert_eq!(
+            error("proto==6 dst_port==1").to_string(),
+            "expected `&&`, `||` or the end at 9"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Flat iteration over the flow records of every packet in a buffer.
+
+use alloc::collections::VecDeque;
+
+use crate::netflow_common::{icmp_type_code, ipfix_icmp, protocol_number, v9_icmp};
+use crate::static_versions::{v5, v7};
+use crate::variable_versions::data_record::DataRecordExt;
+use crate::variable_versions::ipfix::IPFixFieldPair;
+use crate::variable_versions::ipfix_lookup::IPFixField;
+use crate::variable_versions::v9::V9FieldPair;
+use crate::variable_versions::v9_lookup::V9Field;
+use crate::{NetflowPacket, NetflowParser};
+
+use alloc::{vec, vec::Vec};
+use serde::Serialize;
+
+/// A single flow record.  V9/IPFix records hold their fields in template order.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum FlowRecord {
+    V5(v5::FlowSet),
+    V7(v7::FlowSet),
+    V9(Vec<V9FieldPair>),
+    IPFix(Vec<IPFixFieldPair>),
+}
+
+impl FlowRecord {
+    /// Netflow version of the packet the record came from.
+    pub fn version(&self) -> u16 {
+        match self {
+            FlowRecord::V5(_) => 5,
+            FlowRecord::V7(_) => 7,
+            FlowRecord::V9(_) => 9,
+            FlowRecord::IPFix(_) => 10,
+        }
+    }
+
+    /// ICMP or ICMPv6 type and code of the record, `None` unless it is of an ICMP flow.
+    /// V5/V7 exporters send them in the destination port, V9/IPFix exporters in ICMP fields
+    /// of their own or the destination port.
+    pub fn icmp_type_code(&self) -> Option<(u8, u8)> {
+        match self {
+            FlowRecord::V5(record) => icmp_type_code(record.protocol_number, record.dst_port),
+            FlowRecord::V7(record) => icmp_type_code(record.protocol_number, record.dst_port),
+            FlowRecord::V9(record) => {
+                let get = |field| record.get_value(field);
+                icmp_type_code(protocol_number(get(V9Field::Protocol)?)?, v9_icmp(get)?)
+            }
+            FlowRecord::IPFix(record) => {
+                let get = |field| record.get_value(field);
+                let protocol = protocol_number(get(IPFixField::ProtocolIdentifier)?)?;
+                icmp_type_code(protocol, ipfix_icmp(get)?)
+            }
+        }
+    }
+
+    /// Data records of a packet.  Templates, options templates and options data are skipped.
+    pub(crate) fn from_packet(packet: NetflowPacket) -> Vec<FlowRecord> {
+        match packet {
+            NetflowPacket::V5(v5) => v5.flowsets.into_iter().map(FlowRecord::V5).collect(),
+            NetflowPacket::V7(v7) => v7.flowsets.into_iter().map(FlowRecord::V7).collect(),
+            NetflowPacket::V9(v9) => v9
+                .flowsets
+                .into_iter()
+                .filter_map(|flowset| flowset.body.data)
+                .flat_map(|data| data.data_fields)
+                .map(|record| FlowRecord::V9(record.into_values().collect()))
+                .collect(),
+            NetflowPacket::IPFix(ipfix) => ipfix
+                .flowsets
+                .into_iter()
+                .filter_map(|flowset| flowset.body.data)
+                .flat_map(|data| data.data_fields)
+                .map(|record| FlowRecord::IPFix(record.into_values().collect()))
+                .collect(),
+            NetflowPacket::Error(_) => vec![],
+        }
+    }
+}
+
+/// Iterator returned by [`NetflowParser::iter_flow_records`].  Packets are parsed one at a
+/// time as records are consumed.  Iteration stops at the first packet that can't be parsed,
+/// unless it is of an unknown or unallowed version the parser skips.
+pub struct FlowRecords<'p, 'b> {
+    parser: &'p mut NetflowParser,
+    remaining: &'b [u8],
+    pending: VecDeque<FlowRecord>,
+}
+
+impl<'p, 'b> FlowRecords<'p, 'b> {
+    pub(crate) fn new(parser: &'p mut NetflowParser, buf: &'b [u8]) -> Self {
+        Self {
+            parser,
+            remaining: buf,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl Iterator for FlowRecords<'_, '_> {
+    type Item = FlowRecord;
+
+    fn next(&mut self) -> Option<FlowRecord> {
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                return Some(record);
+            }
+            if self.remaining.is_empty() {
+                return None;
+            }
+            match self
+                .parser
+                .parse_packet_by_version(self.remaining, self.parser.receive_time())
+            {
+                Ok(parsed) => {
+                    let consumed = self.remaining.len() - parsed.remaining.len();
+                    self.remaining = &self.remaining[consumed..];
+                    self.pending.extend(FlowRecord::from_packet(parsed.result));
+                }
+                Err(error) => {
+                    let skipped = self.parser.skip_after(self.remaining, &error);
+                    let skipped = skipped.map_or(self.remaining.len(), |(_, length)| length);
+                    self.remaining = &self.remaining[skipped..];
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod flow_records_tests {
+    use super::FlowRecord;
+    use crate::protocol::ProtocolTypes;
+    use crate::testing::V9_PACKET;
+    use crate::variable_versions::data_number::{DataNumber, FieldValue};
+    use crate::variable_versions::v9_lookup::V9Field;
+    use crate::NetflowParser;
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn it_flattens_records_across_packets() {
+        // V9 template + data flowset followed by a V5 packet with one record.
+        let mut packets = V9_PACKET.to_vec();
+        packets.extend_from_slice(&[
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ]);
+
+        let mut parser = NetflowParser::default();
+        let records: Vec<FlowRecord> = parser.iter_flow_records(&packets).collect();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0],
+            FlowRecord::V9(vec![
+                (
+                    V9Field::InBytes,
+                    FieldValue::DataNumber(DataNumber::U32(151126788))
+                ),
+                (
+                    V9Field::Ipv4SrcAddr,
+                    FieldValue::Ip4Addr(Ipv4Addr::new(9, 9, 9, 8))
+                ),
+            ])
+        );
+        assert_eq!(records[1].version(), 5);
+    }
+
+    #[test]
+    fn it_decodes_icmp_type_and_code_of_records() {
+        let icmp = FlowRecord::V9(vec![
+            (
+                V9Field::Protocol,
+                FieldValue::ProtocolType(ProtocolTypes::Icmp),
+            ),
+            (
+                V9Field::L4DstPort,
+                FieldValue::DataNumber(DataNumber::U16(0x0800)),
+            ),
+        ]);
+        assert_eq!(icmp.icmp_type_code(), Some((8, 0)));
+        let tcp = FlowRecord::V9(vec![
+            (
+                V9Field::Protocol,
+                FieldValue::ProtocolType(ProtocolTypes::Tcp),
+            ),
+            (
+                V9Field::L4DstPort,
+                FieldValue::DataNumber(DataNumber::U16(0x0800)),
+            ),
+        ]);
+        assert_eq!(tcp.icmp_type_code(), None);
+    }
+}
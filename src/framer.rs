@@ -0,0 +1,326 @@
+//! # Framer
+//!
+//! Splits a byte stream into whole netflow messages, for transports that don't preserve
+//! message boundaries such as TCP, files or message buses.  Chunks can end anywhere, messages
+//! split across chunks are buffered until the rest arrives.
+//!
+//! The length of V5, V7 and IPFix messages is known from their header.  V9 headers only
+//! carry a record count, so a V9 message is complete once the bytes after its last flowset
+//! start another message, or when the stream ends and [`MessageFramer::finish`] is called.
+//!
+//! ```rust
+//! use netflow_parser::framer::MessageFramer;
+//! use netflow_parser::NetflowParser;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let mut parser = NetflowParser::default();
+//! let mut framer = MessageFramer::new();
+//!
+//! assert!(framer.feed(&v5_packet[..30], &mut parser).is_empty());
+//! let packets = framer.feed(&v5_packet[30..], &mut parser);
+//! assert!(packets[0].is_v5());
+//! ```
+
+use crate::{NetflowError, NetflowPacket, NetflowPacketError, NetflowParser};
+
+use alloc::vec::Vec;
+
+const V5_HEADER_LENGTH: usize = 24;
+const V5_RECORD_LENGTH: usize = 48;
+const V7_HEADER_LENGTH: usize = 24;
+const V7_RECORD_LENGTH: usize = 52;
+const V9_HEADER_LENGTH: usize = 20;
+const IPFIX_HEADER_LENGTH: usize = 16;
+const SET_HEADER_LENGTH: usize = 4;
+/// Most records exporters put in a V5 or V7 packet.
+const V5_MAX_RECORDS: usize = 30;
+const V7_MAX_RECORDS: usize = 28;
+/// V9 flowset ids from 2 to 255 are reserved, though some vendors use them for records of
+/// their own.  One where a flowset would start may also be the version of the next message.
+pub(crate) const V9_RESERVED_FLOWSET_IDS: core::ops::RangeInclusive<u16> = 2..=255;
+/// Longest message accepted, the largest UDP payload.
+const MAX_MESSAGE_LENGTH: usize = 65_535;
+
+/// Buffers stream chunks and splits them into whole netflow messages.
+#[derive(Debug, Default, Clone)]
+pub struct MessageFramer {
+    buffer: Vec<u8>,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([
+        *bytes.get(offset)?,
+        *bytes.get(offset + 1)?,
+    ]))
+}
+
+/// Whether `bytes` plausibly start with a whole message, going by the record count or
+/// length in its header and the id and length of its first flowset.
+pub(crate) fn plausible_message(bytes: &[u8]) -> bool {
+    let (Some(version), Some(count)) = (read_u16(bytes, 0), read_u16(bytes, 2)) else {
+        return false;
+    };
+    let count = usize::from(count);
+    match version {
+        5 => {
+            (1..=V5_MAX_RECORDS).contains(&count)
+                && V5_HEADER_LENGTH + count * V5_RECORD_LENGTH <= bytes.len()
+        }
+        7 => {
+            (1..=V7_MAX_RECORDS).contains(&count)
+                && V7_HEADER_LENGTH + count * V7_RECORD_LENGTH <= bytes.len()
+        }
+        9 => plausible_set(bytes, V9_HEADER_LENGTH, bytes.len(), |id| {
+            !V9_RESERVED_FLOWSET_IDS.contains(&id)
+        }),
+        // The count of an IPFix header is the message length.
+        10 => {
+            (IPFIX_HEADER_LENGTH..=bytes.len()).contains(&count)
+                && (count == IPFIX_HEADER_LENGTH
+                    || plausible_set(bytes, IPFIX_HEADER_LENGTH, count, |id| {
+                        id == 2 || id == 3 || id > 255
+                    }))
+        }
+        _ => false,
+    }
+}
+
+/// Whether `bytes` start with a message as `plausible_message` judges it, `None` until
+/// enough bytes are buffered to tell.
+fn message_starts(bytes: &[u8]) -> Option<bool> {
+    if plausible_message(bytes) {
+        return Some(true);
+    }
+    let version = read_u16(bytes, 0)?;
+    let count = usize::from(read_u16(bytes, 2)?);
+    let needed = match version {
+        5 if (1..=V5_MAX_RECORDS).contains(&count) => {
+            V5_HEADER_LENGTH + count * V5_RECORD_LENGTH
+        }
+        7 if (1..=V7_MAX_RECORDS).contains(&count) => {
+            V7_HEADER_LENGTH + count * V7_RECORD_LENGTH
+        }
+        9 => {
+            let length = read_u16(bytes, V9_HEADER_LENGTH + 2)?;
+            V9_HEADER_LENGTH + usize::from(length)
+        }
+        10 if count >= IPFIX_HEADER_LENGTH => count,
+        _ => return Some(false),
+    };
+    (bytes.len() >= needed).then_some(false)
+}
+
+/// Whether the flowset at `offset` has a valid id and ends by `end`.
+fn plausible_set(bytes: &[u8], offset: usize, end: usize, valid_id: fn(u16) -> bool) -> bool {
+    let (Some(id), Some(length)) = (read_u16(bytes, offset), read_u16(bytes, offset + 2))
+    else {
+        return false;
+    };
+    let length = usize::from(length);
+    valid_id(id) && length >= SET_HEADER_LENGTH && offset + length <= end
+}
+
+impl MessageFramer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a chunk of the stream.
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// Bytes buffered that aren't a whole message yet.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Takes the next whole message off the buffer, `None` until one is complete.  If the
+    /// buffer doesn't start with a message of a known version, or its length is invalid, an
+    /// error is returned and the buffer is dropped, since the stream can't be split further.
+    pub fn next_message(&mut self) -> Option<Result<Vec<u8>, NetflowError>> {
+        match message_length(&self.buffer) {
+            Ok(Some(length)) => Some(Ok(self.buffer.drain(..length).collect())),
+            Ok(None) => None,
+            Err(e) => {
+                self.buffer.clear();
+                Some(Err(e))
+            }
+        }
+    }
+
+    /// Takes whatever is left at the end of the stream, such as a last V9 message, as a
+    /// message.  `None` if nothing is buffered.
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        (!self.buffer.is_empty()).then(|| core::mem::take(&mut self.buffer))
+    }
+
+    /// Appends a chunk and parses every message it completes with `parser`.  Framing errors
+    /// are returned as `NetflowPacket::Error`.
+    pub fn feed(&mut self, chunk: &[u8], parser: &mut NetflowParser) -> Vec<NetflowPacket> {
+        self.push(chunk);
+        let mut packets = Vec::new();
+        while let Some(message) = self.next_message() {
+            match message {
+                Ok(message) => packets.extend(parser.parse_bytes(&message)),
+                Err(error) => packets.push(NetflowPacket::Error(NetflowPacketError {
+                    error,
+                    remaining: Vec::new(),
+                })),
+            }
+        }
+        packets
+    }
+}
+
+/// Length of the message at the start of `buffer`, `None` if it isn't complete.
+pub(crate) fn message_length(buffer: &[u8]) -> Result<Option<usize>, NetflowError> {
+    let Some(version) = read_u16(buffer, 0) else {
+        return Ok(None);
+    };
+    let length = match version {
+        5 | 7 => {
+            let Some(count) = read_u16(buffer, 2) else {
+                return Ok(None);
+            };
+            let (header, record) = match version {
+                5 => (V5_HEADER_LENGTH, V5_RECORD_LENGTH),
+                _ => (V7_HEADER_LENGTH, V7_RECORD_LENGTH),
+            };
+            header + usize::from(count) * record
+        }
+        9 => return v9_message_length(buffer),
+        10 => {
+            let Some(length) = read_u16(buffer, 2) else {
+                return Ok(None);
+            };
+            let length = usize::from(length);
+            if length < IPFIX_HEADER_LENGTH {
+                return Err(NetflowError::InvalidLength {
+                    version,
+                    declared: length,
+                    available: buffer.len(),
+                });
+            }
+            length
+        }
+        _ => return Err(NetflowError::UnknownVersion(version)),
+    };
+    Ok((buffer.len() >= length).then_some(length))
+}
+
+/// Walks the flowsets of the V9 message at the start of `buffer` up to the start of the next
+/// message.
+fn v9_message_length(buffer: &[u8]) -> Result<Option<usize>, NetflowError> {
+    let mut offset = V9_HEADER_LENGTH;
+    loop {
+        let Some(flowset_id) = read_u16(buffer, offset) else {
+            return Ok(None);
+        };
+        if V9_RESERVED_FLOWSET_IDS.contains(&flowset_id) {
+            // The parser keeps flowsets with reserved ids, so only end the message here if
+            // the next one plausibly starts.
+            match message_starts(&buffer[offset..]) {
+                Some(true) => return Ok(Some(offset)),
+                Some(false) => {}
+                None => return Ok(None),
+            }
+        }
+        let Some(length) = read_u16(buffer, offset + 2) else {
+            return Ok(None);
+        };
+        let length = usize::from(length);
+        if length < 4 || offset + length > MAX_MESSAGE_LENGTH {
+            return Err(NetflowError::InvalidLength {
+                version: 9,
+                declared: offset + length,
+                available: buffer.len(),
+            });
+        }
+        offset += length;
+    }
+}
+
+#[cfg(test)]
+mod framer_tests {
+    use super::MessageFramer;
+    use crate::testing::V9_PACKET;
+    use crate::{NetflowError, NetflowPacket, NetflowParser};
+
+    const V5_PACKET: [u8; 72] = [
+        0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4,
+        5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+        4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+    ];
+    // An empty message of 16 bytes
+    const IPFIX_PACKET: [u8; 16] = [0, 10, 0, 16, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1];
+
+    #[test]
+    fn it_frames_messages_split_across_chunks() {
+        let stream = [&V9_PACKET[..], &V5_PACKET, &IPFIX_PACKET, &V9_PACKET].concat();
+        let mut parser = NetflowParser::default();
+        let mut framer = MessageFramer::new();
+        let mut packets = vec![];
+        for chunk in stream.chunks(7) {
+            packets.extend(framer.feed(chunk, &mut parser));
+        }
+        // The last V9 message only ends with the stream.
+        assert_eq!(framer.buffered(), V9_PACKET.len());
+        packets.extend(parser.parse_bytes(&framer.finish().unwrap()));
+        assert_eq!(framer.finish(), None);
+
+        let versions: Vec<_> = packets
+            .iter()
+            .map(|packet| match packet {
+                NetflowPacket::V5(_) => 5,
+                NetflowPacket::V9(_) => 9,
+                NetflowPacket::IPFix(_) => 10,
+                _ => 0,
+            })
+            .collect();
+        assert_eq!(versions, vec![9, 5, 10, 9]);
+    }
+
+    #[test]
+    fn it_keeps_v9_flowsets_with_reserved_ids_in_the_message() {
+        // A vendor flowset with id 128 between the template and the data flowset
+        let v9_packet = [
+            &V9_PACKET[..36],
+            &[0, 128, 0, 8, 1, 2, 3, 4],
+            &V9_PACKET[36..],
+        ]
+        .concat();
+        let stream = [&v9_packet[..], &V5_PACKET].concat();
+        let mut framer = MessageFramer::new();
+        for chunk in stream.chunks(7) {
+            framer.push(chunk);
+        }
+        assert_eq!(framer.next_message(), Some(Ok(v9_packet)));
+        assert_eq!(framer.next_message(), Some(Ok(V5_PACKET.to_vec())));
+
+        // A flowset with id 5 waits for enough bytes to tell it from a V5 message
+        framer.push(&V9_PACKET[..36]);
+        framer.push(&[0, 5, 0, 1]);
+        assert_eq!(framer.next_message(), None);
+    }
+
+    #[test]
+    fn it_drops_streams_it_cannot_frame() {
+        let mut framer = MessageFramer::new();
+        framer.push(&[0, 11, 0, 0]);
+        assert_eq!(
+            framer.next_message(),
+            Some(Err(NetflowError::UnknownVersion(11)))
+        );
+        assert_eq!(framer.buffered(), 0);
+
+        framer.push(&[0, 10, 0, 4]);
+        assert!(matches!(
+            framer.next_message(),
+            Some(Err(NetflowError::InvalidLength { version: 10, .. }))
+        ));
+        assert_eq!(framer.next_message(), None);
+    }
+}
@@ -0,0 +1,251 @@
+//! # Ingest
+//!
+//! Offline processing of files of netflow messages too large to read into memory.
+//! [`FileIngest`] memory maps the file and parses it lazily, one message at a time, so only
+//! the pages being parsed are resident.  Files are either raw captures of concatenated
+//! messages, split by the lengths in their headers as the `framer` does, or length-prefixed
+//! files written with [`write_length_prefixed`], each message after its length as a 32 bit
+//! big endian integer.
+//!
+//! ```rust,no_run
+//! use netflow_parser::ingest::{FileIngest, IngestFormat};
+//! use netflow_parser::NetflowParser;
+//!
+//! let ingest = FileIngest::open("feed.bin", IngestFormat::Concatenated)?;
+//! let mut parser = NetflowParser::default();
+//! for packet in ingest.packets(&mut parser) {
+//!     println!("{packet:?}");
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::framer::message_length;
+use crate::{NetflowError, NetflowPacket, NetflowPacketError, NetflowParser};
+
+use memmap2::Mmap;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+const LENGTH_PREFIX: usize = 4;
+
+/// How messages are laid out in a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IngestFormat {
+    /// Messages back to back, as received
+    Concatenated,
+    /// Each message after its length, see [`write_length_prefixed`]
+    LengthPrefixed,
+}
+
+/// A memory mapped file of netflow messages.
+#[derive(Debug)]
+pub struct FileIngest {
+    map: Mmap,
+    format: IngestFormat,
+}
+
+impl FileIngest {
+    /// Maps the file at `path`.  The file must not be truncated or written to while it is
+    /// mapped, messages would change under the parser or reads would fault.
+    pub fn open(path: impl AsRef<Path>, format: IngestFormat) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // Safety: the file is only read, and callers are told not to modify it while mapped
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Self { map, format })
+    }
+
+    /// Size of the file in bytes.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The messages of the file, borrowed from the mapping.  Iteration ends at the first
+    /// message that can't be split from the rest, after returning its error.
+    pub fn messages(&self) -> IngestMessages<'_> {
+        IngestMessages {
+            bytes: &self.map,
+            format: self.format,
+        }
+    }
+
+    /// The packets of the file, parsed by `parser` as iteration reaches them.  Framing errors
+    /// are returned as `NetflowPacket::Error`.
+    pub fn packets<'a>(&'a self, parser: &'a mut NetflowParser) -> IngestPackets<'a> {
+        IngestPackets {
+            messages: self.messages(),
+            parser,
+            parsed: VecDeque::new(),
+        }
+    }
+}
+
+/// Iterator over the messages of a [`FileIngest`].
+#[derive(Debug, Clone)]
+pub struct IngestMessages<'a> {
+    /// Bytes not split yet
+    bytes: &'a [u8],
+    format: IngestFormat,
+}
+
+impl<'a> IngestMessages<'a> {
+    /// Length of the next message and of what precedes it.
+    fn next_length(&self) -> Result<(usize, usize), NetflowError> {
+        match self.format {
+            // The last message runs to the end of the file, like V9 messages ending a stream
+            IngestFormat::Concatenated => {
+                Ok((0, message_length(self.bytes)?.unwrap_or(self.bytes.len())))
+            }
+            IngestFormat::LengthPrefixed => {
+                let incomplete = |needed| NetflowError::Incomplete {
+                    version: None,
+                    available: self.bytes.len(),
+                    needed: Some(needed),
+                };
+                let prefix: [u8; LENGTH_PREFIX] = self
+                    .bytes
+                    .get(..LENGTH_PREFIX)
+                    .and_then(|prefix| prefix.try_into().ok())
+                    .ok_or_else(|| incomplete(LENGTH_PREFIX - self.bytes.len()))?;
+                let length = u32::from_be_bytes(prefix) as usize;
+                let available = self.bytes.len() - LENGTH_PREFIX;
+                if length > available {
+                    return Err(incomplete(length - available));
+                }
+                Ok((LENGTH_PREFIX, length))
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for IngestMessages<'a> {
+    type Item = Result<&'a [u8], NetflowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        match self.next_length() {
+            Ok((prefix, length)) => {
+                let (message, rest) = self.bytes[prefix..].split_at(length);
+                self.bytes = rest;
+                Some(Ok(message))
+            }
+            Err(error) => {
+                self.bytes = &[];
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Iterator over the packets of a [`FileIngest`].
+#[derive(Debug)]
+pub struct IngestPackets<'a> {
+    messages: IngestMessages<'a>,
+    parser: &'a mut NetflowParser,
+    /// Packets of the last message parsed, not returned yet
+    parsed: VecDeque<NetflowPacket>,
+}
+
+impl Iterator for IngestPackets<'_> {
+    type Item = NetflowPacket;
+
+    fn next(&mut self) -> Option<NetflowPacket> {
+        loop {
+            if let Some(packet) = self.parsed.pop_front() {
+                return Some(packet);
+            }
+            match self.messages.next()? {
+                Ok(message) => self.parsed.extend(self.parser.parse_bytes(message)),
+                Err(error) => {
+                    return Some(NetflowPacket::Error(NetflowPacketError {
+                        error,
+                        remaining: Vec::new(),
+                    }))
+                }
+            }
+        }
+    }
+}
+
+/// Writes `message` to `writer` in the [`IngestFormat::LengthPrefixed`] format.
+pub fn write_length_prefixed(writer: &mut impl Write, message: &[u8]) -> io::Result<()> {
+    let length = u32::try_from(message.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too long"))?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(message)
+}
+
+#[cfg(test)]
+mod ingest_tests {
+    use super::{write_length_prefixed, FileIngest, IngestFormat};
+    use crate::testing::V9_PACKET;
+    use crate::{NetflowError, NetflowPacket, NetflowParser};
+
+    use std::io::Write;
+
+    const V5_PACKET: [u8; 72] = [
+        0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4,
+        5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+        4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+    ];
+
+    fn ingest(name: &str, bytes: &[u8], format: IngestFormat) -> FileIngest {
+        let path = std::env::temp_dir().join(format!(
+            "netflow_parser_ingest_{name}_{}.bin",
+            std::process::id()
+        ));
+        std::fs::File::create(&path)
+            .and_then(|mut file| file.write_all(bytes))
+            .unwrap();
+        let ingest = FileIngest::open(&path, format).unwrap();
+        // Platforms that can't remove mapped files leave it behind
+        let _ = std::fs::remove_file(&path);
+        ingest
+    }
+
+    #[test]
+    fn it_ingests_concatenated_messages() {
+        let bytes = [&V5_PACKET[..], &V9_PACKET, &V5_PACKET, &V9_PACKET].concat();
+        let ingest = ingest("concatenated", &bytes, IngestFormat::Concatenated);
+        assert_eq!(ingest.len(), bytes.len());
+        let mut parser = NetflowParser::default();
+        let packets: Vec<_> = ingest.packets(&mut parser).collect();
+        assert_eq!(packets.len(), 4);
+        assert!(packets[0].is_v5() && packets[2].is_v5());
+        assert!(packets[1].is_v9() && packets[3].is_v9());
+    }
+
+    #[test]
+    fn it_ingests_length_prefixed_messages() {
+        let mut bytes = vec![];
+        for message in [&V9_PACKET[..], &V5_PACKET] {
+            write_length_prefixed(&mut bytes, message).unwrap();
+        }
+        // A message cut short
+        bytes.extend([0, 0, 0, 72, 0, 5]);
+        let ingest = ingest("length_prefixed", &bytes, IngestFormat::LengthPrefixed);
+        let messages: Vec<_> = ingest.messages().collect();
+        assert_eq!(messages[0], Ok(&V9_PACKET[..]));
+        assert_eq!(messages[1], Ok(&V5_PACKET[..]));
+        assert_eq!(
+            messages[2],
+            Err(NetflowError::Incomplete {
+                version: None,
+                available: 6,
+                needed: Some(70),
+            })
+        );
+
+        let mut parser = NetflowParser::default();
+        let packets: Vec<_> = ingest.packets(&mut parser).collect();
+        assert!(packets[0].is_v9() && packets[1].is_v5());
+        assert!(matches!(packets[2], NetflowPacket::Error(_)));
+    }
+}
@@ -0,0 +1,172 @@
+//! # JSON lines
+//!
+//! Writes flow records as one flat JSON object per line, the layout Logstash and Elastic
+//! pipelines ingest.  Each object holds the fields of the packet header merged with those of
+//! the record, instead of nesting records under their version and flowset.  V9/IPFix fields
+//! are keyed by their serialized name with the bare value, without the `FieldValue` variant
+//! it was parsed as.  A field repeated in a record keeps its last value.
+//! Templates and options data aren't flow records and are left out.  Requires the `json`
+//! feature.
+//!
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let packet = [
+//!     0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0, 2, 0,
+//!     1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
+//! ];
+//! let mut out = Vec::new();
+//! for packet in NetflowParser::default().parse_bytes(&packet) {
+//!     packet.to_json_lines(&mut out).expect("written");
+//! }
+//! let line = String::from_utf8(out).unwrap();
+//! assert!(line.contains(r#""InBytes":151126788,"Ipv4SrcAddr":"9.9.9.8""#));
+//! assert!(line.contains(r#""source_id":1"#));
+//! assert!(line.ends_with('\n'));
+//! ```
+
+use crate::variable_versions::data_number::FieldValue;
+use crate::NetflowPacket;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::io::{self, Write};
+use std::time::Duration;
+
+impl NetflowPacket {
+    /// Flow records of the packet as flat JSON objects with the header fields merged in.
+    /// `Error` packets have none.
+    pub fn json_records(&self) -> Vec<Value> {
+        match self {
+            NetflowPacket::V5(v5) => {
+                let header = object(&v5.header, v5.received_at);
+                v5.flowsets
+                    .iter()
+                    .map(|flowset| flatten(&header, object(flowset, None)))
+                    .collect()
+            }
+            NetflowPacket::V7(v7) => {
+                let header = object(&v7.header, v7.received_at);
+                v7.flowsets
+                    .iter()
+                    .map(|flowset| flatten(&header, object(flowset, None)))
+                    .collect()
+            }
+            NetflowPacket::V9(v9) => {
+                let header = object(&v9.header, v9.received_at);
+                v9.flowsets
+                    .iter()
+                    .filter_map(|flowset| flowset.body.data.as_ref())
+                    .flat_map(|data| data.data_fields.iter())
+                    .map(|record| flatten(&header, named(record)))
+                    .collect()
+            }
+            NetflowPacket::IPFix(ipfix) => {
+                let header = object(&ipfix.header, ipfix.received_at);
+                ipfix
+                    .flowsets
+                    .iter()
+                    .filter_map(|flowset| flowset.body.data.as_ref())
+                    .flat_map(|data| data.data_fields.iter())
+                    .map(|record| flatten(&header, named(record)))
+                    .collect()
+            }
+            NetflowPacket::Error(_) => Vec::new(),
+        }
+    }
+
+    /// Writes the flow records of the packet to `writer`, one JSON object per line.
+    pub fn to_json_lines<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for record in self.json_records() {
+            serde_json::to_writer(&mut writer, &record)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Fields of `value` as a JSON object, with `received_at` when known.
+fn object(value: &impl Serialize, received_at: Option<Duration>) -> Map<String, Value> {
+    let mut object = match serde_json::to_value(value) {
+        Ok(Value::Object(object)) => object,
+        _ => Map::new(),
+    };
+    if let Some(received_at) = received_at.and_then(|time| serde_json::to_value(time).ok()) {
+        object.insert("received_at".to_string(), received_at);
+    }
+    object
+}
+
+fn named<F: Debug>(record: &BTreeMap<usize, (F, FieldValue)>) -> Map<String, Value> {
+    record
+        .values()
+        .map(|(field, value)| (format!("{field:?}"), untagged(value)))
+        .collect()
+}
+
+/// `value` without the variant name it serializes under.
+pub(crate) fn untagged(value: &FieldValue) -> Value {
+    match serde_json::to_value(value) {
+        Ok(Value::Object(tagged)) => tagged
+            .into_iter()
+            .next()
+            .map_or(Value::Null, |(_, value)| value),
+        _ => Value::Null,
+    }
+}
+
+fn flatten(header: &Map<String, Value>, record: Map<String, Value>) -> Value {
+    let mut object = header.clone();
+    object.extend(record);
+    Value::Object(object)
+}
+
+#[cfg(test)]
+mod json_lines_tests {
+    use crate::NetflowParser;
+
+    use serde_json::{json, Value};
+
+    #[test]
+    fn it_writes_one_flat_object_per_record() {
+        let v5_packet = [
+            0, 5, 0, 2, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 0, 1, 2, 4, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9,
+            0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let packets = NetflowParser::default().parse_bytes(&v5_packet);
+        let mut out = Vec::new();
+        packets[0].to_json_lines(&mut out).unwrap();
+
+        let lines: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["version"], json!(5));
+        assert_eq!(lines[0]["flow_sequence"], lines[1]["flow_sequence"]);
+        assert_eq!(lines[0]["src_addr"], json!("0.1.2.3"));
+        assert_eq!(lines[1]["src_addr"], json!("0.1.2.4"));
+        assert!(lines[0].get("flowsets").is_none());
+    }
+
+    #[test]
+    fn it_flattens_ipfix_records() {
+        let packet = [
+            0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2,
+            0, 2, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let records = NetflowParser::default().parse_bytes(&packet)[0].json_records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["observation_domain_id"], json!(16909060));
+        assert_eq!(records[0]["SourceIpv4address"], json!("1.2.3.4"));
+        assert_eq!(records[1]["DestinationIpv4address"], json!("0.1.2.3"));
+    }
+}
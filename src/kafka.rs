@@ -0,0 +1,142 @@
+//! # Kafka
+//!
+//! A [`FlowSink`] producing each flow record of the parsed packets to a Kafka topic as a flat
+//! JSON object, the form of [`NetflowPacket::json_records`].  Sending blocks while the
+//! producer's queue is full, so a slow cluster slows down the pipeline instead of records
+//! piling up in memory.  Requires the `kafka` feature, built on `rdkafka`.
+//!
+//! ```rust,no_run
+//! use netflow_parser::kafka::KafkaSink;
+//! use netflow_parser::pipeline::Pipeline;
+//!
+//! let sink = KafkaSink::new("localhost:9092", "netflow").expect("valid configuration");
+//! let pipeline = Pipeline::builder().with_flow_sink(sink).build().expect("valid configuration");
+//! ```
+
+use crate::pipeline::FlowSink;
+use crate::NetflowPacket;
+
+use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, KafkaResult, RDKafkaErrorCode};
+use rdkafka::producer::{BaseProducer, BaseRecord, Producer};
+
+use std::io;
+use std::time::Duration;
+
+/// How long [`KafkaSink`] waits for the delivery of queued records when the pipeline stops.
+pub const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a send waits for delivery reports to free up room in a full queue before
+/// trying again.
+const QUEUE_FULL_POLL: Duration = Duration::from_millis(100);
+
+/// Produces flow records to a Kafka topic, one message per record.
+pub struct KafkaSink {
+    producer: BaseProducer,
+    topic: String,
+    flush_timeout: Duration,
+}
+
+impl KafkaSink {
+    /// A sink producing to `topic` of the cluster at `brokers`, a comma separated list of
+    /// `host:port` bootstrap servers.
+    pub fn new(brokers: &str, topic: impl Into<String>) -> KafkaResult<Self> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .create()?;
+        Ok(Self::from_producer(producer, topic))
+    }
+
+    /// A sink producing to `topic` with a producer configured by the caller, such as for
+    /// authentication or compression.
+    pub fn from_producer(producer: BaseProducer, topic: impl Into<String>) -> Self {
+        Self {
+            producer,
+            topic: topic.into(),
+            flush_timeout: DEFAULT_FLUSH_TIMEOUT,
+        }
+    }
+
+    /// How long to wait for queued records to be delivered when the pipeline stops,
+    /// [`DEFAULT_FLUSH_TIMEOUT`] unless set.
+    pub fn with_flush_timeout(mut self, timeout: Duration) -> Self {
+        self.flush_timeout = timeout;
+        self
+    }
+
+    /// The underlying producer, such as for its queue length or statistics.
+    pub fn producer(&self) -> &BaseProducer {
+        &self.producer
+    }
+
+    fn produce(&self, payload: &[u8]) -> io::Result<()> {
+        let mut record = BaseRecord::<(), [u8]>::to(&self.topic).payload(payload);
+        loop {
+            match self.producer.send(record) {
+                Ok(()) => return Ok(()),
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                    self.producer.poll(QUEUE_FULL_POLL);
+                    record = returned;
+                }
+                Err((error, _)) => return Err(io::Error::other(error)),
+            }
+        }
+    }
+}
+
+impl FlowSink for KafkaSink {
+    fn send(&mut self, packets: &[NetflowPacket]) -> io::Result<()> {
+        for record in packets.iter().flat_map(NetflowPacket::json_records) {
+            self.produce(&serde_json::to_vec(&record)?)?;
+        }
+        self.producer.poll(Duration::ZERO);
+        Ok(())
+    }
+
+    fn poll(&mut self) -> io::Result<()> {
+        self.producer.poll(Duration::ZERO);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.producer
+            .flush(self.flush_timeout)
+            .map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod kafka_tests {
+    use super::KafkaSink;
+    use crate::pipeline::FlowSink;
+    use crate::NetflowParser;
+
+    use rdkafka::config::ClientConfig;
+    use rdkafka::producer::Producer;
+
+    use std::time::Duration;
+
+    #[test]
+    fn it_queues_a_message_per_record() {
+        // Nothing listens on port 1, so records stay queued in the producer.
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", "127.0.0.1:1")
+            .set("log_level", "0")
+            .create()
+            .unwrap();
+        let mut sink =
+            KafkaSink::from_producer(producer, "netflow").with_flush_timeout(Duration::ZERO);
+        let packet = [
+            0, 5, 0, 2, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9,
+            0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let packets = NetflowParser::default().parse_bytes(&packet);
+        sink.send(&packets).unwrap();
+        // The count includes the producer's own requests, such as for metadata
+        assert!(sink.producer().in_flight_count() >= 2);
+        assert!(sink.flush().is_err());
+    }
+}
@@ -23,6 +23,16 @@
 //! }
 //! ```
 //!
+//! To handle errors with `?` instead of matching `NetflowPacket::Error`, `try_parse_bytes` returns
+//! the first error:
+//! ```rust
+//! use netflow_parser::{NetflowError, NetflowParser};
+//!
+//! fn count_packets(parser: &mut NetflowParser, bytes: &[u8]) -> Result<usize, NetflowError> {
+//!     Ok(parser.try_parse_bytes(bytes)?.len())
+//! }
+//! ```
+//!
 //! ## Want Serialization such as JSON?
 //! Structures fully support serialization.  Below is an example using the serde_json macro:
 //! ```rust
@@ -37,6 +47,78 @@
 //! [{"V5":{"body":{"d_octets":66051,"d_pkts":101124105,"dst_addr":"4.5.6.7","dst_as":515,"dst_mask":5,"dst_port":1029,"first":67438087,"input":515,"last":134807553,"next_hop":"8.9.0.1","output":1029,"pad1":6,"pad2":1543,"protocol":"EGP","src_addr":"0.1.2.3","src_as":1,"src_mask":4,"src_port":515,"tcp_flags":7,"tos":9},"header":{"count":512,"engine_id":7,"engine_type":6,"flow_sequence":33752069,"sampling_interval":2057,"sys_up_time":50332672,"unix_nsecs":134807553,"unix_secs":83887623,"unix_time":{"nanos_since_epoch":134807553,"secs_since_epoch":83887623},"version":5}}}]
 //! ```
 //!
+//! ### JSON lines
+//!
+//! With the `json` feature, `NetflowPacket::to_json_lines` writes one flat JSON object per flow
+//! record with the header fields merged in, as Logstash and Elastic pipelines expect, and
+//! `json_records` returns them as `serde_json::Value`s:
+//! ```json
+//! {"InBytes":151126788,"Ipv4SrcAddr":"9.9.9.8","count":2,"sequence_number":1,"source_id":1,"sys_up_time":2313,"unix_secs":66051,"version":9}
+//! ```
+//!
+//! ### Elastic Common Schema
+//!
+//! Also with the `json` feature, `NetflowCommonFlowSet::to_ecs` and `FlowRecord::to_ecs` convert
+//! flows to documents with ECS field names such as `source.ip`, `destination.port` and
+//! `network.transport`, for indexing into Elasticsearch without a Logstash netflow codec.  Raw
+//! records keep all of their fields under `netflow.*`:
+//! ```json
+//! {"destination":{"ip":"4.5.6.7","port":1029,"as":{"number":515}},"network":{"iana_number":"8","transport":"egp","type":"ipv4"},"source":{"ip":"0.1.2.3","port":515,"as":{"number":1}}}
+//! ```
+//!
+//! ### Protobuf
+//!
+//! With the `proto` feature, `proto::Flow` and `proto::FlowRecord` convert from
+//! `NetflowCommonFlowSet` and raw `FlowRecord`s and encode as protobuf with `encode_to_vec`, a
+//! compact alternative to JSON for gRPC or Kafka.  The schema is `proto/netflow.proto`, also
+//! available as `proto::SCHEMA`, for generating consumers.  `FlowRecord::from_v9_data` and
+//! `from_ipfix_data` take field type numbers and enterprise numbers from the template, keeping
+//! fields the parser doesn't know.
+//!
+//! ### CSV
+//!
+//! With the `csv` feature, `csv::write_csv(&flowsets, writer, &columns)` writes
+//! `NetflowCommonFlowSet`s as CSV with a header row and the `csv::Column`s you pick, for quick
+//! offline analysis of captures.  `write_csv_rows` leaves out the header to append to a file
+//! already started:
+//! ```csv
+//! src_addr,dst_addr,protocol
+//! 0.1.2.3,4.5.6.7,Egp
+//! ```
+//!
+//! ### Versioned output
+//!
+//! Wrap output in `schema::Versioned` to record the `schema_version` it was serialized with, so archived flows can be interpreted after future struct changes.  `schema::schema_changes_since(version)` lists what changed after the version an archive was written with.
+//! ```rust
+//! use serde_json::json;
+//! use netflow_parser::schema::Versioned;
+//! use netflow_parser::NetflowParser;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,];
+//! for packet in NetflowParser::default().parse_bytes(&v5_packet) {
+//!     println!("{}", json!(Versioned::new(&packet)));
+//! }
+//! ```
+//!
+//! ### Interned field names
+//!
+//! V9/IPFix records serialize the name of every field.  `variable_versions::field_names::FieldNames` formats each name once and shares it between records, and can dictionary encode records as `[id, value]` pairs so the names are written once with `dictionary()`.
+//! ```rust
+//! use serde_json::json;
+//! use netflow_parser::flow_records::FlowRecord;
+//! use netflow_parser::variable_versions::field_names::FieldNames;
+//! use netflow_parser::NetflowParser;
+//!
+//! let packet = [0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0, 4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2, 0, 2, 0, 1, 2, 3, 4, 5, 6, 7,];
+//! let mut names = FieldNames::new();
+//! for record in NetflowParser::default().iter_flow_records(&packet) {
+//!     if let FlowRecord::IPFix(fields) = record {
+//!         println!("{}", json!(names.encode(&fields)));
+//!     }
+//! }
+//! println!("{}", json!(names.dictionary()));
+//! ```
+//!
 //! ## Filtering for a specific version
 //!
 //! ```rust
@@ -61,36 +143,165 @@
 //!
 // !This code will return an empty Vec as version 5 is not allowed.
 //!
+//! Or configure it through the builder, which validates the configuration:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let parser = NetflowParser::builder()
+//!     .with_allowed_versions([7, 9])
+//!     .build()
+//!     .expect("valid configuration");
+//! ```
+//!
+//! To keep parsing the packets that follow one of a version that isn't allowed, set
+//! `with_skip_unallowed_versions`.  Unallowed packets are then skipped by the length in their
+//! header, and each is reported as a `NetflowPacket::Error` holding
+//! `NetflowError::UnallowedVersion` and the packet's bytes.
+//!
+//! Bytes of an unknown version, such as junk interleaved between packets by a port-mirrored
+//! feed, have no length to skip by and still stop parsing.  Set `with_skip_unknown_versions` to
+//! search ahead for the next plausible packet header of an allowed version and keep parsing.  Each
+//! skipped span is reported as a `NetflowPacket::Error` holding `NetflowError::SkippedBytes` and
+//! the bytes skipped:
+//! ```rust
+//! use netflow_parser::{NetflowError, NetflowPacket, NetflowParser};
+//!
+//! let junk = [0, 11, 1, 2];
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,];
+//! let mut parser = NetflowParser::builder()
+//!     .with_skip_unknown_versions(true)
+//!     .build()
+//!     .expect("valid configuration");
+//! let packets = parser.parse_bytes(&[&junk[..], &v5_packet].concat());
+//! assert!(matches!(
+//!     &packets[0],
+//!     NetflowPacket::Error(e) if e.error == NetflowError::SkippedBytes { version: 11, length: 4 }
+//! ));
+//! assert!(packets[1].is_v5());
+//! ```
+//!
+//! ## Strict vs. lenient parsing
+//!
+//! By default (`ParseMode::Strict`) every anomaly, such as data for an unknown template, is
+//! surfaced as a `NetflowPacket::Error`.  `ParseMode::Lenient` instead skips V9 and IPFix flowsets
+//! that can't be parsed using their length field and continues with the next flowset.  The skipped
+//! flowset is kept with its `parse_error` set:
+//! ```rust
+//! use netflow_parser::{NetflowParser, ParseMode};
+//!
+//! let parser = NetflowParser::builder()
+//!     .with_parse_mode(ParseMode::Lenient)
+//!     .build()
+//!     .expect("valid configuration");
+//! ```
+//!
+//! ## V5 timestamp quirks
+//!
+//! Some V5 exporters write `unix_nsecs` or `sys_up_time` in nonstandard units or byte order.
+//! `V5TimestampQuirks` normalizes them while parsing, so `unix_nsecs` is in nanoseconds and
+//! `sys_up_time`, `first` and `last` are in milliseconds:
+//! ```rust
+//! use netflow_parser::static_versions::v5::{TimeUnit, V5TimestampQuirks};
+//! use netflow_parser::NetflowParser;
+//!
+//! let parser = NetflowParser::builder()
+//!     .with_v5_timestamp_quirks(V5TimestampQuirks {
+//!         unix_subsec_unit: TimeUnit::Microseconds,
+//!         ..Default::default()
+//!     })
+//!     .build()
+//!     .expect("valid configuration");
+//! ```
+//!
+//! V5 and V7 packets convert their timestamps with `export_time`, `flow_start_time` and
+//! `flow_end_time`, which place a flow's sysuptime-relative `first` and `last` in wall-clock
+//! time, including across the wrap of the uptime counter every ~49.7 days.  The `uptime`
+//! module has the conversions for other sysuptime timestamps, V9 packets the same helpers
+//! for the `FirstSwitched` and `LastSwitched` of their records, and `NetflowCommonFlowSet`
+//! has the wall-clock `start_time` and `end_time` of every flow.
+//! ```rust
+//! use netflow_parser::{NetflowPacket, NetflowParser};
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! if let NetflowPacket::V5(v5) = &NetflowParser::default().parse_bytes(&v5_packet)[0] {
+//!     for flow in v5.flowsets.iter() {
+//!         println!("{:?} to {:?}", v5.flow_start_time(flow), v5.flow_end_time(flow));
+//!     }
+//! }
+//! ```
+//!
 //! ## Netflow Common
 //!
 //! We have included a `NetflowCommon` and `NetflowCommonFlowSet` structure.
 //! This will allow you to use common fields without unpacking values from specific versions.
 //! If the packet flow does not have the matching field it will simply be left as `None`.
+//! Ports and AS numbers are wrapped in the `Port` and `Asn` newtypes from `netflow_parser::types` so they can't be swapped with each other, they display and serialize as plain numbers.
 //!
 //! ### NetflowCommon and NetflowCommonFlowSet Struct:
 //! ```rust
 //! use std::net::IpAddr;
+//! use std::time::Duration;
+//! use netflow_parser::netflow_common::{FlowDirection, TunnelInfo};
 //! use netflow_parser::protocol::ProtocolTypes;
+//! use netflow_parser::types::{Asn, Port};
+//! use netflow_parser::variable_versions::data_number::FieldValue;
 //!
 //! #[derive(Debug, Default)]
 //! pub struct NetflowCommon {
 //!     pub version: u16,
 //!     pub timestamp: u32,
 //!     pub flowsets: Vec<NetflowCommonFlowSet>,
+//!     pub options: Vec<NetflowCommonOptionsRecord>,
 //! }
 //!
 //! #[derive(Debug, Default)]
 //! struct NetflowCommonFlowSet {
 //!     src_addr: Option<IpAddr>,
 //!     dst_addr: Option<IpAddr>,
-//!     src_port: Option<u16>,
-//!     dst_port: Option<u16>,
+//!     src_port: Option<Port>,
+//!     dst_port: Option<Port>,
+//!     icmp_type: Option<u8>,
+//!     icmp_code: Option<u8>,
+//!     src_as: Option<Asn>,
+//!     dst_as: Option<Asn>,
 //!     protocol_number: Option<u8>,
 //!     protocol_type: Option<ProtocolTypes>,
+//!     tcp_flags: Option<u16>,
+//!     tos: Option<u8>,
+//!     next_hop: Option<IpAddr>,
+//!     flow_label: Option<u32>,
+//!     ipv6_next_header: Option<u8>,
+//!     src_vlan: Option<u16>,
+//!     dst_vlan: Option<u16>,
+//!     tunnel: Option<TunnelInfo>,
 //!     first_seen: Option<u32>,
 //!     last_seen: Option<u32>,
+//!     start_time: Option<Duration>,
+//!     end_time: Option<Duration>,
 //!     src_mac: Option<String>,
 //!     dst_mac: Option<String>,
+//!     export_delay: Option<Duration>,
+//!     late: Option<bool>,
+//!     input_interface: Option<u32>,
+//!     output_interface: Option<u32>,
+//!     direction: Option<FlowDirection>,
+//!     bytes: Option<u64>,
+//!     packets: Option<u64>,
+//!     out_bytes: Option<u64>,
+//!     out_packets: Option<u64>,
+//!     sampling_interval: Option<u32>,
+//!     input_interface_name: Option<String>,
+//!     output_interface_name: Option<String>,
+//! }
+//!
+//! /// V9/IPFix options data, such as exporter statistics, sampling settings or interface names,
+//! /// keyed by field name.
+//! #[derive(Debug, Default)]
+//! struct NetflowCommonOptionsRecord {
+//!     scope: Vec<(String, FieldValue)>,
+//!     options: Vec<(String, FieldValue)>,
 //! }
 //! ```
 //!
@@ -125,42 +336,976 @@
 //! let netflow_common_flowsets = NetflowParser::default()
 //!                     .parse_bytes_as_netflow_common_flowsets(&v5_packet);
 //!
-//! println!("Flowsets: {:?}", netflow_common_flowsets);
+//! println!("Flowsets: {:?}", netflow_common_flowsets);
+//! ```
+//!
+//! ### Converting flows one at a time
+//!
+//! `netflow_common::for_each_common_flow` hands each flow of a packet to a closure as it converts it, without collecting a `NetflowCommon` first, for consumers that process flows as a stream.  Options data is skipped.
+//!
+//! ```rust
+//! use netflow_parser::netflow_common::for_each_common_flow;
+//! use netflow_parser::NetflowParser;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let mut flows = 0;
+//! for packet in NetflowParser::default().parse_bytes(&v5_packet) {
+//!     for_each_common_flow(&packet, |flow| {
+//!         println!("Src Addr: {:?}", flow.src_addr);
+//!         flows += 1;
+//!     });
+//! }
+//! assert_eq!(flows, 1);
+//! ```
+//!
+//! ### Flagging late flows
+//!
+//! `export_delay` is the time from the end of a flow to the export of its packet, when the packet carries both.  Set `with_late_flow_threshold` on the builder to have `parse_bytes_as_netflow_common_flowsets` mark flows exported more than the threshold after they ended as `late`, such as flows buffered by an exporter, or run a `LateFlowChecker` over a `NetflowCommon` yourself.
+//!
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//! use std::time::Duration;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let mut parser = NetflowParser::builder()
+//!     .with_late_flow_threshold(Duration::from_secs(60))
+//!     .build()
+//!     .expect("valid configuration");
+//! let flowsets = parser.parse_bytes_as_netflow_common_flowsets(&v5_packet);
+//! assert_eq!(flowsets[0].late, Some(false));
+//! ```
+//!
+//! ### Naming interfaces
+//!
+//! Exporters send the names of their interfaces as options records, mapping each SNMP interface index to an `IfName`/`IfDesc` (V9) or `InterfaceName`/`InterfaceDescription` (IPFix).  Set `with_interface_names` on the builder to learn them per V9 source id or IPFix observation domain and have `parse_bytes_as_netflow_common_flowsets` set the `input_interface_name` and `output_interface_name` of flows, or feed an `InterfaceNameCache` yourself and look names up with `resolve_interface(source, ifindex)`.  The cache keeps up to 65536 interfaces, dropping the least recently learned, which `with_max_interfaces` changes, and `forget_source` drops an exporter's.
+//!
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let mut parser = NetflowParser::builder()
+//!     .with_interface_names(true)
+//!     .build()
+//!     .expect("valid configuration");
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! for flowset in parser.parse_bytes_as_netflow_common_flowsets(&v5_packet) {
+//!     println!("{:?} -> {:?}", flowset.input_interface_name, flowset.output_interface_name);
+//! }
+//! ```
+//!
+//! ### Flow direction and counters
+//!
+//! `direction` is whether a V9/IPFix flow was observed entering or leaving its interface, from the `flowDirection` field.  `bytes` and `packets` count the flow, and exporters reporting both directions of a conversation in one record put the outgoing side in `out_bytes` and `out_packets` (`OutBytes`/`OutPkts` in V9, the RFC 5103 `reverseOctetDeltaCount`/`reversePacketDeltaCount` of enterprise 29305 in IPFix).  Set `with_merged_counters` on the builder to have `parse_bytes_as_netflow_common_flowsets` add them into `bytes` and `packets`, or call `merge_counters` on a flowset yourself.
+//!
+//! Sampled flows carry their `sampling_interval`, from the sampling mode and interval packed into the V5 header, decoded as `sampling_mode` and `sampling_rate`, or from the `samplingInterval` and `samplerRandomInterval` fields of V9/IPFix records.  `scale_by_sampling` multiplies a flowset's counters by it to estimate the traffic sampled.
+//!
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let mut parser = NetflowParser::builder()
+//!     .with_merged_counters(true)
+//!     .build()
+//!     .expect("valid configuration");
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! for flowset in parser.parse_bytes_as_netflow_common_flowsets(&v5_packet) {
+//!     println!("{:?}: {:?} bytes", flowset.direction, flowset.bytes);
+//! }
+//! ```
+//!
+//! ### Enriching flows
+//!
+//! Set an `Enrichment` with `with_enrichment` on the builder to have `parse_bytes_as_netflow_common_flowsets` attach data the exporter doesn't send to every flow, without a second pass.  With the `geoip` feature `MaxMindEnrichment` sets `src_country`/`dst_country`, and the AS numbers exporters left at 0, from GeoIP2/GeoLite2 databases.
+//!
+//! ```rust,ignore
+//! use netflow_parser::enrichment::MaxMindEnrichment;
+//! use netflow_parser::NetflowParser;
+//! use std::sync::Arc;
+//!
+//! let geoip = MaxMindEnrichment::new()
+//!     .with_country_database("GeoLite2-Country.mmdb")?
+//!     .with_asn_database("GeoLite2-ASN.mmdb")?;
+//! let mut parser = NetflowParser::builder()
+//!     .with_enrichment(Arc::new(geoip))
+//!     .build()
+//!     .expect("valid configuration");
+//! ```
+//!
+//! With the `dns` feature a `DnsEnricher` sets `src_hostname`/`dst_hostname` from PTR records, caching names and rate limiting lookups, to 100 a second unless set.  Await its `enrich` on the flows of a packet, or set it with `with_enrichment` to name flows from its cache while misses are resolved in the background of the tokio runtime:
+//!
+//! ```rust,ignore
+//! use netflow_parser::dns::DnsEnricher;
+//! use std::time::Duration;
+//!
+//! let dns = DnsEnricher::default()
+//!     .with_ttl(Duration::from_secs(600))
+//!     .with_rate_limit(100, Duration::from_secs(1));
+//! let mut flowsets = parser.parse_bytes_as_netflow_common_flowsets(&packet);
+//! dns.enrich(&mut flowsets).await;
+//! ```
+//!
+//! ### Anonymizing addresses
+//!
+//! Set an `Anonymizer` with `with_anonymizer` on the builder to rewrite every address of parsed packets before they are returned, so flows can be stored or shared without identifying hosts.  `Anonymizer::truncate` keeps a network prefix of IPv4 and IPv6 addresses, and `Anonymizer::crypto_pan` maps addresses one to one with prefix preserving Crypto-PAn keyed by a 32 byte secret.  Raw bytes kept with `with_keep_raw` are not anonymized.
+//!
+//! ```rust
+//! use netflow_parser::anonymize::Anonymizer;
+//! use netflow_parser::NetflowParser;
+//! use std::net::{IpAddr, Ipv4Addr};
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let mut parser = NetflowParser::builder()
+//!     .with_anonymizer(Anonymizer::crypto_pan([7; 32]))
+//!     .build()
+//!     .expect("valid configuration");
+//! let flowsets = parser.parse_bytes_as_netflow_common_flowsets(&v5_packet);
+//! assert_ne!(flowsets[0].src_addr, Some(IpAddr::V4(Ipv4Addr::new(0, 1, 2, 3))));
+//! ```
+//!
+//! ### Keying flows
+//!
+//! `flow_key` returns the 5-tuple of a `NetflowCommonFlowSet` as a `FlowKey`, for bucketing flows into maps, and `hash_key` a stable FNV-1a hash of it, for sharding flows across workers.  Pass `true` to normalize the key so both directions of a conversation share it.
+//!
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//! use std::collections::HashMap;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let mut flows = HashMap::new();
+//! for flowset in NetflowParser::default().parse_bytes_as_netflow_common_flowsets(&v5_packet) {
+//!     *flows.entry(flowset.flow_key(true)).or_insert(0) += 1;
+//! }
+//! assert_eq!(flows.len(), 1);
+//! ```
+//!
+//! ### Iterating over flow records
+//!
+//! To skip templates and options records and only visit flow records, across every packet in a
+//! buffer, use `iter_flow_records`.  Packets are parsed as the iterator is consumed:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//! use netflow_parser::flow_records::FlowRecord;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,];
+//! let mut parser = NetflowParser::default();
+//! for record in parser.iter_flow_records(&v5_packet) {
+//!     match record {
+//!         FlowRecord::V5(flowset) => println!("{}", flowset.src_addr),
+//!         FlowRecord::V9(fields) => println!("{:?}", fields),
+//!         _ => (),
+//!     }
+//! }
+//! ```
+//!
+//! ### Filtering flow records
+//!
+//! `filter::Filter` parses filter expressions such as `proto==6 && dst_port==443 && bytes>1000`, combining comparisons with `&&`, `||`, `!` and parentheses.  Addresses can be compared to a network, as in `src_addr==10.0.0.0/8`.  Give one to `with_filter` and the records it doesn't match are dropped from every packet parsed.  V9 and IPFix data records are tested as they are decoded, so dropped records are never collected:
+//! ```rust
+//! use netflow_parser::filter::Filter;
+//! use netflow_parser::NetflowParser;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let filter = Filter::parse("proto==6 && dst_port==443 && bytes>1000").expect("valid filter");
+//! let mut parser = NetflowParser::builder()
+//!     .with_filter(filter)
+//!     .build()
+//!     .expect("valid configuration");
+//! assert!(parser.parse_bytes_as_netflow_common_flowsets(&v5_packet).is_empty());
+//! ```
+//!
+//! ### Parsing records into an arena
+//!
+//! With the `perf` feature, `NetflowParser::parse_records(&buffer, &mut arena)` parses every packet of a buffer into an `arena::RecordArena` for collectors handling hundreds of thousands of records a second.  V9 and IPFix data records are decoded straight into one `Vec` of fields per version instead of a map per record, and are read back as `ArenaRecord`s borrowing their fields.  `clear()` keeps the arena's memory, so reusing one arena across buffers parses records without allocating beyond the strings and byte fields they hold.  Filters, projections and anonymizers apply as usual, while records in the arena carry no field warnings.
+//!
+//! ### Batching flow records
+//!
+//! Sinks that write in bulk can group records with `batcher::Batcher`, which calls back with a batch once it holds `max_records` records or its oldest record is `max_age` old.  Call `poll()` periodically so a quiet stream is still flushed.  Remaining records are flushed when the batcher is dropped:
+//! ```rust
+//! use netflow_parser::batcher::Batcher;
+//! use netflow_parser::NetflowParser;
+//! use std::time::Duration;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,];
+//! let mut parser = NetflowParser::default();
+//! let mut batcher = Batcher::new(1000, Some(Duration::from_secs(1)), |batch| {
+//!     println!("writing {} records", batch.len());
+//! });
+//! batcher.extend(parser.iter_flow_records(&v5_packet));
+//! batcher.poll();
+//! ```
+//!
+//! ### Aggregating flows
+//!
+//! With the `aggregation` feature `aggregation::Aggregator` sums the bytes, packets and flows of `NetflowCommonFlowSet`s per 5-tuple, source and destination prefix, or AS pair.  An aggregate is emitted once it has been idle for `idle_timeout`, or open for `active_timeout` so long conversations are reported periodically.  At most 65536 aggregates are kept open, set with `with_max_aggregates`, emitting the least recently added to early to make room.  Call `poll()` periodically to emit idle aggregates, and remaining aggregates are emitted when the aggregator is dropped:
+//! ```rust,ignore
+//! use netflow_parser::aggregation::{AggregateBy, Aggregator};
+//! use netflow_parser::NetflowParser;
+//! use std::time::Duration;
+//!
+//! let mut parser = NetflowParser::default();
+//! let mut aggregator = Aggregator::new(
+//!     AggregateBy::Prefix { ipv4: 24, ipv6: 64 },
+//!     Duration::from_secs(60),
+//!     Duration::from_secs(15),
+//!     |aggregate| println!("{:?}: {} bytes", aggregate.key, aggregate.bytes),
+//! );
+//! aggregator.extend(parser.parse_bytes_as_netflow_common_flowsets(&[]));
+//! aggregator.poll();
+//! ```
+//!
+//! ### Processing pcap captures
+//!
+//! `PcapNetflowReader` reads the netflow packets of a pcap or pcapng capture with a parser per exporter address.  Datagrams with data for templates their exporter hasn't announced yet, as at the start of most captures, are held back and parsed again once it does:
+//! ```rust,no_run
+//! use netflow_parser::pcap::PcapNetflowReader;
+//!
+//! for packet in PcapNetflowReader::open("capture.pcapng").unwrap() {
+//!     let packet = packet.unwrap();
+//!     println!("{:?} {}: {:?}", packet.timestamp, packet.exporter, packet.packet);
+//! }
+//! ```
+//!
+//! `BatchProcessor` parses every UDP payload in a pcap capture with a parser per exporter address,
+//! reporting packet, flow and error counts for each interval of capture time.  Returning
+//! `ControlFlow::Break` from the progress callback cancels processing:
+//! ```rust,no_run
+//! use netflow_parser::batch::BatchProcessor;
+//! use netflow_parser::NetflowParser;
+//!
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! use std::ops::ControlFlow;
+//! use std::time::Duration;
+//!
+//! let capture = BufReader::new(File::open("capture.pcap").unwrap());
+//! let mut processor =
+//!     BatchProcessor::new(NetflowParser::builder(), Duration::from_secs(60)).unwrap();
+//! let summary = processor
+//!     .process_pcap(
+//!         capture,
+//!         |exporter, packet| println!("{exporter}: {packet:?}"),
+//!         |progress| {
+//!             println!("{}: {} flows, {} errors", progress.start.as_secs(), progress.flows, progress.errors);
+//!             ControlFlow::Continue(())
+//!         },
+//!     )
+//!     .unwrap();
+//! println!("{} packets, cancelled: {}", summary.progress.packets, summary.cancelled);
+//! ```
+//!
+//! To summarize a capture in one call (packets per version, exporters, templates seen, top
+//! protocols and ports, error rate and time range) use `analyze::analyze` for a buffer of packets
+//! or `analyze::analyze_pcap` for a pcap or pcapng capture.  The `CaptureSummary` is serializable.
+//!
+//! ### Framing byte streams
+//!
+//! `parse_bytes` expects whole packets, as received from UDP.  For streams that can split a packet anywhere, such as TCP, files or message buses, a `MessageFramer` buffers the chunks and parses each message once it is complete.  V9 headers don't carry a length, so a V9 message is only complete when the next message starts or `finish` is called at the end of the stream:
+//! ```rust
+//! use netflow_parser::framer::MessageFramer;
+//! use netflow_parser::NetflowParser;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let mut parser = NetflowParser::default();
+//! let mut framer = MessageFramer::new();
+//! for chunk in v5_packet.chunks(10) {
+//!     for packet in framer.feed(chunk, &mut parser) {
+//!         println!("{packet:?}");
+//!     }
+//! }
+//! if let Some(last) = framer.finish() {
+//!     println!("{:?}", parser.parse_bytes(&last));
+//! }
+//! ```
+//!
+//! ### Ingesting large files
+//!
+//! With the `mmap` feature `FileIngest` memory maps a file of netflow messages and parses it lazily, so multi-GB recordings can be processed without reading them into memory.  Files are either raw concatenated messages, split by their headers like a `MessageFramer` does, or length-prefixed files written with `write_length_prefixed`:
+//! ```rust,ignore
+//! use netflow_parser::ingest::{FileIngest, IngestFormat};
+//! use netflow_parser::NetflowParser;
+//!
+//! let ingest = FileIngest::open("feed.bin", IngestFormat::Concatenated)?;
+//! let mut parser = NetflowParser::default();
+//! for packet in ingest.packets(&mut parser) {
+//!     println!("{packet:?}");
+//! }
+//! ```
+//!
+//! ### Recording and replaying feeds
+//!
+//! `ArchiveWriter` records raw messages with the time they were received and their source address, and `ArchiveReader` reads them back.  `ArchiveReplay` parses a recording again with a parser per exporter, at the original pace, faster, or unpaced, which is handy for regression testing a collector against a captured feed:
+//! ```rust,ignore
+//! use netflow_parser::archive::{ArchiveReader, ArchiveReplay, ReplaySpeed};
+//! use netflow_parser::NetflowParser;
+//! use std::fs::File;
+//! use std::io::BufReader;
+//!
+//! let reader = ArchiveReader::new(BufReader::new(File::open("feed.nfar")?))?;
+//! let mut replay = ArchiveReplay::new(reader, NetflowParser::builder())?
+//!     .with_speed(ReplaySpeed::Accelerated(10.0));
+//! while let Some((record, packets)) = replay.next_packets()? {
+//!     println!("{:?}: {packets:?}", record.source);
+//! }
+//! ```
+//!
+//! ### Collector pipeline
+//!
+//! `Pipeline` assembles a collector from a listener, a parser per exporter address, filters,
+//! enrichers and sinks.  Records that pass every filter are enriched in order and written to every
+//! sink.  Closures, `mpsc::Sender`s and `Batcher`s can be used as sinks.  Sinks are polled
+//! whenever the socket's read timeout expires, so batches are flushed once they are old enough:
+//! ```rust,no_run
+//! use netflow_parser::pipeline::{Pipeline, PipelineRecord};
+//! use netflow_parser::NetflowParser;
+//! use std::net::UdpSocket;
+//! use std::time::Duration;
+//!
+//! let mut socket = UdpSocket::bind("0.0.0.0:2055").unwrap();
+//! socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+//!
+//! let mut pipeline = Pipeline::builder()
+//!     .with_parser(NetflowParser::builder().with_scoped_templates(true))
+//!     .with_filter(|record: &PipelineRecord| record.record.version() >= 9)
+//!     .with_enricher(|record: &mut PipelineRecord| {
+//!         record.tags.insert("site".to_string(), "dc1".to_string());
+//!     })
+//!     .with_sink(|record: &PipelineRecord| {
+//!         println!("{} {:?}", record.exporter, record.record);
+//!         Ok(())
+//!     })
+//!     .build()
+//!     .expect("valid configuration");
+//! pipeline.run(&mut socket).unwrap();
+//! ```
+//! Datagrams from other sources, such as an async socket, can be passed to `Pipeline::process`.
+//!
+//! Exporters that need a configuration of their own, such as only allowing IPFix with a larger
+//! memory budget, get one with `PipelineBuilder::with_source_config(ip, builder)`.  The other
+//! exporters keep using the builder given to `with_parser`.  `Pipeline::set_source_config` changes
+//! an exporter's configuration while running, rebuilding its parser.
+//!
+//! Parsers of exporters that go away are dropped with their templates: those idle for longer than
+//! `with_exporter_ttl`, and the least recently seen once more than `with_max_exporters` (4096
+//! unless set) have been seen.  `Pipeline::evicted_exporters` counts them.
+//!
+//! To ship packets to a message bus, implement `pipeline::FlowSink` over its producer and add
+//! it with `with_flow_sink`.  With the `kafka` feature, `kafka::KafkaSink` is a flow sink
+//! producing each flow record to a Kafka topic as a JSON object.  A flow sink gets every packet parsed from each
+//! datagram, before the record filters, and should block while the bus is full so backpressure
+//! reaches the socket.  A `SyncSender<Vec<NetflowPacket>>` is a flow sink that blocks once its
+//! bounded channel is full, for producing from another thread:
+//! ```rust
+//! use netflow_parser::pipeline::Pipeline;
+//! use netflow_parser::NetflowPacket;
+//! use std::sync::mpsc;
+//!
+//! let (tx, rx) = mpsc::sync_channel::<Vec<NetflowPacket>>(1024);
+//! let pipeline = Pipeline::builder().with_flow_sink(tx).build().expect("valid configuration");
+//! std::thread::spawn(move || {
+//!     for packets in rx {
+//!         // producer.send(packets)
+//!     }
+//! });
+//! ```
+//!
+//! ### Async streams
+//!
+//! With the `tokio` feature, `NetflowParserBuilder::stream_packets(socket)` turns a
+//! `tokio::net::UdpSocket` into a `Stream` of `NetflowPacket`s, with a parser per exporter
+//! address like the pipeline, dropping those of idle exporters with `with_exporter_ttl` and of
+//! the least recently seen past `with_max_exporters` (4096 unless set).  The stream ends if
+//! receiving fails, leaving the error in `PacketStream::error()`.  `stream::NetflowCodec` is a `tokio_util::codec::Decoder` of
+//! packets, framing messages split across reads for `FramedRead` over TCP or files.
+//!
+//! ## Re-Exporting flows
+//! Netflow Parser now supports parsed V5, V7, V9, IPFix can be re-exported back into bytes.
+//! ```rust
+//! use netflow_parser::{NetflowParser, NetflowPacket};
+//!
+//! let packet = [
+//!     0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+//! ];
+//! if let NetflowPacket::V5(v5) = NetflowParser::default()
+//!     .parse_bytes(&packet)
+//!     .first()
+//!     .unwrap()
+//! {
+//!     assert_eq!(v5.to_be_bytes(), packet);
+//! }
+//! ```
+//!
+//! To re-export a list of mixed version packets in order use `export_all`.  It returns a result
+//! per packet, with an error for `Error` packets and packets that can't be exported:
+//! ```rust
+//! use netflow_parser::{export_all, NetflowParser};
+//!
+//! let packet = [
+//!     0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+//! ];
+//! let export = export_all(&NetflowParser::default().parse_bytes(&packet));
+//! assert_eq!(export, vec![Ok(packet.to_vec())]);
+//! ```
+//!
+//! `to_be_bytes` writes the header and set lengths that were parsed.  After adding or removing
+//! flowsets or records, use `to_be_bytes_recalculated` on `V9` or `IPFix` to recompute the
+//! record count, message length and set lengths from the contents:
+//! ```rust
+//! use netflow_parser::{NetflowPacket, NetflowParser};
+//!
+//! let packet = [
+//!     0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0, 4, 0,
+//!     12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2, 0, 2, 0, 1,
+//!     2, 3, 4, 5, 6, 7,
+//! ];
+//! if let Some(NetflowPacket::IPFix(mut ipfix)) = NetflowParser::default().parse_bytes(&packet).pop() {
+//!     ipfix.flowsets[1].body.data.as_mut().unwrap().data_fields.pop();
+//!     assert_eq!(ipfix.to_be_bytes_recalculated().unwrap().len(), 52);
+//! }
+//! ```
+//!
+//! ### Redacting fields
+//!
+//! Before sharing captures or re-exported flows, scrub packets with a `Redactor`.  It zeroes configured V9 and IPFix fields, MAC addresses, captured packet sections and addresses in internal networks of data and options records, keeping every record the length of its template so `to_be_bytes` still writes valid packets.
+//!
+//! ```rust
+//! use netflow_parser::redact::Redactor;
+//! use netflow_parser::variable_versions::ipfix_lookup::IPFixField;
+//! use std::net::{IpAddr, Ipv4Addr};
+//!
+//! let redactor = Redactor::default()
+//!     .with_ipfix_field(IPFixField::InterfaceName)
+//!     .with_mac_addresses()
+//!     .with_packet_sections()
+//!     .with_network(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8);
+//! ```
+//!
+//! ### Building V5 and V7 packets
+//!
+//! `V5Builder` and `V7Builder` build packets from flows, for test fixtures or simulated
+//! exporters.  The header count and `sys_up_time` are filled in, and the flow sequence carries on
+//! from one `build` to the next:
+//! ```rust
+//! use netflow_parser::static_versions::v5::{FlowSet, V5Builder};
+//! use std::net::Ipv4Addr;
+//!
+//! let mut builder = V5Builder::new().with_engine(1, 2);
+//! builder.add_flow(FlowSet {
+//!     src_addr: Ipv4Addr::new(192, 0, 2, 1),
+//!     dst_addr: Ipv4Addr::new(198, 51, 100, 1),
+//!     protocol_number: 17,
+//!     d_pkts: 1,
+//!     d_octets: 64,
+//!     ..Default::default()
+//! });
+//! let packets: Vec<Vec<u8>> = builder.build_bytes();
+//! ```
+//!
+//! ### Synthesizing test traffic
+//!
+//! `testing::PacketSynthesizer` generates valid V5, V9 and IPFix streams with realistic field
+//! values from a seed, templates first, and can break packets to fuzz a collector:
+//! ```rust
+//! use netflow_parser::testing::{Malformation, PacketSynthesizer};
+//!
+//! let mut synthesizer = PacketSynthesizer::new(42).with_flows_per_packet(20);
+//! let v9_stream = synthesizer.v9_packets(100);
+//! let malformed = synthesizer.malform(&v9_stream[0], Malformation::Corrupted);
+//! ```
+//!
+//! ### Mediating IPFix
+//!
+//! `mediation::Mediator` rewrites parsed IPFix messages before forwarding them, dropping, renaming
+//! or adding fields.  Templates are re-announced under new ids and lengths and sequence numbers
+//! are recomputed:
+//! ```rust
+//! use netflow_parser::mediation::{FieldTransform, Mediator};
+//! use netflow_parser::variable_versions::ipfix_lookup::IPFixField;
+//! use netflow_parser::{NetflowPacket, NetflowParser};
+//!
+//! let mut mediator = Mediator::builder()
+//!     .with_transform(FieldTransform::Drop(IPFixField::SourceIpv4address))
+//!     .with_transform(FieldTransform::Rename {
+//!         from: IPFixField::PacketDeltaCount,
+//!         to: IPFixField::PacketTotalCount,
+//!     })
+//!     .build()
+//!     .expect("valid transforms");
+//!
+//! let mut parser = NetflowParser::default();
+//! for packet in parser.parse_bytes(&[]) {
+//!     if let NetflowPacket::IPFix(ipfix) = packet {
+//!         let bytes = mediator.mediate(&ipfix).and_then(|ipfix| ipfix.to_be_bytes());
+//!     }
+//! }
+//! ```
+//!
+//! ### Converting V5, V7 and V9 to IPFix
+//!
+//! `IPFix::try_from(&V9)` converts a V9 packet's templates and data to IPFix, so legacy exporters
+//! can share an IPFix pipeline.  V9 field types map to the IANA information elements of the same
+//! number, and Cisco's vendor field types to Cisco enterprise fields.  `IPFix::try_from(&V5)` and
+//! `IPFix::try_from(&V7)` write legacy records with the fixed `convert::legacy_template()` of
+//! standard information elements.  Converted V9 messages keep the V9 sequence number, which
+//! counts packets rather than the data records IPFix counts; `convert::V9Converter` numbers the
+//! messages of a stream by their records per observation domain instead:
+//! ```rust
+//! use netflow_parser::variable_versions::ipfix::IPFix;
+//! use netflow_parser::{NetflowPacket, NetflowParser};
+//!
+//! let mut parser = NetflowParser::default();
+//! for packet in parser.parse_bytes(&[]) {
+//!     let ipfix = match &packet {
+//!         NetflowPacket::V5(v5) => IPFix::try_from(v5),
+//!         NetflowPacket::V7(v7) => IPFix::try_from(v7),
+//!         NetflowPacket::V9(v9) => IPFix::try_from(v9),
+//!         _ => continue,
+//!     };
+//!     let bytes = ipfix.and_then(|ipfix| ipfix.to_be_bytes());
+//! }
+//! ```
+//!
+//! ### Verifying round trips
+//!
+//! `verify_roundtrip` parses packets and re-exports them, reporting the first byte where each
+//! export differs from the original and the flowset, record and field it belongs to:
+//! ```rust
+//! use netflow_parser::roundtrip::RoundtripLocation;
+//! use netflow_parser::NetflowParser;
+//!
+//! let packet = [
+//!     0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0, 2, 0, 1,
+//!     0, 4, 0, 8, 0, 4, 1, 2, 0, 16, 9, 2, 3, 4, 9, 9, 9, 8, 0, 0, 0, 0,
+//! ];
+//! let report = NetflowParser::default().verify_roundtrip(&packet);
+//! // Padding after the data record isn't exported
+//! assert_eq!(
+//!     report.divergences[0].location,
+//!     RoundtripLocation::FlowSet { index: 1, offset: 12 }
+//! );
+//! ```
+//!
+//! ### Replaying to a collector
+//!
+//! With the `replay` feature `Replayer` re-sends parsed packets, or a whole `ArchiveReplay` at its pace, to a collector over UDP.  V9 source ids and IPFix observation domain ids can be rewritten, and templates learned with `with_templates_from`, the scoped templates of a parser for their own source and its shared templates for every source, are announced ahead of the first data that uses them, so a recording that starts mid-stream still decodes downstream.  They are announced again every 30 minutes or 20 messages, in case a datagram was lost or the collector restarted, which `with_template_refresh_interval` and `with_template_refresh_packets` change:
+//! ```rust,ignore
+//! use netflow_parser::replay::Replayer;
+//! use std::net::UdpSocket;
+//!
+//! let mut replayer = Replayer::new(UdpSocket::bind("0.0.0.0:0")?, "192.0.2.10:2055".parse()?)
+//!     .with_source_id(42)
+//!     .with_templates_from(&parser);
+//! for packet in &packets {
+//!     replayer.send(packet)?;
+//! }
+//! ```
+//!
+//! ## Sharing a parser across threads
+//!
+//! `shared::SharedNetflowParser` can be cloned into many threads or tokio tasks, such as one per socket, and parses packets concurrently.  Every call parses with a pooled parser of its own, while V9/IPFix templates learned by any of them are published to a template cache behind a `RwLock`, so threads don't each have to wait for templates to be resent.  With scoped templates, packets are sharded by their `source_id` or `observation_domain_id`, each shard with its own pool and cache, so parsers only hold the templates of their shard's sources.
+//!
+//! ```rust
+//! use netflow_parser::shared::SharedNetflowParser;
+//! use netflow_parser::NetflowParser;
+//! use std::thread;
+//!
+//! let parser = SharedNetflowParser::new(NetflowParser::builder().with_scoped_templates(true))
+//!     .expect("valid configuration");
+//! let handles: Vec<_> = (0..4)
+//!     .map(|_| {
+//!         let parser = parser.clone();
+//!         thread::spawn(move || parser.parse_bytes(&[0, 5, 0, 1]))
+//!     })
+//!     .collect();
+//! for handle in handles {
+//!     handle.join().unwrap();
+//! }
+//! ```
+//!
+//! ## Metrics
+//!
+//! Every `NetflowParser` counts parsed packets per version, parse errors and bytes processed.
+//! `metrics_snapshot` adds template totals and can be rendered for Prometheus.  For a parser per
+//! exporter, `BatchProcessor::metrics_snapshots` labels each one with its exporter address:
+//! ```rust
+//! use netflow_parser::metrics::render_prometheus;
+//! use netflow_parser::NetflowParser;
+//!
+//! let mut parser = NetflowParser::default();
+//! parser.parse_bytes(&[0, 9, 0, 0]);
+//! let snapshot = parser.metrics_snapshot();
+//! println!("{}", snapshot.to_prometheus());
+//! println!("{}", render_prometheus(&[(vec![("exporter".into(), "10.0.0.1".into())], snapshot)]));
+//! ```
+//!
+//! Packets parsed with `parse_bytes_received_at`, or with `with_receive_time_capture` set on the
+//! builder, carry a `received_at` time.  `NetflowPacket::processing_delay` compares it to the
+//! export time in the header, and the metrics keep the total and longest delay so pipeline latency
+//! can be watched per exporter.  `BatchProcessor` uses the pcap timestamps as receive times.
+//!
+//! With `with_packet_digests` set on the builder, packets carry a `digest` of the source id and
+//! sequence number in their header and an FNV-1a hash of their wire bytes, so exactly-once pipelines
+//! can drop datagrams that were retransmitted or replayed.  See the `digest` module.
+//!
+//! ## V9/IPFix notes:
+//!
+//! Parse the data (`&[u8]` as any other versions.  The parser (NetflowParser) holds onto already parsed templates, so you can just send a header/data flowset combo, and it will use the cached templates.)   To see cached templates simply use the parser for the correct version (v9_parser for v9, ipfix_parser for IPFix.)
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//! let parser = NetflowParser::default();
+//! dbg!(parser.v9_parser.engine.templates);
+//! dbg!(parser.v9_parser.engine.options_templates);
+//! ```
+//!
+//! For dashboards, `get_v9_template` and `get_ipfix_template` return a serializable
+//! `TemplateSummary` of a cached template, with the name, number, length and enterprise number of
+//! each field.  `template_summaries` on `v9_parser` and `ipfix_parser` lists every cached template,
+//! including the scoped ones:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let parser = NetflowParser::default();
+//! if let Some(summary) = parser.get_ipfix_template(256) {
+//!     println!("{summary:?}");
+//! }
+//! for summary in parser.v9_parser.engine.template_summaries() {
+//!     println!("{:?} {} {:?}", summary.scope, summary.template_id, summary.fields);
+//! }
+//! ```
+//!
+//! `validate_templates` checks the cached templates for definitions that would otherwise show up
+//! as data silently parsed wrong: zero length or duplicate fields, records of zero bytes, options
+//! template scopes that don't match their descriptors and IPFix enterprise fields with no
+//! registered decoder.  Each issue found is reported with the template it was found in:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let parser = NetflowParser::default();
+//! let report = parser.validate_templates();
+//! for finding in report.findings {
+//!     eprintln!("V{} template {}: {:?}", finding.version, finding.template_id, finding.issue);
+//! }
+//! ```
+//! To access templates flowset of a processed V9/IPFix flowset you can find the `flowsets` attribute on the Parsed Record.  In there you can find `Templates`, `Option Templates`, and `Data` Flowsets.
+//!
+//! Templates are shared by every `source_id` (V9) or `observation_domain_id` (IPFix) seen by a parser.
+//! If an exporter reuses template ids across them, cache templates per id instead.  The cached
+//! templates can then be found in `scoped_templates` and `scoped_options_templates`:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let parser = NetflowParser::builder()
+//!     .with_scoped_templates(true)
+//!     .build()
+//!     .expect("valid configuration");
+//! ```
+//!
+//! As exporters come and go, scoped caches of sources that went quiet can be dropped.  Sources not
+//! seen for longer than `with_source_ttl` are evicted when a new one shows up or when
+//! `evict_idle_sources` is called, and `with_max_sources` evicts the least recently seen source
+//! once there are too many:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//! use std::time::Duration;
+//!
+//! let mut parser = NetflowParser::builder()
+//!     .with_scoped_templates(true)
+//!     .with_source_ttl(Duration::from_secs(30 * 60))
+//!     .with_max_sources(1_000)
+//!     .build()
+//!     .expect("valid configuration");
+//! let evicted = parser.evict_idle_sources();
+//! ```
+//!
+//! Source ttls, missing template alerts and the template rate limit go by local time.  When
+//! replaying a capture faster than it was recorded, or when the collector may pause,
+//! `with_export_time_clock(true)` times them by the export time in the packet headers instead.
+//! The clock only moves forward, so packets exported out of order don't turn it back.  Each
+//! source moves it by as much as its own export time moved since its first packet, so exporters
+//! with skewed clocks don't age the others, and packets that fail to parse don't move it:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//! use std::time::Duration;
+//!
+//! let parser = NetflowParser::builder()
+//!     .with_scoped_templates(true)
+//!     .with_source_ttl(Duration::from_secs(30 * 60))
+//!     .with_export_time_clock(true)
+//!     .build()
+//!     .expect("valid configuration");
+//! ```
+//!
+//! With `with_source_stats`, parsers count the messages, data records and options data records
+//! they parsed per source in `source_stats`, whether or not templates are scoped.
+//! `V9Parser::source_ids` and `IPFixParser::observation_domains` list the sources seen so far.
+//! Counts are kept for every source unless sources are bounded with `with_max_sources` or
+//! `with_source_ttl`, and evicted sources lose their counts:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let parser = NetflowParser::builder()
+//!     .with_source_stats(true)
+//!     .build()
+//!     .expect("valid configuration");
+//! for domain in parser.ipfix_parser.observation_domains() {
+//!     println!("{domain}: {:?}", parser.ipfix_parser.engine.source_stats[&domain]);
+//! }
+//! ```
+//!
+//! To hot-restart a collector without re-learning templates, export every cached template
+//! (including the scoped ones) and import them into the new parser.  Snapshots can be serialized
+//! with serde:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let parser = NetflowParser::default();
+//! let v9_templates = parser.v9_parser.engine.export_all_templates();
+//! let ipfix_templates = parser.ipfix_parser.engine.export_all_templates();
+//!
+//! let mut restarted = NetflowParser::default();
+//! restarted.v9_parser.engine.import_all_templates(v9_templates);
+//! restarted.ipfix_parser.engine.import_all_templates(ipfix_templates);
+//! ```
+//!
+//! A relay can re-announce the templates it has cached downstream.  `templates_to_flowset` builds
+//! the V9 template flowsets of a source id and `templates_to_message` the IPFix template message
+//! of an observation domain.  `diff` between two snapshots gives the templates added or redefined
+//! since the first:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let parser = NetflowParser::default();
+//! let announced = parser.ipfix_parser.engine.export_all_templates();
+//! let changed = announced.diff(&parser.ipfix_parser.engine.export_all_templates());
+//! assert!(changed.is_empty());
+//!
+//! let mut message = parser.ipfix_parser.templates_to_message(0).expect("fits in a message");
+//! message.header.sequence_number = 1;
+//! let bytes = message.to_be_bytes().expect("templates can be exported");
+//! let v9_flowsets = parser.v9_parser.templates_to_flowset(0).expect("fits in a flowset");
+//! ```
+//!
+//! Fields of a V9/IPFix data record can be read as typed values with the `DataRecordExt` trait,
+//! instead of matching on `FieldValue`.  Getters return `None` if the field is missing or of
+//! another type:
+//! ```rust
+//! use netflow_parser::variable_versions::data_record::DataRecordExt;
+//! use netflow_parser::variable_versions::v9_lookup::V9Field;
+//! use netflow_parser::{NetflowPacket, NetflowParser};
+//!
+//! let packet = [
+//!     0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0, 2, 0,
+//!     1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
+//! ];
+//! if let Some(NetflowPacket::V9(v9)) = NetflowParser::default().parse_bytes(&packet).pop() {
+//!     for data in v9.flowsets.iter().filter_map(|flowset| flowset.body.data.as_ref()) {
+//!         for record in data.data_fields.iter() {
+//!             println!("{:?} {:?}", record.get_ip(V9Field::Ipv4SrcAddr), record.get_u64(V9Field::InBytes));
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! When an exporter re-announces a template id with a different layout the cached template is
+//! replaced.  To be notified, record template events and drain them after parsing, or register a
+//! `TemplateObserver` on `v9_parser.engine.template_observer` / `ipfix_parser.engine.template_observer`:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//! use netflow_parser::variable_versions::TemplateEvent;
+//!
+//! let mut parser = NetflowParser::builder()
+//!     .with_template_events(true)
+//!     .build()
+//!     .expect("valid configuration");
+//! for event in parser.v9_parser.engine.drain_template_events() {
+//!     if let TemplateEvent::Redefined { template_id, .. } = event {
+//!         println!("redefined {template_id}");
+//!     }
+//! }
+//! ```
+//!
+//! NetFlow has no in-band way to request templates, so data for a template that was never
+//! received is dropped until the exporter resends it.  To trigger out of band re-requests, track
+//! dropped data and get `TemplateObserver::on_missing_template` called with the source, template
+//! id and drop count once it has been missing for longer than a threshold:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//! use std::time::Duration;
+//!
+//! let parser = NetflowParser::builder()
+//!     .with_missing_template_alert(Duration::from_secs(60))
+//!     .build()
+//!     .expect("valid configuration");
+//! ```
+//!
+//! IPFix fields from a vendor's private enterprise number (PEN), such as Cisco AVC or Palo Alto
+//! metadata, are parsed as unsigned numbers when their length allows and kept as
+//! `FieldValue::Unknown` otherwise.  Register a decoder per `(PEN, field id)` to decode them into
+//! any `FieldValue`, such as a `FieldValue::Custom`:
+//! ```rust
+//! use netflow_parser::variable_versions::data_number::{CustomValue, FieldValue};
+//! use netflow_parser::variable_versions::enterprise::EnterpriseFieldRegistry;
+//! use netflow_parser::NetflowParser;
+//!
+//! let registry = EnterpriseFieldRegistry::new().with_decoder(9, 12235, |bytes: &[u8]| {
+//!     Some(FieldValue::Custom(CustomValue::Bytes(bytes.to_vec()).into()))
+//! });
+//! let parser = NetflowParser::builder()
+//!     .with_enterprise_fields(registry)
+//!     .build()
+//!     .expect("valid configuration");
+//! ```
+//!
+//! IPFix options templates list their scope fields, which identify what a record describes, before
+//! the fields describing it.  `OptionsTemplate::scope_fields` and `options_fields` split the fields of
+//! a template, and `OptionsData::records` splits every options data record the same way, like V9
+//! options data keeps its `scope_fields` and `options_fields` apart.
+//!
+//! Cisco AVC / NBAR2 exporters classify flows into an IPFix `ApplicationId` (V9
+//! `ApplicationTag`) of a classification engine id and selector.  `get_application_id` decodes it
+//! and an `ApplicationRegistry` resolves it to a name.  It knows common IANA-L3 protocols and
+//! IANA-L4 ports, NBAR2 names can be loaded from a protocol pack mapping or learned from the
+//! application table exporters send as options data:
+//! ```rust
+//! use netflow_parser::variable_versions::application::{ApplicationId, ApplicationRegistry};
+//! use netflow_parser::variable_versions::data_number::FieldValue;
+//! use netflow_parser::variable_versions::ipfix_lookup::IPFixField;
+//!
+//! let mut registry = ApplicationRegistry::well_known();
+//! registry.load_mappings("13:453 webex-meeting\n13:454 ms-teams").unwrap();
+//! assert_eq!(registry.resolve(&ApplicationId::new(3, 443)), Some("https"));
+//!
+//! // An options data record of an exporter's application table
+//! let record = [
+//!     (IPFixField::ApplicationId, FieldValue::Vec(vec![13, 0, 0, 0, 7])),
+//!     (IPFixField::ApplicationName, FieldValue::String("dns".into())),
+//! ];
+//! registry.learn(&record[..], IPFixField::ApplicationId, IPFixField::ApplicationName);
+//! assert_eq!(registry.resolve(&ApplicationId::new(13, 7)), Some("dns"));
+//! ```
+//!
+//! `String` fields, such as interface names and URLs, are decoded as lossy UTF-8 by default.
+//! `with_string_decoding` can instead trim their trailing NUL padding (`StringDecoding::TrimNul`),
+//! keep fields that aren't valid UTF-8 as `FieldValue::Unknown` (`StringDecoding::Strict`) or keep
+//! them as raw bytes (`StringDecoding::Raw`):
+//! ```rust
+//! use netflow_parser::variable_versions::data_number::StringDecoding;
+//! use netflow_parser::NetflowParser;
+//!
+//! let parser = NetflowParser::builder()
+//!     .with_string_decoding(StringDecoding::TrimNul)
+//!     .build()
+//!     .expect("valid configuration");
+//! ```
+//!
+//! A corrupt or hostile exporter can declare huge field lengths or pack a message with thousands of
+//! tiny sets and records.  `with_max_field_length`, `with_max_records_per_set` and
+//! `with_max_sets_per_message` bound what a V9 or IPFix message may hold, a message over a limit is
+//! rejected with `NetflowError::LimitExceeded` before its records are parsed:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let parser = NetflowParser::builder()
+//!     .with_max_field_length(1_024)
+//!     .with_max_records_per_set(4_096)
+//!     .with_max_sets_per_message(64)
+//!     .build()
+//!     .expect("valid configuration");
+//! ```
+//!
+//! Templates declaring an absurd number of fields are rejected the same way, before their fields
+//! are parsed, with `with_v9_max_field_count` and `with_ipfix_max_field_count`.
+//!
+//! `memory_usage` estimates the memory a parser holds in its template caches and pending template
+//! events, to size collectors serving many exporters.  `with_max_memory` caps the bytes of the
+//! template caches, evicting the least recently used templates, recorded as
+//! `TemplateEvent::Evicted`, once a message leaves the parser over budget.  Pending events aren't
+//! evicted and don't count towards the budget; drain them with `drain_template_events`:
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let parser = NetflowParser::builder()
+//!     .with_scoped_templates(true)
+//!     .with_max_memory(64 * 1024 * 1024)
+//!     .build()
+//!     .expect("valid configuration");
+//! println!("{} bytes", parser.memory_usage().total_bytes());
 //! ```
 //!
-//! ## Re-Exporting flows
-//! Netflow Parser now supports parsed V5, V7, V9, IPFix can be re-exported back into bytes.
+//! The `count` of a V9 header ends its flowsets.  By default it is taken as the number of flowsets,
+//! as many exporters set it.  For exporters counting records as RFC 3954 defines it, use
+//! `CountSemantics::Records`.  `CountSemantics::Auto` detects either, parsing flowsets until the
+//! flowsets or the records parsed reach the count, then on until the bytes run out or the next
+//! message starts, for exporters whose counts line up with neither:
 //! ```rust
-//! use netflow_parser::{NetflowParser, NetflowPacket};
+//! use netflow_parser::variable_versions::v9::CountSemantics;
+//! use netflow_parser::NetflowParser;
 //!
-//! let packet = [
-//!     0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
-//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
-//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
-//! ];
-//! if let NetflowPacket::V5(v5) = NetflowParser::default()
-//!     .parse_bytes(&packet)
-//!     .first()
-//!     .unwrap()
-//! {
-//!     assert_eq!(v5.to_be_bytes(), packet);
-//! }
+//! let parser = NetflowParser::builder()
+//!     .with_v9_count_semantics(CountSemantics::Auto)
+//!     .build()
+//!     .expect("valid configuration");
 //! ```
 //!
-//! ## V9/IPFix notes:
-//!
-//! Parse the data (`&[u8]` as any other versions.  The parser (NetflowParser) holds onto already parsed templates, so you can just send a header/data flowset combo, and it will use the cached templates.)   To see cached templates simply use the parser for the correct version (v9_parser for v9, ipfix_parser for IPFix.)
+//! Templates often carry far more fields than a collector needs.  `with_v9_projection` and
+//! `with_ipfix_projection` only decode the listed fields of data records, skipping over the others
+//! by their length, so wide templates don't cost a decode and an allocation per unused field.  Left
+//! out fields are missing from the records, options records are decoded in full:
 //! ```rust
+//! use netflow_parser::variable_versions::ipfix_lookup::IPFixField;
+//! use netflow_parser::variable_versions::v9_lookup::V9Field;
 //! use netflow_parser::NetflowParser;
-//! let parser = NetflowParser::default();
-//! dbg!(parser.v9_parser.templates);
-//! dbg!(parser.v9_parser.options_templates);
+//!
+//! let parser = NetflowParser::builder()
+//!     .with_v9_projection([V9Field::Ipv4SrcAddr, V9Field::Ipv4DstAddr, V9Field::InBytes])
+//!     .with_ipfix_projection([IPFixField::SourceIpv4address, IPFixField::OctetDeltaCount])
+//!     .build()
+//!     .expect("valid configuration");
 //! ```
-//! To access templates flowset of a processed V9/IPFix flowset you can find the `flowsets` attribute on the Parsed Record.  In there you can find `Templates`, `Option Templates`, and `Data` Flowsets.
 //!
 //! ## Features
 //!
+//! * `std` - When enabled the parser uses the standard library, along with the pipeline module.  When disabled it builds as `no_std` with `alloc` for embedded collectors, and the template rate limit and missing template alerts need a `Clock` passed to `NetflowParserBuilder::with_clock`.  Enabled by default.
+//! * `pcap` - Reading netflow from pcap and pcapng captures with the pcap, batch and analyze modules.  Requires `std`.  Enabled by default.
 //! * `parse_unknown_fields` - When enabled fields not listed in this library will attempt to be parsed as a Vec of bytes and the field_number listed.  When disabled an error is thrown when attempting to parse those fields.  Enabled by default.
+//! * `string_formatting` - When enabled String fields are decoded as UTF-8 and MAC addresses are formatted as Strings (pulls in `mac_address` and requires `std`).  When disabled both are kept as a raw Vec of bytes, for minimal builds.  Enabled by default.
+//! * `aggregation` - Adds the `aggregation` module, aggregating `NetflowCommonFlowSet`s by 5-tuple, prefix or AS pair with active and idle timeouts.
+//! * `json` - Writes flow records as JSON lines with the `json_lines` module and as Elastic Common Schema documents with the `ecs` module (pulls in `serde_json` and requires `std`).  Disabled by default.
+//! * `proto` - Encodes flows as protobuf messages with the `proto` module, without any extra dependencies.  Disabled by default.
+//! * `csv` - Writes NetflowCommon flowsets as CSV with the `csv` module.  Requires `std`.  Disabled by default.
+//! * `geoip` - `MaxMindEnrichment` in the `enrichment` module, attaching countries and AS numbers to flows from MaxMind databases (pulls in `maxminddb` and requires `std`).  Disabled by default.
+//! * `dns` - Reverse DNS names for flow addresses with the `dns` module (pulls in `dns-lookup` and enables `tokio`).  Disabled by default.
+//! * `tokio` - Async packet streams and a `tokio_util` codec with the `stream` module (pulls in `tokio`, `tokio-util`, `futures-core` and `bytes`, and requires `std`).  Disabled by default.
+//! * `mmap` - Memory mapped file ingestion with the `ingest` module (pulls in `memmap2` and requires `std`).  Disabled by default.
+//! * `kafka` - Produces flow records to a Kafka topic as JSON with `KafkaSink` in the `kafka` module (pulls in `rdkafka` and enables `json`).  Disabled by default.
+//! * `replay` - Re-sends parsed or archived packets to a collector over UDP with the `replay` module.  Requires `std`.  Disabled by default.
+//! * `perf` - Parses records into a reusable `RecordArena` with the `arena` module and `NetflowParser::parse_records`, without allocating per record.  Disabled by default.
+//! * `cli` - Builds the `netflow_parser-cli` binary.  Enables `csv`, `json` and `pcap`.  Disabled by default.
+//!
+//! ## Benchmarks
+//!
+//! The `benches` directory holds a criterion suite: V5 bulk parsing, V9 templates and data, wide V9 templates, IPFix with enterprise fields and string fields, and NetflowCommon conversion.  Packets are built by `benches/fixtures`.  To catch regressions, save a baseline before a change and compare against it after:
+//!
+//! ```cargo bench -- --save-baseline main```
+//!
+//! ```cargo bench -- --baseline main```
 //!
 //! ## Included Examples
 //! Examples have been included mainly for those who want to use this parser to read from a Socket and parse netflow.  In those cases with V9/IPFix it is best to create a new parser for each router.  There are both single threaded and multithreaded examples in the examples directory.
@@ -176,28 +1321,140 @@
 //! or
 //!
 //! ```cargo run --example netflow_udp_listener_tokio```
+//!
+//! or, assembled with the `pipeline` module,
+//!
+//! ```cargo run --example netflow_udp_listener_pipeline```
+//!
+//! ## Command line tool
+//!
+//! For troubleshooting an exporter without writing any code, the `netflow_parser-cli` binary decodes captures and live traffic:
+//!
+//! ```text
+//! cargo install netflow_parser --features cli
+//!
+//! # Every netflow packet of a capture as JSON lines, or its flows as CSV
+//! netflow_parser-cli decode capture.pcap
+//! netflow_parser-cli decode capture.pcap --format csv
+//!
+//! # Flow records received on UDP port 2055 as JSON lines
+//! netflow_parser-cli listen 2055
+//!
+//! # The latest definition of every template announced in a capture
+//! netflow_parser-cli templates show capture.pcap
+//! ```
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "aggregation")]
+pub mod aggregation;
+#[cfg(feature = "pcap")]
+pub mod analyze;
+pub mod anonymize;
+#[cfg(feature = "std")]
+pub mod archive;
+#[cfg(feature = "perf")]
+pub mod arena;
+#[cfg(feature = "pcap")]
+pub mod batch;
+pub mod batcher;
+pub mod clock;
+pub mod convert;
+#[cfg(feature = "csv")]
+pub mod csv;
+pub mod digest;
+#[cfg(feature = "dns")]
+pub mod dns;
+#[cfg(feature = "json")]
+pub mod ecs;
+pub mod enrichment;
+pub mod filter;
+pub mod flow_records;
+pub mod framer;
+#[cfg(feature = "mmap")]
+pub mod ingest;
+#[cfg(feature = "json")]
+pub mod json_lines;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod mediation;
+pub mod metrics;
 pub mod netflow_common;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "proto")]
+pub mod proto;
 pub mod protocol;
+pub mod redact;
+#[cfg(feature = "replay")]
+pub mod replay;
+pub mod roundtrip;
+pub mod schema;
+#[cfg(feature = "std")]
+pub mod shared;
 pub mod static_versions;
+#[cfg(feature = "tokio")]
+pub mod stream;
+pub mod testing;
 mod tests;
+pub mod types;
+pub mod uptime;
 pub mod variable_versions;
 
-use crate::netflow_common::{NetflowCommon, NetflowCommonError, NetflowCommonFlowSet};
+use crate::anonymize::Anonymizer;
+use crate::digest::PacketDigest;
+use crate::enrichment::Enrichment;
+use crate::filter::Filter;
+use crate::flow_records::FlowRecords;
+use crate::metrics::{MetricsSnapshot, ParserMetrics};
+use crate::netflow_common::{
+    InterfaceNameCache, LateFlowChecker, NetflowCommon, NetflowCommonError,
+    NetflowCommonFlowSet,
+};
+use crate::roundtrip::RoundtripReport;
 
-use static_versions::{v5::V5, v7::V7};
+use static_versions::v5::{V5Parser, V5TimestampQuirks, V5};
+use static_versions::v7::V7;
+use variable_versions::data_number::StringDecoding;
+use variable_versions::enterprise::EnterpriseFieldRegistry;
 use variable_versions::ipfix::{IPFix, IPFixParser};
-use variable_versions::v9::{V9Parser, V9};
+use variable_versions::ipfix_lookup::IPFixField;
+use variable_versions::template_engine::TemplateEngine;
+use variable_versions::v9::{CountSemantics, V9Parser, V9};
+use variable_versions::v9_lookup::V9Field;
+use variable_versions::{
+    LimitKind, MemoryBudget, MemoryStats, MissingTemplateTracker, ParserLimits, SourceTracker,
+    TemplateRateLimit, TemplateReport, TemplateSummary,
+};
+
+use crate::clock::{default_clock, Clock, ExportTimeClock, ExportTimeSources};
 
 use crate::static_versions::v5;
 use crate::static_versions::v7;
 use crate::variable_versions::ipfix;
 use crate::variable_versions::v9;
 
+use nom::error::{Error as NomError, ErrorKind};
+use nom::{Err as NomErr, Needed};
 use nom_derive::{Nom, Parse};
-use serde::Serialize;
+use serde::{Serialize, Serializer};
 
-use std::collections::HashSet;
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+#[cfg(not(feature = "std"))]
+pub(crate) use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{HashMap, HashSet};
 
 /// Enum of supported Netflow Versions
 #[derive(Debug, Clone, Serialize)]
@@ -233,6 +1490,98 @@ impl NetflowPacket {
     pub fn as_netflow_common(&self) -> Result<NetflowCommon, NetflowCommonError> {
         self.try_into()
     }
+    /// Convert the packet back into its wire format.  `Error` packets can't be exported and
+    /// return the error they hold.
+    pub fn to_be_bytes(&self) -> Result<Vec<u8>, NetflowError> {
+        match self {
+            Self::V5(v5) => Ok(v5.to_be_bytes()),
+            Self::V7(v7) => Ok(v7.to_be_bytes()),
+            Self::V9(v9) => v9.to_be_bytes(),
+            Self::IPFix(ipfix) => ipfix.to_be_bytes(),
+            Self::Error(e) => Err(e.error.clone()),
+        }
+    }
+    /// Wall-clock time the packet was received, since the unix epoch, when known.
+    pub fn received_at(&self) -> Option<Duration> {
+        match self {
+            Self::V5(v5) => v5.received_at,
+            Self::V7(v7) => v7.received_at,
+            Self::V9(v9) => v9.received_at,
+            Self::IPFix(ipfix) => ipfix.received_at,
+            Self::Error(_) => None,
+        }
+    }
+    fn set_received_at(&mut self, received_at: Duration) {
+        let field = match self {
+            Self::V5(v5) => &mut v5.received_at,
+            Self::V7(v7) => &mut v7.received_at,
+            Self::V9(v9) => &mut v9.received_at,
+            Self::IPFix(ipfix) => &mut ipfix.received_at,
+            Self::Error(_) => return,
+        };
+        *field = Some(received_at);
+    }
+    /// Identity of the message, when the parser computes packet digests.
+    pub fn digest(&self) -> Option<PacketDigest> {
+        match self {
+            Self::V5(v5) => v5.digest,
+            Self::V7(v7) => v7.digest,
+            Self::V9(v9) => v9.digest,
+            Self::IPFix(ipfix) => ipfix.digest,
+            Self::Error(_) => None,
+        }
+    }
+    fn set_digest(&mut self, digest: PacketDigest) {
+        let field = match self {
+            Self::V5(v5) => &mut v5.digest,
+            Self::V7(v7) => &mut v7.digest,
+            Self::V9(v9) => &mut v9.digest,
+            Self::IPFix(ipfix) => &mut ipfix.digest,
+            Self::Error(_) => return,
+        };
+        *field = Some(digest);
+    }
+    /// Flow sequence of V5 and V7 headers, sequence number of V9 and IPFix headers.
+    pub fn sequence_number(&self) -> Option<u32> {
+        match self {
+            Self::V5(v5) => Some(v5.header.flow_sequence),
+            Self::V7(v7) => Some(v7.header.flow_sequence),
+            Self::V9(v9) => Some(v9.header.sequence_number),
+            Self::IPFix(ipfix) => Some(ipfix.header.sequence_number),
+            Self::Error(_) => None,
+        }
+    }
+    /// V9 source id or IPFix observation domain id of the exporter.
+    pub fn source_id(&self) -> Option<u32> {
+        match self {
+            Self::V9(v9) => Some(v9.header.source_id),
+            Self::IPFix(ipfix) => Some(ipfix.header.observation_domain_id),
+            _ => None,
+        }
+    }
+    /// Export time from the packet header, since the unix epoch.
+    pub fn export_time(&self) -> Option<Duration> {
+        match self {
+            Self::V5(v5) => Some(v5.header.unix_time()),
+            Self::V7(v7) => Some(v7.header.unix_time()),
+            Self::V9(v9) => Some(Duration::from_secs(v9.header.unix_secs.into())),
+            Self::IPFix(ipfix) => Some(Duration::from_secs(ipfix.header.export_time.into())),
+            Self::Error(_) => None,
+        }
+    }
+    /// Time from the export of the packet to its receipt, covering exporter buffering and the
+    /// network.  `None` if the receive time isn't known or is before the export time, such as
+    /// when the exporter's clock is ahead.
+    pub fn processing_delay(&self) -> Option<Duration> {
+        self.received_at()?.checked_sub(self.export_time()?)
+    }
+}
+
+/// Serializes a list of mixed version packets back to wire format in order, for relaying or
+/// replaying flows.  Each packet gets its own result, so `Error` packets and packets that fail
+/// to export don't abort the export and can be matched to their index in `packets`.
+pub fn export_all(packets: &[NetflowPacket]) -> Vec<Result<Vec<u8>, NetflowError>> {
+    packets.iter().map(NetflowPacket::to_be_bytes).collect()
 }
 
 #[derive(Nom)]
@@ -243,9 +1592,46 @@ struct GenericNetflowHeader {
 
 #[derive(Debug)]
 pub struct NetflowParser {
+    pub v5_parser: V5Parser,
     pub v9_parser: V9Parser,
     pub ipfix_parser: IPFixParser,
     pub allowed_versions: HashSet<u16>,
+    /// Counters of parsed packets, see `metrics_snapshot`
+    pub metrics: ParserMetrics,
+    /// Flags late flows in `parse_bytes_as_netflow_common_flowsets`
+    pub late_flow_checker: Option<LateFlowChecker>,
+    /// Learns interface names from options records and names flow interfaces in
+    /// `parse_bytes_as_netflow_common_flowsets`
+    pub interface_names: Option<InterfaceNameCache>,
+    /// Adds outgoing counters into incoming ones in `parse_bytes_as_netflow_common_flowsets`,
+    /// see `NetflowCommonFlowSet::merge_counters`
+    pub merge_counters: bool,
+    /// Run over every flow in `parse_bytes_as_netflow_common_flowsets`
+    pub enrichment: Option<Arc<dyn Enrichment>>,
+    /// Drops the V5 and V7 flow records that don't match from every packet parsed.  The V9
+    /// and IPFix parsers are given a copy by the builder, which drops data records as they
+    /// are decoded.
+    pub filter: Option<Filter>,
+    /// Stamp packets with the system time they are parsed at as `received_at`.  Requires the
+    /// `std` feature.
+    pub capture_receive_time: bool,
+    /// Give packets a `digest` identifying the message, to drop retransmitted datagrams
+    pub packet_digests: bool,
+    /// Anonymizes the addresses of every packet parsed
+    pub anonymizer: Option<Anonymizer>,
+    /// Skip ahead to the next plausible packet header after a packet of an unknown or
+    /// unallowed version, instead of dropping the rest of the buffer
+    pub skip_unknown_versions: bool,
+    /// Skip packets of unallowed versions by the length in their header, marking each with a
+    /// `NetflowError::UnallowedVersion` error, instead of dropping the rest of the buffer
+    pub skip_unallowed_versions: bool,
+    /// Evicts the least recently used templates when the estimated memory of the parser goes
+    /// over budget
+    pub memory_budget: Option<MemoryBudget>,
+    /// Advanced to the export time of each packet parsed, when it times the parser
+    pub export_time_clock: Option<Arc<ExportTimeClock>>,
+    /// Aligns the export times of each source to `export_time_clock`
+    pub export_time_sources: ExportTimeSources,
 }
 
 #[derive(Debug, Clone)]
@@ -266,36 +1652,710 @@ impl ParsedNetflow {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct NetflowPacketError {
-    pub error: NetflowParseError,
+    pub error: NetflowError,
     pub remaining: Vec<u8>,
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub enum NetflowParseError {
-    Incomplete(String),
+/// Crate wide error type used by the parsers, the builder and exporting.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum NetflowError {
+    /// Not enough bytes were available.  `needed` is how many more bytes were required, if known.
+    Incomplete {
+        version: Option<u16>,
+        available: usize,
+        needed: Option<usize>,
+    },
+    /// A packet of a known version failed to parse part way through.
     Partial(PartialParse),
+    /// A data flowset referenced a template that has not been cached.
+    MissingTemplate { version: u16, template_id: u16 },
+    /// A declared length does not agree with the bytes available.
+    InvalidLength {
+        version: u16,
+        declared: usize,
+        available: usize,
+    },
+    /// An IPFix set's length is shorter than the set header or runs past the end of the
+    /// message, or a V9 flowset's length runs past the end of the packet.
+    SetLengthMismatch {
+        set_id: u16,
+        declared: u16,
+        available: usize,
+    },
+    /// A field value can not be represented on the wire.
+    InvalidFieldValue(String),
+    /// The version is not listed in `allowed_versions`.
     UnallowedVersion(u16),
-    UnknownVersion(Vec<u8>),
+    /// The version is not one this crate knows how to parse.
+    UnknownVersion(u16),
+    /// `length` bytes starting with an unknown or unallowed `version` were skipped to reach
+    /// the next plausible packet header.  See `NetflowParserBuilder::with_skip_unknown_versions`.
+    SkippedBytes { version: u16, length: usize },
+    /// A V9 or IPFix message declared more than a `ParserLimits` limit allows, such as a
+    /// field longer than `NetflowParserBuilder::with_max_field_length`.
+    LimitExceeded {
+        limit: LimitKind,
+        value: usize,
+        max: usize,
+    },
+    /// The parser configuration is invalid.
+    ConfigError(String),
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Previous name of [`NetflowError`].
+pub type NetflowParseError = NetflowError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct PartialParse {
     pub version: u16,
     pub remaining: Vec<u8>,
-    pub error: String,
+    /// The nom error kind that stopped parsing
+    #[serde(serialize_with = "serialize_error_kind")]
+    pub error_kind: ErrorKind,
+}
+
+fn serialize_error_kind<S: Serializer>(
+    kind: &ErrorKind,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(kind.description())
+}
+
+impl NetflowError {
+    /// Converts a nom error raised while parsing a `version` packet into a `NetflowError`.
+    pub(crate) fn from_nom(version: u16, packet: &[u8], e: NomErr<NomError<&[u8]>>) -> Self {
+        match e {
+            NomErr::Incomplete(needed) => NetflowError::Incomplete {
+                version: Some(version),
+                available: packet.len(),
+                needed: match needed {
+                    Needed::Size(size) => Some(size.get()),
+                    Needed::Unknown => None,
+                },
+            },
+            NomErr::Error(e) | NomErr::Failure(e) => NetflowError::Partial(PartialParse {
+                version,
+                remaining: packet.to_vec(),
+                error_kind: e.code,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for NetflowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetflowError::Incomplete {
+                version, needed, ..
+            } => match (version, needed) {
+                (Some(v), Some(n)) => {
+                    write!(f, "incomplete v{v} packet, {n} more bytes needed")
+                }
+                (Some(v), None) => write!(f, "incomplete v{v} packet"),
+                (None, _) => write!(f, "incomplete netflow header"),
+            },
+            NetflowError::Partial(partial) => write!(
+                f,
+                "failed to parse v{} packet: {}",
+                partial.version,
+                partial.error_kind.description()
+            ),
+            NetflowError::MissingTemplate {
+                version,
+                template_id,
+            } => write!(f, "v{version} template {template_id} has not been seen"),
+            NetflowError::InvalidLength {
+                version,
+                declared,
+                available,
+            } => write!(
+                f,
+                "v{version} declared length {declared} but {available} bytes are available"
+            ),
+            NetflowError::SetLengthMismatch {
+                set_id,
+                declared,
+                available,
+            } => write!(
+                f,
+                "set {set_id} declared length {declared} but {available} bytes are available"
+            ),
+            NetflowError::InvalidFieldValue(reason) => {
+                write!(f, "invalid field value: {reason}")
+            }
+            NetflowError::UnallowedVersion(v) => write!(f, "version {v} is not allowed"),
+            NetflowError::UnknownVersion(v) => write!(f, "unknown netflow version {v}"),
+            NetflowError::SkippedBytes { version, length } => {
+                write!(f, "skipped {length} bytes starting with version {version}")
+            }
+            NetflowError::LimitExceeded { limit, value, max } => match limit {
+                LimitKind::FieldLength => {
+                    write!(f, "field length {value} exceeds the limit of {max}")
+                }
+                LimitKind::RecordsPerSet => {
+                    write!(f, "{value} records in a set exceed the limit of {max}")
+                }
+                LimitKind::SetsPerMessage => {
+                    write!(f, "{value} sets in a message exceed the limit of {max}")
+                }
+                LimitKind::FieldCount => {
+                    write!(f, "{value} fields in a template exceed the limit of {max}")
+                }
+            },
+            NetflowError::ConfigError(reason) => write!(f, "invalid configuration: {reason}"),
+        }
+    }
+}
+
+impl core::error::Error for NetflowError {}
+
+/// How the V9 and IPFix parsers react to malformed flowsets.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ParseMode {
+    /// Every anomaly (unparseable flowsets, data for unknown templates, flowsets overrunning
+    /// the message) is surfaced as a `NetflowPacket::Error`.
+    #[default]
+    Strict,
+    /// Flowsets that can't be parsed are skipped using their length field and parsing
+    /// continues with the next flowset.
+    Lenient,
+}
+
+/// Versions this crate knows how to parse.
+pub const SUPPORTED_VERSIONS: [u16; 4] = [5, 7, 9, 10];
+
+/// `packets`, or the error of the first `NetflowPacket::Error` among them.
+pub(crate) fn first_error(
+    packets: Vec<NetflowPacket>,
+) -> Result<Vec<NetflowPacket>, NetflowError> {
+    packets
+        .into_iter()
+        .map(|packet| match packet {
+            NetflowPacket::Error(e) => Err(e.error),
+            packet => Ok(packet),
+        })
+        .collect()
+}
+
+/// Source id and export time in the header of a `version` packet, since the unix epoch, read
+/// before the packet is parsed.  V5 and V7 packets have no source id, their source is 0.
+fn header_export_time(version: u16, packet: &[u8]) -> Option<(u32, Duration)> {
+    let read_u32 = |offset: usize| {
+        packet
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    match version {
+        5 | 7 => Some((
+            0,
+            Duration::from_secs(read_u32(8)?.into())
+                + Duration::from_nanos(read_u32(12)?.into()),
+        )),
+        9 => Some((read_u32(16)?, Duration::from_secs(read_u32(8)?.into()))),
+        10 => Some((read_u32(12)?, Duration::from_secs(read_u32(4)?.into()))),
+        _ => None,
+    }
 }
 
 impl Default for NetflowParser {
     fn default() -> Self {
         Self {
+            v5_parser: V5Parser::default(),
             v9_parser: V9Parser::default(),
             ipfix_parser: IPFixParser::default(),
-            allowed_versions: [5, 7, 9, 10].iter().cloned().collect(),
+            allowed_versions: SUPPORTED_VERSIONS.into(),
+            metrics: ParserMetrics::default(),
+            late_flow_checker: None,
+            interface_names: None,
+            merge_counters: false,
+            enrichment: None,
+            filter: None,
+            capture_receive_time: false,
+            packet_digests: false,
+            anonymizer: None,
+            skip_unknown_versions: false,
+            skip_unallowed_versions: false,
+            memory_budget: None,
+            export_time_clock: None,
+            export_time_sources: ExportTimeSources::default(),
+        }
+    }
+}
+
+/// Builder for a [`NetflowParser`].
+///
+/// ```rust
+/// use netflow_parser::NetflowParser;
+///
+/// let parser = NetflowParser::builder()
+///     .with_allowed_versions([9, 10])
+///     .build()
+///     .unwrap();
+/// assert!(!parser.allowed_versions.contains(&5));
+/// ```
+#[derive(Debug, Clone)]
+pub struct NetflowParserBuilder {
+    allowed_versions: HashSet<u16>,
+    parse_mode: ParseMode,
+    v9_count_semantics: CountSemantics,
+    keep_raw: bool,
+    v9_projection: Option<BTreeSet<V9Field>>,
+    ipfix_projection: Option<BTreeSet<IPFixField>>,
+    scope_templates: bool,
+    record_template_events: bool,
+    count_sources: bool,
+    /// `(max_inserts, window)`
+    template_rate_limit: Option<(u32, Duration)>,
+    v5_timestamp_quirks: V5TimestampQuirks,
+    missing_template_threshold: Option<Duration>,
+    clock: Option<Arc<dyn Clock>>,
+    export_time_clock: bool,
+    late_flow_threshold: Option<Duration>,
+    interface_names: bool,
+    merge_counters: bool,
+    enrichment: Option<Arc<dyn Enrichment>>,
+    filter: Option<Filter>,
+    capture_receive_time: bool,
+    packet_digests: bool,
+    anonymizer: Option<Anonymizer>,
+    skip_unknown_versions: bool,
+    skip_unallowed_versions: bool,
+    limits: ParserLimits,
+    v9_max_field_count: Option<u16>,
+    ipfix_max_field_count: Option<u16>,
+    max_memory: Option<usize>,
+    source_ttl: Option<Duration>,
+    max_sources: Option<usize>,
+    enterprise_fields: EnterpriseFieldRegistry,
+    string_decoding: StringDecoding,
+}
+
+impl Default for NetflowParserBuilder {
+    fn default() -> Self {
+        Self {
+            allowed_versions: SUPPORTED_VERSIONS.into(),
+            parse_mode: ParseMode::default(),
+            v9_count_semantics: CountSemantics::default(),
+            keep_raw: false,
+            v9_projection: None,
+            ipfix_projection: None,
+            scope_templates: false,
+            record_template_events: false,
+            count_sources: false,
+            template_rate_limit: None,
+            v5_timestamp_quirks: V5TimestampQuirks::default(),
+            missing_template_threshold: None,
+            clock: None,
+            export_time_clock: false,
+            late_flow_threshold: None,
+            interface_names: false,
+            merge_counters: false,
+            enrichment: None,
+            filter: None,
+            capture_receive_time: false,
+            packet_digests: false,
+            anonymizer: None,
+            skip_unknown_versions: false,
+            skip_unallowed_versions: false,
+            limits: ParserLimits::default(),
+            v9_max_field_count: None,
+            ipfix_max_field_count: None,
+            max_memory: None,
+            source_ttl: None,
+            max_sources: None,
+            enterprise_fields: EnterpriseFieldRegistry::default(),
+            string_decoding: StringDecoding::default(),
+        }
+    }
+}
+
+impl NetflowParserBuilder {
+    /// Only parse the given versions, anything else is skipped.
+    pub fn with_allowed_versions(mut self, versions: impl IntoIterator<Item = u16>) -> Self {
+        self.allowed_versions = versions.into_iter().collect();
+        self
+    }
+
+    /// Sets how V9 and IPFix flowsets that fail to parse are handled.
+    pub fn with_parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = parse_mode;
+        self
+    }
+
+    /// Keeps the wire bytes of every V9 flowset and IPFix set in its `raw`, to compare a
+    /// decoding against a capture.
+    pub fn with_keep_raw(mut self, keep_raw: bool) -> Self {
+        self.keep_raw = keep_raw;
+        self
+    }
+
+    /// Caches V9 templates per `source_id` and IPFix templates per `observation_domain_id`,
+    /// so exporters reusing template ids across them don't overwrite each other.
+    pub fn with_scoped_templates(mut self, scope_templates: bool) -> Self {
+        self.scope_templates = scope_templates;
+        self
+    }
+
+    /// Counts the messages and records parsed per V9 `source_id` / IPFix
+    /// `observation_domain_id` in the `source_stats` of the V9 and IPFix parsers.  A source
+    /// evicted by `with_max_sources` or `with_source_ttl` loses its counts, otherwise they are
+    /// kept for every source seen.
+    pub fn with_source_stats(mut self, count_sources: bool) -> Self {
+        self.count_sources = count_sources;
+        self
+    }
+
+    /// Records `TemplateEvent`s, such as a template id being redefined with a different
+    /// layout, on the V9 and IPFix parsers.  Drain them with `drain_template_events`.
+    pub fn with_template_events(mut self, record_template_events: bool) -> Self {
+        self.record_template_events = record_template_events;
+        self
+    }
+
+    /// Accepts at most `max_inserts` new or changed templates per V9 `source_id` / IPFix
+    /// `observation_domain_id` every `window`.  Rejected announcements are counted in
+    /// `template_rate_limit`.
+    pub fn with_template_rate_limit(mut self, max_inserts: u32, window: Duration) -> Self {
+        self.template_rate_limit = Some((max_inserts, window));
+        self
+    }
+
+    /// Rejects V9 and IPFix data flowsets whose template declares a field longer than
+    /// `max_field_length` bytes.
+    pub fn with_max_field_length(mut self, max_field_length: u16) -> Self {
+        self.limits.max_field_length = Some(max_field_length);
+        self
+    }
+
+    /// Rejects V9 and IPFix data flowsets holding more than `max_records_per_set` records.
+    pub fn with_max_records_per_set(mut self, max_records_per_set: usize) -> Self {
+        self.limits.max_records_per_set = Some(max_records_per_set);
+        self
+    }
+
+    /// Rejects V9 and IPFix messages with more than `max_sets_per_message` flowsets.
+    pub fn with_max_sets_per_message(mut self, max_sets_per_message: usize) -> Self {
+        self.limits.max_sets_per_message = Some(max_sets_per_message);
+        self
+    }
+
+    /// Rejects V9 templates and options templates declaring more than `max_field_count`
+    /// fields, before their fields are parsed.
+    pub fn with_v9_max_field_count(mut self, max_field_count: u16) -> Self {
+        self.v9_max_field_count = Some(max_field_count);
+        self
+    }
+
+    /// Rejects IPFix templates and options templates declaring more than `max_field_count`
+    /// fields, before their fields are parsed.
+    pub fn with_ipfix_max_field_count(mut self, max_field_count: u16) -> Self {
+        self.ipfix_max_field_count = Some(max_field_count);
+        self
+    }
+
+    /// Caps the estimated memory held by the V9 and IPFix template caches at `max_bytes`,
+    /// evicting the least recently used templates once a message leaves the parser over it.
+    /// Pending bytes aren't counted, as they can't be evicted.  See
+    /// `NetflowParser::memory_usage`.
+    pub fn with_max_memory(mut self, max_bytes: usize) -> Self {
+        self.max_memory = Some(max_bytes);
+        self
+    }
+
+    /// With scoped templates, drops the templates of a V9 `source_id` / IPFix
+    /// `observation_domain_id` not seen for longer than `ttl`.  Idle sources are evicted when
+    /// a new source shows up, or with `NetflowParser::evict_idle_sources`.
+    pub fn with_source_ttl(mut self, ttl: Duration) -> Self {
+        self.source_ttl = Some(ttl);
+        self
+    }
+
+    /// With scoped templates, keeps the templates of at most `max_sources` V9 `source_id`s and
+    /// IPFix `observation_domain_id`s each, dropping the least recently seen source's when a
+    /// new one shows up.
+    pub fn with_max_sources(mut self, max_sources: usize) -> Self {
+        self.max_sources = Some(max_sources);
+        self
+    }
+
+    /// Only decodes `fields` of V9 data records.  Other fields are skipped over by their
+    /// length and left out of the records, which saves decoding them on wide templates.
+    pub fn with_v9_projection(mut self, fields: impl IntoIterator<Item = V9Field>) -> Self {
+        self.v9_projection = Some(fields.into_iter().collect());
+        self
+    }
+
+    /// Only decodes `fields` of IPFix data records, like `with_v9_projection`.  Enterprise
+    /// fields are kept with `IPFixField::Enterprise`.
+    pub fn with_ipfix_projection(
+        mut self,
+        fields: impl IntoIterator<Item = IPFixField>,
+    ) -> Self {
+        self.ipfix_projection = Some(fields.into_iter().collect());
+        self
+    }
+
+    /// Sets what the `count` of V9 headers counts, for exporters that count records rather
+    /// than flowsets or whose counts are wrong.
+    pub fn with_v9_count_semantics(mut self, count_semantics: CountSemantics) -> Self {
+        self.v9_count_semantics = count_semantics;
+        self
+    }
+
+    /// Sets how V5 timestamps from exporters that don't follow the spec are interpreted.
+    pub fn with_v5_timestamp_quirks(mut self, quirks: V5TimestampQuirks) -> Self {
+        self.v5_timestamp_quirks = quirks;
+        self
+    }
+
+    /// Tracks V9/IPFix data dropped because its template isn't cached.  Once it has been
+    /// dropped repeatedly for longer than `threshold`, the template observer's
+    /// `on_missing_template` is called, so exporters can be asked to resend templates.
+    pub fn with_missing_template_alert(mut self, threshold: Duration) -> Self {
+        self.missing_template_threshold = Some(threshold);
+        self
+    }
+
+    /// Sets the clock used by the template rate limit and missing template alerts.  Required
+    /// for them without the `std` feature, otherwise the system clock is used.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Times the template rate limit, missing template alerts and source ttls by the export
+    /// time in the headers of the packets parsed rather than local time, for replaying
+    /// captures or collectors that pause.  Each source moves the clock by as much as its own
+    /// export time moved, and only with packets that parse.  Can't be combined with
+    /// `with_clock`.
+    pub fn with_export_time_clock(mut self, export_time_clock: bool) -> Self {
+        self.export_time_clock = export_time_clock;
+        self
+    }
+
+    /// Marks flows exported more than `threshold` after they ended as `late` in
+    /// `parse_bytes_as_netflow_common_flowsets`.
+    pub fn with_late_flow_threshold(mut self, threshold: Duration) -> Self {
+        self.late_flow_threshold = Some(threshold);
+        self
+    }
+
+    /// Learns interface names from the options records of V9/IPFix exporters and sets
+    /// `input_interface_name`/`output_interface_name` in
+    /// `parse_bytes_as_netflow_common_flowsets`.
+    pub fn with_interface_names(mut self, interface_names: bool) -> Self {
+        self.interface_names = interface_names;
+        self
+    }
+
+    /// Adds the outgoing octets and packets of flows carrying both directions into `bytes`
+    /// and `packets` in `parse_bytes_as_netflow_common_flowsets`.
+    pub fn with_merged_counters(mut self, merge_counters: bool) -> Self {
+        self.merge_counters = merge_counters;
+        self
+    }
+
+    /// Runs every flow of `parse_bytes_as_netflow_common_flowsets` through `enrichment`, such
+    /// as a `MaxMindEnrichment` attaching countries and AS numbers.
+    pub fn with_enrichment(mut self, enrichment: Arc<dyn Enrichment>) -> Self {
+        self.enrichment = Some(enrichment);
+        self
+    }
+
+    /// Drops the flow records `filter` doesn't match from every packet parsed.  V9 and IPFix
+    /// data records are dropped as they are decoded, before the rest of the packet is.
+    /// Fields left out by a projection never match.
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Stamps parsed packets with the system time as `received_at`, so their
+    /// `processing_delay` is known and counted in the metrics.  Requires the `std` feature.
+    pub fn with_receive_time_capture(mut self, capture_receive_time: bool) -> Self {
+        self.capture_receive_time = capture_receive_time;
+        self
+    }
+
+    /// Gives every parsed packet a [`PacketDigest`] of its header source id and sequence
+    /// number and a hash of its wire bytes, so pipelines can drop retransmitted datagrams.
+    pub fn with_packet_digests(mut self, packet_digests: bool) -> Self {
+        self.packet_digests = packet_digests;
+        self
+    }
+
+    /// Anonymizes the addresses of every parsed packet, see [`anonymize`].  Raw bytes kept
+    /// with [`with_keep_raw`](Self::with_keep_raw) are left as received.
+    pub fn with_anonymizer(mut self, anonymizer: Anonymizer) -> Self {
+        self.anonymizer = Some(anonymizer);
+        self
+    }
+
+    /// Skips ahead to the next plausible packet header of an allowed version after a packet
+    /// of an unknown or unallowed version, such as junk interleaved by a port-mirrored feed,
+    /// and keeps parsing.  Each skipped span is reported as a `NetflowPacket::Error` with
+    /// `NetflowError::SkippedBytes`.  By default the rest of the buffer is dropped.
+    pub fn with_skip_unknown_versions(mut self, skip_unknown_versions: bool) -> Self {
+        self.skip_unknown_versions = skip_unknown_versions;
+        self
+    }
+
+    /// Skips packets of versions that aren't allowed by the length in their header and keeps
+    /// parsing the packets after them.  Each skipped packet is reported as a
+    /// `NetflowPacket::Error` with `NetflowError::UnallowedVersion` and the packet's bytes.  By
+    /// default the rest of the buffer is dropped.
+    pub fn with_skip_unallowed_versions(mut self, skip_unallowed_versions: bool) -> Self {
+        self.skip_unallowed_versions = skip_unallowed_versions;
+        self
+    }
+
+    /// Decodes IPFix enterprise specific fields with the decoders in `registry`.
+    pub fn with_enterprise_fields(mut self, registry: EnterpriseFieldRegistry) -> Self {
+        self.enterprise_fields = registry;
+        self
+    }
+
+    /// Sets how V9/IPFix `String` fields, such as interface names and URLs, are decoded.
+    /// Defaults to `StringDecoding::Lossy`.
+    pub fn with_string_decoding(mut self, string_decoding: StringDecoding) -> Self {
+        self.string_decoding = string_decoding;
+        self
+    }
+
+    /// Validates the configuration and builds the parser.
+    pub fn build(self) -> Result<NetflowParser, NetflowError> {
+        if let Some(version) = self
+            .allowed_versions
+            .iter()
+            .find(|v| !SUPPORTED_VERSIONS.contains(v))
+        {
+            return Err(NetflowError::ConfigError(format!(
+                "version {version} can not be allowed, supported versions are {SUPPORTED_VERSIONS:?}"
+            )));
+        }
+        if self.capture_receive_time && cfg!(not(feature = "std")) {
+            return Err(NetflowError::ConfigError(
+                "capturing receive times requires the std feature".to_string(),
+            ));
+        }
+        let tracks_sources = self.source_ttl.is_some() || self.max_sources.is_some();
+        if tracks_sources && !self.scope_templates {
+            return Err(NetflowError::ConfigError(
+                "source ttls and max sources require scoped templates".to_string(),
+            ));
         }
+        if self.export_time_clock && self.clock.is_some() {
+            return Err(NetflowError::ConfigError(
+                "the export time clock can't be combined with another clock".to_string(),
+            ));
+        }
+        let export_time_clock = self
+            .export_time_clock
+            .then(|| Arc::new(ExportTimeClock::new()));
+        let clock = export_time_clock
+            .clone()
+            .map(|clock| clock as Arc<dyn Clock>)
+            .or(self.clock)
+            .or_else(default_clock);
+        if clock.is_none()
+            && (self.template_rate_limit.is_some()
+                || self.missing_template_threshold.is_some()
+                || tracks_sources)
+        {
+            return Err(NetflowError::ConfigError(
+                "a clock is required for template rate limits, missing template alerts and \
+                 source eviction"
+                    .to_string(),
+            ));
+        }
+        let source_tracker = clock
+            .clone()
+            .filter(|_| tracks_sources)
+            .map(|clock| SourceTracker::with_clock(self.source_ttl, self.max_sources, clock));
+        let rate_limit = self.template_rate_limit.zip(clock.clone()).map(
+            |((max_inserts, window), clock)| {
+                TemplateRateLimit::with_clock(max_inserts, window, clock)
+            },
+        );
+        let missing_templates = self
+            .missing_template_threshold
+            .zip(clock)
+            .map(|(threshold, clock)| MissingTemplateTracker::with_clock(threshold, clock));
+        Ok(NetflowParser {
+            v5_parser: V5Parser {
+                timestamp_quirks: self.v5_timestamp_quirks,
+            },
+            v9_parser: V9Parser {
+                parse_mode: self.parse_mode,
+                count_semantics: self.v9_count_semantics,
+                keep_raw: self.keep_raw,
+                projection: self.v9_projection,
+                filter: self.filter.clone(),
+                engine: TemplateEngine {
+                    scope_templates: self.scope_templates,
+                    record_template_events: self.record_template_events,
+                    count_sources: self.count_sources,
+                    template_rate_limit: rate_limit.clone(),
+                    missing_templates: missing_templates.clone(),
+                    source_tracker: source_tracker.clone(),
+                    ..TemplateEngine::default()
+                },
+                string_decoding: self.string_decoding,
+                limits: ParserLimits {
+                    max_field_count: self.v9_max_field_count,
+                    ..self.limits
+                },
+                #[cfg(feature = "perf")]
+                arena: None,
+            },
+            ipfix_parser: IPFixParser {
+                parse_mode: self.parse_mode,
+                keep_raw: self.keep_raw,
+                projection: self.ipfix_projection,
+                filter: self.filter.clone(),
+                engine: TemplateEngine {
+                    scope_templates: self.scope_templates,
+                    record_template_events: self.record_template_events,
+                    count_sources: self.count_sources,
+                    template_rate_limit: rate_limit,
+                    missing_templates,
+                    source_tracker,
+                    ..TemplateEngine::default()
+                },
+                enterprise_fields: self.enterprise_fields,
+                string_decoding: self.string_decoding,
+                limits: ParserLimits {
+                    max_field_count: self.ipfix_max_field_count,
+                    ..self.limits
+                },
+                #[cfg(feature = "perf")]
+                arena: None,
+            },
+            allowed_versions: self.allowed_versions,
+            metrics: ParserMetrics::default(),
+            late_flow_checker: self.late_flow_threshold.map(LateFlowChecker::new),
+            interface_names: self.interface_names.then(InterfaceNameCache::new),
+            merge_counters: self.merge_counters,
+            enrichment: self.enrichment,
+            filter: self.filter,
+            capture_receive_time: self.capture_receive_time,
+            packet_digests: self.packet_digests,
+            anonymizer: self.anonymizer,
+            skip_unknown_versions: self.skip_unknown_versions,
+            skip_unallowed_versions: self.skip_unallowed_versions,
+            memory_budget: self.max_memory.map(MemoryBudget::new),
+            export_time_clock,
+            export_time_sources: ExportTimeSources::default(),
+        })
     }
 }
 
 impl NetflowParser {
+    /// Returns a [`NetflowParserBuilder`] for configuring a parser.
+    pub fn builder() -> NetflowParserBuilder {
+        NetflowParserBuilder::default()
+    }
+
     /// Takes a Netflow packet slice and returns a vector of Parsed Netflows.
     /// If we reach some parse error we return what items be have.
     ///
@@ -317,78 +2377,382 @@ impl NetflowParser {
     ///
     #[inline]
     pub fn parse_bytes(&mut self, packet: &[u8]) -> Vec<NetflowPacket> {
+        let received_at = self.receive_time();
+        self.parse_bytes_at(packet, received_at)
+    }
+
+    /// Like `parse_bytes`, returning the first error instead of embedding it as a
+    /// `NetflowPacket::Error`.  Packets ahead of the error are still parsed, so the templates
+    /// they carry are learned.
+    pub fn try_parse_bytes(
+        &mut self,
+        packet: &[u8],
+    ) -> Result<Vec<NetflowPacket>, NetflowError> {
+        first_error(self.parse_bytes(packet))
+    }
+
+    /// Parses the packets in `packet` and re-exports each of them, reporting where the export
+    /// first differs from the original bytes.  Templates are learned as with `parse_bytes`,
+    /// metrics aren't updated.  See [`roundtrip`].
+    pub fn verify_roundtrip(&mut self, packet: &[u8]) -> RoundtripReport {
+        roundtrip::verify(self, packet)
+    }
+
+    /// Like `parse_bytes`, with the wall-clock time the packet was received at, since the unix
+    /// epoch, such as a socket or pcap timestamp.  Packets get it as `received_at`.
+    pub fn parse_bytes_received_at(
+        &mut self,
+        packet: &[u8],
+        received_at: Duration,
+    ) -> Vec<NetflowPacket> {
+        self.parse_bytes_at(packet, Some(received_at))
+    }
+
+    fn parse_bytes_at(
+        &mut self,
+        packet: &[u8],
+        received_at: Option<Duration>,
+    ) -> Vec<NetflowPacket> {
         if packet.is_empty() {
             return vec![];
         }
 
-        match self.parse_packet_by_version(packet) {
+        match self.parse_packet_by_version(packet, received_at) {
             Ok(parsed_netflow) => {
                 let mut results = vec![parsed_netflow.result];
                 if !parsed_netflow.remaining.is_empty() {
-                    results.extend(self.parse_bytes(&parsed_netflow.remaining));
+                    results.extend(self.parse_bytes_at(&parsed_netflow.remaining, received_at));
                 }
                 results
             }
-            Err(e) => match e {
-                NetflowParseError::Incomplete(_) => {
-                    vec![NetflowPacket::Error(NetflowPacketError {
-                        error: e,
-                        remaining: packet.to_vec(),
-                    })]
+            Err(error) => match self.skip_after(packet, &error) {
+                Some((marker, length)) => {
+                    let mut results = vec![NetflowPacket::Error(NetflowPacketError {
+                        error: marker,
+                        remaining: packet[..length].to_vec(),
+                    })];
+                    results.extend(self.parse_bytes_at(&packet[length..], received_at));
+                    results
+                }
+                None if matches!(error, NetflowError::UnallowedVersion(_)) => vec![],
+                None => vec![NetflowPacket::Error(NetflowPacketError {
+                    error,
+                    remaining: packet.to_vec(),
+                })],
+            },
+        }
+    }
+
+    /// Lazily iterates over the flow records of every packet in `packet`, skipping templates
+    /// and options records.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use netflow_parser::NetflowParser;
+    ///
+    /// let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,];
+    /// let mut parser = NetflowParser::default();
+    /// for record in parser.iter_flow_records(&v5_packet) {
+    ///     println!("v{}: {:?}", record.version(), record);
+    /// }
+    /// ```
+    pub fn iter_flow_records<'p, 'b>(&'p mut self, packet: &'b [u8]) -> FlowRecords<'p, 'b> {
+        FlowRecords::new(self, packet)
+    }
+
+    /// Parses every packet of `packet` into `arena`, decoding V9 and IPFix data records
+    /// straight into its fields instead of allocating them per record, see [`arena`].  Packets
+    /// of versions that are skipped are passed over as `iter_flow_records` does; any other
+    /// error stops parsing and is returned, with the records parsed before it kept.  Records
+    /// in the arena carry no field warnings.
+    #[cfg(feature = "perf")]
+    pub fn parse_records(
+        &mut self,
+        packet: &[u8],
+        arena: &mut arena::RecordArena,
+    ) -> Result<(), NetflowError> {
+        let mut remaining = packet;
+        while !remaining.is_empty() {
+            let (v9_start, ipfix_start) = arena.field_counts();
+            let ipfix = remaining.starts_with(&[0, 10]);
+            // Lent to the parser of the packet's version for its records to decode into
+            fn lent(
+                parser: &mut NetflowParser,
+                ipfix: bool,
+            ) -> &mut Option<arena::RecordArena> {
+                match ipfix {
+                    true => &mut parser.ipfix_parser.arena,
+                    false => &mut parser.v9_parser.arena,
                 }
-                NetflowParseError::Partial(partial) => {
-                    vec![NetflowPacket::Error(NetflowPacketError {
-                        error: NetflowParseError::Partial(partial),
-                        remaining: packet.to_vec(),
-                    })]
+            }
+            *lent(self, ipfix) = Some(core::mem::take(arena));
+            let result = self.parse_packet_by_version(remaining, self.receive_time());
+            *arena = lent(self, ipfix).take().unwrap_or_default();
+            match result {
+                Ok(parsed) => {
+                    remaining = &remaining[remaining.len() - parsed.remaining.len()..];
+                    arena.push_packet(parsed.result);
+                    if let Some(anonymizer) = &self.anonymizer {
+                        arena.anonymize_from(anonymizer, v9_start, ipfix_start);
+                    }
                 }
-                NetflowParseError::UnknownVersion(_) => {
-                    vec![NetflowPacket::Error(NetflowPacketError {
-                        error: e,
-                        remaining: packet.to_vec(),
-                    })]
+                Err(error) => match self.skip_after(remaining, &error) {
+                    Some((_, length)) => remaining = &remaining[length..],
+                    None => return Err(error),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the parser's counters along with template totals from the V9 and IPFix parsers.
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        let (v9, ipfix) = (&self.v9_parser.engine, &self.ipfix_parser.engine);
+        let rejected =
+            |limit: &Option<TemplateRateLimit>| limit.as_ref().map_or(0, |l| l.rejected());
+        MetricsSnapshot {
+            parser: self.metrics,
+            templates_added: v9.template_stats.added + ipfix.template_stats.added,
+            templates_redefined: v9.template_stats.redefined + ipfix.template_stats.redefined,
+            templates_rejected: rejected(&v9.template_rate_limit)
+                + rejected(&ipfix.template_rate_limit),
+            cached_templates: (v9.cached_template_count() + ipfix.cached_template_count())
+                as u64,
+        }
+    }
+
+    /// Estimated memory held by the V9 and IPFix template caches and pending template events,
+    /// for sizing collectors.  See `NetflowParserBuilder::with_max_memory` to cap it.
+    pub fn memory_usage(&self) -> MemoryStats {
+        self.v9_parser.engine.memory_usage() + self.ipfix_parser.engine.memory_usage()
+    }
+
+    /// Field layout of the shared V9 template or options template `template_id`.  Templates
+    /// scoped per source are found with `V9Parser::template_summary`.
+    pub fn get_v9_template(&self, template_id: u16) -> Option<TemplateSummary> {
+        self.v9_parser.engine.template_summary(None, template_id)
+    }
+
+    /// Field layout of the shared IPFix template or options template `template_id`.
+    /// Templates scoped per observation domain are found with
+    /// `IPFixParser::template_summary`.
+    pub fn get_ipfix_template(&self, template_id: u16) -> Option<TemplateSummary> {
+        self.ipfix_parser.engine.template_summary(None, template_id)
+    }
+
+    /// Checks every cached V9 and IPFix template for suspicious definitions, such as zero
+    /// length or duplicate fields, scopes not matching their descriptors, or enterprise fields
+    /// without a registered decoder.
+    pub fn validate_templates(&self) -> TemplateReport {
+        self.v9_parser.validate_templates() + self.ipfix_parser.validate_templates()
+    }
+
+    /// Drops the scoped templates of V9 and IPFix sources idle for longer than the source ttl,
+    /// returning how many sources were evicted.  See `NetflowParserBuilder::with_source_ttl`.
+    pub fn evict_idle_sources(&mut self) -> usize {
+        self.v9_parser.engine.evict_idle_sources()
+            + self.ipfix_parser.engine.evict_idle_sources()
+    }
+
+    /// Marks the templates of a parsed V9 or IPFix `packet` as used, then evicts the least
+    /// recently used templates until the parser is within its memory budget.
+    fn enforce_memory_budget(&mut self, packet: Option<&NetflowPacket>) {
+        let Some(budget) = self.memory_budget.as_mut() else {
+            return;
+        };
+        let (tick, max_bytes) = (budget.tick(), budget.max_bytes);
+        match packet {
+            Some(NetflowPacket::V9(v9)) => self.v9_parser.touch_templates(v9, tick),
+            Some(NetflowPacket::IPFix(ipfix)) => self.ipfix_parser.touch_templates(ipfix, tick),
+            _ => {}
+        }
+        // Pending events and tracking can't be evicted and are bounded on their own.
+        let mut usage = self.memory_usage().template_bytes;
+        while usage > max_bytes {
+            let v9 = self.v9_parser.engine.oldest_template();
+            let ipfix = self.ipfix_parser.engine.oldest_template();
+            let freed = match (v9, ipfix) {
+                (Some((v9_tick, (kind, scope, id))), ipfix)
+                    if ipfix.is_none_or(|(ipfix_tick, _)| v9_tick <= ipfix_tick) =>
+                {
+                    self.v9_parser.engine.evict_template(kind, scope, id)
                 }
-                NetflowParseError::UnallowedVersion(_) => {
-                    vec![]
+                (_, Some((_, (kind, scope, id)))) => {
+                    self.ipfix_parser.engine.evict_template(kind, scope, id)
                 }
-            },
+                _ => break,
+            };
+            usage = usage.saturating_sub(freed);
         }
     }
 
-    /// Takes a Netflow packet slice and returns a vector of Parsed NetflowCommonFlowSet
+    /// Takes a Netflow packet slice and returns a vector of Parsed NetflowCommonFlowSet.
+    /// Records the `filter` doesn't match were dropped while parsing.  Flowsets are marked `late` when a
+    /// `late_flow_checker` is set, their interfaces named when `interface_names` is set, their
+    /// counters merged when `merge_counters` is set, and run through the `enrichment` if there
+    /// is one.
     #[inline]
     pub fn parse_bytes_as_netflow_common_flowsets(
         &mut self,
         packet: &[u8],
     ) -> Vec<NetflowCommonFlowSet> {
-        let netflow_packets = self.parse_bytes(packet);
-        netflow_packets
+        self.parse_bytes(packet)
             .iter()
-            .flat_map(|n| n.as_netflow_common().unwrap_or_default().flowsets)
+            .flat_map(|n| {
+                let mut common = n.as_netflow_common().unwrap_or_default();
+                if let Some(checker) = &self.late_flow_checker {
+                    checker.annotate(&mut common);
+                }
+                if let (Some(cache), Some(source)) = (&mut self.interface_names, n.source_id())
+                {
+                    cache.learn(source, &common);
+                    cache.annotate(source, &mut common);
+                }
+                if self.merge_counters {
+                    common
+                        .flowsets
+                        .iter_mut()
+                        .for_each(NetflowCommonFlowSet::merge_counters);
+                }
+                if let Some(enrichment) = &self.enrichment {
+                    common
+                        .flowsets
+                        .iter_mut()
+                        .for_each(|flow| enrichment.enrich(flow));
+                }
+                common.flowsets
+            })
             .collect()
     }
 
     /// Checks the first u16 of the packet to determine the version.  Parses the packet based on the version.
     /// If the version is unknown it returns an error.  If the packet is incomplete it returns an error.
     /// If the packet is parsed successfully it returns the parsed Netflow packet and the remaining bytes.
-    fn parse_packet_by_version<'a>(
+    /// The packet is stamped with `received_at` and the outcome is counted in `metrics`.
+    fn parse_packet_by_version(
+        &mut self,
+        packet: &[u8],
+        received_at: Option<Duration>,
+    ) -> Result<ParsedNetflow, NetflowError> {
+        let version = packet
+            .get(..2)
+            .map_or(0, |version| u16::from_be_bytes([version[0], version[1]]));
+        // The clock is moved to the packet's export time while it is parsed, so templates
+        // and sources are timed by it, and moved back if the packet fails to parse
+        let export_time = self.export_time_clock.as_ref().and_then(|clock| {
+            let (source, export_time) = self
+                .allowed_versions
+                .contains(&version)
+                .then(|| header_export_time(version, packet))??;
+            let now = clock.now();
+            let at = self
+                .export_time_sources
+                .align((version, source), export_time, now);
+            clock.advance(at);
+            Some(((version, source), export_time, now, at))
+        });
+        let mut result = self.parse_packet_for_version(packet);
+        if let (Ok(parsed), Some(filter)) = (&mut result, &self.filter) {
+            filter.retain(&mut parsed.result);
+        }
+        if let (Some(clock), Some((source, export_time, now, at))) =
+            (self.export_time_clock.as_ref(), export_time)
+        {
+            if result.is_ok() {
+                self.export_time_sources.record(source, export_time, at);
+            } else {
+                clock.reset(now);
+            }
+        }
+        if let (Ok(parsed), Some(received_at)) = (&mut result, received_at) {
+            parsed.result.set_received_at(received_at);
+        }
+        if let (Ok(parsed), true) = (&mut result, self.packet_digests) {
+            let message = &packet[..packet.len() - parsed.remaining.len()];
+            if let Some(digest) = PacketDigest::new(&parsed.result, message) {
+                parsed.result.set_digest(digest);
+            }
+        }
+        if let (Ok(parsed), Some(anonymizer)) = (&mut result, &self.anonymizer) {
+            anonymizer.anonymize_packet(&mut parsed.result);
+        }
+        if matches!(version, 9 | 10) && self.allowed_versions.contains(&version) {
+            self.enforce_memory_budget(result.as_ref().ok().map(|parsed| &parsed.result));
+        }
+        self.metrics.record(version, packet.len(), &result);
+        result
+    }
+
+    /// The current system time if receive times are captured.
+    fn receive_time(&self) -> Option<Duration> {
+        #[cfg(feature = "std")]
+        if self.capture_receive_time {
+            return std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .ok();
+        }
+        None
+    }
+
+    /// The error to report and the bytes to skip to go on parsing after `error`, if it is
+    /// an unknown or unallowed version that is skipped.  With `skip_unallowed_versions` an
+    /// unallowed packet is skipped by the length in its header, with `skip_unknown_versions`
+    /// up to the next plausible packet of an allowed version, or to the end of `packet` if
+    /// no packet follows.
+    pub(crate) fn skip_after(
+        &self,
+        packet: &[u8],
+        error: &NetflowError,
+    ) -> Option<(NetflowError, usize)> {
+        let version = match error {
+            NetflowError::UnallowedVersion(version) | NetflowError::UnknownVersion(version) => {
+                *version
+            }
+            _ => return None,
+        };
+        if self.skip_unallowed_versions && matches!(error, NetflowError::UnallowedVersion(_)) {
+            // Versions this crate can't parse have no known length.
+            if let Ok(length) = framer::message_length(packet) {
+                return Some((error.clone(), length.unwrap_or(packet.len())));
+            }
+        }
+        if !self.skip_unknown_versions {
+            return None;
+        }
+        let next = (1..packet.len()).find(|&offset| {
+            let rest = &packet[offset..];
+            rest.get(..2).is_some_and(|version| {
+                self.allowed_versions
+                    .contains(&u16::from_be_bytes([version[0], version[1]]))
+            }) && framer::plausible_message(rest)
+        });
+        let length = next.unwrap_or(packet.len());
+        Some((NetflowError::SkippedBytes { version, length }, length))
+    }
+
+    fn parse_packet_for_version<'a>(
         &'a mut self,
         packet: &'a [u8],
-    ) -> Result<ParsedNetflow, NetflowParseError> {
+    ) -> Result<ParsedNetflow, NetflowError> {
         let (packet, version) = GenericNetflowHeader::parse(packet)
             .map(|(remaining, header)| (remaining, header.version))
-            .map_err(|e| NetflowParseError::Incomplete(e.to_string()))?;
+            .map_err(|_| NetflowError::Incomplete {
+                version: None,
+                available: packet.len(),
+                needed: Some(2_usize.saturating_sub(packet.len())),
+            })?;
 
         if !self.allowed_versions.contains(&version) {
-            return Err(NetflowParseError::UnallowedVersion(version));
+            return Err(NetflowError::UnallowedVersion(version));
         }
 
         match version {
-            5 => v5::parse_netflow_v5(packet),
+            5 => v5::parse_netflow_v5(packet, &self.v5_parser),
             7 => v7::parse_netflow_v7(packet),
             9 => v9::parse_netflow_v9(packet, &mut self.v9_parser),
             10 => ipfix::parse_netflow_ipfix(packet, &mut self.ipfix_parser),
-            _ => Err(NetflowParseError::UnknownVersion(packet.to_vec())),
+            _ => Err(NetflowError::UnknownVersion(version)),
         }
     }
 }
@@ -0,0 +1,548 @@
+//! # Mediation
+//!
+//! Rewrites parsed IPFix messages so they can be forwarded to another collector, for
+//! mediators that drop, rename or add fields on the way.  Templates are re-announced under
+//! new ids, and set lengths, the message length and sequence numbers are recomputed for the
+//! rewritten message.
+//!
+//! Every message of an exporter, templates included, should go through the same
+//! [`Mediator`]: data sets whose template it hasn't seen are left out.  Templates cached by a
+//! parser beforehand can be passed on with `IPFixParser::templates_to_message`.
+//!
+//! ```rust
+//! use netflow_parser::mediation::{FieldTransform, Mediator};
+//! use netflow_parser::variable_versions::ipfix_lookup::IPFixField;
+//! use netflow_parser::{NetflowPacket, NetflowParser};
+//!
+//! let packet = [
+//!     0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0, 4, 0,
+//!     12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2, 0, 2, 0, 1,
+//!     2, 3, 4, 5, 6, 7,
+//! ];
+//! let mut mediator = Mediator::builder()
+//!     .with_transform(FieldTransform::Drop(IPFixField::SourceIpv4address))
+//!     .build()
+//!     .expect("valid transforms");
+//!
+//! for packet in NetflowParser::default().parse_bytes(&packet) {
+//!     if let NetflowPacket::IPFix(ipfix) = packet {
+//!         let forwarded = mediator.mediate(&ipfix).expect("fits in a message");
+//!         let bytes = forwarded.to_be_bytes().expect("exportable");
+//!     }
+//! }
+//! ```
+
+use crate::variable_versions::data_number::FieldValue;
+use crate::variable_versions::ipfix::{
+    Data, FlowSet, FlowSetBody, FlowSetHeader, Header, IPFix, OptionsData, OptionsTemplate,
+    Template, TemplateField,
+};
+use crate::variable_versions::ipfix_lookup::IPFixField;
+use crate::NetflowError;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const TEMPLATE_SET_ID: u16 = 2;
+const OPTIONS_TEMPLATE_SET_ID: u16 = 3;
+const FIRST_TEMPLATE_ID: u16 = 256;
+const HEADER_LENGTH: usize = 16;
+const SET_HEADER_LENGTH: usize = 4;
+
+type Record = BTreeMap<usize, (IPFixField, FieldValue)>;
+
+/// A change made to every template and record, applied in the order they were added to the
+/// builder.  Fields are matched by IANA information element, enterprise fields are kept as is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldTransform {
+    /// Removes the field
+    Drop(IPFixField),
+    /// Exports the field as another information element
+    Rename { from: IPFixField, to: IPFixField },
+    /// Appends the field with the same value to every record
+    Add {
+        field: IPFixField,
+        value: FieldValue,
+    },
+}
+
+/// Where a field of a rewritten record comes from.
+#[derive(Debug, Clone)]
+enum FieldSource {
+    /// The field at this index of the original record, exported as `field`
+    Record { index: usize, field: IPFixField },
+    Added {
+        field: IPFixField,
+        value: FieldValue,
+    },
+}
+
+/// A template as it is re-announced downstream.
+#[derive(Debug, Clone)]
+struct MediatedTemplate {
+    template_id: u16,
+    sources: Vec<FieldSource>,
+}
+
+/// Builder for [`Mediator`].
+#[derive(Debug, Default, Clone)]
+pub struct MediatorBuilder {
+    transforms: Vec<FieldTransform>,
+    observation_domain_id: Option<u32>,
+}
+
+impl MediatorBuilder {
+    /// Adds a transform applied after the ones already added.
+    pub fn with_transform(mut self, transform: FieldTransform) -> Self {
+        self.transforms.push(transform);
+        self
+    }
+
+    /// Forwards every message under this observation domain instead of its own.  Templates of
+    /// different domains still get distinct ids.
+    pub fn with_observation_domain_id(mut self, observation_domain_id: u32) -> Self {
+        self.observation_domain_id = Some(observation_domain_id);
+        self
+    }
+
+    /// Fails if a transform renames to, or adds, a field without an information element
+    /// number, or adds a value that can't be exported.
+    pub fn build(self) -> Result<Mediator, NetflowError> {
+        for transform in self.transforms.iter() {
+            let field = match transform {
+                FieldTransform::Drop(field) => field,
+                FieldTransform::Rename { to, .. } => to,
+                FieldTransform::Add { field, value } => {
+                    let length = value.to_be_bytes()?.len();
+                    if length == 0 || u16::try_from(length).is_err() {
+                        return Err(NetflowError::ConfigError(format!(
+                            "{field:?} value can't be exported in {length} bytes"
+                        )));
+                    }
+                    field
+                }
+            };
+            if matches!(field, IPFixField::Enterprise | IPFixField::Unknown) {
+                return Err(NetflowError::ConfigError(format!(
+                    "{field:?} is not an information element"
+                )));
+            }
+        }
+        Ok(Mediator {
+            transforms: self.transforms,
+            observation_domain_id: self.observation_domain_id,
+            templates: BTreeMap::new(),
+            next_template_ids: BTreeMap::new(),
+            sequence_numbers: BTreeMap::new(),
+            skipped_sets: 0,
+        })
+    }
+}
+
+/// Rewrites IPFix messages with a list of [`FieldTransform`]s.
+#[derive(Debug)]
+pub struct Mediator {
+    transforms: Vec<FieldTransform>,
+    observation_domain_id: Option<u32>,
+    /// Rewritten templates by original observation domain and template id
+    templates: BTreeMap<(u32, u16), MediatedTemplate>,
+    /// Next free template id by forwarded observation domain
+    next_template_ids: BTreeMap<u32, u16>,
+    /// Data records forwarded so far by forwarded observation domain
+    sequence_numbers: BTreeMap<u32, u32>,
+    skipped_sets: u64,
+}
+
+impl Mediator {
+    pub fn builder() -> MediatorBuilder {
+        MediatorBuilder::default()
+    }
+
+    /// Data sets left out because their template wasn't seen.
+    pub fn skipped_sets(&self) -> u64 {
+        self.skipped_sets
+    }
+
+    /// Rewrites a message.  Its export time is kept, the sequence number counts the data
+    /// records forwarded for the observation domain.  Fails if the rewritten message would be
+    /// longer than 65,535 bytes, or a domain runs out of template ids.
+    pub fn mediate(&mut self, ipfix: &IPFix) -> Result<IPFix, NetflowError> {
+        let domain = ipfix.header.observation_domain_id;
+        let forwarded_domain = self.observation_domain_id.unwrap_or(domain);
+        let mut flowsets = Vec::new();
+        let mut record_count: u32 = 0;
+
+        for flowset in ipfix.flowsets.iter() {
+            let body = &flowset.body;
+            let (set_id, body) = if let Some(template) = &body.templates {
+                let (template_id, fields, _) =
+                    self.learn(domain, template.template_id, &template.fields, 0)?;
+                let template = Template {
+                    template_id,
+                    field_count: fields.len() as u16,
+                    fields,
+                };
+                let body = FlowSetBody {
+                    templates: Some(Arc::new(template)),
+                    ..Default::default()
+                };
+                (TEMPLATE_SET_ID, body)
+            } else if let Some(template) = &body.options_templates {
+                let (template_id, fields, scope_field_count) = self.learn(
+                    domain,
+                    template.template_id,
+                    &template.fields,
+                    template.scope_field_count,
+                )?;
+                let body = FlowSetBody {
+                    options_templates: Some(Arc::new(OptionsTemplate::new(
+                        template_id,
+                        scope_field_count,
+                        fields,
+                    ))),
+                    ..Default::default()
+                };
+                (OPTIONS_TEMPLATE_SET_ID, body)
+            } else if body.data.is_some() || body.options_data.is_some() {
+                let Some(template) = self.templates.get(&(domain, flowset.header.header_id))
+                else {
+                    self.skipped_sets += 1;
+                    continue;
+                };
+                let body = match (&body.data, &body.options_data) {
+                    (Some(data), _) => {
+                        let data_fields = rewrite_records(template, &data.data_fields);
+                        record_count = record_count.wrapping_add(data_fields.len() as u32);
+                        FlowSetBody {
+                            data: Some(Data {
+                                template_id: template.template_id,
+                                data_fields,
+                                warnings: Vec::new(),
+                                template: None,
+                            }),
+                            ..Default::default()
+                        }
+                    }
+                    (None, Some(options_data)) => {
+                        let data_fields = rewrite_records(template, &options_data.data_fields);
+                        record_count = record_count.wrapping_add(data_fields.len() as u32);
+                        FlowSetBody {
+                            options_data: Some(OptionsData {
+                                template_id: template.template_id,
+                                scope_field_count: scope_field_count(
+                                    template,
+                                    options_data.scope_field_count,
+                                ),
+                                data_fields,
+                                warnings: Vec::new(),
+                                template: None,
+                            }),
+                            ..Default::default()
+                        }
+                    }
+                    (None, None) => continue,
+                };
+                (template.template_id, body)
+            } else {
+                continue;
+            };
+            flowsets.push(FlowSet {
+                header: FlowSetHeader {
+                    header_id: set_id,
+                    length: 0,
+                },
+                body,
+            });
+        }
+
+        let mut length = HEADER_LENGTH;
+        for flowset in flowsets.iter_mut() {
+            let set_length = SET_HEADER_LENGTH + set_body_length(&flowset.body)?;
+            flowset.header.length = u16::try_from(set_length).unwrap_or(u16::MAX);
+            length += set_length;
+        }
+        let length = u16::try_from(length).map_err(|_| NetflowError::InvalidLength {
+            version: 10,
+            declared: length,
+            available: usize::from(u16::MAX),
+        })?;
+
+        let sequence_number = self.sequence_numbers.entry(forwarded_domain).or_default();
+        let header = Header {
+            version: 10,
+            length,
+            export_time: ipfix.header.export_time,
+            sequence_number: *sequence_number,
+            observation_domain_id: forwarded_domain,
+        };
+        *sequence_number = sequence_number.wrapping_add(record_count);
+        Ok(IPFix {
+            header,
+            flowsets,
+            received_at: ipfix.received_at,
+            digest: None,
+        })
+    }
+
+    /// Applies the transforms to a template, caching how its records are rewritten.  Returns
+    /// the new template id, fields and scope field count.
+    fn learn(
+        &mut self,
+        domain: u32,
+        original_id: u16,
+        fields: &[TemplateField],
+        original_scope_field_count: u16,
+    ) -> Result<(u16, Vec<TemplateField>, u16), NetflowError> {
+        let mut sources = Vec::new();
+        let mut new_fields = Vec::new();
+        let mut scope_field_count = 0;
+        for (index, template_field) in fields.iter().enumerate() {
+            let mut field = Some(template_field.field_type);
+            if template_field.enterprise_number.is_none() {
+                for transform in self.transforms.iter() {
+                    field = match (transform, field) {
+                        (FieldTransform::Drop(dropped), Some(current))
+                            if *dropped == current =>
+                        {
+                            None
+                        }
+                        (FieldTransform::Rename { from, to }, Some(current))
+                            if *from == current =>
+                        {
+                            Some(*to)
+                        }
+                        _ => field,
+                    };
+                }
+            }
+            let Some(field) = field else {
+                continue;
+            };
+            if index < usize::from(original_scope_field_count) {
+                scope_field_count += 1;
+            }
+            let mut new_field = template_field.clone();
+            if field != template_field.field_type {
+                new_field.field_type_number = field as u16;
+                new_field.field_type = field;
+            }
+            sources.push(FieldSource::Record { index, field });
+            new_fields.push(new_field);
+        }
+        for transform in self.transforms.iter() {
+            if let FieldTransform::Add { field, value } = transform {
+                new_fields.push(TemplateField {
+                    field_type_number: *field as u16,
+                    field_type: *field,
+                    // Checked when the mediator was built
+                    field_length: value.to_be_bytes()?.len() as u16,
+                    enterprise_number: None,
+                });
+                sources.push(FieldSource::Added {
+                    field: *field,
+                    value: value.clone(),
+                });
+            }
+        }
+
+        let template_id = match self.templates.get(&(domain, original_id)) {
+            Some(template) => template.template_id,
+            None => {
+                let forwarded_domain = self.observation_domain_id.unwrap_or(domain);
+                let next = self
+                    .next_template_ids
+                    .entry(forwarded_domain)
+                    .or_insert(FIRST_TEMPLATE_ID);
+                let template_id = *next;
+                *next = next.checked_add(1).ok_or_else(|| {
+                    NetflowError::ConfigError(format!(
+                        "no template ids left in observation domain {forwarded_domain}"
+                    ))
+                })?;
+                template_id
+            }
+        };
+        self.templates.insert(
+            (domain, original_id),
+            MediatedTemplate {
+                template_id,
+                sources,
+            },
+        );
+        Ok((template_id, new_fields, scope_field_count))
+    }
+}
+
+/// Rewrites records with a template's field sources.  Records missing a field are left out.
+fn rewrite_records(template: &MediatedTemplate, records: &[Record]) -> Vec<Record> {
+    records
+        .iter()
+        .filter_map(|record| {
+            template
+                .sources
+                .iter()
+                .enumerate()
+                .map(|(position, source)| match source {
+                    FieldSource::Record { index, field } => record
+                        .get(index)
+                        .map(|(_, value)| (position, (*field, value.clone()))),
+                    FieldSource::Added { field, value } => {
+                        Some((position, (*field, value.clone())))
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Scope fields of the original record that are still forwarded.
+fn scope_field_count(template: &MediatedTemplate, original_scope_field_count: u16) -> u16 {
+    template
+        .sources
+        .iter()
+        .filter(|source| {
+            matches!(source, FieldSource::Record { index, .. }
+                if *index < usize::from(original_scope_field_count))
+        })
+        .count() as u16
+}
+
+/// Bytes taken by a rewritten set after its header.
+pub(crate) fn set_body_length(body: &FlowSetBody) -> Result<usize, NetflowError> {
+    let descriptors = |fields: &[TemplateField]| {
+        fields
+            .iter()
+            .map(TemplateField::descriptor_length)
+            .sum::<usize>()
+    };
+    let records = |records: &[Record]| {
+        records
+            .iter()
+            .flat_map(|record| record.values())
+            .try_fold(0, |length, (_, value)| {
+                Ok::<_, NetflowError>(length + value.to_be_bytes()?.len())
+            })
+    };
+    if let Some(template) = &body.templates {
+        Ok(4 + descriptors(&template.fields))
+    } else if let Some(template) = &body.options_templates {
+        Ok(6 + descriptors(&template.fields))
+    } else if let Some(data) = &body.data {
+        records(&data.data_fields)
+    } else if let Some(options_data) = &body.options_data {
+        records(&options_data.data_fields)
+    } else {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod mediation_tests {
+    use super::{FieldTransform, Mediator};
+    use crate::variable_versions::data_number::{DataNumber, FieldValue};
+    use crate::variable_versions::ipfix_lookup::IPFixField;
+    use crate::{NetflowError, NetflowPacket, NetflowParser};
+
+    // Template 256 (SourceIpv4address, DestinationIpv4address, PacketDeltaCount) and 2 records
+    const PACKET: [u8; 64] = [
+        0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0, 4,
+        0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2, 0, 2,
+        0, 1, 2, 3, 4, 5, 6, 7,
+    ];
+
+    fn mediate(mediator: &mut Mediator, packet: &[u8]) -> Vec<u8> {
+        match NetflowParser::default().parse_bytes(packet).pop() {
+            Some(NetflowPacket::IPFix(ipfix)) => {
+                mediator.mediate(&ipfix).unwrap().to_be_bytes().unwrap()
+            }
+            other => panic!("expected IPFix packet, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn it_rewrites_templates_and_records() {
+        let mut mediator = Mediator::builder()
+            .with_transform(FieldTransform::Drop(IPFixField::SourceIpv4address))
+            .with_transform(FieldTransform::Rename {
+                from: IPFixField::PacketDeltaCount,
+                to: IPFixField::PacketTotalCount,
+            })
+            .with_transform(FieldTransform::Add {
+                field: IPFixField::IngressInterface,
+                value: FieldValue::DataNumber(DataNumber::U32(7)),
+            })
+            .with_observation_domain_id(9)
+            .build()
+            .unwrap();
+        let bytes = mediate(&mut mediator, &PACKET);
+
+        let mut parser = NetflowParser::default();
+        let Some(NetflowPacket::IPFix(forwarded)) = parser.parse_bytes(&bytes).pop() else {
+            panic!("expected IPFix packet");
+        };
+        assert_eq!(usize::from(forwarded.header.length), bytes.len());
+        assert_eq!(forwarded.header.observation_domain_id, 9);
+        assert_eq!(forwarded.header.sequence_number, 0);
+        let template = forwarded.flowsets[0].body.templates.as_ref().unwrap();
+        assert_eq!(template.template_id, 256);
+        let fields: Vec<_> = template.fields.iter().map(|f| f.field_type).collect();
+        assert_eq!(
+            fields,
+            vec![
+                IPFixField::DestinationIpv4address,
+                IPFixField::PacketTotalCount,
+                IPFixField::IngressInterface
+            ]
+        );
+        let record = &forwarded.flowsets[1]
+            .body
+            .data
+            .as_ref()
+            .unwrap()
+            .data_fields[0];
+        assert_eq!(
+            record[&2],
+            (
+                IPFixField::IngressInterface,
+                FieldValue::DataNumber(DataNumber::U32(7))
+            )
+        );
+
+        // The next message continues the sequence after the 2 records forwarded
+        let Some(NetflowPacket::IPFix(next)) =
+            parser.parse_bytes(&mediate(&mut mediator, &PACKET)).pop()
+        else {
+            panic!("expected IPFix packet");
+        };
+        assert_eq!(next.header.sequence_number, 2);
+    }
+
+    #[test]
+    fn it_skips_data_without_a_template() {
+        let Some(NetflowPacket::IPFix(mut data_only)) =
+            NetflowParser::default().parse_bytes(&PACKET).pop()
+        else {
+            panic!("expected IPFix packet");
+        };
+        // The mediator never sees the template set
+        data_only.flowsets.remove(0);
+
+        let mut mediator = Mediator::builder().build().unwrap();
+        let forwarded = mediator.mediate(&data_only).unwrap();
+        assert!(forwarded.flowsets.is_empty());
+        assert_eq!(mediator.skipped_sets(), 1);
+    }
+
+    #[test]
+    fn it_rejects_fields_without_an_information_element() {
+        let result = Mediator::builder()
+            .with_transform(FieldTransform::Rename {
+                from: IPFixField::PacketDeltaCount,
+                to: IPFixField::Enterprise,
+            })
+            .build();
+        assert!(matches!(result, Err(NetflowError::ConfigError(_))));
+    }
+}
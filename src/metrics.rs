@@ -0,0 +1,269 @@
+//! # Metrics
+//!
+//! Counters kept by [`NetflowParser`](crate::NetflowParser) and a [`MetricsSnapshot`] that renders them in the
+//! Prometheus text exposition format.
+//!
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,];
+//! let mut parser = NetflowParser::default();
+//! parser.parse_bytes(&v5_packet);
+//!
+//! let snapshot = parser.metrics_snapshot();
+//! assert_eq!(snapshot.parser.packets_v5, 1);
+//! assert!(snapshot
+//!     .to_prometheus()
+//!     .contains("netflow_packets_total{version=\"5\"} 1\n"));
+//! ```
+
+use crate::{NetflowError, ParsedNetflow};
+
+use serde::Serialize;
+
+use alloc::{format, string::String, vec, vec::Vec};
+use core::fmt::Write;
+
+/// Counters updated by every packet a [`NetflowParser`](crate::NetflowParser) parses.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ParserMetrics {
+    pub packets_v5: u64,
+    pub packets_v7: u64,
+    pub packets_v9: u64,
+    pub packets_ipfix: u64,
+    /// Packets that failed to parse
+    pub parse_errors: u64,
+    /// Packets of versions that aren't allowed
+    pub skipped_packets: u64,
+    /// Bytes of parsed packets
+    pub bytes_processed: u64,
+    /// Parsed packets with a known `processing_delay`
+    pub delayed_packets: u64,
+    /// Sum of the processing delays of `delayed_packets`, in milliseconds
+    pub processing_delay_ms: u64,
+    /// Longest processing delay, in milliseconds
+    pub max_processing_delay_ms: u64,
+}
+
+impl ParserMetrics {
+    pub(crate) fn record(
+        &mut self,
+        version: u16,
+        packet_len: usize,
+        result: &Result<ParsedNetflow, NetflowError>,
+    ) {
+        match result {
+            Ok(parsed) => {
+                let counter = match version {
+                    5 => &mut self.packets_v5,
+                    7 => &mut self.packets_v7,
+                    9 => &mut self.packets_v9,
+                    _ => &mut self.packets_ipfix,
+                };
+                *counter += 1;
+                self.bytes_processed += (packet_len - parsed.remaining.len()) as u64;
+                if let Some(delay) = parsed.result.processing_delay() {
+                    let delay_ms = u64::try_from(delay.as_millis()).unwrap_or(u64::MAX);
+                    self.delayed_packets += 1;
+                    self.processing_delay_ms =
+                        self.processing_delay_ms.saturating_add(delay_ms);
+                    self.max_processing_delay_ms = self.max_processing_delay_ms.max(delay_ms);
+                }
+            }
+            Err(NetflowError::UnallowedVersion(_)) => self.skipped_packets += 1,
+            Err(_) => self.parse_errors += 1,
+        }
+    }
+}
+
+/// Point in time copy of a parser's counters and template cache totals.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MetricsSnapshot {
+    pub parser: ParserMetrics,
+    /// Templates announced for the first time, V9 and IPFix
+    pub templates_added: u64,
+    /// Templates redefined with a different layout, V9 and IPFix
+    pub templates_redefined: u64,
+    /// Template announcements rejected by the template rate limit
+    pub templates_rejected: u64,
+    /// Templates and options templates currently cached, including scoped ones
+    pub cached_templates: u64,
+}
+
+/// Labels added to every sample of a snapshot, such as `[("exporter", "10.0.0.1")]`.
+pub type MetricLabels = Vec<(String, String)>;
+
+/// A value with an optional extra label, such as the netflow version.
+type Sample = (Option<(&'static str, &'static str)>, u64);
+
+struct Family {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    samples: fn(&MetricsSnapshot) -> Vec<Sample>,
+}
+
+const FAMILIES: &[Family] = &[
+    Family {
+        name: "netflow_packets_total",
+        kind: "counter",
+        help: "Netflow packets parsed by version.",
+        samples: |s| {
+            vec![
+                (Some(("version", "5")), s.parser.packets_v5),
+                (Some(("version", "7")), s.parser.packets_v7),
+                (Some(("version", "9")), s.parser.packets_v9),
+                (Some(("version", "10")), s.parser.packets_ipfix),
+            ]
+        },
+    },
+    Family {
+        name: "netflow_parse_errors_total",
+        kind: "counter",
+        help: "Netflow packets that failed to parse.",
+        samples: |s| vec![(None, s.parser.parse_errors)],
+    },
+    Family {
+        name: "netflow_skipped_packets_total",
+        kind: "counter",
+        help: "Netflow packets skipped because their version is not allowed.",
+        samples: |s| vec![(None, s.parser.skipped_packets)],
+    },
+    Family {
+        name: "netflow_bytes_total",
+        kind: "counter",
+        help: "Bytes of parsed netflow packets.",
+        samples: |s| vec![(None, s.parser.bytes_processed)],
+    },
+    Family {
+        name: "netflow_processing_delay_milliseconds_total",
+        kind: "counter",
+        help: "Sum of the delays from export to receipt of packets with a known receive time.",
+        samples: |s| vec![(None, s.parser.processing_delay_ms)],
+    },
+    Family {
+        name: "netflow_delayed_packets_total",
+        kind: "counter",
+        help: "Packets with a known receive time and processing delay.",
+        samples: |s| vec![(None, s.parser.delayed_packets)],
+    },
+    Family {
+        name: "netflow_max_processing_delay_milliseconds",
+        kind: "gauge",
+        help: "Longest delay from export to receipt of a packet.",
+        samples: |s| vec![(None, s.parser.max_processing_delay_ms)],
+    },
+    Family {
+        name: "netflow_template_events_total",
+        kind: "counter",
+        help: "V9 and IPFix template cache changes.",
+        samples: |s| {
+            vec![
+                (Some(("event", "added")), s.templates_added),
+                (Some(("event", "redefined")), s.templates_redefined),
+            ]
+        },
+    },
+    Family {
+        name: "netflow_templates_rejected_total",
+        kind: "counter",
+        help: "Template announcements rejected by the template rate limit.",
+        samples: |s| vec![(None, s.templates_rejected)],
+    },
+    Family {
+        name: "netflow_cached_templates",
+        kind: "gauge",
+        help: "V9 and IPFix templates currently cached.",
+        samples: |s| vec![(None, s.cached_templates)],
+    },
+];
+
+impl MetricsSnapshot {
+    /// Renders the snapshot in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        render_prometheus(&[(vec![], *self)])
+    }
+}
+
+/// Renders several labelled snapshots, such as one per exporter, in the Prometheus text
+/// exposition format.  Samples are grouped by metric so each family is described once.
+pub fn render_prometheus(snapshots: &[(MetricLabels, MetricsSnapshot)]) -> String {
+    let mut out = String::new();
+    for family in FAMILIES {
+        let _ = writeln!(out, "# HELP {} {}", family.name, family.help);
+        let _ = writeln!(out, "# TYPE {} {}", family.name, family.kind);
+        for (labels, snapshot) in snapshots {
+            for (extra, value) in (family.samples)(snapshot) {
+                let labels: Vec<String> = labels
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .chain(extra)
+                    .map(|(k, v)| format!("{k}=\"{}\"", escape_label(v)))
+                    .collect();
+                match labels.is_empty() {
+                    true => {
+                        let _ = writeln!(out, "{} {value}", family.name);
+                    }
+                    false => {
+                        let _ =
+                            writeln!(out, "{}{{{}}} {value}", family.name, labels.join(","));
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use super::{render_prometheus, MetricsSnapshot};
+    use crate::testing::V9_PACKET;
+    use crate::NetflowParser;
+
+    #[test]
+    fn it_counts_packets_errors_and_templates() {
+        let v9_packet = V9_PACKET;
+        let mut parser = NetflowParser::builder()
+            .with_allowed_versions([9, 10])
+            .build()
+            .unwrap();
+        parser.parse_bytes(&v9_packet);
+        parser.parse_bytes(&[0, 10, 0, 40]);
+        parser.parse_bytes(&[0, 5, 0, 1]);
+
+        let snapshot = parser.metrics_snapshot();
+        assert_eq!(snapshot.parser.packets_v9, 1);
+        assert_eq!(snapshot.parser.bytes_processed, v9_packet.len() as u64);
+        assert_eq!(snapshot.parser.parse_errors, 1);
+        assert_eq!(snapshot.parser.skipped_packets, 1);
+        assert_eq!(snapshot.templates_added, 1);
+        assert_eq!(snapshot.cached_templates, 1);
+    }
+
+    #[test]
+    fn it_renders_labelled_prometheus_text() {
+        let snapshot = MetricsSnapshot {
+            templates_rejected: 3,
+            ..Default::default()
+        };
+        let text = render_prometheus(&[
+            (vec![("exporter".into(), "10.0.0.1".into())], snapshot),
+            (vec![("exporter".into(), "10.0.0.2".into())], snapshot),
+        ]);
+        assert_eq!(
+            text.matches("# TYPE netflow_templates_rejected_total")
+                .count(),
+            1
+        );
+        assert!(text.contains("netflow_templates_rejected_total{exporter=\"10.0.0.2\"} 3\n"));
+        assert!(text.contains("netflow_packets_total{exporter=\"10.0.0.1\",version=\"9\"} 0\n"));
+    }
+}
@@ -1,25 +1,41 @@
-use std::collections::BTreeMap;
-use std::net::IpAddr;
+use alloc::collections::BTreeMap;
+use core::net::IpAddr;
+use core::time::Duration;
 
+use crate::digest::fnv1a;
 use crate::protocol::ProtocolTypes;
-use crate::static_versions::{v5::V5, v7::V7};
-use crate::variable_versions::data_number::FieldValue;
+use crate::static_versions::v5::{self, V5};
+use crate::static_versions::v7::{self, V7};
+use crate::types::{Asn, Port};
+use crate::uptime::{uptime_elapsed, uptime_value};
+use crate::variable_versions::data_number::{
+    DataNumber, FieldDataType, FieldValue, StringDecoding,
+};
 use crate::variable_versions::ipfix_lookup::IPFixField;
-use crate::variable_versions::v9_lookup::V9Field;
-use crate::variable_versions::{ipfix::IPFix, v9::V9};
+use crate::variable_versions::v9_lookup::{ScopeFieldType, V9Field};
+use crate::variable_versions::{
+    ipfix::{self, IPFix, IPFixFieldPair, OptionsData as IPFixOptionsData},
+    v9::{self, OptionsData as V9OptionsData, V9FieldPair, V9},
+};
 use crate::NetflowPacket;
+use alloc::{format, string::String, vec, vec::Vec};
+
+use serde::Serialize;
 
 #[derive(Debug)]
 pub enum NetflowCommonError {
     UnknownVersion(NetflowPacket),
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 /// Common structure for Netflow
 pub struct NetflowCommon {
     pub version: u16,
     pub timestamp: u32,
     pub flowsets: Vec<NetflowCommonFlowSet>,
+    /// V9/IPFix options data records
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<NetflowCommonOptionsRecord>,
 }
 
 impl TryFrom<&NetflowPacket> for NetflowCommon {
@@ -36,7 +52,7 @@ impl TryFrom<&NetflowPacket> for NetflowCommon {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 /// Common flow set structure for Netflow
 pub struct NetflowCommonFlowSet {
     /// Source IP address
@@ -44,44 +60,958 @@ pub struct NetflowCommonFlowSet {
     /// Destination IP address
     pub dst_addr: Option<IpAddr>,
     /// TCP/UDP source port number or equivalent
-    pub src_port: Option<u16>,
-    /// TCP/UDP destination port number or equivalent
-    pub dst_port: Option<u16>,
+    pub src_port: Option<Port>,
+    /// TCP/UDP destination port number or equivalent.  `None` for ICMP flows, whose type and
+    /// code exporters send in its place.
+    pub dst_port: Option<Port>,
+    /// ICMP or ICMPv6 type of ICMP flows
+    pub icmp_type: Option<u8>,
+    /// ICMP or ICMPv6 code of ICMP flows
+    pub icmp_code: Option<u8>,
+    /// Source AS number
+    pub src_as: Option<Asn>,
+    /// Destination AS number
+    pub dst_as: Option<Asn>,
     /// Number of IP protocol type (for example, TCP = 6; UDP = 17)
     pub protocol_number: Option<u8>,
     /// IP protocol type itself
     pub protocol_type: Option<ProtocolTypes>,
+    /// Union of the TCP flags of the flow's packets.  IPFix exporters sending 2 bytes include
+    /// the header flags of RFC 7125 above the low 8 bits.
+    pub tcp_flags: Option<u16>,
+    /// IP type of service byte, see [`NetflowCommonFlowSet::dscp`]
+    pub tos: Option<u8>,
+    /// Address of the next hop router
+    pub next_hop: Option<IpAddr>,
+    /// The 20 bit IPv6 flow label
+    pub flow_label: Option<u32>,
+    /// Next header of the IPv6 packets, for IPFix exporters sending `nextHeaderIPv6`
+    pub ipv6_next_header: Option<u8>,
+    /// 802.1Q VLAN id of the incoming packets
+    pub src_vlan: Option<u16>,
+    /// 802.1Q VLAN id of the outgoing packets
+    pub dst_vlan: Option<u16>,
+    /// Overlay the flow was carried in, for IPFix exporters sending tunnel fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<TunnelInfo>,
     /// Duration of the flow first
     pub first_seen: Option<u32>,
     /// Duration of the flow last
     pub last_seen: Option<u32>,
+    /// Start of the flow since the unix epoch, from `first_seen` and the packet header across
+    /// wraps of the uptime counter, or from IPFix `flowStart` timestamps
+    pub start_time: Option<Duration>,
+    /// End of the flow since the unix epoch, from `last_seen` or IPFix `flowEnd` timestamps
+    pub end_time: Option<Duration>,
     /// Source MAC address
     pub src_mac: Option<String>,
     /// Destination MAC address
     pub dst_mac: Option<String>,
+    /// Time from the end of the flow to the export of its packet, when the packet carries both
+    pub export_delay: Option<Duration>,
+    /// Whether `export_delay` is over the `LateFlowChecker` threshold, `None` until checked
+    pub late: Option<bool>,
+    /// SNMP index of the input interface
+    pub input_interface: Option<u32>,
+    /// SNMP index of the output interface
+    pub output_interface: Option<u32>,
+    /// Whether the flow was observed entering or leaving its interface
+    pub direction: Option<FlowDirection>,
+    /// Octets in the flow, incoming octets for exporters that also count `out_bytes`
+    pub bytes: Option<u64>,
+    /// Packets in the flow, incoming packets for exporters that also count `out_packets`
+    pub packets: Option<u64>,
+    /// Outgoing octets, `OutBytes` (V9) or the RFC 5103 `reverseOctetDeltaCount` (IPFix)
+    pub out_bytes: Option<u64>,
+    /// Outgoing packets, `OutPkts` (V9) or the RFC 5103 `reversePacketDeltaCount` (IPFix)
+    pub out_packets: Option<u64>,
+    /// One of this many packets was sampled to build the flow, from the V5 header or the
+    /// sampling interval fields of V9/IPFix records.  `None` for unsampled flows.
+    pub sampling_interval: Option<u32>,
+    /// Name of the input interface, set by an `InterfaceNameCache`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_interface_name: Option<String>,
+    /// Name of the output interface, set by an `InterfaceNameCache`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_interface_name: Option<String>,
+    /// ISO 3166-1 code of the source country, set by an `Enrichment`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub src_country: Option<String>,
+    /// ISO 3166-1 code of the destination country, set by an `Enrichment`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dst_country: Option<String>,
+    /// Name of the source address from its PTR record, set by a `DnsEnricher`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub src_hostname: Option<String>,
+    /// Name of the destination address from its PTR record, set by a `DnsEnricher`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dst_hostname: Option<String>,
+}
+
+/// Tunnel or overlay a flow was carried in.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct TunnelInfo {
+    /// `tunnelTechnology`, such as `"vxlan"` or `"gre"`
+    pub technology: Option<String>,
+    /// VxLAN network identifier, from a `layer2SegmentId` of the VxLAN segment type
+    pub vxlan_vni: Option<u32>,
+    /// Key of the GRE header
+    pub gre_key: Option<u32>,
+}
+
+impl TunnelInfo {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// VxLAN network identifier of a `layer2SegmentId` (IE 351): the low 24 bits, when the top
+/// octet holds segment type 1, VxLAN.
+pub fn vxlan_vni(layer2_segment_id: u64) -> Option<u32> {
+    (layer2_segment_id >> 56 == 1).then_some((layer2_segment_id & 0xff_ffff) as u32)
+}
+
+/// ICMP type and code of a flow of `protocol` from `type_code`, `type * 256 + code` as
+/// exporters send them in `icmpTypeCodeIPv4`/`icmpTypeCodeIPv6` or the destination port.
+/// `None` unless `protocol` is ICMP (1) or ICMPv6 (58).
+pub fn icmp_type_code(protocol: u8, type_code: u16) -> Option<(u8, u8)> {
+    let [icmp_type, icmp_code] = type_code.to_be_bytes();
+    matches!(protocol, 1 | 58).then_some((icmp_type, icmp_code))
+}
+
+/// `type * 256 + code` of a V9 record, from its combined or separate ICMP fields, or its
+/// destination port.
+pub(crate) fn v9_icmp<'a>(get: impl Fn(V9Field) -> Option<&'a FieldValue>) -> Option<u16> {
+    icmp_fields(
+        get(V9Field::IcmpType),
+        get(V9Field::IcmpTypeValue).or_else(|| get(V9Field::IcmpIpv6TypeValue)),
+        get(V9Field::IcmpCodeValue).or_else(|| get(V9Field::ImpIpv6CodeValue)),
+    )
+    .or_else(|| get(V9Field::L4DstPort).and_then(|v| v.try_into().ok()))
+}
+
+/// `type * 256 + code` of an IPFix record, from its combined or separate ICMP fields, or its
+/// destination port.
+pub(crate) fn ipfix_icmp<'a>(
+    get: impl Fn(IPFixField) -> Option<&'a FieldValue>,
+) -> Option<u16> {
+    icmp_fields(
+        get(IPFixField::IcmpTypeCodeIpv4).or_else(|| get(IPFixField::IcmpTypeCodeIpv6)),
+        get(IPFixField::IcmpTypeIpv4).or_else(|| get(IPFixField::IcmpTypeIpv6)),
+        get(IPFixField::IcmpCodeIpv4).or_else(|| get(IPFixField::IcmpCodeIpv6)),
+    )
+    .or_else(|| get(IPFixField::DestinationTransportPort).and_then(|v| v.try_into().ok()))
+}
+
+/// `type * 256 + code` from a combined field, or separate type and code fields.
+fn icmp_fields(
+    combined: Option<&FieldValue>,
+    icmp_type: Option<&FieldValue>,
+    icmp_code: Option<&FieldValue>,
+) -> Option<u16> {
+    combined.and_then(|v| v.try_into().ok()).or_else(|| {
+        let icmp_type = u8::try_from(icmp_type?).ok()?;
+        let icmp_code = icmp_code.and_then(|v| v.try_into().ok()).unwrap_or(0);
+        Some(u16::from_be_bytes([icmp_type, icmp_code]))
+    })
+}
+
+/// Direction a flow was observed in at its interface, from `flowDirection` (field 61).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum FlowDirection {
+    Ingress,
+    Egress,
+}
+
+impl TryFrom<u8> for FlowDirection {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, u8> {
+        match value {
+            0 => Ok(Self::Ingress),
+            1 => Ok(Self::Egress),
+            _ => Err(value),
+        }
+    }
+}
+
+/// The 5-tuple of a flow, for bucketing flows into maps.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct FlowKey {
+    pub src_addr: Option<IpAddr>,
+    pub dst_addr: Option<IpAddr>,
+    pub src_port: Option<Port>,
+    pub dst_port: Option<Port>,
+    pub protocol_number: Option<u8>,
+}
+
+impl FlowKey {
+    /// The key with its endpoints ordered, lowest address and port first, so both directions
+    /// of a conversation share a key.
+    pub fn normalized(self) -> Self {
+        if (self.dst_addr, self.dst_port) < (self.src_addr, self.src_port) {
+            Self {
+                src_addr: self.dst_addr,
+                dst_addr: self.src_addr,
+                src_port: self.dst_port,
+                dst_port: self.src_port,
+                protocol_number: self.protocol_number,
+            }
+        } else {
+            self
+        }
+    }
+
+    /// 64 bit FNV-1a hash of the key, stable across platforms and releases unlike `Hash`,
+    /// for sharding flows across workers or storing with them.
+    pub fn hash_key(&self) -> u64 {
+        fn addr(bytes: &mut Vec<u8>, addr: Option<IpAddr>) {
+            match addr {
+                Some(IpAddr::V4(addr)) => {
+                    bytes.push(4);
+                    bytes.extend_from_slice(&addr.octets());
+                }
+                Some(IpAddr::V6(addr)) => {
+                    bytes.push(6);
+                    bytes.extend_from_slice(&addr.octets());
+                }
+                None => bytes.push(0),
+            }
+        }
+        fn optional<const N: usize>(bytes: &mut Vec<u8>, value: Option<[u8; N]>) {
+            bytes.push(u8::from(value.is_some()));
+            bytes.extend_from_slice(&value.unwrap_or([0; N]));
+        }
+        let mut bytes = Vec::with_capacity(44);
+        addr(&mut bytes, self.src_addr);
+        addr(&mut bytes, self.dst_addr);
+        optional(&mut bytes, self.src_port.map(|port| port.0.to_be_bytes()));
+        optional(&mut bytes, self.dst_port.map(|port| port.0.to_be_bytes()));
+        optional(&mut bytes, self.protocol_number.map(u8::to_be_bytes));
+        fnv1a(&bytes)
+    }
+}
+
+impl NetflowCommonFlowSet {
+    /// The 5-tuple of the flow.  With `normalize` both directions of a conversation get the
+    /// same key, see [`FlowKey::normalized`].
+    pub fn flow_key(&self, normalize: bool) -> FlowKey {
+        let key = FlowKey {
+            src_addr: self.src_addr,
+            dst_addr: self.dst_addr,
+            src_port: self.src_port,
+            dst_port: self.dst_port,
+            protocol_number: self.protocol_number,
+        };
+        if normalize {
+            key.normalized()
+        } else {
+            key
+        }
+    }
+
+    /// [`FlowKey::hash_key`] of the flow's key.
+    pub fn hash_key(&self, normalize: bool) -> u64 {
+        self.flow_key(normalize).hash_key()
+    }
+
+    /// Adds `out_bytes` and `out_packets` into `bytes` and `packets` when both are present,
+    /// for exporters counting both directions of a conversation in one record.  The merged
+    /// outgoing counters are cleared so they aren't counted twice.
+    pub fn merge_counters(&mut self) {
+        if let (Some(bytes), Some(out_bytes)) = (self.bytes, self.out_bytes) {
+            self.bytes = Some(bytes.saturating_add(out_bytes));
+            self.out_bytes = None;
+        }
+        if let (Some(packets), Some(out_packets)) = (self.packets, self.out_packets) {
+            self.packets = Some(packets.saturating_add(out_packets));
+            self.out_packets = None;
+        }
+    }
+
+    /// Scales the counters of a sampled flow up by its `sampling_interval`, estimating the
+    /// traffic it stands for.  The interval is cleared so counters aren't scaled twice.
+    pub fn scale_by_sampling(&mut self) {
+        let Some(interval) = self.sampling_interval.take() else {
+            return;
+        };
+        for counter in [
+            &mut self.bytes,
+            &mut self.packets,
+            &mut self.out_bytes,
+            &mut self.out_packets,
+        ] {
+            *counter = counter.map(|count| count.saturating_mul(interval.into()));
+        }
+    }
+
+    /// Time from the first to the last packet of the flow, from `first_seen` and `last_seen`
+    /// across wraps of the uptime counter, or else from `start_time` and `end_time`.
+    pub fn duration(&self) -> Option<Duration> {
+        match (self.first_seen, self.last_seen) {
+            (Some(first), Some(last)) => Some(uptime_elapsed(first, last)),
+            _ => self.end_time?.checked_sub(self.start_time?),
+        }
+    }
+
+    /// Differentiated services code point, the upper 6 bits of `tos`.
+    pub fn dscp(&self) -> Option<u8> {
+        self.tos.map(|tos| tos >> 2)
+    }
+
+    /// Sets `icmp_type` and `icmp_code` of ICMP flows from `type_code`, in place of
+    /// `dst_port`.
+    fn with_icmp(mut self, type_code: Option<u16>) -> Self {
+        let icmp = self
+            .protocol_number
+            .zip(type_code)
+            .and_then(|(protocol, type_code)| icmp_type_code(protocol, type_code));
+        if let Some((icmp_type, icmp_code)) = icmp {
+            self.icmp_type = Some(icmp_type);
+            self.icmp_code = Some(icmp_code);
+            self.dst_port = None;
+        }
+        self
+    }
+
+    /// IPv6 traffic class, which exporters send as `tos`.  `None` for IPv4 flows.
+    pub fn traffic_class(&self) -> Option<u8> {
+        match self.src_addr.or(self.dst_addr)? {
+            IpAddr::V6(_) => self.tos,
+            IpAddr::V4(_) => None,
+        }
+    }
+}
+
+/// Options data record, such as exporter statistics, sampling settings or interface names, with
+/// fields keyed by their V9/IPFix field name.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct NetflowCommonOptionsRecord {
+    /// What the options apply to, such as an interface or the exporting process
+    pub scope: Vec<(String, FieldValue)>,
+    pub options: Vec<(String, FieldValue)>,
+}
+
+impl NetflowCommonOptionsRecord {
+    /// Value of the scope or option field named `name`.
+    pub fn get(&self, name: &str) -> Option<&FieldValue> {
+        self.scope
+            .iter()
+            .chain(&self.options)
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Decodes the raw bytes of a V9 options field as `field_type`.
+fn decode_option_value(bytes: &[u8], field_type: FieldDataType) -> FieldValue {
+    FieldValue::parse_or_unknown(
+        bytes,
+        field_type,
+        bytes.len() as u16,
+        StringDecoding::default(),
+    )
+    .map_or_else(|_| FieldValue::Unknown(bytes.to_vec()), |(_, value)| value)
+}
+
+impl From<&V9OptionsData> for NetflowCommonOptionsRecord {
+    fn from(value: &V9OptionsData) -> Self {
+        let scope = value
+            .scope_fields
+            .iter()
+            .flat_map(|field| {
+                [
+                    (ScopeFieldType::System, &field.system),
+                    (ScopeFieldType::Interface, &field.interface),
+                    (ScopeFieldType::LineCard, &field.line_card),
+                    (ScopeFieldType::NetflowCache, &field.net_flow_cache),
+                    (ScopeFieldType::Template, &field.template),
+                ]
+            })
+            .filter_map(|(scope_type, bytes)| {
+                let value =
+                    decode_option_value(bytes.as_ref()?, FieldDataType::UnsignedDataNumber);
+                Some((format!("{scope_type:?}"), value))
+            })
+            .collect();
+        let options = value
+            .options_fields
+            .iter()
+            .map(|field| {
+                (
+                    format!("{:?}", field.field_type),
+                    decode_option_value(&field.field_value, field.field_type.into()),
+                )
+            })
+            .collect();
+        Self { scope, options }
+    }
+}
+
+/// IPFix options data records, split into scope and option fields.
+fn ipfix_options_records(
+    options_data: &IPFixOptionsData,
+) -> impl Iterator<Item = NetflowCommonOptionsRecord> + '_ {
+    let scope_field_count = usize::from(options_data.scope_field_count);
+    options_data.data_fields.iter().map(move |record| {
+        let mut common = NetflowCommonOptionsRecord::default();
+        for (index, (field, value)) in record {
+            let fields = if *index < scope_field_count {
+                &mut common.scope
+            } else {
+                &mut common.options
+            };
+            fields.push((format!("{field:?}"), value.clone()));
+        }
+        common
+    })
+}
+
+/// Flags flows exported long after they ended, such as flows buffered by an exporter or
+/// delivered late, so pipelines that bill or aggregate by time window can tell them apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LateFlowChecker {
+    /// Flows with an `export_delay` over this are late
+    pub threshold: Duration,
+}
+
+impl LateFlowChecker {
+    pub fn new(threshold: Duration) -> Self {
+        Self { threshold }
+    }
+
+    /// Whether the flow is late, `None` if its `export_delay` isn't known.
+    pub fn is_late(&self, flowset: &NetflowCommonFlowSet) -> Option<bool> {
+        flowset.export_delay.map(|delay| delay > self.threshold)
+    }
+
+    /// Sets `late` on every flowset.
+    pub fn annotate(&self, common: &mut NetflowCommon) {
+        for flowset in common.flowsets.iter_mut() {
+            flowset.late = self.is_late(flowset);
+        }
+    }
+}
+
+/// Name and description of an interface, as exported in options data.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct InterfaceName {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Learns interface names from the options records exporters send, mapping SNMP interface
+/// indexes to `IfName`/`IfDesc` (V9) or `InterfaceName`/`InterfaceDescription` (IPFix).
+///
+/// Indexes are only unique per exporter, so mappings are kept per source: the V9 source id or
+/// IPFix observation domain id of the packet.  At most `max_interfaces` are kept, 65536 unless
+/// set, dropping the least recently learned one to make room.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceNameCache {
+    /// Interfaces with when they were last learned, by the count of `learned`
+    interfaces: BTreeMap<(u32, u32), (InterfaceName, u64)>,
+    learned: u64,
+    max_interfaces: usize,
+    evicted: u64,
+}
+
+/// Interfaces an `InterfaceNameCache` keeps unless `with_max_interfaces` is set.
+const DEFAULT_MAX_INTERFACES: usize = 65_536;
+
+impl Default for InterfaceNameCache {
+    fn default() -> Self {
+        Self {
+            interfaces: BTreeMap::new(),
+            learned: 0,
+            max_interfaces: DEFAULT_MAX_INTERFACES,
+            evicted: 0,
+        }
+    }
+}
+
+/// Fields carrying the SNMP index an options record applies to.
+const INTERFACE_INDEX_FIELDS: [&str; 3] = ["Interface", "IngressInterface", "InputSnmp"];
+
+fn option_string(record: &NetflowCommonOptionsRecord, names: [&str; 2]) -> Option<String> {
+    names.iter().find_map(|name| {
+        let value = String::try_from(record.get(name)?).ok()?;
+        let value = value.trim_end_matches('\0');
+        (!value.is_empty()).then(|| value.into())
+    })
+}
+
+impl InterfaceNameCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps at most `max_interfaces` interfaces, dropping the least recently learned one when
+    /// a new one is learned.
+    pub fn with_max_interfaces(mut self, max_interfaces: usize) -> Self {
+        self.max_interfaces = max_interfaces;
+        self
+    }
+
+    /// Learns the interface an options record names, if it has an index and a name or
+    /// description.  Returns whether it did.
+    pub fn learn_record(&mut self, source: u32, record: &NetflowCommonOptionsRecord) -> bool {
+        let Some(ifindex) = INTERFACE_INDEX_FIELDS
+            .iter()
+            .find_map(|name| interface_index(record.get(name)?))
+        else {
+            return false;
+        };
+        let name = option_string(record, ["IfName", "InterfaceName"]);
+        let description = option_string(record, ["IfDesc", "InterfaceDescription"]);
+        if name.is_none() && description.is_none() {
+            return false;
+        }
+        self.learned += 1;
+        self.interfaces.insert(
+            (source, ifindex),
+            (InterfaceName { name, description }, self.learned),
+        );
+        while self.interfaces.len() > self.max_interfaces {
+            let Some(oldest) = self
+                .interfaces
+                .iter()
+                .min_by_key(|(_, (_, learned))| *learned)
+                .map(|(key, _)| *key)
+            else {
+                break;
+            };
+            self.interfaces.remove(&oldest);
+            self.evicted += 1;
+        }
+        true
+    }
+
+    /// Learns the interfaces in the options records of `common`.  Returns how many were learned.
+    pub fn learn(&mut self, source: u32, common: &NetflowCommon) -> usize {
+        common
+            .options
+            .iter()
+            .filter(|record| self.learn_record(source, record))
+            .count()
+    }
+
+    /// Name and description of interface `ifindex` of `source`, if they were learned.
+    pub fn resolve_interface(&self, source: u32, ifindex: u32) -> Option<&InterfaceName> {
+        self.interfaces
+            .get(&(source, ifindex))
+            .map(|(interface, _)| interface)
+    }
+
+    /// Forgets the interfaces of `source`, such as an exporter that went away.
+    pub fn forget_source(&mut self, source: u32) {
+        self.interfaces
+            .retain(|(interface_source, _), _| *interface_source != source);
+    }
+
+    /// Interfaces dropped to make room.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// Sets `input_interface_name` and `output_interface_name` on every flowset whose
+    /// interfaces have a learned name.
+    pub fn annotate(&self, source: u32, common: &mut NetflowCommon) {
+        let name =
+            |ifindex: Option<u32>| self.resolve_interface(source, ifindex?)?.name.clone();
+        for flowset in common.flowsets.iter_mut() {
+            flowset.input_interface_name = name(flowset.input_interface);
+            flowset.output_interface_name = name(flowset.output_interface);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.interfaces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.interfaces.is_empty()
+    }
+}
+
+/// V9 protocol fields are parsed as a `ProtocolType`, IPFix ones as a number.
+pub(crate) fn protocol_number(value: &FieldValue) -> Option<u8> {
+    match value {
+        FieldValue::ProtocolType(protocol) => Some((*protocol).into()),
+        _ => u8::try_from(value).ok(),
+    }
+}
+
+/// AS numbers are exported as 2 or 4 bytes.
+pub(crate) fn asn(value: &FieldValue) -> Option<Asn> {
+    match value {
+        FieldValue::DataNumber(DataNumber::U16(asn)) => Some((*asn).into()),
+        _ => u32::try_from(value).ok().map(Asn),
+    }
+}
+
+/// Interface indexes are exported as 2 or 4 bytes.
+pub(crate) fn interface_index(value: &FieldValue) -> Option<u32> {
+    match value {
+        FieldValue::DataNumber(DataNumber::U16(index)) => Some((*index).into()),
+        _ => u32::try_from(value).ok(),
+    }
+}
+
+/// Counters are exported as 1, 2, 4 or 8 bytes.
+pub(crate) fn counter(value: &FieldValue) -> Option<u64> {
+    match value {
+        FieldValue::DataNumber(DataNumber::U8(count)) => Some((*count).into()),
+        FieldValue::DataNumber(DataNumber::U16(count)) => Some((*count).into()),
+        FieldValue::DataNumber(DataNumber::U32(count)) => Some((*count).into()),
+        _ => u64::try_from(value).ok(),
+    }
+}
+
+/// IPv6 flow labels are exported as 3 or 4 bytes, of which the low 20 bits are the label.
+pub(crate) fn flow_label(value: &FieldValue) -> Option<u32> {
+    match value {
+        FieldValue::DataNumber(DataNumber::U24(label) | DataNumber::U32(label)) => {
+            Some(label & 0xf_ffff)
+        }
+        _ => None,
+    }
+}
+
+/// VLAN ids are the low 12 bits of the 802.1Q tag control field, exporters sending the whole
+/// field include the priority bits above them.
+pub(crate) fn vlan_id(value: &FieldValue) -> Option<u16> {
+    u16::try_from(value).ok().map(|tci| tci & 0x0fff)
+}
+
+/// Sampling intervals of 1 mean every packet was accounted, and 0 that sampling is off.
+fn sampling_interval(value: &FieldValue) -> Option<u32> {
+    counter(value)
+        .filter(|interval| *interval > 1)
+        .map(|interval| u32::try_from(interval).unwrap_or(u32::MAX))
+}
+
+/// TCP flags are exported as 1 byte, or as 2 bytes with the header flags of RFC 7125.
+pub(crate) fn tcp_flags(value: &FieldValue) -> Option<u16> {
+    match value {
+        FieldValue::DataNumber(DataNumber::U8(flags)) => Some((*flags).into()),
+        _ => u16::try_from(value).ok(),
+    }
+}
+
+/// Delay between a flow's last packet and the export, both in milliseconds of exporter uptime,
+/// across wraps of the uptime counter.  Flows ending after the export have no delay.
+fn uptime_export_delay(sys_up_time: u32, last: u32) -> Duration {
+    let delay = sys_up_time.wrapping_sub(last) as i32;
+    Duration::from_millis(u64::try_from(delay).unwrap_or(0))
+}
+
+/// Absolute time of an IPFix flow's start or end, from the first of `fields` present.
+fn ipfix_time(
+    values: &BTreeMap<IPFixField, &FieldValue>,
+    fields: [IPFixField; 4],
+) -> Option<Duration> {
+    fields
+        .iter()
+        .find_map(|field| match values.get(field).copied() {
+            Some(
+                FieldValue::DateTimeSeconds(time)
+                | FieldValue::DateTimeMillis(time)
+                | FieldValue::DateTimeMicros(time)
+                | FieldValue::DateTimeNanos(time),
+            ) => Some(*time),
+            _ => None,
+        })
+}
+
+/// Delay between an IPFix flow's end and the export, from whichever end time field is present.
+fn ipfix_export_delay(
+    export_time: u32,
+    values: &BTreeMap<IPFixField, &FieldValue>,
+) -> Option<Duration> {
+    if let Some(delta) = values
+        .get(&IPFixField::FlowEndDeltaMicroseconds)
+        .and_then(|v| u32::try_from(*v).ok())
+    {
+        return Some(Duration::from_micros(delta.into()));
+    }
+    let flow_end = [IPFixField::FlowEndMilliseconds, IPFixField::FlowEndSeconds]
+        .iter()
+        .find_map(|field| match values.get(field).copied() {
+            Some(FieldValue::DateTimeMillis(end) | FieldValue::DateTimeSeconds(end)) => {
+                Some(*end)
+            }
+            _ => None,
+        })?;
+    Some(Duration::from_secs(export_time.into()).saturating_sub(flow_end))
+}
+
+/// Calls `f` with each flow of `packet`, converted one at a time instead of collected into a
+/// [`NetflowCommon`], for consumers that process flows as a stream.  Options data is skipped
+/// and packets without a common form, such as errors, have no flows.
+pub fn for_each_common_flow(packet: &NetflowPacket, mut f: impl FnMut(NetflowCommonFlowSet)) {
+    match packet {
+        NetflowPacket::V5(v5) => {
+            v5.flowsets
+                .iter()
+                .for_each(|set| f(v5_flow(&v5.header, set)));
+        }
+        NetflowPacket::V7(v7) => {
+            v7.flowsets
+                .iter()
+                .for_each(|set| f(v7_flow(&v7.header, set)));
+        }
+        NetflowPacket::V9(v9) => {
+            v9.flowsets
+                .iter()
+                .filter_map(|flowset| flowset.body.data.as_ref())
+                .flat_map(|data| data.data_fields.iter())
+                .for_each(|record| f(v9_flow(&v9.header, record)));
+        }
+        NetflowPacket::IPFix(ipfix) => {
+            let export_time = ipfix.header.export_time;
+            ipfix
+                .flowsets
+                .iter()
+                .filter_map(|flowset| flowset.body.data.as_ref())
+                .flat_map(|data| data.data_fields.iter().map(move |record| (data, record)))
+                .for_each(|(data, record)| {
+                    f(ipfix_flow(export_time, record, data.template.as_deref()))
+                });
+        }
+        NetflowPacket::Error(_) => {}
+    }
+}
+
+fn v5_flow(header: &v5::Header, set: &v5::FlowSet) -> NetflowCommonFlowSet {
+    NetflowCommonFlowSet {
+        src_addr: Some(set.src_addr.into()),
+        dst_addr: Some(set.dst_addr.into()),
+        src_port: Some(set.src_port.into()),
+        dst_port: Some(set.dst_port.into()),
+        src_as: Some(set.src_as.into()),
+        dst_as: Some(set.dst_as.into()),
+        protocol_number: Some(set.protocol_number),
+        protocol_type: Some(set.protocol_type),
+        tcp_flags: Some(set.tcp_flags.into()),
+        tos: Some(set.tos),
+        next_hop: Some(set.next_hop.into()),
+        first_seen: Some(set.first),
+        last_seen: Some(set.last),
+        start_time: Some(header.uptime_to_unix_time(set.first)),
+        end_time: Some(header.uptime_to_unix_time(set.last)),
+        export_delay: Some(uptime_export_delay(header.sys_up_time, set.last)),
+        input_interface: Some(set.input.into()),
+        output_interface: Some(set.output.into()),
+        bytes: Some(set.d_octets.into()),
+        packets: Some(set.d_pkts.into()),
+        sampling_interval: header.sampling().filter(|rate| *rate > 1).map(u32::from),
+        ..Default::default()
+    }
+    .with_icmp(Some(set.dst_port))
+}
+
+fn v7_flow(header: &v7::Header, set: &v7::FlowSet) -> NetflowCommonFlowSet {
+    NetflowCommonFlowSet {
+        src_addr: Some(set.src_addr.into()),
+        dst_addr: Some(set.dst_addr.into()),
+        src_port: Some(set.src_port.into()),
+        dst_port: Some(set.dst_port.into()),
+        src_as: Some(set.src_as.into()),
+        dst_as: Some(set.dst_as.into()),
+        protocol_number: Some(set.protocol_number),
+        protocol_type: Some(set.protocol_type),
+        tcp_flags: Some(set.tcp_flags.into()),
+        tos: Some(set.tos),
+        next_hop: Some(set.next_hop.into()),
+        first_seen: Some(set.first),
+        last_seen: Some(set.last),
+        start_time: Some(header.uptime_to_unix_time(set.first)),
+        end_time: Some(header.uptime_to_unix_time(set.last)),
+        export_delay: Some(uptime_export_delay(header.sys_up_time, set.last)),
+        input_interface: Some(set.input.into()),
+        output_interface: Some(set.output.into()),
+        bytes: Some(set.d_octets.into()),
+        packets: Some(set.d_pkts.into()),
+        ..Default::default()
+    }
+    .with_icmp(Some(set.dst_port))
+}
+
+fn v9_flow(header: &v9::Header, record: &BTreeMap<usize, V9FieldPair>) -> NetflowCommonFlowSet {
+    let values: BTreeMap<V9Field, &FieldValue> = record
+        .values()
+        .map(|(field, value)| (*field, value))
+        .collect();
+    let get = |field: V9Field| values.get(&field).copied();
+    let first_seen = get(V9Field::FirstSwitched).and_then(uptime_value);
+    let last_seen = get(V9Field::LastSwitched).and_then(uptime_value);
+    NetflowCommonFlowSet {
+        src_addr: get(V9Field::Ipv4SrcAddr)
+            .or_else(|| get(V9Field::Ipv6SrcAddr))
+            .and_then(|v| v.try_into().ok()),
+        dst_addr: get(V9Field::Ipv4DstAddr)
+            .or_else(|| get(V9Field::Ipv6DstAddr))
+            .and_then(|v| v.try_into().ok()),
+        src_port: get(V9Field::L4SrcPort)
+            .and_then(|v| u16::try_from(v).ok())
+            .map(Port),
+        dst_port: get(V9Field::L4DstPort)
+            .and_then(|v| u16::try_from(v).ok())
+            .map(Port),
+        src_as: get(V9Field::SrcAs).and_then(asn),
+        dst_as: get(V9Field::DstAs).and_then(asn),
+        protocol_number: get(V9Field::Protocol).and_then(protocol_number),
+        protocol_type: get(V9Field::Protocol)
+            .and_then(protocol_number)
+            .map(ProtocolTypes::from),
+        tcp_flags: get(V9Field::TcpFlags).and_then(tcp_flags),
+        tos: get(V9Field::SrcTos).and_then(|v| v.try_into().ok()),
+        next_hop: get(V9Field::Ipv4NextHop)
+            .or_else(|| get(V9Field::Ipv6NextHop))
+            .and_then(|v| v.try_into().ok()),
+        flow_label: get(V9Field::Ipv6FlowLabel).and_then(flow_label),
+        src_vlan: get(V9Field::SrcVlan).and_then(vlan_id),
+        dst_vlan: get(V9Field::DstVlan).and_then(vlan_id),
+        first_seen,
+        last_seen,
+        start_time: first_seen.map(|first| header.uptime_to_unix_time(first)),
+        end_time: last_seen.map(|last| header.uptime_to_unix_time(last)),
+        src_mac: get(V9Field::InSrcMac).and_then(|v| v.try_into().ok()),
+        dst_mac: get(V9Field::InDstMac).and_then(|v| v.try_into().ok()),
+        export_delay: last_seen.map(|last| uptime_export_delay(header.sys_up_time, last)),
+        input_interface: get(V9Field::InputSnmp).and_then(interface_index),
+        output_interface: get(V9Field::OutputSnmp).and_then(interface_index),
+        direction: get(V9Field::Direction)
+            .and_then(|v| u8::try_from(v).ok())
+            .and_then(|v| v.try_into().ok()),
+        bytes: get(V9Field::InBytes).and_then(counter),
+        packets: get(V9Field::InPkts).and_then(counter),
+        out_bytes: get(V9Field::OutBytes).and_then(counter),
+        out_packets: get(V9Field::OutPkts).and_then(counter),
+        sampling_interval: get(V9Field::SamplingInterval)
+            .or_else(|| get(V9Field::FlowSamplerRandomInterval))
+            .and_then(sampling_interval),
+        ..Default::default()
+    }
+    .with_icmp(v9_icmp(get))
+}
+
+/// Private enterprise number of the RFC 5103 reverse information elements, each numbered as
+/// the element it reverses.
+const REVERSE_INFORMATION_ELEMENT_PEN: u32 = 29305;
+
+fn ipfix_flow(
+    export_time: u32,
+    record: &BTreeMap<usize, IPFixFieldPair>,
+    template: Option<&ipfix::Template>,
+) -> NetflowCommonFlowSet {
+    let values: BTreeMap<IPFixField, &FieldValue> = record
+        .values()
+        .map(|(field, value)| (*field, value))
+        .collect();
+    let get = |field: IPFixField| values.get(&field).copied();
+    // Enterprise fields are only told apart by their template
+    let reverse = |forward: IPFixField| {
+        template?
+            .fields
+            .iter()
+            .position(|field| {
+                field.enterprise_number == Some(REVERSE_INFORMATION_ELEMENT_PEN)
+                    && field.field_type_number == forward as u16
+            })
+            .and_then(|index| record.get(&index))
+            .map(|(_, value)| value)
+    };
+    NetflowCommonFlowSet {
+        src_addr: get(IPFixField::SourceIpv4address)
+            .or_else(|| get(IPFixField::SourceIpv6address))
+            .and_then(|v| v.try_into().ok()),
+        dst_addr: get(IPFixField::DestinationIpv4address)
+            .or_else(|| get(IPFixField::DestinationIpv6address))
+            .and_then(|v| v.try_into().ok()),
+        src_port: get(IPFixField::SourceTransportPort)
+            .and_then(|v| u16::try_from(v).ok())
+            .map(Port),
+        dst_port: get(IPFixField::DestinationTransportPort)
+            .and_then(|v| u16::try_from(v).ok())
+            .map(Port),
+        src_as: get(IPFixField::BgpSourceAsNumber).and_then(asn),
+        dst_as: get(IPFixField::BgpDestinationAsNumber).and_then(asn),
+        protocol_number: get(IPFixField::ProtocolIdentifier).and_then(|v| v.try_into().ok()),
+        protocol_type: get(IPFixField::ProtocolIdentifier)
+            .and_then(|v| u8::try_from(v).ok())
+            .map(ProtocolTypes::from),
+        tcp_flags: get(IPFixField::TcpControlBits).and_then(tcp_flags),
+        tos: get(IPFixField::IpClassOfService).and_then(|v| v.try_into().ok()),
+        next_hop: get(IPFixField::IpNextHopIpv4address)
+            .or_else(|| get(IPFixField::IpNextHopIpv6address))
+            .and_then(|v| v.try_into().ok()),
+        flow_label: get(IPFixField::FlowLabelIpv6).and_then(flow_label),
+        ipv6_next_header: get(IPFixField::NextHeaderIpv6).and_then(|v| v.try_into().ok()),
+        src_vlan: get(IPFixField::VlanId)
+            .or_else(|| get(IPFixField::Dot1qVlanId))
+            .and_then(vlan_id),
+        dst_vlan: get(IPFixField::PostVlanId)
+            .or_else(|| get(IPFixField::PostDot1qVlanId))
+            .and_then(vlan_id),
+        tunnel: Some(TunnelInfo {
+            technology: get(IPFixField::TunnelTechnology).and_then(|v| v.try_into().ok()),
+            vxlan_vni: get(IPFixField::Layer2segmentId)
+                .and_then(|v| u64::try_from(v).ok())
+                .and_then(vxlan_vni),
+            gre_key: get(IPFixField::GreKey).and_then(|v| v.try_into().ok()),
+        })
+        .filter(|tunnel| !tunnel.is_empty()),
+        first_seen: get(IPFixField::FlowStartSysUpTime).and_then(|v| v.try_into().ok()),
+        last_seen: get(IPFixField::FlowEndSysUpTime).and_then(|v| v.try_into().ok()),
+        start_time: ipfix_time(
+            &values,
+            [
+                IPFixField::FlowStartNanoseconds,
+                IPFixField::FlowStartMicroseconds,
+                IPFixField::FlowStartMilliseconds,
+                IPFixField::FlowStartSeconds,
+            ],
+        ),
+        end_time: ipfix_time(
+            &values,
+            [
+                IPFixField::FlowEndNanoseconds,
+                IPFixField::FlowEndMicroseconds,
+                IPFixField::FlowEndMilliseconds,
+                IPFixField::FlowEndSeconds,
+            ],
+        ),
+        src_mac: get(IPFixField::SourceMacaddress).and_then(|v| v.try_into().ok()),
+        dst_mac: get(IPFixField::DestinationMacaddress).and_then(|v| v.try_into().ok()),
+        export_delay: ipfix_export_delay(export_time, &values),
+        input_interface: get(IPFixField::IngressInterface).and_then(interface_index),
+        output_interface: get(IPFixField::EgressInterface).and_then(interface_index),
+        direction: get(IPFixField::FlowDirection)
+            .and_then(|v| u8::try_from(v).ok())
+            .and_then(|v| v.try_into().ok()),
+        bytes: get(IPFixField::OctetDeltaCount).and_then(counter),
+        packets: get(IPFixField::PacketDeltaCount).and_then(counter),
+        out_bytes: reverse(IPFixField::OctetDeltaCount).and_then(counter),
+        out_packets: reverse(IPFixField::PacketDeltaCount).and_then(counter),
+        sampling_interval: get(IPFixField::SamplingInterval)
+            .or_else(|| get(IPFixField::SamplerRandomInterval))
+            .and_then(sampling_interval),
+        ..Default::default()
+    }
+    .with_icmp(ipfix_icmp(get))
 }
 
 impl From<&V5> for NetflowCommon {
     fn from(value: &V5) -> Self {
-        // Convert V5 to NetflowCommon
         NetflowCommon {
             version: value.header.version,
             timestamp: value.header.sys_up_time,
+            options: vec![],
             flowsets: value
                 .flowsets
                 .iter()
-                .map(|set| NetflowCommonFlowSet {
-                    src_addr: Some(set.src_addr.into()),
-                    dst_addr: Some(set.dst_addr.into()),
-                    src_port: Some(set.src_port),
-                    dst_port: Some(set.dst_port),
-                    protocol_number: Some(set.protocol_number),
-                    protocol_type: Some(set.protocol_type),
-                    first_seen: Some(set.first),
-                    last_seen: Some(set.last),
-                    src_mac: None,
-                    dst_mac: None,
-                })
+                .map(|set| v5_flow(&value.header, set))
                 .collect(),
         }
     }
@@ -89,25 +1019,14 @@ impl From<&V5> for NetflowCommon {
 
 impl From<&V7> for NetflowCommon {
     fn from(value: &V7) -> Self {
-        // Convert V7 to NetflowCommon
         NetflowCommon {
             version: value.header.version,
             timestamp: value.header.sys_up_time,
+            options: vec![],
             flowsets: value
                 .flowsets
                 .iter()
-                .map(|set| NetflowCommonFlowSet {
-                    src_addr: Some(set.src_addr.into()),
-                    dst_addr: Some(set.dst_addr.into()),
-                    src_port: Some(set.src_port),
-                    dst_port: Some(set.dst_port),
-                    protocol_number: Some(set.protocol_number),
-                    protocol_type: Some(set.protocol_type),
-                    first_seen: Some(set.first),
-                    last_seen: Some(set.last),
-                    src_mac: None,
-                    dst_mac: None,
-                })
+                .map(|set| v7_flow(&value.header, set))
                 .collect(),
         }
     }
@@ -115,51 +1034,19 @@ impl From<&V7> for NetflowCommon {
 
 impl From<&V9> for NetflowCommon {
     fn from(value: &V9) -> Self {
-        // Convert V9 to NetflowCommon
         let mut flowsets = vec![];
+        let mut options = vec![];
 
         for flowset in &value.flowsets {
+            if let Some(options_data) = &flowset.body.options_data {
+                options.push(options_data.into());
+            }
             if let Some(data) = &flowset.body.data {
-                for data_field in &data.data_fields {
-                    let value_map: BTreeMap<V9Field, FieldValue> =
-                        data_field.values().cloned().collect();
-                    flowsets.push(NetflowCommonFlowSet {
-                        src_addr: value_map
-                            .get(&V9Field::Ipv4SrcAddr)
-                            .or_else(|| value_map.get(&V9Field::Ipv6SrcAddr))
-                            .and_then(|v| v.try_into().ok()),
-                        dst_addr: value_map
-                            .get(&V9Field::Ipv4DstAddr)
-                            .or_else(|| value_map.get(&V9Field::Ipv6DstAddr))
-                            .and_then(|v| v.try_into().ok()),
-                        src_port: value_map
-                            .get(&V9Field::L4SrcPort)
-                            .and_then(|v| v.try_into().ok()),
-                        dst_port: value_map
-                            .get(&V9Field::L4DstPort)
-                            .and_then(|v| v.try_into().ok()),
-                        protocol_number: value_map
-                            .get(&V9Field::Protocol)
-                            .and_then(|v| v.try_into().ok()),
-                        protocol_type: value_map.get(&V9Field::Protocol).and_then(|v| {
-                            v.try_into()
-                                .ok()
-                                .map(|proto: u8| ProtocolTypes::from(proto))
-                        }),
-                        first_seen: value_map
-                            .get(&V9Field::FirstSwitched)
-                            .and_then(|v| v.try_into().ok()),
-                        last_seen: value_map
-                            .get(&V9Field::LastSwitched)
-                            .and_then(|v| v.try_into().ok()),
-                        src_mac: value_map
-                            .get(&V9Field::InSrcMac)
-                            .and_then(|v| v.try_into().ok()),
-                        dst_mac: value_map
-                            .get(&V9Field::InDstMac)
-                            .and_then(|v| v.try_into().ok()),
-                    });
-                }
+                flowsets.extend(
+                    data.data_fields
+                        .iter()
+                        .map(|record| v9_flow(&value.header, record)),
+                );
             }
         }
 
@@ -167,60 +1054,24 @@ impl From<&V9> for NetflowCommon {
             version: value.header.version,
             timestamp: value.header.sys_up_time,
             flowsets,
+            options,
         }
     }
 }
 
 impl From<&IPFix> for NetflowCommon {
     fn from(value: &IPFix) -> Self {
-        // Convert IPFix to NetflowCommon
-
         let mut flowsets = vec![];
+        let mut options = vec![];
 
         for flowset in &value.flowsets {
+            if let Some(options_data) = &flowset.body.options_data {
+                options.extend(ipfix_options_records(options_data));
+            }
             if let Some(data) = &flowset.body.data {
-                for data_field in &data.data_fields {
-                    let value_map: BTreeMap<IPFixField, FieldValue> =
-                        data_field.values().cloned().collect();
-                    flowsets.push(NetflowCommonFlowSet {
-                        src_addr: value_map
-                            .get(&IPFixField::SourceIpv4address)
-                            .or_else(|| value_map.get(&IPFixField::SourceIpv6address))
-                            .and_then(|v| v.try_into().ok()),
-                        dst_addr: value_map
-                            .get(&IPFixField::DestinationIpv4address)
-                            .or_else(|| value_map.get(&IPFixField::DestinationIpv6address))
-                            .and_then(|v| v.try_into().ok()),
-                        src_port: value_map
-                            .get(&IPFixField::SourceTransportPort)
-                            .and_then(|v| v.try_into().ok()),
-                        dst_port: value_map
-                            .get(&IPFixField::DestinationTransportPort)
-                            .and_then(|v| v.try_into().ok()),
-                        protocol_number: value_map
-                            .get(&IPFixField::ProtocolIdentifier)
-                            .and_then(|v| v.try_into().ok()),
-                        protocol_type: value_map.get(&IPFixField::ProtocolIdentifier).and_then(
-                            |v| {
-                                v.try_into()
-                                    .ok()
-                                    .map(|proto: u8| ProtocolTypes::from(proto))
-                            },
-                        ),
-                        first_seen: value_map
-                            .get(&IPFixField::FlowStartSysUpTime)
-                            .and_then(|v| v.try_into().ok()),
-                        last_seen: value_map
-                            .get(&IPFixField::FlowEndSysUpTime)
-                            .and_then(|v| v.try_into().ok()),
-                        src_mac: value_map
-                            .get(&IPFixField::SourceMacaddress)
-                            .and_then(|v| v.try_into().ok()),
-                        dst_mac: value_map
-                            .get(&IPFixField::DestinationMacaddress)
-                            .and_then(|v| v.try_into().ok()),
-                    });
-                }
+                flowsets.extend(data.data_fields.iter().map(|record| {
+                    ipfix_flow(value.header.export_time, record, data.template.as_deref())
+                }));
             }
         }
 
@@ -228,6 +1079,7 @@ impl From<&IPFix> for NetflowCommon {
             version: value.header.version,
             timestamp: value.header.export_time,
             flowsets,
+            options,
         }
     }
 }
@@ -237,14 +1089,20 @@ mod common_tests {
 
     use std::collections::BTreeMap;
     use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
 
     use crate::ipfix::{
         Data as IPFixData, FlowSet as IPFixFlowSet, FlowSetBody as IPFixFlowSetBody,
         FlowSetHeader as IPFixFlowSetHeader, Header as IPFixHeader, IPFix,
     };
-    use crate::netflow_common::NetflowCommon;
+    use crate::netflow_common::{
+        for_each_common_flow, icmp_type_code, vxlan_vni, FlowDirection, FlowKey, InterfaceName,
+        InterfaceNameCache, LateFlowChecker, NetflowCommon, NetflowCommonFlowSet,
+        NetflowCommonOptionsRecord, TunnelInfo,
+    };
     use crate::static_versions::v5::{FlowSet as V5FlowSet, Header as V5Header, V5};
     use crate::static_versions::v7::{FlowSet as V7FlowSet, Header as V7Header, V7};
+    use crate::types::{Asn, Port};
     use crate::variable_versions::data_number::{DataNumber, FieldValue};
     use crate::variable_versions::ipfix_lookup::IPFixField;
     use crate::variable_versions::v9::{
@@ -252,6 +1110,7 @@ mod common_tests {
         FlowSetHeader as V9FlowSetHeader, Header as V9Header, V9,
     };
     use crate::variable_versions::v9_lookup::V9Field;
+    use crate::NetflowParser;
 
     #[test]
     fn it_converts_v5_to_common() {
@@ -266,6 +1125,8 @@ mod common_tests {
                 engine_type: 0,
                 engine_id: 0,
                 sampling_interval: 0,
+                sampling_mode: crate::static_versions::v5::SamplingMode::Unsampled,
+                sampling_rate: 0,
             },
             flowsets: vec![V5FlowSet {
                 src_addr: Ipv4Addr::new(192, 168, 1, 1),
@@ -290,9 +1151,11 @@ mod common_tests {
                 dst_mask: 0,
                 pad2: 0,
             }],
+            received_at: None,
+            digest: None,
         };
 
-        let common: NetflowCommon = NetflowCommon::try_from(&v5).unwrap();
+        let common: NetflowCommon = NetflowCommon::from(&v5);
 
         assert_eq!(common.version, 5);
         assert_eq!(common.timestamp, 100);
@@ -306,8 +1169,8 @@ mod common_tests {
             flowset.dst_addr.unwrap(),
             IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2))
         );
-        assert_eq!(flowset.src_port.unwrap(), 1234);
-        assert_eq!(flowset.dst_port.unwrap(), 80);
+        assert_eq!(flowset.src_port, Some(Port(1234)));
+        assert_eq!(flowset.dst_port, Some(Port(80)));
         assert_eq!(flowset.protocol_number.unwrap(), 6);
         assert_eq!(
             flowset.protocol_type.unwrap(),
@@ -315,6 +1178,11 @@ mod common_tests {
         );
         assert_eq!(flowset.first_seen.unwrap(), 100);
         assert_eq!(flowset.last_seen.unwrap(), 200);
+        assert_eq!(flowset.tcp_flags, Some(0));
+        assert_eq!(
+            flowset.next_hop,
+            Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 254)))
+        );
     }
 
     #[test]
@@ -353,9 +1221,11 @@ mod common_tests {
                 flags_fields_valid: 0,
                 router_src: Ipv4Addr::new(192, 168, 1, 254),
             }],
+            received_at: None,
+            digest: None,
         };
 
-        let common: NetflowCommon = NetflowCommon::try_from(&v7).unwrap();
+        let common: NetflowCommon = NetflowCommon::from(&v7);
 
         assert_eq!(common.version, 7);
         assert_eq!(common.timestamp, 100);
@@ -369,8 +1239,8 @@ mod common_tests {
             flowset.dst_addr.unwrap(),
             IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2))
         );
-        assert_eq!(flowset.src_port.unwrap(), 1234);
-        assert_eq!(flowset.dst_port.unwrap(), 80);
+        assert_eq!(flowset.src_port, Some(Port(1234)));
+        assert_eq!(flowset.dst_port, Some(Port(80)));
         assert_eq!(flowset.protocol_number.unwrap(), 6);
         assert_eq!(
             flowset.protocol_type.unwrap(),
@@ -402,7 +1272,12 @@ mod common_tests {
                     options_templates: None,
                     options_data: None,
                     unparsed_data: None,
+                    parse_error: None,
+                    raw: None,
                     data: Some(V9Data {
+                        template_id: 256,
+                        template: None,
+                        warnings: vec![],
                         data_fields: vec![BTreeMap::from([
                             (
                                 0,
@@ -464,13 +1339,29 @@ mod common_tests {
                                     FieldValue::MacAddr("00:00:00:00:00:02".to_string()),
                                 ),
                             ),
+                            (
+                                9,
+                                (
+                                    V9Field::SrcAs,
+                                    FieldValue::DataNumber(DataNumber::U16(64512)),
+                                ),
+                            ),
+                            (
+                                10,
+                                (
+                                    V9Field::DstAs,
+                                    FieldValue::DataNumber(DataNumber::U32(4200000000)),
+                                ),
+                            ),
                         ])],
                     }),
                 },
             }],
+            received_at: None,
+            digest: None,
         };
 
-        let common: NetflowCommon = NetflowCommon::try_from(&v9).unwrap();
+        let common: NetflowCommon = NetflowCommon::from(&v9);
         assert_eq!(common.version, 9);
         assert_eq!(common.timestamp, 100);
         assert_eq!(common.flowsets.len(), 1);
@@ -483,8 +1374,8 @@ mod common_tests {
             flowset.dst_addr.unwrap(),
             IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2))
         );
-        assert_eq!(flowset.src_port.unwrap(), 1234);
-        assert_eq!(flowset.dst_port.unwrap(), 80);
+        assert_eq!(flowset.src_port, Some(Port(1234)));
+        assert_eq!(flowset.dst_port, Some(Port(80)));
         assert_eq!(flowset.protocol_number.unwrap(), 6);
         assert_eq!(
             flowset.protocol_type.unwrap(),
@@ -494,6 +1385,8 @@ mod common_tests {
         assert_eq!(flowset.last_seen.unwrap(), 200);
         assert_eq!(flowset.src_mac.as_ref().unwrap(), "00:00:00:00:00:01");
         assert_eq!(flowset.dst_mac.as_ref().unwrap(), "00:00:00:00:00:02");
+        assert_eq!(flowset.src_as, Some(Asn(64512)));
+        assert_eq!(flowset.dst_as, Some(Asn(4200000000)));
     }
 
     #[test]
@@ -516,7 +1409,13 @@ mod common_tests {
                     templates: None,
                     options_templates: None,
                     options_data: None,
+                    unparsed_data: None,
+                    parse_error: None,
+                    raw: None,
                     data: Some(IPFixData {
+                        template_id: 256,
+                        template: None,
+                        warnings: vec![],
                         data_fields: vec![BTreeMap::from([
                             (
                                 0,
@@ -585,9 +1484,11 @@ mod common_tests {
                     }),
                 },
             }],
+            received_at: None,
+            digest: None,
         };
 
-        let common: NetflowCommon = NetflowCommon::try_from(&ipfix).unwrap();
+        let common: NetflowCommon = NetflowCommon::from(&ipfix);
         assert_eq!(common.version, 10);
         assert_eq!(common.timestamp, 100);
         assert_eq!(common.flowsets.len(), 1);
@@ -600,8 +1501,8 @@ mod common_tests {
             flowset.dst_addr.unwrap(),
             IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2))
         );
-        assert_eq!(flowset.src_port.unwrap(), 1234);
-        assert_eq!(flowset.dst_port.unwrap(), 80);
+        assert_eq!(flowset.src_port, Some(Port(1234)));
+        assert_eq!(flowset.dst_port, Some(Port(80)));
         assert_eq!(flowset.protocol_number.unwrap(), 6);
         assert_eq!(
             flowset.protocol_type.unwrap(),
@@ -612,4 +1513,445 @@ mod common_tests {
         assert_eq!(flowset.src_mac.as_ref().unwrap(), "00:00:00:00:00:01");
         assert_eq!(flowset.dst_mac.as_ref().unwrap(), "00:00:00:00:00:02");
     }
+
+    #[test]
+    fn it_converts_options_records_to_common() {
+        // V9 options template 275 scoped to an interface, then its options data
+        let v9_packet = [
+            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 1, 0, 22, 1, 19, 0,
+            4, 0, 8, 0, 2, 0, 2, 0, 34, 0, 2, 0, 36, 0, 1, 1, 19, 0, 9, 0, 2, 0, 100, 1,
+        ];
+        // IPFix options template 260 with one scope field, then two options data records
+        let ipfix_packet = [
+            0, 10, 0, 64, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 3, 0, 28, 1, 4, 0, 3, 0, 1,
+            128, 123, 0, 4, 0, 0, 0, 2, 0, 41, 0, 2, 0, 42, 0, 2, 0, 0, 1, 4, 0, 20, 0, 0, 0,
+            1, 1, 20, 20, 20, 0, 0, 0, 2, 20, 20, 30, 30,
+        ];
+        let mut parser = NetflowParser::default();
+        let common = |packet: &[u8], parser: &mut NetflowParser| {
+            NetflowCommon::try_from(&parser.parse_bytes(packet)[0]).unwrap()
+        };
+
+        let v9 = common(&v9_packet, &mut parser);
+        assert!(v9.flowsets.is_empty());
+        assert_eq!(
+            v9.options,
+            vec![NetflowCommonOptionsRecord {
+                scope: vec![(
+                    "Interface".to_string(),
+                    FieldValue::DataNumber(DataNumber::U16(2))
+                )],
+                options: vec![
+                    (
+                        "SamplingInterval".to_string(),
+                        FieldValue::DataNumber(DataNumber::U16(100))
+                    ),
+                    (
+                        "FlowActiveTimeout".to_string(),
+                        FieldValue::DataNumber(DataNumber::U8(1))
+                    ),
+                ],
+            }]
+        );
+
+        let ipfix = common(&ipfix_packet, &mut parser);
+        assert_eq!(ipfix.options.len(), 2);
+        assert_eq!(ipfix.options[1].scope.len(), 1);
+        assert_eq!(
+            ipfix.options[1].get("ExportedFlowRecordTotalCount"),
+            Some(&FieldValue::DataNumber(DataNumber::U16(7710)))
+        );
+    }
+
+    #[test]
+    fn it_flags_late_ipfix_flows() {
+        let record = |field, value| BTreeMap::from([(0, (field, value))]);
+        let ipfix = IPFix {
+            header: IPFixHeader {
+                version: 10,
+                length: 0,
+                export_time: 100,
+                sequence_number: 1,
+                observation_domain_id: 0,
+            },
+            flowsets: vec![IPFixFlowSet {
+                header: IPFixFlowSetHeader {
+                    header_id: 256,
+                    length: 0,
+                },
+                body: IPFixFlowSetBody {
+                    templates: None,
+                    options_templates: None,
+                    options_data: None,
+                    unparsed_data: None,
+                    parse_error: None,
+                    raw: None,
+                    data: Some(IPFixData {
+                        template_id: 256,
+                        template: None,
+                        warnings: vec![],
+                        data_fields: vec![
+                            record(
+                                IPFixField::FlowEndMilliseconds,
+                                FieldValue::DateTimeMillis(Duration::from_millis(99_500)),
+                            ),
+                            record(
+                                IPFixField::FlowEndSeconds,
+                                FieldValue::DateTimeSeconds(Duration::from_secs(40)),
+                            ),
+                            record(
+                                IPFixField::ProtocolIdentifier,
+                                FieldValue::DataNumber(DataNumber::U8(6)),
+                            ),
+                        ],
+                    }),
+                },
+            }],
+            received_at: None,
+            digest: None,
+        };
+
+        let mut common = NetflowCommon::from(&ipfix);
+        LateFlowChecker::new(Duration::from_secs(30)).annotate(&mut common);
+
+        let delays: Vec<_> = common.flowsets.iter().map(|f| f.export_delay).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_millis(500)),
+                Some(Duration::from_secs(60)),
+                None
+            ]
+        );
+        let late: Vec<_> = common.flowsets.iter().map(|f| f.late).collect();
+        assert_eq!(late, vec![Some(false), Some(true), None]);
+    }
+
+    #[test]
+    fn it_names_interfaces_from_options_records() {
+        // V9 options template 300 naming interface 2 "wan0", its options data, then a flow
+        // from interface 2 to interface 3
+        let packet = [
+            0, 9, 0, 4, 0, 0, 0, 100, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 7, 0, 1, 0, 18, 1, 44,
+            0, 4, 0, 4, 0, 2, 0, 2, 0, 82, 0, 8, 1, 44, 0, 14, 0, 2, 119, 97, 110, 48, 0, 0, 0,
+            0, 0, 0, 0, 16, 1, 0, 0, 2, 0, 10, 0, 2, 0, 14, 0, 2, 1, 0, 0, 8, 0, 2, 0, 3,
+        ];
+        let mut parser = NetflowParser::builder()
+            .with_interface_names(true)
+            .build()
+            .unwrap();
+        let flowsets = parser.parse_bytes_as_netflow_common_flowsets(&packet);
+        assert_eq!(flowsets.len(), 1);
+        assert_eq!(flowsets[0].input_interface, Some(2));
+        assert_eq!(flowsets[0].input_interface_name.as_deref(), Some("wan0"));
+        assert_eq!(flowsets[0].output_interface, Some(3));
+        assert_eq!(flowsets[0].output_interface_name, None);
+
+        let cache = parser.interface_names.as_ref().unwrap();
+        assert_eq!(
+            cache.resolve_interface(7, 2),
+            Some(&InterfaceName {
+                name: Some("wan0".to_string()),
+                description: None,
+            })
+        );
+        assert_eq!(cache.resolve_interface(8, 2), None);
+
+        // Only the most recently learned interface is kept, here source 8's
+        parser.interface_names = Some(InterfaceNameCache::new().with_max_interfaces(1));
+        let mut other_source = packet;
+        other_source[19] = 8;
+        parser.parse_bytes_as_netflow_common_flowsets(&packet);
+        parser.parse_bytes_as_netflow_common_flowsets(&other_source);
+        let cache = parser.interface_names.as_mut().unwrap();
+        assert_eq!(cache.resolve_interface(7, 2), None);
+        assert!(cache.resolve_interface(8, 2).is_some());
+        assert_eq!(cache.evicted(), 1);
+        cache.forget_source(8);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn it_keys_flows_by_their_5_tuple() {
+        let flowset = NetflowCommonFlowSet {
+            src_addr: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))),
+            dst_addr: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))),
+            src_port: Some(Port(50000)),
+            dst_port: Some(Port(443)),
+            protocol_number: Some(6),
+            ..Default::default()
+        };
+        let reply = NetflowCommonFlowSet {
+            src_addr: flowset.dst_addr,
+            dst_addr: flowset.src_addr,
+            src_port: flowset.dst_port,
+            dst_port: flowset.src_port,
+            protocol_number: Some(6),
+            ..Default::default()
+        };
+        assert_ne!(flowset.flow_key(false), reply.flow_key(false));
+        assert_ne!(flowset.hash_key(false), reply.hash_key(false));
+        assert_eq!(flowset.flow_key(true), reply.flow_key(true));
+        assert_eq!(flowset.hash_key(true), reply.hash_key(true));
+        assert_eq!(flowset.flow_key(true), reply.flow_key(false));
+        assert_ne!(
+            FlowKey::default().hash_key(),
+            FlowKey {
+                protocol_number: Some(0),
+                ..Default::default()
+            }
+            .hash_key()
+        );
+    }
+
+    #[test]
+    fn it_maps_tcp_flags_tos_and_next_hop() {
+        // Template 256 of tcpFlags, srcTos and ipv4NextHop, and a record of it
+        let v9 = [
+            0, 9, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 20, 1, 0, 0,
+            3, 0, 6, 0, 1, 0, 5, 0, 1, 0, 15, 0, 4, 1, 0, 0, 10, 0x12, 0xb8, 192, 0, 2, 1,
+        ];
+        // The same with a 2 byte tcpControlBits carrying the NS flag
+        let ipfix = [
+            0, 10, 0, 47, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 20, 1, 0, 0, 3, 0, 6, 0,
+            2, 0, 5, 0, 1, 0, 15, 0, 4, 1, 0, 0, 11, 1, 0x12, 0xb8, 192, 0, 2, 1,
+        ];
+        let mut parser = NetflowParser::default();
+        for (packet, tcp_flags) in [(&v9[..], 0x12), (&ipfix[..], 0x112)] {
+            let flowsets = parser.parse_bytes_as_netflow_common_flowsets(packet);
+            assert_eq!(flowsets.len(), 1);
+            let flowset = &flowsets[0];
+            assert_eq!(flowset.tcp_flags, Some(tcp_flags));
+            assert_eq!(flowset.tos, Some(0xb8));
+            assert_eq!(flowset.dscp(), Some(46));
+            assert_eq!(
+                flowset.next_hop,
+                Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            );
+        }
+    }
+
+    #[test]
+    fn it_converts_flows_one_at_a_time() {
+        // V9 template 256 and a record of it, then an options template and its data
+        let v9 = [
+            0, 9, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 20, 1, 0, 0,
+            3, 0, 6, 0, 1, 0, 5, 0, 1, 0, 15, 0, 4, 1, 0, 0, 10, 0x12, 0xb8, 192, 0, 2, 1,
+        ];
+        let v9_options = [
+            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 1, 0, 22, 1, 19, 0,
+            4, 0, 8, 0, 2, 0, 2, 0, 34, 0, 2, 0, 36, 0, 1, 1, 19, 0, 9, 0, 2, 0, 100, 1,
+        ];
+        let mut parser = NetflowParser::default();
+        let mut packets = parser.parse_bytes(&v9);
+        packets.extend(parser.parse_bytes(&v9_options));
+        packets.extend(parser.parse_bytes(&[0, 1]));
+
+        let mut flows = vec![];
+        for packet in &packets {
+            for_each_common_flow(packet, |flow| flows.push(flow));
+        }
+        assert_eq!(flows.len(), 1);
+        assert_eq!(
+            format!("{flows:?}"),
+            format!(
+                "{:?}",
+                NetflowCommon::try_from(&packets[0]).unwrap().flowsets
+            )
+        );
+        assert_eq!(flows[0].tcp_flags, Some(0x12));
+    }
+
+    #[test]
+    fn it_maps_direction_and_merges_counters() {
+        // Template 256 of direction, InBytes, InPkts, OutBytes and OutPkts, and an egress
+        // record of it
+        let v9 = [
+            0, 9, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 28, 1, 0, 0,
+            5, 0, 61, 0, 1, 0, 1, 0, 4, 0, 2, 0, 4, 0, 23, 0, 4, 0, 24, 0, 4, 1, 0, 0, 21, 1,
+            0, 0, 0, 100, 0, 0, 0, 5, 0, 0, 0, 50, 0, 0, 0, 3,
+        ];
+        let flowset = &NetflowParser::default().parse_bytes_as_netflow_common_flowsets(&v9)[0];
+        assert_eq!(flowset.direction, Some(FlowDirection::Egress));
+        assert_eq!((flowset.bytes, flowset.packets), (Some(100), Some(5)));
+        assert_eq!(
+            (flowset.out_bytes, flowset.out_packets),
+            (Some(50), Some(3))
+        );
+
+        let mut parser = NetflowParser::builder()
+            .with_merged_counters(true)
+            .build()
+            .unwrap();
+        let flowset = &parser.parse_bytes_as_netflow_common_flowsets(&v9)[0];
+        assert_eq!((flowset.bytes, flowset.packets), (Some(150), Some(8)));
+        assert_eq!((flowset.out_bytes, flowset.out_packets), (None, None));
+    }
+
+    #[test]
+    fn it_maps_ipfix_reverse_counters() {
+        // Template 256 of octetDeltaCount, packetDeltaCount, postOctetDeltaCount and the
+        // RFC 5103 reverseOctetDeltaCount and reversePacketDeltaCount, and a record of it
+        let ipfix = [
+            0, 10, 0, 76, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 36, 1, 0, 0, 5, 0, 1, 0,
+            4, 0, 2, 0, 4, 0, 23, 0, 4, 128, 1, 0, 4, 0, 0, 114, 121, 128, 2, 0, 4, 0, 0, 114,
+            121, 1, 0, 0, 24, 0, 0, 0, 100, 0, 0, 0, 5, 0, 0, 0, 99, 0, 0, 0, 50, 0, 0, 0, 3,
+        ];
+        let flowset =
+            &NetflowParser::default().parse_bytes_as_netflow_common_flowsets(&ipfix)[0];
+        // postOctetDeltaCount counts the same packets after processing, not the reverse
+        assert_eq!((flowset.bytes, flowset.packets), (Some(100), Some(5)));
+        assert_eq!(
+            (flowset.out_bytes, flowset.out_packets),
+            (Some(50), Some(3))
+        );
+
+        // Outgoing counters are kept when there are no incoming ones to merge them into
+        let mut flowset = NetflowCommonFlowSet {
+            packets: Some(5),
+            out_bytes: Some(50),
+            out_packets: Some(3),
+            ..Default::default()
+        };
+        flowset.merge_counters();
+        assert_eq!((flowset.bytes, flowset.out_bytes), (None, Some(50)));
+        assert_eq!((flowset.packets, flowset.out_packets), (Some(8), None));
+    }
+
+    #[test]
+    fn it_maps_ipv6_flow_labels_and_traffic_class() {
+        // Template 256 of ipv6SrcAddr, a 3 byte ipv6FlowLabel and srcTos, and a record of it
+        let v9 = [
+            0, 9, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 20, 1, 0, 0,
+            3, 0, 27, 0, 16, 0, 31, 0, 3, 0, 5, 0, 1, 1, 0, 0, 24, 0x20, 1, 0x0d, 0xb8, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x01, 0xf6, 0x76, 0xb8,
+        ];
+        // The same with a 4 byte flowLabelIPv6 carrying stray upper bits, and nextHeaderIPv6
+        let ipfix = [
+            0, 10, 0, 66, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 24, 1, 0, 0, 4, 0, 27,
+            0, 16, 0, 31, 0, 4, 0, 193, 0, 1, 0, 5, 0, 1, 1, 0, 0, 26, 0x20, 1, 0x0d, 0xb8, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff, 0xf1, 0xf6, 0x76, 6, 0xb8,
+        ];
+        let mut parser = NetflowParser::default();
+        for (packet, next_header) in [(&v9[..], None), (&ipfix[..], Some(6))] {
+            let flowsets = parser.parse_bytes_as_netflow_common_flowsets(packet);
+            assert_eq!(flowsets.len(), 1);
+            let flowset = &flowsets[0];
+            assert_eq!(flowset.flow_label, Some(128630));
+            assert_eq!(flowset.ipv6_next_header, next_header);
+            assert_eq!(flowset.traffic_class(), Some(0xb8));
+        }
+
+        let v4 = NetflowCommonFlowSet {
+            src_addr: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            tos: Some(0xb8),
+            ..Default::default()
+        };
+        assert_eq!(v4.traffic_class(), None);
+    }
+
+    #[test]
+    fn it_maps_vlans_and_tunnels() {
+        // Template 256 of srcVlan and dstVlan, and a record of it
+        let v9 = [
+            0, 9, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 0, 0,
+            2, 0, 58, 0, 2, 0, 59, 0, 2, 1, 0, 0, 8, 0, 10, 0, 20,
+        ];
+        // Template 256 of vlanId, postVlanId with priority bits, layer2SegmentId, greKey and
+        // tunnelTechnology, and a record of it
+        let ipfix = [
+            0, 10, 0, 69, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 28, 1, 0, 0, 5, 0, 58,
+            0, 2, 0, 59, 0, 2, 1, 95, 0, 8, 1, 40, 0, 4, 1, 33, 0, 5, 1, 0, 0, 25, 0, 100,
+            0x20, 200, 1, 0, 0, 0, 0, 0x12, 0xd6, 0x87, 0, 0, 0, 42, b'v', b'x', b'l', b'a',
+            b'n',
+        ];
+        let mut parser = NetflowParser::default();
+        let v9 = &parser.parse_bytes_as_netflow_common_flowsets(&v9)[0];
+        assert_eq!((v9.src_vlan, v9.dst_vlan), (Some(10), Some(20)));
+        assert_eq!(v9.tunnel, None);
+
+        let ipfix = &parser.parse_bytes_as_netflow_common_flowsets(&ipfix)[0];
+        assert_eq!((ipfix.src_vlan, ipfix.dst_vlan), (Some(100), Some(200)));
+        assert_eq!(
+            ipfix.tunnel,
+            Some(TunnelInfo {
+                technology: Some("vxlan".to_string()),
+                vxlan_vni: Some(1234567),
+                gre_key: Some(42),
+            })
+        );
+        assert_eq!(vxlan_vni(0x0200_0000_0012_d687), None);
+    }
+
+    #[test]
+    fn it_decodes_icmp_type_and_code() {
+        // Template 256 of protocol and icmpType, and an echo reply record of it
+        let v9 = [
+            0, 9, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 0, 0,
+            2, 0, 4, 0, 1, 0, 32, 0, 2, 1, 0, 0, 7, 1, 0, 0,
+        ];
+        // Template 256 of protocolIdentifier, destinationTransportPort, icmpTypeIPv6 and
+        // icmpCodeIPv6, and a destination unreachable record of it
+        let ipfix = [
+            0, 10, 0, 49, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 24, 1, 0, 0, 4, 0, 4, 0,
+            1, 0, 11, 0, 2, 0, 178, 0, 1, 0, 179, 0, 1, 1, 0, 0, 9, 58, 0, 0, 1, 4,
+        ];
+        let mut parser = NetflowParser::default();
+        let v9 = &parser.parse_bytes_as_netflow_common_flowsets(&v9)[0];
+        assert_eq!((v9.icmp_type, v9.icmp_code), (Some(0), Some(0)));
+        let ipfix = &parser.parse_bytes_as_netflow_common_flowsets(&ipfix)[0];
+        assert_eq!((ipfix.icmp_type, ipfix.icmp_code), (Some(1), Some(4)));
+        assert_eq!(ipfix.dst_port, None);
+
+        // V5 exporters send them in the destination port
+        let mut v5 = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        v5[58..60].copy_from_slice(&[11, 1]);
+        v5[62] = 1;
+        let flowset = &parser.parse_bytes_as_netflow_common_flowsets(&v5)[0];
+        assert_eq!((flowset.icmp_type, flowset.icmp_code), (Some(11), Some(1)));
+        assert_eq!(flowset.dst_port, None);
+        assert_eq!(icmp_type_code(6, 0x0b01), None);
+    }
+
+    #[test]
+    fn it_times_v9_flows_across_uptime_wraps() {
+        // Template 256 of FirstSwitched and LastSwitched, and a flow started before the uptime
+        // counter wrapped, exported 1 second after it
+        let v9 = [
+            0, 9, 0, 2, 0, 0, 3, 232, 101, 83, 241, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1,
+            0, 0, 2, 0, 22, 0, 4, 0, 21, 0, 4, 1, 0, 0, 12, 255, 255, 248, 48, 0, 0, 1, 244,
+        ];
+        let export_time = Duration::from_secs(1_700_000_000);
+        let mut parser = NetflowParser::default();
+        let flowset = &parser.parse_bytes_as_netflow_common_flowsets(&v9)[0];
+        assert_eq!(flowset.first_seen, Some(u32::MAX - 1_999));
+        assert_eq!(flowset.last_seen, Some(500));
+        assert_eq!(
+            flowset.start_time,
+            Some(export_time - Duration::from_secs(3))
+        );
+        assert_eq!(
+            flowset.end_time,
+            Some(export_time - Duration::from_millis(500))
+        );
+        assert_eq!(flowset.duration(), Some(Duration::from_millis(2_500)));
+        assert_eq!(flowset.export_delay, Some(Duration::from_millis(500)));
+
+        let Some(crate::NetflowPacket::V9(packet)) = parser.parse_bytes(&v9).pop() else {
+            panic!("expected V9 packet");
+        };
+        let record = &packet.flowsets[1].body.data.as_ref().unwrap().data_fields[0];
+        assert_eq!(
+            crate::variable_versions::v9::flow_duration(record),
+            Some(Duration::from_millis(2_500))
+        );
+        assert_eq!(
+            packet.flow_start_time(record),
+            Some(std::time::UNIX_EPOCH + export_time - Duration::from_secs(3))
+        );
+    }
 }
@@ -0,0 +1,908 @@
+//! # PCAP
+//!
+//! Minimal reader for classic pcap and pcapng captures and extraction of the UDP payloads netflow is
+//! exported in.
+//!
+//! References:
+//! - <https://www.tcpdump.org/manpages/pcap-savefile.5.txt>
+//! - <https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html>
+//! - <https://www.tcpdump.org/linktypes.html>
+
+use crate::{NetflowPacket, NetflowParser, NetflowParserBuilder};
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+
+const BLOCK_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 1;
+const BLOCK_SIMPLE_PACKET: u32 = 3;
+const BLOCK_ENHANCED_PACKET: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const OPTION_END: u16 = 0;
+const OPTION_IF_TSRESOL: u16 = 9;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+const IP_PROTOCOL_UDP: u8 = 17;
+
+/// Longest frame read from a capture, the largest snap length tools write.  Longer captured
+/// lengths are treated as corrupt rather than allocated.
+const MAX_CAPTURED_LENGTH: usize = 256 * 1024;
+/// Longest pcapng block read, a frame of `MAX_CAPTURED_LENGTH` with room for its options.
+const MAX_BLOCK_LENGTH: usize = MAX_CAPTURED_LENGTH + 64 * 1024;
+
+/// A captured frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcapPacket {
+    /// Capture time since the unix epoch, zero for pcapng simple packet blocks
+    pub timestamp: Duration,
+    /// Link layer type of the interface the frame was captured on
+    pub link_type: u32,
+    /// Captured bytes of the frame, starting at the link layer
+    pub data: Vec<u8>,
+}
+
+/// A UDP datagram extracted from a captured frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpDatagram<'a> {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub payload: &'a [u8],
+}
+
+/// Reads packets from a classic pcap capture, in either byte order and with micro or
+/// nanosecond timestamps, or from a pcapng capture.
+#[derive(Debug)]
+pub struct PcapReader<R> {
+    reader: R,
+    big_endian: bool,
+    format: Format,
+    link_type: u32,
+    /// Snap length of a pcap capture, capped to `MAX_CAPTURED_LENGTH`
+    max_captured_length: usize,
+}
+
+#[derive(Debug)]
+enum Format {
+    Pcap {
+        nanos: bool,
+    },
+    /// Interfaces described in the current section
+    PcapNg {
+        interfaces: Vec<Interface>,
+    },
+}
+
+/// A pcapng interface description.
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    link_type: u32,
+    /// `if_tsresol`: timestamps are in units of 10^-n seconds, or 2^-n if the high bit is set
+    resolution: u8,
+}
+
+impl Interface {
+    fn timestamp(&self, units: u64) -> Duration {
+        let exponent = u32::from(self.resolution & 0x7f);
+        let (secs, fraction, per_second) = if self.resolution & 0x80 == 0 {
+            let Some(per_second) = 10u64.checked_pow(exponent) else {
+                return Duration::ZERO;
+            };
+            (
+                units / per_second,
+                units % per_second,
+                u128::from(per_second),
+            )
+        } else if exponent < 64 {
+            (
+                units >> exponent,
+                units & ((1 << exponent) - 1),
+                1u128 << exponent,
+            )
+        } else {
+            return Duration::ZERO;
+        };
+        let nanos = u128::from(fraction) * 1_000_000_000 / per_second;
+        Duration::new(secs, nanos as u32)
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Reads the global header of a pcap capture, or the section header and first interface
+    /// description of a pcapng capture.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let magic = u32::from_le_bytes(magic);
+        if magic == BLOCK_SECTION_HEADER {
+            let mut pcap = Self {
+                reader,
+                big_endian: false,
+                format: Format::PcapNg { interfaces: vec![] },
+                link_type: 0,
+                max_captured_length: MAX_CAPTURED_LENGTH,
+            };
+            pcap.read_block_body(BLOCK_SECTION_HEADER)?;
+            // Interfaces are described before any packet captured on them.
+            while let Some((block_type, body)) = pcap.read_block()? {
+                if block_type == BLOCK_INTERFACE_DESCRIPTION {
+                    pcap.add_interface(&body)?;
+                    break;
+                }
+            }
+            return Ok(pcap);
+        }
+
+        let mut header = [0u8; 20];
+        reader.read_exact(&mut header)?;
+        let (big_endian, nanos) = match (magic, magic.swap_bytes()) {
+            (MAGIC_MICROS, _) => (false, false),
+            (MAGIC_NANOS, _) => (false, true),
+            (_, MAGIC_MICROS) => (true, false),
+            (_, MAGIC_NANOS) => (true, true),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("not a pcap capture, magic number {magic:#010x}"),
+                ))
+            }
+        };
+        let mut pcap = Self {
+            reader,
+            big_endian,
+            format: Format::Pcap { nanos },
+            link_type: 0,
+            max_captured_length: MAX_CAPTURED_LENGTH,
+        };
+        pcap.link_type = pcap.read_u32(&header[16..20]);
+        // Some writers leave the snap length at 0
+        match pcap.read_u32(&header[12..16]) as usize {
+            0 => (),
+            snaplen => pcap.max_captured_length = snaplen.min(MAX_CAPTURED_LENGTH),
+        }
+        Ok(pcap)
+    }
+
+    /// Link layer type of the captured frames, of the first interface for pcapng captures.
+    pub fn link_type(&self) -> u32 {
+        self.link_type
+    }
+
+    fn read_u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        }
+    }
+
+    fn read_u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+
+    /// Reads the next packet, `None` at the end of the capture.
+    pub fn next_packet(&mut self) -> io::Result<Option<PcapPacket>> {
+        match self.format {
+            Format::Pcap { nanos } => self.next_pcap_packet(nanos),
+            Format::PcapNg { .. } => self.next_pcapng_packet(),
+        }
+    }
+
+    fn next_pcap_packet(&mut self, nanos: bool) -> io::Result<Option<PcapPacket>> {
+        let mut header = [0u8; 16];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let secs = self.read_u32(&header[0..4]) as u64;
+        let fraction = self.read_u32(&header[4..8]);
+        let captured_length = self.read_u32(&header[8..12]) as usize;
+        if captured_length > self.max_captured_length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "captured length {captured_length} over the snap length {}",
+                    self.max_captured_length
+                ),
+            ));
+        }
+
+        let mut data = vec![0u8; captured_length];
+        self.reader.read_exact(&mut data)?;
+
+        let timestamp = match nanos {
+            true => Duration::new(secs, fraction),
+            false => Duration::from_secs(secs) + Duration::from_micros(fraction as u64),
+        };
+        Ok(Some(PcapPacket {
+            timestamp,
+            link_type: self.link_type,
+            data,
+        }))
+    }
+
+    fn next_pcapng_packet(&mut self) -> io::Result<Option<PcapPacket>> {
+        while let Some((block_type, body)) = self.read_block()? {
+            match block_type {
+                BLOCK_SECTION_HEADER => {
+                    if let Format::PcapNg { interfaces } = &mut self.format {
+                        interfaces.clear();
+                    }
+                }
+                BLOCK_INTERFACE_DESCRIPTION => self.add_interface(&body)?,
+                BLOCK_ENHANCED_PACKET if body.len() >= 20 => {
+                    let interface = self.interface(self.read_u32(&body[0..4]))?;
+                    let units = (u64::from(self.read_u32(&body[4..8])) << 32)
+                        | u64::from(self.read_u32(&body[8..12]));
+                    let captured_length = self.read_u32(&body[12..16]) as usize;
+                    let data = body.get(20..20 + captured_length).ok_or_else(|| {
+                        invalid_block("enhanced packet block shorter than its captured length")
+                    })?;
+                    return Ok(Some(PcapPacket {
+                        timestamp: interface.timestamp(units),
+                        link_type: interface.link_type,
+                        data: data.to_vec(),
+                    }));
+                }
+                BLOCK_SIMPLE_PACKET if body.len() >= 4 => {
+                    let interface = self.interface(0)?;
+                    let original_length = self.read_u32(&body[0..4]) as usize;
+                    let data = &body[4..];
+                    return Ok(Some(PcapPacket {
+                        timestamp: Duration::ZERO,
+                        link_type: interface.link_type,
+                        data: data[..original_length.min(data.len())].to_vec(),
+                    }));
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    fn interface(&self, id: u32) -> io::Result<Interface> {
+        match &self.format {
+            Format::PcapNg { interfaces } => interfaces.get(id as usize).copied(),
+            Format::Pcap { .. } => None,
+        }
+        .ok_or_else(|| invalid_block(&format!("packet of undescribed interface {id}")))
+    }
+
+    fn add_interface(&mut self, body: &[u8]) -> io::Result<()> {
+        if body.len() < 8 {
+            return Err(invalid_block("interface description block too short"));
+        }
+        let mut interface = Interface {
+            link_type: u32::from(self.read_u16(&body[0..2])),
+            resolution: 6,
+        };
+        // Options are a code, a length and a value padded to 32 bits, up to an end of options.
+        let mut options = &body[8..];
+        while options.len() >= 4 {
+            let code = self.read_u16(&options[0..2]);
+            let length = usize::from(self.read_u16(&options[2..4]));
+            if code == OPTION_END {
+                break;
+            }
+            if code == OPTION_IF_TSRESOL && length == 1 && options.len() > 4 {
+                interface.resolution = options[4];
+            }
+            options = options
+                .get(4 + length.next_multiple_of(4)..)
+                .unwrap_or_default();
+        }
+        if let Format::PcapNg { interfaces } = &mut self.format {
+            if interfaces.is_empty() && self.link_type == 0 {
+                self.link_type = interface.link_type;
+            }
+            interfaces.push(interface);
+        }
+        Ok(())
+    }
+
+    /// Reads the next pcapng block, `None` at the end of the capture.  Returns the block type
+    /// and body.  Section headers set the byte order of the blocks that follow.
+    fn read_block(&mut self) -> io::Result<Option<(u32, Vec<u8>)>> {
+        let mut block_type = [0u8; 4];
+        match self.reader.read_exact(&mut block_type) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let block_type = self.read_u32(&block_type);
+        let body = self.read_block_body(block_type)?;
+        Ok(Some((block_type, body)))
+    }
+
+    fn read_block_body(&mut self, block_type: u32) -> io::Result<Vec<u8>> {
+        let mut length = [0u8; 4];
+        self.reader.read_exact(&mut length)?;
+        let mut body = vec![];
+        if block_type == BLOCK_SECTION_HEADER {
+            // The byte order magic comes before anything else is read in the section's order.
+            let mut byte_order = [0u8; 4];
+            self.reader.read_exact(&mut byte_order)?;
+            self.big_endian = match u32::from_le_bytes(byte_order) {
+                BYTE_ORDER_MAGIC => false,
+                magic if magic.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                magic => {
+                    return Err(invalid_block(&format!(
+                        "unknown pcapng byte order magic {magic:#010x}"
+                    )))
+                }
+            };
+            body.extend_from_slice(&byte_order);
+        }
+        let length = self.read_u32(&length) as usize;
+        if length < 12 + body.len() || length > MAX_BLOCK_LENGTH || !length.is_multiple_of(4) {
+            return Err(invalid_block(&format!(
+                "invalid pcapng block length {length}"
+            )));
+        }
+        let mut rest = vec![0u8; length - 8 - body.len()];
+        self.reader.read_exact(&mut rest)?;
+        // Drop the trailing copy of the block length
+        rest.truncate(rest.len() - 4);
+        body.extend_from_slice(&rest);
+        Ok(body)
+    }
+}
+
+fn invalid_block(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = io::Result<PcapPacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+/// Extracts the UDP datagram from a captured frame of the given link type.  Returns `None`
+/// for anything that isn't an unfragmented IPv4/IPv6 UDP datagram.
+pub fn udp_datagram(link_type: u32, frame: &[u8]) -> Option<UdpDatagram<'_>> {
+    let (ethertype, ip) = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+            let mut offset = 14;
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                ethertype =
+                    u16::from_be_bytes([*frame.get(offset + 2)?, *frame.get(offset + 3)?]);
+                offset += 4;
+            }
+            (ethertype, frame.get(offset..)?)
+        }
+        LINKTYPE_LINUX_SLL => (
+            u16::from_be_bytes([*frame.get(14)?, *frame.get(15)?]),
+            frame.get(16..)?,
+        ),
+        LINKTYPE_LINUX_SLL2 => (
+            u16::from_be_bytes([*frame.first()?, *frame.get(1)?]),
+            frame.get(20..)?,
+        ),
+        LINKTYPE_RAW | LINKTYPE_NULL => {
+            let ip = match link_type {
+                LINKTYPE_NULL => frame.get(4..)?,
+                _ => frame,
+            };
+            match ip.first()? >> 4 {
+                4 => (ETHERTYPE_IPV4, ip),
+                6 => (ETHERTYPE_IPV6, ip),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    let (source, destination, udp) = match ethertype {
+        ETHERTYPE_IPV4 => {
+            let header_length = ((*ip.first()? & 0x0f) as usize) * 4;
+            let flags_fragment = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]);
+            // More fragments flag or a fragment offset
+            if ip.get(9)? != &IP_PROTOCOL_UDP || flags_fragment & 0x3fff != 0 {
+                return None;
+            }
+            let source: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::V4(Ipv4Addr::from(source)),
+                IpAddr::V4(Ipv4Addr::from(destination)),
+                ip.get(header_length..)?,
+            )
+        }
+        ETHERTYPE_IPV6 => {
+            if ip.get(6)? != &IP_PROTOCOL_UDP {
+                return None;
+            }
+            let source: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            (
+                IpAddr::V6(Ipv6Addr::from(source)),
+                IpAddr::V6(Ipv6Addr::from(destination)),
+                ip.get(40..)?,
+            )
+        }
+        _ => return None,
+    };
+
+    let source_port = u16::from_be_bytes([*udp.first()?, *udp.get(1)?]);
+    let destination_port = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+    let length = u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]) as usize;
+    // Captures may be truncated to the snap length
+    let payload = udp.get(8..length.max(8).min(udp.len()))?;
+    Some(UdpDatagram {
+        source: SocketAddr::new(source, source_port),
+        destination: SocketAddr::new(destination, destination_port),
+        payload,
+    })
+}
+
+/// Datagrams held back per exporter while waiting for its templates.  Once over, the oldest
+/// is parsed as it is.
+const MAX_HELD_DATAGRAMS: usize = 1_024;
+
+/// Exporters given a parser of their own.  Once over, the least recently seen exporter is
+/// dropped along with its templates, and what it held is parsed as it is.
+const MAX_EXPORTERS: usize = 4_096;
+
+/// A netflow packet read from a capture.
+#[derive(Debug, Clone)]
+pub struct PcapNetflowPacket {
+    /// Capture time of the datagram the packet came in
+    pub timestamp: Duration,
+    /// Address the packet was sent from
+    pub exporter: SocketAddr,
+    pub packet: NetflowPacket,
+}
+
+/// A datagram with data for templates its exporter hasn't announced yet.
+#[derive(Debug)]
+struct HeldDatagram {
+    timestamp: Duration,
+    exporter: SocketAddr,
+    payload: Vec<u8>,
+}
+
+/// Whether `payload` is a V9 or IPFix message with data sets for templates `parser` hasn't
+/// cached, going by its set headers so the message is only parsed once they are.  Messages
+/// announcing templates never wait.
+fn waits_for_templates(parser: &NetflowParser, payload: &[u8]) -> bool {
+    let u16_at = |i: usize| Some(u16::from_be_bytes(payload.get(i..i + 2)?.try_into().ok()?));
+    let u32_at = |i: usize| Some(u32::from_be_bytes(payload.get(i..i + 4)?.try_into().ok()?));
+    // Source offset, first set and end of the message, and the template set ids
+    let (source, mut offset, end, template_sets) = match u16_at(0) {
+        Some(9) => (u32_at(16), 20, payload.len(), [0, 1]),
+        Some(10) => (
+            u32_at(12),
+            16,
+            u16_at(2).map_or(0, usize::from).min(payload.len()),
+            [2, 3],
+        ),
+        _ => return false,
+    };
+    let Some(source) = source else {
+        return false;
+    };
+    let mut missing = false;
+    while offset + 4 <= end {
+        let (Some(id), Some(length)) = (u16_at(offset), u16_at(offset + 2)) else {
+            break;
+        };
+        if template_sets.contains(&id) {
+            return false;
+        }
+        missing |= id > 255
+            && !match u16_at(0) {
+                Some(9) => parser.v9_parser.engine.has_template(source, id),
+                _ => parser.ipfix_parser.engine.has_template(source, id),
+            };
+        if length < 4 {
+            break;
+        }
+        offset += usize::from(length);
+    }
+    missing
+}
+
+fn has_templates(packet: &NetflowPacket) -> bool {
+    match packet {
+        NetflowPacket::V9(v9) => v9.has_templates(),
+        NetflowPacket::IPFix(ipfix) => ipfix.has_templates(),
+        _ => false,
+    }
+}
+
+/// Reads the netflow packets of a pcap or pcapng capture, parsing each exporter's packets
+/// with its own parser.
+///
+/// Captures often start part way through an export, with data before the templates it needs.
+/// Datagrams with data for unknown templates are held back until their exporter announces a
+/// template and only parsed then, so their flows aren't lost.  Datagrams still waiting at the
+/// end of the capture are parsed as they are.
+#[derive(Debug)]
+pub struct PcapNetflowReader<R> {
+    reader: PcapReader<R>,
+    builder: NetflowParserBuilder,
+    parsers: HashMap<IpAddr, NetflowParser>,
+    /// Datagrams read when each exporter was last seen
+    last_seen: HashMap<IpAddr, u64>,
+    datagrams: u64,
+    held: HashMap<IpAddr, VecDeque<HeldDatagram>>,
+    ready: VecDeque<PcapNetflowPacket>,
+    skipped_frames: u64,
+    finished: bool,
+}
+
+impl PcapNetflowReader<BufReader<File>> {
+    /// Opens the capture at `path`, scoping templates by V9 source id and IPFix observation
+    /// domain.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(
+            BufReader::new(File::open(path)?),
+            NetflowParser::builder().with_scoped_templates(true),
+        )
+    }
+}
+
+impl<R: Read> PcapNetflowReader<R> {
+    /// Parsers for new exporters are built from `builder`.
+    pub fn new(capture: R, builder: NetflowParserBuilder) -> io::Result<Self> {
+        // Surface configuration errors now rather than on the first exporter.
+        builder
+            .clone()
+            .build()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(Self {
+            reader: PcapReader::new(capture)?,
+            builder,
+            parsers: HashMap::new(),
+            last_seen: HashMap::new(),
+            datagrams: 0,
+            held: HashMap::new(),
+            ready: VecDeque::new(),
+            skipped_frames: 0,
+            finished: false,
+        })
+    }
+
+    /// Parsers by exporter address, holding the templates learned so far.
+    pub fn parsers(&self) -> &HashMap<IpAddr, NetflowParser> {
+        &self.parsers
+    }
+
+    /// Captured frames that weren't UDP.
+    pub fn skipped_frames(&self) -> u64 {
+        self.skipped_frames
+    }
+
+    /// Reads the next netflow packet, `None` at the end of the capture.
+    pub fn next_packet(&mut self) -> io::Result<Option<PcapNetflowPacket>> {
+        loop {
+            if let Some(packet) = self.ready.pop_front() {
+                return Ok(Some(packet));
+            }
+            if self.finished {
+                return Ok(None);
+            }
+            match self.reader.next_packet()? {
+                Some(frame) => self.read_frame(frame)?,
+                None => {
+                    self.finished = true;
+                    let mut held: Vec<_> =
+                        self.held.drain().flat_map(|(_, held)| held).collect();
+                    held.sort_by_key(|datagram| datagram.timestamp);
+                    for datagram in held {
+                        self.parse(datagram);
+                    }
+                }
+            }
+        }
+    }
+
+    fn read_frame(&mut self, frame: PcapPacket) -> io::Result<()> {
+        let Some(datagram) = udp_datagram(frame.link_type, &frame.data) else {
+            self.skipped_frames += 1;
+            return Ok(());
+        };
+        let exporter = datagram.source;
+        let parser = self.see_exporter(exporter.ip())?;
+        let waiting = waits_for_templates(parser, datagram.payload);
+        let datagram = HeldDatagram {
+            timestamp: frame.timestamp,
+            exporter,
+            payload: datagram.payload.to_vec(),
+        };
+
+        if waiting {
+            let waiting = self.held.entry(exporter.ip()).or_default();
+            waiting.push_back(datagram);
+            if waiting.len() > MAX_HELD_DATAGRAMS {
+                if let Some(oldest) = waiting.pop_front() {
+                    self.parse(oldest);
+                }
+            }
+            return Ok(());
+        }
+
+        if self.parse(datagram) {
+            // Parse what was waiting for this exporter's templates.
+            let Some(waiting) = self.held.remove(&exporter.ip()) else {
+                return Ok(());
+            };
+            let mut still_waiting = VecDeque::new();
+            for datagram in waiting {
+                if waits_for_templates(&self.parsers[&exporter.ip()], &datagram.payload) {
+                    still_waiting.push_back(datagram);
+                } else {
+                    self.parse(datagram);
+                }
+            }
+            if !still_waiting.is_empty() {
+                self.held.insert(exporter.ip(), still_waiting);
+            }
+        }
+        Ok(())
+    }
+
+    /// The parser of `exporter`, built if it is new, which drops the least recently seen
+    /// exporter once there are more than `MAX_EXPORTERS`.
+    fn see_exporter(&mut self, exporter: IpAddr) -> io::Result<&NetflowParser> {
+        self.datagrams += 1;
+        self.last_seen.insert(exporter, self.datagrams);
+        if !self.parsers.contains_key(&exporter) {
+            let parser = self
+                .builder
+                .clone()
+                .build()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            self.parsers.insert(exporter, parser);
+            if self.parsers.len() > MAX_EXPORTERS {
+                let oldest = self
+                    .last_seen
+                    .iter()
+                    .min_by_key(|(_, seen)| **seen)
+                    .map(|(oldest, _)| *oldest);
+                if let Some(oldest) = oldest {
+                    for datagram in self.held.remove(&oldest).unwrap_or_default() {
+                        self.parse(datagram);
+                    }
+                    self.parsers.remove(&oldest);
+                    self.last_seen.remove(&oldest);
+                }
+            }
+        }
+        Ok(&self.parsers[&exporter])
+    }
+
+    /// Parses `datagram` with its exporter's parser and passes its packets on, returning
+    /// whether they announce templates.
+    fn parse(&mut self, datagram: HeldDatagram) -> bool {
+        let Some(parser) = self.parsers.get_mut(&datagram.exporter.ip()) else {
+            return false;
+        };
+        let packets = parser.parse_bytes_received_at(&datagram.payload, datagram.timestamp);
+        let announces_templates = packets.iter().any(has_templates);
+        self.ready
+            .extend(packets.into_iter().map(|packet| PcapNetflowPacket {
+                timestamp: datagram.timestamp,
+                exporter: datagram.exporter,
+                packet,
+            }));
+        announces_templates
+    }
+}
+
+impl<R: Read> Iterator for PcapNetflowReader<R> {
+    type Item = io::Result<PcapNetflowPacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod pcap_tests {
+    use super::{
+        udp_datagram, PcapNetflowReader, PcapReader, BLOCK_ENHANCED_PACKET,
+        BLOCK_INTERFACE_DESCRIPTION, BLOCK_SECTION_HEADER, LINKTYPE_ETHERNET,
+    };
+    use crate::{NetflowPacket, NetflowParser, ParseMode};
+
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    /// An Ethernet/IPv4/UDP frame sent from 10.0.0.1:2055 to 10.0.0.2:2055.
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let udp_length = 8 + payload.len() as u16;
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&(20 + udp_length).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend_from_slice(&2055u16.to_be_bytes());
+        frame.extend_from_slice(&2055u16.to_be_bytes());
+        frame.extend_from_slice(&udp_length.to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// Builds a little endian, microsecond pcap capture of Ethernet/IPv4/UDP frames sent from
+    /// 10.0.0.1:2055 to 10.0.0.2:2055.
+    pub(crate) fn capture(packets: &[(Duration, &[u8])]) -> Vec<u8> {
+        let mut pcap = vec![];
+        pcap.extend_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+        pcap.extend_from_slice(&[2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0]);
+        pcap.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        for (timestamp, payload) in packets {
+            let frame = frame(payload);
+            pcap.extend_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+            pcap.extend_from_slice(&timestamp.subsec_micros().to_le_bytes());
+            pcap.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            pcap.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            pcap.extend_from_slice(&frame);
+        }
+        pcap
+    }
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let length = (12 + body.len()) as u32;
+        let mut block = block_type.to_be_bytes().to_vec();
+        block.extend_from_slice(&length.to_be_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&length.to_be_bytes());
+        block
+    }
+
+    /// Builds a big endian pcapng capture with one Ethernet interface of nanosecond
+    /// resolution.
+    fn capture_ng(packets: &[(Duration, &[u8])]) -> Vec<u8> {
+        let mut pcapng = block(
+            BLOCK_SECTION_HEADER,
+            &[
+                0x1a, 0x2b, 0x3c, 0x4d, 0, 1, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                0xff,
+            ],
+        );
+        // Link type, snap length, if_tsresol of 10^-9 and the end of options
+        pcapng.extend(block(
+            BLOCK_INTERFACE_DESCRIPTION,
+            &[
+                0, 1, 0, 0, 0, 0, 0xff, 0xff, 0, 9, 0, 1, 9, 0, 0, 0, 0, 0, 0, 0,
+            ],
+        ));
+        for (timestamp, payload) in packets {
+            let mut frame = frame(payload);
+            let captured_length = frame.len() as u32;
+            frame.resize(frame.len().next_multiple_of(4), 0);
+            let nanos = timestamp.as_nanos() as u64;
+            let mut body = 0u32.to_be_bytes().to_vec();
+            body.extend_from_slice(&((nanos >> 32) as u32).to_be_bytes());
+            body.extend_from_slice(&(nanos as u32).to_be_bytes());
+            body.extend_from_slice(&captured_length.to_be_bytes());
+            body.extend_from_slice(&captured_length.to_be_bytes());
+            body.extend_from_slice(&frame);
+            pcapng.extend(block(BLOCK_ENHANCED_PACKET, &body));
+        }
+        pcapng
+    }
+
+    #[test]
+    fn it_reads_udp_payloads_from_pcap() {
+        let pcap = capture(&[
+            (Duration::from_millis(1_500), &[1, 2, 3]),
+            (Duration::from_secs(2), &[4]),
+        ]);
+        let mut reader = PcapReader::new(pcap.as_slice()).unwrap();
+        assert_eq!(reader.link_type(), LINKTYPE_ETHERNET);
+
+        let packet = reader.next_packet().unwrap().unwrap();
+        assert_eq!(packet.timestamp, Duration::from_millis(1_500));
+        let datagram = udp_datagram(reader.link_type(), &packet.data).unwrap();
+        assert_eq!(
+            datagram.source,
+            "10.0.0.1:2055".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(datagram.payload, [1, 2, 3]);
+
+        assert_eq!(reader.count(), 1);
+    }
+
+    #[test]
+    fn it_rejects_non_pcap_input() {
+        assert!(PcapReader::new([0u8; 24].as_slice()).is_err());
+    }
+
+    #[test]
+    fn it_rejects_lengths_it_would_not_allocate() {
+        // A record claiming more than the 65535 byte snap length
+        let mut pcap = capture(&[]);
+        pcap.extend_from_slice(&[0; 8]);
+        pcap.extend_from_slice(&70_000u32.to_le_bytes());
+        pcap.extend_from_slice(&70_000u32.to_le_bytes());
+        let mut reader = PcapReader::new(pcap.as_slice()).unwrap();
+        assert!(reader.next_packet().is_err());
+
+        // An enhanced packet block claiming 1 GiB
+        let mut pcapng = capture_ng(&[]);
+        pcapng.extend_from_slice(&BLOCK_ENHANCED_PACKET.to_be_bytes());
+        pcapng.extend_from_slice(&(1u32 << 30).to_be_bytes());
+        let mut reader = PcapReader::new(pcapng.as_slice()).unwrap();
+        assert!(reader.next_packet().is_err());
+    }
+
+    #[test]
+    fn it_reads_udp_payloads_from_pcapng() {
+        let pcapng = capture_ng(&[
+            (Duration::new(1, 5), &[1, 2, 3]),
+            (Duration::from_secs(2), &[4]),
+        ]);
+        let mut reader = PcapReader::new(pcapng.as_slice()).unwrap();
+        assert_eq!(reader.link_type(), LINKTYPE_ETHERNET);
+
+        let packet = reader.next_packet().unwrap().unwrap();
+        assert_eq!(packet.timestamp, Duration::new(1, 5));
+        assert_eq!(packet.link_type, LINKTYPE_ETHERNET);
+        let datagram = udp_datagram(packet.link_type, &packet.data).unwrap();
+        assert_eq!(datagram.payload, [1, 2, 3]);
+
+        assert_eq!(reader.count(), 1);
+    }
+
+    #[test]
+    fn it_holds_data_until_its_template_is_read() {
+        let header = [0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1];
+        let template = [0, 0, 0, 16, 1, 2, 0, 2, 0, 1, 0, 4, 0, 8, 0, 4];
+        let data = [1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8];
+        let unknown_data = [1, 3, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8];
+        let pcap = capture(&[
+            (Duration::from_secs(1), &[&header[..], &data].concat()),
+            (
+                Duration::from_secs(2),
+                &[&header[..], &unknown_data].concat(),
+            ),
+            (Duration::from_secs(3), &[&header[..], &template].concat()),
+        ]);
+
+        let builder = NetflowParser::builder().with_parse_mode(ParseMode::Lenient);
+        let reader = PcapNetflowReader::new(pcap.as_slice(), builder).unwrap();
+        let packets: Vec<_> = reader.map(Result::unwrap).collect();
+        let timestamps: Vec<_> = packets.iter().map(|p| p.timestamp.as_secs()).collect();
+        assert_eq!(timestamps, vec![3, 1, 2]);
+        let NetflowPacket::V9(v9) = &packets[1].packet else {
+            panic!("expected a V9 packet");
+        };
+        assert!(v9.flowsets[0].body.data.is_some());
+        let NetflowPacket::V9(v9) = &packets[2].packet else {
+            panic!("expected a V9 packet");
+        };
+        assert!(v9.has_missing_templates());
+        assert_eq!(
+            packets[0].exporter,
+            "10.0.0.1:2055".parse::<SocketAddr>().unwrap()
+        );
+    }
+}
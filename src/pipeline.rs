@@ -0,0 +1,627 @@
+//! # Pipeline
+//!
+//! Composes a collector out of a listener, a parser per exporter, filters, enrichers and
+//! sinks.  Each datagram is parsed by the parser of the address it came from, so templates
+//! from different exporters never collide.  Its flow records are passed through the filters,
+//! then the enrichers, and written to every sink.
+//!
+//! Parsers of exporters that go away are dropped along with their templates, once idle for
+//! longer than `with_exporter_ttl`, or when more than `with_max_exporters` (4096 unless set)
+//! exporters have been seen.
+//!
+//! ```rust,no_run
+//! use netflow_parser::pipeline::{Pipeline, PipelineRecord};
+//! use netflow_parser::NetflowParser;
+//! use std::net::UdpSocket;
+//! use std::time::Duration;
+//!
+//! let socket = UdpSocket::bind("0.0.0.0:2055")?;
+//! // Poll sinks at least once a second when traffic is quiet.
+//! socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+//!
+//! let mut pipeline = Pipeline::builder()
+//!     .with_parser(NetflowParser::builder().with_scoped_templates(true))
+//!     .with_filter(|record: &PipelineRecord| record.record.version() >= 9)
+//!     .with_enricher(|record: &mut PipelineRecord| {
+//!         let site = if record.exporter.ip().is_loopback() { "lab" } else { "dc1" };
+//!         record.tags.insert("site".to_string(), site.to_string());
+//!     })
+//!     .with_sink(|record: &PipelineRecord| {
+//!         println!("{} {:?} {:?}", record.exporter, record.tags, record.record);
+//!         Ok(())
+//!     })
+//!     .build()
+//!     .expect("valid configuration");
+//! pipeline.run(&mut socket.try_clone()?)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::batcher::Batcher;
+use crate::flow_records::FlowRecord;
+use crate::variable_versions::SourceTracker;
+use crate::{NetflowError, NetflowPacket, NetflowParser, NetflowParserBuilder};
+
+use serde::Serialize;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::mpsc::{Sender, SyncSender};
+use std::time::Duration;
+
+/// Exporters a pipeline keeps parsers for unless `with_max_exporters` is set.
+const DEFAULT_MAX_EXPORTERS: usize = 4_096;
+
+/// Source of netflow datagrams.
+pub trait Listener {
+    /// Receives a datagram into `buf`, returning its length and the address it came from.
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+}
+
+impl Listener for UdpSocket {
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.recv_from(buf)
+    }
+}
+
+/// A flow record on its way through the pipeline.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PipelineRecord {
+    /// Address the record was exported from
+    pub exporter: SocketAddr,
+    pub record: FlowRecord,
+    /// Values added by enrichers
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Decides whether a record continues through the pipeline.
+pub trait Filter: Send {
+    fn keep(&mut self, record: &PipelineRecord) -> bool;
+}
+
+impl<F: FnMut(&PipelineRecord) -> bool + Send> Filter for F {
+    fn keep(&mut self, record: &PipelineRecord) -> bool {
+        self(record)
+    }
+}
+
+/// Adds to or changes a record before it is written to the sinks.
+pub trait Enricher: Send {
+    fn enrich(&mut self, record: &mut PipelineRecord);
+}
+
+impl<F: FnMut(&mut PipelineRecord) + Send> Enricher for F {
+    fn enrich(&mut self, record: &mut PipelineRecord) {
+        self(record)
+    }
+}
+
+/// Destination of the records that made it through the pipeline.
+pub trait Sink: Send {
+    fn write(&mut self, record: &PipelineRecord) -> io::Result<()>;
+
+    /// Called when the listener times out, for sinks that write out buffered records after a
+    /// while.
+    fn poll(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Writes out anything buffered.  Called when the pipeline stops.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&PipelineRecord) -> io::Result<()> + Send> Sink for F {
+    fn write(&mut self, record: &PipelineRecord) -> io::Result<()> {
+        self(record)
+    }
+}
+
+/// Sends records to another thread.  Fails once the receiver is gone.
+impl Sink for Sender<PipelineRecord> {
+    fn write(&mut self, record: &PipelineRecord) -> io::Result<()> {
+        self.send(record.clone())
+            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))
+    }
+}
+
+/// Collects records into batches.  Batches older than `max_age` are flushed on
+/// [`Sink::poll`], and any batch on [`Sink::flush`].
+impl<F: FnMut(Vec<PipelineRecord>) + Send> Sink for Batcher<PipelineRecord, F> {
+    fn write(&mut self, record: &PipelineRecord) -> io::Result<()> {
+        self.push(record.clone());
+        Ok(())
+    }
+
+    fn poll(&mut self) -> io::Result<()> {
+        Batcher::poll(self);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Batcher::flush(self);
+        Ok(())
+    }
+}
+
+/// Destination of the parsed packets of each datagram, such as a message bus producer.  Gets
+/// every packet parsed, before the record filters run.  Implementations should block while
+/// the destination is full, so backpressure slows down reading from the listener instead of
+/// buffering without bound.
+pub trait FlowSink: Send {
+    fn send(&mut self, packets: &[NetflowPacket]) -> io::Result<()>;
+
+    /// Called when the listener times out, like [`Sink::poll`].
+    fn poll(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Writes out anything buffered.  Called when the pipeline stops.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&[NetflowPacket]) -> io::Result<()> + Send> FlowSink for F {
+    fn send(&mut self, packets: &[NetflowPacket]) -> io::Result<()> {
+        self(packets)
+    }
+}
+
+/// Sends packets to another thread over a bounded channel, blocking while it is full.  Fails
+/// once the receiver is gone.
+impl FlowSink for SyncSender<Vec<NetflowPacket>> {
+    fn send(&mut self, packets: &[NetflowPacket]) -> io::Result<()> {
+        SyncSender::send(self, packets.to_vec())
+            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))
+    }
+}
+
+/// Counts of a pipeline's traffic.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineStats {
+    /// Netflow packets parsed
+    pub packets: u64,
+    /// Packets that failed to parse
+    pub errors: u64,
+    /// Flow records parsed
+    pub records: u64,
+    /// Records dropped by a filter
+    pub filtered: u64,
+}
+
+/// Builder for a [`Pipeline`].
+#[derive(Default)]
+pub struct PipelineBuilder {
+    parser: NetflowParserBuilder,
+    source_configs: HashMap<IpAddr, NetflowParserBuilder>,
+    exporter_ttl: Option<Duration>,
+    max_exporters: Option<usize>,
+    filters: Vec<Box<dyn Filter>>,
+    enrichers: Vec<Box<dyn Enricher>>,
+    sinks: Vec<Box<dyn Sink>>,
+    flow_sinks: Vec<Box<dyn FlowSink>>,
+}
+
+impl PipelineBuilder {
+    /// Parsers for new exporters are built from `builder`.
+    pub fn with_parser(mut self, builder: NetflowParserBuilder) -> Self {
+        self.parser = builder;
+        self
+    }
+
+    /// The parser for `exporter` is built from `builder` instead, such as to only allow
+    /// IPFix from some exporters and V5 from others.
+    pub fn with_source_config(
+        mut self,
+        exporter: IpAddr,
+        builder: NetflowParserBuilder,
+    ) -> Self {
+        self.source_configs.insert(exporter, builder);
+        self
+    }
+
+    /// Drops the parser and templates of an exporter not seen for longer than `ttl`.  Idle
+    /// exporters are evicted when a new exporter shows up.
+    pub fn with_exporter_ttl(mut self, ttl: Duration) -> Self {
+        self.exporter_ttl = Some(ttl);
+        self
+    }
+
+    /// Keeps parsers for at most `max_exporters` exporters, dropping the least recently seen
+    /// exporter's when a new one shows up.  Defaults to 4096.
+    pub fn with_max_exporters(mut self, max_exporters: usize) -> Self {
+        self.max_exporters = Some(max_exporters);
+        self
+    }
+
+    /// Drops records `filter` doesn't keep.  Filters run in the order they were added.
+    pub fn with_filter(mut self, filter: impl Filter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Enrichers run in the order they were added, after the filters.
+    pub fn with_enricher(mut self, enricher: impl Enricher + 'static) -> Self {
+        self.enrichers.push(Box::new(enricher));
+        self
+    }
+
+    /// Writes records to `sink`.  Every sink gets every record.
+    pub fn with_sink(mut self, sink: impl Sink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Sends the packets of every datagram to `sink`, before records are filtered.
+    pub fn with_flow_sink(mut self, sink: impl FlowSink + 'static) -> Self {
+        self.flow_sinks.push(Box::new(sink));
+        self
+    }
+
+    pub fn build(self) -> Result<Pipeline, NetflowError> {
+        if self.sinks.is_empty() && self.flow_sinks.is_empty() {
+            return Err(NetflowError::ConfigError(
+                "pipeline needs at least one sink".to_string(),
+            ));
+        }
+        // Surface configuration errors now rather than on the first exporter.
+        self.parser.clone().build()?;
+        for builder in self.source_configs.values() {
+            builder.clone().build()?;
+        }
+        Ok(Pipeline {
+            builder: self.parser,
+            source_configs: self.source_configs,
+            parsers: HashMap::new(),
+            exporters: SourceTracker::new(
+                self.exporter_ttl,
+                Some(self.max_exporters.unwrap_or(DEFAULT_MAX_EXPORTERS)),
+            ),
+            filters: self.filters,
+            enrichers: self.enrichers,
+            sinks: self.sinks,
+            flow_sinks: self.flow_sinks,
+            stats: PipelineStats::default(),
+        })
+    }
+}
+
+impl fmt::Debug for PipelineBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PipelineBuilder")
+            .field("parser", &self.parser)
+            .field("source_configs", &self.source_configs)
+            .field("exporter_ttl", &self.exporter_ttl)
+            .field("max_exporters", &self.max_exporters)
+            .field("filters", &self.filters.len())
+            .field("enrichers", &self.enrichers.len())
+            .field("sinks", &self.sinks.len())
+            .field("flow_sinks", &self.flow_sinks.len())
+            .finish()
+    }
+}
+
+/// A collector assembled by [`PipelineBuilder`].
+pub struct Pipeline {
+    builder: NetflowParserBuilder,
+    /// Builders for exporters configured apart from the rest
+    source_configs: HashMap<IpAddr, NetflowParserBuilder>,
+    parsers: HashMap<IpAddr, NetflowParser>,
+    /// When each exporter with a parser was last seen
+    exporters: SourceTracker<IpAddr>,
+    filters: Vec<Box<dyn Filter>>,
+    enrichers: Vec<Box<dyn Enricher>>,
+    sinks: Vec<Box<dyn Sink>>,
+    flow_sinks: Vec<Box<dyn FlowSink>>,
+    stats: PipelineStats,
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    /// Receives datagrams from `listener` until it or a sink fails.  Read timeouts of the
+    /// listener poll the sinks and keep listening.  Sinks are flushed before returning.
+    pub fn run(&mut self, listener: &mut impl Listener) -> io::Result<()> {
+        let mut buf = vec![0; 65_535];
+        let result = loop {
+            match listener.recv(&mut buf) {
+                Ok((length, exporter)) => {
+                    if let Err(e) = self.process(exporter, &buf[..length]) {
+                        break Err(e);
+                    }
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    if let Err(e) = self.poll() {
+                        break Err(e);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.flush().and(result)
+    }
+
+    /// Passes a datagram received from `exporter` through the pipeline, for datagrams that
+    /// don't come from a [`Listener`], such as an async socket.
+    pub fn process(&mut self, exporter: SocketAddr, datagram: &[u8]) -> io::Result<()> {
+        let (idle, evicted) = self.exporters.see(exporter.ip());
+        for gone in idle.iter().chain(&evicted) {
+            self.parsers.remove(gone);
+        }
+        let parser = match self.parsers.get_mut(&exporter.ip()) {
+            Some(parser) => parser,
+            None => {
+                let parser = self
+                    .source_configs
+                    .get(&exporter.ip())
+                    .unwrap_or(&self.builder)
+                    .clone()
+                    .build()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                self.parsers.entry(exporter.ip()).or_insert(parser)
+            }
+        };
+        let mut packets = parser.parse_bytes(datagram);
+        let parsed = packets.len();
+        packets.retain(|packet| !packet.is_error());
+        self.stats.errors += (parsed - packets.len()) as u64;
+        for sink in &mut self.flow_sinks {
+            sink.send(&packets)?;
+        }
+        for packet in packets {
+            self.stats.packets += 1;
+            'records: for record in FlowRecord::from_packet(packet) {
+                self.stats.records += 1;
+                let mut record = PipelineRecord {
+                    exporter,
+                    record,
+                    tags: BTreeMap::new(),
+                };
+                for filter in &mut self.filters {
+                    if !filter.keep(&record) {
+                        self.stats.filtered += 1;
+                        continue 'records;
+                    }
+                }
+                for enricher in &mut self.enrichers {
+                    enricher.enrich(&mut record);
+                }
+                for sink in &mut self.sinks {
+                    sink.write(&record)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Polls every sink, letting them write out records that waited long enough.
+    pub fn poll(&mut self) -> io::Result<()> {
+        for sink in &mut self.sinks {
+            sink.poll()?;
+        }
+        for sink in &mut self.flow_sinks {
+            sink.poll()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes every sink.
+    pub fn flush(&mut self) -> io::Result<()> {
+        for sink in &mut self.sinks {
+            sink.flush()?;
+        }
+        for sink in &mut self.flow_sinks {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Exporters whose parsers were dropped for being idle or to make room.
+    pub fn evicted_exporters(&self) -> u64 {
+        self.exporters.evicted()
+    }
+
+    pub fn stats(&self) -> PipelineStats {
+        self.stats
+    }
+
+    /// Parsers by exporter address, holding the templates learned so far.
+    pub fn parsers(&self) -> &HashMap<IpAddr, NetflowParser> {
+        &self.parsers
+    }
+
+    /// Builds the parser for `exporter` from `builder` from now on, see
+    /// [`PipelineBuilder::with_source_config`].  A parser already built for it is replaced,
+    /// dropping its templates.  Fails if the configuration is invalid.
+    pub fn set_source_config(
+        &mut self,
+        exporter: IpAddr,
+        builder: NetflowParserBuilder,
+    ) -> Result<(), NetflowError> {
+        let parser = builder.clone().build()?;
+        if let Some(existing) = self.parsers.get_mut(&exporter) {
+            *existing = parser;
+        }
+        self.source_configs.insert(exporter, builder);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("builder", &self.builder)
+            .field("source_configs", &self.source_configs)
+            .field("exporters", &self.parsers.len())
+            .field("filters", &self.filters.len())
+            .field("enrichers", &self.enrichers.len())
+            .field("sinks", &self.sinks.len())
+            .field("flow_sinks", &self.flow_sinks.len())
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod pipeline_tests {
+    use super::{Pipeline, PipelineRecord, PipelineStats};
+    use crate::batcher::Batcher;
+    use crate::{NetflowError, NetflowPacket, NetflowParser};
+
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::mpsc;
+
+    const V5_PACKET: [u8; 72] = [
+        0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4,
+        5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+        4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+    ];
+    const V7_PACKET: [u8; 76] = [
+        0, 7, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4,
+        5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+        4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+    ];
+
+    #[test]
+    fn it_runs_records_through_filters_enrichers_and_sinks() {
+        let (tx, rx) = mpsc::channel();
+        let mut pipeline = Pipeline::builder()
+            .with_filter(|record: &PipelineRecord| record.record.version() == 5)
+            .with_enricher(|record: &mut PipelineRecord| {
+                record
+                    .tags
+                    .insert("exporter".to_string(), record.exporter.ip().to_string());
+            })
+            .with_sink(tx)
+            .build()
+            .unwrap();
+
+        let exporter = "192.0.2.1:2055".parse().unwrap();
+        pipeline.process(exporter, &V5_PACKET).unwrap();
+        pipeline.process(exporter, &V7_PACKET).unwrap();
+        pipeline.process(exporter, &V5_PACKET[..10]).unwrap();
+
+        let records: Vec<PipelineRecord> = rx.try_iter().collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].record.version(), 5);
+        assert_eq!(records[0].tags["exporter"], "192.0.2.1");
+        assert_eq!(
+            pipeline.stats(),
+            PipelineStats {
+                packets: 2,
+                errors: 1,
+                records: 2,
+                filtered: 1,
+            }
+        );
+        assert_eq!(pipeline.parsers().len(), 1);
+    }
+
+    #[test]
+    fn it_flushes_batching_sinks() {
+        let (tx, rx) = mpsc::channel();
+        let batcher = Batcher::new(10, None, move |batch: Vec<PipelineRecord>| {
+            tx.send(batch.len()).unwrap();
+        });
+        let mut pipeline = Pipeline::builder().with_sink(batcher).build().unwrap();
+        pipeline
+            .process("192.0.2.1:2055".parse().unwrap(), &V5_PACKET)
+            .unwrap();
+        pipeline.poll().unwrap();
+        assert_eq!(rx.try_recv().ok(), None);
+        pipeline.flush().unwrap();
+        assert_eq!(rx.try_recv().ok(), Some(1));
+
+        assert!(matches!(
+            Pipeline::builder().build(),
+            Err(NetflowError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn it_sends_packets_to_flow_sinks() {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let mut pipeline = Pipeline::builder()
+            .with_filter(|_: &PipelineRecord| false)
+            .with_flow_sink(tx)
+            .build()
+            .unwrap();
+        let exporter = "192.0.2.1:2055".parse().unwrap();
+        pipeline.process(exporter, &V5_PACKET).unwrap();
+        // Filters don't apply to packets
+        let packets: Vec<NetflowPacket> = rx.try_recv().unwrap();
+        assert!(packets[0].is_v5());
+
+        // Once the receiver is gone the pipeline fails
+        drop(rx);
+        let error = pipeline.process(exporter, &V7_PACKET).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn it_builds_parsers_from_source_configs() {
+        let (tx, rx) = mpsc::channel();
+        let v5_exporter: SocketAddr = "192.0.2.1:2055".parse().unwrap();
+        let other: SocketAddr = "192.0.2.2:2055".parse().unwrap();
+        let mut pipeline = Pipeline::builder()
+            .with_source_config(
+                v5_exporter.ip(),
+                NetflowParser::builder().with_allowed_versions([5]),
+            )
+            .with_sink(tx)
+            .build()
+            .unwrap();
+        pipeline.process(v5_exporter, &V5_PACKET).unwrap();
+        pipeline.process(v5_exporter, &V7_PACKET).unwrap();
+        pipeline.process(other, &V7_PACKET).unwrap();
+        let versions: Vec<u16> = rx.try_iter().map(|r| r.record.version()).collect();
+        assert_eq!(versions, vec![5, 7]);
+
+        // Replacing the configuration of an exporter rebuilds its parser
+        pipeline
+            .set_source_config(
+                other.ip(),
+                NetflowParser::builder().with_allowed_versions([5]),
+            )
+            .unwrap();
+        pipeline.process(other, &V7_PACKET).unwrap();
+        assert_eq!(rx.try_iter().count(), 0);
+        assert!(pipeline
+            .set_source_config(
+                other.ip(),
+                NetflowParser::builder().with_allowed_versions([11])
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn it_drops_the_parsers_of_least_recently_seen_exporters() {
+        let (tx, _rx) = mpsc::channel();
+        let mut pipeline = Pipeline::builder()
+            .with_max_exporters(2)
+            .with_sink(tx)
+            .build()
+            .unwrap();
+        let exporter = |last: u8| SocketAddr::from(([192, 0, 2, last], 2055));
+        pipeline.process(exporter(1), &V5_PACKET).unwrap();
+        pipeline.process(exporter(2), &V5_PACKET).unwrap();
+        pipeline.process(exporter(1), &V5_PACKET).unwrap();
+        pipeline.process(exporter(3), &V5_PACKET).unwrap();
+
+        assert_eq!(pipeline.parsers().len(), 2);
+        assert!(!pipeline.parsers().contains_key(&exporter(2).ip()));
+        assert_eq!(pipeline.evicted_exporters(), 1);
+    }
+}
@@ -0,0 +1,558 @@
+//! # Protobuf
+//!
+//! Encodes flows as protobuf messages, a compact binary form for shipping them over gRPC or
+//! Kafka instead of JSON.  The messages are defined in [`SCHEMA`], `proto/netflow.proto` in
+//! the crate, which consumers can generate their own code from.  Field numbers are stable.
+//!
+//! [`Flow`] converts from a `NetflowCommonFlowSet`, and [`FlowRecord`] from a raw
+//! `flow_records::FlowRecord` with every field it was exported with.  Requires the `proto`
+//! feature, which pulls in no dependencies.
+//!
+//! ```rust
+//! use netflow_parser::proto::Flow;
+//! use netflow_parser::NetflowParser;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! for flowset in NetflowParser::default().parse_bytes_as_netflow_common_flowsets(&v5_packet) {
+//!     let bytes = Flow::from(&flowset).encode_to_vec();
+//!     assert_eq!(&bytes[..6], [10, 4, 0, 1, 2, 3]);
+//! }
+//! ```
+
+use crate::flow_records;
+use crate::netflow_common::NetflowCommonFlowSet;
+use crate::static_versions::{v5, v7};
+use crate::variable_versions::data_number::{CustomValue, DataNumber, FieldValue};
+use crate::variable_versions::ipfix_lookup::IPFixField;
+use crate::variable_versions::v9_lookup::V9Field;
+use crate::variable_versions::{ipfix, v9};
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::net::{IpAddr, Ipv4Addr};
+
+/// The `.proto` definition of the messages.
+pub const SCHEMA: &str = include_str!("../proto/netflow.proto");
+
+const VARINT: u32 = 0;
+const FIXED64: u32 = 1;
+const LENGTH_DELIMITED: u32 = 2;
+
+/// `Flow` message, a flow in the version independent form of `NetflowCommonFlowSet`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Flow {
+    pub src_addr: Option<IpAddr>,
+    pub dst_addr: Option<IpAddr>,
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+    pub src_as: Option<u32>,
+    pub dst_as: Option<u32>,
+    pub protocol: Option<u8>,
+    pub first_seen: Option<u32>,
+    pub last_seen: Option<u32>,
+    pub src_mac: Option<String>,
+    pub dst_mac: Option<String>,
+    pub input_interface: Option<u32>,
+    pub output_interface: Option<u32>,
+    pub input_interface_name: Option<String>,
+    pub output_interface_name: Option<String>,
+    pub export_delay_ms: Option<u64>,
+    pub tcp_flags: Option<u16>,
+    pub tos: Option<u8>,
+    pub next_hop: Option<IpAddr>,
+}
+
+/// `FlowRecord` message, a raw flow record.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FlowRecord {
+    /// Netflow version of the packet the record came from, 10 for IPFix
+    pub version: u16,
+    pub fields: Vec<Field>,
+}
+
+/// `Field` message, a field of a [`FlowRecord`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    /// V9 field type or IPFix information element id
+    pub field_type: u16,
+    /// Enterprise number of an IPFix enterprise specific information element
+    pub enterprise_number: Option<u32>,
+    /// `None` for values with no protobuf form, such as nested custom values
+    pub value: Option<Value>,
+}
+
+/// The `value` oneof of a [`Field`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    Bytes(Vec<u8>),
+    Ip(IpAddr),
+    DurationNanos(u64),
+    /// Since the unix epoch
+    TimestampNanos(u64),
+}
+
+impl From<&NetflowCommonFlowSet> for Flow {
+    fn from(flowset: &NetflowCommonFlowSet) -> Self {
+        Flow {
+            src_addr: flowset.src_addr,
+            dst_addr: flowset.dst_addr,
+            src_port: flowset.src_port.map(|port| port.0),
+            dst_port: flowset.dst_port.map(|port| port.0),
+            src_as: flowset.src_as.map(|asn| asn.0),
+            dst_as: flowset.dst_as.map(|asn| asn.0),
+            protocol: flowset.protocol_number,
+            first_seen: flowset.first_seen,
+            last_seen: flowset.last_seen,
+            src_mac: flowset.src_mac.clone(),
+            dst_mac: flowset.dst_mac.clone(),
+            input_interface: flowset.input_interface,
+            output_interface: flowset.output_interface,
+            input_interface_name: flowset.input_interface_name.clone(),
+            output_interface_name: flowset.output_interface_name.clone(),
+            export_delay_ms: flowset
+                .export_delay
+                .map(|delay| u64::try_from(delay.as_millis()).unwrap_or(u64::MAX)),
+            tcp_flags: flowset.tcp_flags,
+            tos: flowset.tos,
+            next_hop: flowset.next_hop,
+        }
+    }
+}
+
+impl From<&FieldValue> for Option<Value> {
+    fn from(value: &FieldValue) -> Self {
+        Some(match value {
+            FieldValue::DataNumber(number) => match *number {
+                DataNumber::U8(n) => Value::Unsigned(n.into()),
+                DataNumber::U16(n) => Value::Unsigned(n.into()),
+                DataNumber::U24(n) | DataNumber::U32(n) => Value::Unsigned(n.into()),
+                DataNumber::U64(n) => Value::Unsigned(n),
+                DataNumber::U128(n) => match u64::try_from(n) {
+                    Ok(n) => Value::Unsigned(n),
+                    Err(_) => Value::Bytes(n.to_be_bytes().to_vec()),
+                },
+                DataNumber::I24(n) | DataNumber::I32(n) => Value::Signed(n.into()),
+            },
+            FieldValue::Float32(f) => Value::Float((*f).into()),
+            FieldValue::Float64(f) => Value::Float(*f),
+            FieldValue::Bool(b) => Value::Boolean(*b),
+            FieldValue::String(s) | FieldValue::MacAddr(s) => Value::String(s.clone()),
+            FieldValue::Vec(bytes) | FieldValue::Unknown(bytes) => Value::Bytes(bytes.clone()),
+            FieldValue::Ip4Addr(ip) => Value::Ip((*ip).into()),
+            FieldValue::Ip6Addr(ip) => Value::Ip((*ip).into()),
+            FieldValue::ProtocolType(protocol) => Value::Unsigned(u8::from(*protocol).into()),
+            FieldValue::Duration(d) => Value::DurationNanos(nanos(d.as_nanos())),
+            FieldValue::DateTimeSeconds(d)
+            | FieldValue::DateTimeMillis(d)
+            | FieldValue::DateTimeMicros(d)
+            | FieldValue::DateTimeNanos(d) => Value::TimestampNanos(nanos(d.as_nanos())),
+            FieldValue::Custom(custom) => match &custom.value {
+                CustomValue::Bool(b) => Value::Boolean(*b),
+                CustomValue::Unsigned(n) => Value::Unsigned(*n),
+                CustomValue::Signed(n) => Value::Signed(*n),
+                CustomValue::Float(f) => Value::Float(*f),
+                CustomValue::String(s) => Value::String(s.clone()),
+                CustomValue::Bytes(bytes) => Value::Bytes(bytes.clone()),
+                CustomValue::List(_) | CustomValue::Map(_) => return None,
+            },
+        })
+    }
+}
+
+fn nanos(nanos: u128) -> u64 {
+    u64::try_from(nanos).unwrap_or(u64::MAX)
+}
+
+/// V9/IPFix fields are written under the type of their variant, and skipped when it has none
+/// (`Unknown`, `Vendor` and `Enterprise` fields, whose numbers only their template has).  Use
+/// [`FlowRecord::from_v9_data`] or [`FlowRecord::from_ipfix_data`] to keep them.
+impl From<&flow_records::FlowRecord> for FlowRecord {
+    fn from(record: &flow_records::FlowRecord) -> Self {
+        let fields = match record {
+            flow_records::FlowRecord::V5(flowset) => v5_fields(flowset),
+            flow_records::FlowRecord::V7(flowset) => v7_fields(flowset),
+            flow_records::FlowRecord::V9(fields) => fields
+                .iter()
+                .filter(|(field, _)| !matches!(field, V9Field::Vendor | V9Field::Unknown))
+                .map(|(field, value)| Field::new(*field as u16, value))
+                .collect(),
+            flow_records::FlowRecord::IPFix(fields) => fields
+                .iter()
+                .filter(|(field, _)| {
+                    !matches!(field, IPFixField::Enterprise | IPFixField::Unknown)
+                })
+                .map(|(field, value)| Field::new(*field as u16, value))
+                .collect(),
+        };
+        FlowRecord {
+            version: record.version(),
+            fields,
+        }
+    }
+}
+
+impl FlowRecord {
+    /// The records of a V9 data flowset, each field under the type number its template
+    /// gives it.  Empty when the data was parsed without a template.
+    pub fn from_v9_data(data: &v9::Data) -> Vec<FlowRecord> {
+        let Some(template) = &data.template else {
+            return vec![];
+        };
+        data.data_fields
+            .iter()
+            .map(|record| FlowRecord {
+                version: 9,
+                fields: record
+                    .iter()
+                    .filter_map(|(index, (_, value))| {
+                        let field = template.fields.get(*index)?;
+                        Some(Field::new(field.field_type_number, value))
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// The records of an IPFix data set, each field under the information element id and
+    /// enterprise number its template gives it.  Empty when the data was parsed without a
+    /// template.
+    pub fn from_ipfix_data(data: &ipfix::Data) -> Vec<FlowRecord> {
+        let Some(template) = &data.template else {
+            return vec![];
+        };
+        data.data_fields
+            .iter()
+            .map(|record| FlowRecord {
+                version: 10,
+                fields: record
+                    .iter()
+                    .filter_map(|(index, (_, value))| {
+                        let field = template.fields.get(*index)?;
+                        Some(Field {
+                            enterprise_number: field.enterprise_number,
+                            ..Field::new(field.field_type_number, value)
+                        })
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+impl Field {
+    fn new(field_type: u16, value: &FieldValue) -> Self {
+        Field {
+            field_type,
+            enterprise_number: None,
+            value: value.into(),
+        }
+    }
+}
+
+fn field(field_type: u16, value: Value) -> Field {
+    Field {
+        field_type,
+        enterprise_number: None,
+        value: Some(value),
+    }
+}
+
+fn ip(ip: Ipv4Addr) -> Value {
+    Value::Ip(ip.into())
+}
+
+/// V5 fields under their V9 field types.
+fn v5_fields(flowset: &v5::FlowSet) -> Vec<Field> {
+    vec![
+        field(8, ip(flowset.src_addr)),
+        field(12, ip(flowset.dst_addr)),
+        field(15, ip(flowset.next_hop)),
+        field(10, Value::Unsigned(flowset.input.into())),
+        field(14, Value::Unsigned(flowset.output.into())),
+        field(2, Value::Unsigned(flowset.d_pkts.into())),
+        field(1, Value::Unsigned(flowset.d_octets.into())),
+        field(22, Value::Unsigned(flowset.first.into())),
+        field(21, Value::Unsigned(flowset.last.into())),
+        field(7, Value::Unsigned(flowset.src_port.into())),
+        field(11, Value::Unsigned(flowset.dst_port.into())),
+        field(6, Value::Unsigned(flowset.tcp_flags.into())),
+        field(4, Value::Unsigned(flowset.protocol_number.into())),
+        field(5, Value::Unsigned(flowset.tos.into())),
+        field(16, Value::Unsigned(flowset.src_as.into())),
+        field(17, Value::Unsigned(flowset.dst_as.into())),
+        field(9, Value::Unsigned(flowset.src_mask.into())),
+        field(13, Value::Unsigned(flowset.dst_mask.into())),
+    ]
+}
+
+/// V7 fields under their V9 field types.
+fn v7_fields(flowset: &v7::FlowSet) -> Vec<Field> {
+    vec![
+        field(8, ip(flowset.src_addr)),
+        field(12, ip(flowset.dst_addr)),
+        field(15, ip(flowset.next_hop)),
+        field(10, Value::Unsigned(flowset.input.into())),
+        field(14, Value::Unsigned(flowset.output.into())),
+        field(2, Value::Unsigned(flowset.d_pkts.into())),
+        field(1, Value::Unsigned(flowset.d_octets.into())),
+        field(22, Value::Unsigned(flowset.first.into())),
+        field(21, Value::Unsigned(flowset.last.into())),
+        field(7, Value::Unsigned(flowset.src_port.into())),
+        field(11, Value::Unsigned(flowset.dst_port.into())),
+        field(6, Value::Unsigned(flowset.tcp_flags.into())),
+        field(4, Value::Unsigned(flowset.protocol_number.into())),
+        field(5, Value::Unsigned(flowset.tos.into())),
+        field(16, Value::Unsigned(flowset.src_as.into())),
+        field(17, Value::Unsigned(flowset.dst_as.into())),
+        field(9, Value::Unsigned(flowset.src_mask.into())),
+        field(13, Value::Unsigned(flowset.dst_mask.into())),
+    ]
+}
+
+impl Flow {
+    /// The message in protobuf wire format.
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let Flow {
+            src_addr,
+            dst_addr,
+            src_port,
+            dst_port,
+            src_as,
+            dst_as,
+            protocol,
+            first_seen,
+            last_seen,
+            src_mac,
+            dst_mac,
+            input_interface,
+            output_interface,
+            input_interface_name,
+            output_interface_name,
+            export_delay_ms,
+            tcp_flags,
+            tos,
+            next_hop,
+        } = self;
+        if let Some(addr) = src_addr {
+            write_bytes(&mut buf, 1, &ip_bytes(addr));
+        }
+        if let Some(addr) = dst_addr {
+            write_bytes(&mut buf, 2, &ip_bytes(addr));
+        }
+        let numbers = [
+            (3, src_port.map(u64::from)),
+            (4, dst_port.map(u64::from)),
+            (5, src_as.map(u64::from)),
+            (6, dst_as.map(u64::from)),
+            (7, protocol.map(u64::from)),
+            (8, first_seen.map(u64::from)),
+            (9, last_seen.map(u64::from)),
+        ];
+        for (number, value) in numbers {
+            if let Some(value) = value {
+                write_uint(&mut buf, number, value);
+            }
+        }
+        if let Some(mac) = src_mac {
+            write_bytes(&mut buf, 10, mac.as_bytes());
+        }
+        if let Some(mac) = dst_mac {
+            write_bytes(&mut buf, 11, mac.as_bytes());
+        }
+        if let Some(index) = input_interface {
+            write_uint(&mut buf, 12, (*index).into());
+        }
+        if let Some(index) = output_interface {
+            write_uint(&mut buf, 13, (*index).into());
+        }
+        if let Some(name) = input_interface_name {
+            write_bytes(&mut buf, 14, name.as_bytes());
+        }
+        if let Some(name) = output_interface_name {
+            write_bytes(&mut buf, 15, name.as_bytes());
+        }
+        if let Some(delay) = export_delay_ms {
+            write_uint(&mut buf, 16, *delay);
+        }
+        if let Some(flags) = tcp_flags {
+            write_uint(&mut buf, 17, (*flags).into());
+        }
+        if let Some(tos) = tos {
+            write_uint(&mut buf, 18, (*tos).into());
+        }
+        if let Some(addr) = next_hop {
+            write_bytes(&mut buf, 19, &ip_bytes(addr));
+        }
+        buf
+    }
+}
+
+impl FlowRecord {
+    /// The message in protobuf wire format.
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if self.version != 0 {
+            write_uint(&mut buf, 1, self.version.into());
+        }
+        for field in &self.fields {
+            write_bytes(&mut buf, 2, &field.encode_to_vec());
+        }
+        buf
+    }
+}
+
+impl Field {
+    /// The message in protobuf wire format.
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if self.field_type != 0 {
+            write_uint(&mut buf, 1, self.field_type.into());
+        }
+        match &self.value {
+            Some(Value::Unsigned(n)) => write_uint(&mut buf, 2, *n),
+            Some(Value::Signed(n)) => write_uint(&mut buf, 3, ((n << 1) ^ (n >> 63)) as u64),
+            Some(Value::Float(f)) => {
+                write_key(&mut buf, 4, FIXED64);
+                buf.extend_from_slice(&f.to_le_bytes());
+            }
+            Some(Value::Boolean(b)) => write_uint(&mut buf, 5, u64::from(*b)),
+            Some(Value::String(s)) => write_bytes(&mut buf, 6, s.as_bytes()),
+            Some(Value::Bytes(bytes)) => write_bytes(&mut buf, 7, bytes),
+            Some(Value::Ip(addr)) => write_bytes(&mut buf, 8, &ip_bytes(addr)),
+            Some(Value::DurationNanos(n)) => write_uint(&mut buf, 9, *n),
+            Some(Value::TimestampNanos(n)) => write_uint(&mut buf, 10, *n),
+            None => {}
+        }
+        if let Some(enterprise_number) = self.enterprise_number {
+            write_uint(&mut buf, 11, enterprise_number.into());
+        }
+        buf
+    }
+}
+
+fn ip_bytes(addr: &IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_key(buf: &mut Vec<u8>, number: u32, wire_type: u32) {
+    write_varint(buf, u64::from((number << 3) | wire_type));
+}
+
+fn write_uint(buf: &mut Vec<u8>, number: u32, value: u64) {
+    write_key(buf, number, VARINT);
+    write_varint(buf, value);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, number: u32, bytes: &[u8]) {
+    write_key(buf, number, LENGTH_DELIMITED);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod proto_tests {
+    use super::{Field, Flow, FlowRecord, Value};
+    use crate::testing::V9_PACKET;
+    use crate::{NetflowPacket, NetflowParser};
+
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn it_encodes_flows() {
+        let flow = Flow {
+            src_addr: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+            dst_port: Some(443),
+            protocol: Some(6),
+            input_interface_name: Some("eth0".to_string()),
+            export_delay_ms: Some(300),
+            ..Default::default()
+        };
+        assert_eq!(
+            flow.encode_to_vec(),
+            vec![
+                10, 4, 192, 0, 2, 1, 32, 187, 3, 56, 6, 114, 4, b'e', b't', b'h', b'0', 128, 1,
+                172, 2,
+            ]
+        );
+        assert!(Flow::default().encode_to_vec().is_empty());
+    }
+
+    #[test]
+    fn it_encodes_raw_records() {
+        let packet = V9_PACKET;
+        let record = NetflowParser::default()
+            .iter_flow_records(&packet)
+            .next()
+            .unwrap();
+        let record = FlowRecord::from(&record);
+        assert_eq!(
+            record.fields,
+            vec![
+                Field {
+                    field_type: 1,
+                    enterprise_number: None,
+                    value: Some(Value::Unsigned(0x09020304)),
+                },
+                Field {
+                    field_type: 8,
+                    enterprise_number: None,
+                    value: Some(Value::Ip(IpAddr::V4(Ipv4Addr::new(9, 9, 9, 8)))),
+                },
+            ]
+        );
+        assert_eq!(
+            record.encode_to_vec(),
+            vec![8, 9, 18, 7, 8, 1, 16, 132, 134, 136, 72, 18, 8, 8, 8, 66, 4, 9, 9, 9, 8,]
+        );
+
+        let signed = Field {
+            field_type: 1,
+            enterprise_number: None,
+            value: Some(Value::Signed(-2)),
+        };
+        assert_eq!(signed.encode_to_vec(), vec![8, 1, 24, 3]);
+    }
+
+    #[test]
+    fn it_writes_the_field_type_numbers_of_templates() {
+        // Field type 1024 is unknown to the parser
+        let packet = [
+            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 4, 0, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
+        ];
+        let record = NetflowParser::default()
+            .iter_flow_records(&packet)
+            .next()
+            .unwrap();
+        assert_eq!(FlowRecord::from(&record).fields.len(), 1);
+
+        let NetflowPacket::V9(v9) = NetflowParser::default().parse_bytes(&packet).remove(0)
+        else {
+            panic!("expected a V9 packet");
+        };
+        let data = v9.flowsets[1].body.data.as_ref().unwrap();
+        let records = FlowRecord::from_v9_data(data);
+        let types: Vec<u16> = records[0].fields.iter().map(|f| f.field_type).collect();
+        assert_eq!(types, vec![1, 1024]);
+    }
+}
@@ -1,307 +1,263 @@
-use nom_derive::*;
+//! IP protocol numbers.
+
+use alloc::string::String;
+use core::fmt;
+use core::str::FromStr;
+
+use nom::combinator::map;
+use nom::number::complete::be_u8;
+use nom::IResult;
 use serde::Serialize;
 
-#[repr(u8)]
-#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Serialize, Nom)]
-pub enum ProtocolTypes {
-    Hopopt = 0,
-    Icmp = 1,
-    Igmp = 2,
-    Ggp = 3,
-    Ipv4 = 4,
-    St = 5,
-    Tcp = 6,
-    Cbt = 7,
-    Egp = 8,
-    Igp = 9,
-    Bbcrccmon = 10,
-    Nvpii = 11,
-    Pup = 12,
-    Argus = 13,
-    Emcon = 14,
-    Xnet = 15,
-    Chaos = 16,
-    Udp = 17,
-    Mux = 18,
-    Dcnmeas = 19,
-    Hmp = 20,
-    Prm = 21,
-    Xnxidp = 22,
-    Trunk1 = 23,
-    Trunk2 = 24,
-    Leaf1 = 25,
-    Leaf2 = 26,
-    Rdp = 27,
-    Irtp = 28,
-    Isotp4 = 29,
-    Netblt = 30,
-    Mfensp = 31,
-    Meritinp = 32,
-    Dccp = 33,
-    Threepc = 34,
-    Idpr = 35,
-    Xtp = 36,
-    Ddp = 37,
-    Idprcmtp = 38,
-    Tppp = 39,
-    Il = 40,
-    Ipv6 = 41,
-    Sdrp = 42,
-    Ipv6Route = 43,
-    Ipv6Frag = 44,
-    Idrp = 45,
-    Rsvp = 46,
-    Gre = 47,
-    Dsr = 48,
-    Bna = 49,
-    Esp = 50,
-    Ah = 51,
-    Inlsp = 52,
-    Swipe = 53,
-    Narp = 54,
-    Mobile = 55,
-    Tlsp = 56,
-    Skip = 57,
-    Ipv6Icmp = 58,
-    Ipv6Nonxt = 59,
-    Ipv6Opts = 60,
-    Anydistributedprotocol = 61,
-    Cftp = 62,
-    Anylocalnetwork = 63,
-    Satexpak = 64,
-    Kryptolan = 65,
-    Rvd = 66,
-    Ippc = 67,
-    Anydistributedfilesystem = 68,
-    Satmon = 69,
-    Visa = 70,
-    Ipcv = 71,
-    Cpnx = 72,
-    Cphb = 73,
-    Wsn = 74,
-    Pvp = 75,
-    Brsatmon = 76,
-    Sunnd = 77,
-    Wbmon = 78,
-    Wbexpak = 79,
-    Isoip = 80,
-    Vmtp = 81,
-    Securevmtp = 82,
-    Vines = 83,
-    Iptm = 84,
-    Nsfnetigp = 85,
-    Dgp = 86,
-    Tcf = 87,
-    Eigrp = 88,
-    Ospfigp = 89,
-    Spriterpc = 90,
-    Larp = 91,
-    Mtp = 92,
-    Ax25 = 93,
-    Ipip = 94,
-    Micp = 95,
-    Sccsp = 96,
-    Etherip = 97,
-    Encap = 98,
-    Anyprivateencryptionscheme = 99,
-    Gmtp = 100,
-    Ifmp = 101,
-    Pnni = 102,
-    Pim = 103,
-    Aris = 104,
-    Scps = 105,
-    Qnx = 106,
-    An = 107,
-    Ipcomp = 108,
-    Snp = 109,
-    Compaqpeer = 110,
-    Ipxinip = 111,
-    Vrrp = 112,
-    Pgm = 113,
-    Any0Hopprotocol = 114,
-    L2Tp = 115,
-    Ddx = 116,
-    Iatp = 117,
-    Stp = 118,
-    Srp = 119,
-    Uti = 120,
-    Smp = 121,
-    Sm = 122,
-    Ptp = 123,
-    Isisoveripv4 = 124,
-    Fire = 125,
-    Crtp = 126,
-    Crudp = 127,
-    Sscopmce = 128,
-    Iplt = 129,
-    Sps = 130,
-    Pipe = 131,
-    Sctp = 132,
-    Fc = 133,
-    Rsvpe2Eignore = 134,
-    Mobilityheader = 135,
-    Udplite = 136,
-    Mplsinip = 137,
-    Manet = 138,
-    Hip = 139,
-    Shim6 = 140,
-    Wesp = 141,
-    Rohc = 142,
-    Ethernet = 143,
-    Aggfrag = 144,
-    Unknown,
-    Reserved = 255,
+macro_rules! protocol_types {
+    ($($number:literal => $name:ident, $keyword:expr;)*) => {
+        /// IP protocol of a flow, by its number in the IANA registry.  Numbers IANA hasn't
+        /// assigned are kept as `Other`.
+        #[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash, Serialize)]
+        pub enum ProtocolTypes {
+            $($name,)*
+            Other(u8),
+        }
+
+        impl From<u8> for ProtocolTypes {
+            fn from(item: u8) -> Self {
+                match item {
+                    $($number => ProtocolTypes::$name,)*
+                    _ => ProtocolTypes::Other(item),
+                }
+            }
+        }
+
+        impl From<ProtocolTypes> for u8 {
+            fn from(protocol: ProtocolTypes) -> Self {
+                match protocol {
+                    $(ProtocolTypes::$name => $number,)*
+                    ProtocolTypes::Other(number) => number,
+                }
+            }
+        }
+
+        impl ProtocolTypes {
+            /// Lower case IANA keyword of the protocol, such as `"tcp"` or `"ipv6-icmp"`.
+            /// `None` for numbers without one, such as those for experimentation.
+            pub fn keyword(&self) -> Option<&'static str> {
+                match self {
+                    $(ProtocolTypes::$name => $keyword,)*
+                    ProtocolTypes::Other(_) => None,
+                }
+            }
+        }
+    };
+}
+
+protocol_types! {
+    0 => Hopopt, Some("hopopt");
+    1 => Icmp, Some("icmp");
+    2 => Igmp, Some("igmp");
+    3 => Ggp, Some("ggp");
+    4 => Ipv4, Some("ipv4");
+    5 => St, Some("st");
+    6 => Tcp, Some("tcp");
+    7 => Cbt, Some("cbt");
+    8 => Egp, Some("egp");
+    9 => Igp, Some("igp");
+    10 => Bbcrccmon, Some("bbn-rcc-mon");
+    11 => Nvpii, Some("nvp-ii");
+    12 => Pup, Some("pup");
+    13 => Argus, Some("argus");
+    14 => Emcon, Some("emcon");
+    15 => Xnet, Some("xnet");
+    16 => Chaos, Some("chaos");
+    17 => Udp, Some("udp");
+    18 => Mux, Some("mux");
+    19 => Dcnmeas, Some("dcn-meas");
+    20 => Hmp, Some("hmp");
+    21 => Prm, Some("prm");
+    22 => Xnxidp, Some("xns-idp");
+    23 => Trunk1, Some("trunk-1");
+    24 => Trunk2, Some("trunk-2");
+    25 => Leaf1, Some("leaf-1");
+    26 => Leaf2, Some("leaf-2");
+    27 => Rdp, Some("rdp");
+    28 => Irtp, Some("irtp");
+    29 => Isotp4, Some("iso-tp4");
+    30 => Netblt, Some("netblt");
+    31 => Mfensp, Some("mfe-nsp");
+    32 => Meritinp, Some("merit-inp");
+    33 => Dccp, Some("dccp");
+    34 => Threepc, Some("3pc");
+    35 => Idpr, Some("idpr");
+    36 => Xtp, Some("xtp");
+    37 => Ddp, Some("ddp");
+    38 => Idprcmtp, Some("idpr-cmtp");
+    39 => Tppp, Some("tp++");
+    40 => Il, Some("il");
+    41 => Ipv6, Some("ipv6");
+    42 => Sdrp, Some("sdrp");
+    43 => Ipv6Route, Some("ipv6-route");
+    44 => Ipv6Frag, Some("ipv6-frag");
+    45 => Idrp, Some("idrp");
+    46 => Rsvp, Some("rsvp");
+    47 => Gre, Some("gre");
+    48 => Dsr, Some("dsr");
+    49 => Bna, Some("bna");
+    50 => Esp, Some("esp");
+    51 => Ah, Some("ah");
+    52 => Inlsp, Some("i-nlsp");
+    53 => Swipe, Some("swipe");
+    54 => Narp, Some("narp");
+    55 => Mobile, Some("min-ipv4");
+    56 => Tlsp, Some("tlsp");
+    57 => Skip, Some("skip");
+    58 => Ipv6Icmp, Some("ipv6-icmp");
+    59 => Ipv6Nonxt, Some("ipv6-nonxt");
+    60 => Ipv6Opts, Some("ipv6-opts");
+    61 => Anydistributedprotocol, None;
+    62 => Cftp, Some("cftp");
+    63 => Anylocalnetwork, None;
+    64 => Satexpak, Some("sat-expak");
+    65 => Kryptolan, Some("kryptolan");
+    66 => Rvd, Some("rvd");
+    67 => Ippc, Some("ippc");
+    68 => Anydistributedfilesystem, None;
+    69 => Satmon, Some("sat-mon");
+    70 => Visa, Some("visa");
+    71 => Ipcv, Some("ipcv");
+    72 => Cpnx, Some("cpnx");
+    73 => Cphb, Some("cphb");
+    74 => Wsn, Some("wsn");
+    75 => Pvp, Some("pvp");
+    76 => Brsatmon, Some("br-sat-mon");
+    77 => Sunnd, Some("sun-nd");
+    78 => Wbmon, Some("wb-mon");
+    79 => Wbexpak, Some("wb-expak");
+    80 => Isoip, Some("iso-ip");
+    81 => Vmtp, Some("vmtp");
+    82 => Securevmtp, Some("secure-vmtp");
+    83 => Vines, Some("vines");
+    84 => Iptm, Some("iptm");
+    85 => Nsfnetigp, Some("nsfnet-igp");
+    86 => Dgp, Some("dgp");
+    87 => Tcf, Some("tcf");
+    88 => Eigrp, Some("eigrp");
+    89 => Ospfigp, Some("ospfigp");
+    90 => Spriterpc, Some("sprite-rpc");
+    91 => Larp, Some("larp");
+    92 => Mtp, Some("mtp");
+    93 => Ax25, Some("ax.25");
+    94 => Ipip, Some("ipip");
+    95 => Micp, Some("micp");
+    96 => Sccsp, Some("scc-sp");
+    97 => Etherip, Some("etherip");
+    98 => Encap, Some("encap");
+    99 => Anyprivateencryptionscheme, None;
+    100 => Gmtp, Some("gmtp");
+    101 => Ifmp, Some("ifmp");
+    102 => Pnni, Some("pnni");
+    103 => Pim, Some("pim");
+    104 => Aris, Some("aris");
+    105 => Scps, Some("scps");
+    106 => Qnx, Some("qnx");
+    107 => An, Some("a/n");
+    108 => Ipcomp, Some("ipcomp");
+    109 => Snp, Some("snp");
+    110 => Compaqpeer, Some("compaq-peer");
+    111 => Ipxinip, Some("ipx-in-ip");
+    112 => Vrrp, Some("vrrp");
+    113 => Pgm, Some("pgm");
+    114 => Any0Hopprotocol, None;
+    115 => L2Tp, Some("l2tp");
+    116 => Ddx, Some("ddx");
+    117 => Iatp, Some("iatp");
+    118 => Stp, Some("stp");
+    119 => Srp, Some("srp");
+    120 => Uti, Some("uti");
+    121 => Smp, Some("smp");
+    122 => Sm, Some("sm");
+    123 => Ptp, Some("ptp");
+    124 => Isisoveripv4, Some("isis-over-ipv4");
+    125 => Fire, Some("fire");
+    126 => Crtp, Some("crtp");
+    127 => Crudp, Some("crudp");
+    128 => Sscopmce, Some("sscopmce");
+    129 => Iplt, Some("iplt");
+    130 => Sps, Some("sps");
+    131 => Pipe, Some("pipe");
+    132 => Sctp, Some("sctp");
+    133 => Fc, Some("fc");
+    134 => Rsvpe2Eignore, Some("rsvp-e2e-ignore");
+    135 => Mobilityheader, Some("mobility-header");
+    136 => Udplite, Some("udplite");
+    137 => Mplsinip, Some("mpls-in-ip");
+    138 => Manet, Some("manet");
+    139 => Hip, Some("hip");
+    140 => Shim6, Some("shim6");
+    141 => Wesp, Some("wesp");
+    142 => Rohc, Some("rohc");
+    143 => Ethernet, Some("ethernet");
+    144 => Aggfrag, Some("aggfrag");
+    145 => Nsh, Some("nsh");
+    146 => Homa, Some("homa");
+    147 => BitEmu, Some("bit-emu");
+    253 => Experimental253, None;
+    254 => Experimental254, None;
+    255 => Reserved, None;
 }
 
-impl From<u8> for ProtocolTypes {
-    fn from(item: u8) -> Self {
-        match item {
-            1 => ProtocolTypes::Hopopt,
-            2 => ProtocolTypes::Igmp,
-            3 => ProtocolTypes::Ggp,
-            4 => ProtocolTypes::Ipv4,
-            5 => ProtocolTypes::St,
-            6 => ProtocolTypes::Tcp,
-            7 => ProtocolTypes::Cbt,
-            8 => ProtocolTypes::Egp,
-            9 => ProtocolTypes::Igp,
-            10 => ProtocolTypes::Bbcrccmon,
-            11 => ProtocolTypes::Nvpii,
-            12 => ProtocolTypes::Pup,
-            13 => ProtocolTypes::Argus,
-            14 => ProtocolTypes::Emcon,
-            15 => ProtocolTypes::Xnet,
-            16 => ProtocolTypes::Chaos,
-            17 => ProtocolTypes::Udp,
-            18 => ProtocolTypes::Mux,
-            19 => ProtocolTypes::Dcnmeas,
-            20 => ProtocolTypes::Hmp,
-            21 => ProtocolTypes::Prm,
-            22 => ProtocolTypes::Xnxidp,
-            23 => ProtocolTypes::Trunk1,
-            24 => ProtocolTypes::Trunk2,
-            25 => ProtocolTypes::Leaf1,
-            26 => ProtocolTypes::Leaf2,
-            27 => ProtocolTypes::Rdp,
-            28 => ProtocolTypes::Irtp,
-            29 => ProtocolTypes::Isotp4,
-            30 => ProtocolTypes::Netblt,
-            31 => ProtocolTypes::Mfensp,
-            32 => ProtocolTypes::Meritinp,
-            33 => ProtocolTypes::Dccp,
-            34 => ProtocolTypes::Threepc,
-            35 => ProtocolTypes::Idpr,
-            36 => ProtocolTypes::Xtp,
-            37 => ProtocolTypes::Ddp,
-            38 => ProtocolTypes::Idprcmtp,
-            39 => ProtocolTypes::Tppp,
-            40 => ProtocolTypes::Il,
-            41 => ProtocolTypes::Ipv6,
-            42 => ProtocolTypes::Sdrp,
-            43 => ProtocolTypes::Ipv6Route,
-            44 => ProtocolTypes::Ipv6Frag,
-            45 => ProtocolTypes::Idrp,
-            46 => ProtocolTypes::Rsvp,
-            47 => ProtocolTypes::Gre,
-            48 => ProtocolTypes::Dsr,
-            49 => ProtocolTypes::Bna,
-            50 => ProtocolTypes::Esp,
-            51 => ProtocolTypes::Ah,
-            52 => ProtocolTypes::Inlsp,
-            53 => ProtocolTypes::Swipe,
-            54 => ProtocolTypes::Narp,
-            55 => ProtocolTypes::Mobile,
-            56 => ProtocolTypes::Tlsp,
-            57 => ProtocolTypes::Skip,
-            58 => ProtocolTypes::Ipv6Icmp,
-            59 => ProtocolTypes::Ipv6Nonxt,
-            60 => ProtocolTypes::Ipv6Opts,
-            61 => ProtocolTypes::Anydistributedprotocol,
-            62 => ProtocolTypes::Cftp,
-            63 => ProtocolTypes::Anylocalnetwork,
-            64 => ProtocolTypes::Satexpak,
-            65 => ProtocolTypes::Kryptolan,
-            66 => ProtocolTypes::Rvd,
-            67 => ProtocolTypes::Ippc,
-            68 => ProtocolTypes::Anydistributedfilesystem,
-            69 => ProtocolTypes::Satmon,
-            70 => ProtocolTypes::Visa,
-            71 => ProtocolTypes::Ipcv,
-            72 => ProtocolTypes::Cpnx,
-            73 => ProtocolTypes::Cphb,
-            74 => ProtocolTypes::Wsn,
-            75 => ProtocolTypes::Pvp,
-            76 => ProtocolTypes::Brsatmon,
-            77 => ProtocolTypes::Sunnd,
-            78 => ProtocolTypes::Wbmon,
-            79 => ProtocolTypes::Wbexpak,
-            80 => ProtocolTypes::Isoip,
-            81 => ProtocolTypes::Vmtp,
-            82 => ProtocolTypes::Securevmtp,
-            83 => ProtocolTypes::Vines,
-            84 => ProtocolTypes::Iptm,
-            85 => ProtocolTypes::Nsfnetigp,
-            86 => ProtocolTypes::Dgp,
-            87 => ProtocolTypes::Tcf,
-            88 => ProtocolTypes::Eigrp,
-            89 => ProtocolTypes::Ospfigp,
-            90 => ProtocolTypes::Spriterpc,
-            91 => ProtocolTypes::Larp,
-            92 => ProtocolTypes::Mtp,
-            93 => ProtocolTypes::Ax25,
-            94 => ProtocolTypes::Ipip,
-            95 => ProtocolTypes::Micp,
-            96 => ProtocolTypes::Sccsp,
-            97 => ProtocolTypes::Etherip,
-            98 => ProtocolTypes::Encap,
-            99 => ProtocolTypes::Anyprivateencryptionscheme,
-            100 => ProtocolTypes::Gmtp,
-            101 => ProtocolTypes::Ifmp,
-            102 => ProtocolTypes::Pnni,
-            103 => ProtocolTypes::Pim,
-            104 => ProtocolTypes::Aris,
-            105 => ProtocolTypes::Scps,
-            106 => ProtocolTypes::Qnx,
-            107 => ProtocolTypes::An,
-            108 => ProtocolTypes::Ipcomp,
-            109 => ProtocolTypes::Snp,
-            110 => ProtocolTypes::Compaqpeer,
-            111 => ProtocolTypes::Ipxinip,
-            112 => ProtocolTypes::Vrrp,
-            113 => ProtocolTypes::Pgm,
-            114 => ProtocolTypes::Any0Hopprotocol,
-            115 => ProtocolTypes::L2Tp,
-            116 => ProtocolTypes::Ddx,
-            117 => ProtocolTypes::Iatp,
-            118 => ProtocolTypes::Stp,
-            119 => ProtocolTypes::Srp,
-            120 => ProtocolTypes::Uti,
-            121 => ProtocolTypes::Smp,
-            122 => ProtocolTypes::Sm,
-            123 => ProtocolTypes::Ptp,
-            124 => ProtocolTypes::Isisoveripv4,
-            125 => ProtocolTypes::Fire,
-            126 => ProtocolTypes::Crtp,
-            127 => ProtocolTypes::Crudp,
-            128 => ProtocolTypes::Sscopmce,
-            129 => ProtocolTypes::Iplt,
-            130 => ProtocolTypes::Sps,
-            131 => ProtocolTypes::Pipe,
-            132 => ProtocolTypes::Sctp,
-            133 => ProtocolTypes::Fc,
-            134 => ProtocolTypes::Rsvpe2Eignore,
-            135 => ProtocolTypes::Mobilityheader,
-            136 => ProtocolTypes::Udplite,
-            137 => ProtocolTypes::Mplsinip,
-            138 => ProtocolTypes::Manet,
-            139 => ProtocolTypes::Hip,
-            140 => ProtocolTypes::Shim6,
-            141 => ProtocolTypes::Wesp,
-            142 => ProtocolTypes::Rohc,
-            143 => ProtocolTypes::Ethernet,
-            144 => ProtocolTypes::Reserved,
-            _ => ProtocolTypes::Unknown,
+impl ProtocolTypes {
+    /// Parses the protocol number byte.  Every number parses, unassigned ones as `Other`.
+    pub fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        map(be_u8, ProtocolTypes::from)(i)
+    }
+
+    /// Whether the protocol carries port numbers: TCP, UDP, DCCP, SCTP and UDP-Lite.
+    pub fn is_transport(&self) -> bool {
+        matches!(
+            self,
+            ProtocolTypes::Tcp
+                | ProtocolTypes::Udp
+                | ProtocolTypes::Dccp
+                | ProtocolTypes::Sctp
+                | ProtocolTypes::Udplite
+        )
+    }
+}
+
+/// The keyword of the protocol, or its number if it has none.
+impl fmt::Display for ProtocolTypes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.keyword() {
+            Some(keyword) => f.write_str(keyword),
+            None => write!(f, "{}", u8::from(*self)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseProtocolError(pub String);
+
+impl fmt::Display for ParseProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown IP protocol {:?}", self.0)
+    }
+}
+
+/// Parses an IANA keyword in any case, such as `"tcp"` or `"UDP"`, or a protocol number.
+impl FromStr for ProtocolTypes {
+    type Err = ParseProtocolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(number) = s.parse::<u8>() {
+            return Ok(number.into());
         }
+        (0..=u8::MAX)
+            .map(ProtocolTypes::from)
+            .find(|protocol| {
+                protocol
+                    .keyword()
+                    .is_some_and(|keyword| keyword.eq_ignore_ascii_case(s))
+            })
+            .ok_or_else(|| ParseProtocolError(s.into()))
     }
 }
 
@@ -314,7 +270,24 @@ mod protocol_lookup_tests {
 
     #[test]
     fn it_tests_protocol_types() {
-        let protocols = (0..=144).map(ProtocolTypes::from).collect::<Vec<_>>();
+        let protocols = (0..=u8::MAX).map(ProtocolTypes::from).collect::<Vec<_>>();
         assert_yaml_snapshot!(protocols);
+        for protocol in protocols {
+            assert_eq!(ProtocolTypes::from(u8::from(protocol)), protocol);
+            assert_eq!(protocol.to_string().parse(), Ok(protocol));
+        }
+    }
+
+    #[test]
+    fn it_parses_protocol_names() {
+        assert_eq!("tcp".parse(), Ok(ProtocolTypes::Tcp));
+        assert_eq!("UDP".parse(), Ok(ProtocolTypes::Udp));
+        assert_eq!("ipv6-icmp".parse(), Ok(ProtocolTypes::Ipv6Icmp));
+        assert_eq!("200".parse(), Ok(ProtocolTypes::Other(200)));
+        assert!("tcpp".parse::<ProtocolTypes>().is_err());
+        assert_eq!(ProtocolTypes::Icmp.to_string(), "icmp");
+        assert_eq!(ProtocolTypes::Other(200).to_string(), "200");
+        assert!(ProtocolTypes::Sctp.is_transport());
+        assert!(!ProtocolTypes::Icmp.is_transport());
     }
 }
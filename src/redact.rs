@@ -0,0 +1,378 @@
+//! # Redact
+//!
+//! Scrubs parsed packets before they are re-exported with `to_be_bytes`, for sharing
+//! captures and flows with vendors.  A [`Redactor`] zeroes configured V9 and IPFix fields,
+//! MAC addresses, captured packet sections and addresses in internal networks, in data and
+//! options records.  Values are replaced by zeroes of the same type, strings and bytes as
+//! long as their field in the template, so every record keeps its length and still matches
+//! its template.
+//!
+//! ```rust
+//! use netflow_parser::redact::Redactor;
+//! use netflow_parser::{NetflowPacket, NetflowParser};
+//! use std::net::{IpAddr, Ipv4Addr};
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let redactor = Redactor::default().with_network(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 8);
+//! let mut packet = NetflowParser::default().parse_bytes(&v5_packet).remove(0);
+//! redactor.redact(&mut packet);
+//! let NetflowPacket::V5(v5) = &packet else { unreachable!() };
+//! assert_eq!(v5.flowsets[0].src_addr, Ipv4Addr::UNSPECIFIED);
+//! assert_eq!(packet.to_be_bytes().unwrap().len(), v5_packet.len());
+//! ```
+
+use crate::anonymize::prefix_mask;
+use crate::protocol::ProtocolTypes;
+use crate::variable_versions::data_number::{
+    CustomField, CustomValue, DataNumber, FieldDataType, FieldValue,
+};
+use crate::variable_versions::ipfix_lookup::IPFixField;
+use crate::variable_versions::template_engine::CachedTemplate;
+use crate::variable_versions::v9_lookup::V9Field;
+use crate::NetflowPacket;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use core::time::Duration;
+
+/// IPFix fields carrying sections of the observed packets.
+const IPFIX_PACKET_SECTIONS: [IPFixField; 5] = [
+    IPFixField::Layer2packetSectionData,
+    IPFixField::IpHeaderPacketSection,
+    IPFixField::IpPayloadPacketSection,
+    IPFixField::DataLinkFrameSection,
+    IPFixField::MplsPayloadPacketSection,
+];
+
+/// What to scrub from packets.  Nothing is redacted by default.
+#[derive(Debug, Default, Clone)]
+pub struct Redactor {
+    v9_fields: Vec<V9Field>,
+    ipfix_fields: Vec<IPFixField>,
+    mac_addresses: bool,
+    packet_sections: bool,
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl Redactor {
+    /// Zeroes `field` in V9 data and options data records.
+    pub fn with_v9_field(mut self, field: V9Field) -> Self {
+        self.v9_fields.push(field);
+        self
+    }
+
+    /// Zeroes `field` in IPFix data and options data records.
+    pub fn with_ipfix_field(mut self, field: IPFixField) -> Self {
+        self.ipfix_fields.push(field);
+        self
+    }
+
+    /// Zeroes every MAC address field.
+    pub fn with_mac_addresses(mut self) -> Self {
+        self.mac_addresses = true;
+        self
+    }
+
+    /// Zeroes the headers, payloads and frames captured from observed packets, such as
+    /// `ipPayloadPacketSection`.
+    pub fn with_packet_sections(mut self) -> Self {
+        self.packet_sections = true;
+        self
+    }
+
+    /// Replaces addresses within `addr/prefix` by the unspecified address, `0.0.0.0` or `::`.
+    pub fn with_network(mut self, addr: IpAddr, prefix: u8) -> Self {
+        self.networks.push((addr, prefix));
+        self
+    }
+
+    /// Whether `addr` is in one of the redacted networks.
+    pub fn is_redacted_address(&self, addr: IpAddr) -> bool {
+        self.networks
+            .iter()
+            .any(|(network, prefix)| match (network, addr) {
+                (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                    let mask = prefix_mask::<u32>(*prefix);
+                    u32::from(*network) & mask == u32::from(addr) & mask
+                }
+                (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                    let mask = prefix_mask::<u128>(*prefix);
+                    u128::from(*network) & mask == u128::from(addr) & mask
+                }
+                _ => false,
+            })
+    }
+
+    /// Redacts `packet` in place.
+    pub fn redact(&self, packet: &mut NetflowPacket) {
+        match packet {
+            NetflowPacket::V5(v5) => {
+                for flowset in v5.flowsets.iter_mut() {
+                    for addr in [
+                        &mut flowset.src_addr,
+                        &mut flowset.dst_addr,
+                        &mut flowset.next_hop,
+                    ] {
+                        self.redact_v4(addr);
+                    }
+                }
+            }
+            NetflowPacket::V7(v7) => {
+                for flowset in v7.flowsets.iter_mut() {
+                    for addr in [
+                        &mut flowset.src_addr,
+                        &mut flowset.dst_addr,
+                        &mut flowset.next_hop,
+                        &mut flowset.router_src,
+                    ] {
+                        self.redact_v4(addr);
+                    }
+                }
+            }
+            NetflowPacket::V9(v9) => {
+                let redacted = |field: V9Field| {
+                    self.v9_fields.contains(&field)
+                        || (self.mac_addresses
+                            && FieldDataType::from(field) == FieldDataType::MacAddr)
+                        || (self.packet_sections && field == V9Field::Layer2packetSectionData)
+                };
+                for flowset in v9.flowsets.iter_mut() {
+                    if let Some(data) = flowset.body.data.as_mut() {
+                        let lengths =
+                            data.template.as_deref().map(CachedTemplate::field_lengths);
+                        for record in data.data_fields.iter_mut() {
+                            self.redact_record(record, lengths.as_deref(), redacted);
+                        }
+                    }
+                    if let Some(options_data) = flowset.body.options_data.as_mut() {
+                        for field in options_data.options_fields.iter_mut() {
+                            self.redact_bytes(
+                                field.field_type.into(),
+                                &mut field.field_value,
+                                redacted(field.field_type),
+                            );
+                        }
+                    }
+                }
+            }
+            NetflowPacket::IPFix(ipfix) => {
+                let redacted = |field: IPFixField| {
+                    self.ipfix_fields.contains(&field)
+                        || (self.mac_addresses
+                            && FieldDataType::from(field) == FieldDataType::MacAddr)
+                        || (self.packet_sections && IPFIX_PACKET_SECTIONS.contains(&field))
+                };
+                for flowset in ipfix.flowsets.iter_mut() {
+                    if let Some(data) = flowset.body.data.as_mut() {
+                        let lengths =
+                            data.template.as_deref().map(CachedTemplate::field_lengths);
+                        for record in data.data_fields.iter_mut() {
+                            self.redact_record(record, lengths.as_deref(), redacted);
+                        }
+                    }
+                    if let Some(data) = flowset.body.options_data.as_mut() {
+                        let lengths =
+                            data.template.as_deref().map(CachedTemplate::field_lengths);
+                        for record in data.data_fields.iter_mut() {
+                            self.redact_record(record, lengths.as_deref(), redacted);
+                        }
+                    }
+                }
+            }
+            NetflowPacket::Error(_) => {}
+        }
+    }
+
+    fn redact_v4(&self, addr: &mut Ipv4Addr) {
+        if self.is_redacted_address(IpAddr::V4(*addr)) {
+            *addr = Ipv4Addr::UNSPECIFIED;
+        }
+    }
+
+    /// Redacts the raw bytes of a field of `data_type`, zeroing them if `redacted`.
+    fn redact_bytes(&self, data_type: FieldDataType, bytes: &mut [u8], redacted: bool) {
+        let addr = match (
+            data_type,
+            <[u8; 4]>::try_from(&*bytes),
+            <[u8; 16]>::try_from(&*bytes),
+        ) {
+            (FieldDataType::Ip4Addr, Ok(addr), _) => Some(IpAddr::from(addr)),
+            (FieldDataType::Ip6Addr, _, Ok(addr)) => Some(IpAddr::from(addr)),
+            _ => None,
+        };
+        if redacted || addr.is_some_and(|addr| self.is_redacted_address(addr)) {
+            bytes.fill(0);
+        }
+    }
+
+    /// Redacts the fields of `record`, zeroing redacted ones to their length in `lengths`,
+    /// the field lengths of the record's template.
+    fn redact_record<F: Copy>(
+        &self,
+        record: &mut BTreeMap<usize, (F, FieldValue)>,
+        lengths: Option<&[u16]>,
+        redacted: impl Fn(F) -> bool,
+    ) {
+        for (index, (field, value)) in record.iter_mut() {
+            if redacted(*field) {
+                // Variable length IPFix fields keep the length of their value
+                let length = lengths
+                    .and_then(|lengths| lengths.get(*index))
+                    .filter(|length| **length != u16::MAX)
+                    .map(|length| usize::from(*length));
+                *value = zeroed(value, length);
+                continue;
+            }
+            match value {
+                FieldValue::Ip4Addr(addr) if self.is_redacted_address(IpAddr::V4(*addr)) => {
+                    *addr = Ipv4Addr::UNSPECIFIED
+                }
+                FieldValue::Ip6Addr(addr) if self.is_redacted_address(IpAddr::V6(*addr)) => {
+                    *addr = Ipv6Addr::UNSPECIFIED
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Zero of the type of `value`, written with the same number of bytes.  Strings and bytes
+/// are `length` long, the field's length on the wire, when it is known.
+fn zeroed(value: &FieldValue, length: Option<usize>) -> FieldValue {
+    let wire_length = |decoded: usize| length.unwrap_or(decoded);
+    match value {
+        FieldValue::String(s) => FieldValue::String("\0".repeat(wire_length(s.len()))),
+        FieldValue::DataNumber(number) => FieldValue::DataNumber(match number {
+            DataNumber::U8(_) => DataNumber::U8(0),
+            DataNumber::U16(_) => DataNumber::U16(0),
+            DataNumber::U24(_) => DataNumber::U24(0),
+            DataNumber::I24(_) => DataNumber::I24(0),
+            DataNumber::U32(_) => DataNumber::U32(0),
+            DataNumber::U64(_) => DataNumber::U64(0),
+            DataNumber::U128(_) => DataNumber::U128(0),
+            DataNumber::I32(_) => DataNumber::I32(0),
+        }),
+        FieldValue::Float32(_) => FieldValue::Float32(0.0),
+        FieldValue::Float64(_) => FieldValue::Float64(0.0),
+        FieldValue::Bool(_) => FieldValue::Bool(false),
+        FieldValue::Duration(_) => FieldValue::Duration(Duration::ZERO),
+        FieldValue::DateTimeSeconds(_) => FieldValue::DateTimeSeconds(Duration::ZERO),
+        FieldValue::DateTimeMillis(_) => FieldValue::DateTimeMillis(Duration::ZERO),
+        FieldValue::DateTimeMicros(_) => FieldValue::DateTimeMicros(Duration::ZERO),
+        FieldValue::DateTimeNanos(_) => FieldValue::DateTimeNanos(Duration::ZERO),
+        FieldValue::Ip4Addr(_) => FieldValue::Ip4Addr(Ipv4Addr::UNSPECIFIED),
+        FieldValue::Ip6Addr(_) => FieldValue::Ip6Addr(Ipv6Addr::UNSPECIFIED),
+        FieldValue::MacAddr(_) => FieldValue::MacAddr(String::from("00:00:00:00:00:00")),
+        FieldValue::Vec(bytes) => FieldValue::Vec(alloc::vec![0; wire_length(bytes.len())]),
+        FieldValue::ProtocolType(_) => FieldValue::ProtocolType(ProtocolTypes::from(0)),
+        FieldValue::Unknown(bytes) => {
+            FieldValue::Unknown(alloc::vec![0; wire_length(bytes.len())])
+        }
+        FieldValue::Custom(custom) => {
+            let zeroes = alloc::vec![0; wire_length(custom.raw.len())];
+            FieldValue::Custom(CustomField {
+                value: CustomValue::Bytes(zeroes.clone()),
+                raw: zeroes,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod redact_tests {
+    use super::Redactor;
+    use crate::variable_versions::data_number::{FieldValue, StringDecoding};
+    use crate::variable_versions::ipfix_lookup::IPFixField;
+    use crate::variable_versions::v9_lookup::V9Field;
+    use crate::NetflowParser;
+
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn it_redacts_ipfix_fields_keeping_lengths() {
+        // Template 256 of sourceIPv4Address, sourceMacAddress and ipPayloadPacketSection,
+        // and a record of it
+        let ipfix = [
+            0, 10, 0, 54, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 56, 0, 6, 1, 58, 0, 4, 1, 0, 0, 18, 10, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0xde,
+            0xad, 0xbe, 0xef,
+        ];
+        let mut packet = NetflowParser::default().parse_bytes(&ipfix).remove(0);
+        assert_eq!(packet.to_be_bytes().unwrap(), ipfix);
+
+        Redactor::default()
+            .with_network(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)), 24)
+            .redact(&mut packet);
+        assert_eq!(packet.to_be_bytes().unwrap(), ipfix);
+
+        Redactor::default()
+            .with_network(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8)
+            .with_mac_addresses()
+            .with_packet_sections()
+            .redact(&mut packet);
+        let mut redacted = ipfix;
+        redacted[40..].fill(0);
+        assert_eq!(packet.to_be_bytes().unwrap(), redacted);
+    }
+
+    #[test]
+    fn it_redacts_configured_fields() {
+        let ipfix = [
+            0, 10, 0, 54, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 56, 0, 6, 1, 58, 0, 4, 1, 0, 0, 18, 10, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0xde,
+            0xad, 0xbe, 0xef,
+        ];
+        let mut packet = NetflowParser::default().parse_bytes(&ipfix).remove(0);
+        Redactor::default()
+            .with_ipfix_field(IPFixField::SourceIpv4address)
+            .redact(&mut packet);
+        let crate::NetflowPacket::IPFix(ipfix) = packet else {
+            panic!("expected IPFix")
+        };
+        let record = &ipfix.flowsets[1].body.data.as_ref().unwrap().data_fields[0];
+        assert_eq!(record[&0].1, FieldValue::Ip4Addr(Ipv4Addr::UNSPECIFIED));
+        assert_ne!(record[&2].1, FieldValue::Vec(vec![0; 4]));
+    }
+
+    #[test]
+    fn it_zeroes_strings_to_their_field_length() {
+        // Template 256 of an 8 byte interfaceName, and a record of it
+        let ipfix = [
+            0, 10, 0, 40, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 12, 1, 0, 0, 1, 0, 82,
+            0, 8, 1, 0, 0, 12, b'e', b't', b'h', b'0', 0, 0, 0, 0,
+        ];
+        let mut parser = NetflowParser::builder()
+            .with_string_decoding(StringDecoding::TrimNul)
+            .build()
+            .unwrap();
+        let mut packet = parser.parse_bytes(&ipfix).remove(0);
+        Redactor::default()
+            .with_ipfix_field(IPFixField::InterfaceName)
+            .redact(&mut packet);
+        let mut redacted = ipfix;
+        redacted[32..].fill(0);
+        assert_eq!(packet.to_be_bytes().unwrap(), redacted);
+    }
+
+    #[test]
+    fn it_redacts_v9_options_data() {
+        // Options template 256 of a system scope, ipv4SrcAddr and inBytes, and a record of it
+        let v9 = [
+            0, 9, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 1, 0, 22, 1, 0, 0,
+            4, 0, 8, 0, 1, 0, 4, 0, 8, 0, 4, 0, 1, 0, 4, 1, 0, 0, 16, 0, 0, 0, 1, 10, 1, 2, 3,
+            0, 0, 0, 100,
+        ];
+        let mut packet = NetflowParser::default().parse_bytes(&v9).remove(0);
+        Redactor::default()
+            .with_network(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8)
+            .with_v9_field(V9Field::InBytes)
+            .redact(&mut packet);
+        let mut redacted = v9;
+        redacted[50..].fill(0);
+        assert_eq!(packet.to_be_bytes().unwrap(), redacted);
+    }
+}
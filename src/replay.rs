@@ -0,0 +1,566 @@
+//! # Replay
+//!
+//! Re-sends parsed or archived packets over UDP, for testing downstream collectors against
+//! recorded traffic.  V9 source ids and IPFix observation domain ids can be rewritten on the
+//! way, so a replay shows up as an exporter of its own.
+//!
+//! Templates are announced before the data that uses them.  Templates in replayed packets
+//! are sent as they are, and templates learned beforehand, such as from the parser that
+//! parsed a recording which doesn't start with its templates, are sent in a message of their
+//! own ahead of the first data set using them.  That message copies the header of the data
+//! message it precedes.  As a lost datagram or a restarted collector would otherwise never
+//! get them again, learned templates are announced again once
+//! [`DEFAULT_TEMPLATE_REFRESH_INTERVAL`] has passed or [`DEFAULT_TEMPLATE_REFRESH_PACKETS`]
+//! messages were sent since, as exporters refresh their templates.
+//!
+//! ```rust,no_run
+//! use netflow_parser::replay::Replayer;
+//! use netflow_parser::NetflowParser;
+//! use std::net::UdpSocket;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let socket = UdpSocket::bind("0.0.0.0:0")?;
+//! let mut replayer = Replayer::new(socket, "127.0.0.1:2055".parse().unwrap())
+//!     .with_source_id(42)
+//!     .with_observation_domain_id(42);
+//! for packet in NetflowParser::default().parse_bytes(&v5_packet) {
+//!     replayer.send(&packet)?;
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::archive::ArchiveReplay;
+use crate::variable_versions::{ipfix, v9};
+use crate::{NetflowError, NetflowPacket, NetflowParser};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long after announcing a template [`Replayer`] announces it again.
+pub const DEFAULT_TEMPLATE_REFRESH_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// How many messages [`Replayer`] sends after announcing a template before announcing it
+/// again.
+pub const DEFAULT_TEMPLATE_REFRESH_PACKETS: usize = 20;
+
+/// Ids of V9 and IPFix template sets, and the lowest data set id
+const V9_TEMPLATE_ID: u16 = 0;
+const V9_OPTIONS_TEMPLATE_ID: u16 = 1;
+const IPFIX_TEMPLATE_ID: u16 = 2;
+const IPFIX_OPTIONS_TEMPLATE_ID: u16 = 3;
+const MIN_DATA_SET_ID: u16 = 256;
+
+/// Version, original source id or observation domain id, and template id
+type TemplateKey = (u16, u32, u16);
+
+/// Version, original source id or observation domain id, or `None` for templates shared by
+/// every source, and template id
+type LearnedKey = (u16, Option<u32>, u16);
+
+#[derive(Debug, Clone)]
+enum CachedTemplate {
+    V9(Arc<v9::Template>),
+    V9Options(Arc<v9::OptionsTemplate>),
+    IPFix(Arc<ipfix::Template>),
+    IPFixOptions(Arc<ipfix::OptionsTemplate>),
+}
+
+/// Sends packets to a collector, announcing templates ahead of their data.
+#[derive(Debug)]
+pub struct Replayer {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    source_id: Option<u32>,
+    observation_domain_id: Option<u32>,
+    /// Templates known for data sets, by their original source
+    templates: BTreeMap<LearnedKey, CachedTemplate>,
+    /// Templates the destination has been sent, with when and `sent` at the time
+    announced: BTreeMap<TemplateKey, (Instant, usize)>,
+    /// Messages sent, template announcements included
+    sent: usize,
+    refresh_interval: Duration,
+    refresh_packets: usize,
+}
+
+/// Scopes to learn the templates of a parser for: the sources it has scoped templates for, or
+/// `None` for its shared templates.
+fn learned_scopes(scope_templates: bool, sources: BTreeSet<u32>) -> Vec<Option<u32>> {
+    if scope_templates {
+        sources.into_iter().map(Some).collect()
+    } else {
+        vec![None]
+    }
+}
+
+fn unexportable(error: NetflowError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+impl Replayer {
+    /// Sends from `socket` to `destination`.
+    pub fn new(socket: UdpSocket, destination: SocketAddr) -> Self {
+        Self {
+            socket,
+            destination,
+            source_id: None,
+            observation_domain_id: None,
+            templates: BTreeMap::new(),
+            announced: BTreeMap::new(),
+            sent: 0,
+            refresh_interval: DEFAULT_TEMPLATE_REFRESH_INTERVAL,
+            refresh_packets: DEFAULT_TEMPLATE_REFRESH_PACKETS,
+        }
+    }
+
+    /// Announces learned templates again once `interval` has passed since they were last
+    /// sent, [`DEFAULT_TEMPLATE_REFRESH_INTERVAL`] unless set.
+    pub fn with_template_refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    /// Announces learned templates again once `packets` messages were sent since they were
+    /// last sent, [`DEFAULT_TEMPLATE_REFRESH_PACKETS`] unless set.
+    pub fn with_template_refresh_packets(mut self, packets: usize) -> Self {
+        self.refresh_packets = packets;
+        self
+    }
+
+    /// Rewrites the source id of V9 packets to `source_id`.
+    pub fn with_source_id(mut self, source_id: u32) -> Self {
+        self.source_id = Some(source_id);
+        self
+    }
+
+    /// Rewrites the observation domain id of IPFix messages to `observation_domain_id`.
+    pub fn with_observation_domain_id(mut self, observation_domain_id: u32) -> Self {
+        self.observation_domain_id = Some(observation_domain_id);
+        self
+    }
+
+    /// Learns the V9 and IPFix templates `parser` has cached, to announce ahead of replayed
+    /// data that uses them.  Scoped templates are learned for the source id or observation
+    /// domain id they were cached for, and shared ones for every source.
+    pub fn with_templates_from(mut self, parser: &NetflowParser) -> Self {
+        let v9_engine = &parser.v9_parser.engine;
+        for scope in learned_scopes(v9_engine.scope_templates, v9_engine.known_sources()) {
+            let (templates, options_templates) =
+                v9_engine.cached_templates(scope.unwrap_or_default());
+            for template in templates {
+                self.templates.insert(
+                    (9, scope, template.template_id),
+                    CachedTemplate::V9(template.clone()),
+                );
+            }
+            for template in options_templates {
+                self.templates.insert(
+                    (9, scope, template.template_id),
+                    CachedTemplate::V9Options(template.clone()),
+                );
+            }
+        }
+        let ipfix_engine = &parser.ipfix_parser.engine;
+        for scope in learned_scopes(ipfix_engine.scope_templates, ipfix_engine.known_sources())
+        {
+            let (templates, options_templates) =
+                ipfix_engine.cached_templates(scope.unwrap_or_default());
+            for template in templates {
+                self.templates.insert(
+                    (10, scope, template.template_id),
+                    CachedTemplate::IPFix(template.clone()),
+                );
+            }
+            for template in options_templates {
+                self.templates.insert(
+                    (10, scope, template.template_id),
+                    CachedTemplate::IPFixOptions(template.clone()),
+                );
+            }
+        }
+        self
+    }
+
+    /// Templates learned so far, from `with_templates_from` and replayed packets.
+    pub fn learned_template_count(&self) -> usize {
+        self.templates.len()
+    }
+
+    /// Messages sent so far, template announcements included.
+    pub fn sent(&self) -> usize {
+        self.sent
+    }
+
+    /// Sends `packet`, preceded by a message announcing the learned templates its data sets
+    /// use, if the destination hasn't been sent them yet or they are due for a refresh.
+    /// `Error` packets and packets that can't be exported fail with `InvalidData`.
+    pub fn send(&mut self, packet: &NetflowPacket) -> io::Result<()> {
+        match packet {
+            NetflowPacket::V9(v9) => self.send_v9(v9),
+            NetflowPacket::IPFix(ipfix) => self.send_ipfix(ipfix),
+            packet => {
+                let bytes = packet.to_be_bytes().map_err(unexportable)?;
+                self.send_bytes(&bytes)
+            }
+        }
+    }
+
+    /// Sends every packet of `replay`, paced as the replay is, returning how many were
+    /// sent.  Packets that fail to parse are skipped.
+    pub fn send_archive<R: Read>(
+        &mut self,
+        replay: &mut ArchiveReplay<R>,
+    ) -> io::Result<usize> {
+        let mut sent = 0;
+        while let Some((_, packets)) = replay.next_packets()? {
+            for packet in packets.iter().filter(|packet| !packet.is_error()) {
+                self.send(packet)?;
+                sent += 1;
+            }
+        }
+        Ok(sent)
+    }
+
+    fn send_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.socket.send_to(bytes, self.destination)?;
+        self.sent += 1;
+        Ok(())
+    }
+
+    /// Learns the templates of `flowsets`, which the destination is sent along with them,
+    /// and takes the learned templates used by its data sets that haven't been announced or
+    /// are due for a refresh.
+    fn unannounced(
+        &mut self,
+        version: u16,
+        domain: u32,
+        templates: impl Iterator<Item = (u16, CachedTemplate)>,
+        data_set_ids: impl Iterator<Item = u16>,
+    ) -> Vec<CachedTemplate> {
+        let now = Instant::now();
+        for (template_id, template) in templates {
+            self.templates
+                .insert((version, Some(domain), template_id), template);
+            self.announced
+                .insert((version, domain, template_id), (now, self.sent));
+        }
+        let mut unannounced = vec![];
+        for set_id in data_set_ids.filter(|id| *id >= MIN_DATA_SET_ID) {
+            let key = (version, domain, set_id);
+            let Some(template) = self
+                .templates
+                .get(&(version, Some(domain), set_id))
+                .or_else(|| self.templates.get(&(version, None, set_id)))
+            else {
+                continue;
+            };
+            let due = self.announced.get(&key).is_none_or(|(at, sent)| {
+                now.duration_since(*at) >= self.refresh_interval
+                    || self.sent - sent >= self.refresh_packets
+            });
+            if due {
+                self.announced.insert(key, (now, self.sent));
+                unannounced.push(template.clone());
+            }
+        }
+        unannounced
+    }
+
+    fn send_v9(&mut self, v9: &v9::V9) -> io::Result<()> {
+        let templates = v9.flowsets.iter().flat_map(|flowset| {
+            let templates = flowset.body.templates.iter().flatten();
+            let options_templates = flowset.body.options_templates.iter().flatten();
+            templates
+                .map(|t| (t.template_id, CachedTemplate::V9(t.clone())))
+                .chain(
+                    options_templates
+                        .map(|t| (t.template_id, CachedTemplate::V9Options(t.clone()))),
+                )
+        });
+        let set_ids = v9.flowsets.iter().map(|flowset| flowset.header.flowset_id);
+        let unannounced = self.unannounced(9, v9.header.source_id, templates, set_ids);
+
+        let header = v9::Header {
+            source_id: self.source_id.unwrap_or(v9.header.source_id),
+            ..v9.header
+        };
+        if !unannounced.is_empty() {
+            let mut templates = vec![];
+            let mut options_templates = vec![];
+            for template in unannounced {
+                match template {
+                    CachedTemplate::V9(template) => templates.push(template),
+                    CachedTemplate::V9Options(template) => options_templates.push(template),
+                    _ => unreachable!("keyed by version"),
+                }
+            }
+            let flowset = |flowset_id, body| v9::FlowSet {
+                header: v9::FlowSetHeader {
+                    flowset_id,
+                    length: 0,
+                },
+                body,
+            };
+            let flowsets = [
+                (!templates.is_empty()).then(|| {
+                    flowset(
+                        V9_TEMPLATE_ID,
+                        v9::FlowSetBody {
+                            templates: Some(templates),
+                            ..Default::default()
+                        },
+                    )
+                }),
+                (!options_templates.is_empty()).then(|| {
+                    flowset(
+                        V9_OPTIONS_TEMPLATE_ID,
+                        v9::FlowSetBody {
+                            options_templates: Some(options_templates),
+                            ..Default::default()
+                        },
+                    )
+                }),
+            ];
+            let announcement = v9::V9 {
+                header,
+                flowsets: flowsets.into_iter().flatten().collect(),
+                received_at: None,
+                digest: None,
+            };
+            let bytes = announcement
+                .to_be_bytes_recalculated()
+                .map_err(unexportable)?;
+            self.send_bytes(&bytes)?;
+        }
+
+        let bytes = v9::V9 {
+            header,
+            ..v9.clone()
+        }
+        .to_be_bytes()
+        .map_err(unexportable)?;
+        self.send_bytes(&bytes)
+    }
+
+    fn send_ipfix(&mut self, ipfix: &ipfix::IPFix) -> io::Result<()> {
+        let templates = ipfix.flowsets.iter().flat_map(|flowset| {
+            let template = flowset.body.templates.iter();
+            let options_template = flowset.body.options_templates.iter();
+            template
+                .map(|t| (t.template_id, CachedTemplate::IPFix(t.clone())))
+                .chain(
+                    options_template
+                        .map(|t| (t.template_id, CachedTemplate::IPFixOptions(t.clone()))),
+                )
+        });
+        let set_ids = ipfix
+            .flowsets
+            .iter()
+            .map(|flowset| flowset.header.header_id);
+        let domain = ipfix.header.observation_domain_id;
+        let unannounced = self.unannounced(10, domain, templates, set_ids);
+
+        let header = ipfix::Header {
+            observation_domain_id: self.observation_domain_id.unwrap_or(domain),
+            ..ipfix.header
+        };
+        if !unannounced.is_empty() {
+            let flowsets = unannounced
+                .into_iter()
+                .map(|template| {
+                    let (header_id, body) = match template {
+                        CachedTemplate::IPFix(template) => (
+                            IPFIX_TEMPLATE_ID,
+                            ipfix::FlowSetBody {
+                                templates: Some(template),
+                                ..Default::default()
+                            },
+                        ),
+                        CachedTemplate::IPFixOptions(template) => (
+                            IPFIX_OPTIONS_TEMPLATE_ID,
+                            ipfix::FlowSetBody {
+                                options_templates: Some(template),
+                                ..Default::default()
+                            },
+                        ),
+                        _ => unreachable!("keyed by version"),
+                    };
+                    ipfix::FlowSet {
+                        header: ipfix::FlowSetHeader {
+                            header_id,
+                            length: 0,
+                        },
+                        body,
+                    }
+                })
+                .collect();
+            let announcement = ipfix::IPFix {
+                header,
+                flowsets,
+                received_at: None,
+                digest: None,
+            };
+            let bytes = announcement
+                .to_be_bytes_recalculated()
+                .map_err(unexportable)?;
+            self.send_bytes(&bytes)?;
+        }
+
+        let bytes = ipfix::IPFix {
+            header,
+            ..ipfix.clone()
+        }
+        .to_be_bytes()
+        .map_err(unexportable)?;
+        self.send_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod replay_tests {
+    use super::Replayer;
+    use crate::archive::{ArchiveReader, ArchiveReplay, ArchiveWriter, ReplaySpeed};
+    use crate::{NetflowPacket, NetflowParser};
+
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    const V9_TEMPLATE: [u8; 36] = [
+        0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0, 2, 0,
+        1, 0, 4, 0, 8, 0, 4,
+    ];
+    const V9_DATA: [u8; 32] = [
+        0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 2, 0, 0, 0, 1, 1, 2, 0, 12, 9, 2, 3, 4, 9,
+        9, 9, 8,
+    ];
+    const IPFIX_MESSAGE: [u8; 64] = [
+        0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0, 4,
+        0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2, 0, 2,
+        0, 1, 2, 3, 4, 5, 6, 7,
+    ];
+
+    fn collector() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket
+    }
+
+    fn receive(collector: &UdpSocket, parser: &mut NetflowParser) -> Vec<NetflowPacket> {
+        let mut buf = [0; 1500];
+        let (length, _) = collector.recv_from(&mut buf).unwrap();
+        parser.parse_bytes(&buf[..length])
+    }
+
+    #[test]
+    fn it_announces_learned_templates_before_data() {
+        let mut recording_parser = NetflowParser::default();
+        recording_parser.parse_bytes(&V9_TEMPLATE);
+        let data = recording_parser.parse_bytes(&V9_DATA);
+        let NetflowPacket::V9(recorded) = &data[0] else {
+            panic!("expected a V9 packet");
+        };
+
+        let collector = collector();
+        let mut replayer = Replayer::new(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            collector.local_addr().unwrap(),
+        )
+        .with_source_id(42)
+        .with_templates_from(&recording_parser);
+        assert_eq!(replayer.learned_template_count(), 1);
+        let mut scoped_parser = NetflowParser::builder()
+            .with_scoped_templates(true)
+            .build()
+            .unwrap();
+        scoped_parser.parse_bytes(&V9_TEMPLATE);
+        let scoped = Replayer::new(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            collector.local_addr().unwrap(),
+        )
+        .with_templates_from(&scoped_parser);
+        assert_eq!(scoped.learned_template_count(), 1);
+        replayer.send(&data[0]).unwrap();
+        replayer.send(&data[0]).unwrap();
+        assert_eq!(replayer.sent(), 3);
+
+        let mut parser = NetflowParser::default();
+        let Some(NetflowPacket::V9(announcement)) = receive(&collector, &mut parser).pop()
+        else {
+            panic!("expected a V9 packet");
+        };
+        assert_eq!(announcement.header.source_id, 42);
+        assert!(announcement.flowsets[0].body.templates.is_some());
+        for _ in 0..2 {
+            let Some(NetflowPacket::V9(v9)) = receive(&collector, &mut parser).pop() else {
+                panic!("expected a V9 packet");
+            };
+            assert_eq!(v9.header.source_id, 42);
+            assert_eq!(v9.flowsets[0].body, recorded.flowsets[0].body);
+        }
+    }
+
+    #[test]
+    fn it_announces_learned_templates_again() {
+        let mut recording_parser = NetflowParser::default();
+        recording_parser.parse_bytes(&V9_TEMPLATE);
+        let data = recording_parser.parse_bytes(&V9_DATA);
+        let replayer = |collector: &UdpSocket| {
+            Replayer::new(
+                UdpSocket::bind("127.0.0.1:0").unwrap(),
+                collector.local_addr().unwrap(),
+            )
+            .with_templates_from(&recording_parser)
+        };
+        let collector = collector();
+
+        // Announced, then again once three messages were sent since
+        let mut by_packets = replayer(&collector).with_template_refresh_packets(3);
+        for _ in 0..3 {
+            by_packets.send(&data[0]).unwrap();
+        }
+        assert_eq!(by_packets.sent(), 5);
+
+        let mut by_interval =
+            replayer(&collector).with_template_refresh_interval(Duration::ZERO);
+        for _ in 0..2 {
+            by_interval.send(&data[0]).unwrap();
+        }
+        assert_eq!(by_interval.sent(), 4);
+    }
+
+    #[test]
+    fn it_replays_archives_with_rewritten_domains() {
+        let mut writer = ArchiveWriter::new(vec![]).unwrap();
+        writer
+            .write_message(Duration::from_secs(1), None, &IPFIX_MESSAGE)
+            .unwrap();
+        let archive = writer.into_inner();
+        let reader = ArchiveReader::new(archive.as_slice()).unwrap();
+        let mut replay = ArchiveReplay::new(reader, NetflowParser::builder())
+            .unwrap()
+            .with_speed(ReplaySpeed::Unpaced);
+
+        let collector = collector();
+        let mut replayer = Replayer::new(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            collector.local_addr().unwrap(),
+        )
+        .with_observation_domain_id(7);
+        assert_eq!(replayer.send_archive(&mut replay).unwrap(), 1);
+        // The message carries its own template
+        assert_eq!(replayer.sent(), 1);
+
+        let packets = receive(&collector, &mut NetflowParser::default());
+        let Some(NetflowPacket::IPFix(ipfix)) = packets.first() else {
+            panic!("expected an IPFix message");
+        };
+        assert_eq!(ipfix.header.observation_domain_id, 7);
+        assert!(ipfix.flowsets[1].body.data.is_some());
+    }
+}
@@ -0,0 +1,323 @@
+//! # Roundtrip
+//!
+//! Checks that packets re-export to the bytes they were parsed from, for validating an
+//! exporter's quirks before relaying its packets.  Each packet is parsed and exported again,
+//! and where the bytes first differ is located down to the flowset, record and field.
+//!
+//! ```rust
+//! use netflow_parser::NetflowParser;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+//!     4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+//!     2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7];
+//! let report = NetflowParser::default().verify_roundtrip(&v5_packet);
+//! assert!(report.is_ok());
+//! assert_eq!(report.packets, 1);
+//! ```
+
+use crate::variable_versions::data_number::FieldValue;
+use crate::{NetflowError, NetflowPacket, NetflowParser};
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+const V5_HEADER_LENGTH: usize = 24;
+const V5_RECORD_LENGTH: usize = 48;
+const V7_HEADER_LENGTH: usize = 24;
+const V7_RECORD_LENGTH: usize = 52;
+const V9_HEADER_LENGTH: usize = 20;
+const IPFIX_HEADER_LENGTH: usize = 16;
+const SET_HEADER_LENGTH: usize = 4;
+
+/// Outcome of [`NetflowParser::verify_roundtrip`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RoundtripReport {
+    /// Packets parsed from the bytes
+    pub packets: usize,
+    /// Where each packet that didn't re-export to its original bytes first diverged
+    pub divergences: Vec<Divergence>,
+    /// Why verification stopped before the end of the bytes, if it did
+    pub error: Option<NetflowError>,
+}
+
+impl RoundtripReport {
+    /// Every packet was parsed and re-exported to its original bytes.
+    pub fn is_ok(&self) -> bool {
+        self.divergences.is_empty() && self.error.is_none()
+    }
+}
+
+/// First byte where a re-exported packet differs from the original.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index of the packet in the bytes verified
+    pub packet: usize,
+    /// Offset of the byte from the start of the packet
+    pub offset: usize,
+    /// Part of the original packet the byte belongs to
+    pub location: RoundtripLocation,
+    /// Original byte, `None` past the end of the original packet
+    pub original: Option<u8>,
+    /// Exported byte, `None` past the end of the exported packet
+    pub exported: Option<u8>,
+}
+
+/// Part of a packet, as laid out in the original bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoundtripLocation {
+    /// The packet header
+    Header,
+    /// A flowset outside of a data record field, such as its header, a template or padding.
+    /// V5 and V7 flow records are flowsets.  `offset` is from the start of the flowset.
+    FlowSet { index: usize, offset: usize },
+    /// A field of a V9 or IPFix data record, `field` being its index in the template
+    Field {
+        flowset: usize,
+        record: usize,
+        field: usize,
+    },
+    /// After the last flowset
+    Trailing,
+}
+
+/// Parses each packet in `bytes` with `parser` and compares its export to the original.
+pub(crate) fn verify(parser: &mut NetflowParser, bytes: &[u8]) -> RoundtripReport {
+    let mut report = RoundtripReport::default();
+    let mut remaining = bytes;
+    while !remaining.is_empty() {
+        let parsed = match parser.parse_packet_for_version(remaining) {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                report.error = Some(error);
+                break;
+            }
+        };
+        let original = &remaining[..remaining.len() - parsed.remaining.len()];
+        let exported = match parsed.result.to_be_bytes() {
+            Ok(exported) => exported,
+            Err(error) => {
+                report.error = Some(error);
+                break;
+            }
+        };
+        if let Some(offset) = first_difference(original, &exported) {
+            report.divergences.push(Divergence {
+                packet: report.packets,
+                offset,
+                location: locate(&parsed.result, offset),
+                original: original.get(offset).copied(),
+                exported: exported.get(offset).copied(),
+            });
+        }
+        report.packets += 1;
+        if original.is_empty() {
+            break;
+        }
+        remaining = &remaining[original.len()..];
+    }
+    report
+}
+
+fn first_difference(original: &[u8], exported: &[u8]) -> Option<usize> {
+    original
+        .iter()
+        .zip(exported)
+        .position(|(original, exported)| original != exported)
+        .or_else(|| {
+            (original.len() != exported.len()).then(|| original.len().min(exported.len()))
+        })
+}
+
+/// Locates `offset` in the original packet, walking the lengths that were parsed.
+fn locate(packet: &NetflowPacket, offset: usize) -> RoundtripLocation {
+    match packet {
+        NetflowPacket::V5(v5) => locate_record(
+            offset,
+            V5_HEADER_LENGTH,
+            V5_RECORD_LENGTH,
+            v5.flowsets.len(),
+        ),
+        NetflowPacket::V7(v7) => locate_record(
+            offset,
+            V7_HEADER_LENGTH,
+            V7_RECORD_LENGTH,
+            v7.flowsets.len(),
+        ),
+        NetflowPacket::V9(v9) => {
+            let sets = v9.flowsets.iter().map(|set| {
+                let records = set
+                    .body
+                    .data
+                    .as_ref()
+                    .map(|data| data.data_fields.as_slice());
+                (usize::from(set.header.length), records)
+            });
+            locate_set(offset, V9_HEADER_LENGTH, sets)
+        }
+        NetflowPacket::IPFix(ipfix) => {
+            let sets = ipfix.flowsets.iter().map(|set| {
+                let records = set
+                    .body
+                    .data
+                    .as_ref()
+                    .map(|data| data.data_fields.as_slice())
+                    .or_else(|| {
+                        set.body
+                            .options_data
+                            .as_ref()
+                            .map(|data| data.data_fields.as_slice())
+                    });
+                (usize::from(set.header.length), records)
+            });
+            locate_set(offset, IPFIX_HEADER_LENGTH, sets)
+        }
+        NetflowPacket::Error(_) => RoundtripLocation::Header,
+    }
+}
+
+fn locate_record(
+    offset: usize,
+    header_length: usize,
+    record_length: usize,
+    records: usize,
+) -> RoundtripLocation {
+    let Some(offset) = offset.checked_sub(header_length) else {
+        return RoundtripLocation::Header;
+    };
+    let index = offset / record_length;
+    if index < records {
+        RoundtripLocation::FlowSet {
+            index,
+            offset: offset % record_length,
+        }
+    } else {
+        RoundtripLocation::Trailing
+    }
+}
+
+fn locate_set<'a, F: 'a>(
+    offset: usize,
+    header_length: usize,
+    sets: impl Iterator<Item = (usize, Option<&'a [BTreeMap<usize, (F, FieldValue)>]>)>,
+) -> RoundtripLocation {
+    if offset < header_length {
+        return RoundtripLocation::Header;
+    }
+    let mut start = header_length;
+    for (index, (length, records)) in sets.enumerate() {
+        if offset < start + length {
+            let field = records
+                .and_then(|records| locate_field(records, start + SET_HEADER_LENGTH, offset));
+            return match field {
+                Some((record, field)) => RoundtripLocation::Field {
+                    flowset: index,
+                    record,
+                    field,
+                },
+                None => RoundtripLocation::FlowSet {
+                    index,
+                    offset: offset - start,
+                },
+            };
+        }
+        start += length;
+    }
+    RoundtripLocation::Trailing
+}
+
+/// Record and field index of the field holding `offset`, records starting at `start`.  Field
+/// lengths are those of their exported values, the same as parsed up to the divergence.
+fn locate_field<F>(
+    records: &[BTreeMap<usize, (F, FieldValue)>],
+    start: usize,
+    offset: usize,
+) -> Option<(usize, usize)> {
+    if offset < start {
+        return None;
+    }
+    let mut position = start;
+    for (record_index, record) in records.iter().enumerate() {
+        for (field_index, (_, value)) in record.iter() {
+            position += value.to_be_bytes().map_or(0, |bytes| bytes.len());
+            if offset < position {
+                return Some((record_index, *field_index));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod roundtrip_tests {
+    use super::{RoundtripLocation, RoundtripReport};
+    use crate::testing::V9_PACKET;
+    use crate::{NetflowError, NetflowParser};
+
+    #[test]
+    fn it_verifies_packets_that_roundtrip() {
+        let ipfix_packet = [
+            0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2,
+            0, 2, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let bytes = [&V9_PACKET[..], &ipfix_packet].concat();
+        let report = NetflowParser::default().verify_roundtrip(&bytes);
+        assert_eq!(
+            report,
+            RoundtripReport {
+                packets: 2,
+                ..Default::default()
+            }
+        );
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn it_reports_where_exports_diverge() {
+        let mut parser = NetflowParser::default();
+        assert!(parser.verify_roundtrip(&V9_PACKET).is_ok());
+
+        // The data flowset is padded to 12 bytes, padding isn't exported
+        let padded = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 2, 0, 0, 0, 1, 1, 2, 0, 16, 9, 2, 3,
+            4, 9, 9, 9, 8, 0, 0, 0, 0,
+        ];
+        let report = parser.verify_roundtrip(&padded);
+        assert!(!report.is_ok());
+        assert_eq!(report.divergences.len(), 1);
+        let divergence = &report.divergences[0];
+        assert_eq!(divergence.offset, 32);
+        assert_eq!(
+            divergence.location,
+            RoundtripLocation::FlowSet {
+                index: 0,
+                offset: 12
+            }
+        );
+        assert_eq!((divergence.original, divergence.exported), (Some(0), None));
+
+        // Last switched is parsed as milliseconds and exported as seconds
+        let last_switched = [
+            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 21, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 0, 0, 3, 232,
+        ];
+        let report = parser.verify_roundtrip(&last_switched);
+        let divergence = &report.divergences[0];
+        assert_eq!(divergence.offset, 46);
+        assert_eq!(
+            divergence.location,
+            RoundtripLocation::Field {
+                flowset: 1,
+                record: 0,
+                field: 1
+            }
+        );
+        assert_eq!(
+            (divergence.original, divergence.exported),
+            (Some(3), Some(0))
+        );
+
+        let report = parser.verify_roundtrip(&[0, 11]);
+        assert_eq!(report.error, Some(NetflowError::UnallowedVersion(11)));
+    }
+}
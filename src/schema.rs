@@ -0,0 +1,101 @@
+//! # Schema
+//!
+//! Serialized packets and `NetflowCommon` output change shape as fields are added or retyped.
+//! Wrapping output in [`Versioned`] records the [`SCHEMA_VERSION`] it was written with, so
+//! long-term flow archives can be read correctly later.  [`schema_changes_since`] lists what
+//! changed after the version an archive was written with.
+//!
+//! ```rust
+//! use netflow_parser::schema::{Versioned, SCHEMA_VERSION};
+//! use netflow_parser::NetflowParser;
+//! use serde_json::json;
+//!
+//! let v5_packet = [0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,];
+//! let packets = NetflowParser::default().parse_bytes(&v5_packet);
+//! let output = json!(Versioned::new(&packets[0]));
+//! assert_eq!(output["schema_version"], SCHEMA_VERSION);
+//! assert_eq!(output["V5"]["header"]["version"], 5);
+//! ```
+
+use serde::Serialize;
+
+/// Version of the serialized output of this crate.  Bumped whenever a serialized field is
+/// added, removed, renamed or changes type, with a note in [`SCHEMA_CHANGES`].
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A change to the serialized output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SchemaChange {
+    /// Schema version the change was made in
+    pub version: u32,
+    /// Crate version the schema version was released in
+    pub crate_version: &'static str,
+    /// What changed and how to read older output
+    pub note: &'static str,
+}
+
+/// Every schema version, oldest first.
+pub const SCHEMA_CHANGES: &[SchemaChange] = &[SchemaChange {
+    version: 1,
+    crate_version: "0.6.0",
+    note: "First versioned schema.",
+}];
+
+/// Changes made after `version`, oldest first.  Empty for the current version.
+pub fn schema_changes_since(version: u32) -> impl Iterator<Item = &'static SchemaChange> {
+    SCHEMA_CHANGES
+        .iter()
+        .filter(move |change| change.version > version)
+}
+
+/// Output tagged with the schema version it was serialized with.  The wrapped value's fields
+/// are serialized alongside `schema_version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Versioned<T> {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub output: T,
+}
+
+impl<T> Versioned<T> {
+    /// Tags `output` with the current [`SCHEMA_VERSION`].
+    pub fn new(output: T) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            output,
+        }
+    }
+}
+
+#[cfg(test)]
+mod schema_tests {
+    use super::{schema_changes_since, Versioned, SCHEMA_CHANGES, SCHEMA_VERSION};
+    use crate::NetflowParser;
+
+    use serde_json::json;
+
+    #[test]
+    fn it_versions_netflow_common_output() {
+        let v5_packet = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let packets = NetflowParser::default().parse_bytes(&v5_packet);
+        let common = packets[0].as_netflow_common().unwrap();
+        let output = json!(Versioned::new(&common));
+        assert_eq!(output["schema_version"], SCHEMA_VERSION);
+        assert_eq!(output["version"], 5);
+        assert_eq!(output["flowsets"][0]["dst_port"], 1029);
+    }
+
+    #[test]
+    fn it_lists_schema_changes() {
+        assert_eq!(
+            SCHEMA_CHANGES.last().map(|change| change.version),
+            Some(SCHEMA_VERSION)
+        );
+        assert_eq!(schema_changes_since(SCHEMA_VERSION).count(), 0);
+        assert_eq!(schema_changes_since(0).count(), SCHEMA_CHANGES.len());
+    }
+}
@@ -0,0 +1,351 @@
+//! # Shared parser
+//!
+//! A [`SharedNetflowParser`] can be cloned into many threads or tasks, such as one per socket,
+//! and parse packets concurrently while sharing the V9/IPFix templates any of them has learned.
+//!
+//! Each call parses with a parser of its own, taken from a pool, so parsing never waits on
+//! another packet.  With scoped templates, packets are routed by their V9 `source_id` or IPFix
+//! `observation_domain_id` to one of several shards, each with its own pool and template cache
+//! behind a `RwLock`.  Templates learned while parsing are published to the cache of the shard,
+//! and its parsers pick them up before their next packet, so a parser only ever holds the
+//! templates of the sources in its shard.  Unscoped templates are shared by every source and
+//! kept in a single shard.
+//!
+//! ```rust
+//! use netflow_parser::shared::SharedNetflowParser;
+//! use netflow_parser::{NetflowPacket, NetflowParser};
+//! use std::thread;
+//!
+//! let template = [
+//!     0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0, 2, 0,
+//!     1, 0, 4, 0, 8, 0, 4,
+//! ];
+//! let data = [
+//!     0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 2, 0, 0, 0, 1, 1, 2, 0, 12, 9, 2, 3, 4, 9,
+//!     9, 9, 8,
+//! ];
+//! let parser = SharedNetflowParser::new(NetflowParser::builder()).expect("valid configuration");
+//! parser.parse_bytes(&template);
+//!
+//! let other = parser.clone();
+//! let packets = thread::spawn(move || other.parse_bytes(&data)).join().unwrap();
+//! assert!(matches!(packets[0], NetflowPacket::V9(_)));
+//! ```
+
+use crate::variable_versions::ipfix::TemplateSnapshot as IPFixTemplateSnapshot;
+use crate::variable_versions::v9::TemplateSnapshot as V9TemplateSnapshot;
+use crate::variable_versions::TemplateStats;
+use crate::{first_error, NetflowError, NetflowPacket, NetflowParser, NetflowParserBuilder};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+/// Shards of a parser with scoped templates.
+const SHARDS: usize = 16;
+
+/// Templates cached by the parsers of a shard.
+#[derive(Debug, Default)]
+struct Templates {
+    v9: V9TemplateSnapshot,
+    ipfix: IPFixTemplateSnapshot,
+}
+
+/// Sources whose packets are parsed by the same pool of parsers, sharing their templates.
+#[derive(Debug, Default)]
+struct Shard {
+    templates: RwLock<Templates>,
+    /// Bumped every time templates are published
+    generation: AtomicU64,
+    /// Idle parsers with the generation they last synced
+    idle: Mutex<Vec<(NetflowParser, u64)>>,
+}
+
+impl Shard {
+    fn idle(&self) -> MutexGuard<'_, Vec<(NetflowParser, u64)>> {
+        self.idle
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Publishes the templates `parser` learned since `before`, a snapshot of its caches taken
+    /// before it parsed.  Templates it learned earlier may have been redefined by another
+    /// parser since, so only the new ones are published.
+    fn publish(&self, parser: &NetflowParser, before: Templates) {
+        let v9 = before
+            .v9
+            .diff(&parser.v9_parser.engine.export_all_templates());
+        let ipfix = before
+            .ipfix
+            .diff(&parser.ipfix_parser.engine.export_all_templates());
+        let mut templates = self
+            .templates
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        templates.v9.extend(v9);
+        templates.ipfix.extend(ipfix);
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Imports the templates of the shard that `parser` doesn't have into it, if any were
+    /// published since `synced`.
+    fn sync(&self, parser: &mut NetflowParser, synced: &mut u64) {
+        let generation = self.generation.load(Ordering::Acquire);
+        if generation == *synced {
+            return;
+        }
+        let templates = self
+            .templates
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let v9 = &mut parser.v9_parser.engine;
+        v9.import_all_templates(v9.uncached_templates(&templates.v9));
+        let ipfix = &mut parser.ipfix_parser.engine;
+        ipfix.import_all_templates(ipfix.uncached_templates(&templates.ipfix));
+        *synced = generation;
+    }
+
+    fn template_count(&self) -> usize {
+        let templates = self
+            .templates
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let v9 = &templates.v9;
+        let ipfix = &templates.ipfix;
+        v9.templates.len()
+            + v9.options_templates.len()
+            + v9.scoped_templates.values().map(|t| t.len()).sum::<usize>()
+            + v9.scoped_options_templates
+                .values()
+                .map(|t| t.len())
+                .sum::<usize>()
+            + ipfix.templates.len()
+            + ipfix.options_templates.len()
+            + ipfix
+                .scoped_templates
+                .values()
+                .map(|t| t.len())
+                .sum::<usize>()
+            + ipfix
+                .scoped_options_templates
+                .values()
+                .map(|t| t.len())
+                .sum::<usize>()
+    }
+}
+
+/// Changes whenever the parser learns a new or redefined template.
+fn template_stats(parser: &NetflowParser) -> (TemplateStats, TemplateStats) {
+    (
+        parser.v9_parser.engine.template_stats,
+        parser.ipfix_parser.engine.template_stats,
+    )
+}
+
+#[derive(Debug)]
+struct Shared {
+    builder: NetflowParserBuilder,
+    /// Whether V9 and IPFix templates are scoped per source
+    scoped: (bool, bool),
+    shards: Vec<Shard>,
+}
+
+impl Shared {
+    /// Shard of the source of the first message in `packet`.  Packets without a source, or
+    /// whose templates aren't scoped, share the first shard.
+    fn shard(&self, packet: &[u8]) -> &Shard {
+        let u32_at =
+            |i: usize| Some(u32::from_be_bytes(packet.get(i..i + 4)?.try_into().ok()?));
+        let source = match packet.get(..2) {
+            Some([0, 9]) if self.scoped.0 => u32_at(16),
+            Some([0, 10]) if self.scoped.1 => u32_at(12),
+            _ => None,
+        };
+        &self.shards[source.unwrap_or_default() as usize % self.shards.len()]
+    }
+}
+
+/// A parser that can be cloned and used from many threads at once, sharing V9/IPFix templates.
+/// Clones share the same template cache.
+///
+/// Options such as template observers, rate limits and metrics apply to each of the pooled
+/// parsers separately.  Templates are shared across every exporter, so exporters reusing
+/// template ids should use `with_scoped_templates` on the builder, which also lets packets of
+/// different sources be parsed by separate shards.
+#[derive(Debug, Clone)]
+pub struct SharedNetflowParser {
+    shared: Arc<Shared>,
+}
+
+impl SharedNetflowParser {
+    /// Creates a shared parser whose pooled parsers are built from `builder`.
+    pub fn new(builder: NetflowParserBuilder) -> Result<Self, NetflowError> {
+        // Surface configuration errors now rather than on the first packet
+        let parser = builder.clone().build()?;
+        let scoped = (
+            parser.v9_parser.engine.scope_templates,
+            parser.ipfix_parser.engine.scope_templates,
+        );
+        let shards = if scoped.0 || scoped.1 { SHARDS } else { 1 };
+        let shards: Vec<Shard> = (0..shards).map(|_| Shard::default()).collect();
+        shards[0].idle().push((parser, 0));
+        Ok(Self {
+            shared: Arc::new(Shared {
+                builder,
+                scoped,
+                shards,
+            }),
+        })
+    }
+
+    /// Takes a Netflow packet slice and returns a vector of Parsed Netflows, like
+    /// `NetflowParser::parse_bytes`.
+    pub fn parse_bytes(&self, packet: &[u8]) -> Vec<NetflowPacket> {
+        let shard = self.shared.shard(packet);
+        let (mut parser, mut synced) = self.take_parser(shard);
+        shard.sync(&mut parser, &mut synced);
+
+        let stats = template_stats(&parser);
+        let before = Templates {
+            v9: parser.v9_parser.engine.export_all_templates(),
+            ipfix: parser.ipfix_parser.engine.export_all_templates(),
+        };
+        let packets = parser.parse_bytes(packet);
+        if template_stats(&parser) != stats {
+            shard.publish(&parser, before);
+        }
+
+        shard.idle().push((parser, synced));
+        packets
+    }
+
+    /// Like `parse_bytes`, returning the first error instead of embedding it as a
+    /// `NetflowPacket::Error`, like `NetflowParser::try_parse_bytes`.
+    pub fn try_parse_bytes(&self, packet: &[u8]) -> Result<Vec<NetflowPacket>, NetflowError> {
+        first_error(self.parse_bytes(packet))
+    }
+
+    /// Templates and options templates shared so far, including scoped ones.
+    pub fn cached_template_count(&self) -> usize {
+        self.shared.shards.iter().map(Shard::template_count).sum()
+    }
+
+    fn take_parser(&self, shard: &Shard) -> (NetflowParser, u64) {
+        if let Some(idle) = shard.idle().pop() {
+            return idle;
+        }
+        let parser = self
+            .shared
+            .builder
+            .clone()
+            .build()
+            .expect("builder was validated in SharedNetflowParser::new");
+        (parser, 0)
+    }
+}
+
+#[cfg(test)]
+mod shared_tests {
+    use super::{SharedNetflowParser, Templates};
+    use crate::{NetflowPacket, NetflowParser};
+
+    use std::thread;
+
+    const TEMPLATE: [u8; 36] = [
+        0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0, 2, 0,
+        1, 0, 4, 0, 8, 0, 4,
+    ];
+    const DATA: [u8; 32] = [
+        0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 2, 0, 0, 0, 1, 1, 2, 0, 12, 9, 2, 3, 4, 9,
+        9, 9, 8,
+    ];
+
+    fn has_data(packets: &[NetflowPacket]) -> bool {
+        matches!(packets, [NetflowPacket::V9(v9)] if v9.flowsets[0].body.data.is_some())
+    }
+
+    #[test]
+    fn it_shares_templates_between_pooled_parsers() {
+        let parser = SharedNetflowParser::new(NetflowParser::builder()).unwrap();
+        // Keep the first parser busy so the template is learned by a second one
+        let shard = &parser.shared.shards[0];
+        let busy = parser.take_parser(shard);
+        parser.parse_bytes(&TEMPLATE);
+        shard.idle().push(busy);
+
+        assert!(has_data(&parser.parse_bytes(&DATA)));
+        assert_eq!(parser.cached_template_count(), 1);
+    }
+
+    #[test]
+    fn it_publishes_only_the_templates_a_parser_learned() {
+        let parser = SharedNetflowParser::new(NetflowParser::builder()).unwrap();
+        parser.parse_bytes(&TEMPLATE);
+        let shard = &parser.shared.shards[0];
+        let (mut stale, mut synced) = parser.take_parser(shard);
+        shard.sync(&mut stale, &mut synced);
+
+        // Template 258 is redefined by another parser while the synced one is parsing a
+        // packet announcing template 259
+        let mut redefined = TEMPLATE;
+        redefined[33] = 12;
+        parser.parse_bytes(&redefined);
+        let mut other_template = TEMPLATE;
+        other_template[25] = 3;
+        let before = Templates {
+            v9: stale.v9_parser.engine.export_all_templates(),
+            ipfix: stale.ipfix_parser.engine.export_all_templates(),
+        };
+        stale.parse_bytes(&other_template);
+        shard.publish(&stale, before);
+
+        let templates = shard.templates.read().unwrap();
+        assert_eq!(templates.v9.templates[&258].fields[1].field_type_number, 12);
+        assert!(templates.v9.templates.contains_key(&259));
+        drop(templates);
+        shard.sync(&mut stale, &mut synced);
+        let cached = &stale.v9_parser.engine.templates;
+        assert_eq!(cached[&258].fields[1].field_type_number, 12);
+    }
+
+    #[test]
+    fn it_parses_from_many_threads() {
+        let parser = SharedNetflowParser::new(NetflowParser::builder()).unwrap();
+        parser.parse_bytes(&TEMPLATE);
+        let results: Vec<bool> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| has_data(&parser.parse_bytes(&DATA))))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(results, vec![true; 4]);
+    }
+
+    #[test]
+    fn it_keeps_templates_of_a_source_in_its_shard() {
+        let parser =
+            SharedNetflowParser::new(NetflowParser::builder().with_scoped_templates(true))
+                .unwrap();
+        // The same template from source ids 1 and 2
+        let mut other_source = TEMPLATE;
+        other_source[19] = 2;
+        parser.parse_bytes(&TEMPLATE);
+        parser.parse_bytes(&other_source);
+        assert_eq!(parser.cached_template_count(), 2);
+
+        let shard = parser.shared.shard(&TEMPLATE);
+        let (mut pooled, mut synced) = parser.take_parser(shard);
+        shard.sync(&mut pooled, &mut synced);
+        let scoped = &pooled.v9_parser.engine.scoped_templates;
+        assert!(scoped.contains_key(&1));
+        assert!(!scoped.contains_key(&2));
+        assert!(has_data(&parser.parse_bytes(&DATA)));
+    }
+
+    #[test]
+    fn it_rejects_invalid_configuration() {
+        assert!(
+            SharedNetflowParser::new(NetflowParser::builder().with_allowed_versions([4]))
+                .is_err()
+        );
+    }
+}
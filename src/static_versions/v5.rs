@@ -3,26 +3,115 @@
 //! References:
 //! - <https://www.cisco.com/en/US/technologies/tk648/tk362/technologies_white_paper09186a00800a3db9.html>
 
+use crate::digest::PacketDigest;
 use crate::protocol::ProtocolTypes;
-use crate::{NetflowPacket, NetflowParseError, ParsedNetflow, PartialParse};
+use crate::uptime::uptime_to_unix_time;
+use crate::{NetflowError, NetflowPacket, ParsedNetflow};
 
 use nom::number::complete::be_u32;
 use nom_derive::*;
 use serde::Serialize;
 use Nom;
 
-use std::net::Ipv4Addr;
+use alloc::{vec, vec::Vec};
+use core::net::Ipv4Addr;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub(crate) fn parse_netflow_v5(packet: &[u8]) -> Result<ParsedNetflow, NetflowParseError> {
+pub(crate) fn parse_netflow_v5(
+    packet: &[u8],
+    parser: &V5Parser,
+) -> Result<ParsedNetflow, NetflowError> {
     V5::parse(packet)
-        .map(|(remaining, v5)| ParsedNetflow::new(remaining, NetflowPacket::V5(v5)))
-        .map_err(|e| {
-            NetflowParseError::Partial(PartialParse {
-                version: 5,
-                error: e.to_string(),
-                remaining: packet.to_vec(),
-            })
+        .map(|(remaining, mut v5)| {
+            parser.timestamp_quirks.normalize(&mut v5);
+            ParsedNetflow::new(remaining, NetflowPacket::V5(v5))
         })
+        .map_err(|e| NetflowError::from_nom(5, packet, e))
+}
+
+/// V5 parsing options.  V5 packets need no state, this only holds compatibility settings for
+/// nonconforming exporters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct V5Parser {
+    /// How the header and flow timestamps are interpreted
+    pub timestamp_quirks: V5TimestampQuirks,
+}
+
+/// Unit a timestamp field is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+impl TimeUnit {
+    fn nanos(self) -> u64 {
+        match self {
+            TimeUnit::Seconds => 1_000_000_000,
+            TimeUnit::Milliseconds => 1_000_000,
+            TimeUnit::Microseconds => 1_000,
+            TimeUnit::Nanoseconds => 1,
+        }
+    }
+
+    /// Converts `value` from this unit to `unit`, saturating at `u32::MAX`.
+    fn convert(self, value: u32, unit: TimeUnit) -> u32 {
+        let nanos = value as u64 * self.nanos();
+        (nanos / unit.nanos()).min(u32::MAX as u64) as u32
+    }
+}
+
+/// Interpretation of V5 timestamps for exporters that don't follow the spec.  Parsed values
+/// are normalized to the standard units, `unix_nsecs` in nanoseconds and `sys_up_time`,
+/// `first` and `last` in milliseconds, so re-exported packets are conforming.  The default
+/// leaves timestamps untouched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct V5TimestampQuirks {
+    /// Unit the exporter writes the residual `unix_nsecs` in
+    pub unix_subsec_unit: TimeUnit,
+    /// Unit the exporter writes `sys_up_time` and the flow `first` / `last` times in
+    pub sys_up_time_unit: TimeUnit,
+    /// The exporter writes `sys_up_time`, `unix_secs` and `unix_nsecs` in little endian
+    pub little_endian_header_times: bool,
+}
+
+impl Default for V5TimestampQuirks {
+    fn default() -> Self {
+        Self {
+            unix_subsec_unit: TimeUnit::Nanoseconds,
+            sys_up_time_unit: TimeUnit::Milliseconds,
+            little_endian_header_times: false,
+        }
+    }
+}
+
+impl V5TimestampQuirks {
+    fn normalize(&self, v5: &mut V5) {
+        if *self == Self::default() {
+            return;
+        }
+        let header = &mut v5.header;
+        if self.little_endian_header_times {
+            header.sys_up_time = header.sys_up_time.swap_bytes();
+            header.unix_secs = header.unix_secs.swap_bytes();
+            header.unix_nsecs = header.unix_nsecs.swap_bytes();
+        }
+        header.unix_nsecs = self
+            .unix_subsec_unit
+            .convert(header.unix_nsecs, TimeUnit::Nanoseconds)
+            // A residual can't reach a whole second
+            .min(999_999_999);
+        let uptime = |value| self.sys_up_time_unit.convert(value, TimeUnit::Milliseconds);
+        header.sys_up_time = uptime(header.sys_up_time);
+        for flowset in v5.flowsets.iter_mut() {
+            flowset.first = uptime(flowset.first);
+            flowset.last = uptime(flowset.last);
+        }
+    }
 }
 
 #[derive(Nom, Debug, Clone, Serialize)]
@@ -32,6 +121,15 @@ pub struct V5 {
     /// V5 Sets
     #[nom(Count = "header.count")]
     pub flowsets: Vec<FlowSet>,
+    /// Wall-clock time the packet was received, since the unix epoch, when known.  Set by
+    /// `NetflowParser::parse_bytes_received_at` or when receive times are captured.
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<Duration>,
+    /// Identity of the message, set when the parser computes packet digests.
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<PacketDigest>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Nom)]
@@ -55,6 +153,51 @@ pub struct Header {
     pub engine_id: u8,
     /// First two bits hold the sampling mode; remaining 14 bits hold value of sampling interval
     pub sampling_interval: u16,
+    /// Sampling mode, from the first two bits of `sampling_interval`
+    #[nom(Value = "SamplingMode::from((sampling_interval >> 14) as u8)")]
+    pub sampling_mode: SamplingMode,
+    /// Sampling interval, one of this many packets, from the last 14 bits of
+    /// `sampling_interval`.  Exported packets are written from `sampling_interval`.
+    #[nom(Value = "sampling_interval & SAMPLING_RATE_MASK")]
+    pub sampling_rate: u16,
+}
+
+const SAMPLING_RATE_MASK: u16 = 0x3fff;
+
+/// How the exporter sampled the packets it built flows from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+pub enum SamplingMode {
+    /// Every packet was accounted, or no sampling mode was configured
+    Unsampled,
+    /// One packet of every `sampling_rate`
+    Deterministic,
+    /// One packet picked at random of every `sampling_rate`
+    Random,
+    /// The reserved mode 3
+    Reserved,
+}
+
+impl From<u8> for SamplingMode {
+    fn from(mode: u8) -> Self {
+        match mode & 0b11 {
+            0 => SamplingMode::Unsampled,
+            1 => SamplingMode::Deterministic,
+            2 => SamplingMode::Random,
+            _ => SamplingMode::Reserved,
+        }
+    }
+}
+
+impl Header {
+    /// Sampling interval flows were sampled at, `None` for unsampled flows.
+    pub fn sampling(&self) -> Option<u16> {
+        match self.sampling_mode {
+            SamplingMode::Deterministic | SamplingMode::Random if self.sampling_rate > 0 => {
+                Some(self.sampling_rate)
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Nom)]
@@ -106,6 +249,188 @@ pub struct FlowSet {
     pub pad2: u16,
 }
 
+impl Default for FlowSet {
+    fn default() -> Self {
+        Self {
+            src_addr: Ipv4Addr::UNSPECIFIED,
+            dst_addr: Ipv4Addr::UNSPECIFIED,
+            next_hop: Ipv4Addr::UNSPECIFIED,
+            input: 0,
+            output: 0,
+            d_pkts: 0,
+            d_octets: 0,
+            first: 0,
+            last: 0,
+            src_port: 0,
+            dst_port: 0,
+            pad1: 0,
+            tcp_flags: 0,
+            protocol_number: 0,
+            protocol_type: ProtocolTypes::from(0),
+            tos: 0,
+            src_as: 0,
+            dst_as: 0,
+            src_mask: 0,
+            dst_mask: 0,
+            pad2: 0,
+        }
+    }
+}
+
+/// Most flows a V5 packet carries.
+pub const MAX_FLOWS: usize = 30;
+
+/// Builds V5 packets, for test fixtures or simulated exporters.  The header count is filled in
+/// from the flows, `sys_up_time` is moved up to the end of the latest flow, and the flow
+/// sequence carries on from one `build` to the next.
+#[derive(Debug, Clone)]
+pub struct V5Builder {
+    header: Header,
+    flowsets: Vec<FlowSet>,
+}
+
+impl Default for V5Builder {
+    fn default() -> Self {
+        Self {
+            header: Header {
+                version: 5,
+                count: 0,
+                sys_up_time: 0,
+                unix_secs: 0,
+                unix_nsecs: 0,
+                flow_sequence: 0,
+                engine_type: 0,
+                engine_id: 0,
+                sampling_interval: 0,
+                sampling_mode: SamplingMode::Unsampled,
+                sampling_rate: 0,
+            },
+            flowsets: Vec::new(),
+        }
+    }
+}
+
+impl V5Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the milliseconds since the exporter booted.
+    pub fn with_sys_up_time(mut self, sys_up_time: u32) -> Self {
+        self.header.sys_up_time = sys_up_time;
+        self
+    }
+
+    /// Sets the export time, since the unix epoch.
+    pub fn with_unix_time(mut self, unix_time: Duration) -> Self {
+        self.header.unix_secs = unix_time.as_secs() as u32;
+        self.header.unix_nsecs = unix_time.subsec_nanos();
+        self
+    }
+
+    /// Sets the sequence number of the first flow built.
+    pub fn with_flow_sequence(mut self, flow_sequence: u32) -> Self {
+        self.header.flow_sequence = flow_sequence;
+        self
+    }
+
+    pub fn with_engine(mut self, engine_type: u8, engine_id: u8) -> Self {
+        self.header.engine_type = engine_type;
+        self.header.engine_id = engine_id;
+        self
+    }
+
+    /// Sets the raw header `sampling_interval`, the sampling mode in the first two bits and
+    /// the interval in the rest.
+    pub fn with_sampling_interval(mut self, sampling_interval: u16) -> Self {
+        self.header.sampling_interval = sampling_interval;
+        self.header.sampling_mode = SamplingMode::from((sampling_interval >> 14) as u8);
+        self.header.sampling_rate = sampling_interval & SAMPLING_RATE_MASK;
+        self
+    }
+
+    /// Samples one of every `rate` packets in `mode`.  Rates above 14 bits are truncated.
+    pub fn with_sampling(self, mode: SamplingMode, rate: u16) -> Self {
+        let mode = match mode {
+            SamplingMode::Unsampled => 0,
+            SamplingMode::Deterministic => 1,
+            SamplingMode::Random => 2,
+            SamplingMode::Reserved => 3,
+        };
+        self.with_sampling_interval(mode << 14 | rate & SAMPLING_RATE_MASK)
+    }
+
+    /// Adds a flow to the next packets built.  Its `protocol_type` is set from
+    /// `protocol_number`.
+    pub fn add_flow(&mut self, mut flow: FlowSet) -> &mut Self {
+        flow.protocol_type = ProtocolTypes::from(flow.protocol_number);
+        self.flowsets.push(flow);
+        self
+    }
+
+    /// Takes the flows added so far as packets of up to `MAX_FLOWS` flows each.
+    pub fn build(&mut self) -> Vec<V5> {
+        let flowsets = core::mem::take(&mut self.flowsets);
+        let latest = flowsets.iter().map(|flow| flow.last).max().unwrap_or(0);
+        self.header.sys_up_time = self.header.sys_up_time.max(latest);
+        flowsets
+            .chunks(MAX_FLOWS)
+            .map(|flowsets| {
+                let header = Header {
+                    count: flowsets.len() as u16,
+                    ..self.header
+                };
+                self.header.flow_sequence = self
+                    .header
+                    .flow_sequence
+                    .wrapping_add(flowsets.len() as u32);
+                V5 {
+                    header,
+                    flowsets: flowsets.to_vec(),
+                    received_at: None,
+                    digest: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Takes the flows added so far as exported packets.
+    pub fn build_bytes(&mut self) -> Vec<Vec<u8>> {
+        self.build().iter().map(V5::to_be_bytes).collect()
+    }
+}
+
+impl Header {
+    /// Export time, since the unix epoch, from `unix_secs` and `unix_nsecs`.
+    pub fn unix_time(&self) -> Duration {
+        Duration::new(self.unix_secs.into(), self.unix_nsecs)
+    }
+
+    /// Unix time of sysuptime reading `uptime`, such as a flow's `first` or `last`, across
+    /// wraps of the uptime counter.  See [`uptime_to_unix_time`].
+    pub fn uptime_to_unix_time(&self, uptime: u32) -> Duration {
+        uptime_to_unix_time(self.unix_time(), self.sys_up_time, uptime)
+    }
+}
+
+#[cfg(feature = "std")]
+impl V5 {
+    /// Export time of the packet.
+    pub fn export_time(&self) -> SystemTime {
+        UNIX_EPOCH + self.header.unix_time()
+    }
+
+    /// Time the first packet of `record` was switched.
+    pub fn flow_start_time(&self, record: &FlowSet) -> SystemTime {
+        UNIX_EPOCH + self.header.uptime_to_unix_time(record.first)
+    }
+
+    /// Time the last packet of `record` was switched.
+    pub fn flow_end_time(&self, record: &FlowSet) -> SystemTime {
+        UNIX_EPOCH + self.header.uptime_to_unix_time(record.last)
+    }
+}
+
 impl V5 {
     /// Convert the V5 struct to a `Vec<u8>` of bytes in big-endian order for exporting
     pub fn to_be_bytes(&self) -> Vec<u8> {
@@ -3,26 +3,26 @@
 //! References:
 //! - <https://www.cisco.com/en/US/technologies/tk648/tk362/technologies_white_paper09186a00800a3db9.html>
 
+use crate::digest::PacketDigest;
 use crate::protocol::ProtocolTypes;
-use crate::{NetflowPacket, NetflowParseError, ParsedNetflow, PartialParse};
+use crate::uptime::uptime_to_unix_time;
+use crate::{NetflowError, NetflowPacket, ParsedNetflow};
 
 use nom::number::complete::be_u32;
 use nom_derive::*;
 use serde::Serialize;
 use Nom;
 
-use std::net::Ipv4Addr;
+use alloc::{vec, vec::Vec};
+use core::net::Ipv4Addr;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub(crate) fn parse_netflow_v7(packet: &[u8]) -> Result<ParsedNetflow, NetflowParseError> {
+pub(crate) fn parse_netflow_v7(packet: &[u8]) -> Result<ParsedNetflow, NetflowError> {
     V7::parse(packet)
         .map(|(remaining, v7)| ParsedNetflow::new(remaining, NetflowPacket::V7(v7)))
-        .map_err(|e| {
-            NetflowParseError::Partial(PartialParse {
-                version: 7,
-                error: e.to_string(),
-                remaining: packet.to_vec(),
-            })
-        })
+        .map_err(|e| NetflowError::from_nom(7, packet, e))
 }
 
 #[derive(Debug, Nom, Clone, Serialize)]
@@ -32,6 +32,15 @@ pub struct V7 {
     /// V7 Sets
     #[nom(Count = "header.count")]
     pub flowsets: Vec<FlowSet>,
+    /// Wall-clock time the packet was received, since the unix epoch, when known.  Set by
+    /// `NetflowParser::parse_bytes_received_at` or when receive times are captured.
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<Duration>,
+    /// Identity of the message, set when the parser computes packet digests.
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<PacketDigest>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Nom, Serialize)]
@@ -106,6 +115,159 @@ pub struct FlowSet {
     pub router_src: Ipv4Addr,
 }
 
+impl Default for FlowSet {
+    fn default() -> Self {
+        Self {
+            src_addr: Ipv4Addr::UNSPECIFIED,
+            dst_addr: Ipv4Addr::UNSPECIFIED,
+            next_hop: Ipv4Addr::UNSPECIFIED,
+            input: 0,
+            output: 0,
+            d_pkts: 0,
+            d_octets: 0,
+            first: 0,
+            last: 0,
+            src_port: 0,
+            dst_port: 0,
+            flags_fields_valid: 0,
+            tcp_flags: 0,
+            protocol_number: 0,
+            protocol_type: ProtocolTypes::from(0),
+            tos: 0,
+            src_as: 0,
+            dst_as: 0,
+            src_mask: 0,
+            dst_mask: 0,
+            flags_fields_invalid: 0,
+            router_src: Ipv4Addr::UNSPECIFIED,
+        }
+    }
+}
+
+/// Most flows a V7 packet carries.
+pub const MAX_FLOWS: usize = 28;
+
+/// Builds V7 packets, for test fixtures or simulated exporters.  The header count is filled in
+/// from the flows, `sys_up_time` is moved up to the end of the latest flow, and the flow
+/// sequence carries on from one `build` to the next.
+#[derive(Debug, Clone)]
+pub struct V7Builder {
+    header: Header,
+    flowsets: Vec<FlowSet>,
+}
+
+impl Default for V7Builder {
+    fn default() -> Self {
+        Self {
+            header: Header {
+                version: 7,
+                count: 0,
+                sys_up_time: 0,
+                unix_secs: 0,
+                unix_nsecs: 0,
+                flow_sequence: 0,
+                reserved: 0,
+            },
+            flowsets: Vec::new(),
+        }
+    }
+}
+
+impl V7Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the milliseconds since the exporter booted.
+    pub fn with_sys_up_time(mut self, sys_up_time: u32) -> Self {
+        self.header.sys_up_time = sys_up_time;
+        self
+    }
+
+    /// Sets the export time, since the unix epoch.
+    pub fn with_unix_time(mut self, unix_time: Duration) -> Self {
+        self.header.unix_secs = unix_time.as_secs() as u32;
+        self.header.unix_nsecs = unix_time.subsec_nanos();
+        self
+    }
+
+    /// Sets the sequence number of the first flow built.
+    pub fn with_flow_sequence(mut self, flow_sequence: u32) -> Self {
+        self.header.flow_sequence = flow_sequence;
+        self
+    }
+
+    /// Adds a flow to the next packets built.  Its `protocol_type` is set from
+    /// `protocol_number`.
+    pub fn add_flow(&mut self, mut flow: FlowSet) -> &mut Self {
+        flow.protocol_type = ProtocolTypes::from(flow.protocol_number);
+        self.flowsets.push(flow);
+        self
+    }
+
+    /// Takes the flows added so far as packets of up to `MAX_FLOWS` flows each.
+    pub fn build(&mut self) -> Vec<V7> {
+        let flowsets = core::mem::take(&mut self.flowsets);
+        let latest = flowsets.iter().map(|flow| flow.last).max().unwrap_or(0);
+        self.header.sys_up_time = self.header.sys_up_time.max(latest);
+        flowsets
+            .chunks(MAX_FLOWS)
+            .map(|flowsets| {
+                let header = Header {
+                    count: flowsets.len() as u16,
+                    ..self.header
+                };
+                self.header.flow_sequence = self
+                    .header
+                    .flow_sequence
+                    .wrapping_add(flowsets.len() as u32);
+                V7 {
+                    header,
+                    flowsets: flowsets.to_vec(),
+                    received_at: None,
+                    digest: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Takes the flows added so far as exported packets.
+    pub fn build_bytes(&mut self) -> Vec<Vec<u8>> {
+        self.build().iter().map(V7::to_be_bytes).collect()
+    }
+}
+
+impl Header {
+    /// Export time, since the unix epoch, from `unix_secs` and `unix_nsecs`.
+    pub fn unix_time(&self) -> Duration {
+        Duration::new(self.unix_secs.into(), self.unix_nsecs)
+    }
+
+    /// Unix time of sysuptime reading `uptime`, such as a flow's `first` or `last`, across
+    /// wraps of the uptime counter.  See [`uptime_to_unix_time`].
+    pub fn uptime_to_unix_time(&self, uptime: u32) -> Duration {
+        uptime_to_unix_time(self.unix_time(), self.sys_up_time, uptime)
+    }
+}
+
+#[cfg(feature = "std")]
+impl V7 {
+    /// Export time of the packet.
+    pub fn export_time(&self) -> SystemTime {
+        UNIX_EPOCH + self.header.unix_time()
+    }
+
+    /// Time the first packet of `record` was switched.
+    pub fn flow_start_time(&self, record: &FlowSet) -> SystemTime {
+        UNIX_EPOCH + self.header.uptime_to_unix_time(record.first)
+    }
+
+    /// Time the last packet of `record` was switched.
+    pub fn flow_end_time(&self, record: &FlowSet) -> SystemTime {
+        UNIX_EPOCH + self.header.uptime_to_unix_time(record.last)
+    }
+}
+
 impl V7 {
     /// Convert the V7 struct to a `Vec<u8>` of bytes in big-endian order for exporting
     pub fn to_be_bytes(&self) -> Vec<u8> {
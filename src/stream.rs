@@ -0,0 +1,355 @@
+//! # Stream
+//!
+//! Async adapters for tokio services.  [`NetflowParserBuilder::stream_packets`] turns a
+//! `tokio::net::UdpSocket` into a `Stream` of packets, parsing each datagram with a parser
+//! built for the address it came from, so templates from different exporters never collide.
+//! [`NetflowCodec`] is a `tokio_util::codec::Decoder` for netflow carried over TCP, files or
+//! anything else `FramedRead` reads from, framing messages as [`MessageFramer`] does.
+//! Requires the `tokio` feature.
+//!
+//! A `PacketStream` drops the parsers of exporters idle for longer than
+//! [`PacketStream::with_exporter_ttl`], and of the least recently seen ones once more than
+//! [`PacketStream::with_max_exporters`] (4096 unless set) have been seen.
+//!
+//! ```rust,no_run
+//! use netflow_parser::NetflowParser;
+//! use tokio::net::UdpSocket;
+//! use tokio_stream::StreamExt;
+//!
+//! # async fn run() -> std::io::Result<()> {
+//! let socket = UdpSocket::bind("0.0.0.0:2055").await?;
+//! let mut packets = NetflowParser::builder()
+//!     .stream_packets(socket)
+//!     .expect("valid configuration");
+//! while let Some(packet) = packets.next().await {
+//!     println!("{packet:?}");
+//! }
+//! if let Some(error) = packets.error() {
+//!     eprintln!("socket failed: {error}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::framer::MessageFramer;
+use crate::variable_versions::SourceTracker;
+use crate::{
+    NetflowError, NetflowPacket, NetflowPacketError, NetflowParser, NetflowParserBuilder,
+};
+
+use bytes::BytesMut;
+use futures_core::Stream;
+use tokio::io::ReadBuf;
+use tokio::net::UdpSocket;
+use tokio_util::codec::Decoder;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// Exporters a stream keeps parsers for unless `with_max_exporters` is set.
+const DEFAULT_MAX_EXPORTERS: usize = 4_096;
+
+impl NetflowParserBuilder {
+    /// Streams the packets of datagrams received on `socket`, with a parser built from this
+    /// builder for each exporter address.  Fails if the configuration is invalid.
+    pub fn stream_packets(self, socket: UdpSocket) -> Result<PacketStream, NetflowError> {
+        // Surface configuration errors now rather than on the first exporter.
+        self.clone().build()?;
+        Ok(PacketStream {
+            socket,
+            builder: self,
+            parsers: HashMap::new(),
+            exporter_ttl: None,
+            max_exporters: DEFAULT_MAX_EXPORTERS,
+            exporters: SourceTracker::new(None, Some(DEFAULT_MAX_EXPORTERS)),
+            buffer: vec![0; 65_535],
+            pending: VecDeque::new(),
+            error: None,
+        })
+    }
+}
+
+/// Packets parsed from the datagrams of a UDP socket, see
+/// [`NetflowParserBuilder::stream_packets`].  The stream ends when receiving fails, with the
+/// error kept in [`PacketStream::error`].
+pub struct PacketStream {
+    socket: UdpSocket,
+    builder: NetflowParserBuilder,
+    parsers: HashMap<IpAddr, NetflowParser>,
+    exporter_ttl: Option<Duration>,
+    max_exporters: usize,
+    /// When each exporter with a parser was last seen
+    exporters: SourceTracker<IpAddr>,
+    buffer: Vec<u8>,
+    /// Packets of the last datagram not yet yielded
+    pending: VecDeque<NetflowPacket>,
+    error: Option<io::Error>,
+}
+
+impl PacketStream {
+    /// Drops the parser and templates of an exporter not seen for longer than `ttl`.  Idle
+    /// exporters are evicted when a new exporter shows up.
+    pub fn with_exporter_ttl(mut self, ttl: Duration) -> Self {
+        self.exporter_ttl = Some(ttl);
+        self.exporters = SourceTracker::new(self.exporter_ttl, Some(self.max_exporters));
+        self
+    }
+
+    /// Keeps parsers for at most `max_exporters` exporters, dropping the least recently seen
+    /// exporter's when a new one shows up.  Defaults to 4096.
+    pub fn with_max_exporters(mut self, max_exporters: usize) -> Self {
+        self.max_exporters = max_exporters;
+        self.exporters = SourceTracker::new(self.exporter_ttl, Some(self.max_exporters));
+        self
+    }
+
+    /// Why the stream ended, if it did.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Parsers by exporter address, holding the templates learned so far.
+    pub fn parsers(&self) -> &HashMap<IpAddr, NetflowParser> {
+        &self.parsers
+    }
+
+    /// The socket packets are received on.
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Exporters whose parsers were dropped for being idle or to make room.
+    pub fn evicted_exporters(&self) -> u64 {
+        self.exporters.evicted()
+    }
+
+    fn parse(&mut self, exporter: IpAddr, length: usize) -> io::Result<()> {
+        let (idle, evicted) = self.exporters.see(exporter);
+        for gone in idle.iter().chain(&evicted) {
+            self.parsers.remove(gone);
+        }
+        let parser = match self.parsers.get_mut(&exporter) {
+            Some(parser) => parser,
+            None => {
+                let parser = self
+                    .builder
+                    .clone()
+                    .build()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                self.parsers.entry(exporter).or_insert(parser)
+            }
+        };
+        self.pending
+            .extend(parser.parse_bytes(&self.buffer[..length]));
+        Ok(())
+    }
+}
+
+impl Stream for PacketStream {
+    type Item = NetflowPacket;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<NetflowPacket>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(packet) = this.pending.pop_front() {
+                return Poll::Ready(Some(packet));
+            }
+            if this.error.is_some() {
+                return Poll::Ready(None);
+            }
+            let mut buf = ReadBuf::new(&mut this.buffer);
+            let received = match this.socket.poll_recv_from(cx, &mut buf) {
+                Poll::Ready(received) => {
+                    received.map(|exporter| (exporter, buf.filled().len()))
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            let result =
+                received.and_then(|(exporter, length)| this.parse(exporter.ip(), length));
+            match result {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => this.error = Some(e),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for PacketStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PacketStream")
+            .field("socket", &self.socket)
+            .field("builder", &self.builder)
+            .field("exporters", &self.parsers.len())
+            .field("exporter_ttl", &self.exporter_ttl)
+            .field("max_exporters", &self.max_exporters)
+            .field("pending", &self.pending.len())
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+/// Decodes netflow messages from a byte stream into packets, for `FramedRead` and
+/// `UdpFramed`.  Framing errors are yielded as `NetflowPacket::Error`.  Every message is
+/// parsed with the same parser, so with `UdpFramed` build it `with_scoped_templates` to keep
+/// the templates of different exporters apart.
+#[derive(Debug)]
+pub struct NetflowCodec {
+    parser: NetflowParser,
+    framer: MessageFramer,
+    pending: VecDeque<NetflowPacket>,
+}
+
+impl NetflowCodec {
+    pub fn new(parser: NetflowParser) -> Self {
+        Self {
+            parser,
+            framer: MessageFramer::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn parser(&self) -> &NetflowParser {
+        &self.parser
+    }
+
+    pub fn into_parser(self) -> NetflowParser {
+        self.parser
+    }
+
+    fn parse(&mut self, message: &[u8]) {
+        self.pending.extend(self.parser.parse_bytes(message));
+    }
+}
+
+impl Default for NetflowCodec {
+    fn default() -> Self {
+        Self::new(NetflowParser::default())
+    }
+}
+
+impl From<NetflowParser> for NetflowCodec {
+    fn from(parser: NetflowParser) -> Self {
+        Self::new(parser)
+    }
+}
+
+impl Decoder for NetflowCodec {
+    type Item = NetflowPacket;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<NetflowPacket>> {
+        if !src.is_empty() {
+            self.framer.push(&src.split());
+        }
+        while self.pending.is_empty() {
+            match self.framer.next_message() {
+                Some(Ok(message)) => self.parse(&message),
+                Some(Err(error)) => {
+                    self.pending
+                        .push_back(NetflowPacket::Error(NetflowPacketError {
+                            error,
+                            remaining: Vec::new(),
+                        }))
+                }
+                None => break,
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    /// Decodes what is left, parsing a last V9 message that only ends with the stream.  For
+    /// `UdpFramed` this is every datagram.
+    fn decode_eof(&mut self, src: &mut BytesMut) -> io::Result<Option<NetflowPacket>> {
+        if let Some(packet) = self.decode(src)? {
+            return Ok(Some(packet));
+        }
+        if let Some(message) = self.framer.finish() {
+            self.parse(&message);
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use super::NetflowCodec;
+    use crate::testing::V9_PACKET;
+    use crate::NetflowParser;
+
+    use bytes::BytesMut;
+    use tokio::net::UdpSocket;
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::{Decoder, FramedRead};
+    use tokio_util::udp::UdpFramed;
+
+    const V5_PACKET: [u8; 72] = [
+        0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4,
+        5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+        4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+    ];
+
+    #[tokio::test]
+    async fn it_streams_packets_per_exporter() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let mut packets = NetflowParser::builder().stream_packets(socket).unwrap();
+
+        let exporter = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        exporter.send_to(&V9_PACKET, address).await.unwrap();
+        exporter.send_to(&V5_PACKET, address).await.unwrap();
+
+        assert!(packets.next().await.unwrap().is_v9());
+        assert!(packets.next().await.unwrap().is_v5());
+        assert_eq!(packets.parsers().len(), 1);
+        assert!(packets.error().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_drops_the_parsers_of_least_recently_seen_exporters() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let mut packets = NetflowParser::builder()
+            .stream_packets(socket)
+            .unwrap()
+            .with_max_exporters(1);
+
+        for exporter in ["127.0.0.2:0", "127.0.0.3:0"] {
+            let exporter = UdpSocket::bind(exporter).await.unwrap();
+            exporter.send_to(&V5_PACKET, address).await.unwrap();
+            assert!(packets.next().await.unwrap().is_v5());
+        }
+        let exporters: Vec<_> = packets.parsers().keys().map(|ip| ip.to_string()).collect();
+        assert_eq!(exporters, vec!["127.0.0.3"]);
+        assert_eq!(packets.evicted_exporters(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_decodes_framed_streams_and_datagrams() {
+        let stream = [&V9_PACKET[..], &V5_PACKET, &V9_PACKET].concat();
+        let packets: Vec<_> = FramedRead::new(&stream[..], NetflowCodec::default())
+            .collect()
+            .await;
+        let packets: Vec<_> = packets.into_iter().map(Result::unwrap).collect();
+        assert_eq!(packets.len(), 3);
+        assert!(packets[0].is_v9() && packets[1].is_v5() && packets[2].is_v9());
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let mut framed = UdpFramed::new(socket, NetflowCodec::default());
+        let exporter = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        exporter.send_to(&V9_PACKET, address).await.unwrap();
+        let (packet, from) = framed.next().await.unwrap().unwrap();
+        assert!(packet.is_v9());
+        assert_eq!(from, exporter.local_addr().unwrap());
+
+        let mut codec = NetflowCodec::default();
+        let mut bytes = BytesMut::from(&[0u8, 11, 0, 0][..]);
+        assert!(codec.decode(&mut bytes).unwrap().unwrap().is_error());
+    }
+}
@@ -0,0 +1,546 @@
+//! # Testing
+//!
+//! Synthesizes valid V5, V9 and IPFix streams, and malformed variants of them, for load
+//! testing and fuzzing collectors without writing packets by hand.  V9 and IPFix streams
+//! announce their template first and re-announce it every `template_interval` packets, like an
+//! exporter would.  The same seed always gives the same packets.
+//!
+//! ```rust
+//! use netflow_parser::testing::{Malformation, PacketSynthesizer};
+//! use netflow_parser::{NetflowPacket, NetflowParser};
+//!
+//! let mut synthesizer = PacketSynthesizer::new(7).with_flows_per_packet(5);
+//! let mut parser = NetflowParser::default();
+//! for packet in synthesizer.ipfix_packets(3) {
+//!     assert!(parser.parse_bytes(&packet).iter().all(|packet| packet.is_ipfix()));
+//! }
+//!
+//! let v5 = synthesizer.v5_packets(1).remove(0);
+//! let truncated = synthesizer.malform(&v5, Malformation::Truncated);
+//! assert!(parser.parse_bytes(&truncated).iter().any(NetflowPacket::is_error));
+//! ```
+
+use crate::static_versions::v5::{FlowSet as V5FlowSet, V5Builder};
+use crate::variable_versions::data_number::{DataNumber, FieldValue};
+use crate::variable_versions::ipfix::{
+    Data as IPFixData, FlowSet as IPFixFlowSet, FlowSetBody as IPFixFlowSetBody,
+    FlowSetHeader as IPFixFlowSetHeader, Header as IPFixHeader, IPFix,
+    Template as IPFixTemplate, TemplateField as IPFixTemplateField,
+};
+use crate::variable_versions::ipfix_lookup::IPFixField;
+use crate::variable_versions::v9::{
+    Data as V9Data, FlowSet as V9FlowSet, FlowSetBody as V9FlowSetBody,
+    FlowSetHeader as V9FlowSetHeader, Header as V9Header, Template as V9Template,
+    TemplateField as V9TemplateField, V9,
+};
+use crate::variable_versions::v9_lookup::V9Field;
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::{vec, vec::Vec};
+use core::net::Ipv4Addr;
+use core::time::Duration;
+
+/// Template id of the synthesized V9 and IPFix data.
+const TEMPLATE_ID: u16 = 256;
+/// Destination ports flows are picked from
+const SERVICE_PORTS: [u16; 6] = [22, 53, 80, 123, 443, 8080];
+
+/// A V9 packet of a template for 258, of `InBytes` and `Ipv4SrcAddr`, and a data flowset of
+/// one record using it, shared by the tests.
+#[cfg(test)]
+pub(crate) const V9_PACKET: [u8; 48] = [
+    0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0, 2, 0, 1,
+    0, 4, 0, 8, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
+];
+
+/// A way to break a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Malformation {
+    /// Cuts the packet short
+    Truncated,
+    /// Declares a count or length larger than the packet
+    BadLength,
+    /// Overwrites random bytes after the version
+    Corrupted,
+}
+
+/// A flow with realistic values, before it is encoded for a version.
+#[derive(Debug, Clone)]
+struct Flow {
+    src_addr: Ipv4Addr,
+    dst_addr: Ipv4Addr,
+    src_port: u16,
+    dst_port: u16,
+    protocol: u8,
+    packets: u32,
+    octets: u32,
+    /// Milliseconds since the exporter booted
+    first: u32,
+    last: u32,
+}
+
+/// Generates packets from a seeded pseudo random number generator.
+#[derive(Debug, Clone)]
+pub struct PacketSynthesizer {
+    state: u64,
+    flows_per_packet: usize,
+    template_interval: usize,
+    source_id: u32,
+    start_time: Duration,
+    /// Milliseconds since the simulated exporter booted
+    sys_up_time: u32,
+    v5: V5Builder,
+    v9_packets_sent: u32,
+    ipfix_records_sent: u32,
+    v9_packets_since_template: Option<usize>,
+    ipfix_packets_since_template: Option<usize>,
+}
+
+impl PacketSynthesizer {
+    /// A synthesizer whose packets are determined by `seed`.
+    pub fn new(seed: u64) -> Self {
+        let start_time = Duration::from_secs(1_700_000_000);
+        Self {
+            state: seed,
+            flows_per_packet: 10,
+            template_interval: 20,
+            source_id: 1,
+            start_time,
+            sys_up_time: 60_000,
+            v5: V5Builder::new(),
+            v9_packets_sent: 0,
+            ipfix_records_sent: 0,
+            v9_packets_since_template: None,
+            ipfix_packets_since_template: None,
+        }
+    }
+
+    /// Sets how many flows each packet carries, 10 by default.  V5 packets carry at most 30.
+    pub fn with_flows_per_packet(mut self, flows_per_packet: usize) -> Self {
+        self.flows_per_packet = flows_per_packet.max(1);
+        self
+    }
+
+    /// Sets how many V9 or IPFix packets are sent between template announcements, 20 by
+    /// default.
+    pub fn with_template_interval(mut self, template_interval: usize) -> Self {
+        self.template_interval = template_interval.max(1);
+        self
+    }
+
+    /// Sets the V9 source id and IPFix observation domain id, 1 by default.
+    pub fn with_source_id(mut self, source_id: u32) -> Self {
+        self.source_id = source_id;
+        self
+    }
+
+    /// Sets the export time of the first packet, since the unix epoch.
+    pub fn with_start_time(mut self, start_time: Duration) -> Self {
+        self.start_time = start_time;
+        self
+    }
+
+    /// Splitmix64
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `low..=high`.
+    fn between(&mut self, low: u32, high: u32) -> u32 {
+        low + (self.next_u64() % (u64::from(high - low) + 1)) as u32
+    }
+
+    /// Advances the exporter clock by about a second and returns the export time.  The clock
+    /// starts a minute after boot.
+    fn tick(&mut self) -> Duration {
+        self.sys_up_time = self.sys_up_time.wrapping_add(self.between(500, 1_500));
+        self.start_time
+            + Duration::from_millis(u64::from(self.sys_up_time.saturating_sub(60_000)))
+    }
+
+    fn flow(&mut self) -> Flow {
+        let protocol = match self.between(0, 9) {
+            0..=5 => 6,
+            6..=8 => 17,
+            _ => 1,
+        };
+        let (src_port, dst_port) = match protocol {
+            1 => (0, 0),
+            _ => {
+                let service = SERVICE_PORTS[self.between(0, 5) as usize];
+                (self.between(49_152, 65_535) as u16, service)
+            }
+        };
+        let packets = self.between(1, 1_000);
+        let octets = packets * self.between(40, 1_500);
+        let duration = self.between(0, 30_000).min(self.sys_up_time);
+        let last = self.sys_up_time - self.between(0, 500).min(self.sys_up_time - duration);
+        Flow {
+            src_addr: Ipv4Addr::new(
+                10,
+                self.between(0, 255) as u8,
+                self.between(0, 255) as u8,
+                self.between(1, 254) as u8,
+            ),
+            dst_addr: Ipv4Addr::new(203, 0, 113, self.between(1, 254) as u8),
+            src_port,
+            dst_port,
+            protocol,
+            packets,
+            octets,
+            first: last - duration,
+            last,
+        }
+    }
+
+    fn flows(&mut self) -> Vec<Flow> {
+        (0..self.flows_per_packet).map(|_| self.flow()).collect()
+    }
+
+    /// Synthesizes `count` V5 packets, each split in several when it has more than 30 flows.
+    pub fn v5_packets(&mut self, count: usize) -> Vec<Vec<u8>> {
+        let mut packets = vec![];
+        for _ in 0..count {
+            let unix_time = self.tick();
+            let flows = self.flows();
+            let mut builder = core::mem::take(&mut self.v5)
+                .with_unix_time(unix_time)
+                .with_sys_up_time(self.sys_up_time);
+            for flow in flows {
+                builder.add_flow(V5FlowSet {
+                    src_addr: flow.src_addr,
+                    dst_addr: flow.dst_addr,
+                    d_pkts: flow.packets,
+                    d_octets: flow.octets,
+                    first: flow.first,
+                    last: flow.last,
+                    src_port: flow.src_port,
+                    dst_port: flow.dst_port,
+                    protocol_number: flow.protocol,
+                    ..Default::default()
+                });
+            }
+            packets.extend(builder.build_bytes());
+            self.v5 = builder;
+        }
+        packets
+    }
+
+    /// Synthesizes `count` V9 packets, announcing the template when it is due.
+    pub fn v9_packets(&mut self, count: usize) -> Vec<Vec<u8>> {
+        let fields = [
+            (V9Field::Ipv4SrcAddr, 4),
+            (V9Field::Ipv4DstAddr, 4),
+            (V9Field::L4SrcPort, 2),
+            (V9Field::L4DstPort, 2),
+            (V9Field::Protocol, 1),
+            (V9Field::InPkts, 4),
+            (V9Field::InBytes, 4),
+            (V9Field::FirstSwitched, 4),
+            (V9Field::LastSwitched, 4),
+        ];
+        let mut packets = vec![];
+        for _ in 0..count {
+            let unix_time = self.tick();
+            let mut flowsets = vec![];
+            if self.template_due(false) {
+                let template = V9Template {
+                    template_id: TEMPLATE_ID,
+                    field_count: fields.len() as u16,
+                    fields: fields
+                        .iter()
+                        .map(|(field, length)| V9TemplateField {
+                            field_type_number: *field as u16,
+                            field_type: *field,
+                            field_length: *length,
+                        })
+                        .collect(),
+                };
+                flowsets.push(V9FlowSet {
+                    header: V9FlowSetHeader {
+                        flowset_id: 0,
+                        length: 0,
+                    },
+                    body: V9FlowSetBody {
+                        templates: Some(vec![Arc::new(template)]),
+                        ..Default::default()
+                    },
+                });
+            }
+            let data_fields = self
+                .flows()
+                .into_iter()
+                .map(|flow| {
+                    let values = [
+                        FieldValue::Ip4Addr(flow.src_addr),
+                        FieldValue::Ip4Addr(flow.dst_addr),
+                        FieldValue::DataNumber(DataNumber::U16(flow.src_port)),
+                        FieldValue::DataNumber(DataNumber::U16(flow.dst_port)),
+                        FieldValue::DataNumber(DataNumber::U8(flow.protocol)),
+                        FieldValue::DataNumber(DataNumber::U32(flow.packets)),
+                        FieldValue::DataNumber(DataNumber::U32(flow.octets)),
+                        FieldValue::DataNumber(DataNumber::U32(flow.first)),
+                        FieldValue::DataNumber(DataNumber::U32(flow.last)),
+                    ];
+                    fields
+                        .iter()
+                        .zip(values)
+                        .enumerate()
+                        .map(|(index, ((field, _), value))| (index, (*field, value)))
+                        .collect::<BTreeMap<_, _>>()
+                })
+                .collect();
+            flowsets.push(V9FlowSet {
+                header: V9FlowSetHeader {
+                    flowset_id: TEMPLATE_ID,
+                    length: 0,
+                },
+                body: V9FlowSetBody {
+                    data: Some(V9Data {
+                        template_id: TEMPLATE_ID,
+                        data_fields,
+                        warnings: vec![],
+                        template: None,
+                    }),
+                    ..Default::default()
+                },
+            });
+            let v9 = V9 {
+                header: V9Header {
+                    version: 9,
+                    count: 0,
+                    sys_up_time: self.sys_up_time,
+                    unix_secs: unix_time.as_secs() as u32,
+                    sequence_number: self.v9_packets_sent,
+                    source_id: self.source_id,
+                },
+                flowsets,
+                received_at: None,
+                digest: None,
+            };
+            self.v9_packets_sent = self.v9_packets_sent.wrapping_add(1);
+            // The lengths and count are filled in on export
+            packets.extend(v9.to_be_bytes_recalculated().ok());
+        }
+        packets
+    }
+
+    /// Synthesizes `count` IPFix messages, announcing the template when it is due.
+    pub fn ipfix_packets(&mut self, count: usize) -> Vec<Vec<u8>> {
+        let fields = [
+            (IPFixField::SourceIpv4address, 4),
+            (IPFixField::DestinationIpv4address, 4),
+            (IPFixField::SourceTransportPort, 2),
+            (IPFixField::DestinationTransportPort, 2),
+            (IPFixField::ProtocolIdentifier, 1),
+            (IPFixField::PacketDeltaCount, 4),
+            (IPFixField::OctetDeltaCount, 4),
+            (IPFixField::FlowStartMilliseconds, 8),
+            (IPFixField::FlowEndMilliseconds, 8),
+        ];
+        let mut packets = vec![];
+        for _ in 0..count {
+            let export_time = self.tick();
+            let boot_time =
+                export_time.saturating_sub(Duration::from_millis(u64::from(self.sys_up_time)));
+            let mut flowsets = vec![];
+            if self.template_due(true) {
+                let template = IPFixTemplate {
+                    template_id: TEMPLATE_ID,
+                    field_count: fields.len() as u16,
+                    fields: fields
+                        .iter()
+                        .map(|(field, length)| IPFixTemplateField {
+                            field_type_number: *field as u16,
+                            field_type: *field,
+                            field_length: *length,
+                            enterprise_number: None,
+                        })
+                        .collect(),
+                };
+                flowsets.push(IPFixFlowSet {
+                    header: IPFixFlowSetHeader {
+                        header_id: 2,
+                        length: 0,
+                    },
+                    body: IPFixFlowSetBody {
+                        templates: Some(Arc::new(template)),
+                        ..Default::default()
+                    },
+                });
+            }
+            let data_fields: Vec<_> = self
+                .flows()
+                .into_iter()
+                .map(|flow| {
+                    let millis = |uptime: u32| {
+                        FieldValue::DateTimeMillis(
+                            boot_time + Duration::from_millis(u64::from(uptime)),
+                        )
+                    };
+                    let values = [
+                        FieldValue::Ip4Addr(flow.src_addr),
+                        FieldValue::Ip4Addr(flow.dst_addr),
+                        FieldValue::DataNumber(DataNumber::U16(flow.src_port)),
+                        FieldValue::DataNumber(DataNumber::U16(flow.dst_port)),
+                        FieldValue::DataNumber(DataNumber::U8(flow.protocol)),
+                        FieldValue::DataNumber(DataNumber::U32(flow.packets)),
+                        FieldValue::DataNumber(DataNumber::U32(flow.octets)),
+                        millis(flow.first),
+                        millis(flow.last),
+                    ];
+                    fields
+                        .iter()
+                        .zip(values)
+                        .enumerate()
+                        .map(|(index, ((field, _), value))| (index, (*field, value)))
+                        .collect::<BTreeMap<_, _>>()
+                })
+                .collect();
+            let records = data_fields.len() as u32;
+            flowsets.push(IPFixFlowSet {
+                header: IPFixFlowSetHeader {
+                    header_id: TEMPLATE_ID,
+                    length: 0,
+                },
+                body: IPFixFlowSetBody {
+                    data: Some(IPFixData {
+                        template_id: TEMPLATE_ID,
+                        data_fields,
+                        warnings: vec![],
+                        template: None,
+                    }),
+                    ..Default::default()
+                },
+            });
+            let ipfix = IPFix {
+                header: IPFixHeader {
+                    version: 10,
+                    length: 0,
+                    export_time: export_time.as_secs() as u32,
+                    sequence_number: self.ipfix_records_sent,
+                    observation_domain_id: self.source_id,
+                },
+                flowsets,
+                received_at: None,
+                digest: None,
+            };
+            self.ipfix_records_sent = self.ipfix_records_sent.wrapping_add(records);
+            // The lengths are filled in on export
+            packets.extend(ipfix.to_be_bytes_recalculated().ok());
+        }
+        packets
+    }
+
+    /// Whether the next V9 or IPFix packet announces the template.
+    fn template_due(&mut self, ipfix: bool) -> bool {
+        let since_template = if ipfix {
+            &mut self.ipfix_packets_since_template
+        } else {
+            &mut self.v9_packets_since_template
+        };
+        let sent = match *since_template {
+            Some(sent) if sent + 1 < self.template_interval => sent + 1,
+            _ => 0,
+        };
+        *since_template = Some(sent);
+        sent == 0
+    }
+
+    /// A copy of `packet` broken in the given way.
+    pub fn malform(&mut self, packet: &[u8], malformation: Malformation) -> Vec<u8> {
+        let mut packet = packet.to_vec();
+        match malformation {
+            Malformation::Truncated => {
+                if packet.len() > 1 {
+                    let length = self.between(1, packet.len() as u32 - 1);
+                    packet.truncate(length as usize);
+                }
+            }
+            Malformation::BadLength => {
+                // The V5, V7 and V9 count and the IPFix length all follow the version
+                if let Some(length) = packet.get_mut(2..4) {
+                    length.copy_from_slice(&u16::MAX.to_be_bytes());
+                }
+            }
+            Malformation::Corrupted if packet.len() > 2 => {
+                for _ in 0..self.between(1, 8) {
+                    let index = self.between(2, packet.len() as u32 - 1) as usize;
+                    packet[index] = self.next_u64() as u8;
+                }
+            }
+            Malformation::Corrupted => {}
+        }
+        packet
+    }
+}
+
+#[cfg(test)]
+mod testing_tests {
+    use super::{Malformation, PacketSynthesizer};
+    use crate::{NetflowPacket, NetflowParser};
+
+    #[test]
+    fn it_synthesizes_parseable_streams() {
+        let mut synthesizer = PacketSynthesizer::new(1)
+            .with_flows_per_packet(40)
+            .with_template_interval(2);
+        let mut parser = NetflowParser::default();
+
+        let v5 = synthesizer.v5_packets(2);
+        // 40 flows don't fit in one V5 packet
+        assert_eq!(v5.len(), 4);
+        let v9 = synthesizer.v9_packets(3);
+        let ipfix = synthesizer.ipfix_packets(3);
+        let mut records = 0;
+        for packet in v5.iter().chain(v9.iter()).chain(ipfix.iter()) {
+            for packet in parser.parse_bytes(packet) {
+                assert!(!packet.is_error(), "{packet:?}");
+                records += packet.as_netflow_common().unwrap().flowsets.len();
+            }
+        }
+        assert_eq!(records, 40 * 8);
+
+        // Only the first and third packets announce the template
+        let announced: Vec<_> = v9
+            .iter()
+            .map(|packet| match parser.parse_bytes(packet).pop() {
+                Some(NetflowPacket::V9(v9)) => v9.has_templates(),
+                other => panic!("expected V9 packet, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(announced, vec![true, false, true]);
+    }
+
+    #[test]
+    fn it_is_deterministic() {
+        let packets = |seed| PacketSynthesizer::new(seed).ipfix_packets(2);
+        assert_eq!(packets(5), packets(5));
+        assert_ne!(packets(5), packets(6));
+    }
+
+    #[test]
+    fn it_malforms_packets() {
+        let mut synthesizer = PacketSynthesizer::new(3);
+        let packet = synthesizer.v5_packets(1).remove(0);
+        let mut parser = NetflowParser::default();
+        for malformation in [Malformation::Truncated, Malformation::BadLength] {
+            let malformed = synthesizer.malform(&packet, malformation);
+            assert!(
+                parser
+                    .parse_bytes(&malformed)
+                    .iter()
+                    .any(NetflowPacket::is_error),
+                "{malformation:?}"
+            );
+        }
+        let corrupted = synthesizer.malform(&packet, Malformation::Corrupted);
+        assert_eq!(corrupted.len(), packet.len());
+        assert_eq!(corrupted[..2], packet[..2]);
+    }
+}
@@ -1,17 +1,48 @@
 #[cfg(test)]
+#[allow(
+    clippy::field_reassign_with_default,
+    clippy::single_component_path_imports
+)]
 mod base_tests {
 
+    use crate::clock::{Clock, ExportTimeClock, ManualClock};
+    use crate::flow_records::FlowRecord;
+    use crate::protocol::ProtocolTypes;
+    use crate::static_versions::v5::{
+        FlowSet as V5FlowSet, SamplingMode, TimeUnit, V5Builder, V5TimestampQuirks,
+    };
+    use crate::static_versions::v7::{FlowSet as V7FlowSet, V7Builder};
+    use crate::testing::PacketSynthesizer;
+    use crate::testing::V9_PACKET;
+    use crate::types::Asn;
+    use crate::variable_versions::data_number::{
+        CustomValue, DataNumber, FieldValue, FieldWarning,
+    };
+    use crate::variable_versions::data_record::DataRecordExt;
+    use crate::variable_versions::enterprise::EnterpriseFieldRegistry;
     use crate::variable_versions::ipfix::{
         Template as IPFixTemplate, TemplateField as IPFixTemplateField,
     };
+    use crate::variable_versions::ipfix_lookup::IPFixField;
     use crate::variable_versions::v9::{
-        Template as V9Template, TemplateField as V9TemplateField,
+        CountSemantics, Header as V9Header, Template as V9Template,
+        TemplateField as V9TemplateField, TemplateSnapshot as V9TemplateSnapshot, V9,
+    };
+    use crate::variable_versions::v9_lookup::V9Field;
+    use crate::variable_versions::{
+        LimitKind, MemoryStats, MissingTemplate, SourceStats, TemplateEvent,
+        TemplateFieldSummary, TemplateIssue, TemplateKind, TemplateObserver,
+    };
+    use crate::{
+        export_all, NetflowError, NetflowPacket, NetflowParser, NetflowParserBuilder, ParseMode,
     };
-    use crate::{NetflowPacket, NetflowParser};
 
     use hex;
     use insta::assert_yaml_snapshot;
     use std::collections::HashSet;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, UNIX_EPOCH};
 
     #[test]
     fn it_parses_unix_timestamp_correctly() {
@@ -22,13 +53,13 @@ mod base_tests {
         let (remain, secs1) =
             be_u32::<&[u8], nom::error::Error<&[u8]>>(packet.as_slice()).unwrap();
         let (remain, nsecs1) = be_u32::<&[u8], nom::error::Error<&[u8]>>(remain).unwrap();
-        assert_eq!(remain, []);
+        assert_eq!(remain, [0u8; 0]);
 
         let time1 = Duration::from_nanos(nsecs1 as u64) + Duration::from_secs(secs1 as u64);
 
         let (remain, secs_nsecs) =
             be_u64::<&[u8], nom::error::Error<&[u8]>>(packet.as_slice()).unwrap();
-        assert_eq!(remain, []);
+        assert_eq!(remain, [0u8; 0]);
         let secs2 = (secs_nsecs >> 32) as u32 as u64;
         let nsecs2 = secs_nsecs as u32;
 
@@ -49,6 +80,44 @@ mod base_tests {
         assert_yaml_snapshot!(NetflowParser::default().parse_bytes(&packet));
     }
 
+    #[test]
+    fn it_normalizes_v5_timestamp_quirks() {
+        let mut packet = vec![0, 5, 0, 1];
+        packet.extend_from_slice(&100u32.to_be_bytes()); // sys_up_time in seconds
+        packet.extend_from_slice(&1_700_000_000u32.to_be_bytes());
+        packet.extend_from_slice(&250u32.to_be_bytes()); // unix_nsecs in milliseconds
+        packet.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0]);
+        let mut flowset = [0u8; 48];
+        flowset[24..28].copy_from_slice(&90u32.to_be_bytes());
+        flowset[28..32].copy_from_slice(&95u32.to_be_bytes());
+        packet.extend_from_slice(&flowset);
+
+        let mut parser = NetflowParser::builder()
+            .with_v5_timestamp_quirks(V5TimestampQuirks {
+                unix_subsec_unit: TimeUnit::Milliseconds,
+                sys_up_time_unit: TimeUnit::Seconds,
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let Some(NetflowPacket::V5(v5)) = parser.parse_bytes(&packet).pop() else {
+            panic!("expected a V5 packet");
+        };
+        assert_eq!(v5.header.unix_secs, 1_700_000_000);
+        assert_eq!(v5.header.unix_nsecs, 250_000_000);
+        assert_eq!(v5.header.sys_up_time, 100_000);
+        assert_eq!(
+            (v5.flowsets[0].first, v5.flowsets[0].last),
+            (90_000, 95_000)
+        );
+
+        let Some(NetflowPacket::V5(v5)) = NetflowParser::default().parse_bytes(&packet).pop()
+        else {
+            panic!("expected a V5 packet");
+        };
+        assert_eq!(v5.header.unix_nsecs, 250);
+    }
+
     #[test]
     fn it_doesnt_allow_v5() {
         let packet = [
@@ -61,6 +130,221 @@ mod base_tests {
         assert_yaml_snapshot!(parser.parse_bytes(&packet));
     }
 
+    #[test]
+    fn it_builds_parser_with_allowed_versions() {
+        let parser = NetflowParser::builder()
+            .with_allowed_versions([9, 10])
+            .build()
+            .unwrap();
+        assert_eq!(parser.allowed_versions, HashSet::from([9, 10]));
+    }
+
+    #[test]
+    fn it_rejects_unsupported_allowed_versions() {
+        let result = NetflowParser::builder()
+            .with_allowed_versions([5, 8])
+            .build();
+        assert!(matches!(result, Err(NetflowError::ConfigError(_))));
+    }
+
+    #[test]
+    fn it_skips_unknown_versions_to_the_next_packet() {
+        let v5_packet = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let junk = [0, 11, 1, 2, 3, 0, 5, 0];
+        let buffer = [&junk[..], &v5_packet, &[9, 9, 9], &v5_packet].concat();
+
+        assert!(NetflowParser::default().parse_bytes(&buffer).is_empty());
+
+        let mut parser = NetflowParser::builder()
+            .with_skip_unknown_versions(true)
+            .build()
+            .unwrap();
+        let packets = parser.parse_bytes(&buffer);
+        assert_eq!(packets.len(), 4);
+        match &packets[0] {
+            NetflowPacket::Error(e) => {
+                assert_eq!(
+                    e.error,
+                    NetflowError::SkippedBytes {
+                        version: 11,
+                        length: 8
+                    }
+                );
+                assert_eq!(e.remaining, junk);
+            }
+            packet => panic!("expected skipped bytes, got {packet:?}"),
+        }
+        assert!(packets[1].is_v5() && packets[3].is_v5());
+        assert!(matches!(
+            &packets[2],
+            NetflowPacket::Error(e) if e.error == NetflowError::SkippedBytes { version: 2313, length: 3 }
+        ));
+        assert_eq!(parser.iter_flow_records(&buffer).count(), 2);
+    }
+
+    #[test]
+    fn it_skips_unallowed_versions_by_their_length() {
+        let v5_packet = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let v9_packet = V9_PACKET;
+        let buffer = [&v9_packet[..], &v5_packet, &v9_packet, &v5_packet].concat();
+
+        let mut parser = NetflowParser::builder()
+            .with_allowed_versions([5])
+            .build()
+            .unwrap();
+        assert!(parser.parse_bytes(&buffer).is_empty());
+
+        let mut parser = NetflowParser::builder()
+            .with_allowed_versions([5])
+            .with_skip_unallowed_versions(true)
+            .build()
+            .unwrap();
+        let packets = parser.parse_bytes(&buffer);
+        assert_eq!(packets.len(), 4);
+        for unallowed in [&packets[0], &packets[2]] {
+            match unallowed {
+                NetflowPacket::Error(e) => {
+                    assert_eq!(e.error, NetflowError::UnallowedVersion(9));
+                    assert_eq!(e.remaining, v9_packet);
+                }
+                packet => panic!("expected an unallowed version, got {packet:?}"),
+            }
+        }
+        assert!(packets[1].is_v5() && packets[3].is_v5());
+        assert_eq!(parser.metrics.skipped_packets, 2);
+        assert_eq!(parser.iter_flow_records(&buffer).count(), 2);
+    }
+
+    #[test]
+    fn it_rejects_messages_over_the_limits() {
+        // A template of three 4 byte fields and a data set of two records
+        let ipfix_packet = [
+            0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2,
+            0, 2, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let v9_packet = V9_PACKET;
+        let error = |builder: NetflowParserBuilder, packet: &[u8]| match builder
+            .build()
+            .unwrap()
+            .parse_bytes(packet)
+            .pop()
+        {
+            Some(NetflowPacket::Error(e)) => Some(e.error),
+            _ => None,
+        };
+        let exceeded =
+            |limit, value, max| Some(NetflowError::LimitExceeded { limit, value, max });
+
+        let generous = NetflowParser::builder()
+            .with_max_field_length(4)
+            .with_max_records_per_set(2)
+            .with_max_sets_per_message(2);
+        assert_eq!(error(generous.clone(), &ipfix_packet), None);
+        assert_eq!(error(generous, &v9_packet), None);
+
+        let short_fields = NetflowParser::builder().with_max_field_length(2);
+        assert_eq!(
+            error(short_fields.clone(), &ipfix_packet),
+            exceeded(LimitKind::FieldLength, 4, 2)
+        );
+        assert_eq!(
+            error(short_fields, &v9_packet),
+            exceeded(LimitKind::FieldLength, 4, 2)
+        );
+        assert_eq!(
+            error(
+                NetflowParser::builder().with_max_records_per_set(1),
+                &ipfix_packet
+            ),
+            exceeded(LimitKind::RecordsPerSet, 2, 1)
+        );
+        assert_eq!(
+            error(
+                NetflowParser::builder().with_max_sets_per_message(1),
+                &v9_packet
+            ),
+            exceeded(LimitKind::SetsPerMessage, 2, 1)
+        );
+    }
+
+    #[test]
+    fn it_rejects_templates_with_too_many_fields() {
+        // A template of three fields, the IPFix one followed by a data set
+        let ipfix_packet = [
+            0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2,
+            0, 2, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        // A template claiming 65535 fields in a 12 byte flowset
+        let v9_packet = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 12, 1, 2, 255,
+            255, 0, 1, 0, 4,
+        ];
+        let exceeded = |value, max| NetflowError::LimitExceeded {
+            limit: LimitKind::FieldCount,
+            value,
+            max,
+        };
+
+        let mut parser = NetflowParser::builder()
+            .with_v9_max_field_count(3)
+            .with_ipfix_max_field_count(3)
+            .with_parse_mode(ParseMode::Lenient)
+            .build()
+            .unwrap();
+        assert!(parser.parse_bytes(&ipfix_packet)[0].is_ipfix());
+        // Skipped like any flowset that fails to parse in lenient mode
+        match &parser.parse_bytes(&v9_packet)[0] {
+            NetflowPacket::V9(v9) => {
+                assert_eq!(v9.flowsets[0].body.parse_error, Some(exceeded(65_535, 3)))
+            }
+            packet => panic!("expected V9, got {packet:?}"),
+        }
+        assert!(parser.v9_parser.engine.templates.is_empty());
+
+        let mut parser = NetflowParser::builder()
+            .with_ipfix_max_field_count(2)
+            .with_parse_mode(ParseMode::Strict)
+            .build()
+            .unwrap();
+        match &parser.parse_bytes(&ipfix_packet)[0] {
+            NetflowPacket::Error(e) => assert_eq!(e.error, exceeded(3, 2)),
+            packet => panic!("expected an error, got {packet:?}"),
+        }
+        assert!(parser.ipfix_parser.engine.templates.is_empty());
+    }
+
+    #[test]
+    fn it_returns_the_first_error_from_try_parse_bytes() {
+        let v5_packet = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let mut parser = NetflowParser::default();
+        let packets = parser.try_parse_bytes(&v5_packet).unwrap();
+        assert!(packets.len() == 1 && packets[0].is_v5());
+
+        // A packet followed by a truncated one
+        let truncated = [&v5_packet[..], &v5_packet[..30]].concat();
+        assert!(matches!(
+            parser.try_parse_bytes(&truncated),
+            Err(NetflowError::Partial(_))
+        ));
+
+        let shared = crate::shared::SharedNetflowParser::new(NetflowParser::builder()).unwrap();
+        assert!(shared.try_parse_bytes(&v5_packet[..30]).is_err());
+    }
+
     #[test]
     fn it_parses_v5_incomplete() {
         let packet = [0, 5, 0, 0, 1, 1, 1, 1];
@@ -129,21 +413,136 @@ mod base_tests {
         }
     }
 
+    #[test]
+    fn it_decodes_v5_sampling() {
+        let mut builder = V5Builder::new().with_sampling(SamplingMode::Random, 100);
+        builder.add_flow(V5FlowSet {
+            d_pkts: 3,
+            d_octets: 300,
+            ..Default::default()
+        });
+        let packet = builder.build_bytes().remove(0);
+        assert_eq!(packet[22..24], [0x80, 100]);
+
+        let mut parser = NetflowParser::default();
+        let Some(NetflowPacket::V5(v5)) = parser.parse_bytes(&packet).pop() else {
+            panic!("expected V5 packet");
+        };
+        assert_eq!(v5.header.sampling_interval, 0x8064);
+        assert_eq!(v5.header.sampling_mode, SamplingMode::Random);
+        assert_eq!(v5.header.sampling_rate, 100);
+        assert_eq!(v5.header.sampling(), Some(100));
+
+        let mut flowset = parser
+            .parse_bytes_as_netflow_common_flowsets(&packet)
+            .remove(0);
+        assert_eq!(flowset.sampling_interval, Some(100));
+        flowset.scale_by_sampling();
+        assert_eq!((flowset.bytes, flowset.packets), (Some(30_000), Some(300)));
+        assert_eq!(flowset.sampling_interval, None);
+
+        // A rate without a sampling mode isn't sampling
+        let mut unsampled = packet.clone();
+        unsampled[22] = 0;
+        let flowset = &parser.parse_bytes_as_netflow_common_flowsets(&unsampled)[0];
+        assert_eq!(flowset.sampling_interval, None);
+    }
+
+    #[test]
+    fn it_times_v5_flows_across_uptime_wraps() {
+        let mut builder = V5Builder::new()
+            .with_unix_time(Duration::new(1_700_000_000, 500_000_000))
+            .with_sys_up_time(1_000);
+        // Started before the uptime counter wrapped
+        builder.add_flow(V5FlowSet {
+            first: u32::MAX - 1_999,
+            last: 500,
+            ..Default::default()
+        });
+        let packet = builder.build_bytes().remove(0);
+        let Some(NetflowPacket::V5(v5)) = NetflowParser::default().parse_bytes(&packet).pop()
+        else {
+            panic!("expected V5 packet");
+        };
+        let export_time = UNIX_EPOCH + Duration::new(1_700_000_000, 500_000_000);
+        assert_eq!(v5.export_time(), export_time);
+        let flow = &v5.flowsets[0];
+        assert_eq!(
+            v5.flow_start_time(flow),
+            export_time - Duration::from_secs(3)
+        );
+        assert_eq!(
+            v5.flow_end_time(flow),
+            export_time - Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn it_builds_v5_packets() {
+        let mut builder = V5Builder::new()
+            .with_unix_time(Duration::new(1_700_000_000, 5))
+            .with_flow_sequence(10);
+        for last in 0..31 {
+            builder.add_flow(V5FlowSet {
+                src_addr: Ipv4Addr::new(192, 0, 2, 1),
+                protocol_number: 6,
+                last,
+                ..Default::default()
+            });
+        }
+        let packets = builder.build_bytes();
+        assert_eq!(packets.len(), 2);
+
+        let mut parser = NetflowParser::default();
+        let parsed: Vec<_> = packets
+            .iter()
+            .flat_map(|packet| parser.parse_bytes(packet))
+            .collect();
+        let Some(NetflowPacket::V5(last_packet)) = parsed.last() else {
+            panic!("expected V5 packet");
+        };
+        assert_eq!(last_packet.header.count, 1);
+        assert_eq!(last_packet.header.flow_sequence, 40);
+        assert_eq!(last_packet.header.sys_up_time, 30);
+        assert_eq!(last_packet.header.unix_nsecs, 5);
+        assert_eq!(last_packet.flowsets[0].protocol_type, ProtocolTypes::Tcp);
+
+        // The sequence carries on into the next packets
+        builder.add_flow(V5FlowSet::default());
+        assert_eq!(builder.build()[0].header.flow_sequence, 41);
+        assert!(builder.build().is_empty());
+    }
+
+    #[test]
+    fn it_builds_v7_packets() {
+        let mut builder = V7Builder::new().with_sys_up_time(100);
+        builder
+            .add_flow(V7FlowSet {
+                dst_addr: Ipv4Addr::new(198, 51, 100, 1),
+                last: 50,
+                ..Default::default()
+            })
+            .add_flow(V7FlowSet::default());
+        let packets = builder.build_bytes();
+        match NetflowParser::default().parse_bytes(&packets[0]).first() {
+            Some(NetflowPacket::V7(v7)) => {
+                assert_eq!(v7.header.count, 2);
+                assert_eq!(v7.header.sys_up_time, 100);
+                assert_eq!(v7.flowsets[0].dst_addr, Ipv4Addr::new(198, 51, 100, 1));
+            }
+            other => panic!("expected V7 packet, got {other:?}"),
+        }
+    }
+
     #[test]
     fn it_parses_v9() {
-        let packet = [
-            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
-            2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
-        ];
+        let packet = V9_PACKET;
         assert_yaml_snapshot!(NetflowParser::default().parse_bytes(&packet));
     }
 
     #[test]
     fn it_doesnt_allow_v9() {
-        let packet = [
-            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
-            2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
-        ];
+        let packet = V9_PACKET;
         let mut parser = NetflowParser::default();
         parser.allowed_versions = HashSet::default();
         assert_yaml_snapshot!(parser.parse_bytes(&packet));
@@ -160,20 +559,659 @@ mod base_tests {
 
     #[test]
     fn it_parses_v9_and_re_exports() {
-        let packet = [
-            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
-            2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
-        ];
+        let packet = V9_PACKET;
         if let NetflowPacket::V9(v9) = NetflowParser::default()
             .parse_bytes(&packet)
             .first()
             .unwrap()
         {
-            assert_yaml_snapshot!(v9.to_be_bytes());
-            assert_eq!(v9.to_be_bytes(), packet);
+            assert_yaml_snapshot!(v9.to_be_bytes().unwrap());
+            assert_eq!(v9.to_be_bytes().unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn it_recalculates_v9_counts_and_lengths_on_export() {
+        let packet = V9_PACKET;
+        let Some(NetflowPacket::V9(mut v9)) =
+            NetflowParser::default().parse_bytes(&packet).pop()
+        else {
+            panic!("expected V9 packet");
+        };
+        assert_eq!(v9.to_be_bytes_recalculated().unwrap(), packet);
+
+        let data = v9.flowsets[1].body.data.as_mut().unwrap();
+        data.data_fields.push(data.data_fields[0].clone());
+        let bytes = v9.to_be_bytes_recalculated().unwrap();
+        assert_eq!(&bytes[2..4], &[0, 3]);
+        assert_eq!(&bytes[38..40], &[0, 20]);
+
+        let Some(NetflowPacket::V9(exported)) =
+            NetflowParser::default().parse_bytes(&bytes).pop()
+        else {
+            panic!("expected V9 packet");
+        };
+        assert_eq!(
+            exported.flowsets[1]
+                .body
+                .data
+                .as_ref()
+                .unwrap()
+                .data_fields
+                .len(),
+            2
+        );
+
+        // Field counts that don't fit in 16 bits fail instead of being truncated
+        let templates = v9.flowsets[0].body.templates.as_mut().unwrap();
+        let mut template = (*templates[0]).clone();
+        template.fields = vec![template.fields[0].clone(); 65536];
+        templates[0] = Arc::new(template);
+        assert!(matches!(
+            v9.to_be_bytes_recalculated(),
+            Err(NetflowError::InvalidLength {
+                declared: 65536,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn it_exports_all_packets_in_order() {
+        let v5_packet = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let v9_packet = V9_PACKET;
+        let mut parser = NetflowParser::default();
+        let mut packets = parser.parse_bytes(&v9_packet);
+        packets.extend(parser.parse_bytes(&[0, 9, 10, 11]));
+        packets.extend(parser.parse_bytes(&v5_packet));
+
+        let export = export_all(&packets);
+        assert_eq!(export.len(), 3);
+        assert_eq!(export[0], Ok(v9_packet.to_vec()));
+        assert!(export[1].is_err());
+        assert_eq!(export[2], Ok(v5_packet.to_vec()));
+    }
+
+    #[test]
+    fn it_scopes_v9_templates_by_source_id() {
+        // Source id 1 defines template 258 as (InBytes, Ipv4SrcAddr) and sends data.
+        let source_1 = V9_PACKET;
+        // Source id 2 reuses template id 258 as (Ipv4SrcAddr, InBytes).
+        let source_2 = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 8, 0, 4, 0, 1, 0, 4,
+        ];
+        // Source id 1 sends data for template 258 again.
+        let source_1_data = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 2, 0, 0, 0, 1, 1, 2, 0, 12, 9, 2, 3,
+            4, 9, 9, 9, 8,
+        ];
+
+        let first_field = |parser: &mut NetflowParser| {
+            parser.parse_bytes(&source_1);
+            parser.parse_bytes(&source_2);
+            match parser.parse_bytes(&source_1_data).first() {
+                Some(NetflowPacket::V9(v9)) => {
+                    v9.flowsets[0].body.data.as_ref().unwrap().data_fields[0][&0].0
+                }
+                other => panic!("expected V9 packet, got {other:?}"),
+            }
+        };
+
+        let mut scoped = NetflowParser::builder()
+            .with_scoped_templates(true)
+            .build()
+            .unwrap();
+        assert_eq!(first_field(&mut scoped), V9Field::InBytes);
+        assert_eq!(scoped.v9_parser.engine.scoped_templates.len(), 2);
+        assert!(scoped.v9_parser.engine.templates.is_empty());
+
+        let mut shared = NetflowParser::default();
+        assert_eq!(first_field(&mut shared), V9Field::Ipv4SrcAddr);
+    }
+
+    #[test]
+    fn it_records_v9_template_redefinitions() {
+        let template = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4,
+        ];
+        let redefined = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 8, 0, 4, 0, 1, 0, 4,
+        ];
+        let mut parser = NetflowParser::builder()
+            .with_template_events(true)
+            .build()
+            .unwrap();
+        parser.parse_bytes(&template);
+        parser.parse_bytes(&template);
+        assert_eq!(
+            parser.v9_parser.engine.drain_template_events(),
+            vec![TemplateEvent::Added {
+                kind: TemplateKind::Template,
+                template_id: 258,
+                scope: None,
+            }]
+        );
+
+        parser.parse_bytes(&redefined);
+        assert_eq!(
+            parser.v9_parser.engine.drain_template_events(),
+            vec![TemplateEvent::Redefined {
+                kind: TemplateKind::Template,
+                template_id: 258,
+                scope: None,
+            }]
+        );
+        assert!(parser.v9_parser.engine.template_events.is_empty());
+    }
+
+    #[test]
+    fn it_shares_refreshed_templates_with_the_cache() {
+        let template = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4,
+        ];
+        let mut parser = NetflowParser::default();
+        let templates = |packets: Vec<NetflowPacket>| match packets.into_iter().next() {
+            Some(NetflowPacket::V9(v9)) => v9.flowsets[0].body.templates.clone().unwrap(),
+            _ => panic!("expected a V9 packet"),
+        };
+        let first = templates(parser.parse_bytes(&template));
+        let refreshed = templates(parser.parse_bytes(&template));
+        let cached = &parser.v9_parser.engine.templates[&258];
+        assert!(Arc::ptr_eq(&first[0], cached));
+        assert!(Arc::ptr_eq(&refreshed[0], cached));
+    }
+
+    #[test]
+    fn it_records_the_template_of_data_flowsets() {
+        let packet = V9_PACKET;
+        let mut parser = NetflowParser::default();
+        let Some(NetflowPacket::V9(v9)) = parser.parse_bytes(&packet).pop() else {
+            panic!("expected a V9 packet");
+        };
+        let data = v9.flowsets[1].body.data.as_ref().unwrap();
+        assert_eq!(data.template_id, 258);
+        let template = data.template.as_ref().unwrap();
+        assert!(Arc::ptr_eq(
+            template,
+            &parser.v9_parser.engine.templates[&258]
+        ));
+        assert_eq!(template.fields.len(), data.data_fields[0].len());
+    }
+
+    #[test]
+    fn it_evicts_least_recently_used_templates_over_the_memory_budget() {
+        let template = |source_id| {
+            [
+                0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, source_id, 0, 0, 0,
+                16, 1, 2, 0, 2, 0, 1, 0, 4, 0, 8, 0, 4,
+            ]
+        };
+        // Sized with the tracking of when the template was last used
+        let mut unbounded = NetflowParser::builder()
+            .with_scoped_templates(true)
+            .with_max_memory(usize::MAX)
+            .build()
+            .unwrap();
+        assert_eq!(unbounded.memory_usage(), MemoryStats::default());
+        unbounded.parse_bytes(&template(1));
+        let usage = unbounded.memory_usage();
+        assert_eq!(usage.templates, 1);
+        assert!(usage.template_bytes > 0);
+        let one = usage.template_bytes;
+
+        // Room for two templates
+        let mut parser = NetflowParser::builder()
+            .with_scoped_templates(true)
+            .with_max_memory(3 * one - 1)
+            .build()
+            .unwrap();
+        parser.parse_bytes(&template(1));
+        parser.parse_bytes(&template(2));
+        // Refreshes the template of source 1, leaving source 2 the least recently used
+        parser.parse_bytes(&template(1));
+        parser.parse_bytes(&template(3));
+
+        let cached = |source_id| {
+            parser
+                .v9_parser
+                .engine
+                .scoped_templates
+                .get(&source_id)
+                .is_some_and(|templates| templates.contains_key(&258))
+        };
+        assert!(cached(1) && !cached(2) && cached(3));
+        assert_eq!(parser.v9_parser.engine.template_stats.evicted, 1);
+        assert_eq!(parser.memory_usage().templates, 2);
+        assert!(parser.memory_usage().template_bytes < 3 * one);
+
+        // Pending events don't evict templates
+        let mut parser = NetflowParser::builder()
+            .with_scoped_templates(true)
+            .with_template_events(true)
+            .with_max_memory(one)
+            .build()
+            .unwrap();
+        parser.parse_bytes(&template(1));
+        assert!(parser.memory_usage().pending_bytes > 0);
+        assert_eq!(parser.memory_usage().templates, 1);
+    }
+
+    #[test]
+    fn it_notifies_template_observer() {
+        #[derive(Debug, Default)]
+        struct Counts(Arc<Mutex<(usize, usize)>>);
+
+        impl TemplateObserver for Counts {
+            fn on_added(&mut self, _: TemplateKind, _: u16, scope: Option<u32>) {
+                assert_eq!(scope, Some(1));
+                self.0.lock().unwrap().0 += 1;
+            }
+            fn on_replaced(&mut self, _: TemplateKind, _: u16, _: Option<u32>) {
+                self.0.lock().unwrap().1 += 1;
+            }
+        }
+
+        let template = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4,
+        ];
+        let redefined = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 8, 0, 4, 0, 1, 0, 4,
+        ];
+        let counts = Arc::new(Mutex::new((0, 0)));
+        let mut parser = NetflowParser::builder()
+            .with_scoped_templates(true)
+            .build()
+            .unwrap();
+        parser.v9_parser.engine.template_observer = Some(Box::new(Counts(counts.clone())));
+        parser.parse_bytes(&template);
+        parser.parse_bytes(&template);
+        parser.parse_bytes(&redefined);
+
+        assert_eq!(*counts.lock().unwrap(), (1, 1));
+        // Events are only kept when recording is enabled
+        assert!(parser.v9_parser.engine.template_events.is_empty());
+    }
+
+    #[test]
+    fn it_alerts_on_data_dropped_for_missing_templates() {
+        #[derive(Debug, Default)]
+        struct Alerts(Arc<Mutex<Vec<MissingTemplate>>>);
+
+        impl TemplateObserver for Alerts {
+            fn on_missing_template(&mut self, missing: &MissingTemplate) {
+                self.0.lock().unwrap().push(*missing);
+            }
+        }
+
+        let data = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 1, 2, 0, 12, 9, 2, 3,
+            4, 9, 9, 9, 8,
+        ];
+        let template = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4,
+        ];
+        let alerts = Arc::new(Mutex::new(vec![]));
+        let mut parser = NetflowParser::builder()
+            .with_missing_template_alert(Duration::ZERO)
+            .build()
+            .unwrap();
+        parser.v9_parser.engine.template_observer = Some(Box::new(Alerts(alerts.clone())));
+
+        parser.parse_bytes(&data);
+        assert!(alerts.lock().unwrap().is_empty());
+        parser.parse_bytes(&data);
+        let alert = alerts.lock().unwrap()[0];
+        assert_eq!(
+            (alert.source, alert.template_id, alert.dropped),
+            (1, 258, 2)
+        );
+
+        let tracker = parser.v9_parser.engine.missing_templates.as_ref().unwrap();
+        assert_eq!(tracker.missing().len(), 1);
+        parser.parse_bytes(&template);
+        let tracker = parser.v9_parser.engine.missing_templates.as_ref().unwrap();
+        assert!(tracker.missing().is_empty());
+    }
+
+    #[test]
+    fn it_rate_limits_v9_template_announcements() {
+        // One template flowset announcing templates 256, 257 and 258.
+        let packet = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 40, 1, 0, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 1, 0, 2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 2, 0, 1, 0,
+            4, 0, 8, 0, 4,
+        ];
+        let mut parser = NetflowParser::builder()
+            .with_template_rate_limit(2, Duration::from_secs(60))
+            .build()
+            .unwrap();
+        parser.parse_bytes(&packet);
+        assert_eq!(parser.v9_parser.engine.templates.len(), 2);
+        assert_eq!(
+            parser
+                .v9_parser
+                .engine
+                .template_rate_limit
+                .as_ref()
+                .unwrap()
+                .rejected(),
+            1
+        );
+
+        // Refreshing known templates is not limited
+        parser.parse_bytes(&packet);
+        assert_eq!(
+            parser
+                .v9_parser
+                .engine
+                .template_rate_limit
+                .as_ref()
+                .unwrap()
+                .rejected(),
+            2
+        );
+        assert_eq!(parser.v9_parser.engine.templates.len(), 2);
+    }
+
+    #[test]
+    fn it_flags_late_v5_flows() {
+        // sys_up_time is 50332672ms, the flow ended at 134807553ms
+        let mut packet = vec![
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let mut parser = NetflowParser::builder()
+            .with_late_flow_threshold(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        let flowsets = parser.parse_bytes_as_netflow_common_flowsets(&packet);
+        assert_eq!(flowsets[0].export_delay, Some(Duration::ZERO));
+        assert_eq!(flowsets[0].late, Some(false));
+
+        // Flow ended 61 seconds before the export
+        packet[4..8].copy_from_slice(&(134807553u32 + 61_000).to_be_bytes());
+        let flowsets = parser.parse_bytes_as_netflow_common_flowsets(&packet);
+        assert_eq!(flowsets[0].export_delay, Some(Duration::from_secs(61)));
+        assert_eq!(flowsets[0].late, Some(true));
+
+        // Without a threshold flows are left unchecked
+        let flowsets = NetflowParser::default().parse_bytes_as_netflow_common_flowsets(&packet);
+        assert_eq!(flowsets[0].late, None);
+    }
+
+    #[test]
+    fn it_measures_processing_delay() {
+        // Exported at 83887623s and 134807553ns
+        let packet = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let exported = Duration::new(83887623, 134807553);
+        let mut parser = NetflowParser::default();
+
+        let parsed = parser.parse_bytes_received_at(&packet, exported + Duration::from_secs(2));
+        assert_eq!(parsed[0].processing_delay(), Some(Duration::from_secs(2)));
+        // Received before it was exported
+        let parsed = parser.parse_bytes_received_at(&packet, Duration::from_secs(1));
+        assert_eq!(parsed[0].processing_delay(), None);
+        assert_eq!(parser.parse_bytes(&packet)[0].received_at(), None);
+
+        let metrics = parser.metrics_snapshot().parser;
+        assert_eq!(metrics.delayed_packets, 1);
+        assert_eq!(metrics.processing_delay_ms, 2000);
+        assert_eq!(metrics.max_processing_delay_ms, 2000);
+
+        let mut parser = NetflowParser::builder()
+            .with_receive_time_capture(true)
+            .build()
+            .unwrap();
+        assert!(parser.parse_bytes(&packet)[0].received_at().is_some());
+    }
+
+    #[test]
+    fn it_measures_template_rate_limit_windows_with_the_given_clock() {
+        let packet = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 40, 1, 0, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 1, 0, 2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 2, 0, 1, 0,
+            4, 0, 8, 0, 4,
+        ];
+        let clock = Arc::new(ManualClock::default());
+        let mut parser = NetflowParser::builder()
+            .with_template_rate_limit(2, Duration::from_secs(60))
+            .with_clock(clock.clone())
+            .build()
+            .unwrap();
+        parser.parse_bytes(&packet);
+        assert_eq!(parser.v9_parser.engine.templates.len(), 2);
+
+        // The window only resets once the clock has moved past it
+        clock.set(Duration::from_secs(60));
+        parser.parse_bytes(&packet);
+        assert_eq!(parser.v9_parser.engine.templates.len(), 3);
+        assert_eq!(
+            parser
+                .v9_parser
+                .engine
+                .template_rate_limit
+                .as_ref()
+                .unwrap()
+                .rejected(),
+            1
+        );
+    }
+
+    #[test]
+    fn it_evicts_idle_and_least_recently_seen_sources() {
+        let template = |source_id| {
+            [
+                0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, source_id, 0, 0, 0,
+                16, 1, 2, 0, 2, 0, 1, 0, 4, 0, 8, 0, 4,
+            ]
+        };
+        let clock = Arc::new(ManualClock::default());
+        let set_time = |secs| clock.set(Duration::from_secs(secs));
+        let mut parser = NetflowParser::builder()
+            .with_scoped_templates(true)
+            .with_template_events(true)
+            .with_source_ttl(Duration::from_secs(60))
+            .with_max_sources(2)
+            .with_clock(clock.clone())
+            .build()
+            .unwrap();
+        parser.parse_bytes(&template(1));
+        parser.parse_bytes(&template(2));
+        set_time(10);
+        parser.parse_bytes(&template(1));
+        set_time(20);
+        parser.parse_bytes(&template(3));
+
+        let mut sources: Vec<_> = parser
+            .v9_parser
+            .engine
+            .scoped_templates
+            .keys()
+            .copied()
+            .collect();
+        sources.sort();
+        assert_eq!(sources, vec![1, 3]);
+        assert!(parser.v9_parser.engine.drain_template_events().contains(
+            &TemplateEvent::Evicted {
+                kind: TemplateKind::Template,
+                template_id: 258,
+                scope: Some(2),
+            }
+        ));
+
+        set_time(75);
+        assert_eq!(parser.evict_idle_sources(), 1);
+        assert_eq!(
+            parser
+                .v9_parser
+                .engine
+                .scoped_templates
+                .keys()
+                .collect::<Vec<_>>(),
+            vec![&3]
+        );
+        assert_eq!(
+            parser.v9_parser.engine.drain_template_events(),
+            vec![TemplateEvent::Expired {
+                kind: TemplateKind::Template,
+                template_id: 258,
+                scope: Some(1),
+            }]
+        );
+        assert_eq!(parser.v9_parser.engine.template_stats.expired, 1);
+        let tracker = parser.v9_parser.engine.source_tracker.as_ref().unwrap();
+        assert_eq!(tracker.evicted(), 2);
+        assert_eq!(tracker.last_seen(3), Some(Duration::from_secs(20)));
+
+        assert!(NetflowParser::builder()
+            .with_max_sources(2)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn it_restores_scoped_v9_templates_from_snapshot() {
+        let template = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 7, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4,
+        ];
+        let data = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 2, 0, 0, 0, 7, 1, 2, 0, 12, 9, 2, 3,
+            4, 9, 9, 9, 8,
+        ];
+        let builder = NetflowParser::builder().with_scoped_templates(true);
+        let mut parser = builder.clone().build().unwrap();
+        parser.parse_bytes(&template);
+
+        let json =
+            serde_json::to_string(&parser.v9_parser.engine.export_all_templates()).unwrap();
+        let snapshot: V9TemplateSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot.scoped_templates[&7].len(), 1);
+
+        let mut restarted = builder.build().unwrap();
+        restarted.v9_parser.engine.import_all_templates(snapshot);
+        match restarted.parse_bytes(&data).first() {
+            Some(NetflowPacket::V9(v9)) => assert!(v9.flowsets[0].body.data.is_some()),
+            other => panic!("expected V9 packet, got {other:?}"),
         }
     }
 
+    #[test]
+    fn it_re_announces_cached_v9_templates() {
+        let template = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4,
+        ];
+        let options_template = [
+            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 1, 0, 22, 1, 19, 0,
+            4, 0, 8, 0, 2, 0, 2, 0, 34, 0, 2, 0, 36, 0, 1, 1, 19, 0, 9, 0, 2, 0, 100, 1,
+        ];
+        let mut parser = NetflowParser::default();
+        parser.parse_bytes(&template);
+        parser.parse_bytes(&options_template);
+
+        let flowsets = parser.v9_parser.templates_to_flowset(1).unwrap();
+        let announcement = V9 {
+            header: V9Header {
+                version: 9,
+                count: 2,
+                sys_up_time: 0,
+                unix_secs: 0,
+                sequence_number: 0,
+                source_id: 1,
+            },
+            flowsets,
+            received_at: None,
+            digest: None,
+        };
+        let mut downstream = NetflowParser::default();
+        downstream.parse_bytes(&announcement.to_be_bytes().unwrap());
+        assert_eq!(
+            downstream.v9_parser.engine.export_all_templates(),
+            parser.v9_parser.engine.export_all_templates()
+        );
+
+        let scoped = NetflowParser::builder()
+            .with_scoped_templates(true)
+            .build()
+            .unwrap();
+        assert!(scoped.v9_parser.templates_to_flowset(1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_diffs_v9_template_snapshots() {
+        let template = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4,
+        ];
+        // Template 258 redefined as (Ipv4SrcAddr, InBytes)
+        let redefined = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 8, 0, 4, 0, 1, 0, 4,
+        ];
+        let mut parser = NetflowParser::default();
+        let before = parser.v9_parser.engine.export_all_templates();
+        parser.parse_bytes(&template);
+        let added = parser.v9_parser.engine.export_all_templates();
+        assert_eq!(before.diff(&added).templates.len(), 1);
+        assert!(added.diff(&added).is_empty());
+        // Templates that went away aren't reported
+        assert!(added.diff(&before).is_empty());
+
+        parser.parse_bytes(&redefined);
+        let changed = added.diff(&parser.v9_parser.engine.export_all_templates());
+        assert_eq!(
+            changed.templates[&258].fields[0].field_type,
+            V9Field::Ipv4SrcAddr
+        );
+    }
+
+    #[test]
+    fn it_keeps_undecodable_v9_field_as_unknown() {
+        // Template 258 declares Ipv4SrcAddr with a length of 3 followed by InBytes.
+        let packet = [
+            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 8, 0, 3, 0, 1, 0, 4, 1, 2, 0, 11, 1, 2, 3, 0, 0, 0, 5,
+        ];
+        let parsed = NetflowParser::default().parse_bytes(&packet);
+        let Some(NetflowPacket::V9(v9)) = parsed.first() else {
+            panic!("expected V9 packet, got {parsed:?}");
+        };
+        let data = v9.flowsets[1].body.data.as_ref().unwrap();
+        assert_eq!(
+            data.data_fields[0][&0],
+            (V9Field::Ipv4SrcAddr, FieldValue::Unknown(vec![1, 2, 3]))
+        );
+        assert_eq!(
+            data.data_fields[0][&1],
+            (V9Field::InBytes, FieldValue::DataNumber(DataNumber::U32(5)))
+        );
+        assert_eq!(
+            data.warnings,
+            vec![FieldWarning {
+                record: 0,
+                field: 0
+            }]
+        );
+        assert_eq!(v9.to_be_bytes().unwrap(), packet);
+    }
+
     #[test]
     fn it_parses_v9_no_data() {
         let packet = [
@@ -193,10 +1231,7 @@ mod base_tests {
     #[test]
     #[cfg(feature = "parse_unknown_fields")]
     fn it_parses_multiple_packets() {
-        let v9_packet = [
-            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
-            2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
-        ];
+        let v9_packet = V9_PACKET;
         let v7_packet = [
             0, 7, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
             4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
@@ -241,6 +1276,47 @@ mod base_tests {
         assert_yaml_snapshot!(NetflowParser::default().parse_bytes(&packet));
     }
 
+    #[test]
+    fn it_parses_v9_options_template_with_unaligned_scope_length() {
+        // Scope length 6 covers one scope field and 2 bytes of padding
+        let packet = [
+            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 1, 0, 24, 1, 19, 0,
+            6, 0, 8, 0, 2, 0, 2, 0, 0, 0, 34, 0, 2, 0, 36, 0, 1, 1, 19, 0, 9, 0, 2, 0, 100, 1,
+        ];
+        let mut parsed = NetflowParser::default().parse_bytes(&packet);
+        let Some(NetflowPacket::V9(v9)) = parsed.pop() else {
+            panic!("expected a V9 packet");
+        };
+        let template = &v9.flowsets[0].body.options_templates.as_ref().unwrap()[0];
+        assert_eq!(template.scope_fields.len(), 1);
+        assert_eq!(template.option_fields.len(), 2);
+        let options_data = v9.flowsets[1].body.options_data.as_ref().unwrap();
+        assert_eq!(options_data.options_fields.len(), 2);
+        assert_eq!(v9.to_be_bytes().unwrap(), packet);
+    }
+
+    #[test]
+    fn it_parses_v9_flowsets_without_alignment_padding() {
+        // Data flowsets of 15 and 9 bytes, the first with 1 byte of padding, followed by 2
+        // stray padding bytes at the end of the packet
+        let packet = [
+            0, 9, 0, 4, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 4, 0, 1, 1, 2, 0, 15, 0, 0, 0, 10, 6, 0, 0, 0, 20, 17, 0, 1, 2,
+            0, 9, 0, 0, 0, 30, 1, 0, 0,
+        ];
+        let parsed = NetflowParser::default().parse_bytes(&packet);
+        let [NetflowPacket::V9(v9)] = parsed.as_slice() else {
+            panic!("expected a single V9 packet, got {parsed:?}");
+        };
+        let records: Vec<usize> = v9
+            .flowsets
+            .iter()
+            .filter_map(|flowset| flowset.body.data.as_ref())
+            .map(|data| data.data_fields.len())
+            .collect();
+        assert_eq!(records, vec![2, 1]);
+    }
+
     #[test]
     fn it_parses_v9_data_cached_template() {
         let packet = [
@@ -265,7 +1341,11 @@ mod base_tests {
             fields,
         };
         let mut parser = NetflowParser::default();
-        parser.v9_parser.templates.insert(258, template);
+        parser
+            .v9_parser
+            .engine
+            .templates
+            .insert(258, Arc::new(template));
         assert_yaml_snapshot!(parser.parse_bytes(&packet));
     }
 
@@ -279,6 +1359,43 @@ mod base_tests {
         assert_yaml_snapshot!(NetflowParser::default().parse_bytes(&packet));
     }
 
+    #[test]
+    fn it_reads_typed_fields_from_ipfix_records() {
+        let packet = [
+            0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2,
+            0, 2, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let mut parser = NetflowParser::default();
+        let Some(NetflowPacket::IPFix(ipfix)) = parser.parse_bytes(&packet).pop() else {
+            panic!("expected an IPFix packet");
+        };
+        let data = ipfix.flowsets[1].body.data.as_ref().unwrap();
+        let record = &data.data_fields[0];
+        assert_eq!(
+            record.get_ip(IPFixField::SourceIpv4address),
+            Some(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)))
+        );
+        assert_eq!(record.get_u64(IPFixField::PacketDeltaCount), Some(16909058));
+        assert_eq!(record.get_u16(IPFixField::PacketDeltaCount), None);
+        assert_eq!(record.get_ip(IPFixField::PacketDeltaCount), None);
+        assert_eq!(record.get_u64(IPFixField::OctetDeltaCount), None);
+        assert_eq!(
+            record.get_asn(IPFixField::PacketDeltaCount),
+            Some(Asn(16909058))
+        );
+        assert_eq!(record.get_port(IPFixField::PacketDeltaCount), None);
+
+        let records: Vec<FlowRecord> = parser.iter_flow_records(&packet).collect();
+        let FlowRecord::IPFix(fields) = &records[0] else {
+            panic!("expected an IPFix record");
+        };
+        assert_eq!(
+            fields.get_ip(IPFixField::DestinationIpv4address),
+            Some(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 3)))
+        );
+    }
+
     #[test]
     fn it_doesnt_allow_ipfix() {
         let packet = [
@@ -303,11 +1420,52 @@ mod base_tests {
             .first()
             .unwrap()
         {
-            assert_yaml_snapshot!(ipfix.to_be_bytes());
-            assert_eq!(ipfix.to_be_bytes(), packet);
+            assert_yaml_snapshot!(ipfix.to_be_bytes().unwrap());
+            assert_eq!(ipfix.to_be_bytes().unwrap(), packet);
         }
     }
 
+    #[test]
+    fn it_recalculates_ipfix_lengths_on_export() {
+        let packet = [
+            0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2,
+            0, 2, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let Some(NetflowPacket::IPFix(mut ipfix)) =
+            NetflowParser::default().parse_bytes(&packet).pop()
+        else {
+            panic!("expected IPFix packet");
+        };
+        assert_eq!(ipfix.to_be_bytes_recalculated().unwrap(), packet);
+
+        ipfix.flowsets[1]
+            .body
+            .data
+            .as_mut()
+            .unwrap()
+            .data_fields
+            .pop();
+        let bytes = ipfix.to_be_bytes_recalculated().unwrap();
+        assert_eq!(bytes.len(), 52);
+        assert_eq!(&bytes[2..4], &[0, 52]);
+        assert_eq!(&bytes[38..40], &[0, 16]);
+        // Still declares the original lengths
+        assert_eq!(ipfix.to_be_bytes().unwrap()[2..4], [0, 64]);
+
+        let template = ipfix.flowsets[0].body.templates.as_mut().unwrap();
+        let mut fields = template.fields.clone();
+        fields.resize(65536, fields[0].clone());
+        Arc::make_mut(template).fields = fields;
+        assert!(matches!(
+            ipfix.to_be_bytes_recalculated(),
+            Err(NetflowError::InvalidLength {
+                declared: 65536,
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn it_doesnt_parse_0_length_fields_ipfix() {
         let packet = [
@@ -317,6 +1475,348 @@ mod base_tests {
         assert_yaml_snapshot!(NetflowParser::default().parse_bytes(&packet));
     }
 
+    #[test]
+    fn it_recovers_from_0_length_fields_ipfix_in_lenient_mode() {
+        let packet = [
+            0, 10, 0, 48, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 65, 0, 0, 1, 0, 0, 12, 1, 2, 3, 4, 1, 2, 3, 4,
+        ];
+        let mut parser = NetflowParser::builder()
+            .with_parse_mode(ParseMode::Lenient)
+            .build()
+            .unwrap();
+        match parser.parse_bytes(&packet).first() {
+            Some(NetflowPacket::IPFix(ipfix)) => {
+                assert_eq!(ipfix.flowsets.len(), 2);
+                assert!(ipfix.flowsets[0].body.parse_error.is_none());
+                assert!(matches!(
+                    ipfix.flowsets[1].body.parse_error,
+                    Some(NetflowError::Partial(_))
+                ));
+                // The skipped set is written back as it was received
+                assert_eq!(ipfix.to_be_bytes().unwrap(), packet);
+            }
+            other => panic!("expected IPFix packet, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn it_continues_after_bad_flowset_v9_in_lenient_mode() {
+        // Template 258 with a 0 length field, a data flowset that can't be parsed with it,
+        // followed by a V5 packet.
+        let mut packets = vec![
+            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 0, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
+        ];
+        packets.extend_from_slice(&[
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ]);
+        let mut parser = NetflowParser::builder()
+            .with_parse_mode(ParseMode::Lenient)
+            .build()
+            .unwrap();
+        assert_yaml_snapshot!(parser.parse_bytes(&packets));
+    }
+
+    #[test]
+    fn it_reports_missing_template_in_strict_mode() {
+        let packet = [
+            0, 9, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 1, 2, 0, 8, 0, 0, 0, 1,
+        ];
+        let mut parser = NetflowParser::builder()
+            .with_parse_mode(ParseMode::Strict)
+            .build()
+            .unwrap();
+        match parser.parse_bytes(&packet).first() {
+            Some(NetflowPacket::Error(e)) => assert_eq!(
+                e.error,
+                NetflowError::MissingTemplate {
+                    version: 9,
+                    template_id: 258
+                }
+            ),
+            other => panic!("expected error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn it_reports_ipfix_set_length_mismatch() {
+        // The data set declares 40 bytes but only 28 are left in the message
+        let packet = [
+            0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 40, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2,
+            0, 2, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let mismatch = NetflowError::SetLengthMismatch {
+            set_id: 256,
+            declared: 40,
+            available: 28,
+        };
+
+        let mut parser = NetflowParser::builder()
+            .with_parse_mode(ParseMode::Lenient)
+            .build()
+            .unwrap();
+        let Some(NetflowPacket::IPFix(ipfix)) = parser.parse_bytes(&packet).pop() else {
+            panic!("expected an IPFix packet");
+        };
+        assert_eq!(ipfix.flowsets[1].body.parse_error, Some(mismatch.clone()));
+        assert_eq!(
+            ipfix.flowsets[1]
+                .body
+                .data
+                .as_ref()
+                .unwrap()
+                .data_fields
+                .len(),
+            2
+        );
+
+        let mut parser = NetflowParser::builder()
+            .with_parse_mode(ParseMode::Strict)
+            .build()
+            .unwrap();
+        match parser.parse_bytes(&packet).pop() {
+            Some(NetflowPacket::Error(e)) => assert_eq!(e.error, mismatch),
+            other => panic!("expected an error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn it_parses_v9_flowsets_overrunning_the_packet() {
+        // The data flowset declares 40 bytes but only 20 are left in the packet
+        let packet = [
+            0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 40, 9, 2, 3, 4, 9, 9, 9, 8, 1, 1, 1, 1, 2, 2,
+            2, 2,
+        ];
+        let mismatch = NetflowError::SetLengthMismatch {
+            set_id: 258,
+            declared: 40,
+            available: 20,
+        };
+
+        let mut parser = NetflowParser::builder()
+            .with_parse_mode(ParseMode::Lenient)
+            .build()
+            .unwrap();
+        let Some(NetflowPacket::V9(v9)) = parser.parse_bytes(&packet).pop() else {
+            panic!("expected a V9 packet");
+        };
+        assert_eq!(v9.flowsets[1].body.parse_error, Some(mismatch.clone()));
+        assert_eq!(
+            v9.flowsets[1].body.data.as_ref().unwrap().data_fields.len(),
+            2
+        );
+
+        let mut parser = NetflowParser::builder()
+            .with_parse_mode(ParseMode::Strict)
+            .build()
+            .unwrap();
+        match parser.parse_bytes(&packet).pop() {
+            Some(NetflowPacket::Error(e)) => assert_eq!(e.error, mismatch),
+            other => panic!("expected an error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn it_keeps_v9_flowsets_with_reserved_ids() {
+        // A flowset with reserved id 128, then a template and a data flowset using it
+        let packet = [
+            0, 9, 0, 3, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 128, 0, 8, 1, 2, 3,
+            4, 0, 0, 0, 16, 1, 2, 0, 2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9,
+            9, 8,
+        ];
+        for parser in [
+            NetflowParser::builder().with_parse_mode(ParseMode::Strict),
+            NetflowParser::builder().with_v9_count_semantics(CountSemantics::Auto),
+        ] {
+            let Some(NetflowPacket::V9(v9)) =
+                parser.build().unwrap().parse_bytes(&packet).pop()
+            else {
+                panic!("expected a V9 packet");
+            };
+            assert_eq!(v9.flowsets.len(), 3);
+            assert_eq!(
+                v9.flowsets[0].unsupported_data(),
+                Some(&[0, 128, 0, 8, 1, 2, 3, 4][..])
+            );
+            assert_eq!(v9.flowsets[1].unsupported_data(), None);
+            assert!(v9.flowsets[2].body.data.is_some());
+        }
+    }
+
+    #[test]
+    fn it_skips_ipfix_sets_shorter_than_their_header() {
+        let packet = [
+            0, 10, 0, 44, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 2, 1, 2, 3, 4,
+        ];
+        let mut parser = NetflowParser::builder()
+            .with_parse_mode(ParseMode::Lenient)
+            .build()
+            .unwrap();
+        let Some(NetflowPacket::IPFix(ipfix)) = parser.parse_bytes(&packet).pop() else {
+            panic!("expected an IPFix packet");
+        };
+        assert_eq!(ipfix.flowsets.len(), 2);
+        assert_eq!(
+            ipfix.flowsets[1].body.parse_error,
+            Some(NetflowError::SetLengthMismatch {
+                set_id: 256,
+                declared: 2,
+                available: 8,
+            })
+        );
+    }
+
+    #[test]
+    fn it_decodes_ipfix_enterprise_fields_with_registered_decoders() {
+        // Template 256 with sourceIPv4Address and field 1 of enterprise 9, then a data set
+        let packet = [
+            0, 10, 0, 48, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 2, 0, 8, 0,
+            4, 128, 1, 0, 4, 0, 0, 0, 9, 1, 0, 0, 12, 1, 2, 3, 4, 0, 0, 0, 7,
+        ];
+        let enterprise_value = |parser: &mut NetflowParser| {
+            let Some(NetflowPacket::IPFix(ipfix)) = parser.parse_bytes(&packet).pop() else {
+                panic!("expected an IPFix packet");
+            };
+            let template = &ipfix.flowsets[0].body.templates.as_ref().unwrap().fields[1];
+            assert_eq!(
+                (template.field_type_number, template.enterprise_number),
+                (1, Some(9))
+            );
+            let data = ipfix.flowsets[1].body.data.as_ref().unwrap();
+            (
+                data.data_fields[0]
+                    .get_value(IPFixField::Enterprise)
+                    .cloned(),
+                ipfix.to_be_bytes().unwrap(),
+            )
+        };
+
+        let (value, bytes) = enterprise_value(&mut NetflowParser::default());
+        assert_eq!(value, Some(FieldValue::DataNumber(DataNumber::U32(7))));
+        assert_eq!(bytes, packet);
+
+        let registry = EnterpriseFieldRegistry::new().with_decoder(9, 1, |bytes: &[u8]| {
+            Some(FieldValue::Custom(
+                CustomValue::String(format!("app-{}", bytes[3])).into(),
+            ))
+        });
+        let mut parser = NetflowParser::builder()
+            .with_enterprise_fields(registry)
+            .build()
+            .unwrap();
+        let (value, bytes) = enterprise_value(&mut parser);
+        let Some(FieldValue::Custom(custom)) = value else {
+            panic!("expected a custom value, got {value:?}");
+        };
+        assert_eq!(custom.value, CustomValue::String("app-7".into()));
+        // The decoded field is written back as it was received
+        assert_eq!(bytes, packet);
+    }
+
+    #[test]
+    #[cfg(feature = "string_formatting")]
+    fn it_decodes_v9_strings_as_configured() {
+        use crate::variable_versions::data_number::StringDecoding;
+
+        // Template 256 with an 8 byte IfName, then a data flowset with a NUL padded name
+        let packet = |name: [u8; 8]| {
+            let mut packet = vec![
+                0, 9, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 12, 1, 0,
+                0, 1, 0, 82, 0, 8, 1, 0, 0, 12,
+            ];
+            packet.extend_from_slice(&name);
+            packet
+        };
+        let if_name = |string_decoding, name| {
+            let mut parser = NetflowParser::builder()
+                .with_string_decoding(string_decoding)
+                .build()
+                .unwrap();
+            let Some(NetflowPacket::V9(v9)) = parser.parse_bytes(&packet(name)).pop() else {
+                panic!("expected a V9 packet");
+            };
+            v9.flowsets[1].body.data.as_ref().unwrap().data_fields[0]
+                .get_value(V9Field::IfName)
+                .cloned()
+                .unwrap()
+        };
+
+        let eth0 = *b"eth0\0\0\0\0";
+        assert_eq!(
+            if_name(StringDecoding::Lossy, eth0),
+            FieldValue::String("eth0\0\0\0\0".into())
+        );
+        assert_eq!(
+            if_name(StringDecoding::TrimNul, eth0),
+            FieldValue::String("eth0".into())
+        );
+        assert_eq!(
+            if_name(StringDecoding::Raw, eth0),
+            FieldValue::Vec(eth0.to_vec())
+        );
+        let invalid = *b"eth\xff\0\0\0\0";
+        assert_eq!(
+            if_name(StringDecoding::Lossy, invalid),
+            FieldValue::String("eth\u{fffd}\0\0\0\0".into())
+        );
+        assert_eq!(
+            if_name(StringDecoding::Strict, invalid),
+            FieldValue::Unknown(invalid.to_vec())
+        );
+    }
+
+    #[test]
+    fn it_parses_ipfix_variable_length_fields() {
+        // A template of source address and a variable length application name, then a
+        // data set of the records in `records`
+        let packet = |records: &[u8]| {
+            let length = 36 + records.len() as u16;
+            let mut packet = vec![0, 10];
+            packet.extend(length.to_be_bytes());
+            packet.extend([0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1]);
+            packet.extend([0, 2, 0, 16, 1, 0, 0, 2, 0, 8, 0, 4, 0, 96, 255, 255, 1, 0]);
+            packet.extend((4 + records.len() as u16).to_be_bytes());
+            packet.extend(records);
+            packet
+        };
+        let names = |packet: &[u8]| {
+            let Some(NetflowPacket::IPFix(ipfix)) =
+                NetflowParser::default().parse_bytes(packet).pop()
+            else {
+                panic!("expected an IPFix packet");
+            };
+            let data = ipfix.flowsets[1].body.data.as_ref().unwrap();
+            let names: Vec<FieldValue> = data
+                .data_fields
+                .iter()
+                .map(|record| record[&1].1.clone())
+                .collect();
+            (ipfix, names)
+        };
+
+        // Short values and an empty one
+        let short = packet(&[1, 2, 3, 4, 3, b's', b's', b'h', 5, 6, 7, 8, 0]);
+        let (ipfix, short_names) = names(&short);
+        assert_eq!(
+            short_names,
+            vec![
+                FieldValue::String("ssh".into()),
+                FieldValue::String("".into())
+            ]
+        );
+        assert_eq!(ipfix.to_be_bytes().unwrap(), short);
+
+        // A value whose length follows a 255 prefix, then padding shorter than a record
+        let long = packet(&[1, 2, 3, 4, 255, 0, 3, b'd', b'n', b's', 0, 0]);
+        assert_eq!(names(&long).1, vec![FieldValue::String("dns".into())]);
+    }
+
     #[test]
     fn it_doesnt_panic_with_invalid_options_ipfix_template() {
         let packet = [
@@ -353,7 +1853,11 @@ mod base_tests {
             fields,
         };
         let mut parser = NetflowParser::default();
-        parser.ipfix_parser.templates.insert(258, template);
+        parser
+            .ipfix_parser
+            .engine
+            .templates
+            .insert(258, Arc::new(template));
         assert_yaml_snapshot!(parser.parse_bytes(&packet));
     }
 
@@ -368,7 +1872,11 @@ mod base_tests {
             fields: vec![],
         };
         let mut parser = NetflowParser::default();
-        parser.ipfix_parser.templates.insert(258, template);
+        parser
+            .ipfix_parser
+            .engine
+            .templates
+            .insert(258, Arc::new(template));
         assert_yaml_snapshot!(parser.parse_bytes(&packet));
     }
 
@@ -383,7 +1891,11 @@ mod base_tests {
             fields: vec![],
         };
         let mut parser = NetflowParser::default();
-        parser.v9_parser.templates.insert(258, template);
+        parser
+            .v9_parser
+            .engine
+            .templates
+            .insert(258, Arc::new(template));
         assert_yaml_snapshot!(parser.parse_bytes(&packet));
     }
 
@@ -396,6 +1908,54 @@ mod base_tests {
         assert_yaml_snapshot!(NetflowParser::default().parse_bytes(&packet));
     }
 
+    #[test]
+    fn it_re_announces_cached_ipfix_templates() {
+        let template = [
+            0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2,
+            0, 2, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        // Options template with an enterprise field and padding
+        let options_template = [
+            0, 10, 0, 44, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 3, 0, 28, 1, 4, 0, 3, 0, 1,
+            128, 123, 0, 4, 0, 0, 0, 2, 0, 41, 0, 2, 0, 42, 0, 2, 0, 0,
+        ];
+        let mut parser = NetflowParser::builder()
+            .with_scoped_templates(true)
+            .build()
+            .unwrap();
+        parser.parse_bytes(&template);
+        parser.parse_bytes(&options_template);
+
+        let message = parser.ipfix_parser.templates_to_message(2).unwrap();
+        assert_eq!(message.flowsets.len(), 1);
+        let mut downstream = NetflowParser::default();
+        downstream.parse_bytes(&message.to_be_bytes().unwrap());
+        // Padding isn't re-announced
+        assert_eq!(
+            downstream.ipfix_parser.engine.options_templates[&260].fields,
+            parser.ipfix_parser.engine.scoped_options_templates[&2][&260].fields
+        );
+
+        let message = parser
+            .ipfix_parser
+            .templates_to_message(0x01020304)
+            .unwrap();
+        let bytes = message.to_be_bytes().unwrap();
+        assert_eq!(usize::from(message.header.length), bytes.len());
+        downstream.parse_bytes(&bytes);
+        assert_eq!(
+            downstream.ipfix_parser.engine.templates,
+            parser.ipfix_parser.engine.scoped_templates[&0x01020304]
+        );
+        assert!(parser
+            .ipfix_parser
+            .templates_to_message(3)
+            .unwrap()
+            .flowsets
+            .is_empty());
+    }
+
     #[test]
     fn it_parses_ipfix_options_template_with_data() {
         let packet = [
@@ -405,4 +1965,432 @@ mod base_tests {
         ];
         assert_yaml_snapshot!(NetflowParser::default().parse_bytes(&packet));
     }
+
+    #[test]
+    fn it_counts_records_per_source() {
+        let mut parser = NetflowParser::builder()
+            .with_source_stats(true)
+            .build()
+            .unwrap();
+        for source_id in [7, 1] {
+            let mut synthesizer = PacketSynthesizer::new(1)
+                .with_source_id(source_id)
+                .with_flows_per_packet(5);
+            for packet in synthesizer.v9_packets(2) {
+                parser.parse_bytes(&packet);
+            }
+            for packet in synthesizer.ipfix_packets(3) {
+                parser.parse_bytes(&packet);
+            }
+        }
+
+        assert_eq!(
+            parser.v9_parser.source_ids().collect::<Vec<_>>(),
+            vec![1, 7]
+        );
+        assert_eq!(
+            parser
+                .ipfix_parser
+                .observation_domains()
+                .collect::<Vec<_>>(),
+            vec![1, 7]
+        );
+        assert_eq!(
+            parser.v9_parser.engine.source_stats[&7],
+            SourceStats {
+                packets: 2,
+                records: 10,
+                options_records: 0,
+            }
+        );
+        assert_eq!(
+            parser.ipfix_parser.engine.source_stats[&1],
+            SourceStats {
+                packets: 3,
+                records: 15,
+                options_records: 0,
+            }
+        );
+
+        let mut uncounted = NetflowParser::default();
+        for packet in PacketSynthesizer::new(1).with_source_id(7).v9_packets(1) {
+            uncounted.parse_bytes(&packet);
+        }
+        assert!(uncounted.v9_parser.engine.source_stats.is_empty());
+    }
+
+    #[test]
+    fn it_summarizes_cached_templates() {
+        // Template 256 with sourceIPv4Address and field 1 of enterprise 9
+        let ipfix_packet = [
+            0, 10, 0, 36, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 2, 0, 8, 0,
+            4, 128, 1, 0, 4, 0, 0, 0, 9,
+        ];
+        // Template 258 with InBytes and Ipv4SrcAddr from source id 1
+        let v9_packet = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4,
+        ];
+        let mut parser = NetflowParser::default();
+        parser.parse_bytes(&ipfix_packet);
+        let summary = parser.get_ipfix_template(256).unwrap();
+        assert_eq!(summary.kind, TemplateKind::Template);
+        assert_eq!(summary.record_length, Some(8));
+        assert_eq!(
+            summary.fields[1],
+            TemplateFieldSummary {
+                name: "Enterprise".to_string(),
+                field_type_number: 1,
+                field_length: 4,
+                enterprise_number: Some(9),
+                scope: false,
+            }
+        );
+        assert_eq!(parser.get_ipfix_template(257), None);
+
+        let mut scoped = NetflowParser::builder()
+            .with_scoped_templates(true)
+            .build()
+            .unwrap();
+        scoped.parse_bytes(&v9_packet);
+        assert_eq!(scoped.get_v9_template(258), None);
+        let summaries = scoped.v9_parser.engine.template_summaries();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].scope, Some(1));
+        assert_eq!(
+            summaries[0]
+                .fields
+                .iter()
+                .map(|field| field.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["InBytes", "Ipv4SrcAddr"]
+        );
+        assert_eq!(
+            scoped
+                .v9_parser
+                .engine
+                .template_summary(Some(1), 258)
+                .as_ref(),
+            summaries.first()
+        );
+    }
+
+    #[test]
+    fn it_reports_suspicious_templates() {
+        // Template 258 with InBytes twice, both zero bytes long
+        let v9_packet = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 0, 0, 1, 0, 0,
+        ];
+        // Template 256 with sourceIPv4Address and field 1 of enterprise 9
+        let ipfix_packet = [
+            0, 10, 0, 36, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 2, 0, 8, 0,
+            4, 128, 1, 0, 4, 0, 0, 0, 9,
+        ];
+        let mut parser = NetflowParser::default();
+        assert!(parser.validate_templates().is_ok());
+        parser.parse_bytes(&v9_packet);
+        parser.parse_bytes(&ipfix_packet);
+
+        let report = parser.validate_templates();
+        assert_eq!(report.templates, 2);
+        let issues: Vec<_> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.version, finding.template_id, finding.issue.clone()))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                (9, 258, TemplateIssue::EmptyRecord),
+                (9, 258, TemplateIssue::ZeroLengthField { field: 0 }),
+                (9, 258, TemplateIssue::ZeroLengthField { field: 1 }),
+                (9, 258, TemplateIssue::DuplicateField { field: 1, first: 0 }),
+                (
+                    10,
+                    256,
+                    TemplateIssue::UnknownEnterpriseField {
+                        field: 1,
+                        enterprise_number: 9,
+                    }
+                ),
+            ]
+        );
+
+        let registry = EnterpriseFieldRegistry::new().with_decoder(9, 1, |_: &[u8]| None);
+        let mut parser = NetflowParser::builder()
+            .with_enterprise_fields(registry)
+            .build()
+            .unwrap();
+        parser.parse_bytes(&ipfix_packet);
+        assert!(parser.validate_templates().is_ok());
+    }
+
+    #[test]
+    fn it_times_source_ttls_by_export_time() {
+        let template = |source_id, unix_secs: u32| {
+            let mut packet = vec![
+                0, 9, 0, 1, 0, 0, 9, 9, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, source_id, 0, 0, 0,
+                16, 1, 2, 0, 2, 0, 1, 0, 4, 0, 8, 0, 4,
+            ];
+            packet[8..12].copy_from_slice(&unix_secs.to_be_bytes());
+            packet
+        };
+        let mut parser = NetflowParser::builder()
+            .with_scoped_templates(true)
+            .with_source_ttl(Duration::from_secs(60))
+            .with_export_time_clock(true)
+            .build()
+            .unwrap();
+        parser.parse_bytes(&template(1, 1_700_000_000));
+        parser.parse_bytes(&template(2, 1_700_000_030));
+        assert_eq!(parser.evict_idle_sources(), 0);
+        // A new source with a clock an hour ahead doesn't age the others
+        parser.parse_bytes(&template(3, 1_700_003_600));
+        assert_eq!(parser.evict_idle_sources(), 0);
+        // Source 2 exporting an hour after its first packet makes the others idle, however
+        // little time passed
+        parser.parse_bytes(&template(2, 1_700_003_630));
+        assert_eq!(parser.evict_idle_sources(), 2);
+        assert_eq!(
+            parser
+                .v9_parser
+                .engine
+                .scoped_templates
+                .keys()
+                .collect::<Vec<_>>(),
+            vec![&2]
+        );
+        let clock = parser.export_time_clock.clone().unwrap();
+        assert_eq!(clock.now(), Duration::from_secs(1_700_003_600));
+        // Packets exported earlier don't move the clock back
+        parser.parse_bytes(&template(2, 1_700_000_000));
+        assert_eq!(clock.now(), Duration::from_secs(1_700_003_600));
+        // Nor do packets that fail to parse move it forward
+        let mut truncated = template(2, 1_700_007_200);
+        truncated.truncate(30);
+        parser.parse_bytes(&truncated);
+        assert_eq!(clock.now(), Duration::from_secs(1_700_003_600));
+        assert_eq!(parser.export_time_sources.len(), 3);
+
+        assert!(matches!(
+            NetflowParser::builder()
+                .with_export_time_clock(true)
+                .with_clock(Arc::new(ExportTimeClock::new()))
+                .build(),
+            Err(NetflowError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn it_ends_v9_messages_by_the_count_semantics() {
+        // A template, then a data flowset of two records, counted as RFC 3954 defines
+        let v9_packet = [
+            0, 9, 0, 3, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0,
+            2, 0, 1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 20, 9, 2, 3, 4, 9, 9, 9, 8, 1, 1, 1, 1, 2, 2,
+            2, 2,
+        ];
+        let v5_packet = [
+            0, 5, 0, 1, 3, 0, 4, 0, 5, 0, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3,
+            4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let parse = |semantics, bytes: &[u8]| {
+            NetflowParser::builder()
+                .with_v9_count_semantics(semantics)
+                .build()
+                .unwrap()
+                .parse_bytes(bytes)
+        };
+        let flowsets = |packet: &NetflowPacket| match packet {
+            NetflowPacket::V9(v9) => v9.flowsets.len(),
+            _ => 0,
+        };
+
+        let buffer = [&v9_packet[..], &v5_packet].concat();
+        for semantics in [CountSemantics::Records, CountSemantics::Auto] {
+            let packets = parse(semantics, &buffer);
+            assert_eq!(packets.len(), 2);
+            assert_eq!(flowsets(&packets[0]), 2);
+            assert!(packets[1].is_v5());
+        }
+
+        // Counts that are too low stop flowsets early unless they are ignored
+        let mut undercounted = v9_packet;
+        undercounted[3] = 1;
+        assert_eq!(
+            flowsets(&parse(CountSemantics::FlowSets, &undercounted)[0]),
+            1
+        );
+        assert_eq!(flowsets(&parse(CountSemantics::Auto, &undercounted)[0]), 2);
+
+        // A vendor flowset with reserved id 9 that looks like a message is kept while neither
+        // count is reached
+        let vendor_packet = [
+            0, 9, 0, 1, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 9, 0, 28, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 0, 8, 0, 0, 0, 0,
+        ];
+        let packets = parse(CountSemantics::Auto, &vendor_packet);
+        assert_eq!(packets.len(), 1);
+        let NetflowPacket::V9(v9) = &packets[0] else {
+            panic!("expected a V9 packet");
+        };
+        assert_eq!(
+            v9.flowsets[0].unsupported_data(),
+            Some(&vendor_packet[20..])
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_raw_bytes_of_flowsets() {
+        let ipfix_packet = [
+            0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2,
+            0, 2, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let v9_packet = V9_PACKET;
+        let mut parser = NetflowParser::builder()
+            .with_keep_raw(true)
+            .build()
+            .unwrap();
+        let (ipfix, v9) = (
+            parser.parse_bytes(&ipfix_packet),
+            parser.parse_bytes(&v9_packet),
+        );
+        let (NetflowPacket::IPFix(ipfix), NetflowPacket::V9(v9)) = (&ipfix[0], &v9[0]) else {
+            panic!("expected an IPFix and a V9 packet");
+        };
+        assert_eq!(
+            ipfix.flowsets[0].body.raw.as_deref(),
+            Some(&ipfix_packet[16..36])
+        );
+        assert_eq!(
+            ipfix.flowsets[1].body.raw.as_deref(),
+            Some(&ipfix_packet[36..])
+        );
+        assert_eq!(v9.flowsets[0].body.raw.as_deref(), Some(&v9_packet[20..36]));
+        assert_eq!(v9.flowsets[1].body.raw.as_deref(), Some(&v9_packet[36..]));
+
+        let packets = NetflowParser::default().parse_bytes(&v9_packet);
+        let NetflowPacket::V9(v9) = &packets[0] else {
+            panic!("expected a V9 packet");
+        };
+        assert!(v9.flowsets.iter().all(|flowset| flowset.body.raw.is_none()));
+    }
+
+    #[test]
+    fn it_digests_packets() {
+        let v9_packet = V9_PACKET;
+        let mut next = v9_packet;
+        next[15] = 2;
+        let mut parser = NetflowParser::builder()
+            .with_packet_digests(true)
+            .build()
+            .unwrap();
+        let packets = parser.parse_bytes(&[&v9_packet[..], &v9_packet, &next].concat());
+        let digests: Vec<_> = packets.iter().map(|p| p.digest().unwrap()).collect();
+        assert_eq!(digests[0].source_id, Some(1));
+        assert_eq!(digests[0].sequence, 1);
+        assert_eq!(digests[0].hash, crate::digest::fnv1a(&v9_packet));
+        assert_eq!(digests[0], digests[1]);
+        assert_eq!(digests[2].sequence, 2);
+        assert_ne!(digests[2].hash, digests[0].hash);
+
+        let packets = NetflowParser::default().parse_bytes(&v9_packet);
+        assert_eq!(packets[0].digest(), None);
+    }
+
+    #[test]
+    fn it_only_decodes_projected_fields() {
+        let ipfix_packet = [
+            0, 10, 0, 64, 1, 2, 3, 4, 0, 0, 0, 0, 1, 2, 3, 4, 0, 2, 0, 20, 1, 0, 0, 3, 0, 8, 0,
+            4, 0, 12, 0, 4, 0, 2, 0, 4, 1, 0, 0, 28, 1, 2, 3, 4, 1, 2, 3, 3, 1, 2, 3, 2, 0, 2,
+            0, 2, 0, 1, 2, 3, 4, 5, 6, 7,
+        ];
+        let v9_packet = V9_PACKET;
+        let mut parser = NetflowParser::builder()
+            .with_v9_projection([V9Field::Ipv4SrcAddr])
+            .with_ipfix_projection([IPFixField::DestinationIpv4address])
+            .build()
+            .unwrap();
+        let (ipfix, v9) = (
+            parser.parse_bytes(&ipfix_packet),
+            parser.parse_bytes(&v9_packet),
+        );
+        let (NetflowPacket::IPFix(ipfix), NetflowPacket::V9(v9)) = (&ipfix[0], &v9[0]) else {
+            panic!("expected an IPFix and a V9 packet");
+        };
+        let records = &ipfix.flowsets[1].body.data.as_ref().unwrap().data_fields;
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0]
+                .values()
+                .map(|(field, _)| *field)
+                .collect::<Vec<_>>(),
+            vec![IPFixField::DestinationIpv4address]
+        );
+        let records = &v9.flowsets[1].body.data.as_ref().unwrap().data_fields;
+        assert_eq!(
+            records[0].values().cloned().collect::<Vec<_>>(),
+            vec![(
+                V9Field::Ipv4SrcAddr,
+                FieldValue::Ip4Addr(Ipv4Addr::new(9, 9, 9, 8))
+            )]
+        );
+    }
+
+    #[test]
+    fn it_splits_ipfix_options_records_into_scope_and_options_fields() {
+        let packet = [
+            0, 10, 0, 64, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 3, 0, 28, 1, 4, 0, 3, 0, 1,
+            128, 123, 0, 4, 0, 0, 0, 2, 0, 41, 0, 2, 0, 42, 0, 2, 0, 0, 1, 4, 0, 20, 0, 0, 0,
+            1, 1, 20, 20, 20, 0, 0, 0, 2, 20, 20, 30, 30,
+        ];
+        let packets = NetflowParser::default().parse_bytes(&packet);
+        let NetflowPacket::IPFix(ipfix) = &packets[0] else {
+            panic!("expected an IPFix packet");
+        };
+        let template = ipfix.flowsets[0].body.options_templates.as_ref().unwrap();
+        assert_eq!(template.scope_fields().len(), 1);
+        assert_eq!(template.options_fields().len(), 2);
+        assert!(template.is_scope(0) && !template.is_scope(1));
+
+        let options_data = ipfix.flowsets[1].body.options_data.as_ref().unwrap();
+        let records: Vec<_> = options_data.records().collect();
+        assert_eq!(records.len(), 2);
+        let field_types = |fields: &mut dyn Iterator<Item = &(IPFixField, FieldValue)>| {
+            fields.map(|(field, _)| *field).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            field_types(&mut records[1].scope_fields()),
+            vec![IPFixField::Enterprise]
+        );
+        assert_eq!(
+            field_types(&mut records[1].options_fields()),
+            vec![
+                IPFixField::ExportedMessageTotalCount,
+                IPFixField::ExportedFlowRecordTotalCount
+            ]
+        );
+    }
+
+    #[test]
+    fn it_re_announces_ipfix_templates_sorted_by_id() {
+        // Template sets for 300 and then 256, each with a single sourceIPv4Address field
+        let packet = [
+            0, 10, 0, 40, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 0, 12, 1, 44, 0, 1, 0, 8,
+            0, 4, 0, 2, 0, 12, 1, 0, 0, 1, 0, 8, 0, 4,
+        ];
+        let mut parser = NetflowParser::default();
+        parser.parse_bytes(&packet);
+
+        let message = parser.ipfix_parser.templates_to_message(1).unwrap();
+        let ids: Vec<u16> = message
+            .flowsets
+            .iter()
+            .filter_map(|set| set.body.templates.as_ref())
+            .map(|template| template.template_id)
+            .collect();
+        assert_eq!(ids, vec![256, 300]);
+    }
 }
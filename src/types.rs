@@ -0,0 +1,70 @@
+//! # Types
+//!
+//! Newtypes for values that are easy to mix up when they are all plain integers, such as
+//! source and destination ports or AS numbers.  They serialize as the bare number.
+//!
+//! ```rust
+//! use netflow_parser::types::{Asn, Port};
+//!
+//! let port = Port(443);
+//! assert_eq!(port.to_string(), "443");
+//! assert_eq!(u16::from(port), 443);
+//! assert_eq!(Asn::from(64512u32).to_string(), "64512");
+//! ```
+
+use core::fmt;
+
+use serde::Serialize;
+
+/// TCP/UDP port number or equivalent.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(transparent)]
+pub struct Port(pub u16);
+
+/// Autonomous system number.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(transparent)]
+pub struct Asn(pub u32);
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for Asn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<u16> for Port {
+    fn from(port: u16) -> Self {
+        Port(port)
+    }
+}
+
+impl From<Port> for u16 {
+    fn from(port: Port) -> Self {
+        port.0
+    }
+}
+
+impl From<u32> for Asn {
+    fn from(asn: u32) -> Self {
+        Asn(asn)
+    }
+}
+
+/// V5/V7 records carry 2 byte AS numbers.
+impl From<u16> for Asn {
+    fn from(asn: u16) -> Self {
+        Asn(asn.into())
+    }
+}
+
+impl From<Asn> for u32 {
+    fn from(asn: Asn) -> Self {
+        asn.0
+    }
+}
@@ -0,0 +1,82 @@
+//! # Uptime
+//!
+//! Conversions of sysuptime timestamps, the milliseconds since the exporter booted that V5, V7
+//! and V9 flows are timed in, to durations and unix times.  The 32 bit counter wraps every
+//! ~49.7 days, so readings are compared as serial numbers (RFC 1982): a flow's `first` and
+//! `last` are taken to be within ~24.8 days of the header `sys_up_time`, on whichever side
+//! of it that is, across a wrap.
+//!
+//! ```rust
+//! use netflow_parser::uptime::{uptime_elapsed, uptime_to_unix_time};
+//! use std::time::Duration;
+//!
+//! // A flow started 2 seconds before the counter wrapped and ended 1 second after
+//! let (first, last) = (u32::MAX - 1_999, 1_000);
+//! assert_eq!(uptime_elapsed(first, last), Duration::from_secs(3));
+//!
+//! // Exported 5 seconds after it ended
+//! let export_time = Duration::from_secs(1_700_000_000);
+//! assert_eq!(
+//!     uptime_to_unix_time(export_time, 6_000, first),
+//!     Duration::from_secs(1_700_000_000 - 8)
+//! );
+//! ```
+
+use crate::variable_versions::data_number::FieldValue;
+
+use core::time::Duration;
+
+/// Time from sysuptime reading `earlier` to `later`, such as a flow's `first` and `last`,
+/// across a wrap of the counter.
+pub fn uptime_elapsed(earlier: u32, later: u32) -> Duration {
+    Duration::from_millis(later.wrapping_sub(earlier).into())
+}
+
+/// Unix time of sysuptime reading `uptime`, from a packet exported at `export_time`, since the
+/// unix epoch, when the exporter's uptime was `sys_up_time`.
+pub fn uptime_to_unix_time(export_time: Duration, sys_up_time: u32, uptime: u32) -> Duration {
+    // Positive for readings before the export
+    let age = sys_up_time.wrapping_sub(uptime) as i32;
+    let offset = Duration::from_millis(age.unsigned_abs().into());
+    if age >= 0 {
+        export_time.saturating_sub(offset)
+    } else {
+        export_time.saturating_add(offset)
+    }
+}
+
+/// Sysuptime reading of a V9 `FirstSwitched`/`LastSwitched` style field, parsed as a duration
+/// in milliseconds or kept as a number.
+pub(crate) fn uptime_value(value: &FieldValue) -> Option<u32> {
+    match value {
+        FieldValue::Duration(uptime) => u32::try_from(uptime.as_millis()).ok(),
+        _ => u32::try_from(value).ok(),
+    }
+}
+
+#[cfg(test)]
+mod uptime_tests {
+    use super::{uptime_elapsed, uptime_to_unix_time};
+    use core::time::Duration;
+
+    #[test]
+    fn it_converts_uptimes_across_a_wrap() {
+        let export_time = Duration::from_secs(1_000);
+        assert_eq!(
+            uptime_to_unix_time(export_time, 500, 100),
+            Duration::from_millis(999_600)
+        );
+        // Recorded before the counter wrapped, exported after
+        assert_eq!(
+            uptime_to_unix_time(export_time, 500, u32::MAX - 499),
+            Duration::from_secs(999)
+        );
+        // A flow ending a little after the header time, for exporters stamping it late
+        assert_eq!(
+            uptime_to_unix_time(export_time, u32::MAX, 9),
+            Duration::from_millis(1_000_010)
+        );
+        assert_eq!(uptime_elapsed(100, 500), Duration::from_millis(400));
+        assert_eq!(uptime_elapsed(u32::MAX, 0), Duration::from_millis(1));
+    }
+}
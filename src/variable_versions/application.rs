@@ -0,0 +1,263 @@
+//! Application ids (RFC 6759), as exported by Cisco AVC / NBAR2 in the IPFix `ApplicationId`
+//! and V9 `ApplicationTag` fields.
+//!
+//! An application id is a classification engine id followed by a selector within that
+//! engine, such as engine 3 (IANA-L4) with selector 443.  [`ApplicationRegistry`] resolves
+//! them to names.  It knows common IANA-L3 protocols and IANA-L4 ports, names of NBAR2
+//! (PANA-L7) selectors depend on the protocol pack of the exporter and can be loaded from a
+//! mapping file or learned from the application table an exporter sends as options data.
+//!
+//! ```rust
+//! use netflow_parser::variable_versions::application::{ApplicationId, ApplicationRegistry};
+//!
+//! let mut registry = ApplicationRegistry::well_known();
+//! registry.load_mappings("13:453 webex-meeting").unwrap();
+//!
+//! let https = ApplicationId::from_bytes(&[3, 1, 187]).unwrap();
+//! assert_eq!(https.to_string(), "3:443");
+//! assert_eq!(registry.resolve(&https), Some("https"));
+//! assert_eq!(registry.resolve(&ApplicationId::new(13, 453)), Some("webex-meeting"));
+//! ```
+
+use super::data_record::DataRecordExt;
+use crate::NetflowError;
+
+use serde::Serialize;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt;
+
+/// Engine id and selector of an application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct ApplicationId {
+    /// Classification engine id
+    pub engine_id: u8,
+    /// Selector id within the engine
+    pub selector: u64,
+}
+
+impl ApplicationId {
+    pub fn new(engine_id: u8, selector: u64) -> Self {
+        Self {
+            engine_id,
+            selector,
+        }
+    }
+
+    /// Decodes the engine id byte followed by a big-endian selector of up to 8 bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (&engine_id, selector) = bytes.split_first()?;
+        if selector.len() > 8 {
+            return None;
+        }
+        let selector = selector
+            .iter()
+            .fold(0u64, |acc, &byte| (acc << 8) | u64::from(byte));
+        Some(Self::new(engine_id, selector))
+    }
+
+    /// Name of the classification engine from RFC 6759, if it is a known one.
+    pub fn engine_name(&self) -> Option<&'static str> {
+        match self.engine_id {
+            1 => Some("IANA-L3"),
+            2 => Some("PANA-L3"),
+            3 => Some("IANA-L4"),
+            4 => Some("PANA-L4"),
+            6 => Some("USER-Defined"),
+            12 => Some("PANA-L2"),
+            13 => Some("PANA-L7"),
+            18 => Some("ETHERTYPE"),
+            19 => Some("LLC"),
+            20 => Some("PANA-L7-PEN"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ApplicationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.engine_id, self.selector)
+    }
+}
+
+/// IANA-L3 protocol numbers
+const IANA_L3: &[(u64, &str)] = &[
+    (1, "icmp"),
+    (2, "igmp"),
+    (6, "tcp"),
+    (17, "udp"),
+    (41, "ipv6"),
+    (47, "gre"),
+    (50, "esp"),
+    (51, "ah"),
+    (58, "ipv6-icmp"),
+    (89, "ospf"),
+    (132, "sctp"),
+];
+
+/// IANA-L4 ports, by their IANA service names
+const IANA_L4: &[(u64, &str)] = &[
+    (20, "ftp-data"),
+    (21, "ftp"),
+    (22, "ssh"),
+    (23, "telnet"),
+    (25, "smtp"),
+    (53, "domain"),
+    (67, "bootps"),
+    (69, "tftp"),
+    (80, "http"),
+    (110, "pop3"),
+    (123, "ntp"),
+    (143, "imap"),
+    (161, "snmp"),
+    (179, "bgp"),
+    (389, "ldap"),
+    (443, "https"),
+    (514, "syslog"),
+    (993, "imaps"),
+    (995, "pop3s"),
+    (3389, "ms-wbt-server"),
+    (5060, "sip"),
+];
+
+/// Application names by [`ApplicationId`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ApplicationRegistry {
+    names: BTreeMap<ApplicationId, String>,
+}
+
+impl ApplicationRegistry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with common IANA-L3 protocols and IANA-L4 ports.
+    pub fn well_known() -> Self {
+        let mut registry = Self::new();
+        for (engine_id, table) in [(1, IANA_L3), (3, IANA_L4)] {
+            for &(selector, name) in table {
+                registry.insert(ApplicationId::new(engine_id, selector), name);
+            }
+        }
+        registry
+    }
+
+    /// Names `id`, replacing any name it had.
+    pub fn insert(&mut self, id: ApplicationId, name: impl Into<String>) {
+        self.names.insert(id, name.into());
+    }
+
+    /// Name of `id`, if it is known.
+    pub fn resolve(&self, id: &ApplicationId) -> Option<&str> {
+        self.names.get(id).map(String::as_str)
+    }
+
+    /// Loads mappings from a protocol pack, one `<engine id>:<selector> <name>` per line.
+    /// Blank lines and lines starting with `#` are skipped.  Returns how many were loaded.
+    pub fn load_mappings(&mut self, mappings: &str) -> Result<usize, NetflowError> {
+        let mut loaded = 0;
+        for (number, line) in mappings.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                NetflowError::ConfigError(format!(
+                    "invalid application mapping on line {}: {line}",
+                    number + 1
+                ))
+            };
+            let (id, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (engine_id, selector) = id.split_once(':').ok_or_else(invalid)?;
+            let id = ApplicationId::new(
+                engine_id.parse().map_err(|_| invalid())?,
+                selector.parse().map_err(|_| invalid())?,
+            );
+            self.insert(id, name.trim().to_string());
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// Learns a name from an exporter's application table record, which carries the id in
+    /// `id_field` and the name in `name_field`.  Returns whether the record had both.
+    pub fn learn<F: Copy, R: DataRecordExt<F> + ?Sized>(
+        &mut self,
+        record: &R,
+        id_field: F,
+        name_field: F,
+    ) -> bool {
+        match (
+            record.get_application_id(id_field),
+            record.get_str(name_field),
+        ) {
+            (Some(id), Some(name)) => {
+                self.insert(id, name.trim_end_matches('\0'));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod application_tests {
+    use super::{ApplicationId, ApplicationRegistry};
+    use crate::variable_versions::data_number::FieldValue;
+    use crate::variable_versions::ipfix_lookup::IPFixField;
+    use crate::NetflowError;
+
+    #[test]
+    fn it_decodes_application_ids() {
+        let id = ApplicationId::from_bytes(&[13, 0, 0, 1, 197]).unwrap();
+        assert_eq!(id, ApplicationId::new(13, 453));
+        assert_eq!(id.engine_name(), Some("PANA-L7"));
+        assert_eq!(ApplicationId::from_bytes(&[]), None);
+        assert_eq!(ApplicationId::from_bytes(&[3; 10]), None);
+    }
+
+    #[test]
+    fn it_loads_and_learns_application_names() {
+        let mut registry = ApplicationRegistry::new();
+        assert_eq!(
+            registry.load_mappings("# pack 42\n\n13:453 webex-meeting\n13:454  ms-teams\n"),
+            Ok(2)
+        );
+        assert_eq!(
+            registry.resolve(&ApplicationId::new(13, 454)),
+            Some("ms-teams")
+        );
+        assert!(matches!(
+            registry.load_mappings("13 webex"),
+            Err(NetflowError::ConfigError(_))
+        ));
+
+        let record = [
+            (
+                IPFixField::ApplicationId,
+                FieldValue::Vec(vec![13, 0, 0, 0, 7]),
+            ),
+            (
+                IPFixField::ApplicationName,
+                FieldValue::String("dns\0\0\0".into()),
+            ),
+        ];
+        assert!(registry.learn(
+            &record[..],
+            IPFixField::ApplicationId,
+            IPFixField::ApplicationName
+        ));
+        assert_eq!(registry.resolve(&ApplicationId::new(13, 7)), Some("dns"));
+    }
+}
@@ -1,17 +1,20 @@
 use crate::protocol::ProtocolTypes;
+use crate::NetflowError;
 
-use byteorder::{BigEndian, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder};
 use nom::bytes::complete::take;
 use nom::error::{Error as NomError, ErrorKind};
-use nom::number::complete::{be_i24, be_u128, be_u24, be_u32};
+use nom::number::complete::{be_f32, be_i24, be_u128, be_u24, be_u32};
 use nom::Err as NomErr;
 use nom::IResult;
 use nom_derive::*;
 use serde::Serialize;
 
-use std::convert::Into;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-use std::time::Duration;
+use alloc::collections::BTreeMap;
+use alloc::{format, string::String, string::ToString, vec, vec::Vec};
+use core::convert::Into;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use core::time::Duration;
 
 macro_rules! impl_try_from {
     ($($t:ty => $v:ident),*; $($s:ty => $sv:ident),*) => {
@@ -98,6 +101,51 @@ impl TryFrom<&FieldValue> for IpAddr {
     }
 }
 
+/// Seconds from the NTP epoch (1900-01-01) to the unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Parses an NTP timestamp (RFC 7011 dateTimeMicroseconds / dateTimeNanoseconds) into the time
+/// since the unix epoch, keeping `precision` fractions of a second.
+fn parse_ntp_timestamp(
+    remaining: &[u8],
+    field_length: u16,
+    precision: u64,
+) -> IResult<&[u8], Duration> {
+    if field_length != 8 {
+        return Err(NomErr::Error(NomError::new(
+            remaining,
+            ErrorKind::LengthValue,
+        )));
+    }
+    let (i, seconds) = be_u32(remaining)?;
+    let (i, fraction) = be_u32(i)?;
+    let fraction = if precision == 1_000_000 {
+        // The lower 11 bits of a dateTimeMicroseconds fraction are to be ignored.
+        fraction & !0x7FF
+    } else {
+        fraction
+    };
+    let units = (u64::from(fraction) * precision + (1 << 31)) >> 32;
+    let since_epoch = u64::from(seconds).saturating_sub(NTP_UNIX_OFFSET);
+    Ok((
+        i,
+        Duration::new(since_epoch, (units * (1_000_000_000 / precision)) as u32),
+    ))
+}
+
+/// Writes the time since the unix epoch as an NTP timestamp.  The fraction is rounded up so
+/// parsing it again gives back the same time.
+fn ntp_timestamp_be_bytes(time: &Duration, precision: u64) -> Result<Vec<u8>, NetflowError> {
+    let seconds = u32::try_from(time.as_secs() + NTP_UNIX_OFFSET).map_err(|_| {
+        NetflowError::InvalidFieldValue(format!("{time:?} is past the end of NTP era 0"))
+    })?;
+    let units = u64::from(time.subsec_nanos()) / (1_000_000_000 / precision);
+    let fraction = (units << 32).div_ceil(precision);
+    let mut bytes = seconds.to_be_bytes().to_vec();
+    bytes.extend_from_slice(&(fraction as u32).to_be_bytes());
+    Ok(bytes)
+}
+
 #[cfg(feature = "parse_unknown_fields")]
 fn parse_unknown_fields(remaining: &[u8], field_length: u16) -> IResult<&[u8], FieldValue> {
     let (i, taken) = take(field_length)(remaining)?;
@@ -105,11 +153,7 @@ fn parse_unknown_fields(remaining: &[u8], field_length: u16) -> IResult<&[u8], F
 }
 
 #[cfg(not(feature = "parse_unknown_fields"))]
-fn parse_unknown_fields(
-    remaining: &[u8],
-    field_type: FieldDataType,
-    field_length: u16,
-) -> IResult<&[u8], FieldValue> {
+fn parse_unknown_fields(remaining: &[u8], _field_length: u16) -> IResult<&[u8], FieldValue> {
     Err(NomErr::Error(NomError::new(remaining, ErrorKind::Fail)))
 }
 
@@ -130,24 +174,34 @@ impl DataNumber {
         }
     }
 
-    fn to_be_bytes(&self) -> Vec<u8> {
+    fn to_be_bytes(&self) -> Result<Vec<u8>, NetflowError> {
         match self {
-            DataNumber::U8(n) => n.to_be_bytes().to_vec(),
-            DataNumber::U16(n) => n.to_be_bytes().to_vec(),
+            DataNumber::U8(n) => Ok(n.to_be_bytes().to_vec()),
+            DataNumber::U16(n) => Ok(n.to_be_bytes().to_vec()),
             DataNumber::U24(n) => {
-                let mut wtr = Vec::new();
-                wtr.write_u24::<BigEndian>(*n).unwrap();
-                wtr
+                if *n > 0xFF_FFFF {
+                    return Err(NetflowError::InvalidFieldValue(format!(
+                        "{n} does not fit in 3 bytes"
+                    )));
+                }
+                let mut buf = [0u8; 3];
+                BigEndian::write_u24(&mut buf, *n);
+                Ok(buf.to_vec())
             }
             DataNumber::I24(n) => {
-                let mut wtr = Vec::new();
-                wtr.write_i24::<BigEndian>(*n).unwrap();
-                wtr
+                if !(-0x80_0000..=0x7F_FFFF).contains(n) {
+                    return Err(NetflowError::InvalidFieldValue(format!(
+                        "{n} does not fit in 3 bytes"
+                    )));
+                }
+                let mut buf = [0u8; 3];
+                BigEndian::write_i24(&mut buf, *n);
+                Ok(buf.to_vec())
             }
-            DataNumber::U32(n) => n.to_be_bytes().to_vec(),
-            DataNumber::U64(n) => n.to_be_bytes().to_vec(),
-            DataNumber::U128(n) => n.to_be_bytes().to_vec(),
-            DataNumber::I32(n) => n.to_be_bytes().to_vec(),
+            DataNumber::U32(n) => Ok(n.to_be_bytes().to_vec()),
+            DataNumber::U64(n) => Ok(n.to_be_bytes().to_vec()),
+            DataNumber::U128(n) => Ok(n.to_be_bytes().to_vec()),
+            DataNumber::I32(n) => Ok(n.to_be_bytes().to_vec()),
         }
     }
 
@@ -155,6 +209,22 @@ impl DataNumber {
         remaining: &[u8],
         field_type: FieldDataType,
         field_length: u16,
+    ) -> IResult<&[u8], FieldValue> {
+        Self::from_field_type_decoding(
+            remaining,
+            field_type,
+            field_length,
+            StringDecoding::default(),
+        )
+    }
+
+    /// Like `from_field_type`, decoding `String` fields as `string_decoding` says.
+    #[cfg_attr(not(feature = "string_formatting"), allow(unused_variables))]
+    pub fn from_field_type_decoding(
+        remaining: &[u8],
+        field_type: FieldDataType,
+        field_length: u16,
+        string_decoding: StringDecoding,
     ) -> IResult<&[u8], FieldValue> {
         let (remaining, field_value) = match field_type {
             FieldDataType::UnsignedDataNumber => {
@@ -165,12 +235,19 @@ impl DataNumber {
                 let (i, data_number) = DataNumber::parse(remaining, field_length, true)?;
                 (i, FieldValue::DataNumber(data_number))
             }
+            #[cfg(feature = "string_formatting")]
             FieldDataType::String => {
                 let (i, taken) = take(field_length)(remaining)?;
-                (
-                    i,
-                    FieldValue::String(String::from_utf8_lossy(taken).to_string()),
-                )
+                let value = string_decoding.decode(taken).ok_or_else(|| {
+                    NomErr::Error(NomError::new(remaining, ErrorKind::Verify))
+                })?;
+                (i, value)
+            }
+            // Without string formatting the raw bytes are kept as is
+            #[cfg(not(feature = "string_formatting"))]
+            FieldDataType::String => {
+                let (i, taken) = take(field_length)(remaining)?;
+                (i, FieldValue::Vec(taken.to_vec()))
             }
             FieldDataType::Ip4Addr => {
                 let (i, taken) = be_u32(remaining)?;
@@ -182,6 +259,7 @@ impl DataNumber {
                 let ip_addr = Ipv6Addr::from(taken);
                 (i, FieldValue::Ip6Addr(ip_addr))
             }
+            #[cfg(feature = "string_formatting")]
             FieldDataType::MacAddr => {
                 let (i, taken) = take(6_usize)(remaining)?;
                 let taken: &[u8; 6] = taken
@@ -191,6 +269,12 @@ impl DataNumber {
                 let mac_addr = mac_address::MacAddress::from(*taken).to_string();
                 (i, FieldValue::MacAddr(mac_addr))
             }
+            // Without string formatting the raw 6 bytes are kept as is
+            #[cfg(not(feature = "string_formatting"))]
+            FieldDataType::MacAddr => {
+                let (i, taken) = take(6_usize)(remaining)?;
+                (i, FieldValue::Vec(taken.to_vec()))
+            }
             FieldDataType::DurationSeconds => {
                 let (i, data_number) = DataNumber::parse(remaining, field_length, false)?;
                 (
@@ -227,14 +311,68 @@ impl DataNumber {
                     )),
                 )
             }
+            FieldDataType::DateTimeSeconds => {
+                let (i, data_number) = DataNumber::parse(remaining, field_length, false)?;
+                (
+                    i,
+                    FieldValue::DateTimeSeconds(Duration::from_secs(<DataNumber as Into<
+                        usize,
+                    >>::into(
+                        data_number
+                    )
+                        as u64)),
+                )
+            }
+            FieldDataType::DateTimeMillis => {
+                let (i, data_number) = DataNumber::parse(remaining, field_length, false)?;
+                (
+                    i,
+                    FieldValue::DateTimeMillis(Duration::from_millis(<DataNumber as Into<
+                        usize,
+                    >>::into(
+                        data_number
+                    )
+                        as u64)),
+                )
+            }
+            FieldDataType::DateTimeMicros => {
+                let (i, time) = parse_ntp_timestamp(remaining, field_length, 1_000_000)?;
+                (i, FieldValue::DateTimeMicros(time))
+            }
+            FieldDataType::DateTimeNanos => {
+                let (i, time) = parse_ntp_timestamp(remaining, field_length, 1_000_000_000)?;
+                (i, FieldValue::DateTimeNanos(time))
+            }
             FieldDataType::ProtocolType => {
                 let (i, protocol) = ProtocolTypes::parse(remaining)?;
                 (i, FieldValue::ProtocolType(protocol))
             }
+            // Reduced size encoding (RFC 7011 6.2) of a float64
+            FieldDataType::Float32 | FieldDataType::Float64 if field_length == 4 => {
+                let (i, f) = be_f32(remaining)?;
+                (i, FieldValue::Float32(f))
+            }
+            FieldDataType::Float32 => {
+                return Err(NomErr::Error(NomError::new(
+                    remaining,
+                    ErrorKind::LengthValue,
+                )))
+            }
             FieldDataType::Float64 => {
                 let (i, f) = f64::parse(remaining)?;
                 (i, FieldValue::Float64(f))
             }
+            FieldDataType::Boolean => {
+                let (i, b) = u8::parse(remaining)?;
+                let b = match b {
+                    1 => true,
+                    2 => false,
+                    _ => {
+                        return Err(NomErr::Error(NomError::new(remaining, ErrorKind::Verify)))
+                    }
+                };
+                (i, FieldValue::Bool(b))
+            }
             FieldDataType::Vec => {
                 let (i, taken) = take(field_length)(remaining)?;
                 (i, FieldValue::Vec(taken.to_vec()))
@@ -266,14 +404,62 @@ impl From<DataNumber> for usize {
 pub enum FieldValue {
     String(String),
     DataNumber(DataNumber),
+    Float32(f32),
     Float64(f64),
+    Bool(bool),
     Duration(Duration),
+    /// `dateTimeSeconds`, time since the unix epoch
+    DateTimeSeconds(Duration),
+    /// `dateTimeMilliseconds`, time since the unix epoch
+    DateTimeMillis(Duration),
+    /// `dateTimeMicroseconds`, time since the unix epoch
+    DateTimeMicros(Duration),
+    /// `dateTimeNanoseconds`, time since the unix epoch
+    DateTimeNanos(Duration),
     Ip4Addr(Ipv4Addr),
     Ip6Addr(Ipv6Addr),
     MacAddr(String),
     Vec(Vec<u8>),
     ProtocolType(ProtocolTypes),
-    Unknown,
+    /// Raw bytes of a field that could not be decoded as its data type
+    Unknown(Vec<u8>),
+    /// Value of an enterprise specific field decoded by an `EnterpriseFieldDecoder`
+    Custom(CustomField),
+}
+
+/// A [`CustomValue`] with the bytes it was decoded from, written back when the field is
+/// exported.  Serializes as the bare value.
+#[derive(Debug, PartialEq, PartialOrd, Clone, Serialize)]
+#[serde(transparent)]
+pub struct CustomField {
+    pub value: CustomValue,
+    /// Bytes of the field, set by the parser once the value is decoded
+    #[serde(skip)]
+    pub raw: Vec<u8>,
+}
+
+impl From<CustomValue> for CustomField {
+    fn from(value: CustomValue) -> Self {
+        Self {
+            value,
+            raw: Vec::new(),
+        }
+    }
+}
+
+/// Value in a shape of the decoder's choosing, such as vendor metadata with several parts.
+/// Serializes as the bare value, the way it would be written as JSON.
+#[derive(Debug, PartialEq, PartialOrd, Clone, Serialize)]
+#[serde(untagged)]
+pub enum CustomValue {
+    Bool(bool),
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<CustomValue>),
+    Map(BTreeMap<String, CustomValue>),
 }
 
 #[derive(Debug)]
@@ -282,14 +468,136 @@ pub enum FieldValueError {
 }
 
 impl FieldValue {
-    pub fn to_be_bytes(&self) -> Vec<u8> {
+    pub fn to_be_bytes(&self) -> Result<Vec<u8>, NetflowError> {
         match self {
-            FieldValue::String(s) => s.as_bytes().to_vec(),
+            FieldValue::String(s) => Ok(s.as_bytes().to_vec()),
             FieldValue::DataNumber(d) => d.to_be_bytes(),
-            FieldValue::Float64(f) => f.to_be_bytes().to_vec(),
-            FieldValue::Duration(d) => (d.as_secs() as u32).to_be_bytes().to_vec(),
-            FieldValue::Ip4Addr(ip) => ip.octets().to_vec(),
-            _ => vec![],
+            FieldValue::Float32(f) => Ok(f.to_be_bytes().to_vec()),
+            FieldValue::Float64(f) => Ok(f.to_be_bytes().to_vec()),
+            FieldValue::Bool(b) => Ok(vec![if *b { 1 } else { 2 }]),
+            FieldValue::Duration(d) => Ok((d.as_secs() as u32).to_be_bytes().to_vec()),
+            FieldValue::DateTimeSeconds(d) => u32::try_from(d.as_secs())
+                .map(|secs| secs.to_be_bytes().to_vec())
+                .map_err(|_| {
+                    NetflowError::InvalidFieldValue(format!(
+                        "{d:?} does not fit in dateTimeSeconds"
+                    ))
+                }),
+            FieldValue::DateTimeMillis(d) => u64::try_from(d.as_millis())
+                .map(|millis| millis.to_be_bytes().to_vec())
+                .map_err(|_| {
+                    NetflowError::InvalidFieldValue(format!(
+                        "{d:?} does not fit in dateTimeMilliseconds"
+                    ))
+                }),
+            FieldValue::DateTimeMicros(d) => ntp_timestamp_be_bytes(d, 1_000_000),
+            FieldValue::DateTimeNanos(d) => ntp_timestamp_be_bytes(d, 1_000_000_000),
+            FieldValue::Ip4Addr(ip) => Ok(ip.octets().to_vec()),
+            FieldValue::Ip6Addr(ip) => Ok(ip.octets().to_vec()),
+            FieldValue::MacAddr(mac) => mac
+                .split(':')
+                .map(|octet| u8::from_str_radix(octet, 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| {
+                    NetflowError::InvalidFieldValue(format!("{mac} is not a MAC address"))
+                }),
+            FieldValue::ProtocolType(protocol) => Ok(vec![u8::from(*protocol)]),
+            FieldValue::Vec(bytes) | FieldValue::Unknown(bytes) => Ok(bytes.clone()),
+            FieldValue::Custom(custom) => Ok(custom.raw.clone()),
+        }
+    }
+
+    /// Parses a field as `field_type`.  If the field can't be decoded, or its type doesn't
+    /// match `field_length`, the raw bytes are kept as `FieldValue::Unknown` so the rest of
+    /// the record can still be parsed.
+    pub(crate) fn parse_or_unknown(
+        remaining: &[u8],
+        field_type: FieldDataType,
+        field_length: u16,
+        string_decoding: StringDecoding,
+    ) -> IResult<&[u8], FieldValue> {
+        let parsed = DataNumber::from_field_type_decoding(
+            remaining,
+            field_type,
+            field_length,
+            string_decoding,
+        );
+        match parsed {
+            Ok((i, value)) if remaining.len() - i.len() == field_length as usize => {
+                Ok((i, value))
+            }
+            _ if field_length == 0 => parsed,
+            _ => match take::<_, _, NomError<&[u8]>>(field_length)(remaining) {
+                Ok((i, taken)) => Ok((i, FieldValue::Unknown(taken.to_vec()))),
+                Err(_) => parsed,
+            },
+        }
+    }
+}
+
+/// A field in a data record that could not be decoded and was kept as `FieldValue::Unknown`.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct FieldWarning {
+    /// Index of the record in `data_fields`
+    pub record: usize,
+    /// Index of the field in the record
+    pub field: usize,
+}
+
+/// Collects a warning for every `FieldValue::Unknown` field in the parsed records.
+pub(crate) fn field_warnings<F>(
+    records: &[BTreeMap<usize, (F, FieldValue)>],
+) -> Vec<FieldWarning> {
+    records
+        .iter()
+        .enumerate()
+        .flat_map(|(record, fields)| {
+            fields
+                .iter()
+                .filter(|(_, (_, value))| matches!(value, FieldValue::Unknown(_)))
+                .map(move |(field, _)| FieldWarning {
+                    record,
+                    field: *field,
+                })
+        })
+        .collect()
+}
+
+/// How V9/IPFix `String` fields, such as interface names and URLs, are decoded.  Without the
+/// `string_formatting` feature they are always kept as raw bytes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum StringDecoding {
+    /// Invalid UTF-8 is replaced with `U+FFFD`
+    #[default]
+    Lossy,
+    /// Like `Lossy`, with the trailing NUL padding of fixed length fields removed
+    TrimNul,
+    /// Fields that aren't valid UTF-8 are kept as `FieldValue::Unknown`
+    Strict,
+    /// Kept as `FieldValue::Vec`
+    Raw,
+}
+
+impl StringDecoding {
+    /// Decodes `bytes`, or `None` if they aren't valid for a `Strict` decoding.
+    pub fn decode(self, bytes: &[u8]) -> Option<FieldValue> {
+        match self {
+            StringDecoding::Lossy => Some(FieldValue::String(
+                String::from_utf8_lossy(bytes).into_owned(),
+            )),
+            StringDecoding::TrimNul => {
+                let end = bytes
+                    .iter()
+                    .rposition(|b| *b != 0)
+                    .map_or(0, |last| last + 1);
+                Some(FieldValue::String(
+                    String::from_utf8_lossy(&bytes[..end]).into_owned(),
+                ))
+            }
+            StringDecoding::Strict => core::str::from_utf8(bytes)
+                .ok()
+                .map(|s| FieldValue::String(s.to_string())),
+            StringDecoding::Raw => Some(FieldValue::Vec(bytes.to_vec())),
         }
     }
 }
@@ -300,14 +608,23 @@ pub enum FieldDataType {
     String,
     SignedDataNumber,
     UnsignedDataNumber,
+    Float32,
     Float64,
+    Boolean,
     DurationSeconds,
     DurationMillis,
     DurationMicros,
     DurationNanos,
+    DateTimeSeconds,
+    DateTimeMillis,
+    /// NTP timestamp with microsecond precision
+    DateTimeMicros,
+    /// NTP timestamp with nanosecond precision
+    DateTimeNanos,
     Ip4Addr,
     Ip6Addr,
     MacAddr,
+    /// `octetArray`, kept as raw bytes
     Vec,
     ProtocolType,
     Unknown,
@@ -319,6 +636,91 @@ mod data_number_tests {
     fn it_tests_3_byte_data_number_exports() {
         use super::DataNumber;
         let data = DataNumber::parse(&[1, 246, 118], 3, false).unwrap().1;
-        assert_eq!(data.to_be_bytes(), vec![1, 246, 118]);
+        assert_eq!(data.to_be_bytes().unwrap(), vec![1, 246, 118]);
+    }
+
+    #[test]
+    fn it_errors_exporting_out_of_range_3_byte_data_number() {
+        use super::DataNumber;
+        use crate::NetflowError;
+        assert!(matches!(
+            DataNumber::U24(0x0100_0000).to_be_bytes(),
+            Err(NetflowError::InvalidFieldValue(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "string_formatting")]
+    fn it_formats_mac_addr() {
+        use super::{DataNumber, FieldDataType, FieldValue};
+        let (_, value) =
+            DataNumber::from_field_type(&[0, 1, 2, 3, 4, 5], FieldDataType::MacAddr, 6)
+                .unwrap();
+        assert_eq!(value, FieldValue::MacAddr("00:01:02:03:04:05".to_string()));
+    }
+
+    #[test]
+    #[cfg(not(feature = "string_formatting"))]
+    fn it_keeps_raw_mac_addr_bytes() {
+        use super::{DataNumber, FieldDataType, FieldValue};
+        let (_, value) =
+            DataNumber::from_field_type(&[0, 1, 2, 3, 4, 5], FieldDataType::MacAddr, 6)
+                .unwrap();
+        assert_eq!(value, FieldValue::Vec(vec![0, 1, 2, 3, 4, 5]));
+    }
+
+    #[test]
+    fn it_parses_ntp_date_times() {
+        use super::{DataNumber, FieldDataType, FieldValue};
+        use core::time::Duration;
+
+        // 2021-01-01T00:00:00.5Z, with the lower fraction bits set
+        let ntp = [227, 152, 228, 128, 128, 0, 7, 255];
+        let (_, nanos) =
+            DataNumber::from_field_type(&ntp, FieldDataType::DateTimeNanos, 8).unwrap();
+        assert_eq!(
+            nanos,
+            FieldValue::DateTimeNanos(Duration::new(1_609_459_200, 500_000_477))
+        );
+        let (_, micros) =
+            DataNumber::from_field_type(&ntp, FieldDataType::DateTimeMicros, 8).unwrap();
+        assert_eq!(
+            micros,
+            FieldValue::DateTimeMicros(Duration::new(1_609_459_200, 500_000_000))
+        );
+
+        for value in [nanos, micros] {
+            let bytes = value.to_be_bytes().unwrap();
+            let field_type = match value {
+                FieldValue::DateTimeNanos(_) => FieldDataType::DateTimeNanos,
+                _ => FieldDataType::DateTimeMicros,
+            };
+            assert_eq!(
+                DataNumber::from_field_type(&bytes, field_type, 8)
+                    .unwrap()
+                    .1,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn it_parses_float32_and_boolean_fields() {
+        use super::{DataNumber, FieldDataType, FieldValue};
+
+        let (_, value) =
+            DataNumber::from_field_type(&1.5f32.to_be_bytes(), FieldDataType::Float64, 4)
+                .unwrap();
+        assert_eq!(value, FieldValue::Float32(1.5));
+        assert_eq!(value.to_be_bytes().unwrap(), 1.5f32.to_be_bytes());
+
+        let (_, value) = DataNumber::from_field_type(&[2], FieldDataType::Boolean, 1).unwrap();
+        assert_eq!(value, FieldValue::Bool(false));
+        assert_eq!(
+            FieldValue::parse_or_unknown(&[3], FieldDataType::Boolean, 1, Default::default())
+                .unwrap()
+                .1,
+            FieldValue::Unknown(vec![3])
+        );
     }
 }
@@ -0,0 +1,128 @@
+//! Typed access to the fields of V9/IPFix data records.
+//!
+//! ```rust
+//! use netflow_parser::variable_versions::data_record::DataRecordExt;
+//! use netflow_parser::variable_versions::v9_lookup::V9Field;
+//! use netflow_parser::{NetflowPacket, NetflowParser};
+//!
+//! let packet = [
+//!     0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0, 2, 0,
+//!     1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
+//! ];
+//! let Some(NetflowPacket::V9(v9)) = NetflowParser::default().parse_bytes(&packet).pop() else {
+//!     panic!("expected a V9 packet");
+//! };
+//! let data = v9.flowsets[1].body.data.as_ref().unwrap();
+//! let record = &data.data_fields[0];
+//! assert_eq!(record.get_u64(V9Field::InBytes), Some(151126788));
+//! assert_eq!(record.get_ip(V9Field::Ipv4SrcAddr), Some("9.9.9.8".parse().unwrap()));
+//! assert_eq!(record.get_str(V9Field::InBytes), None);
+//! ```
+
+use super::application::ApplicationId;
+use super::data_number::{DataNumber, FieldValue};
+use crate::types::{Asn, Port};
+
+use alloc::collections::BTreeMap;
+use core::net::IpAddr;
+use core::time::Duration;
+
+/// Typed getters for a data record holding `(field, value)` pairs.  Each getter returns
+/// `None` if the field is missing or its value isn't of the requested type.  When a field
+/// appears more than once in a record the first occurrence is used.
+pub trait DataRecordExt<F> {
+    /// Value of `field`.
+    fn get_value(&self, field: F) -> Option<&FieldValue>;
+
+    /// Any integer value that fits in a `u64`.
+    fn get_u64(&self, field: F) -> Option<u64> {
+        match self.get_value(field)? {
+            FieldValue::DataNumber(number) => match *number {
+                DataNumber::U8(n) => Some(n.into()),
+                DataNumber::U16(n) => Some(n.into()),
+                DataNumber::U24(n) | DataNumber::U32(n) => Some(n.into()),
+                DataNumber::U64(n) => Some(n),
+                DataNumber::U128(n) => n.try_into().ok(),
+                DataNumber::I24(n) | DataNumber::I32(n) => n.try_into().ok(),
+            },
+            _ => None,
+        }
+    }
+
+    /// Any integer value that fits in a `u32`.
+    fn get_u32(&self, field: F) -> Option<u32> {
+        self.get_u64(field)?.try_into().ok()
+    }
+
+    /// Any integer value that fits in a `u16`.
+    fn get_u16(&self, field: F) -> Option<u16> {
+        self.get_u64(field)?.try_into().ok()
+    }
+
+    /// Port number, such as `L4SrcPort` or `SourceTransportPort`.
+    fn get_port(&self, field: F) -> Option<Port> {
+        self.get_u16(field).map(Port)
+    }
+
+    /// AS number, such as `SrcAs` or `BgpSourceAsNumber`.
+    fn get_asn(&self, field: F) -> Option<Asn> {
+        self.get_u32(field).map(Asn)
+    }
+
+    /// IPv4 or IPv6 address value.
+    fn get_ip(&self, field: F) -> Option<IpAddr> {
+        self.get_value(field)?.try_into().ok()
+    }
+
+    /// String or formatted MAC address value.
+    fn get_str(&self, field: F) -> Option<&str> {
+        match self.get_value(field)? {
+            FieldValue::String(s) | FieldValue::MacAddr(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Duration value, or the time since the unix epoch of a date time value such as flow
+    /// start/end times.
+    fn get_duration(&self, field: F) -> Option<Duration> {
+        match self.get_value(field)? {
+            FieldValue::Duration(duration)
+            | FieldValue::DateTimeSeconds(duration)
+            | FieldValue::DateTimeMillis(duration)
+            | FieldValue::DateTimeMicros(duration)
+            | FieldValue::DateTimeNanos(duration) => Some(*duration),
+            _ => None,
+        }
+    }
+
+    /// Raw bytes of a field parsed as bytes or kept as `FieldValue::Unknown`.
+    fn get_bytes(&self, field: F) -> Option<&[u8]> {
+        match self.get_value(field)? {
+            FieldValue::Vec(bytes) | FieldValue::Unknown(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Application id, such as IPFix `ApplicationId` or V9 `ApplicationTag`.
+    fn get_application_id(&self, field: F) -> Option<ApplicationId> {
+        ApplicationId::from_bytes(self.get_bytes(field)?)
+    }
+}
+
+/// Records in `Data::data_fields`, keyed by field position.
+impl<F: PartialEq> DataRecordExt<F> for BTreeMap<usize, (F, FieldValue)> {
+    fn get_value(&self, field: F) -> Option<&FieldValue> {
+        self.values()
+            .find(|(f, _)| *f == field)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Records flattened into field order, such as `FlowRecord::V9` and `FlowRecord::IPFix`.
+impl<F: PartialEq> DataRecordExt<F> for [(F, FieldValue)] {
+    fn get_value(&self, field: F) -> Option<&FieldValue> {
+        self.iter()
+            .find(|(f, _)| *f == field)
+            .map(|(_, value)| value)
+    }
+}
@@ -0,0 +1,108 @@
+//! Decoders for enterprise specific IPFix fields.
+//!
+//! Vendors such as Cisco (AVC), Palo Alto and Gigamon export fields under their own private
+//! enterprise number (PEN) with encodings of their own.  Without a decoder such a field is
+//! parsed as an unsigned number if its length allows, or kept as `FieldValue::Unknown`.
+//! Register an [`EnterpriseFieldDecoder`], or a closure, per `(PEN, field id)` to decode it
+//! into any `FieldValue`, including a `FieldValue::Custom`.  Custom values keep the bytes they
+//! were decoded from, which are written back when the packet is exported.
+//!
+//! ```rust
+//! use netflow_parser::variable_versions::data_number::{CustomValue, FieldValue};
+//! use netflow_parser::variable_versions::enterprise::EnterpriseFieldRegistry;
+//! use netflow_parser::NetflowParser;
+//!
+//! const CISCO: u32 = 9;
+//!
+//! let registry = EnterpriseFieldRegistry::new().with_decoder(CISCO, 12235, |bytes: &[u8]| {
+//!     Some(FieldValue::Custom(
+//!         CustomValue::String(String::from_utf8_lossy(bytes).into_owned()).into(),
+//!     ))
+//! });
+//! let parser = NetflowParser::builder()
+//!     .with_enterprise_fields(registry)
+//!     .build()
+//!     .expect("valid configuration");
+//! ```
+
+use super::data_number::FieldValue;
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Decodes the raw bytes of an enterprise specific field.
+pub trait EnterpriseFieldDecoder: Send + Sync {
+    /// Returns `None` if the bytes can't be decoded, the field is then kept as
+    /// `FieldValue::Unknown`.
+    fn decode(&self, bytes: &[u8]) -> Option<FieldValue>;
+}
+
+impl<F> EnterpriseFieldDecoder for F
+where
+    F: Fn(&[u8]) -> Option<FieldValue> + Send + Sync,
+{
+    fn decode(&self, bytes: &[u8]) -> Option<FieldValue> {
+        self(bytes)
+    }
+}
+
+/// Decoders by private enterprise number and field id.
+#[derive(Clone, Default)]
+pub struct EnterpriseFieldRegistry {
+    decoders: BTreeMap<(u32, u16), Arc<dyn EnterpriseFieldDecoder>>,
+}
+
+impl EnterpriseFieldRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes field `field_id` of `enterprise_number` with `decoder`, replacing any decoder
+    /// registered for it before.
+    pub fn register(
+        &mut self,
+        enterprise_number: u32,
+        field_id: u16,
+        decoder: impl EnterpriseFieldDecoder + 'static,
+    ) {
+        self.decoders
+            .insert((enterprise_number, field_id), Arc::new(decoder));
+    }
+
+    /// Same as [`register`](Self::register), for chaining.
+    pub fn with_decoder(
+        mut self,
+        enterprise_number: u32,
+        field_id: u16,
+        decoder: impl EnterpriseFieldDecoder + 'static,
+    ) -> Self {
+        self.register(enterprise_number, field_id, decoder);
+        self
+    }
+
+    /// Decoder registered for field `field_id` of `enterprise_number`.
+    pub fn decoder(
+        &self,
+        enterprise_number: u32,
+        field_id: u16,
+    ) -> Option<&dyn EnterpriseFieldDecoder> {
+        self.decoders
+            .get(&(enterprise_number, field_id))
+            .map(|decoder| decoder.as_ref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+}
+
+impl fmt::Debug for EnterpriseFieldRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<_> = self.decoders.keys().collect();
+        f.debug_struct("EnterpriseFieldRegistry")
+            .field("fields", &fields)
+            .finish()
+    }
+}
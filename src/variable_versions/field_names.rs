@@ -0,0 +1,166 @@
+//! Interned field names for serializing V9/IPFix records.
+//!
+//! Serializing millions of records writes the same few field names over and over.
+//! [`FieldNames`] formats each field's name once and shares it, reference counted, with every
+//! record serialized through it.  Records can also be dictionary encoded, referring to fields
+//! by id so the names are written only once, such as at the start of a file or batch.
+//!
+//! ```rust
+//! use netflow_parser::flow_records::FlowRecord;
+//! use netflow_parser::variable_versions::field_names::FieldNames;
+//! use netflow_parser::NetflowParser;
+//! use serde_json::json;
+//!
+//! let packet = [
+//!     0, 9, 0, 2, 0, 0, 9, 9, 0, 1, 2, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 16, 1, 2, 0, 2, 0,
+//!     1, 0, 4, 0, 8, 0, 4, 1, 2, 0, 12, 9, 2, 3, 4, 9, 9, 9, 8,
+//! ];
+//! let mut names = FieldNames::new();
+//! for record in NetflowParser::default().iter_flow_records(&packet) {
+//!     if let FlowRecord::V9(fields) = record {
+//!         assert!(json!(names.named(&fields)).get("InBytes").is_some());
+//!         assert_eq!(json!(names.encode(&fields))[1][0], 1);
+//!     }
+//! }
+//! assert_eq!(json!(names.dictionary()), json!(["InBytes", "Ipv4SrcAddr"]));
+//! ```
+
+use super::data_number::FieldValue;
+use crate::HashMap;
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::hash::Hash;
+
+/// Interned names of `F`, such as `V9Field` or `IPFixField`, matching their serialized
+/// names.  Ids are assigned in the order fields are first seen, starting at 0.
+#[derive(Debug, Clone)]
+pub struct FieldNames<F> {
+    ids: HashMap<F, u32>,
+    names: Vec<Arc<str>>,
+}
+
+impl<F> Default for FieldNames<F> {
+    fn default() -> Self {
+        Self {
+            ids: HashMap::default(),
+            names: Vec::new(),
+        }
+    }
+}
+
+impl<F: Copy + Eq + Hash + Debug> FieldNames<F> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Id of `field`, interning its name if it hasn't been seen yet.
+    pub fn id(&mut self, field: F) -> u32 {
+        if let Some(&id) = self.ids.get(&field) {
+            return id;
+        }
+        let id = self.names.len() as u32;
+        self.names.push(format!("{field:?}").into());
+        self.ids.insert(field, id);
+        id
+    }
+
+    /// Name of `field`, shared with every other use of it.
+    pub fn name(&mut self, field: F) -> Arc<str> {
+        let id = self.id(field);
+        Arc::clone(&self.names[id as usize])
+    }
+
+    /// Names indexed by id, the dictionary for records from [`encode`](Self::encode).
+    pub fn dictionary(&self) -> &[Arc<str>] {
+        &self.names
+    }
+
+    /// A record that serializes as a map of field name to value.
+    pub fn named<'a>(&mut self, record: &'a [(F, FieldValue)]) -> NamedRecord<'a> {
+        NamedRecord(
+            record
+                .iter()
+                .map(|(field, value)| (self.name(*field), value))
+                .collect(),
+        )
+    }
+
+    /// A record that serializes as `[id, value]` pairs, with names in the
+    /// [`dictionary`](Self::dictionary).
+    pub fn encode<'a>(&mut self, record: &'a [(F, FieldValue)]) -> EncodedRecord<'a> {
+        EncodedRecord(
+            record
+                .iter()
+                .map(|(field, value)| (self.id(*field), value))
+                .collect(),
+        )
+    }
+}
+
+/// Record from [`FieldNames::named`].  A field that appears more than once in the record is
+/// written once per occurrence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedRecord<'a>(pub Vec<(Arc<str>, &'a FieldValue)>);
+
+impl Serialize for NamedRecord<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in &self.0 {
+            map.serialize_entry(name.as_ref(), value)?;
+        }
+        map.end()
+    }
+}
+
+/// Record from [`FieldNames::encode`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EncodedRecord<'a>(pub Vec<(u32, &'a FieldValue)>);
+
+#[cfg(test)]
+mod field_names_tests {
+    use super::FieldNames;
+    use crate::variable_versions::data_number::{DataNumber, FieldValue};
+    use crate::variable_versions::ipfix_lookup::IPFixField;
+
+    use alloc::sync::Arc;
+    use serde_json::json;
+
+    #[test]
+    fn it_shares_interned_names() {
+        let mut names = FieldNames::new();
+        let a = names.name(IPFixField::SourceTransportPort);
+        let b = names.name(IPFixField::SourceTransportPort);
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(&*a, "SourceTransportPort");
+        assert_eq!(names.id(IPFixField::DestinationTransportPort), 1);
+        assert_eq!(names.dictionary().len(), 2);
+    }
+
+    #[test]
+    fn it_matches_serialized_names() {
+        let mut names = FieldNames::new();
+        let record = [
+            (
+                IPFixField::OctetDeltaCount,
+                FieldValue::DataNumber(DataNumber::U32(10)),
+            ),
+            (
+                IPFixField::PacketDeltaCount,
+                FieldValue::DataNumber(DataNumber::U32(1)),
+            ),
+        ];
+        let names_only: Vec<_> = record.iter().map(|(field, _)| json!(field)).collect();
+        assert_eq!(json!(names.named(&record)).as_object().unwrap().len(), 2);
+        assert_eq!(json!(names.dictionary()), json!(names_only));
+        assert_eq!(
+            json!(names.encode(&record)),
+            json!([[0, &record[0].1], [1, &record[1].1]])
+        );
+    }
+}
@@ -7,46 +7,284 @@
 //! - <https://www.iana.org/assignments/ipfix/ipfix.xhtml>
 
 use super::data_number::*;
+use super::enterprise::EnterpriseFieldRegistry;
+use super::template_engine::{self, announced_length, CachedTemplate, TemplateEngine};
+use super::{
+    exceed_limit, MessageError, MessageResult, ParserLimits, TemplateFieldSummary,
+    TemplateIssue, TemplateKind, TemplateReport, TemplateSummary,
+};
+use crate::digest::PacketDigest;
+use crate::filter::Filter;
 use crate::variable_versions::ipfix_lookup::*;
-use crate::{NetflowPacket, NetflowParseError, ParsedNetflow, PartialParse};
+use crate::{NetflowError, NetflowPacket, ParseMode, ParsedNetflow};
 
 use nom::bytes::complete::take;
 use nom::error::{Error as NomError, ErrorKind};
 use nom::multi::count;
+use nom::number::complete::{be_u16, be_u8};
 use nom::Err as NomErr;
 use nom::IResult;
 use nom_derive::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use Nom;
 
-use std::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::{format, vec, vec::Vec};
+use core::time::Duration;
 
 const TEMPLATE_ID: u16 = 2;
 const OPTIONS_TEMPLATE_ID: u16 = 3;
 const SET_MIN_RANGE: u16 = 255;
+/// Set ID and length
+const SET_HEADER_LENGTH: usize = 4;
+/// Length of the message header.
+const HEADER_LENGTH: usize = 16;
+/// Set in a template field's type when an enterprise number follows
+const ENTERPRISE_BIT: u16 = 0x8000;
+/// Field length of variable length information elements
+const VARIABLE_LENGTH: u16 = 65_535;
 
-type TemplateId = u16;
-type IPFixFieldPair = (IPFixField, FieldValue);
+pub type IPFixFieldPair = (IPFixField, FieldValue);
 
 pub(crate) fn parse_netflow_ipfix(
     packet: &[u8],
     parser: &mut IPFixParser,
-) -> Result<ParsedNetflow, NetflowParseError> {
-    IPFix::parse(packet, parser)
-        .map(|(remaining, ipfix)| ParsedNetflow::new(remaining, NetflowPacket::IPFix(ipfix)))
-        .map_err(|e| {
-            NetflowParseError::Partial(PartialParse {
-                version: 10,
-                error: e.to_string(),
-                remaining: packet.to_vec(),
-            })
-        })
+) -> Result<ParsedNetflow, NetflowError> {
+    let observation_domain_id = Header::parse(packet)
+        .ok()
+        .map(|(_, header)| header.observation_domain_id);
+    let message = parser.engine.begin_message(observation_domain_id);
+    let result = parse_message(packet, parser);
+    parser.engine.end_message(message);
+    let (remaining, ipfix) =
+        result.map_err(|e| MessageError::into_netflow_error(e, 10, packet))?;
+    parser.track_missing_templates(&ipfix);
+    if parser.parse_mode == ParseMode::Strict {
+        ipfix.validate()?;
+    }
+    parser.count_source(&ipfix);
+    Ok(ParsedNetflow::new(remaining, NetflowPacket::IPFix(ipfix)))
 }
 
+/// `IPFix::parse`, failing with the limit a message exceeded.
+fn parse_message<'a>(packet: &'a [u8], parser: &mut IPFixParser) -> MessageResult<'a, IPFix> {
+    let (i, header) = Header::parse(packet).map_err(NomErr::convert)?;
+    let (remaining, flowsets) = parse_sets(i, parser, header.length)?;
+    Ok((
+        remaining,
+        IPFix {
+            header,
+            flowsets,
+            received_at: None,
+            digest: None,
+        },
+    ))
+}
+
+/// Every template cached by a parser, see `TemplateEngine::export_all_templates`.
+pub type TemplateSnapshot =
+    template_engine::TemplateSnapshot<Arc<Template>, Arc<OptionsTemplate>>;
+
 #[derive(Default, Debug)]
 pub struct IPFixParser {
-    pub templates: BTreeMap<TemplateId, Template>,
-    pub options_templates: BTreeMap<TemplateId, OptionsTemplate>,
+    /// Templates and everything kept about them, scoped per header `observation_domain_id`
+    /// when `engine.scope_templates` is set
+    pub engine: TemplateEngine<Arc<Template>, Arc<OptionsTemplate>>,
+    /// How sets that fail to parse are handled
+    pub(crate) parse_mode: ParseMode,
+    /// Keep the wire bytes of every set in its `raw`
+    pub(crate) keep_raw: bool,
+    /// Only decode these fields of data records, skipping over the others by their length
+    pub(crate) projection: Option<BTreeSet<IPFixField>>,
+    /// Data records that don't match are dropped as they are decoded
+    pub(crate) filter: Option<Filter>,
+    /// Decoders for enterprise specific fields
+    pub(crate) enterprise_fields: EnterpriseFieldRegistry,
+    /// How `String` fields are decoded
+    pub(crate) string_decoding: StringDecoding,
+    /// Caps on field lengths, records and sets of a message
+    pub(crate) limits: ParserLimits,
+    /// Data records are decoded into the arena rather than their sets while it is set
+    #[cfg(feature = "perf")]
+    pub(crate) arena: Option<crate::arena::RecordArena>,
+}
+
+impl IPFixParser {
+    /// A message announcing every template cached for `observation_domain_id`, or every
+    /// shared template if templates aren't scoped, so a relay can re-announce them downstream.
+    /// Each template gets its own set, sorted by id.  The export time and sequence number are
+    /// left at zero for the caller to fill in.  Fails if the message would be longer than
+    /// 65,535 bytes.
+    pub fn templates_to_message(
+        &self,
+        observation_domain_id: u32,
+    ) -> Result<IPFix, NetflowError> {
+        let (templates, options_templates) =
+            self.engine.cached_templates(observation_domain_id);
+        let template_sets = templates.into_iter().map(|template| {
+            let body = FlowSetBody {
+                templates: Some(template.clone()),
+                ..Default::default()
+            };
+            (TEMPLATE_ID, template.record_length(), body)
+        });
+        let options_template_sets = options_templates.into_iter().map(|template| {
+            let body = FlowSetBody {
+                options_templates: Some(Arc::new(OptionsTemplate {
+                    padding: None,
+                    ..(**template).clone()
+                })),
+                ..Default::default()
+            };
+            (OPTIONS_TEMPLATE_ID, template.record_length(), body)
+        });
+
+        let mut length = HEADER_LENGTH;
+        let mut flowsets = vec![];
+        for (header_id, set_length, body) in template_sets.chain(options_template_sets) {
+            let set_length = SET_HEADER_LENGTH + set_length;
+            length += set_length;
+            flowsets.push(FlowSet {
+                header: FlowSetHeader {
+                    header_id,
+                    // Checked with the message length below
+                    length: set_length as u16,
+                },
+                body,
+            });
+        }
+        let length = announced_length(10, length)?;
+        Ok(IPFix {
+            header: Header {
+                version: 10,
+                length,
+                export_time: 0,
+                sequence_number: 0,
+                observation_domain_id,
+            },
+            flowsets,
+            received_at: None,
+            digest: None,
+        })
+    }
+
+    /// Checks every cached template, including the scoped ones, for suspicious definitions
+    /// such as zero length or duplicate fields, which would otherwise show up as data silently
+    /// parsed wrong.  Enterprise fields without a decoder in `enterprise_fields` are reported.
+    pub fn validate_templates(&self) -> TemplateReport {
+        self.engine.validate(10, Some(&self.enterprise_fields))
+    }
+
+    /// Observation domain ids messages have been parsed from, in ascending order, when
+    /// `engine.count_sources` is set.  See `engine.source_stats` for what each sent.
+    pub fn observation_domains(&self) -> impl Iterator<Item = u32> + '_ {
+        self.engine.source_stats.keys().copied()
+    }
+
+    /// Counts the records of `ipfix` for its observation domain id.
+    fn count_source(&mut self, ipfix: &IPFix) {
+        let (records, options_records) =
+            ipfix
+                .flowsets
+                .iter()
+                .fold((0, 0), |(records, options_records), flowset| {
+                    let body = &flowset.body;
+                    (
+                        records + body.data.as_ref().map_or(0, |data| data.data_fields.len()),
+                        options_records
+                            + body
+                                .options_data
+                                .as_ref()
+                                .map_or(0, |data| data.data_fields.len()),
+                    )
+                });
+        self.engine
+            .count_source(ipfix.header.observation_domain_id, records, options_records);
+    }
+
+    /// Marks the templates announced or used by data in `ipfix` as used at `tick`.
+    pub(crate) fn touch_templates(&mut self, ipfix: &IPFix, tick: u64) {
+        let used = ipfix.flowsets.iter().flat_map(|set| {
+            let body = &set.body;
+            let id = set.header.header_id;
+            body.templates
+                .as_ref()
+                .map(|t| (TemplateKind::Template, t.template_id))
+                .into_iter()
+                .chain(
+                    body.options_templates
+                        .as_ref()
+                        .map(|t| (TemplateKind::OptionsTemplate, t.template_id)),
+                )
+                .chain(body.data.as_ref().map(|_| (TemplateKind::Template, id)))
+                .chain(
+                    body.options_data
+                        .as_ref()
+                        .map(|_| (TemplateKind::OptionsTemplate, id)),
+                )
+        });
+        self.engine
+            .touch(ipfix.header.observation_domain_id, used, tick);
+    }
+
+    /// Counts data sets dropped for missing templates and clears templates received in
+    /// `ipfix`.
+    fn track_missing_templates(&mut self, ipfix: &IPFix) {
+        let sets = ipfix.flowsets.iter().flat_map(|set| {
+            let body = &set.body;
+            let templates = body.templates.iter().map(|t| t.template_id);
+            let options = body.options_templates.iter().map(|t| t.template_id);
+            let id = set.header.header_id;
+            let dropped = id > SET_MIN_RANGE && set.is_unparsed();
+            templates
+                .chain(options)
+                .map(|template_id| (template_id, false))
+                .chain(dropped.then_some((id, true)))
+        });
+        self.engine
+            .track_missing(ipfix.header.observation_domain_id, sets);
+    }
+
+    /// Keeps `bytes` as the wire bytes of `set` if `keep_raw` is set.
+    fn keep_raw(&self, set: &mut FlowSet, bytes: &[u8]) {
+        if self.keep_raw {
+            set.body.raw = Some(bytes.to_vec());
+        }
+    }
+
+    /// Checks the set at the start of `set`, the `index`th of its message, against the
+    /// limits.  Sets running past the message are checked with the bytes available.
+    fn check_limits(&self, index: usize, set: &[u8]) -> Result<(), NetflowError> {
+        let Ok((_, header)) = FlowSetHeader::parse(set) else {
+            return Ok(());
+        };
+        let body_length = usize::from(header.length)
+            .min(set.len())
+            .saturating_sub(SET_HEADER_LENGTH);
+        self.engine
+            .check_set_limits(&self.limits, index, header.header_id, body_length)
+    }
+
+    /// Checks the field count of the template or options template in the set at the start
+    /// of `set` against the limits, before its fields are parsed.
+    fn check_field_counts(&self, set: &[u8]) -> Result<(), NetflowError> {
+        let word = |at: usize| {
+            set.get(at..at + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        };
+        let (Some(set_id), Some(field_count)) = (word(0), word(SET_HEADER_LENGTH + 2)) else {
+            return Ok(());
+        };
+        let field_count = match (set_id, word(SET_HEADER_LENGTH + 4)) {
+            (TEMPLATE_ID, _) => usize::from(field_count),
+            (OPTIONS_TEMPLATE_ID, Some(scope_field_count)) => {
+                OptionsTemplate::combined_count(field_count, scope_field_count)
+            }
+            _ => return Ok(()),
+        };
+        self.limits.check_field_count(field_count)
+    }
 }
 
 #[derive(Nom, Debug, PartialEq, Clone, Serialize)]
@@ -55,8 +293,18 @@ pub struct IPFix {
     /// IPFix Header
     pub header: Header,
     /// Sets
-    #[nom(Parse = "{ |i| parse_sets(i, parser, header.length) }")]
+    #[nom(Parse = "{ |i| parse_sets(i, parser, header.length)
+        .map_err(|e| e.map(MessageError::into_nom)) }")]
     pub flowsets: Vec<FlowSet>,
+    /// Wall-clock time the packet was received, since the unix epoch, when known.  Set by
+    /// `NetflowParser::parse_bytes_received_at` or when receive times are captured.
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<Duration>,
+    /// Identity of the message, set when the parser computes packet digests.
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<PacketDigest>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Nom)]
@@ -111,78 +359,225 @@ pub struct FlowSetHeader {
     pub length: u16,
 }
 
-#[derive(Debug, PartialEq, Clone, Serialize, Nom)]
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Nom)]
 #[nom(ExtraArgs(parser: &mut IPFixParser, id: u16, length: u16))]
 pub struct FlowSetBody {
     #[nom(
         Cond = "id == TEMPLATE_ID",
-        // Save our templates
-        PostExec = "if let Some(templates) = templates.clone() { parser.templates.insert(templates.template_id, templates); }"
+        // Saves our templates, keeping the cached one
+        Parse = "{ |i| Template::parse(i)
+            .map(|(i, template)| (i, parser.engine.add_template(Arc::new(template)))) }"
     )]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub templates: Option<Template>,
+    pub templates: Option<Arc<Template>>,
     #[nom(
         Cond = "id == OPTIONS_TEMPLATE_ID",
         PreExec = "let set_length = length.checked_sub(4).unwrap_or(length);",
-        Parse = "{ |i| OptionsTemplate::parse(i, set_length) }",
-        // Save our templates
-        PostExec = "if let Some(options_templates) = options_templates.clone() {
-                      parser.options_templates.insert(options_templates.template_id, options_templates);
-                    }"
+        // Saves our templates, keeping the cached one
+        Parse = "{ |i| OptionsTemplate::parse(i, set_length)
+            .map(|(i, template)| (i, parser.engine.add_options_template(Arc::new(template)))) }"
     )]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub options_templates: Option<OptionsTemplate>,
+    pub options_templates: Option<Arc<OptionsTemplate>>,
     // Data
     #[nom(
-        Cond = "id > SET_MIN_RANGE && parser.templates.contains_key(&id)",
+        Cond = "id > SET_MIN_RANGE && parser.engine.templates.contains_key(&id)",
         Parse = "{ |i| Data::parse(i, parser, id) }"
     )]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<Data>,
     // OptionsData
     #[nom(
-        Cond = "id > SET_MIN_RANGE && parser.options_templates.contains_key(&id)",
+        Cond = "id > SET_MIN_RANGE && parser.engine.options_templates.contains_key(&id)",
         Parse = "{ |i| OptionsData::parse(i, parser, id) }"
     )]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub options_data: Option<OptionsData>,
+    /// Bytes of a set that couldn't be parsed, header included, written back on export
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unparsed_data: Option<Vec<u8>>,
+    /// Why the set could not be parsed, set when it was skipped in `ParseMode::Lenient`.  Sets
+    /// whose length doesn't fit the message get a `NetflowError::SetLengthMismatch`, and in
+    /// `ParseMode::Lenient` the bytes that are available are still parsed.
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parse_error: Option<NetflowError>,
+    /// Wire bytes of the set, header included, kept when `keep_raw` is set.
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<Vec<u8>>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Nom)]
 #[nom(ExtraArgs(parser: &mut IPFixParser, set_id: u16))]
 pub struct Data {
-    #[nom(Parse = "{ |i| parse_fields::<Template>(i, parser.templates.get(&set_id)) }")]
+    /// Id of the template that decoded the records, the set id
+    #[nom(Value = "set_id")]
+    pub template_id: u16,
+    #[nom(Parse = "{ |i| parse_data_records(i, parser, set_id) }")]
     pub data_fields: Vec<BTreeMap<usize, (IPFixField, FieldValue)>>,
+    /// Fields that could not be decoded and were kept as `FieldValue::Unknown`
+    #[nom(Value = "field_warnings(&data_fields)")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<FieldWarning>,
+    /// The template that decoded the records when parsed, shared with the parser's cache
+    #[nom(Value = "parser.engine.templates.get(&set_id).cloned()")]
+    #[serde(skip)]
+    pub template: Option<Arc<Template>>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Nom)]
 #[nom(ExtraArgs(parser: &mut IPFixParser, set_id: u16))]
 pub struct OptionsData {
+    /// Id of the options template that decoded the records, the set id
+    #[nom(Value = "set_id")]
+    pub template_id: u16,
+    /// Number of scope fields at the start of each record, from the options template
     #[nom(
-        Parse = "{ |i| parse_fields::<OptionsTemplate>(i, parser.options_templates.get(&set_id)) }"
+        Value = "parser.engine.options_templates.get(&set_id).map_or(0, |t| t.scope_field_count)"
     )]
+    pub scope_field_count: u16,
+    #[nom(Parse = "{ |i| parse_fields::<OptionsTemplate>(
+            i,
+            parser.engine.options_templates.get(&set_id).map(Arc::as_ref),
+            None,
+            None,
+            &parser.enterprise_fields,
+            parser.string_decoding,
+        ) }")]
     pub data_fields: Vec<BTreeMap<usize, (IPFixField, FieldValue)>>,
+    /// Fields that could not be decoded and were kept as `FieldValue::Unknown`
+    #[nom(Value = "field_warnings(&data_fields)")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<FieldWarning>,
+    /// The options template that decoded the records when parsed, shared with the parser's cache
+    #[nom(Value = "parser.engine.options_templates.get(&set_id).cloned()")]
+    #[serde(skip)]
+    pub template: Option<Arc<OptionsTemplate>>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Nom)]
+impl OptionsData {
+    /// Records split into their scope and options fields.
+    pub fn records(&self) -> impl Iterator<Item = OptionsRecord<'_>> {
+        self.data_fields.iter().map(|fields| OptionsRecord {
+            fields,
+            scope_field_count: usize::from(self.scope_field_count),
+        })
+    }
+}
+
+/// An options data record, whose first `scope_field_count` fields are scope fields.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptionsRecord<'a> {
+    pub fields: &'a BTreeMap<usize, IPFixFieldPair>,
+    pub scope_field_count: usize,
+}
+
+impl<'a> OptionsRecord<'a> {
+    /// Fields identifying what the record describes, such as an interface or a template.
+    pub fn scope_fields(&self) -> impl Iterator<Item = &'a IPFixFieldPair> {
+        self.fields
+            .range(..self.scope_field_count)
+            .map(|(_, field)| field)
+    }
+
+    /// Fields describing the scope.
+    pub fn options_fields(&self) -> impl Iterator<Item = &'a IPFixFieldPair> {
+        self.fields
+            .range(self.scope_field_count..)
+            .map(|(_, field)| field)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Nom)]
 #[nom(ExtraArgs(set_length: u16))]
 pub struct OptionsTemplate {
     pub template_id: u16,
     pub field_count: u16,
     pub scope_field_count: u16,
     #[nom(
-        PreExec = "let combined_count = scope_field_count as usize + 
-                       field_count.checked_sub(scope_field_count).unwrap_or(field_count) as usize;",
-        Parse = "count(|i| TemplateField::parse(i, true), combined_count)",
+        PreExec = "let combined_count = OptionsTemplate::combined_count(field_count, scope_field_count);",
+        Parse = "count(|i| TemplateField::parse(i), combined_count)",
         PostExec = "let options_remaining = set_length.checked_sub(field_count * 4).unwrap_or(set_length) > 0;"
     )]
     pub fields: Vec<TemplateField>,
     #[nom(Cond = "options_remaining && !i.is_empty()")]
-    #[serde(skip_serializing)]
+    #[serde(skip)]
     padding: Option<u16>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Nom)]
+impl OptionsTemplate {
+    /// An options template whose first `scope_field_count` fields are scope fields.
+    pub fn new(template_id: u16, scope_field_count: u16, fields: Vec<TemplateField>) -> Self {
+        Self {
+            template_id,
+            field_count: fields.len() as u16,
+            scope_field_count,
+            fields,
+            padding: None,
+        }
+    }
+
+    /// Number of field specifiers an options template declaring `field_count` fields, of which
+    /// `scope_field_count` are scope fields, is parsed with.
+    fn combined_count(field_count: u16, scope_field_count: u16) -> usize {
+        usize::from(scope_field_count)
+            + usize::from(
+                field_count
+                    .checked_sub(scope_field_count)
+                    .unwrap_or(field_count),
+            )
+    }
+
+    /// The first `scope_field_count` fields.
+    pub fn scope_fields(&self) -> &[TemplateField] {
+        &self.fields[..self.scope_len()]
+    }
+
+    /// The fields after the scope fields.
+    pub fn options_fields(&self) -> &[TemplateField] {
+        &self.fields[self.scope_len()..]
+    }
+
+    /// Whether the field at `index` is a scope field.
+    pub fn is_scope(&self, index: usize) -> bool {
+        index < self.scope_len()
+    }
+
+    fn scope_len(&self) -> usize {
+        usize::from(self.scope_field_count).min(self.fields.len())
+    }
+
+    /// Scope field count above the field count, or no scope fields at all.
+    fn scope_issue(&self) -> Option<TemplateIssue> {
+        let declared = usize::from(self.scope_field_count);
+        let described = usize::from(self.field_count);
+        if declared == 0 {
+            Some(TemplateIssue::NoScopeFields)
+        } else if declared > described {
+            Some(TemplateIssue::ScopeMismatch {
+                declared,
+                described,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Layout of the options template, cached under `scope`.
+    pub fn summary(&self, scope: Option<u32>) -> TemplateSummary {
+        summarize(
+            TemplateKind::OptionsTemplate,
+            self.template_id,
+            scope,
+            &self.fields,
+            usize::from(self.scope_field_count),
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Nom)]
 pub struct Template {
     pub template_id: u16,
     pub field_count: u16,
@@ -196,7 +591,7 @@ fn parse_template_fields(i: &[u8], count: u16) -> IResult<&[u8], Vec<TemplateFie
     let mut remaining = i;
 
     for _ in 0..count {
-        let (i, field) = TemplateField::parse(remaining, false)?;
+        let (i, field) = TemplateField::parse(remaining)?;
         result.push(field);
         remaining = i;
     }
@@ -204,19 +599,17 @@ fn parse_template_fields(i: &[u8], count: u16) -> IResult<&[u8], Vec<TemplateFie
     Ok((remaining, result))
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Nom)]
-#[nom(ExtraArgs(options_template: bool))]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Nom)]
 pub struct TemplateField {
+    /// Information element id, without the enterprise bit
     pub field_type_number: u16,
     #[nom(Value(IPFixField::from(field_type_number)))]
     pub field_type: IPFixField,
     pub field_length: u16,
     #[nom(
-        Cond = "options_template && field_type_number > 32767",
-        PostExec = "let field_type_number = if options_template {
-                      field_type_number.overflowing_sub(32768).0
-                    } else { field_type_number };",
-        PostExec = "let field_type = if options_template && enterprise_number.is_some() {
+        Cond = "field_type_number & ENTERPRISE_BIT != 0",
+        PostExec = "let field_type_number = field_type_number & !ENTERPRISE_BIT;",
+        PostExec = "let field_type = if enterprise_number.is_some() {
                         IPFixField::Enterprise
                     } else { field_type };"
     )]
@@ -224,9 +617,104 @@ pub struct TemplateField {
     pub enterprise_number: Option<u32>,
 }
 
+impl TemplateField {
+    /// Bytes taken by the field specifier, longer when it has an enterprise number.
+    pub(crate) fn descriptor_length(&self) -> usize {
+        match self.enterprise_number {
+            Some(_) => 8,
+            None => 4,
+        }
+    }
+
+    fn summary(&self, scope: bool) -> TemplateFieldSummary {
+        TemplateFieldSummary {
+            name: format!("{:?}", self.field_type),
+            field_type_number: self.field_type_number,
+            field_length: self.field_length,
+            enterprise_number: self.enterprise_number,
+            scope,
+        }
+    }
+
+    fn write_be_bytes(&self, result: &mut Vec<u8>) {
+        match self.enterprise_number {
+            Some(enterprise) => {
+                result.extend_from_slice(
+                    &(self.field_type_number | ENTERPRISE_BIT).to_be_bytes(),
+                );
+                result.extend_from_slice(&self.field_length.to_be_bytes());
+                result.extend_from_slice(&enterprise.to_be_bytes());
+            }
+            None => {
+                result.extend_from_slice(&self.field_type_number.to_be_bytes());
+                result.extend_from_slice(&self.field_length.to_be_bytes());
+            }
+        }
+    }
+}
+
 // Common trait for both templates.  Mainly for fetching fields.
-trait CommonTemplate {
+trait CommonTemplate: Sized {
     fn get_fields(&self) -> &Vec<TemplateField>;
+
+    /// Estimated bytes held by the template.
+    fn memory_bytes(&self) -> usize {
+        core::mem::size_of::<Self>()
+            + self.get_fields().capacity() * core::mem::size_of::<TemplateField>()
+    }
+
+    /// Bytes taken by the field specifiers.
+    fn descriptors_length(&self) -> usize {
+        self.get_fields()
+            .iter()
+            .map(TemplateField::descriptor_length)
+            .sum()
+    }
+
+    fn field_lengths(&self) -> Vec<u16> {
+        self.get_fields()
+            .iter()
+            .map(|field| field.field_length)
+            .collect()
+    }
+}
+
+/// Layout of `fields`, the first `scope_field_count` of them scope fields.
+fn summarize(
+    kind: TemplateKind,
+    template_id: u16,
+    scope: Option<u32>,
+    fields: &[TemplateField],
+    scope_field_count: usize,
+) -> TemplateSummary {
+    let record_length = fields
+        .iter()
+        .map(|field| (field.field_length != VARIABLE_LENGTH).then_some(field.field_length))
+        .try_fold(0, |total, length| Some(total + usize::from(length?)));
+    TemplateSummary {
+        kind,
+        template_id,
+        scope,
+        record_length,
+        fields: fields
+            .iter()
+            .enumerate()
+            .map(|(index, field)| field.summary(index < scope_field_count))
+            .collect(),
+    }
+}
+
+impl Template {
+    /// Layout of the template, cached under `scope`.
+    pub fn summary(&self, scope: Option<u32>) -> TemplateSummary {
+        summarize(
+            TemplateKind::Template,
+            self.template_id,
+            scope,
+            &self.fields,
+            0,
+        )
+    }
 }
 
 impl CommonTemplate for Template {
@@ -241,12 +729,64 @@ impl CommonTemplate for OptionsTemplate {
     }
 }
 
+impl CachedTemplate for Template {
+    const KIND: TemplateKind = TemplateKind::Template;
+
+    fn template_id(&self) -> u16 {
+        self.template_id
+    }
+
+    fn summary(&self, scope: Option<u32>) -> TemplateSummary {
+        Template::summary(self, scope)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        CommonTemplate::memory_bytes(self)
+    }
+
+    fn record_length(&self) -> usize {
+        4 + CommonTemplate::descriptors_length(self)
+    }
+
+    fn field_lengths(&self) -> Vec<u16> {
+        CommonTemplate::field_lengths(self)
+    }
+}
+
+impl CachedTemplate for OptionsTemplate {
+    const KIND: TemplateKind = TemplateKind::OptionsTemplate;
+
+    fn template_id(&self) -> u16 {
+        self.template_id
+    }
+
+    fn summary(&self, scope: Option<u32>) -> TemplateSummary {
+        OptionsTemplate::summary(self, scope)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        CommonTemplate::memory_bytes(self)
+    }
+
+    fn issue(&self) -> Option<TemplateIssue> {
+        self.scope_issue()
+    }
+
+    fn record_length(&self) -> usize {
+        6 + CommonTemplate::descriptors_length(self)
+    }
+
+    fn field_lengths(&self) -> Vec<u16> {
+        CommonTemplate::field_lengths(self)
+    }
+}
+
 // Custom parse set function to take only length provided by header.
 fn parse_sets<'a>(
     i: &'a [u8],
     parser: &mut IPFixParser,
     length: u16,
-) -> IResult<&'a [u8], Vec<FlowSet>> {
+) -> MessageResult<'a, Vec<FlowSet>> {
     let length = length.checked_sub(16).unwrap_or(length);
     let (_, taken) = take(length)(i)?;
 
@@ -255,14 +795,110 @@ fn parse_sets<'a>(
     let mut remaining = taken;
 
     while !remaining.is_empty() {
-        let (i, set) = FlowSet::parse(remaining, parser)?;
-        sets.push(set);
-        remaining = i;
+        if let Err(error) = parser.check_limits(sets.len(), remaining) {
+            return exceed_limit(error);
+        }
+        if let Ok((_, header)) = FlowSetHeader::parse(remaining) {
+            if let Some(mismatch) = set_length_mismatch(&header, remaining.len()) {
+                // Without a usable length there is no telling where the next set starts.
+                let mut set = parse_mismatched_set(remaining, parser, header, mismatch);
+                if set.is_unparsed() {
+                    set.body.unparsed_data = Some(remaining.to_vec());
+                }
+                parser.keep_raw(&mut set, remaining);
+                sets.push(set);
+                remaining = &[];
+                break;
+            }
+        }
+        let parsed = match parser.check_field_counts(remaining) {
+            Ok(()) => FlowSet::parse(remaining, parser).map_err(NomErr::convert),
+            Err(error) => exceed_limit(error),
+        };
+        match parsed {
+            Ok((i, mut set)) => {
+                let taken = &remaining[..remaining.len() - i.len()];
+                if set.is_unparsed() {
+                    set.body.unparsed_data = Some(taken.to_vec());
+                }
+                parser.keep_raw(&mut set, taken);
+                sets.push(set);
+                remaining = i;
+            }
+            Err(e) if parser.parse_mode == ParseMode::Lenient => {
+                // Skip over the bad set using its header length if we can.
+                let header = match FlowSetHeader::parse(remaining) {
+                    Ok((_, header))
+                        if header.length >= 4 && header.length as usize <= remaining.len() =>
+                    {
+                        header
+                    }
+                    _ => return Err(e),
+                };
+                let (taken, rest) = remaining.split_at(header.length as usize);
+                let mut set = FlowSet {
+                    header,
+                    body: FlowSetBody {
+                        unparsed_data: Some(taken.to_vec()),
+                        parse_error: Some(MessageError::into_netflow_error(e, 10, taken)),
+                        ..Default::default()
+                    },
+                };
+                parser.keep_raw(&mut set, taken);
+                sets.push(set);
+                remaining = rest;
+            }
+            Err(e) => return Err(e),
+        }
     }
 
     Ok((remaining, sets))
 }
 
+/// Returns a `NetflowError::SetLengthMismatch` when the set's length is shorter than its header
+/// or runs past the `available` bytes of the message.
+fn set_length_mismatch(header: &FlowSetHeader, available: usize) -> Option<NetflowError> {
+    let declared = usize::from(header.length);
+    (declared < SET_HEADER_LENGTH || declared > available).then_some(
+        NetflowError::SetLengthMismatch {
+            set_id: header.header_id,
+            declared: header.length,
+            available,
+        },
+    )
+}
+
+/// Keeps a set whose length doesn't fit the message.  In `ParseMode::Lenient` a set that runs
+/// past the end is truncated to the bytes that are available and parsed.
+fn parse_mismatched_set(
+    i: &[u8],
+    parser: &mut IPFixParser,
+    header: FlowSetHeader,
+    mismatch: NetflowError,
+) -> FlowSet {
+    let truncated = usize::from(header.length) > i.len();
+    let lenient = parser.parse_mode == ParseMode::Lenient;
+    let body = if truncated && lenient && parser.check_field_counts(i).is_ok() {
+        parse_set_body(
+            &i[SET_HEADER_LENGTH..],
+            parser,
+            i.len() as u16,
+            header.header_id,
+        )
+        .map(|(_, body)| body)
+        .unwrap_or_default()
+    } else {
+        FlowSetBody::default()
+    };
+    FlowSet {
+        header,
+        body: FlowSetBody {
+            parse_error: Some(mismatch),
+            ..body
+        },
+    }
+}
+
 // Custom parse set body function to take only length provided by set header.
 fn parse_set_body<'a>(
     i: &'a [u8],
@@ -280,9 +916,15 @@ fn parse_set_body<'a>(
 /// Takes a byte stream and a cached template.
 /// Fields get matched to static types.
 /// Returns BTree of IPFix Types & Fields or IResult Error.
+/// Fields outside `projection` are skipped over by their length and left out, and records
+/// `filter` doesn't match are dropped.
 fn parse_fields<'a, T: CommonTemplate>(
     i: &'a [u8],
     template: Option<&T>,
+    projection: Option<&BTreeSet<IPFixField>>,
+    filter: Option<&Filter>,
+    enterprise_fields: &EnterpriseFieldRegistry,
+    string_decoding: StringDecoding,
 ) -> IResult<&'a [u8], Vec<BTreeMap<usize, IPFixFieldPair>>> {
     // If no fields there are no fields to parse, return an error.
     let template_fields = template
@@ -290,127 +932,394 @@ fn parse_fields<'a, T: CommonTemplate>(
         .ok_or_else(|| NomErr::Error(NomError::new(i, ErrorKind::Fail)))?
         .get_fields();
 
-    let total_size = template_fields
-        .iter()
-        .map(|m| m.field_length as usize)
-        .sum::<usize>();
-
-    if total_size == 0 {
+    let min_length = min_record_length(template_fields);
+    if min_length == 0 {
         return Ok((&[], vec![]));
     }
 
-    let record_count: usize = i.len() / total_size;
-    let mut fields = vec![];
+    let mut fields = Vec::with_capacity(i.len() / min_length);
     let mut remaining = i;
 
-    // Iter through template fields and push them to a vec.  If we encouter any zero length fields we return an error.
-    for _ in 0..record_count {
+    // Anything shorter than a record left at the end is padding
+    while remaining.len() >= min_length {
         let mut data_field = BTreeMap::new();
-        for (c, template_field) in template_fields.iter().enumerate() {
-            let (i, field_value) = parse_field(remaining, template_field)?;
-            if i.len() == remaining.len() {
-                return Err(NomErr::Error(NomError::new(remaining, ErrorKind::Fail)));
-            }
-            remaining = i;
-            data_field.insert(c, (template_field.field_type, field_value));
+        remaining = parse_record(
+            remaining,
+            template_fields,
+            projection,
+            enterprise_fields,
+            string_decoding,
+            |c, pair| {
+                data_field.insert(c, pair);
+            },
+        )?
+        .0;
+        if filter.is_none_or(|filter| filter.matches(&data_field)) {
+            fields.push(data_field);
         }
-        fields.push(data_field);
     }
 
     Ok((&[], fields))
 }
 
-fn parse_field<'a>(
+/// Decodes the data records of a set, into the parser's arena when it has one.
+fn parse_data_records<'a>(
+    input: &'a [u8],
+    parser: &mut IPFixParser,
+    set_id: u16,
+) -> IResult<&'a [u8], Vec<BTreeMap<usize, IPFixFieldPair>>> {
+    let template = parser.engine.templates.get(&set_id).map(Arc::as_ref);
+    let (projection, filter) = (parser.projection.as_ref(), parser.filter.as_ref());
+    #[cfg(feature = "perf")]
+    if let Some(arena) = parser.arena.as_mut() {
+        let template_fields = template
+            .filter(|t| !t.get_fields().is_empty())
+            .ok_or_else(|| NomErr::Error(NomError::new(input, ErrorKind::Fail)))?
+            .get_fields();
+        let min_length = min_record_length(template_fields);
+        if min_length == 0 {
+            return Ok((&[], Vec::new()));
+        }
+        let mark = arena.ipfix_mark();
+        let mut remaining = input;
+        while remaining.len() >= min_length {
+            let start = arena.ipfix_fields().len();
+            let decoded = parse_record(
+                remaining,
+                template_fields,
+                projection,
+                &parser.enterprise_fields,
+                parser.string_decoding,
+                |_, pair| arena.ipfix_fields().push(pair),
+            );
+            match decoded {
+                Ok((i, ())) => remaining = i,
+                Err(e) => {
+                    // The set fails as a whole, as it does without the arena
+                    arena.ipfix_rollback(mark);
+                    return Err(e);
+                }
+            }
+            let keep =
+                filter.is_none_or(|filter| filter.matches(&&arena.ipfix_fields()[start..]));
+            arena.end_ipfix_record(start, keep);
+        }
+        return Ok((&[], Vec::new()));
+    }
+    parse_fields(
+        input,
+        template,
+        projection,
+        filter,
+        &parser.enterprise_fields,
+        parser.string_decoding,
+    )
+}
+
+/// Length of the shortest record of `template_fields`, with variable length fields taking
+/// only the byte of their length prefix.
+fn min_record_length(template_fields: &[TemplateField]) -> usize {
+    template_fields
+        .iter()
+        .map(|field| match field.field_length {
+            VARIABLE_LENGTH => 1,
+            length => usize::from(length),
+        })
+        .sum()
+}
+
+/// Length of a variable length field's value, from its one byte prefix or, when that is 255,
+/// the two bytes after it (RFC 7011 section 7).
+fn variable_length(i: &[u8]) -> IResult<&[u8], u16> {
+    match be_u8(i)? {
+        (i, 255) => be_u16(i),
+        (i, length) => Ok((i, u16::from(length))),
+    }
+}
+
+/// Decodes one record, handing `push` each decoded field with its index in the template.
+/// Fails on any field that consumes nothing.
+fn parse_record<'a>(
+    mut remaining: &'a [u8],
+    template_fields: &[TemplateField],
+    projection: Option<&BTreeSet<IPFixField>>,
+    enterprise_fields: &EnterpriseFieldRegistry,
+    string_decoding: StringDecoding,
+    mut push: impl FnMut(usize, IPFixFieldPair),
+) -> IResult<&'a [u8], ()> {
+    for (c, template_field) in template_fields.iter().enumerate() {
+        let variable;
+        let (value, template_field) = match template_field.field_length {
+            VARIABLE_LENGTH => {
+                let (value, field_length) = variable_length(remaining)?;
+                variable = TemplateField {
+                    field_length,
+                    ..template_field.clone()
+                };
+                (value, &variable)
+            }
+            _ => (remaining, template_field),
+        };
+        let projected = projection.is_none_or(|p| p.contains(&template_field.field_type));
+        let (i, field_value) = if projected {
+            let (i, field_value) =
+                parse_field(value, template_field, enterprise_fields, string_decoding)?;
+            (i, Some(field_value))
+        } else {
+            (take(template_field.field_length)(value)?.0, None)
+        };
+        if i.len() == remaining.len() {
+            return Err(NomErr::Error(NomError::new(remaining, ErrorKind::Fail)));
+        }
+        remaining = i;
+        if let Some(field_value) = field_value {
+            push(c, (template_field.field_type, field_value));
+        }
+    }
+    Ok((remaining, ()))
+}
+
+pub(crate) fn parse_field<'a>(
     i: &'a [u8],
     template_field: &TemplateField,
+    enterprise_fields: &EnterpriseFieldRegistry,
+    string_decoding: StringDecoding,
 ) -> IResult<&'a [u8], FieldValue> {
-    let has_enterprise_number = template_field.enterprise_number.is_some();
-
-    if has_enterprise_number {
-        // Simplified parsing when `enterprise_number` is present
-        parse_enterprise_field(i)
-    } else {
+    match template_field.enterprise_number {
+        Some(enterprise_number) => {
+            parse_enterprise_field(i, template_field, enterprise_number, enterprise_fields)
+        }
         // Parse field based on its type and length
-        DataNumber::from_field_type(
+        None => FieldValue::parse_or_unknown(
             i,
             template_field.field_type.into(),
             template_field.field_length,
-        )
+            string_decoding,
+        ),
     }
 }
 
-fn parse_enterprise_field(i: &[u8]) -> IResult<&[u8], FieldValue> {
-    let (remaining, data_number) = DataNumber::parse(i, 4, false)?;
-    Ok((remaining, FieldValue::DataNumber(data_number)))
+/// Decodes an enterprise specific field with its registered decoder, or as an unsigned number
+/// if there is none.
+fn parse_enterprise_field<'a>(
+    i: &'a [u8],
+    template_field: &TemplateField,
+    enterprise_number: u32,
+    enterprise_fields: &EnterpriseFieldRegistry,
+) -> IResult<&'a [u8], FieldValue> {
+    match enterprise_fields.decoder(enterprise_number, template_field.field_type_number) {
+        Some(decoder) => {
+            let (remaining, taken) = take(template_field.field_length)(i)?;
+            let value = match decoder.decode(taken) {
+                Some(FieldValue::Custom(mut custom)) => {
+                    custom.raw = taken.to_vec();
+                    FieldValue::Custom(custom)
+                }
+                Some(value) => value,
+                None => FieldValue::Unknown(taken.to_vec()),
+            };
+            Ok((remaining, value))
+        }
+        None => FieldValue::parse_or_unknown(
+            i,
+            FieldDataType::UnsignedDataNumber,
+            template_field.field_length,
+            StringDecoding::default(),
+        ),
+    }
+}
+
+impl FlowSet {
+    fn is_unparsed(&self) -> bool {
+        self.body.templates.is_none()
+            && self.body.options_templates.is_none()
+            && self.body.data.is_none()
+            && self.body.options_data.is_none()
+    }
 }
 
 impl IPFix {
-    /// Convert the IPFix to a `Vec<u8>` of bytes in big-endian order for exporting
-    pub fn to_be_bytes(&self) -> Vec<u8> {
-        let mut result = vec![];
+    /// Whether the packet announces a template or options template.
+    pub fn has_templates(&self) -> bool {
+        self.flowsets.iter().any(|flowset| {
+            flowset.body.templates.is_some() || flowset.body.options_templates.is_some()
+        })
+    }
+
+    /// Whether a data flowset was left unparsed because its template hadn't been seen.
+    pub fn has_missing_templates(&self) -> bool {
+        self.flowsets
+            .iter()
+            .any(|flowset| flowset.header.header_id > SET_MIN_RANGE && flowset.is_unparsed())
+    }
 
-        result.extend_from_slice(&self.header.version.to_be_bytes());
-        result.extend_from_slice(&self.header.length.to_be_bytes());
-        result.extend_from_slice(&self.header.export_time.to_be_bytes());
-        result.extend_from_slice(&self.header.sequence_number.to_be_bytes());
-        result.extend_from_slice(&self.header.observation_domain_id.to_be_bytes());
+    /// Checks for anomalies that are tolerated in `ParseMode::Lenient`.
+    fn validate(&self) -> Result<(), NetflowError> {
+        for flowset in self.flowsets.iter() {
+            if let Some(mismatch @ NetflowError::SetLengthMismatch { .. }) =
+                &flowset.body.parse_error
+            {
+                return Err(mismatch.clone());
+            }
+            if flowset.header.header_id > SET_MIN_RANGE && flowset.is_unparsed() {
+                return Err(NetflowError::MissingTemplate {
+                    version: 10,
+                    template_id: flowset.header.header_id,
+                });
+            }
+        }
+        Ok(())
+    }
 
+    /// Convert the IPFix to a `Vec<u8>` of bytes in big-endian order for exporting
+    pub fn to_be_bytes(&self) -> Result<Vec<u8>, NetflowError> {
+        let mut result = vec![];
+        write_header(&self.header, &mut result);
         for flow in &self.flowsets {
             result.extend_from_slice(&flow.header.header_id.to_be_bytes());
             result.extend_from_slice(&flow.header.length.to_be_bytes());
+            write_set_body(&flow.body, false, &mut result)?;
+        }
+        Ok(result)
+    }
 
-            let mut result_flowset = vec![];
+    /// Like `to_be_bytes`, but the message length, set lengths and template field counts are
+    /// recomputed from the contents, for messages whose sets or records were changed.  Padding
+    /// is left out, as are sets with nothing parsed.
+    pub fn to_be_bytes_recalculated(&self) -> Result<Vec<u8>, NetflowError> {
+        let mut sets = vec![];
+        for flow in self.flowsets.iter().filter(|flow| !flow.is_unparsed()) {
+            let mut body = vec![];
+            write_set_body(&flow.body, true, &mut body)?;
+            let length = SET_HEADER_LENGTH + body.len();
+            let length = u16::try_from(length).map_err(|_| NetflowError::InvalidLength {
+                version: 10,
+                declared: length,
+                available: usize::from(u16::MAX),
+            })?;
+            sets.extend_from_slice(&flow.header.header_id.to_be_bytes());
+            sets.extend_from_slice(&length.to_be_bytes());
+            sets.append(&mut body);
+        }
+        let length = HEADER_LENGTH + sets.len();
+        let header = Header {
+            length: u16::try_from(length).map_err(|_| NetflowError::InvalidLength {
+                version: 10,
+                declared: length,
+                available: usize::from(u16::MAX),
+            })?,
+            ..self.header
+        };
+        let mut result = vec![];
+        write_header(&header, &mut result);
+        result.append(&mut sets);
+        Ok(result)
+    }
+}
 
-            if let Some(template) = &flow.body.templates {
-                result_flowset.extend_from_slice(&template.template_id.to_be_bytes());
-                result_flowset.extend_from_slice(&template.field_count.to_be_bytes());
+fn write_header(header: &Header, result: &mut Vec<u8>) {
+    result.extend_from_slice(&header.version.to_be_bytes());
+    result.extend_from_slice(&header.length.to_be_bytes());
+    result.extend_from_slice(&header.export_time.to_be_bytes());
+    result.extend_from_slice(&header.sequence_number.to_be_bytes());
+    result.extend_from_slice(&header.observation_domain_id.to_be_bytes());
+}
 
-                for field in template.fields.iter() {
-                    result_flowset.extend_from_slice(&field.field_type_number.to_be_bytes());
-                    result_flowset.extend_from_slice(&field.field_length.to_be_bytes());
-                    if let Some(enterprise) = field.enterprise_number {
-                        result_flowset.extend_from_slice(&enterprise.to_be_bytes());
+/// Writes data records, prefixing the values of `template_fields` that are variable length
+/// with their length.
+fn write_records(
+    records: &[BTreeMap<usize, IPFixFieldPair>],
+    template_fields: Option<&[TemplateField]>,
+    result: &mut Vec<u8>,
+) -> Result<(), NetflowError> {
+    for record in records {
+        for (index, (_, value)) in record {
+            let bytes = value.to_be_bytes()?;
+            let variable = template_fields
+                .and_then(|fields| fields.get(*index))
+                .is_some_and(|field| field.field_length == VARIABLE_LENGTH);
+            if variable {
+                match u8::try_from(bytes.len()) {
+                    Ok(length) if length < 255 => result.push(length),
+                    _ => {
+                        let length = u16::try_from(bytes.len()).map_err(|_| {
+                            NetflowError::InvalidFieldValue(format!(
+                                "variable length value of {} bytes is longer than 65535",
+                                bytes.len()
+                            ))
+                        })?;
+                        result.push(255);
+                        result.extend_from_slice(&length.to_be_bytes());
                     }
                 }
             }
+            result.extend_from_slice(&bytes);
+        }
+    }
+    Ok(())
+}
 
-            if let Some(options_template) = &flow.body.options_templates {
-                result_flowset.extend_from_slice(&options_template.template_id.to_be_bytes());
-                result_flowset.extend_from_slice(&options_template.field_count.to_be_bytes());
-                result_flowset
-                    .extend_from_slice(&options_template.scope_field_count.to_be_bytes());
-
-                for field in options_template.fields.iter() {
-                    result_flowset.extend_from_slice(&field.field_type_number.to_be_bytes());
-                    result_flowset.extend_from_slice(&field.field_length.to_be_bytes());
-                    if let Some(enterprise) = field.enterprise_number {
-                        result_flowset.extend_from_slice(&enterprise.to_be_bytes());
-                    }
-                }
-                if let Some(padding) = &options_template.padding {
-                    result_flowset.extend_from_slice(&padding.to_be_bytes());
-                }
-            }
+/// A field count recomputed for `to_be_bytes_recalculated`.  Fails if it is more than 65,535.
+fn recalculated_length(length: usize) -> Result<u16, NetflowError> {
+    u16::try_from(length).map_err(|_| NetflowError::InvalidLength {
+        version: 10,
+        declared: length,
+        available: usize::from(u16::MAX),
+    })
+}
 
-            if let Some(data) = &flow.body.data {
-                for item in data.data_fields.iter() {
-                    for (_, (_, v)) in item.iter() {
-                        result_flowset.extend_from_slice(&v.to_be_bytes());
-                    }
-                }
-            }
+/// Writes the records of a set.  With `recalculate`, template field counts are taken from their
+/// fields instead of what was parsed, and options template padding is dropped.
+fn write_set_body(
+    body: &FlowSetBody,
+    recalculate: bool,
+    result: &mut Vec<u8>,
+) -> Result<(), NetflowError> {
+    if let Some(template) = &body.templates {
+        let field_count = if recalculate {
+            recalculated_length(template.fields.len())?
+        } else {
+            template.field_count
+        };
+        result.extend_from_slice(&template.template_id.to_be_bytes());
+        result.extend_from_slice(&field_count.to_be_bytes());
 
-            if let Some(data) = &flow.body.options_data {
-                for item in data.data_fields.iter() {
-                    for (_, (_, v)) in item.iter() {
-                        result_flowset.extend_from_slice(&v.to_be_bytes());
-                    }
-                }
-            }
+        for field in template.fields.iter() {
+            field.write_be_bytes(result);
+        }
+    }
+
+    if let Some(options_template) = &body.options_templates {
+        let field_count = if recalculate {
+            recalculated_length(options_template.fields.len())?
+        } else {
+            options_template.field_count
+        };
+        result.extend_from_slice(&options_template.template_id.to_be_bytes());
+        result.extend_from_slice(&field_count.to_be_bytes());
+        result.extend_from_slice(&options_template.scope_field_count.to_be_bytes());
 
-            result.append(&mut result_flowset);
+        for field in options_template.fields.iter() {
+            field.write_be_bytes(result);
         }
+        if let Some(padding) = options_template.padding.filter(|_| !recalculate) {
+            result.extend_from_slice(&padding.to_be_bytes());
+        }
+    }
+
+    if let Some(data) = &body.data {
+        let fields = data.template.as_deref().map(|t| t.get_fields().as_slice());
+        write_records(&data.data_fields, fields, result)?;
+    }
 
-        result
+    if let Some(data) = &body.options_data {
+        let fields = data.template.as_deref().map(|t| t.get_fields().as_slice());
+        write_records(&data.data_fields, fields, result)?;
     }
+
+    if let Some(unparsed_data) = &body.unparsed_data {
+        result.extend_from_slice(unparsed_data.get(SET_HEADER_LENGTH..).unwrap_or_default());
+    }
+
+    Ok(())
 }
@@ -3,11 +3,13 @@
 use super::data_number::*;
 
 use nom_derive::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 /// IANA IPFix Fields
 #[repr(u16)]
-#[derive(Debug, Hash, PartialEq, Eq, Clone, Ord, PartialOrd, Copy, Serialize, Nom)]
+#[derive(
+    Debug, Hash, PartialEq, Eq, Clone, Ord, PartialOrd, Copy, Serialize, Deserialize, Nom,
+)]
 pub enum IPFixField {
     Reserved = 0,
     OctetDeltaCount = 1,
@@ -578,7 +580,7 @@ impl From<IPFixField> for FieldDataType {
             92 => FieldDataType::UnsignedDataNumber,
             93 => FieldDataType::UnsignedDataNumber,
             94 => FieldDataType::String,
-            95 => FieldDataType::String,
+            95 => FieldDataType::Vec,
             96 => FieldDataType::String,
             98 => FieldDataType::UnsignedDataNumber,
             99 => FieldDataType::UnsignedDataNumber,
@@ -609,17 +611,17 @@ impl From<IPFixField> for FieldDataType {
             147 => FieldDataType::String,
             148 => FieldDataType::UnsignedDataNumber,
             149 => FieldDataType::UnsignedDataNumber,
-            150 => FieldDataType::DurationSeconds,
-            151 => FieldDataType::DurationSeconds,
-            152 => FieldDataType::DurationMillis,
-            153 => FieldDataType::DurationMillis,
-            154 => FieldDataType::DurationMicros,
-            155 => FieldDataType::DurationMicros,
-            156 => FieldDataType::DurationNanos,
-            157 => FieldDataType::DurationNanos,
+            150 => FieldDataType::DateTimeSeconds,
+            151 => FieldDataType::DateTimeSeconds,
+            152 => FieldDataType::DateTimeMillis,
+            153 => FieldDataType::DateTimeMillis,
+            154 => FieldDataType::DateTimeMicros,
+            155 => FieldDataType::DateTimeMicros,
+            156 => FieldDataType::DateTimeNanos,
+            157 => FieldDataType::DateTimeNanos,
             158 => FieldDataType::UnsignedDataNumber,
             159 => FieldDataType::UnsignedDataNumber,
-            160 => FieldDataType::DurationMillis,
+            160 => FieldDataType::DateTimeMillis,
             161 => FieldDataType::UnsignedDataNumber,
             162 => FieldDataType::UnsignedDataNumber,
             163 => FieldDataType::UnsignedDataNumber,
@@ -669,7 +671,7 @@ impl From<IPFixField> for FieldDataType {
             207 => FieldDataType::UnsignedDataNumber,
             208 => FieldDataType::UnsignedDataNumber,
             209 => FieldDataType::UnsignedDataNumber,
-            210 => FieldDataType::String,
+            210 => FieldDataType::Vec,
             211 => FieldDataType::Ip4Addr,
             212 => FieldDataType::Ip6Addr,
             213 => FieldDataType::UnsignedDataNumber,
@@ -717,25 +719,25 @@ impl From<IPFixField> for FieldDataType {
             255 => FieldDataType::UnsignedDataNumber,
             256 => FieldDataType::UnsignedDataNumber,
             257 => FieldDataType::UnsignedDataNumber,
-            258 => FieldDataType::DurationMillis,
+            258 => FieldDataType::DateTimeMillis,
             259 => FieldDataType::UnsignedDataNumber,
-            260 => FieldDataType::DurationSeconds,
-            261 => FieldDataType::DurationSeconds,
+            260 => FieldDataType::DateTimeSeconds,
+            261 => FieldDataType::DateTimeSeconds,
             262 => FieldDataType::String,
             263 => FieldDataType::UnsignedDataNumber,
-            264 => FieldDataType::DurationSeconds,
-            265 => FieldDataType::DurationSeconds,
+            264 => FieldDataType::DateTimeSeconds,
+            265 => FieldDataType::DateTimeSeconds,
             266 => FieldDataType::String,
             267 => FieldDataType::UnsignedDataNumber,
-            268 => FieldDataType::DurationMicros,
-            269 => FieldDataType::DurationMillis,
-            270 => FieldDataType::DurationNanos,
-            271 => FieldDataType::DurationMicros,
-            272 => FieldDataType::DurationMillis,
-            273 => FieldDataType::DurationNanos,
+            268 => FieldDataType::DateTimeMicros,
+            269 => FieldDataType::DateTimeMillis,
+            270 => FieldDataType::DateTimeNanos,
+            271 => FieldDataType::DateTimeMicros,
+            272 => FieldDataType::DateTimeMillis,
+            273 => FieldDataType::DateTimeNanos,
             274 => FieldDataType::String,
             275 => FieldDataType::String,
-            276 => FieldDataType::UnsignedDataNumber,
+            276 => FieldDataType::Boolean,
             277 => FieldDataType::UnsignedDataNumber,
             278 => FieldDataType::UnsignedDataNumber,
             279 => FieldDataType::UnsignedDataNumber,
@@ -772,19 +774,19 @@ impl From<IPFixField> for FieldDataType {
             310 => FieldDataType::UnsignedDataNumber,
             311 => FieldDataType::Float64,
             312 => FieldDataType::UnsignedDataNumber,
-            313 => FieldDataType::String,
-            314 => FieldDataType::String,
-            315 => FieldDataType::String,
-            316 => FieldDataType::String,
-            317 => FieldDataType::String,
+            313 => FieldDataType::Vec,
+            314 => FieldDataType::Vec,
+            315 => FieldDataType::Vec,
+            316 => FieldDataType::Vec,
+            317 => FieldDataType::Vec,
             318 => FieldDataType::UnsignedDataNumber,
             319 => FieldDataType::UnsignedDataNumber,
             320 => FieldDataType::Float64,
             321 => FieldDataType::Float64,
-            322 => FieldDataType::DurationSeconds,
-            323 => FieldDataType::DurationMillis,
-            324 => FieldDataType::DurationMicros,
-            325 => FieldDataType::DurationNanos,
+            322 => FieldDataType::DateTimeSeconds,
+            323 => FieldDataType::DateTimeMillis,
+            324 => FieldDataType::DateTimeMicros,
+            325 => FieldDataType::DateTimeNanos,
             326 => FieldDataType::UnsignedDataNumber,
             327 => FieldDataType::UnsignedDataNumber,
             328 => FieldDataType::UnsignedDataNumber,
@@ -818,8 +820,8 @@ impl From<IPFixField> for FieldDataType {
             356 => FieldDataType::UnsignedDataNumber,
             357 => FieldDataType::UnsignedDataNumber,
             358 => FieldDataType::UnsignedDataNumber,
-            359 => FieldDataType::DurationMillis,
-            360 => FieldDataType::DurationMillis,
+            359 => FieldDataType::DateTimeMillis,
+            360 => FieldDataType::DateTimeMillis,
             361 => FieldDataType::UnsignedDataNumber,
             362 => FieldDataType::UnsignedDataNumber,
             363 => FieldDataType::UnsignedDataNumber,
@@ -894,7 +896,7 @@ impl From<IPFixField> for FieldDataType {
             434 => FieldDataType::SignedDataNumber,
             435 => FieldDataType::String,
             436 => FieldDataType::String,
-            437 => FieldDataType::String,
+            437 => FieldDataType::Vec,
             438 => FieldDataType::Ip4Addr,
             439 => FieldDataType::UnsignedDataNumber,
             440 => FieldDataType::UnsignedDataNumber,
@@ -1,5 +1,839 @@
+pub mod application;
 pub mod data_number;
+pub mod data_record;
+pub mod enterprise;
+pub mod field_names;
 pub mod ipfix;
 pub mod ipfix_lookup;
+pub mod template_engine;
 pub mod v9;
 pub mod v9_lookup;
+
+use crate::clock::Clock;
+#[cfg(feature = "std")]
+use crate::clock::SystemClock;
+use crate::variable_versions::enterprise::EnterpriseFieldRegistry;
+use crate::{HashMap, NetflowError};
+
+use nom::error::{Error as NomError, ErrorKind, ParseError};
+use nom::Err as NomErr;
+use nom::IResult;
+use serde::Serialize;
+
+use alloc::collections::btree_map::{BTreeMap, Entry};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash::Hash;
+use core::time::Duration;
+
+/// Which template cache a [`TemplateEvent`] refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum TemplateKind {
+    Template,
+    OptionsTemplate,
+}
+
+/// Template cache changes recorded by the V9 and IPFix parsers.  `scope` is the V9
+/// `source_id` or IPFix `observation_domain_id` when templates are scoped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum TemplateEvent {
+    /// A template id was announced for the first time.
+    Added {
+        kind: TemplateKind,
+        template_id: u16,
+        scope: Option<u32>,
+    },
+    /// A template id was announced again with a different definition and replaced the cached one.
+    Redefined {
+        kind: TemplateKind,
+        template_id: u16,
+        scope: Option<u32>,
+    },
+    /// A template was removed from the cache to stay within the memory budget.
+    Evicted {
+        kind: TemplateKind,
+        template_id: u16,
+        scope: Option<u32>,
+    },
+    /// A scoped template was removed with its source after the source was idle for longer
+    /// than the `SourceTracker` ttl.
+    Expired {
+        kind: TemplateKind,
+        template_id: u16,
+        scope: Option<u32>,
+    },
+}
+
+impl TemplateEvent {
+    pub(crate) fn set_scope(&mut self, new_scope: u32) {
+        match self {
+            TemplateEvent::Added { scope, .. }
+            | TemplateEvent::Redefined { scope, .. }
+            | TemplateEvent::Evicted { scope, .. }
+            | TemplateEvent::Expired { scope, .. } => *scope = Some(new_scope),
+        }
+    }
+
+    /// Calls the matching `TemplateObserver` method for this event.
+    pub fn notify(&self, observer: &mut dyn TemplateObserver) {
+        match *self {
+            TemplateEvent::Added {
+                kind,
+                template_id,
+                scope,
+            } => observer.on_added(kind, template_id, scope),
+            TemplateEvent::Redefined {
+                kind,
+                template_id,
+                scope,
+            } => observer.on_replaced(kind, template_id, scope),
+            TemplateEvent::Evicted {
+                kind,
+                template_id,
+                scope,
+            } => observer.on_evicted(kind, template_id, scope),
+            TemplateEvent::Expired {
+                kind,
+                template_id,
+                scope,
+            } => observer.on_expired(kind, template_id, scope),
+        }
+    }
+}
+
+/// Running totals of template cache changes, kept whether or not events are recorded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TemplateStats {
+    /// Template ids announced for the first time
+    pub added: u64,
+    /// Template ids redefined with a different layout
+    pub redefined: u64,
+    /// Templates evicted to stay within the memory budget
+    pub evicted: u64,
+    /// Templates dropped with their idle source
+    pub expired: u64,
+}
+
+impl TemplateStats {
+    pub(crate) fn count(&mut self, event: &TemplateEvent) {
+        match event {
+            TemplateEvent::Added { .. } => self.added += 1,
+            TemplateEvent::Redefined { .. } => self.redefined += 1,
+            TemplateEvent::Evicted { .. } => self.evicted += 1,
+            TemplateEvent::Expired { .. } => self.expired += 1,
+        }
+    }
+}
+
+/// Traffic of a V9 source id or IPFix observation domain id, counted whether or not templates
+/// are scoped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SourceStats {
+    /// Messages parsed
+    pub packets: u64,
+    /// Data records parsed
+    pub records: u64,
+    /// Options data records parsed
+    pub options_records: u64,
+}
+
+impl SourceStats {
+    pub(crate) fn count(&mut self, records: usize, options_records: usize) {
+        self.packets += 1;
+        self.records += records as u64;
+        self.options_records += options_records as u64;
+    }
+}
+
+/// Layout of a cached template, for displaying what an exporter is sending.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TemplateSummary {
+    pub kind: TemplateKind,
+    pub template_id: u16,
+    /// V9 `source_id` or IPFix `observation_domain_id` of a scoped template
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<u32>,
+    /// Bytes taken by each record, `None` if a field is variable length
+    pub record_length: Option<usize>,
+    /// Fields in record order, scope fields first
+    pub fields: Vec<TemplateFieldSummary>,
+}
+
+/// A field of a [`TemplateSummary`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TemplateFieldSummary {
+    /// Name of the field type as serialized, such as `Ipv4SrcAddr`
+    pub name: String,
+    pub field_type_number: u16,
+    pub field_length: u16,
+    /// Private enterprise number of an IPFix enterprise field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enterprise_number: Option<u32>,
+    /// Whether it is a scope field of an options template
+    pub scope: bool,
+}
+
+impl TemplateSummary {
+    /// Issues found in the fields of the template.  Enterprise fields are checked against
+    /// `enterprise_fields` when given.
+    pub(crate) fn lint(
+        &self,
+        enterprise_fields: Option<&EnterpriseFieldRegistry>,
+    ) -> Vec<TemplateIssue> {
+        let mut issues = Vec::new();
+        if self.record_length == Some(0) {
+            issues.push(TemplateIssue::EmptyRecord);
+        }
+        let mut seen = BTreeMap::new();
+        for (index, field) in self.fields.iter().enumerate() {
+            if field.field_length == 0 {
+                issues.push(TemplateIssue::ZeroLengthField { field: index });
+            }
+            let key = (
+                field.scope,
+                field.field_type_number,
+                field.enterprise_number,
+            );
+            match seen.entry(key) {
+                Entry::Occupied(first) => issues.push(TemplateIssue::DuplicateField {
+                    field: index,
+                    first: *first.get(),
+                }),
+                Entry::Vacant(entry) => {
+                    entry.insert(index);
+                }
+            }
+            if let (Some(registry), Some(enterprise_number)) =
+                (enterprise_fields, field.enterprise_number)
+            {
+                if registry
+                    .decoder(enterprise_number, field.field_type_number)
+                    .is_none()
+                {
+                    issues.push(TemplateIssue::UnknownEnterpriseField {
+                        field: index,
+                        enterprise_number,
+                    });
+                }
+            }
+        }
+        issues
+    }
+}
+
+/// A suspicious definition in a cached template, found by `validate_templates`.  `field` is
+/// the index of a field in [`TemplateSummary::fields`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum TemplateIssue {
+    /// The field is zero bytes long
+    ZeroLengthField { field: usize },
+    /// The field repeats the type of field `first`
+    DuplicateField { field: usize, first: usize },
+    /// Records take no bytes, so data using the template can't be parsed
+    EmptyRecord,
+    /// The scope of an options template doesn't match its field descriptors.  For V9 the
+    /// scope length in bytes against the bytes of whole scope field descriptors, for IPFix
+    /// the scope field count against the field count.
+    ScopeMismatch { declared: usize, described: usize },
+    /// An options template without scope fields
+    NoScopeFields,
+    /// An IPFix enterprise field no decoder is registered for, kept as raw bytes
+    UnknownEnterpriseField {
+        field: usize,
+        enterprise_number: u32,
+    },
+}
+
+/// An issue found in a cached template.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TemplateFinding {
+    /// 9 for V9, 10 for IPFix
+    pub version: u16,
+    pub kind: TemplateKind,
+    pub template_id: u16,
+    /// V9 `source_id` or IPFix `observation_domain_id` of a scoped template
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<u32>,
+    pub issue: TemplateIssue,
+}
+
+/// Outcome of `validate_templates`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct TemplateReport {
+    /// Templates and options templates checked, including scoped ones
+    pub templates: usize,
+    pub findings: Vec<TemplateFinding>,
+}
+
+impl TemplateReport {
+    /// No issue was found.
+    pub fn is_ok(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Records a template checked and the issues found in it.
+    pub(crate) fn add(
+        &mut self,
+        version: u16,
+        summary: &TemplateSummary,
+        issues: Vec<TemplateIssue>,
+    ) {
+        self.templates += 1;
+        self.findings
+            .extend(issues.into_iter().map(|issue| TemplateFinding {
+                version,
+                kind: summary.kind,
+                template_id: summary.template_id,
+                scope: summary.scope,
+                issue,
+            }));
+    }
+}
+
+impl core::ops::Add for TemplateReport {
+    type Output = TemplateReport;
+
+    fn add(mut self, other: TemplateReport) -> TemplateReport {
+        self.templates += other.templates;
+        self.findings.extend(other.findings);
+        self
+    }
+}
+
+/// Callbacks for template lifecycle events, registered on the `engine` of `V9Parser` or
+/// `IPFixParser` through `template_observer`.  Observers are called once a packet has been
+/// parsed.  All methods default to doing nothing.
+pub trait TemplateObserver: fmt::Debug + Send {
+    /// A template id was announced for the first time.
+    fn on_added(&mut self, _kind: TemplateKind, _template_id: u16, _scope: Option<u32>) {}
+    /// A template id was redefined with a different layout.
+    fn on_replaced(&mut self, _kind: TemplateKind, _template_id: u16, _scope: Option<u32>) {}
+    /// A template was removed from a cache to make room for others.
+    fn on_evicted(&mut self, _kind: TemplateKind, _template_id: u16, _scope: Option<u32>) {}
+    /// A template was removed from a cache because it was not refreshed in time.
+    fn on_expired(&mut self, _kind: TemplateKind, _template_id: u16, _scope: Option<u32>) {}
+    /// Data for a template that isn't cached kept being dropped for longer than the
+    /// `MissingTemplateTracker` threshold.  Called again every threshold while it stays missing.
+    fn on_missing_template(&mut self, _missing: &MissingTemplate) {}
+}
+
+/// A template that data flowsets were dropped for because it isn't cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MissingTemplate {
+    /// V9 `source_id` or IPFix `observation_domain_id` of the dropped data
+    pub source: u32,
+    pub template_id: u16,
+    /// Flowsets dropped since the template went missing
+    pub dropped: u64,
+    /// Time since the first dropped flowset
+    pub missing_for: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct MissingState {
+    first_drop: Duration,
+    last_alert: Option<Duration>,
+    dropped: u64,
+}
+
+/// Tracks data flowsets dropped for missing templates, so integrators can ask exporters to
+/// resend templates out of band.  Once data for a template id has been dropped more than once
+/// over at least `threshold`, `TemplateObserver::on_missing_template` is called.  Receiving the
+/// template clears it.
+#[derive(Debug, Clone)]
+pub struct MissingTemplateTracker {
+    threshold: Duration,
+    missing: HashMap<(u32, u16), MissingState>,
+    clock: Arc<dyn Clock>,
+}
+
+impl MissingTemplateTracker {
+    /// Missing templates tracked before the oldest ones are forgotten.
+    const MAX_TRACKED: usize = 4096;
+
+    #[cfg(feature = "std")]
+    pub fn new(threshold: Duration) -> Self {
+        Self::with_clock(threshold, Arc::new(SystemClock::default()))
+    }
+
+    /// Tracks drops using time from `clock`.
+    pub fn with_clock(threshold: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            threshold,
+            missing: HashMap::new(),
+            clock,
+        }
+    }
+
+    /// Templates data is currently being dropped for.
+    pub fn missing(&self) -> Vec<MissingTemplate> {
+        let now = self.clock.now();
+        let mut missing: Vec<MissingTemplate> = self
+            .missing
+            .iter()
+            .map(|(&(source, template_id), state)| MissingTemplate {
+                source,
+                template_id,
+                dropped: state.dropped,
+                missing_for: now.saturating_sub(state.first_drop),
+            })
+            .collect();
+        missing.sort_by_key(|m| (m.source, m.template_id));
+        missing
+    }
+
+    pub(crate) fn memory_bytes(&self) -> usize {
+        self.missing.len() * core::mem::size_of::<((u32, u16), MissingState)>()
+    }
+
+    /// Counts a dropped flowset, returning the alert to raise if one is due.
+    pub(crate) fn record_drop(
+        &mut self,
+        source: u32,
+        template_id: u16,
+    ) -> Option<MissingTemplate> {
+        let now = self.clock.now();
+        if self.missing.len() >= Self::MAX_TRACKED
+            && !self.missing.contains_key(&(source, template_id))
+        {
+            let oldest = self
+                .missing
+                .iter()
+                .min_by_key(|(_, state)| state.first_drop)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.missing.remove(&oldest);
+            }
+        }
+        let state = self
+            .missing
+            .entry((source, template_id))
+            .or_insert(MissingState {
+                first_drop: now,
+                last_alert: None,
+                dropped: 0,
+            });
+        state.dropped += 1;
+        let missing_for = now.saturating_sub(state.first_drop);
+        let alert_due = state
+            .last_alert
+            .is_none_or(|last| now.saturating_sub(last) >= self.threshold);
+        if state.dropped < 2 || missing_for < self.threshold || !alert_due {
+            return None;
+        }
+        state.last_alert = Some(now);
+        Some(MissingTemplate {
+            source,
+            template_id,
+            dropped: state.dropped,
+            missing_for,
+        })
+    }
+
+    /// The template was received.
+    pub(crate) fn resolve(&mut self, source: u32, template_id: u16) {
+        self.missing.remove(&(source, template_id));
+    }
+}
+
+/// Limits how many new or changed templates each V9 `source_id` / IPFix
+/// `observation_domain_id` may announce per time window.  Announcements over the limit are
+/// ignored and counted in `rejected()`, so a hostile exporter can't thrash the template caches.
+#[derive(Debug, Clone)]
+pub struct TemplateRateLimit {
+    max_inserts: u32,
+    window: Duration,
+    windows: HashMap<u32, (Duration, u32)>,
+    source: u32,
+    rejected: u64,
+    clock: Arc<dyn Clock>,
+}
+
+impl TemplateRateLimit {
+    /// Sources tracked before windows that have ended are pruned.
+    const MAX_TRACKED_SOURCES: usize = 1024;
+
+    /// Allows up to `max_inserts` template inserts per source every `window`.
+    #[cfg(feature = "std")]
+    pub fn new(max_inserts: u32, window: Duration) -> Self {
+        Self::with_clock(max_inserts, window, Arc::new(SystemClock::default()))
+    }
+
+    /// Measures windows using time from `clock`.
+    pub fn with_clock(max_inserts: u32, window: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            max_inserts,
+            window,
+            windows: HashMap::new(),
+            source: 0,
+            rejected: 0,
+            clock,
+        }
+    }
+
+    /// Number of template announcements rejected so far.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// Sets the source the following inserts are counted against.
+    pub(crate) fn set_source(&mut self, source: u32) {
+        self.source = source;
+    }
+
+    /// Counts an insert for the current source, returns false if it is over the limit.
+    pub(crate) fn allow(&mut self) -> bool {
+        let now = self.clock.now();
+        if self.windows.len() >= Self::MAX_TRACKED_SOURCES {
+            let window = self.window;
+            self.windows
+                .retain(|_, (start, _)| now.saturating_sub(*start) < window);
+        }
+        let (start, count) = self.windows.entry(self.source).or_insert((now, 0));
+        if now.saturating_sub(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.max_inserts {
+            self.rejected += 1;
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+/// Tracks when each V9 `source_id` / IPFix `observation_domain_id` with scoped templates was
+/// last seen, so the template caches of exporters that went away don't pile up.  When a new
+/// source shows up, sources idle for longer than `ttl` are evicted, and then the least recently
+/// seen ones while more than `max_sources` are tracked.
+///
+/// Other per exporter state, such as the parsers of a `Pipeline`, is tracked the same way by
+/// keying the tracker on the exporter's address.
+#[derive(Debug, Clone)]
+pub struct SourceTracker<K = u32> {
+    ttl: Option<Duration>,
+    max_sources: Option<usize>,
+    last_seen: HashMap<K, Duration>,
+    evicted: u64,
+    clock: Arc<dyn Clock>,
+}
+
+impl<K: Copy + Eq + Hash> SourceTracker<K> {
+    #[cfg(feature = "std")]
+    pub fn new(ttl: Option<Duration>, max_sources: Option<usize>) -> Self {
+        Self::with_clock(ttl, max_sources, Arc::new(SystemClock::default()))
+    }
+
+    /// Measures idle time using time from `clock`.
+    pub fn with_clock(
+        ttl: Option<Duration>,
+        max_sources: Option<usize>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            ttl,
+            max_sources,
+            last_seen: HashMap::new(),
+            evicted: 0,
+            clock,
+        }
+    }
+
+    /// Number of sources evicted so far.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// Time `source` was last seen at, by the tracker's clock.
+    pub fn last_seen(&self, source: K) -> Option<Duration> {
+        self.last_seen.get(&source).copied()
+    }
+
+    /// Number of sources tracked.
+    pub fn len(&self) -> usize {
+        self.last_seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_seen.is_empty()
+    }
+
+    /// Marks `source` as seen now.  If it is new, returns the sources that were idle for
+    /// longer than the ttl and the least recently seen ones evicted to make room.
+    pub(crate) fn see(&mut self, source: K) -> (Vec<K>, Vec<K>) {
+        let now = self.clock.now();
+        if self.last_seen.insert(source, now).is_some() {
+            return (Vec::new(), Vec::new());
+        }
+        let idle = self.idle();
+        let mut evicted = Vec::new();
+        if let Some(max_sources) = self.max_sources {
+            while self.last_seen.len() > max_sources {
+                let Some(oldest) = self
+                    .last_seen
+                    .iter()
+                    .filter(|(other, _)| **other != source)
+                    .min_by_key(|(_, seen)| **seen)
+                    .map(|(other, _)| *other)
+                else {
+                    break;
+                };
+                self.last_seen.remove(&oldest);
+                self.evicted += 1;
+                evicted.push(oldest);
+            }
+        }
+        (idle, evicted)
+    }
+
+    /// Forgets and returns the sources idle for longer than the ttl.
+    pub(crate) fn idle(&mut self) -> Vec<K> {
+        let Some(ttl) = self.ttl else {
+            return Vec::new();
+        };
+        let now = self.clock.now();
+        let idle: Vec<K> = self
+            .last_seen
+            .iter()
+            .filter(|(_, seen)| now.saturating_sub(**seen) > ttl)
+            .map(|(source, _)| *source)
+            .collect();
+        for source in idle.iter() {
+            self.last_seen.remove(source);
+        }
+        self.evicted += idle.len() as u64;
+        idle
+    }
+}
+
+/// Which of the [`ParserLimits`] a message exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LimitKind {
+    FieldLength,
+    RecordsPerSet,
+    SetsPerMessage,
+    FieldCount,
+}
+
+/// Caps on what a single V9 or IPFix message may declare, so crafted packets claiming huge
+/// fields or record counts are rejected before they are decoded.  Unset limits aren't
+/// enforced.  A message over a limit fails to parse with `NetflowError::LimitExceeded`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ParserLimits {
+    /// Longest field the template of a data flowset may declare, in bytes
+    pub max_field_length: Option<u16>,
+    /// Most records a data flowset may hold
+    pub max_records_per_set: Option<usize>,
+    /// Most flowsets a message may hold
+    pub max_sets_per_message: Option<usize>,
+    /// Most fields a template or options template may declare
+    pub max_field_count: Option<u16>,
+}
+
+impl ParserLimits {
+    /// Checks the flowset at `index` in its message before it is parsed.  `field_lengths` are
+    /// those of its template's fields, empty if it isn't a data flowset with a cached
+    /// template, and `body_length` is its length without the flowset header.
+    pub(crate) fn check_set(
+        &self,
+        index: usize,
+        field_lengths: impl Iterator<Item = u16>,
+        body_length: usize,
+    ) -> Result<(), NetflowError> {
+        let exceeded = |limit, value, max| NetflowError::LimitExceeded { limit, value, max };
+        if let Some(max) = self.max_sets_per_message.filter(|max| index >= *max) {
+            return Err(exceeded(LimitKind::SetsPerMessage, index + 1, max));
+        }
+        let (mut longest, mut record_length) = (0, 0);
+        for length in field_lengths {
+            longest = longest.max(length);
+            record_length += usize::from(length);
+        }
+        if let Some(max) = self.max_field_length.filter(|max| longest > *max) {
+            return Err(exceeded(
+                LimitKind::FieldLength,
+                usize::from(longest),
+                usize::from(max),
+            ));
+        }
+        let records = body_length.checked_div(record_length).unwrap_or(0);
+        if let Some(max) = self.max_records_per_set.filter(|max| records > *max) {
+            return Err(exceeded(LimitKind::RecordsPerSet, records, max));
+        }
+        Ok(())
+    }
+
+    /// Checks the `field_count` a template declares before its fields are parsed.
+    pub(crate) fn check_field_count(&self, field_count: usize) -> Result<(), NetflowError> {
+        match self.max_field_count {
+            Some(max) if field_count > usize::from(max) => Err(NetflowError::LimitExceeded {
+                limit: LimitKind::FieldCount,
+                value: field_count,
+                max: usize::from(max),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// nom error of the V9 and IPFix message parsers.  A message over one of the [`ParserLimits`]
+/// fails with the limit it exceeded rather than a nom error kind.
+#[derive(Debug)]
+pub(crate) enum MessageError<'a> {
+    Nom(NomError<&'a [u8]>),
+    Limit(NetflowError),
+}
+
+pub(crate) type MessageResult<'a, T> = IResult<&'a [u8], T, MessageError<'a>>;
+
+impl<'a> ParseError<&'a [u8]> for MessageError<'a> {
+    fn from_error_kind(input: &'a [u8], kind: ErrorKind) -> Self {
+        MessageError::Nom(NomError::new(input, kind))
+    }
+
+    fn append(_: &'a [u8], _: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<'a> From<NomError<&'a [u8]>> for MessageError<'a> {
+    fn from(error: NomError<&'a [u8]>) -> Self {
+        MessageError::Nom(error)
+    }
+}
+
+impl<'a> MessageError<'a> {
+    /// The error of a `version` message failing to parse from `packet`.
+    pub(crate) fn into_netflow_error(
+        error: NomErr<MessageError<'a>>,
+        version: u16,
+        packet: &[u8],
+    ) -> NetflowError {
+        match error {
+            NomErr::Error(MessageError::Limit(error))
+            | NomErr::Failure(MessageError::Limit(error)) => error,
+            error => NetflowError::from_nom(version, packet, error.map(MessageError::into_nom)),
+        }
+    }
+
+    /// The nom error of the derived parsers, which have no room for the limit.
+    pub(crate) fn into_nom(self) -> NomError<&'a [u8]> {
+        match self {
+            MessageError::Nom(error) => error,
+            MessageError::Limit(_) => NomError::new(&[], ErrorKind::TooLarge),
+        }
+    }
+}
+
+/// Fails the message being parsed with `error`, a limit it exceeded.
+pub(crate) fn exceed_limit<'a, T>(error: NetflowError) -> MessageResult<'a, T> {
+    Err(NomErr::Failure(MessageError::Limit(error)))
+}
+
+/// Estimated memory held by a parser, see `NetflowParser::memory_usage`.  Sizes count the
+/// structs and their heap allocations but not allocator or hash table overhead, so they are a
+/// lower bound useful for sizing rather than an exact figure.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MemoryStats {
+    /// Templates and options templates cached, including scoped ones
+    pub templates: usize,
+    /// Bytes held by cached templates and their usage tracking
+    pub template_bytes: usize,
+    /// Bytes held by template events not yet drained, per source counts and missing templates
+    /// being tracked.  Not counted towards the memory budget, as they aren't evicted.
+    pub pending_bytes: usize,
+}
+
+impl MemoryStats {
+    pub fn total_bytes(&self) -> usize {
+        self.template_bytes + self.pending_bytes
+    }
+}
+
+impl core::ops::Add for MemoryStats {
+    type Output = MemoryStats;
+
+    fn add(self, other: MemoryStats) -> MemoryStats {
+        MemoryStats {
+            templates: self.templates + other.templates,
+            template_bytes: self.template_bytes + other.template_bytes,
+            pending_bytes: self.pending_bytes + other.pending_bytes,
+        }
+    }
+}
+
+/// Caps the estimated memory of a `NetflowParser`, see
+/// `NetflowParserBuilder::with_max_memory`.  Once a message leaves the parser with more than
+/// `max_bytes` of templates, the least recently used templates are evicted until they fit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget {
+    pub max_bytes: usize,
+    /// V9 and IPFix messages parsed, the clock templates are last used by
+    tick: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(max_bytes: usize) -> Self {
+        Self { max_bytes, tick: 0 }
+    }
+
+    pub(crate) fn tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+pub(crate) type UsageKey = (TemplateKind, Option<u32>, u16);
+
+/// When each cached template was last announced or used by data, to find the least recently
+/// used one.  `scope` is the V9 `source_id` or IPFix `observation_domain_id` of scoped
+/// templates.
+#[derive(Debug, Default, Clone)]
+pub struct TemplateUsage {
+    last_used: HashMap<UsageKey, u64>,
+}
+
+impl TemplateUsage {
+    pub(crate) fn touch(&mut self, kind: TemplateKind, scope: Option<u32>, id: u16, tick: u64) {
+        self.last_used.insert((kind, scope, id), tick);
+    }
+
+    pub(crate) fn forget(&mut self, kind: TemplateKind, scope: Option<u32>, id: u16) {
+        self.last_used.remove(&(kind, scope, id));
+    }
+
+    /// The least recently used of the templates of `kind` identified by `cached`, with when
+    /// it was last used.  Templates that were never used, such as imported ones, come first.
+    pub(crate) fn oldest(
+        &self,
+        kind: TemplateKind,
+        cached: impl Iterator<Item = (Option<u32>, u16)>,
+    ) -> Option<(u64, UsageKey)> {
+        cached
+            .map(|(scope, id)| {
+                let key = (kind, scope, id);
+                (self.last_used.get(&key).copied().unwrap_or(0), key)
+            })
+            .min_by_key(|(tick, _)| *tick)
+    }
+
+    pub(crate) fn memory_bytes(&self) -> usize {
+        self.last_used.len() * core::mem::size_of::<(UsageKey, u64)>()
+    }
+}
+
+/// Number and estimated bytes of `templates`, sized by `size`, counting their cache key.
+pub(crate) fn cache_memory<'a, T: 'a>(
+    templates: impl Iterator<Item = &'a T>,
+    size: fn(&T) -> usize,
+) -> (usize, usize) {
+    templates.fold((0, 0), |(count, bytes), template| {
+        (
+            count + 1,
+            bytes + core::mem::size_of::<u16>() + size(template),
+        )
+    })
+}
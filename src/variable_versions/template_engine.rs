@@ -0,0 +1,798 @@
+//! # Template Engine
+//!
+//! Template caching shared by V9 and IPFix.  A [`TemplateEngine`] keeps the templates and
+//! options templates a parser has seen, scoped per source when asked to, and everything kept
+//! about them: template events and their observer, the rate limit, missing template alerts,
+//! least recently used eviction, idle sources and validation.  The protocol parsers only parse
+//! their own templates and data and hand the templates to the engine.
+//!
+//! The source of a message is its V9 `source_id` or its IPFix `observation_domain_id`.
+//!
+//! The parsers cache templates as `Arc`s, shared with the template flowsets of the packets
+//! they were parsed from, so a template refreshed in every packet is kept once.
+
+use super::enterprise::EnterpriseFieldRegistry;
+use super::ParserLimits;
+use super::{
+    cache_memory, MemoryStats, MissingTemplateTracker, SourceStats, SourceTracker,
+    TemplateEvent, TemplateIssue, TemplateKind, TemplateObserver, TemplateRateLimit,
+    TemplateReport, TemplateStats, TemplateSummary, TemplateUsage, UsageKey,
+};
+use crate::{HashMap, NetflowError};
+
+use serde::{Deserialize, Serialize};
+
+use alloc::collections::BTreeMap;
+#[cfg(feature = "replay")]
+use alloc::collections::BTreeSet;
+use alloc::sync::Arc;
+use alloc::{boxed::Box, vec, vec::Vec};
+
+type TemplateId = u16;
+
+/// A template or options template cached by a [`TemplateEngine`].
+pub trait CachedTemplate: Clone + PartialEq {
+    /// Kind of the template in events and summaries
+    const KIND: TemplateKind;
+
+    fn template_id(&self) -> u16;
+
+    /// Layout of the template, cached under `scope`.
+    fn summary(&self, scope: Option<u32>) -> TemplateSummary;
+
+    /// Estimated bytes held by the template.
+    fn memory_bytes(&self) -> usize;
+
+    /// Issue of the definition its summary doesn't show, such as a scope that doesn't add up.
+    fn issue(&self) -> Option<TemplateIssue> {
+        None
+    }
+
+    /// Bytes the template takes as a record of a template set, when it is announced.
+    fn record_length(&self) -> usize;
+
+    /// Lengths of the fields of its data records, scope fields first.
+    fn field_lengths(&self) -> Vec<u16>;
+}
+
+impl<C: CachedTemplate> CachedTemplate for Arc<C> {
+    const KIND: TemplateKind = C::KIND;
+
+    fn template_id(&self) -> u16 {
+        C::template_id(self)
+    }
+
+    fn summary(&self, scope: Option<u32>) -> TemplateSummary {
+        C::summary(self, scope)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        C::memory_bytes(self)
+    }
+
+    fn issue(&self) -> Option<TemplateIssue> {
+        C::issue(self)
+    }
+
+    fn record_length(&self) -> usize {
+        C::record_length(self)
+    }
+
+    fn field_lengths(&self) -> Vec<u16> {
+        C::field_lengths(self)
+    }
+}
+
+/// Length of a template set or message of `length` bytes, re-announcing templates.  Fails if
+/// it is longer than 65,535 bytes.
+pub(crate) fn announced_length(version: u16, length: usize) -> Result<u16, NetflowError> {
+    u16::try_from(length).map_err(|_| NetflowError::InvalidLength {
+        version,
+        declared: length,
+        available: usize::from(u16::MAX),
+    })
+}
+
+/// Every template cached by a parser, see `export_all_templates`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateSnapshot<T, O> {
+    pub templates: HashMap<TemplateId, T>,
+    pub options_templates: HashMap<TemplateId, O>,
+    /// Templates per source
+    pub scoped_templates: HashMap<u32, HashMap<TemplateId, T>>,
+    /// Options templates per source
+    pub scoped_options_templates: HashMap<u32, HashMap<TemplateId, O>>,
+}
+
+impl<T, O> Default for TemplateSnapshot<T, O> {
+    fn default() -> Self {
+        Self {
+            templates: HashMap::new(),
+            options_templates: HashMap::new(),
+            scoped_templates: HashMap::new(),
+            scoped_options_templates: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone + PartialEq, O: Clone + PartialEq> TemplateSnapshot<T, O> {
+    /// Adds the templates of `other`, replacing ones with the same id and scope.
+    pub fn extend(&mut self, other: TemplateSnapshot<T, O>) {
+        self.templates.extend(other.templates);
+        self.options_templates.extend(other.options_templates);
+        extend_scopes(&mut self.scoped_templates, other.scoped_templates);
+        extend_scopes(
+            &mut self.scoped_options_templates,
+            other.scoped_options_templates,
+        );
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+            && self.options_templates.is_empty()
+            && self.scoped_templates.is_empty()
+            && self.scoped_options_templates.is_empty()
+    }
+
+    /// Templates of `newer` that this snapshot doesn't have, or has with a different
+    /// definition.  Templates that are no longer in `newer` aren't reported.
+    pub fn diff(&self, newer: &TemplateSnapshot<T, O>) -> TemplateSnapshot<T, O> {
+        TemplateSnapshot {
+            templates: changed_templates(Some(&self.templates), &newer.templates),
+            options_templates: changed_templates(
+                Some(&self.options_templates),
+                &newer.options_templates,
+            ),
+            scoped_templates: changed_scopes(&self.scoped_templates, &newer.scoped_templates),
+            scoped_options_templates: changed_scopes(
+                &self.scoped_options_templates,
+                &newer.scoped_options_templates,
+            ),
+        }
+    }
+}
+
+/// Template caches of a V9 or IPFix parser.  `T` is the template type of the protocol and `O`
+/// its options template type.
+#[derive(Debug)]
+pub struct TemplateEngine<T, O> {
+    pub templates: HashMap<TemplateId, T>,
+    pub options_templates: HashMap<TemplateId, O>,
+    /// Cache templates per source instead of sharing them across all sources
+    pub scope_templates: bool,
+    /// Templates per source, used when `scope_templates` is set
+    pub scoped_templates: HashMap<u32, HashMap<TemplateId, T>>,
+    /// Options templates per source, used when `scope_templates` is set
+    pub scoped_options_templates: HashMap<u32, HashMap<TemplateId, O>>,
+    /// Record `TemplateEvent`s in `template_events`
+    pub record_template_events: bool,
+    /// Template events recorded since they were last drained
+    pub template_events: Vec<TemplateEvent>,
+    /// Called for every template event once a packet has been parsed
+    pub template_observer: Option<Box<dyn TemplateObserver>>,
+    /// Limits how fast new or changed templates are accepted
+    pub template_rate_limit: Option<TemplateRateLimit>,
+    /// Totals of templates added and redefined
+    pub template_stats: TemplateStats,
+    /// Tracks data dropped for templates that aren't cached
+    pub missing_templates: Option<MissingTemplateTracker>,
+    /// When cached templates were last used, kept when the parser has a memory budget
+    pub template_usage: TemplateUsage,
+    /// Evicts the scoped templates of idle sources, used when `scope_templates` is set
+    pub source_tracker: Option<SourceTracker>,
+    /// Count messages and records per source in `source_stats`
+    pub count_sources: bool,
+    /// Messages and records parsed per source, counted when `count_sources` is set
+    pub source_stats: BTreeMap<u32, SourceStats>,
+}
+
+impl<T, O> Default for TemplateEngine<T, O> {
+    fn default() -> Self {
+        Self {
+            templates: HashMap::new(),
+            options_templates: HashMap::new(),
+            scope_templates: false,
+            scoped_templates: HashMap::new(),
+            scoped_options_templates: HashMap::new(),
+            record_template_events: false,
+            template_events: Vec::new(),
+            template_observer: None,
+            template_rate_limit: None,
+            template_stats: TemplateStats::default(),
+            missing_templates: None,
+            template_usage: TemplateUsage::default(),
+            source_tracker: None,
+            count_sources: false,
+            source_stats: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: CachedTemplate, O: CachedTemplate> TemplateEngine<T, O> {
+    /// Copies every cached template, including the scoped ones, so they can be persisted and
+    /// restored with `import_all_templates` after a restart.
+    pub fn export_all_templates(&self) -> TemplateSnapshot<T, O> {
+        TemplateSnapshot {
+            templates: self.templates.clone(),
+            options_templates: self.options_templates.clone(),
+            scoped_templates: self.scoped_templates.clone(),
+            scoped_options_templates: self.scoped_options_templates.clone(),
+        }
+    }
+
+    /// Restores templates from a snapshot.  Templates already cached under the same id and
+    /// scope are replaced.  No template events are recorded and the rate limit is not applied.
+    pub fn import_all_templates(&mut self, snapshot: TemplateSnapshot<T, O>) {
+        self.templates.extend(snapshot.templates);
+        self.options_templates.extend(snapshot.options_templates);
+        extend_scopes(&mut self.scoped_templates, snapshot.scoped_templates);
+        extend_scopes(
+            &mut self.scoped_options_templates,
+            snapshot.scoped_options_templates,
+        );
+    }
+
+    /// Templates of `snapshot` that aren't cached, or are cached with a different definition,
+    /// like `export_all_templates().diff(snapshot)` without copying the cache.
+    #[cfg(feature = "std")]
+    pub(crate) fn uncached_templates(
+        &self,
+        snapshot: &TemplateSnapshot<T, O>,
+    ) -> TemplateSnapshot<T, O> {
+        TemplateSnapshot {
+            templates: changed_templates(Some(&self.templates), &snapshot.templates),
+            options_templates: changed_templates(
+                Some(&self.options_templates),
+                &snapshot.options_templates,
+            ),
+            scoped_templates: changed_scopes(
+                &self.scoped_templates,
+                &snapshot.scoped_templates,
+            ),
+            scoped_options_templates: changed_scopes(
+                &self.scoped_options_templates,
+                &snapshot.scoped_options_templates,
+            ),
+        }
+    }
+
+    /// Takes the template events recorded so far.
+    pub fn drain_template_events(&mut self) -> Vec<TemplateEvent> {
+        core::mem::take(&mut self.template_events)
+    }
+
+    /// Number of cached templates and options templates, including scoped ones.
+    pub fn cached_template_count(&self) -> usize {
+        self.templates.len()
+            + self.options_templates.len()
+            + self
+                .scoped_templates
+                .values()
+                .map(|t| t.len())
+                .sum::<usize>()
+            + self
+                .scoped_options_templates
+                .values()
+                .map(|t| t.len())
+                .sum::<usize>()
+    }
+
+    /// Checks a set for template `set_id` with `body_length` bytes, the `index`th of its
+    /// message, against `limits`, using the field lengths of the cached template.  Ids up to
+    /// 255 are template sets or reserved in V9 and IPFix, and have no template.
+    pub(crate) fn check_set_limits(
+        &self,
+        limits: &ParserLimits,
+        index: usize,
+        set_id: u16,
+        body_length: usize,
+    ) -> Result<(), NetflowError> {
+        let (template, options_template) = match set_id {
+            0..=255 => (None, None),
+            _ => (
+                self.templates.get(&set_id),
+                self.options_templates.get(&set_id),
+            ),
+        };
+        let field_lengths = template
+            .map(CachedTemplate::field_lengths)
+            .into_iter()
+            .chain(options_template.map(CachedTemplate::field_lengths))
+            .flatten();
+        limits.check_set(index, field_lengths, body_length)
+    }
+
+    /// Sources counted in `source_stats` or with scoped templates cached.
+    #[cfg(feature = "replay")]
+    pub(crate) fn known_sources(&self) -> BTreeSet<u32> {
+        let scoped = self.scoped_templates.keys();
+        let scoped = scoped.chain(self.scoped_options_templates.keys());
+        self.source_stats.keys().chain(scoped).copied().collect()
+    }
+
+    /// Templates and options templates cached for `source`, or the shared ones if templates
+    /// aren't scoped, sorted by id.
+    pub(crate) fn cached_templates(&self, source: u32) -> (Vec<&T>, Vec<&O>) {
+        let (templates, options_templates) = if self.scope_templates {
+            (
+                self.scoped_templates.get(&source),
+                self.scoped_options_templates.get(&source),
+            )
+        } else {
+            (Some(&self.templates), Some(&self.options_templates))
+        };
+        let mut templates: Vec<&T> = templates.into_iter().flat_map(|t| t.values()).collect();
+        templates.sort_by_key(|template| template.template_id());
+        let mut options_templates: Vec<&O> = options_templates
+            .into_iter()
+            .flat_map(|t| t.values())
+            .collect();
+        options_templates.sort_by_key(|template| template.template_id());
+        (templates, options_templates)
+    }
+
+    /// Whether a template or options template `template_id` is cached for `source`, or shared
+    /// if templates aren't scoped.
+    #[cfg(feature = "pcap")]
+    pub(crate) fn has_template(&self, source: u32, template_id: u16) -> bool {
+        let scope = Some(source).filter(|_| self.scope_templates);
+        cached(&self.templates, &self.scoped_templates, scope, template_id).is_some()
+            || cached(
+                &self.options_templates,
+                &self.scoped_options_templates,
+                scope,
+                template_id,
+            )
+            .is_some()
+    }
+
+    /// Drops the scoped templates of sources idle for longer than the `source_tracker` ttl,
+    /// returning how many sources were evicted.
+    pub fn evict_idle_sources(&mut self) -> usize {
+        let idle = self
+            .source_tracker
+            .as_mut()
+            .map(SourceTracker::idle)
+            .unwrap_or_default();
+        self.evict_sources(&idle, true);
+        idle.len()
+    }
+
+    /// Layout of template `template_id`, or of options template `template_id` if there is no
+    /// such template.  `scope` picks the templates cached for a source when templates are
+    /// scoped, `None` the shared ones.
+    pub fn template_summary(
+        &self,
+        scope: Option<u32>,
+        template_id: u16,
+    ) -> Option<TemplateSummary> {
+        cached(&self.templates, &self.scoped_templates, scope, template_id)
+            .map(|template| template.summary(scope))
+            .or_else(|| {
+                cached(
+                    &self.options_templates,
+                    &self.scoped_options_templates,
+                    scope,
+                    template_id,
+                )
+                .map(|template| template.summary(scope))
+            })
+    }
+
+    /// Layouts of every cached template, including the scoped ones, sorted by scope, kind and
+    /// id.
+    pub fn template_summaries(&self) -> Vec<TemplateSummary> {
+        let mut summaries: Vec<TemplateSummary> =
+            summaries(&self.templates, &self.scoped_templates)
+                .chain(summaries(
+                    &self.options_templates,
+                    &self.scoped_options_templates,
+                ))
+                .collect();
+        summaries.sort_by_key(|summary| (summary.scope, summary.kind, summary.template_id));
+        summaries
+    }
+
+    /// Checks every cached template for `validate_templates`, reporting findings under
+    /// `version`.  Enterprise fields are checked against `enterprise_fields` when given.
+    pub(crate) fn validate(
+        &self,
+        version: u16,
+        enterprise_fields: Option<&EnterpriseFieldRegistry>,
+    ) -> TemplateReport {
+        let mut report = TemplateReport::default();
+        for summary in self.template_summaries() {
+            let mut issues = summary.lint(enterprise_fields);
+            let (scope, id) = (summary.scope, summary.template_id);
+            let issue = match summary.kind {
+                TemplateKind::Template => {
+                    cached(&self.templates, &self.scoped_templates, scope, id)
+                        .and_then(T::issue)
+                }
+                TemplateKind::OptionsTemplate => cached(
+                    &self.options_templates,
+                    &self.scoped_options_templates,
+                    scope,
+                    id,
+                )
+                .and_then(O::issue),
+            };
+            issues.extend(issue);
+            report.add(version, &summary, issues);
+        }
+        report
+    }
+
+    /// Estimated memory held by the template caches and pending events.
+    pub fn memory_usage(&self) -> MemoryStats {
+        let scoped = self.scoped_templates.values().flat_map(|t| t.values());
+        let (templates, template_bytes) =
+            cache_memory(self.templates.values().chain(scoped), T::memory_bytes);
+        let scoped = self
+            .scoped_options_templates
+            .values()
+            .flat_map(|t| t.values());
+        let (options_templates, options_bytes) = cache_memory(
+            self.options_templates.values().chain(scoped),
+            O::memory_bytes,
+        );
+        let missing = self
+            .missing_templates
+            .as_ref()
+            .map_or(0, MissingTemplateTracker::memory_bytes);
+        MemoryStats {
+            templates: templates + options_templates,
+            template_bytes: template_bytes + options_bytes + self.template_usage.memory_bytes(),
+            pending_bytes: self.template_events.capacity()
+                * core::mem::size_of::<TemplateEvent>()
+                + self.source_stats.len() * core::mem::size_of::<(u32, SourceStats)>()
+                + missing,
+        }
+    }
+
+    /// Readies the caches for a message from `source`, when its header could be read: the
+    /// rate limit is charged to it and, if templates are scoped, its templates are swapped in.
+    /// Returns the scope and the first event of the message, for `end_message`.
+    pub(crate) fn begin_message(&mut self, source: Option<u32>) -> (Option<u32>, usize) {
+        if let (Some(limit), Some(source)) = (self.template_rate_limit.as_mut(), source) {
+            limit.set_source(source);
+        }
+        let scope = source.filter(|_| self.scope_templates);
+        if let Some(scope) = scope {
+            self.see_source(scope);
+            self.swap_scope(scope);
+        }
+        (scope, self.template_events.len())
+    }
+
+    /// Swaps back the templates `begin_message` swapped in and passes the events of the
+    /// message to the observer.
+    pub(crate) fn end_message(&mut self, (scope, events_start): (Option<u32>, usize)) {
+        if let Some(scope) = scope {
+            self.swap_scope(scope);
+        }
+        self.finish_events(events_start, scope);
+    }
+
+    /// Caches `template`, returning the cached template if an identical one already was, so
+    /// the packet shares it.
+    pub(crate) fn add_template(&mut self, template: T) -> T {
+        let (template, event) =
+            insert(&mut self.templates, template, &mut self.template_rate_limit);
+        if let Some(event) = event {
+            self.record_event(event);
+        }
+        template
+    }
+
+    pub(crate) fn add_options_template(&mut self, template: O) -> O {
+        let cache = &mut self.options_templates;
+        let (template, event) = insert(cache, template, &mut self.template_rate_limit);
+        if let Some(event) = event {
+            self.record_event(event);
+        }
+        template
+    }
+
+    /// Counts `records` and `options_records` parsed from `source`.
+    pub(crate) fn count_source(&mut self, source: u32, records: usize, options_records: usize) {
+        if !self.count_sources {
+            return;
+        }
+        self.source_stats
+            .entry(source)
+            .or_default()
+            .count(records, options_records);
+    }
+
+    /// Marks the templates `used` by a message from `source` as used at `tick`.
+    pub(crate) fn touch(
+        &mut self,
+        source: u32,
+        used: impl Iterator<Item = (TemplateKind, TemplateId)>,
+        tick: u64,
+    ) {
+        let scope = Some(source).filter(|_| self.scope_templates);
+        for (kind, template_id) in used {
+            self.template_usage.touch(kind, scope, template_id, tick);
+        }
+    }
+
+    /// The least recently used cached template, with when it was last used.
+    pub(crate) fn oldest_template(&self) -> Option<(u64, UsageKey)> {
+        let templates = self.template_usage.oldest(
+            TemplateKind::Template,
+            cached_ids(&self.templates, &self.scoped_templates),
+        );
+        let options_templates = self.template_usage.oldest(
+            TemplateKind::OptionsTemplate,
+            cached_ids(&self.options_templates, &self.scoped_options_templates),
+        );
+        templates
+            .into_iter()
+            .chain(options_templates)
+            .min_by_key(|(tick, _)| *tick)
+    }
+
+    /// Evicts a cached template, returning the estimated bytes freed.
+    pub(crate) fn evict_template(
+        &mut self,
+        kind: TemplateKind,
+        scope: Option<u32>,
+        template_id: u16,
+    ) -> usize {
+        let freed = match kind {
+            TemplateKind::Template => remove_cached(
+                &mut self.templates,
+                &mut self.scoped_templates,
+                scope,
+                template_id,
+            )
+            .map(|template| template.memory_bytes()),
+            TemplateKind::OptionsTemplate => remove_cached(
+                &mut self.options_templates,
+                &mut self.scoped_options_templates,
+                scope,
+                template_id,
+            )
+            .map(|template| template.memory_bytes()),
+        };
+        self.template_usage.forget(kind, scope, template_id);
+        let start = self.template_events.len();
+        self.record_event(TemplateEvent::Evicted {
+            kind,
+            template_id,
+            scope,
+        });
+        self.finish_events(start, None);
+        freed.map_or(0, |bytes| bytes + core::mem::size_of::<TemplateId>())
+    }
+
+    /// Tracks the templates of a message from `source`, in message order: each item of `sets`
+    /// is the id of an announced template, which clears it, or with `true` the template of a
+    /// data set dropped because it isn't cached.  Alerts that are due go to the observer.
+    pub(crate) fn track_missing(
+        &mut self,
+        source: u32,
+        sets: impl Iterator<Item = (TemplateId, bool)>,
+    ) {
+        let Some(tracker) = self.missing_templates.as_mut() else {
+            return;
+        };
+        let mut alerts = vec![];
+        for (template_id, dropped) in sets {
+            if dropped {
+                alerts.extend(tracker.record_drop(source, template_id));
+            } else {
+                tracker.resolve(source, template_id);
+            }
+        }
+        if let Some(observer) = self.template_observer.as_mut() {
+            for alert in alerts.iter() {
+                observer.on_missing_template(alert);
+            }
+        }
+    }
+
+    /// Sets the scope of the events recorded from `start` on and passes them to the observer.
+    fn finish_events(&mut self, start: usize, scope: Option<u32>) {
+        for event in self.template_events[start..].iter_mut() {
+            if let Some(scope) = scope {
+                event.set_scope(scope);
+            }
+            if let Some(observer) = self.template_observer.as_mut() {
+                event.notify(observer.as_mut());
+            }
+        }
+        if !self.record_template_events {
+            self.template_events.truncate(start);
+        }
+    }
+
+    /// Marks `source` as seen, dropping the idle sources and the ones the tracker makes room
+    /// by.
+    fn see_source(&mut self, source: u32) {
+        if let Some(tracker) = self.source_tracker.as_mut() {
+            let (idle, evicted) = tracker.see(source);
+            self.evict_sources(&idle, true);
+            self.evict_sources(&evicted, false);
+        }
+    }
+
+    /// Drops the scoped templates and stats of `sources`, recording an eviction per template,
+    /// or an expiry if the sources were idle.
+    fn evict_sources(&mut self, sources: &[u32], idle: bool) {
+        let start = self.template_events.len();
+        for source in sources.iter().copied() {
+            self.source_stats.remove(&source);
+            let templates = self.scoped_templates.remove(&source).unwrap_or_default();
+            let options = self
+                .scoped_options_templates
+                .remove(&source)
+                .unwrap_or_default();
+            let evicted = templates
+                .into_keys()
+                .map(|id| (TemplateKind::Template, id))
+                .chain(
+                    options
+                        .into_keys()
+                        .map(|id| (TemplateKind::OptionsTemplate, id)),
+                );
+            for (kind, template_id) in evicted {
+                self.template_usage.forget(kind, Some(source), template_id);
+                let scope = Some(source);
+                self.record_event(if idle {
+                    TemplateEvent::Expired {
+                        kind,
+                        template_id,
+                        scope,
+                    }
+                } else {
+                    TemplateEvent::Evicted {
+                        kind,
+                        template_id,
+                        scope,
+                    }
+                });
+            }
+        }
+        self.finish_events(start, None);
+    }
+
+    fn record_event(&mut self, event: TemplateEvent) {
+        self.template_stats.count(&event);
+        if self.record_template_events || self.template_observer.is_some() {
+            self.template_events.push(event);
+        }
+    }
+
+    /// Swaps the active template caches with the ones cached for `source`.  Calling it a
+    /// second time with the same `source` restores the previous caches.
+    fn swap_scope(&mut self, source: u32) {
+        core::mem::swap(
+            &mut self.templates,
+            self.scoped_templates.entry(source).or_default(),
+        );
+        core::mem::swap(
+            &mut self.options_templates,
+            self.scoped_options_templates.entry(source).or_default(),
+        );
+    }
+}
+
+/// Caches `template`, returning the template to keep in the packet and the event to record.
+/// There is no event if an identical template was already cached, which is returned instead,
+/// or if the rate limit turned it away.
+fn insert<C: CachedTemplate>(
+    cache: &mut HashMap<TemplateId, C>,
+    template: C,
+    rate_limit: &mut Option<TemplateRateLimit>,
+) -> (C, Option<TemplateEvent>) {
+    let template_id = template.template_id();
+    // Refreshing an identical template doesn't count against the rate limit
+    if let Some(cached) = cache
+        .get(&template_id)
+        .filter(|cached| **cached == template)
+    {
+        return (cached.clone(), None);
+    }
+    if !rate_limit.as_mut().is_none_or(TemplateRateLimit::allow) {
+        return (template, None);
+    }
+    let kind = C::KIND;
+    let event = match cache.insert(template_id, template.clone()) {
+        None => TemplateEvent::Added {
+            kind,
+            template_id,
+            scope: None,
+        },
+        Some(_) => TemplateEvent::Redefined {
+            kind,
+            template_id,
+            scope: None,
+        },
+    };
+    (template, Some(event))
+}
+
+fn extend_scopes<T>(
+    scoped: &mut HashMap<u32, HashMap<TemplateId, T>>,
+    other: HashMap<u32, HashMap<TemplateId, T>>,
+) {
+    for (scope, templates) in other {
+        scoped.entry(scope).or_default().extend(templates);
+    }
+}
+
+fn changed_templates<T: PartialEq + Clone>(
+    old: Option<&HashMap<TemplateId, T>>,
+    new: &HashMap<TemplateId, T>,
+) -> HashMap<TemplateId, T> {
+    new.iter()
+        .filter(|(id, template)| old.and_then(|old| old.get(*id)) != Some(*template))
+        .map(|(id, template)| (*id, template.clone()))
+        .collect()
+}
+
+fn changed_scopes<T: PartialEq + Clone>(
+    old: &HashMap<u32, HashMap<TemplateId, T>>,
+    new: &HashMap<u32, HashMap<TemplateId, T>>,
+) -> HashMap<u32, HashMap<TemplateId, T>> {
+    new.iter()
+        .map(|(scope, templates)| (*scope, changed_templates(old.get(scope), templates)))
+        .filter(|(_, templates)| !templates.is_empty())
+        .collect()
+}
+
+/// Ids of the templates in `shared` and in `scoped`, with their scope.
+fn cached_ids<'a, T>(
+    shared: &'a HashMap<TemplateId, T>,
+    scoped: &'a HashMap<u32, HashMap<TemplateId, T>>,
+) -> impl Iterator<Item = (Option<u32>, TemplateId)> + 'a {
+    let scoped = scoped
+        .iter()
+        .flat_map(|(scope, templates)| templates.keys().map(|id| (Some(*scope), *id)));
+    shared.keys().map(|id| (None, *id)).chain(scoped)
+}
+
+/// Summaries of every template in `shared` and `scoped`.
+fn summaries<'a, T: CachedTemplate>(
+    shared: &'a HashMap<TemplateId, T>,
+    scoped: &'a HashMap<u32, HashMap<TemplateId, T>>,
+) -> impl Iterator<Item = TemplateSummary> + 'a {
+    let scoped = scoped.iter().flat_map(move |(scope, templates)| {
+        templates
+            .values()
+            .map(move |template| template.summary(Some(*scope)))
+    });
+    shared
+        .values()
+        .map(move |template| template.summary(None))
+        .chain(scoped)
+}
+
+/// Template `id` of `shared`, or of `scoped` under `scope`.
+fn cached<'a, T>(
+    shared: &'a HashMap<TemplateId, T>,
+    scoped: &'a HashMap<u32, HashMap<TemplateId, T>>,
+    scope: Option<u32>,
+    id: TemplateId,
+) -> Option<&'a T> {
+    match scope {
+        None => shared.get(&id),
+        Some(scope) => scoped.get(&scope)?.get(&id),
+    }
+}
+
+/// Removes template `id` from `shared`, or from `scoped` under `scope`.
+fn remove_cached<T>(
+    shared: &mut HashMap<TemplateId, T>,
+    scoped: &mut HashMap<u32, HashMap<TemplateId, T>>,
+    scope: Option<u32>,
+    id: TemplateId,
+) -> Option<T> {
+    match scope {
+        None => shared.remove(&id),
+        Some(scope) => scoped.get_mut(&scope)?.remove(&id),
+    }
+}
@@ -5,46 +5,310 @@
 //! - <https://www.cisco.com/en/US/technologies/tk648/tk362/technologies_white_paper09186a00800a3db9.html>
 
 use super::data_number::*;
+use super::template_engine::{self, announced_length, CachedTemplate, TemplateEngine};
+use super::{
+    exceed_limit, MessageError, MessageResult, ParserLimits, TemplateFieldSummary,
+    TemplateIssue, TemplateKind, TemplateReport, TemplateSummary,
+};
+use crate::digest::PacketDigest;
+use crate::filter::Filter;
+use crate::framer::{plausible_message, V9_RESERVED_FLOWSET_IDS};
+use crate::uptime::{uptime_elapsed, uptime_to_unix_time, uptime_value};
 use crate::variable_versions::v9_lookup::*;
-use crate::{NetflowPacket, NetflowParseError, ParsedNetflow, PartialParse};
+use crate::{NetflowError, NetflowPacket, ParseMode, ParsedNetflow};
 
 use nom::bytes::complete::take;
+use nom::combinator::complete;
 use nom::error::{Error as NomError, ErrorKind};
+use nom::multi::many0;
 use nom::Err as NomErr;
 use nom::IResult;
 use nom_derive::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use Nom;
 
-use std::collections::BTreeMap;
-use std::collections::HashMap;
+use crate::HashMap;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::{format, vec, vec::Vec};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const TEMPLATE_ID: u16 = 0;
 const OPTIONS_TEMPLATE_ID: u16 = 1;
 const FLOWSET_MIN_RANGE: u16 = 255;
+/// Field type and length of a template field
+const DESCRIPTOR_LENGTH: usize = 4;
+/// FlowSet ID and length
+const FLOWSET_HEADER_LENGTH: usize = 4;
 
-type TemplateId = u16;
 pub type V9FieldPair = (V9Field, FieldValue);
 
 pub(crate) fn parse_netflow_v9(
     packet: &[u8],
     parser: &mut V9Parser,
-) -> Result<ParsedNetflow, NetflowParseError> {
-    V9::parse(packet, parser)
-        .map(|(remaining, v9)| ParsedNetflow::new(remaining, NetflowPacket::V9(v9)))
-        .map_err(|e| {
-            NetflowParseError::Partial(PartialParse {
-                version: 9,
-                error: e.to_string(),
-                remaining: packet.to_vec(),
-            })
-        })
+) -> Result<ParsedNetflow, NetflowError> {
+    let source_id = Header::parse(packet)
+        .ok()
+        .map(|(_, header)| header.source_id);
+    let message = parser.engine.begin_message(source_id);
+    let result = parse_message(packet, parser);
+    parser.engine.end_message(message);
+    let (remaining, v9) = result.map_err(|e| MessageError::into_netflow_error(e, 9, packet))?;
+    parser.track_missing_templates(&v9);
+    if parser.parse_mode == ParseMode::Strict {
+        v9.validate()?;
+    }
+    parser.count_source(&v9);
+    Ok(ParsedNetflow::new(remaining, NetflowPacket::V9(v9)))
+}
+
+/// `V9::parse`, failing with the limit a message exceeded.
+fn parse_message<'a>(packet: &'a [u8], parser: &mut V9Parser) -> MessageResult<'a, V9> {
+    let (i, header) = Header::parse(packet).map_err(NomErr::convert)?;
+    let (remaining, flowsets) = parse_flowsets(i, parser, header.count)?;
+    Ok((
+        remaining,
+        V9 {
+            header,
+            flowsets,
+            received_at: None,
+            digest: None,
+        },
+    ))
+}
+
+/// Every template cached by a parser, see `TemplateEngine::export_all_templates`.
+pub type TemplateSnapshot =
+    template_engine::TemplateSnapshot<Arc<Template>, Arc<OptionsTemplate>>;
+
+/// Header of a template flowset announcing `templates`.
+fn template_flowset_header<C: CachedTemplate>(
+    flowset_id: u16,
+    templates: &[C],
+) -> Result<FlowSetHeader, NetflowError> {
+    let length = templates.iter().map(C::record_length).sum::<usize>();
+    Ok(FlowSetHeader {
+        flowset_id,
+        length: announced_length(9, FLOWSET_HEADER_LENGTH + length)?,
+    })
+}
+
+/// What the `count` in a V9 header counts, which decides where the flowsets of a message end.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CountSemantics {
+    /// Flowsets, as many exporters set it.  Parsing stops after `count` flowsets.
+    #[default]
+    FlowSets,
+    /// Records of every flowset, templates included, as RFC 3954 defines it.  Parsing stops
+    /// once `count` records have been parsed.
+    Records,
+    /// Detected per message.  Flowsets are parsed until either the flowsets or the records
+    /// parsed reach the header `count`, then on until the bytes run out or the next message
+    /// starts, for exporters whose counts line up with neither.
+    Auto,
+}
+
+impl CountSemantics {
+    /// Whether to parse the flowset at the start of `remaining`, with `flowsets` flowsets and
+    /// `records` records parsed so far towards the header `count`.  Flowsets that weren't
+    /// parsed count as one record.
+    fn more_flowsets(
+        &self,
+        flowsets: usize,
+        records: usize,
+        count: u16,
+        remaining: &[u8],
+    ) -> bool {
+        let count = usize::from(count);
+        match self {
+            CountSemantics::FlowSets => flowsets < count,
+            CountSemantics::Records => records < count,
+            // Until either count is reached, a reserved flowset id that looks like the
+            // version of another message is still a flowset of this one.
+            CountSemantics::Auto => {
+                (flowsets < count && records < count) || !plausible_message(remaining)
+            }
+        }
+    }
 }
 
 #[derive(Default, Debug)]
 pub struct V9Parser {
-    pub templates: HashMap<TemplateId, Template>,
-    pub options_templates: HashMap<TemplateId, OptionsTemplate>,
+    /// Templates and everything kept about them, scoped per header `source_id` when
+    /// `engine.scope_templates` is set
+    pub engine: TemplateEngine<Arc<Template>, Arc<OptionsTemplate>>,
+    /// How flowsets that fail to parse are handled
+    pub(crate) parse_mode: ParseMode,
+    /// What the header `count` counts
+    pub(crate) count_semantics: CountSemantics,
+    /// Keep the wire bytes of every flowset in its `raw`
+    pub(crate) keep_raw: bool,
+    /// Only decode these fields of data records, skipping over the others by their length
+    pub(crate) projection: Option<BTreeSet<V9Field>>,
+    /// Data records that don't match are dropped as they are decoded
+    pub(crate) filter: Option<Filter>,
+    /// Data records are decoded into the arena rather than their flowsets while it is set
+    #[cfg(feature = "perf")]
+    pub(crate) arena: Option<crate::arena::RecordArena>,
+    /// How `String` fields are decoded
+    pub(crate) string_decoding: StringDecoding,
+    /// Caps on field lengths, records and flowsets of a message
+    pub(crate) limits: ParserLimits,
+}
+
+impl V9Parser {
+    /// Template and options template flowsets announcing every template cached for
+    /// `source_id`, or every shared template if templates aren't scoped, so a relay can
+    /// re-announce them downstream.  Templates are sorted by id and flowsets are left out
+    /// when there is nothing to announce.  Fails if a flowset would be longer than 65,535
+    /// bytes.
+    pub fn templates_to_flowset(&self, source_id: u32) -> Result<Vec<FlowSet>, NetflowError> {
+        let (templates, options_templates) = self.engine.cached_templates(source_id);
+        let templates: Vec<Arc<Template>> = templates.into_iter().cloned().collect();
+        let options_templates: Vec<Arc<OptionsTemplate>> =
+            options_templates.into_iter().cloned().collect();
+
+        let mut flowsets = vec![];
+        if !templates.is_empty() {
+            flowsets.push(FlowSet {
+                header: template_flowset_header(TEMPLATE_ID, &templates)?,
+                body: FlowSetBody {
+                    templates: Some(templates),
+                    ..Default::default()
+                },
+            });
+        }
+        if !options_templates.is_empty() {
+            flowsets.push(FlowSet {
+                header: template_flowset_header(OPTIONS_TEMPLATE_ID, &options_templates)?,
+                body: FlowSetBody {
+                    options_templates: Some(options_templates),
+                    ..Default::default()
+                },
+            });
+        }
+        Ok(flowsets)
+    }
+
+    /// Checks every cached template, including the scoped ones, for suspicious definitions
+    /// such as zero length or duplicate fields, which would otherwise show up as data silently
+    /// parsed wrong.
+    pub fn validate_templates(&self) -> TemplateReport {
+        self.engine.validate(9, None)
+    }
+
+    /// Source ids messages have been parsed from, in ascending order, when
+    /// `engine.count_sources` is set.  See `engine.source_stats` for what each sent.
+    pub fn source_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.engine.source_stats.keys().copied()
+    }
+
+    /// Counts the records of `v9` for its source id.
+    fn count_source(&mut self, v9: &V9) {
+        let (records, options_records) =
+            v9.flowsets
+                .iter()
+                .fold((0, 0), |(records, options_records), flowset| {
+                    let body = &flowset.body;
+                    (
+                        records + body.data.as_ref().map_or(0, |data| data.data_fields.len()),
+                        options_records + usize::from(body.options_data.is_some()),
+                    )
+                });
+        self.engine
+            .count_source(v9.header.source_id, records, options_records);
+    }
+
+    /// Marks the templates announced or used by data in `v9` as used at `tick`.
+    pub(crate) fn touch_templates(&mut self, v9: &V9, tick: u64) {
+        let used = v9.flowsets.iter().flat_map(|flowset| {
+            let body = &flowset.body;
+            let id = flowset.header.flowset_id;
+            let templates = body.templates.iter().flatten();
+            let options = body.options_templates.iter().flatten();
+            templates
+                .map(|t| (TemplateKind::Template, t.template_id))
+                .chain(options.map(|t| (TemplateKind::OptionsTemplate, t.template_id)))
+                .chain(body.data.as_ref().map(|_| (TemplateKind::Template, id)))
+                .chain(
+                    body.options_data
+                        .as_ref()
+                        .map(|_| (TemplateKind::OptionsTemplate, id)),
+                )
+        });
+        self.engine.touch(v9.header.source_id, used, tick);
+    }
+
+    /// Counts data flowsets dropped for missing templates and clears templates received in
+    /// `v9`.
+    fn track_missing_templates(&mut self, v9: &V9) {
+        let sets = v9.flowsets.iter().flat_map(|flowset| {
+            let body = &flowset.body;
+            let templates = body.templates.iter().flatten().map(|t| t.template_id);
+            let options = body.options_templates.iter().flatten();
+            let id = flowset.header.flowset_id;
+            let dropped = id > FLOWSET_MIN_RANGE && flowset.is_unparsed();
+            templates
+                .chain(options.map(|t| t.template_id))
+                .map(|template_id| (template_id, false))
+                .chain(dropped.then_some((id, true)))
+        });
+        self.engine.track_missing(v9.header.source_id, sets);
+    }
+
+    /// Keeps `bytes` as the wire bytes of `flowset` if `keep_raw` is set.
+    fn keep_raw(&self, flowset: &mut FlowSet, bytes: &[u8]) {
+        if self.keep_raw {
+            flowset.body.raw = Some(bytes.to_vec());
+        }
+    }
+
+    /// Checks the flowset at the start of `flowset`, the `index`th of its message, against
+    /// the limits.
+    fn check_limits(&self, index: usize, flowset: &[u8]) -> Result<(), NetflowError> {
+        let Ok((_, header)) = FlowSetHeader::parse(flowset) else {
+            return Ok(());
+        };
+        let body_length = usize::from(header.length).saturating_sub(FLOWSET_HEADER_LENGTH);
+        self.engine
+            .check_set_limits(&self.limits, index, header.flowset_id, body_length)
+    }
+
+    /// Checks the field counts of the templates or options templates in the flowset at the
+    /// start of `flowset` against the limits, before their fields are parsed.
+    fn check_field_counts(&self, flowset: &[u8]) -> Result<(), NetflowError> {
+        let Ok((body, header)) = FlowSetHeader::parse(flowset) else {
+            return Ok(());
+        };
+        // Templates start with their id and field count, options templates with their id
+        // and the lengths of their scope and option field descriptors.
+        let record_header_length = match header.flowset_id {
+            TEMPLATE_ID => 4,
+            OPTIONS_TEMPLATE_ID => 6,
+            _ => return Ok(()),
+        };
+        let length = usize::from(header.length)
+            .min(flowset.len())
+            .saturating_sub(FLOWSET_HEADER_LENGTH);
+        let mut records = &body[..length];
+        let word = |records: &[u8], at: usize| {
+            usize::from(u16::from_be_bytes([records[at], records[at + 1]]))
+        };
+        while records.len() >= record_header_length {
+            let descriptors = match header.flowset_id {
+                TEMPLATE_ID => word(records, 2) * DESCRIPTOR_LENGTH,
+                _ => word(records, 2) + word(records, 4),
+            };
+            self.limits
+                .check_field_count(descriptors / DESCRIPTOR_LENGTH)?;
+            records = records
+                .get(record_header_length + descriptors..)
+                .unwrap_or_default();
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Nom)]
@@ -53,8 +317,18 @@ pub struct V9 {
     /// V9 Header
     pub header: Header,
     /// Flowsets
-    #[nom(Parse = "{ |i| parse_flowsets(i, parser, header.count) }")]
+    #[nom(Parse = "{ |i| parse_flowsets(i, parser, header.count)
+        .map_err(|e| e.map(MessageError::into_nom)) }")]
     pub flowsets: Vec<FlowSet>,
+    /// Wall-clock time the packet was received, since the unix epoch, when known.  Set by
+    /// `NetflowParser::parse_bytes_received_at` or when receive times are captured.
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<Duration>,
+    /// Identity of the message, set when the parser computes packet digests.
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<PacketDigest>,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy, Serialize, Nom)]
@@ -107,44 +381,35 @@ pub struct FlowSetHeader {
     pub length: u16,
 }
 
-#[derive(Debug, PartialEq, Clone, Serialize, Nom)]
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Nom)]
 #[nom(ExtraArgs(parser: &mut V9Parser, flowset_id: u16))]
 pub struct FlowSetBody {
     /// Templates
     #[nom(
         Cond = "flowset_id == TEMPLATE_ID",
-        // Save our templates
-        PostExec = "if let Some(templates) = templates.clone() { 
-            for template in templates {
-                parser.templates.insert(template.template_id, template); 
-            }
-        }"
+        // Saves our templates
+        Parse = "{ |i| parse_template_vec(i, parser) }"
     )]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub templates: Option<Vec<Template>>,
+    pub templates: Option<Vec<Arc<Template>>>,
     // Options template
     #[nom(
         Cond = "flowset_id == OPTIONS_TEMPLATE_ID",
-        Parse = "parse_options_template_vec",
-        // Save our options templates
-        PostExec = "if let Some(options_templates) = options_templates.clone() { 
-            for template in options_templates {
-                parser.options_templates.insert(template.template_id, template); 
-            } 
-        }"
+        // Saves our options templates
+        Parse = "{ |i| parse_options_template_vec(i, parser) }"
     )]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub options_templates: Option<Vec<OptionsTemplate>>,
+    pub options_templates: Option<Vec<Arc<OptionsTemplate>>>,
     // Options Data
     #[nom(
-        Cond = "flowset_id > FLOWSET_MIN_RANGE && parser.options_templates.contains_key(&flowset_id)",
+        Cond = "flowset_id > FLOWSET_MIN_RANGE && parser.engine.options_templates.contains_key(&flowset_id)",
         Parse = "{ |i| OptionsData::parse(i, parser, flowset_id) }"
     )]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub options_data: Option<OptionsData>,
     // Data
     #[nom(
-        Cond = "flowset_id > FLOWSET_MIN_RANGE && parser.templates.contains_key(&flowset_id)",
+        Cond = "flowset_id > FLOWSET_MIN_RANGE && parser.engine.templates.contains_key(&flowset_id)",
         Parse = "{ |i| Data::parse(i, parser, flowset_id) }"
     )]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -153,9 +418,19 @@ pub struct FlowSetBody {
     #[nom(Ignore)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub unparsed_data: Option<Vec<u8>>,
+    /// Why the flowset could not be parsed, set when it was skipped in `ParseMode::Lenient`.
+    /// Flowsets running past the end of the packet get a `NetflowError::SetLengthMismatch`,
+    /// and in `ParseMode::Lenient` the records that fit are still parsed.
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parse_error: Option<NetflowError>,
+    /// Wire bytes of the flowset, header included, kept when `keep_raw` is set.
+    #[nom(Ignore)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<Vec<u8>>,
 }
 
-#[derive(Debug, PartialEq, Clone, Serialize, Nom)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Nom)]
 pub struct Template {
     /// As a router generates different template FlowSets to match the type of NetFlow
     /// data it will be exporting, each template is given a unique ID. This uniqueness
@@ -172,7 +447,7 @@ pub struct Template {
     pub fields: Vec<TemplateField>,
 }
 
-#[derive(Debug, PartialEq, Clone, Serialize, Nom)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Nom)]
 pub struct OptionsTemplate {
     /// As a router generates different template FlowSets to match the type of NetFlow data it is exporting, each template is given a unique ID. This uniqueness is local to the router that generated the template ID. The Template ID is greater than 255. Template IDs inferior to 255 are reserved.
     pub template_id: u16,
@@ -181,15 +456,17 @@ pub struct OptionsTemplate {
     /// This field gives the length (in bytes) of any Options field definitions that are contained in this options template
     pub options_length: u16,
     /// Options Scope Fields
-    #[nom(Count = "(options_scope_length / 4) as usize")]
+    #[nom(
+        Parse = "{ |i| parse_descriptors(i, options_scope_length, OptionsTemplateScopeField::parse) }"
+    )]
     pub scope_fields: Vec<OptionsTemplateScopeField>,
     /// Options Fields
-    #[nom(Count = "(options_length / 4) as usize")]
+    #[nom(Parse = "{ |i| parse_descriptors(i, options_length, TemplateField::parse) }")]
     pub option_fields: Vec<TemplateField>,
 }
 
 /// Options Scope Fields
-#[derive(Debug, PartialEq, Clone, Serialize, Nom)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Nom)]
 pub struct OptionsTemplateScopeField {
     pub field_type_number: u16,
     #[nom(Value(ScopeFieldType::from(field_type_number)))]
@@ -197,7 +474,7 @@ pub struct OptionsTemplateScopeField {
     pub field_length: u16,
 }
 
-#[derive(Debug, PartialEq, Clone, Serialize, Nom)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Nom)]
 pub struct TemplateField {
     /// This numeric value represents the type of the field. The possible values of the
     /// field type are vendor specific. Cisco supplied values are consistent across all
@@ -217,14 +494,23 @@ pub struct TemplateField {
 #[derive(Debug, PartialEq, Clone, Serialize, Nom)]
 #[nom(ExtraArgs(parser: &mut V9Parser, flowset_id: u16))]
 pub struct OptionsData {
+    /// Id of the options template that decoded the records, the flowset id
+    #[nom(Value = "flowset_id")]
+    pub template_id: u16,
     // Scope Data
-    #[nom(Parse = "{ |i| parse_scope_data_fields(i, flowset_id, &parser.options_templates) }")]
+    #[nom(
+        Parse = "{ |i| parse_scope_data_fields(i, flowset_id, &parser.engine.options_templates) }"
+    )]
     pub scope_fields: Vec<ScopeDataField>,
     // Options Data Fields
     #[nom(
-        Parse = "{ |i| parse_options_data_fields(i, flowset_id, parser.options_templates.clone()) }"
+        Parse = "{ |i| parse_options_data_fields(i, flowset_id, &parser.engine.options_templates) }"
     )]
     pub options_fields: Vec<OptionDataField>,
+    /// The options template that decoded the records when parsed, shared with the parser's cache
+    #[nom(Value = "parser.engine.options_templates.get(&flowset_id).cloned()")]
+    #[serde(skip)]
+    pub template: Option<Arc<OptionsTemplate>>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Nom)]
@@ -275,9 +561,20 @@ pub struct ScopeDataField {
 #[derive(Debug, PartialEq, Clone, Serialize, Nom)]
 #[nom(ExtraArgs(parser: &mut V9Parser, flowset_id: u16))]
 pub struct Data {
+    /// Id of the template that decoded the records, the flowset id
+    #[nom(Value = "flowset_id")]
+    pub template_id: u16,
     // Data Fields
-    #[nom(Parse = "{ |i| parse_fields(i, parser.templates.get(&flowset_id)) }")]
+    #[nom(Parse = "{ |i| parse_data_records(i, parser, flowset_id) }")]
     pub data_fields: Vec<BTreeMap<usize, V9FieldPair>>,
+    /// Fields that could not be decoded and were kept as `FieldValue::Unknown`
+    #[nom(Value = "field_warnings(&data_fields)")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<FieldWarning>,
+    /// The template that decoded the records when parsed, shared with the parser's cache
+    #[nom(Value = "parser.engine.templates.get(&flowset_id).cloned()")]
+    #[serde(skip)]
+    pub template: Option<Arc<Template>>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Nom)]
@@ -295,9 +592,138 @@ impl Template {
             .iter()
             .fold(0, |acc, i| acc.saturating_add(i.field_length))
     }
+
+    /// Layout of the template, cached under `scope`.
+    pub fn summary(&self, scope: Option<u32>) -> TemplateSummary {
+        TemplateSummary {
+            kind: TemplateKind::Template,
+            template_id: self.template_id,
+            scope,
+            record_length: Some(usize::from(self.get_total_size())),
+            fields: self.fields.iter().map(TemplateField::summary).collect(),
+        }
+    }
+}
+
+impl OptionsTemplate {
+    /// Scope length not matching the scope field descriptors, or no scope fields at all.
+    fn scope_issue(&self) -> Option<TemplateIssue> {
+        let declared = usize::from(self.options_scope_length);
+        let described = self.scope_fields.len() * DESCRIPTOR_LENGTH;
+        if declared != described {
+            Some(TemplateIssue::ScopeMismatch {
+                declared,
+                described,
+            })
+        } else if self.scope_fields.is_empty() {
+            Some(TemplateIssue::NoScopeFields)
+        } else {
+            None
+        }
+    }
+
+    /// Layout of the options template, cached under `scope`.
+    pub fn summary(&self, scope: Option<u32>) -> TemplateSummary {
+        let scope_fields = self.scope_fields.iter().map(|field| TemplateFieldSummary {
+            name: format!("{:?}", field.field_type),
+            field_type_number: field.field_type_number,
+            field_length: field.field_length,
+            enterprise_number: None,
+            scope: true,
+        });
+        let fields: Vec<TemplateFieldSummary> = scope_fields
+            .chain(self.option_fields.iter().map(TemplateField::summary))
+            .collect();
+        TemplateSummary {
+            kind: TemplateKind::OptionsTemplate,
+            template_id: self.template_id,
+            scope,
+            record_length: Some(fields.iter().map(|f| usize::from(f.field_length)).sum()),
+            fields,
+        }
+    }
+}
+
+impl CachedTemplate for Template {
+    const KIND: TemplateKind = TemplateKind::Template;
+
+    fn template_id(&self) -> u16 {
+        self.template_id
+    }
+
+    fn summary(&self, scope: Option<u32>) -> TemplateSummary {
+        Template::summary(self, scope)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        core::mem::size_of::<Self>()
+            + self.fields.capacity() * core::mem::size_of::<TemplateField>()
+    }
+
+    fn record_length(&self) -> usize {
+        4 + self.fields.len() * DESCRIPTOR_LENGTH
+    }
+
+    fn field_lengths(&self) -> Vec<u16> {
+        self.fields.iter().map(|field| field.field_length).collect()
+    }
+}
+
+impl CachedTemplate for OptionsTemplate {
+    const KIND: TemplateKind = TemplateKind::OptionsTemplate;
+
+    fn template_id(&self) -> u16 {
+        self.template_id
+    }
+
+    fn summary(&self, scope: Option<u32>) -> TemplateSummary {
+        OptionsTemplate::summary(self, scope)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        core::mem::size_of::<Self>()
+            + self.scope_fields.capacity() * core::mem::size_of::<OptionsTemplateScopeField>()
+            + self.option_fields.capacity() * core::mem::size_of::<TemplateField>()
+    }
+
+    fn issue(&self) -> Option<TemplateIssue> {
+        self.scope_issue()
+    }
+
+    fn record_length(&self) -> usize {
+        6 + usize::from(self.options_scope_length) + usize::from(self.options_length)
+    }
+
+    fn field_lengths(&self) -> Vec<u16> {
+        let scope = self.scope_fields.iter().map(|field| field.field_length);
+        scope
+            .chain(self.option_fields.iter().map(|field| field.field_length))
+            .collect()
+    }
+}
+
+impl TemplateField {
+    fn summary(&self) -> TemplateFieldSummary {
+        TemplateFieldSummary {
+            name: format!("{:?}", self.field_type),
+            field_type_number: self.field_type_number,
+            field_length: self.field_length,
+            enterprise_number: None,
+            scope: false,
+        }
+    }
 }
 
 impl FlowSet {
+    /// Bytes of a flowset with a reserved id, from 2 to 255, which some vendors use for
+    /// records of their own.  They are kept rather than failing the packet.
+    pub fn unsupported_data(&self) -> Option<&[u8]> {
+        if !V9_RESERVED_FLOWSET_IDS.contains(&self.header.flowset_id) {
+            return None;
+        }
+        self.body.unparsed_data.as_deref()
+    }
+
     fn is_unparsed(&self) -> bool {
         self.body.templates.is_none()
             && self.body.options_templates.is_none()
@@ -308,6 +734,73 @@ impl FlowSet {
     fn is_empty(&self) -> bool {
         self.header.length == 0
     }
+
+    /// Records counted towards the header `count`.  Options data flowsets hold one record.
+    fn record_count(&self) -> usize {
+        self.body.templates.as_ref().map_or(0, Vec::len)
+            + self.body.options_templates.as_ref().map_or(0, Vec::len)
+            + self
+                .body
+                .data
+                .as_ref()
+                .map_or(0, |data| data.data_fields.len())
+            + usize::from(self.body.options_data.is_some())
+    }
+
+    /// Records the flowset held on the wire, data records the filter dropped included.
+    fn wire_record_count(&self) -> usize {
+        let template = self
+            .body
+            .data
+            .as_ref()
+            .and_then(|data| data.template.as_ref());
+        match template {
+            Some(template) => {
+                usize::from(self.header.length).saturating_sub(FLOWSET_HEADER_LENGTH)
+                    / usize::from(template.get_total_size()).max(1)
+            }
+            None => self.record_count(),
+        }
+    }
+}
+
+/// Keeps a flowset whose length runs past the end of the packet, as some exporters such as
+/// softflowd send, with a `NetflowError::SetLengthMismatch`.  In `ParseMode::Lenient` the
+/// records that fit in the bytes available are still parsed.
+fn parse_overrunning_flowset(
+    i: &[u8],
+    parser: &mut V9Parser,
+    header: FlowSetHeader,
+) -> FlowSet {
+    let mismatch = NetflowError::SetLengthMismatch {
+        set_id: header.flowset_id,
+        declared: header.length,
+        available: i.len(),
+    };
+    let body =
+        if parser.parse_mode == ParseMode::Lenient && parser.check_field_counts(i).is_ok() {
+            parse_set_body(
+                &i[FLOWSET_HEADER_LENGTH..],
+                parser,
+                header.flowset_id,
+                i.len() as u16,
+            )
+            .map(|(_, body)| body)
+            .unwrap_or_default()
+        } else {
+            FlowSetBody::default()
+        };
+    let mut flowset = FlowSet {
+        header,
+        body: FlowSetBody {
+            parse_error: Some(mismatch),
+            ..body
+        },
+    };
+    if flowset.is_unparsed() {
+        flowset.body.unparsed_data = Some(i.to_vec());
+    }
+    flowset
 }
 
 // Custom parse set body function to take only length provided by set header.
@@ -328,14 +821,64 @@ fn parse_flowsets<'a>(
     i: &'a [u8],
     parser: &mut V9Parser,
     record_count: u16,
-) -> IResult<&'a [u8], Vec<FlowSet>> {
+) -> MessageResult<'a, Vec<FlowSet>> {
     let mut flowsets = vec![];
     let mut remaining = i;
-    let mut record_count_index = 0;
+    let semantics = parser.count_semantics;
+    let mut counted_records = 0;
 
-    // Header.count represents total number of records in data + records in templates
-    while !remaining.is_empty() && record_count_index < record_count {
-        let (i, mut flowset) = FlowSet::parse(remaining, parser)?;
+    // Exporters that don't pad flowsets to 4 bytes, such as softflowd, may leave a few stray
+    // bytes that are too short to be another flowset.
+    while remaining.len() >= FLOWSET_HEADER_LENGTH
+        && semantics.more_flowsets(flowsets.len(), counted_records, record_count, remaining)
+    {
+        if let Err(error) = parser.check_limits(flowsets.len(), remaining) {
+            return exceed_limit(error);
+        }
+        if let Ok((_, header)) = FlowSetHeader::parse(remaining) {
+            if usize::from(header.length) > remaining.len() {
+                // Nothing can follow a flowset running past the end of the packet.
+                let mut flowset = parse_overrunning_flowset(remaining, parser, header);
+                parser.keep_raw(&mut flowset, remaining);
+                flowsets.push(flowset);
+                remaining = &[];
+                break;
+            }
+        }
+        let parsed = match parser.check_field_counts(remaining) {
+            Ok(()) => FlowSet::parse(remaining, parser).map_err(NomErr::convert),
+            Err(error) => exceed_limit(error),
+        };
+        let (i, mut flowset) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) if parser.parse_mode == ParseMode::Lenient => {
+                // Skip over the bad flowset using its header length if we can.
+                let header = match FlowSetHeader::parse(remaining) {
+                    Ok((_, header))
+                        if header.length >= 4 && header.length as usize <= remaining.len() =>
+                    {
+                        header
+                    }
+                    _ => return Err(e),
+                };
+                let (taken, rest) = remaining.split_at(header.length as usize);
+                counted_records += 1;
+                let mut flowset = FlowSet {
+                    header,
+                    body: FlowSetBody {
+                        unparsed_data: Some(taken.to_vec()),
+                        parse_error: Some(MessageError::into_netflow_error(e, 9, taken)),
+                        ..Default::default()
+                    },
+                };
+                parser.keep_raw(&mut flowset, taken);
+                flowsets.push(flowset);
+                remaining = rest;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let start = remaining;
 
         if flowset.is_empty() {
             flowset.body.unparsed_data = Some(remaining.to_vec());
@@ -348,77 +891,198 @@ fn parse_flowsets<'a>(
             remaining = i;
         }
 
+        parser.keep_raw(&mut flowset, &start[..start.len() - remaining.len()]);
+        counted_records += flowset.wire_record_count().max(1);
         flowsets.push(flowset);
+    }
 
-        record_count_index += 1;
+    if remaining.len() < FLOWSET_HEADER_LENGTH {
+        // Trailing padding
+        remaining = &[];
     }
 
     Ok((remaining, flowsets))
 }
 
-fn parse_options_template_vec(i: &[u8]) -> IResult<&[u8], Vec<OptionsTemplate>> {
+/// Parses the templates of a template flowset and caches them, keeping the cached ones.
+fn parse_template_vec<'a>(
+    i: &'a [u8],
+    parser: &mut V9Parser,
+) -> IResult<&'a [u8], Vec<Arc<Template>>> {
+    let (remaining, templates) = many0(complete(Template::parse))(i)?;
+    let templates = templates
+        .into_iter()
+        .map(|template| parser.engine.add_template(Arc::new(template)))
+        .collect();
+    Ok((remaining, templates))
+}
+
+fn parse_options_template_vec<'a>(
+    i: &'a [u8],
+    parser: &mut V9Parser,
+) -> IResult<&'a [u8], Vec<Arc<OptionsTemplate>>> {
     let mut fields = vec![];
     let mut remaining = i;
-    while let Ok((rem, data)) = OptionsTemplate::parse(remaining) {
-        fields.push(data);
-        remaining = rem;
+    loop {
+        match OptionsTemplate::parse(remaining) {
+            Ok((rem, data)) => {
+                fields.push(parser.engine.add_options_template(Arc::new(data)));
+                remaining = rem;
+            }
+            Err(NomErr::Failure(e)) => return Err(NomErr::Failure(e)),
+            Err(_) => break,
+        }
     }
     Ok((remaining, fields))
 }
 
-fn parse_fields<'a>(
+/// Parses field descriptors until `length` bytes are consumed.  Trailing bytes too short for a
+/// descriptor are skipped as padding, so lengths that aren't a multiple of 4 don't misalign the
+/// rest of the options template.
+fn parse_descriptors<'a, T>(
+    i: &'a [u8],
+    length: u16,
+    mut parse_descriptor: impl FnMut(&'a [u8]) -> IResult<&'a [u8], T>,
+) -> IResult<&'a [u8], Vec<T>> {
+    let (remaining, mut descriptors) = take(length)(i)?;
+    let mut fields = vec![];
+    while descriptors.len() >= DESCRIPTOR_LENGTH {
+        let (rest, field) = parse_descriptor(descriptors)?;
+        descriptors = rest;
+        fields.push(field);
+    }
+    Ok((remaining, fields))
+}
+
+/// Decodes the data records of flowset `flowset_id`, into the parser's record arena instead
+/// when it has one.
+fn parse_data_records<'a>(
     input: &'a [u8],
-    template: Option<&Template>,
+    parser: &mut V9Parser,
+    flowset_id: u16,
 ) -> IResult<&'a [u8], Vec<BTreeMap<usize, V9FieldPair>>> {
+    let template = parser.engine.templates.get(&flowset_id).map(Arc::as_ref);
+    let (projection, filter) = (parser.projection.as_ref(), parser.filter.as_ref());
+    #[cfg(feature = "perf")]
+    if let Some(arena) = parser.arena.as_mut() {
+        let mark = arena.v9_mark();
+        let mut records = records_of(input, template)?;
+        for record in records.chunks.by_ref() {
+            let start = arena.v9_fields().len();
+            let decoded = parse_data_field(
+                record,
+                records.template,
+                projection,
+                parser.string_decoding,
+                |_, pair| arena.v9_fields().push(pair),
+            );
+            if let Err(e) = decoded {
+                // The flowset fails as a whole, as it does without the arena
+                arena.v9_rollback(mark);
+                return Err(e);
+            }
+            let keep = filter.is_none_or(|filter| filter.matches(&&arena.v9_fields()[start..]));
+            arena.end_v9_record(start, keep);
+        }
+        return Ok((records.padding, Vec::new()));
+    }
+    parse_fields(input, template, projection, filter, parser.string_decoding)
+}
+
+/// Records of a data flowset and the template they are decoded with.
+struct Records<'a, 't> {
+    template: &'t Template,
+    chunks: core::slice::ChunksExact<'a, u8>,
+    /// Bytes left over after the last record
+    padding: &'a [u8],
+}
+
+/// Splits `input` into records of `template`.  Records are delimited by the template size
+/// alone, anything left over is padding whether or not the exporter aligned the flowset.
+fn records_of<'a, 't>(
+    input: &'a [u8],
+    template: Option<&'t Template>,
+) -> Result<Records<'a, 't>, NomErr<NomError<&'a [u8]>>> {
     let template = template
         .filter(|t| !t.fields.is_empty() && t.get_total_size() > 0)
         .ok_or_else(|| NomErr::Error(NomError::new(input, ErrorKind::Fail)))?;
+    let chunks = input.chunks_exact(usize::from(template.get_total_size()));
+    let padding = chunks.remainder();
+    Ok(Records {
+        template,
+        chunks,
+        padding,
+    })
+}
 
-    let mut fields = vec![];
-    let mut remaining = input;
-    let record_count = input.len() as u16 / template.get_total_size();
-
-    for _ in 0..record_count {
-        // Fields
-        let (new_remaining, data_field) = parse_data_field(remaining, template)?;
-        remaining = new_remaining;
-        fields.push(data_field);
+fn parse_fields<'a>(
+    input: &'a [u8],
+    template: Option<&Template>,
+    projection: Option<&BTreeSet<V9Field>>,
+    filter: Option<&Filter>,
+    string_decoding: StringDecoding,
+) -> IResult<&'a [u8], Vec<BTreeMap<usize, V9FieldPair>>> {
+    let mut records = records_of(input, template)?;
+    let mut fields = Vec::with_capacity(records.chunks.len());
+    for record in records.chunks.by_ref() {
+        let mut data_field = BTreeMap::new();
+        parse_data_field(
+            record,
+            records.template,
+            projection,
+            string_decoding,
+            |index, pair| {
+                data_field.insert(index, pair);
+            },
+        )?;
+        if filter.is_none_or(|filter| filter.matches(&data_field)) {
+            fields.push(data_field);
+        }
     }
 
-    Ok((remaining, fields))
+    Ok((records.padding, fields))
 }
 
+/// Decodes the fields of a record, passing each to `push` with its index in the template.
+/// Fields outside `projection` are skipped and left out.
 fn parse_data_field<'a>(
     mut input: &'a [u8],
     template: &Template,
-) -> IResult<&'a [u8], BTreeMap<usize, V9FieldPair>> {
-    let mut data_field = BTreeMap::new();
-
+    projection: Option<&BTreeSet<V9Field>>,
+    string_decoding: StringDecoding,
+    mut push: impl FnMut(usize, V9FieldPair),
+) -> IResult<&'a [u8], ()> {
     for (field_index, template_field) in template.fields.iter().enumerate() {
-        let (new_input, field_value) = parse_field(input, template_field)?;
+        if projection.is_some_and(|p| !p.contains(&template_field.field_type)) {
+            (input, _) = take(template_field.field_length)(input)?;
+            continue;
+        }
+        let (new_input, field_value) = parse_field(input, template_field, string_decoding)?;
         input = new_input;
-        data_field.insert(field_index, (template_field.field_type, field_value));
+        push(field_index, (template_field.field_type, field_value));
     }
 
-    Ok((input, data_field))
+    Ok((input, ()))
 }
 
 fn parse_field<'a>(
     input: &'a [u8],
     template_field: &TemplateField,
+    string_decoding: StringDecoding,
 ) -> IResult<&'a [u8], FieldValue> {
-    DataNumber::from_field_type(
+    FieldValue::parse_or_unknown(
         input,
         template_field.field_type.into(),
         template_field.field_length,
+        string_decoding,
     )
 }
 
-fn parse_options_data_fields(
-    i: &[u8],
+fn parse_options_data_fields<'a>(
+    i: &'a [u8],
     flowset_id: u16,
-    templates: HashMap<u16, OptionsTemplate>,
-) -> IResult<&[u8], Vec<OptionDataField>> {
+    templates: &HashMap<u16, Arc<OptionsTemplate>>,
+) -> IResult<&'a [u8], Vec<OptionDataField>> {
     let template = templates.get(&flowset_id).ok_or_else(|| {
         // dbg!("Could not fetch any v9 options templates!");
         NomErr::Error(NomError::new(i, ErrorKind::Fail))
@@ -436,7 +1100,7 @@ fn parse_options_data_fields(
 fn parse_scope_data_fields<'a>(
     i: &'a [u8],
     flowset_id: u16,
-    templates: &HashMap<u16, OptionsTemplate>,
+    templates: &HashMap<u16, Arc<OptionsTemplate>>,
 ) -> IResult<&'a [u8], Vec<ScopeDataField>> {
     let template = templates.get(&flowset_id).ok_or_else(|| {
         // dbg!("Could not fetch any v9 options templates!");
@@ -452,90 +1116,252 @@ fn parse_scope_data_fields<'a>(
     Ok((remaining, fields))
 }
 
+impl Header {
+    /// Export time, since the unix epoch.
+    pub fn unix_time(&self) -> Duration {
+        Duration::from_secs(self.unix_secs.into())
+    }
+
+    /// Unix time of sysuptime reading `uptime`, such as a record's `FirstSwitched` or
+    /// `LastSwitched`, across wraps of the uptime counter.  See [`uptime_to_unix_time`].
+    pub fn uptime_to_unix_time(&self, uptime: u32) -> Duration {
+        uptime_to_unix_time(self.unix_time(), self.sys_up_time, uptime)
+    }
+}
+
+/// Sysuptime of `field` in `record`.
+fn switched(record: &BTreeMap<usize, V9FieldPair>, field: V9Field) -> Option<u32> {
+    record
+        .values()
+        .find(|(record_field, _)| *record_field == field)
+        .and_then(|(_, value)| uptime_value(value))
+}
+
+/// Time from `FirstSwitched` to `LastSwitched` of `record`, across wraps of the uptime
+/// counter.  `None` unless the record has both.
+pub fn flow_duration(record: &BTreeMap<usize, V9FieldPair>) -> Option<Duration> {
+    let first = switched(record, V9Field::FirstSwitched)?;
+    let last = switched(record, V9Field::LastSwitched)?;
+    Some(uptime_elapsed(first, last))
+}
+
+#[cfg(feature = "std")]
 impl V9 {
-    /// Convert the V9 struct to a `Vec<u8>` of bytes in big-endian order for exporting
-    pub fn to_be_bytes(&self) -> Vec<u8> {
-        let mut result = vec![];
+    /// Time the first packet of `record` was switched, from its `FirstSwitched`.
+    pub fn flow_start_time(&self, record: &BTreeMap<usize, V9FieldPair>) -> Option<SystemTime> {
+        let first = switched(record, V9Field::FirstSwitched)?;
+        Some(UNIX_EPOCH + self.header.uptime_to_unix_time(first))
+    }
+
+    /// Time the last packet of `record` was switched, from its `LastSwitched`.
+    pub fn flow_end_time(&self, record: &BTreeMap<usize, V9FieldPair>) -> Option<SystemTime> {
+        let last = switched(record, V9Field::LastSwitched)?;
+        Some(UNIX_EPOCH + self.header.uptime_to_unix_time(last))
+    }
+}
+
+impl V9 {
+    /// Whether the packet announces a template or options template.
+    pub fn has_templates(&self) -> bool {
+        self.flowsets.iter().any(|flowset| {
+            flowset.body.templates.is_some() || flowset.body.options_templates.is_some()
+        })
+    }
 
-        result.extend_from_slice(&self.header.version.to_be_bytes());
-        result.extend_from_slice(&self.header.count.to_be_bytes());
-        result.extend_from_slice(&self.header.sys_up_time.to_be_bytes());
-        result.extend_from_slice(&self.header.unix_secs.to_be_bytes());
-        result.extend_from_slice(&self.header.sequence_number.to_be_bytes());
-        result.extend_from_slice(&self.header.source_id.to_be_bytes());
+    /// Whether a data flowset was left unparsed because its template hadn't been seen.
+    pub fn has_missing_templates(&self) -> bool {
+        self.flowsets.iter().any(|flowset| {
+            flowset.header.flowset_id > FLOWSET_MIN_RANGE && flowset.is_unparsed()
+        })
+    }
+
+    /// Checks for anomalies that are tolerated in `ParseMode::Lenient`.
+    fn validate(&self) -> Result<(), NetflowError> {
+        for flowset in self.flowsets.iter() {
+            if let Some(mismatch @ NetflowError::SetLengthMismatch { .. }) =
+                &flowset.body.parse_error
+            {
+                return Err(mismatch.clone());
+            }
+            if flowset.header.flowset_id > FLOWSET_MIN_RANGE && flowset.is_unparsed() {
+                return Err(NetflowError::MissingTemplate {
+                    version: 9,
+                    template_id: flowset.header.flowset_id,
+                });
+            }
+        }
+        Ok(())
+    }
 
+    /// Convert the V9 struct to a `Vec<u8>` of bytes in big-endian order for exporting
+    pub fn to_be_bytes(&self) -> Result<Vec<u8>, NetflowError> {
+        let mut result = vec![];
+        write_header(&self.header, &mut result);
         for set in self.flowsets.iter() {
             result.extend_from_slice(&set.header.flowset_id.to_be_bytes());
             result.extend_from_slice(&set.header.length.to_be_bytes());
+            write_flowset_body(&set.body, false, &mut result)?;
+        }
+        Ok(result)
+    }
 
-            if let Some(templates) = &set.body.templates {
-                for template in templates.iter() {
-                    result.extend_from_slice(&template.template_id.to_be_bytes());
-                    result.extend_from_slice(&template.field_count.to_be_bytes());
-                    for field in template.fields.iter() {
-                        result.extend_from_slice(&field.field_type_number.to_be_bytes());
-                        result.extend_from_slice(&field.field_length.to_be_bytes());
-                    }
-                }
-            }
+    /// Like `to_be_bytes`, but the header count, flowset lengths and template lengths are
+    /// recomputed from the contents, for packets whose flowsets or records were changed.
+    /// Flowsets are padded to 4 bytes, and flowsets with nothing parsed are left out.
+    pub fn to_be_bytes_recalculated(&self) -> Result<Vec<u8>, NetflowError> {
+        let flowsets: Vec<&FlowSet> = self
+            .flowsets
+            .iter()
+            .filter(|set| !set.is_unparsed())
+            .collect();
+        let count = flowsets.iter().map(|set| set.record_count()).sum::<usize>();
+        let header = Header {
+            count: u16::try_from(count).map_err(|_| {
+                NetflowError::InvalidFieldValue(format!(
+                    "{count} records do not fit in a packet"
+                ))
+            })?,
+            ..self.header
+        };
+        let mut result = vec![];
+        write_header(&header, &mut result);
+        for set in flowsets {
+            let mut body = vec![];
+            write_flowset_body(&set.body, true, &mut body)?;
+            body.resize(body.len().next_multiple_of(4), 0);
+            let length = FLOWSET_HEADER_LENGTH + body.len();
+            let length = u16::try_from(length).map_err(|_| NetflowError::InvalidLength {
+                version: 9,
+                declared: length,
+                available: usize::from(u16::MAX),
+            })?;
+            result.extend_from_slice(&set.header.flowset_id.to_be_bytes());
+            result.extend_from_slice(&length.to_be_bytes());
+            result.append(&mut body);
+        }
+        Ok(result)
+    }
+}
 
-            if let Some(options_templates) = &set.body.options_templates {
-                for template in options_templates.iter() {
-                    result.extend_from_slice(&template.template_id.to_be_bytes());
-                    result.extend_from_slice(&template.options_scope_length.to_be_bytes());
-                    result.extend_from_slice(&template.options_length.to_be_bytes());
-                    for field in template.scope_fields.iter() {
-                        result.extend_from_slice(&field.field_type_number.to_be_bytes());
-                        result.extend_from_slice(&field.field_length.to_be_bytes());
-                    }
-                    for field in template.option_fields.iter() {
-                        result.extend_from_slice(&field.field_type_number.to_be_bytes());
-                        result.extend_from_slice(&field.field_length.to_be_bytes());
-                    }
-                }
+fn write_header(header: &Header, result: &mut Vec<u8>) {
+    result.extend_from_slice(&header.version.to_be_bytes());
+    result.extend_from_slice(&header.count.to_be_bytes());
+    result.extend_from_slice(&header.sys_up_time.to_be_bytes());
+    result.extend_from_slice(&header.unix_secs.to_be_bytes());
+    result.extend_from_slice(&header.sequence_number.to_be_bytes());
+    result.extend_from_slice(&header.source_id.to_be_bytes());
+}
+
+/// A field count or length recomputed for `to_be_bytes_recalculated`.  Fails if it is more
+/// than 65,535.
+fn recalculated_length(length: usize) -> Result<u16, NetflowError> {
+    u16::try_from(length).map_err(|_| NetflowError::InvalidLength {
+        version: 9,
+        declared: length,
+        available: usize::from(u16::MAX),
+    })
+}
+
+/// Writes the records of a flowset.  With `recalculate`, template field counts and options
+/// template lengths are taken from their fields instead of what was parsed.
+fn write_flowset_body(
+    body: &FlowSetBody,
+    recalculate: bool,
+    result: &mut Vec<u8>,
+) -> Result<(), NetflowError> {
+    if let Some(templates) = &body.templates {
+        for template in templates.iter() {
+            let field_count = if recalculate {
+                recalculated_length(template.fields.len())?
+            } else {
+                template.field_count
+            };
+            result.extend_from_slice(&template.template_id.to_be_bytes());
+            result.extend_from_slice(&field_count.to_be_bytes());
+            for field in template.fields.iter() {
+                result.extend_from_slice(&field.field_type_number.to_be_bytes());
+                result.extend_from_slice(&field.field_length.to_be_bytes());
             }
+        }
+    }
 
-            if let Some(data) = &set.body.data {
-                for data_field in data.data_fields.iter() {
-                    for (_field_type, (_, field_value)) in data_field.iter() {
-                        result.extend_from_slice(&field_value.to_be_bytes());
-                    }
-                }
+    if let Some(options_templates) = &body.options_templates {
+        for template in options_templates.iter() {
+            let (options_scope_length, options_length) = if recalculate {
+                (
+                    recalculated_length(template.scope_fields.len() * DESCRIPTOR_LENGTH)?,
+                    recalculated_length(template.option_fields.len() * DESCRIPTOR_LENGTH)?,
+                )
+            } else {
+                (template.options_scope_length, template.options_length)
+            };
+            result.extend_from_slice(&template.template_id.to_be_bytes());
+            result.extend_from_slice(&options_scope_length.to_be_bytes());
+            result.extend_from_slice(&options_length.to_be_bytes());
+            for field in template.scope_fields.iter() {
+                result.extend_from_slice(&field.field_type_number.to_be_bytes());
+                result.extend_from_slice(&field.field_length.to_be_bytes());
+            }
+            // Keep padding so the declared lengths still match
+            let padding = usize::from(options_scope_length)
+                .saturating_sub(template.scope_fields.len() * DESCRIPTOR_LENGTH);
+            result.resize(result.len() + padding, 0);
+            for field in template.option_fields.iter() {
+                result.extend_from_slice(&field.field_type_number.to_be_bytes());
+                result.extend_from_slice(&field.field_length.to_be_bytes());
             }
+            let padding = usize::from(options_length)
+                .saturating_sub(template.option_fields.len() * DESCRIPTOR_LENGTH);
+            result.resize(result.len() + padding, 0);
+        }
+    }
 
-            if let Some(options_data) = &set.body.options_data {
-                for scope_field in options_data.scope_fields.iter() {
-                    match scope_field {
-                        ScopeDataField {
-                            system: Some(system),
-                            ..
-                        } => result.extend_from_slice(system.as_slice()),
-                        ScopeDataField {
-                            interface: Some(interface),
-                            ..
-                        } => result.extend_from_slice(interface.as_slice()),
-                        ScopeDataField {
-                            line_card: Some(line_card),
-                            ..
-                        } => result.extend_from_slice(line_card.as_slice()),
-                        ScopeDataField {
-                            net_flow_cache: Some(net_flow_cache),
-                            ..
-                        } => result.extend_from_slice(net_flow_cache.as_slice()),
-                        ScopeDataField {
-                            template: Some(template),
-                            ..
-                        } => result.extend_from_slice(template.as_slice()),
-                        _ => {}
-                    }
-                }
+    if let Some(data) = &body.data {
+        for data_field in data.data_fields.iter() {
+            for (_field_type, (_, field_value)) in data_field.iter() {
+                result.extend_from_slice(&field_value.to_be_bytes()?);
+            }
+        }
+    }
 
-                for option_field in options_data.options_fields.iter() {
-                    result.extend_from_slice(&option_field.field_value);
-                }
+    if let Some(options_data) = &body.options_data {
+        for scope_field in options_data.scope_fields.iter() {
+            match scope_field {
+                ScopeDataField {
+                    system: Some(system),
+                    ..
+                } => result.extend_from_slice(system.as_slice()),
+                ScopeDataField {
+                    interface: Some(interface),
+                    ..
+                } => result.extend_from_slice(interface.as_slice()),
+                ScopeDataField {
+                    line_card: Some(line_card),
+                    ..
+                } => result.extend_from_slice(line_card.as_slice()),
+                ScopeDataField {
+                    net_flow_cache: Some(net_flow_cache),
+                    ..
+                } => result.extend_from_slice(net_flow_cache.as_slice()),
+                ScopeDataField {
+                    template: Some(template),
+                    ..
+                } => result.extend_from_slice(template.as_slice()),
+                _ => {}
             }
         }
 
-        result
+        for option_field in options_data.options_fields.iter() {
+            result.extend_from_slice(&option_field.field_value);
+        }
+    }
+
+    if let Some(unparsed_data) = &body.unparsed_data {
+        result.extend_from_slice(
+            unparsed_data
+                .get(FLOWSET_HEADER_LENGTH..)
+                .unwrap_or_default(),
+        );
     }
+    Ok(())
 }
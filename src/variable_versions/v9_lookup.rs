@@ -1,10 +1,10 @@
 use super::data_number::*;
 
 use nom_derive::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[repr(u16)]
-#[derive(Default, Debug, PartialEq, Eq, Clone, Copy, Serialize, Nom)]
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Nom)]
 pub enum ScopeFieldType {
     System = 1,
     Interface = 2,
@@ -29,7 +29,9 @@ impl From<u16> for ScopeFieldType {
 }
 
 #[repr(u16)]
-#[derive(Debug, Hash, PartialEq, Eq, Clone, Ord, PartialOrd, Copy, Serialize, Nom)]
+#[derive(
+    Debug, Hash, PartialEq, Eq, Clone, Ord, PartialOrd, Copy, Serialize, Deserialize, Nom,
+)]
 pub enum V9Field {
     InBytes = 1,
     InPkts = 2,
@@ -200,6 +202,7 @@ impl From<V9Field> for FieldDataType {
             56 => FieldDataType::MacAddr,
             57 => FieldDataType::MacAddr,
             58 => FieldDataType::UnsignedDataNumber,
+            59 => FieldDataType::UnsignedDataNumber,
             60 => FieldDataType::UnsignedDataNumber,
             61 => FieldDataType::UnsignedDataNumber,
             62 => FieldDataType::Ip6Addr,
@@ -217,9 +220,9 @@ impl From<V9Field> for FieldDataType {
             79 => FieldDataType::UnsignedDataNumber,
             80 => FieldDataType::MacAddr,
             81 => FieldDataType::MacAddr,
-            82 => FieldDataType::UnsignedDataNumber,
-            83 => FieldDataType::UnsignedDataNumber,
-            84 => FieldDataType::UnsignedDataNumber,
+            82 => FieldDataType::String,
+            83 => FieldDataType::String,
+            84 => FieldDataType::String,
             85 => FieldDataType::UnsignedDataNumber,
             86 => FieldDataType::UnsignedDataNumber,
             87 => FieldDataType::UnsignedDataNumber,
@@ -234,8 +237,8 @@ impl From<V9Field> for FieldDataType {
             96 => FieldDataType::String,
             98 => FieldDataType::UnsignedDataNumber,
             99 => FieldDataType::UnsignedDataNumber,
-            152 => FieldDataType::DurationMillis,
-            153 => FieldDataType::DurationMillis,
+            152 => FieldDataType::DateTimeMillis,
+            153 => FieldDataType::DateTimeMillis,
             176 => FieldDataType::UnsignedDataNumber,
             177 => FieldDataType::UnsignedDataNumber,
             178 => FieldDataType::UnsignedDataNumber,
@@ -0,0 +1,28 @@
+#!/bin/sh
+# Regenerates the tshark reference output of every fixture from its `.hex` payload.
+#
+# Each payload is wrapped in a UDP datagram to port 2055 with text2pcap and decoded as
+# cflow by tshark.  Requires Wireshark's `text2pcap` and `tshark` and `xxd`.
+#
+#   tests/fixtures/tshark/generate.sh
+set -eu
+
+cd "$(dirname "$0")"
+
+FIELDS="cflow.version cflow.count cflow.sequence cflow.source_id cflow.od_id
+cflow.srcaddr cflow.dstaddr cflow.nexthop cflow.inputint cflow.outputint cflow.packets
+cflow.octets cflow.srcport cflow.dstport cflow.protocol cflow.tos cflow.srcas cflow.dstas
+cflow.srcmask cflow.dstmask"
+
+pcap=$(mktemp)
+trap 'rm -f "$pcap"' EXIT
+
+for hex in *.hex; do
+    name=${hex%.hex}
+    xxd -r -p "$hex" | od -Ax -tx1 -v | text2pcap -q -u 2055,2055 - "$pcap"
+    set --
+    for field in $FIELDS; do
+        set -- "$@" -e "$field"
+    done
+    tshark -r "$pcap" -d udp.port==2055,cflow -T json "$@" > "$name.json"
+done
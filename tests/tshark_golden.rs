@@ -0,0 +1,163 @@
+//! Compares parsed output against reference values decoded by Wireshark's `tshark` for the
+//! same packets.  Each fixture in `tests/fixtures/tshark` is a pair of files:
+//!
+//! * `<name>.hex` - the UDP payload as a hex string.
+//! * `<name>.json` - `tshark -T json -e <field> ...` output for that payload.
+//!
+//! The `.json` files are generated from the `.hex` payloads with
+//! `tests/fixtures/tshark/generate.sh`, which wraps each payload in a UDP datagram with
+//! `text2pcap` and decodes it with `tshark -d udp.port==2055,cflow -T json`.  Rerun it after
+//! adding or changing a payload.
+//!
+//! Run with `cargo test --features golden_tests --test tshark_golden`.
+
+use netflow_parser::NetflowParser;
+use serde_json::Value;
+
+use std::fs;
+use std::path::Path;
+
+/// Where a tshark field lives in our serialized output.
+enum Location {
+    /// Key in the packet header.
+    Header(&'static str),
+    /// Key in a V5/V7 flowset, or the field type name in V9/IPFix data records.
+    Record(&'static str),
+}
+
+const V5_FIELDS: &[(&str, Location)] = &[
+    ("cflow.version", Location::Header("version")),
+    ("cflow.count", Location::Header("count")),
+    ("cflow.sequence", Location::Header("flow_sequence")),
+    ("cflow.srcaddr", Location::Record("src_addr")),
+    ("cflow.dstaddr", Location::Record("dst_addr")),
+    ("cflow.nexthop", Location::Record("next_hop")),
+    ("cflow.inputint", Location::Record("input")),
+    ("cflow.outputint", Location::Record("output")),
+    ("cflow.packets", Location::Record("d_pkts")),
+    ("cflow.octets", Location::Record("d_octets")),
+    ("cflow.srcport", Location::Record("src_port")),
+    ("cflow.dstport", Location::Record("dst_port")),
+    ("cflow.protocol", Location::Record("protocol_number")),
+    ("cflow.tos", Location::Record("tos")),
+    ("cflow.srcas", Location::Record("src_as")),
+    ("cflow.dstas", Location::Record("dst_as")),
+    ("cflow.srcmask", Location::Record("src_mask")),
+    ("cflow.dstmask", Location::Record("dst_mask")),
+];
+
+const V9_FIELDS: &[(&str, Location)] = &[
+    ("cflow.version", Location::Header("version")),
+    ("cflow.count", Location::Header("count")),
+    ("cflow.sequence", Location::Header("sequence_number")),
+    ("cflow.source_id", Location::Header("source_id")),
+    ("cflow.octets", Location::Record("InBytes")),
+    ("cflow.packets", Location::Record("InPkts")),
+    ("cflow.srcaddr", Location::Record("Ipv4SrcAddr")),
+    ("cflow.dstaddr", Location::Record("Ipv4DstAddr")),
+    ("cflow.srcport", Location::Record("L4SrcPort")),
+    ("cflow.dstport", Location::Record("L4DstPort")),
+];
+
+const IPFIX_FIELDS: &[(&str, Location)] = &[
+    ("cflow.version", Location::Header("version")),
+    ("cflow.sequence", Location::Header("sequence_number")),
+    ("cflow.od_id", Location::Header("observation_domain_id")),
+    ("cflow.octets", Location::Record("OctetDeltaCount")),
+    ("cflow.packets", Location::Record("PacketDeltaCount")),
+    ("cflow.srcaddr", Location::Record("SourceIpv4address")),
+    ("cflow.dstaddr", Location::Record("DestinationIpv4address")),
+    ("cflow.srcport", Location::Record("SourceTransportPort")),
+    (
+        "cflow.dstport",
+        Location::Record("DestinationTransportPort"),
+    ),
+];
+
+/// Flattens serialized values such as `{"Ip4Addr": "1.2.3.4"}` into tshark's string form.
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Object(map) if map.len() == 1 => scalar(map.values().next().unwrap()),
+        other => other.to_string(),
+    }
+}
+
+fn record_values(packet: &Value, key: &str) -> Vec<String> {
+    let flowsets = packet["flowsets"].as_array().cloned().unwrap_or_default();
+    let mut values = vec![];
+    for flowset in flowsets.iter() {
+        // V5/V7 records are the flowsets themselves.
+        if let Some(value) = flowset.get(key) {
+            values.push(scalar(value));
+            continue;
+        }
+        let Some(records) = flowset["body"]["data"]["data_fields"].as_array() else {
+            continue;
+        };
+        for record in records.iter().filter_map(Value::as_object) {
+            for field in record.values().filter_map(Value::as_array) {
+                if field.first().and_then(Value::as_str) == Some(key) {
+                    values.push(scalar(&field[1]));
+                }
+            }
+        }
+    }
+    values
+}
+
+fn compare_fixture(name: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tshark");
+    let hex = fs::read_to_string(dir.join(format!("{name}.hex"))).unwrap();
+    let packet = hex::decode(hex.trim()).unwrap();
+    let tshark: Value =
+        serde_json::from_str(&fs::read_to_string(dir.join(format!("{name}.json"))).unwrap())
+            .unwrap();
+    let layers = &tshark[0]["_source"]["layers"];
+
+    let parsed = serde_json::to_value(NetflowParser::default().parse_bytes(&packet)).unwrap();
+    let (version, parsed) = parsed[0].as_object().unwrap().iter().next().unwrap();
+    let fields = match version.as_str() {
+        "V5" | "V7" => V5_FIELDS,
+        "V9" => V9_FIELDS,
+        "IPFix" => IPFIX_FIELDS,
+        other => panic!("{name}: unexpected parse result {other}: {parsed}"),
+    };
+
+    let mut mismatches = vec![];
+    for (tshark_field, location) in fields.iter() {
+        let Some(expected) = layers.get(*tshark_field) else {
+            continue;
+        };
+        let expected: Vec<String> = expected.as_array().unwrap().iter().map(scalar).collect();
+        let actual = match location {
+            Location::Header(key) => vec![scalar(&parsed["header"][*key])],
+            Location::Record(key) => record_values(parsed, key),
+        };
+        if expected != actual {
+            mismatches.push(format!(
+                "{tshark_field}: tshark {expected:?}, parsed {actual:?}"
+            ));
+        }
+    }
+    assert!(
+        mismatches.is_empty(),
+        "{name} field mismatches:\n{}",
+        mismatches.join("\n")
+    );
+}
+
+#[test]
+fn it_matches_tshark_v5() {
+    compare_fixture("v5");
+}
+
+#[test]
+fn it_matches_tshark_v9() {
+    compare_fixture("v9");
+}
+
+#[test]
+fn it_matches_tshark_ipfix() {
+    compare_fixture("ipfix");
+}